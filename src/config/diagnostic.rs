@@ -0,0 +1,302 @@
+/*
+serde tells where a config fails to deserialize as a path through its tree, e.g.
+`pipes[2].extra_labels`, which is turned into the file the item comes from, the line and the
+path within that file.
+
+The inbounds, outbounds, protocols and pipes are enums tagged by their `type`, which serde
+buffers before picking the variant: the path stops at the item. It is narrowed down by leaving
+out the fields of the item one at a time, the field whose absence makes the error go away, or
+turn into its own `missing field`, is the culprit.
+*/
+
+use std::{fmt::Write, ops::Range, path::Path};
+
+use miette::NamedSource;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use super::{
+    error::FieldError,
+    include::{format_of, Format, Sources},
+    Error, Result,
+};
+
+// The tags of the enums of the config: an item without one fails as a whole
+const TAG_KEYS: &[&str] = &["type", "op"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// Deserialize the merged tree of a config, pointing at the invalid field on error.
+pub fn deserialize<T: DeserializeOwned>(tree: &Value, sources: &Sources) -> Result<T> {
+    let e = match serde_path_to_error::deserialize::<_, T>(tree) {
+        Ok(value) => return Ok(value),
+        Err(e) => e,
+    };
+
+    let message = e.inner().to_string();
+    let mut path = e
+        .path()
+        .iter()
+        .map_while(|segment| match segment {
+            serde_path_to_error::Segment::Seq { index } => Some(Segment::Index(*index)),
+            serde_path_to_error::Segment::Map { key } => Some(Segment::Key(key.clone())),
+            serde_path_to_error::Segment::Enum { variant } => Some(Segment::Key(variant.clone())),
+            serde_path_to_error::Segment::Unknown => None,
+        })
+        .collect::<Vec<_>>();
+    // Nothing to point at within the item
+    if !message.starts_with("missing field") {
+        narrow::<T>(tree, &mut path);
+    }
+
+    Err(locate(sources, path, message))
+}
+
+/// A config file which is not valid TOML.
+pub fn syntax_error(path: &Path, text: String, e: toml::de::Error) -> Error {
+    let span = e.span();
+    let line = span.as_ref().map(|span| line_of(&text, span));
+    Error::InvalidField(Box::new(FieldError {
+        location: location(path, line, &[]),
+        message: e.message().to_string(),
+        source_code: Some(NamedSource::new(path.display().to_string(), text)),
+        span: span.map(Into::into),
+    }))
+}
+
+/// Descend from `path` to the field the error comes from.
+fn narrow<T: DeserializeOwned>(tree: &Value, path: &mut Vec<Segment>) {
+    loop {
+        let children = match lookup(tree, path) {
+            Some(Value::Object(fields)) => fields
+                .keys()
+                .filter(|key| !TAG_KEYS.contains(&key.as_str()))
+                .map(|key| Segment::Key(key.clone()))
+                .collect::<Vec<_>>(),
+            Some(Value::Array(items)) => (0..items.len()).map(Segment::Index).collect(),
+            _ => return,
+        };
+
+        let culprit = children.into_iter().find(|child| {
+            let mut pruned = tree.clone();
+            remove(&mut pruned, path, child);
+            match T::deserialize(&pruned) {
+                Ok(_) => true,
+                Err(e) => matches!(
+                    child,
+                    Segment::Key(key) if e.to_string() == format!("missing field `{}`", key)
+                ),
+            }
+        });
+        match culprit {
+            Some(child) => path.push(child),
+            None => return,
+        }
+    }
+}
+
+fn lookup<'a>(tree: &'a Value, path: &[Segment]) -> Option<&'a Value> {
+    path.iter().try_fold(tree, |value, segment| match segment {
+        Segment::Key(key) => value.get(key),
+        Segment::Index(index) => value.get(index),
+    })
+}
+
+fn remove(tree: &mut Value, path: &[Segment], child: &Segment) {
+    let parent = path.iter().try_fold(tree, |value, segment| match segment {
+        Segment::Key(key) => value.get_mut(key),
+        Segment::Index(index) => value.get_mut(index),
+    });
+    match (parent, child) {
+        (Some(Value::Object(fields)), Segment::Key(key)) => {
+            fields.remove(key);
+        }
+        (Some(Value::Array(items)), Segment::Index(index)) => {
+            items.remove(*index);
+        }
+        _ => {}
+    }
+}
+
+/// The error pointing at the file the path comes from, and within it when it is TOML.
+fn locate(sources: &Sources, mut path: Vec<Segment>, message: String) -> Error {
+    let file = match path.as_mut_slice() {
+        [Segment::Key(key), Segment::Index(index), ..] => match sources.item(key, *index) {
+            Some((file, in_file)) => {
+                *index = in_file;
+                file
+            }
+            None => sources.root(),
+        },
+        _ => sources.root(),
+    };
+
+    let text = (format_of(file) == Some(Format::Toml))
+        .then(|| std::fs::read_to_string(file).ok())
+        .flatten();
+    let span = text.as_deref().and_then(|text| toml_span(text, &path));
+    let line = text
+        .as_deref()
+        .zip(span.as_ref())
+        .map(|(text, span)| line_of(text, span));
+
+    Error::InvalidField(Box::new(FieldError {
+        location: location(file, line, &path),
+        message,
+        source_code: text.map(|text| NamedSource::new(file.display().to_string(), text)),
+        span: span.map(Into::into),
+    }))
+}
+
+/// The span of the deepest part of the path found in a TOML file.
+fn toml_span(text: &str, path: &[Segment]) -> Option<Range<usize>> {
+    let document = toml_edit::ImDocument::parse(text).ok()?;
+    let mut item = document.as_item();
+    let mut span = None;
+    for segment in path {
+        let next = match segment {
+            Segment::Key(key) => item.get(key.as_str()),
+            Segment::Index(index) => item.get(*index),
+        };
+        let Some(next) = next else {
+            break;
+        };
+        item = next;
+        span = item.span().or(span);
+    }
+    span
+}
+
+fn line_of(text: &str, span: &Range<usize>) -> usize {
+    text[..span.start.min(text.len())].matches('\n').count() + 1
+}
+
+fn location(file: &Path, line: Option<usize>, path: &[Segment]) -> String {
+    let mut location = file.display().to_string();
+    if let Some(line) = line {
+        let _ = write!(location, ":{}", line);
+    }
+    for (i, segment) in path.iter().enumerate() {
+        let _ = match segment {
+            Segment::Key(key) if i == 0 => write!(location, ": {}", key),
+            Segment::Key(key) => write!(location, ".{}", key),
+            Segment::Index(index) => write!(location, "[{}]", index),
+        };
+    }
+    location
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{include::load_tree, Config};
+
+    fn load(dir: &Path, files: &[(&str, &str)]) -> FieldError {
+        for (name, content) in files {
+            std::fs::write(dir.join(name), content).unwrap();
+        }
+        let root = dir.join(files[0].0);
+        let result = load_tree(&root)
+            .and_then(|(tree, sources)| deserialize::<Config>(&tree, &sources).map(|_| ()));
+        match result {
+            Err(Error::InvalidField(e)) => *e,
+            other => panic!("{:?}", other),
+        }
+    }
+
+    const HEAD: &str = r#"
+inbounds = []
+outbounds = []
+protocols = []
+
+[[pipes]]
+type = "filter"
+inbounds = ["inbound:a"]
+conditions = []
+
+[[pipes]]
+type = "throttle"
+inbounds = ["inbound:a"]
+rate = 10
+"#;
+
+    #[test]
+    fn test_field_path() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // Within the item of a tagged enum
+        let broken = format!(
+            "{}\n[[pipes]]\ntype = \"timeseries\"\ninbounds = [\"pipe:a\"]\nextra_labels = 5\n",
+            HEAD
+        );
+        let e = load(dir.path(), &[("config.toml", &broken)]);
+        assert!(
+            e.location
+                .ends_with("config.toml:19: pipes[2].extra_labels"),
+            "{}",
+            e.location
+        );
+        assert!(e.message.contains("expected a map"), "{}", e.message);
+        let span = e.span.unwrap();
+        assert_eq!(&broken[span.offset()..span.offset() + span.len()], "5");
+
+        // The variants are listed
+        let broken = format!("{}\n[[pipes]]\ntype = \"timeseriez\"\n", HEAD);
+        let e = load(dir.path(), &[("config.toml", &broken)]);
+        assert!(
+            e.location.ends_with("config.toml:17: pipes[2].type"),
+            "{}",
+            e.location
+        );
+        assert!(
+            e.message.contains("expected one of `timeseries`"),
+            "{}",
+            e.message
+        );
+
+        // Nothing to point at but the item
+        let broken = format!("{}\n[[pipes]]\ntype = \"throttle\"\n", HEAD);
+        let e = load(dir.path(), &[("config.toml", &broken)]);
+        assert!(
+            e.location.ends_with("config.toml:16: pipes[2]"),
+            "{}",
+            e.location
+        );
+        assert_eq!(e.message, "missing field `inbounds`");
+    }
+
+    #[test]
+    fn test_included_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = format!("include = [\"pipes.json\"]\n{}", HEAD);
+        let e = load(
+            dir.path(),
+            &[
+                ("config.toml", &root),
+                (
+                    "pipes.json",
+                    r#"{ "pipes": [{ "type": "throttle", "inbounds": ["inbound:a"], "rate": "fast" }] }"#,
+                ),
+            ],
+        );
+        // Indexed within the JSON file, without a line
+        assert!(
+            e.location.ends_with("pipes.json: pipes[0].rate"),
+            "{}",
+            e.location
+        );
+        assert!(e.source_code.is_none());
+    }
+
+    #[test]
+    fn test_syntax_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let e = load(dir.path(), &[("config.toml", "inbounds = []\npipes = [\n")]);
+        assert!(e.location.ends_with("config.toml:3"), "{}", e.location);
+        assert!(e.span.is_some());
+    }
+}
@@ -6,48 +6,37 @@ Format:
     - The default value can be placed in the end of the string, separated by a ":".
     - Otherwise, it will be treated as a normal string.
 If no environment variable or file is found, the default value will be used.
+
+Some fields (addresses, credentials, paths, label values) also interpolate `${VAR}` and
+`${VAR:-default}` anywhere in the string once the config is loaded, see [`interpolate`].
+`$$` stands for a literal `$`: a value of these fields holding `$$` or a literal `${`, e.g. a
+password, is written `$$$$` or `$${`. A lone `$` not followed by `{` is kept as is.
 */
 
-use std::{ops::Deref, str::FromStr};
+use std::{fmt::Display, ops::Deref, path::PathBuf, str::FromStr};
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy)]
-enum EnvStringType {
-    Env,
-    File,
-    String,
-}
+use super::Error;
 
 #[derive(Debug, Clone)]
 pub struct Env<T> {
-    r#type: EnvStringType,
-    key: String,
     value: T,
 }
 
 impl<T> Env<T> {
-    pub fn is_env(&self) -> bool {
-        matches!(self.r#type, EnvStringType::Env)
-    }
-
-    pub fn is_file(&self) -> bool {
-        matches!(self.r#type, EnvStringType::File)
-    }
-
-    pub fn is_string(&self) -> bool {
-        matches!(self.r#type, EnvStringType::String)
-    }
-
-    pub fn take(self) -> T {
-        self.value
-    }
-
     pub fn get(&self) -> &T {
         &self.value
     }
 }
 
+impl Env<String> {
+    /// Interpolate the environment variables in the value, see [`interpolate`].
+    pub fn interpolate(&mut self, owner: impl Display, field: &str) -> super::Result<()> {
+        interpolate_field(&mut self.value, owner, field)
+    }
+}
+
 impl<T> Deref for Env<T> {
     type Target = T;
 
@@ -77,44 +66,28 @@ where
         D: serde::Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        let (key, r#type) = if s.starts_with("env:") {
-            (s[4..].to_string(), EnvStringType::Env)
-        } else if s.starts_with("file:") {
-            (s[5..].to_string(), EnvStringType::File)
+        let (key, value) = if let Some(key) = s.strip_prefix("env:") {
+            let value = std::env::var(key).map_err(|_| {
+                serde::de::Error::custom(format!("Environment variable not found: {}", key))
+            })?;
+            (key, value)
+        } else if let Some(key) = s.strip_prefix("file:") {
+            let value = std::fs::read_to_string(key).map_err(|_| {
+                serde::de::Error::custom(format!("File not found or cannot be read: {}", key))
+            })?;
+            (key, value)
         } else {
-            (s.clone(), EnvStringType::String)
-        };
-
-        let value = match r#type {
-            EnvStringType::Env => match std::env::var(&key) {
-                Ok(v) => v,
-                Err(_) => {
-                    return Err(serde::de::Error::custom(format!(
-                        "Environment variable not found: {}",
-                        key
-                    )))
-                }
-            },
-            EnvStringType::File => match std::fs::read_to_string(&key) {
-                Ok(v) => v,
-                Err(_) => {
-                    return Err(serde::de::Error::custom(format!(
-                        "File not found or cannot be read: {}",
-                        key
-                    )))
-                }
-            },
-            EnvStringType::String => key.clone(),
+            (s.as_str(), s.clone())
         };
 
         let value = T::from_str(&value).map_err(|_| {
             serde::de::Error::custom(format!(
                 "Failed to parse value from string: {}:{}",
-                &key, value
+                key, value
             ))
         })?;
 
-        Ok(Env { r#type, key, value })
+        Ok(Env { value })
     }
 }
 
@@ -126,3 +99,165 @@ where
         write!(f, "{}", self.value)
     }
 }
+
+/// Replace `${VAR}` with the value of `VAR` and `${VAR:-default}` with the value of `VAR`, or
+/// `default` if it is unset or empty, and `$$` with `$`. Fails on an unset variable without
+/// default, and on a `${` left unclosed.
+pub fn interpolate(s: &str) -> std::result::Result<String, String> {
+    interpolate_with(s, |var| std::env::var(var).ok())
+}
+
+fn interpolate_with<F>(s: &str, lookup: F) -> std::result::Result<String, String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('$') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(after) = rest.strip_prefix("$$") {
+            out.push('$');
+            rest = after;
+            continue;
+        }
+
+        let Some(after) = rest.strip_prefix("${") else {
+            out.push('$');
+            rest = &rest[1..];
+            continue;
+        };
+        let end = after
+            .find('}')
+            .ok_or_else(|| format!("unterminated ${{ in {:?}", s))?;
+        let (var, default) = match after[..end].split_once(":-") {
+            Some((var, default)) => (var, Some(default)),
+            None => (&after[..end], None),
+        };
+        if var.is_empty() {
+            return Err(format!("empty variable name in {:?}", s));
+        }
+
+        match (lookup(var), default) {
+            (Some(value), Some(default)) if value.is_empty() => out.push_str(default),
+            (Some(value), _) => out.push_str(&value),
+            (None, Some(default)) => out.push_str(default),
+            (None, None) => return Err(format!("environment variable {} is not set", var)),
+        }
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+
+    Ok(out)
+}
+
+/// [`interpolate`] a string field in place, `owner` and `field` name it in the error.
+pub fn interpolate_field(
+    value: &mut String,
+    owner: impl Display,
+    field: &str,
+) -> super::Result<()> {
+    if !value.contains('$') {
+        return Ok(());
+    }
+
+    *value = interpolate(value)
+        .map_err(|reason| Error::InvalidConfig(format!("{}.{}: {}", owner, field, reason)))?;
+    Ok(())
+}
+
+/// [`interpolate_field`] for paths, which are left as is if not valid UTF-8.
+pub fn interpolate_path(path: &mut PathBuf, owner: impl Display, field: &str) -> super::Result<()> {
+    let Some(s) = path.to_str() else {
+        return Ok(());
+    };
+
+    let mut s = s.to_string();
+    interpolate_field(&mut s, owner, field)?;
+    *path = PathBuf::from(s);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::outbound::auth::AuthConfig;
+
+    #[test]
+    fn test_interpolate() {
+        let lookup = |var: &str| match var {
+            "HOST" => Some("prom.internal".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        };
+
+        let cases = [
+            (
+                "http://${HOST}:9090/api/v1/write",
+                "http://prom.internal:9090/api/v1/write",
+            ),
+            ("${PORT:-9090}", "9090"),
+            ("${EMPTY:-fallback}", "fallback"),
+            ("a${EMPTY}b", "ab"),
+            ("${HOST:-}", "prom.internal"),
+            ("cost $5, $$HOST", "cost $5, $HOST"),
+            ("no variables", "no variables"),
+            // The escapes of a literal `$$` and `${`
+            ("pa$$$$word", "pa$$word"),
+            ("pa$${HOST}", "pa${HOST}"),
+            ("trailing $", "trailing $"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                interpolate_with(input, lookup).unwrap(),
+                expected,
+                "{}",
+                input
+            );
+        }
+
+        let err = interpolate_with("http://${MISSING}/", lookup).unwrap_err();
+        assert!(err.contains("MISSING"), "{}", err);
+        assert!(interpolate_with("${HOST", lookup).is_err());
+        // A literal `${` not escaped
+        assert!(interpolate_with("pa${ss", lookup).is_err());
+        assert!(interpolate_with("${}", lookup).is_err());
+    }
+
+    #[test]
+    fn test_interpolate_on_verify() {
+        use crate::config::{outbound::prometheus::PrometheusOutboundConfig, Verify};
+
+        std::env::set_var("VOID_TEST_PROM_HOST", "prom.internal");
+        let config = |password: &str| {
+            let mut cfg: PrometheusOutboundConfig = toml::from_str(&format!(
+                "address = \"http://${{VOID_TEST_PROM_HOST}}:${{VOID_TEST_PROM_PORT:-9090}}/api/v1/write\"\ninbounds = [\"pipe:timeseries\"]\nauth = {{ type = \"basic\", username = \"void\", password = \"{}\" }}",
+                password
+            ))
+            .unwrap();
+            cfg.verify().map(|_| cfg)
+        };
+
+        let cfg = config("pass$$word").unwrap();
+        assert_eq!(cfg.address.get(), "http://prom.internal:9090/api/v1/write");
+        assert!(matches!(
+            cfg.auth,
+            AuthConfig::Basic { ref password, .. } if password.get() == "pass$word"
+        ));
+
+        let err = config("${VOID_TEST_PROM_PASSWORD}")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("auth.password"), "{}", err);
+        assert!(err.contains("VOID_TEST_PROM_PASSWORD"), "{}", err);
+
+        let err = config("pa${ss").unwrap_err().to_string();
+        assert!(err.contains("auth.password"), "{}", err);
+        let cfg = config("pa$${ss").unwrap();
+        assert!(matches!(
+            cfg.auth,
+            AuthConfig::Basic { ref password, .. } if password.get() == "pa${ss"
+        ));
+    }
+}
@@ -1,4 +1,4 @@
-use miette::Diagnostic;
+use miette::{Diagnostic, NamedSource, SourceSpan};
 use thiserror::Error;
 
 use crate::core::tag::TagId;
@@ -13,12 +13,38 @@ pub enum Error {
     InvalidConfig(String),
     #[error("Empty field: {0}.{1}")]
     EmptyField(TagId, &'static str),
+    #[error("{0}.{1} must not be zero")]
+    ZeroValue(String, &'static str),
+    #[error("Failed to resolve secret {0}: {1}")]
+    Secret(String, String),
+    #[error("{0}: {1}")]
+    Include(String, String),
+    #[error("Config files include each other: {0}")]
+    IncludeCycle(String),
     #[error("Invalid config file format: {0}")]
     InvalidConfigFileFormat(String),
     #[error(transparent)]
     InvalidJsonConfig(#[from] serde_json::Error),
     #[error(transparent)]
     InvalidTomlConfig(#[from] toml::de::Error),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    InvalidField(Box<FieldError>),
+}
+
+/// A config file which does not parse, or a field which does not deserialize, located as
+/// precisely as possible.
+#[derive(Debug, Error, Diagnostic)]
+#[error("{location}: {message}")]
+pub struct FieldError {
+    // The file, the line when known and the path of the field in the file, e.g.
+    // `config.toml:12: pipes[2].extra_labels`
+    pub location: String,
+    pub message: String,
+    #[source_code]
+    pub source_code: Option<NamedSource<String>>,
+    #[label("here")]
+    pub span: Option<SourceSpan>,
 }
 
 pub type Result<T> = miette::Result<T, Error>;
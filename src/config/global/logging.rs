@@ -0,0 +1,84 @@
+use std::{collections::BTreeMap, fmt::Display};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Verify;
+
+/// Prefix of the targets naming an actor, e.g. `tag:prometheus_out`.
+pub const TAG_TARGET_PREFIX: &str = "tag:";
+
+/// `[global.log]`, applied again on reload.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LogConfig {
+    // Level of the targets not listed below, `RUST_LOG` or `info` without it
+    #[serde(default)]
+    pub level: Option<LogLevel>,
+
+    // By module, e.g. `"app::core::pipe::timeseries" = "debug"`, the longest prefix wins, or by
+    // actor, e.g. `"tag:prometheus_out" = "trace"`, which takes precedence
+    #[serde(default)]
+    pub targets: BTreeMap<String, LogLevel>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for log::LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => log::LevelFilter::Off,
+            LogLevel::Error => log::LevelFilter::Error,
+            LogLevel::Warn => log::LevelFilter::Warn,
+            LogLevel::Info => log::LevelFilter::Info,
+            LogLevel::Debug => log::LevelFilter::Debug,
+            LogLevel::Trace => log::LevelFilter::Trace,
+        }
+    }
+}
+
+impl Display for LogLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            log::LevelFilter::from(*self).as_str().to_lowercase()
+        )
+    }
+}
+
+impl Display for LogConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.level {
+            Some(level) => write!(f, "{}", level)?,
+            None => write!(f, "RUST_LOG")?,
+        }
+        for (target, level) in &self.targets {
+            write!(f, ", {} = {}", target, level)?;
+        }
+        Ok(())
+    }
+}
+
+impl Verify for LogConfig {
+    fn verify(&mut self) -> crate::config::Result<()> {
+        for target in self.targets.keys() {
+            let name = target.strip_prefix(TAG_TARGET_PREFIX).unwrap_or(target);
+            if name.is_empty() || name.chars().any(char::is_whitespace) {
+                return Err(crate::config::Error::InvalidConfig(format!(
+                    "global.log: invalid target {:?}",
+                    target
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
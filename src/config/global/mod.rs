@@ -1,7 +1,14 @@
+use std::{fmt::Display, net::SocketAddr, ops::Deref};
+
 use log::warn;
 use serde::{Deserialize, Serialize};
 
-use super::Verify;
+use super::{pipe::label_policy::LabelPolicyConfig, types::DurationValue, Verify};
+use crate::core::{metrics::INTERNAL_METRICS_TAG, tag::TagId};
+
+pub mod logging;
+
+use logging::LogConfig;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GlobalConfig {
@@ -9,37 +16,280 @@ pub struct GlobalConfig {
     pub inbound_channel_buffer_size: usize,
     #[serde(default = "default_channel_buffer_size")]
     pub channel_buffer_size: usize,
+    // What the producers do once a channel is full, overridden by `channel_overflow` of an
+    // inbound or a pipe and by `inbound_overflow` of the consumers. Unset, the inbounds block
+    // and the others drop the oldest records.
+    #[serde(default)]
+    pub channel_overflow: Option<OverflowPolicy>,
     #[serde(default)]
     pub time_tracing: bool,
+    // How often the latencies of the stages are logged with `time_tracing`
+    #[serde(default = "default_time_tracing_interval")]
+    pub time_tracing_interval: DurationValue,
+    #[serde(default)]
+    pub stats: bool,
+
+    // Logs the size of the string interner and the strings counted the most when set
+    #[serde(default)]
+    pub interner_report_interval: Option<DurationValue>,
+    // Warns when more strings than this were interned since the previous report, the
+    // interner never frees them
+    #[serde(default = "default_interner_growth_warn_threshold")]
+    pub interner_growth_warn_threshold: usize,
+
+    // Log levels per target and per actor
+    #[serde(default)]
+    pub log: LogConfig,
+
+    // Label keys stripped by every pipe which does not override it
+    #[serde(default)]
+    pub label_policy: Option<LabelPolicyConfig>,
+
+    // Soft deadline of a single actor poll, per actor kind
+    #[serde(default)]
+    pub max_poll_duration: MaxPollDurationConfig,
+
+    // On shutdown, how long to wait for the records in flight to reach the outbounds
+    #[serde(default = "default_drain_timeout")]
+    pub drain_timeout: DurationValue,
+
+    // How often a panicking actor is restarted before the whole process shuts down
+    #[serde(default)]
+    pub restart_policy: RestartPolicyConfig,
+
+    // Sends the metrics of the actors into `internal:metrics` when set
+    #[serde(default)]
+    pub internal_metrics: Option<InternalMetricsConfig>,
+
+    // The records the pipes and outbounds fail on are sent into this channel, e.g.
+    // `internal:dead_letter`, instead of being dropped
+    #[serde(default)]
+    pub dead_letter: Option<TagId>,
+
+    // Serves the topology and the status of the actors over HTTP, e.g. `127.0.0.1:9099`
+    #[serde(default)]
+    pub admin_listen: Option<SocketAddr>,
+}
+
+/// What a producer does with a record once its channel is full, i.e. its slowest consumer
+/// has yet to receive `channel_buffer_size` records.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Wait for the slowest consumer to make room, slowing the producer down
+    Block,
+    /// Drop the record being sent
+    DropNewest,
+    /// Overwrite the oldest record the slowest consumer has yet to receive
+    #[default]
+    DropOldest,
+}
+
+impl OverflowPolicy {
+    /// Used when the consumers of a channel disagree: `block` over `drop_newest`, which keeps
+    /// what the consumers have yet to receive, over `drop_oldest`.
+    pub fn strictest(self, other: Self) -> Self {
+        let rank = |policy| match policy {
+            OverflowPolicy::Block => 2,
+            OverflowPolicy::DropNewest => 1,
+            OverflowPolicy::DropOldest => 0,
+        };
+        if rank(other) > rank(self) {
+            other
+        } else {
+            self
+        }
+    }
+}
+
+impl Display for OverflowPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OverflowPolicy::Block => write!(f, "block"),
+            OverflowPolicy::DropNewest => write!(f, "drop_newest"),
+            OverflowPolicy::DropOldest => write!(f, "drop_oldest"),
+        }
+    }
+}
+
+/// The built-in `internal:metrics` source, one record per actor every `interval`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InternalMetricsConfig {
+    #[serde(default = "default_internal_metrics_interval")]
+    pub interval: DurationValue,
+}
+
+impl Default for InternalMetricsConfig {
+    fn default() -> Self {
+        Self {
+            interval: default_internal_metrics_interval(),
+        }
+    }
+}
+
+/// A poll running past its deadline stops at the next record or chunk boundary and carries the
+/// rest of its work over to the next poll, so that cancellation is noticed in time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaxPollDurationConfig {
+    #[serde(default = "default_pipe_max_poll_duration")]
+    pub pipe: DurationValue,
+    #[serde(default = "default_outbound_max_poll_duration")]
+    pub outbound: DurationValue,
+}
+
+impl Default for MaxPollDurationConfig {
+    fn default() -> Self {
+        Self {
+            pipe: default_pipe_max_poll_duration(),
+            outbound: default_outbound_max_poll_duration(),
+        }
+    }
+}
+
+/// An actor whose poll panics is spawned again, unless it has already been restarted
+/// `max_restarts` times within the last `restart_window`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RestartPolicyConfig {
+    #[serde(default = "default_max_restarts")]
+    pub max_restarts: usize,
+    #[serde(default = "default_restart_window")]
+    pub restart_window: DurationValue,
+}
+
+impl Default for RestartPolicyConfig {
+    fn default() -> Self {
+        Self {
+            max_restarts: default_max_restarts(),
+            restart_window: default_restart_window(),
+        }
+    }
 }
 
 fn default_channel_buffer_size() -> usize {
     128
 }
 
+fn default_pipe_max_poll_duration() -> DurationValue {
+    DurationValue::from_millis(200)
+}
+
+fn default_outbound_max_poll_duration() -> DurationValue {
+    DurationValue::from_millis(500)
+}
+
+fn default_drain_timeout() -> DurationValue {
+    DurationValue::from_secs(10)
+}
+
+fn default_max_restarts() -> usize {
+    3
+}
+
+fn default_restart_window() -> DurationValue {
+    DurationValue::from_secs(60)
+}
+
+fn default_time_tracing_interval() -> DurationValue {
+    DurationValue::from_secs(10)
+}
+
+fn default_interner_growth_warn_threshold() -> usize {
+    10000
+}
+
+fn default_internal_metrics_interval() -> DurationValue {
+    DurationValue::from_secs(15)
+}
+
 pub static GLOBAL_CONFIG: once_cell::sync::OnceCell<GlobalConfig> =
     once_cell::sync::OnceCell::new();
 
-pub fn inbound_channel_buffer_size() -> usize {
+pub fn channel_buffer_size() -> usize {
     GLOBAL_CONFIG
         .get()
         .map_or(default_channel_buffer_size(), |config| {
-            config.inbound_channel_buffer_size
+            config.channel_buffer_size
         })
 }
 
-pub fn channel_buffer_size() -> usize {
+pub fn channel_overflow() -> OverflowPolicy {
     GLOBAL_CONFIG
         .get()
-        .map_or(default_channel_buffer_size(), |config| {
-            config.channel_buffer_size
-        })
+        .and_then(|config| config.channel_overflow)
+        .unwrap_or_default()
+}
+
+/// Same as [`channel_overflow`] for the channels of the inbounds, which block unless set so
+/// that a slow consumer throttles the reads instead of losing records.
+pub fn inbound_channel_overflow() -> OverflowPolicy {
+    GLOBAL_CONFIG
+        .get()
+        .and_then(|config| config.channel_overflow)
+        .unwrap_or(OverflowPolicy::Block)
 }
 
 pub fn use_time_tracing() -> bool {
     GLOBAL_CONFIG
         .get()
-        .map_or(false, |config| config.time_tracing)
+        .is_some_and(|config| config.time_tracing)
+}
+
+pub fn time_tracing_interval() -> DurationValue {
+    GLOBAL_CONFIG
+        .get()
+        .map_or_else(default_time_tracing_interval, |config| {
+            config.time_tracing_interval
+        })
+}
+
+pub fn use_stats() -> bool {
+    GLOBAL_CONFIG.get().is_some_and(|config| config.stats)
+}
+
+pub fn interner_report_interval() -> Option<DurationValue> {
+    GLOBAL_CONFIG
+        .get()
+        .and_then(|config| config.interner_report_interval)
+}
+
+pub fn interner_growth_warn_threshold() -> usize {
+    GLOBAL_CONFIG
+        .get()
+        .map_or_else(default_interner_growth_warn_threshold, |config| {
+            config.interner_growth_warn_threshold
+        })
+}
+
+pub fn label_policy() -> Option<LabelPolicyConfig> {
+    GLOBAL_CONFIG
+        .get()
+        .and_then(|config| config.label_policy.clone())
+}
+
+pub fn max_poll_duration() -> MaxPollDurationConfig {
+    GLOBAL_CONFIG
+        .get()
+        .map_or_else(MaxPollDurationConfig::default, |config| {
+            config.max_poll_duration.clone()
+        })
+}
+
+pub fn drain_timeout() -> DurationValue {
+    GLOBAL_CONFIG
+        .get()
+        .map_or_else(default_drain_timeout, |config| config.drain_timeout)
+}
+
+pub fn admin_listen() -> Option<SocketAddr> {
+    GLOBAL_CONFIG.get().and_then(|config| config.admin_listen)
+}
+
+pub fn restart_policy() -> RestartPolicyConfig {
+    GLOBAL_CONFIG
+        .get()
+        .map_or_else(RestartPolicyConfig::default, |config| {
+            config.restart_policy.clone()
+        })
 }
 
 impl Default for GlobalConfig {
@@ -47,7 +297,20 @@ impl Default for GlobalConfig {
         Self {
             inbound_channel_buffer_size: default_channel_buffer_size(),
             channel_buffer_size: default_channel_buffer_size(),
+            channel_overflow: None,
             time_tracing: false,
+            time_tracing_interval: default_time_tracing_interval(),
+            stats: false,
+            interner_report_interval: None,
+            interner_growth_warn_threshold: default_interner_growth_warn_threshold(),
+            log: LogConfig::default(),
+            label_policy: None,
+            max_poll_duration: MaxPollDurationConfig::default(),
+            drain_timeout: default_drain_timeout(),
+            restart_policy: RestartPolicyConfig::default(),
+            internal_metrics: None,
+            dead_letter: None,
+            admin_listen: None,
         }
     }
 }
@@ -56,7 +319,69 @@ impl Verify for GlobalConfig {
     fn verify(&mut self) -> super::Result<()> {
         warn!("Global Settings: ");
         warn!("  - channel_buffer_size: {}", self.channel_buffer_size);
-        warn!("  - time_tracing: {}", self.time_tracing);
+        match self.channel_overflow {
+            Some(overflow) => warn!("  - channel_overflow: {}", overflow),
+            None => warn!("  - channel_overflow: block (inbounds), drop_oldest (others)"),
+        }
+        self.time_tracing_interval
+            .ensure_non_zero("global", "time_tracing_interval")?;
+        match self.time_tracing {
+            true => warn!(
+                "  - time_tracing: true, every {}",
+                self.time_tracing_interval
+            ),
+            false => warn!("  - time_tracing: false"),
+        }
+        warn!("  - stats: {}", self.stats);
+        if let Some(interval) = self.interner_report_interval {
+            interval.ensure_non_zero("global", "interner_report_interval")?;
+            warn!(
+                "  - interner_report_interval: {}, warn above {} new strings",
+                interval, self.interner_growth_warn_threshold
+            );
+        }
+        self.log.verify()?;
+        warn!("  - log: {}", self.log);
+        warn!(
+            "  - max_poll_duration: pipe {}, outbound {}",
+            self.max_poll_duration.pipe, self.max_poll_duration.outbound
+        );
+        self.max_poll_duration
+            .pipe
+            .ensure_non_zero("global.max_poll_duration", "pipe")?;
+        self.max_poll_duration
+            .outbound
+            .ensure_non_zero("global.max_poll_duration", "outbound")?;
+        warn!("  - drain_timeout: {}", self.drain_timeout);
+        self.restart_policy
+            .restart_window
+            .ensure_non_zero("global.restart_policy", "restart_window")?;
+        warn!(
+            "  - restart_policy: at most {} restarts within {}",
+            self.restart_policy.max_restarts, self.restart_policy.restart_window
+        );
+        if let Some(ref internal_metrics) = self.internal_metrics {
+            internal_metrics
+                .interval
+                .ensure_non_zero("global.internal_metrics", "interval")?;
+            warn!("  - internal_metrics: every {}", internal_metrics.interval);
+        }
+        if let Some(ref dead_letter) = self.dead_letter {
+            if !dead_letter.is_internal() || dead_letter == INTERNAL_METRICS_TAG.deref() {
+                return Err(super::Error::InvalidConfig(format!(
+                    "global.dead_letter must be an internal tag other than {}, got {}",
+                    *INTERNAL_METRICS_TAG, dead_letter
+                )));
+            }
+            warn!("  - dead_letter: {}", dead_letter);
+        }
+        if let Some(ref admin_listen) = self.admin_listen {
+            warn!("  - admin_listen: {}", admin_listen);
+        }
+        if let Some(ref mut label_policy) = self.label_policy {
+            label_policy.verify()?;
+            warn!("  - label_policy: {}", label_policy);
+        }
 
         Ok(())
     }
@@ -0,0 +1,108 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::types::DurationValue, core::tag::TagId};
+
+/// Stop accepting new connections while the outbound channel of the inbound is saturated.
+///
+/// Pending connections wait in the listener backlog, existing connections are not affected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AcceptThrottleConfig {
+    /// Pause once the channel occupancy (0.0 ~ 1.0) reaches this value
+    #[serde(default = "default_accept_pause_threshold")]
+    pub accept_pause_threshold: f64,
+
+    /// Resume once the channel occupancy drops to this value
+    #[serde(default = "default_resume_threshold")]
+    pub resume_threshold: f64,
+
+    /// How often the occupancy is checked while paused
+    #[serde(default = "default_check_interval")]
+    pub check_interval: DurationValue,
+}
+
+impl Default for AcceptThrottleConfig {
+    fn default() -> Self {
+        Self {
+            accept_pause_threshold: default_accept_pause_threshold(),
+            resume_threshold: default_resume_threshold(),
+            check_interval: default_check_interval(),
+        }
+    }
+}
+
+impl AcceptThrottleConfig {
+    pub fn verify_for(&self, tag: &TagId) -> crate::config::Result<()> {
+        let valid = 0.0 < self.accept_pause_threshold && self.accept_pause_threshold <= 1.0;
+        if !valid {
+            return Err(crate::config::Error::InvalidConfig(format!(
+                "{}: accept_pause_threshold must be in (0, 1], got {}",
+                tag, self.accept_pause_threshold
+            )));
+        }
+
+        // 两个阈值之间留出间隔, 避免在阈值附近反复暂停/恢复
+        let valid =
+            0.0 <= self.resume_threshold && self.resume_threshold < self.accept_pause_threshold;
+        if !valid {
+            return Err(crate::config::Error::InvalidConfig(format!(
+                "{}: resume_threshold must be in [0, accept_pause_threshold), got {}",
+                tag, self.resume_threshold
+            )));
+        }
+
+        self.check_interval.ensure_non_zero(tag, "check_interval")
+    }
+}
+
+impl Display for AcceptThrottleConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "pause at {:.0}%, resume at {:.0}%",
+            self.accept_pause_threshold * 100.0,
+            self.resume_threshold * 100.0
+        )
+    }
+}
+
+fn default_accept_pause_threshold() -> f64 {
+    0.9
+}
+
+fn default_resume_threshold() -> f64 {
+    0.5
+}
+
+fn default_check_interval() -> DurationValue {
+    DurationValue::from_millis(100)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tag::InboundTagId;
+
+    fn verify(pause: f64, resume: f64) -> bool {
+        let cfg = AcceptThrottleConfig {
+            accept_pause_threshold: pause,
+            resume_threshold: resume,
+            ..Default::default()
+        };
+        cfg.verify_for(&InboundTagId::new("unix_socket").into())
+            .is_ok()
+    }
+
+    #[test]
+    fn test_thresholds() {
+        assert!(verify(0.9, 0.5));
+        assert!(verify(1.0, 0.0));
+        assert!(!verify(0.5, 0.5));
+        assert!(!verify(0.5, 0.9));
+        assert!(!verify(0.0, 0.0));
+        assert!(!verify(1.5, 0.5));
+        assert!(!verify(0.9, -0.1));
+        assert!(!verify(f64::NAN, 0.5));
+    }
+}
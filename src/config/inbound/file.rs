@@ -0,0 +1,60 @@
+use std::{fmt::Display, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{global::OverflowPolicy, inbound::timestamp::TimestampBoundsConfig, Verify},
+    core::tag::{InboundTagId, ProtocolTagId, TagId},
+};
+
+/// Reads a file once, from the beginning to the end, e.g. an export to backfill.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileInboundConfig {
+    #[serde(default = "default_file_tag")]
+    pub tag: InboundTagId,
+    pub path: PathBuf,
+    pub protocol: ProtocolTagId,
+    #[serde(default)]
+    pub disabled: bool,
+
+    // Overrides `global.channel_overflow` for the channel of this inbound
+    #[serde(default)]
+    pub channel_overflow: Option<OverflowPolicy>,
+
+    #[serde(default)]
+    pub timestamp_bounds: Option<TimestampBoundsConfig>,
+
+    // Send a `LifecycleEvent` record when the file is opened and once it is read
+    #[serde(default)]
+    pub emit_lifecycle_events: bool,
+
+    // Memory-map the file and parse it in parallel. Only for regular files and the CSV
+    // protocol, other inputs fall back to the streaming reader.
+    #[serde(default)]
+    pub bulk_mode: bool,
+}
+
+impl Display for FileInboundConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "FileInboundConfig {{ tag: {}, path: {}, bulk_mode: {}}}",
+            self.tag.as_ref(),
+            self.path.display(),
+            self.bulk_mode,
+        )
+    }
+}
+
+impl Verify for FileInboundConfig {
+    fn verify(&mut self) -> super::Result<()> {
+        if let Some(bounds) = &self.timestamp_bounds {
+            bounds.verify_for(&TagId::from(&self.tag))?;
+        }
+        Ok(())
+    }
+}
+
+fn default_file_tag() -> InboundTagId {
+    InboundTagId::new("file")
+}
@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::types::{ByteSize, DurationValue},
+    core::tag::TagId,
+};
+
+/// Protects a connection-based inbound from clients which never end a line, or which
+/// connect and never send anything.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionLimitsConfig {
+    /// The connection is closed once a line grows past it
+    #[serde(default = "default_max_line_bytes")]
+    pub max_line_bytes: ByteSize,
+
+    /// Close the connection once nothing arrived for this long, never by default
+    #[serde(default)]
+    pub idle_timeout: Option<DurationValue>,
+
+    /// New connections are closed right away while this many are open, unlimited by default
+    #[serde(default)]
+    pub max_connections: Option<usize>,
+}
+
+impl Default for ConnectionLimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_line_bytes: default_max_line_bytes(),
+            idle_timeout: None,
+            max_connections: None,
+        }
+    }
+}
+
+impl ConnectionLimitsConfig {
+    /// Whether another connection may be served while `open` are.
+    pub fn accepts(&self, open: usize) -> bool {
+        self.max_connections.is_none_or(|max| open < max)
+    }
+
+    pub fn verify_for(&self, tag: &TagId) -> crate::config::Result<()> {
+        self.max_line_bytes.ensure_non_zero(tag, "max_line_bytes")?;
+        if let Some(idle_timeout) = self.idle_timeout {
+            idle_timeout.ensure_non_zero(tag, "idle_timeout")?;
+        }
+        if self.max_connections == Some(0) {
+            return Err(crate::config::Error::ZeroValue(
+                tag.to_string(),
+                "max_connections",
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn default_max_line_bytes() -> ByteSize {
+    ByteSize::from(1024 * 1024)
+}
@@ -1,4 +1,9 @@
+pub mod accept;
+pub mod file;
+pub mod limits;
 pub mod named_pipe;
+pub mod tcp;
+pub mod timestamp;
 pub mod unix;
 
 use std::fmt::Display;
@@ -8,7 +13,7 @@ use serde::{Deserialize, Serialize};
 use crate::core::tag::{HasTag, TagId};
 
 pub use super::Result;
-use super::Verify;
+use super::{global::OverflowPolicy, Verify};
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
@@ -17,6 +22,10 @@ pub enum InboundConfig {
     UnixSocket(unix::UnixSocketConfig),
     #[serde(rename = "named_pipe")]
     NamedPipe(named_pipe::NamedPipeConfig),
+    #[serde(rename = "file")]
+    File(file::FileInboundConfig),
+    #[serde(rename = "tcp")]
+    Tcp(tcp::TcpConfig),
 }
 
 impl InboundConfig {
@@ -24,6 +33,8 @@ impl InboundConfig {
         match self {
             InboundConfig::UnixSocket(cfg) => From::from(&cfg.protocol),
             InboundConfig::NamedPipe(cfg) => From::from(&cfg.protocol),
+            InboundConfig::File(cfg) => From::from(&cfg.protocol),
+            InboundConfig::Tcp(cfg) => From::from(&cfg.protocol),
         }
     }
 
@@ -31,6 +42,37 @@ impl InboundConfig {
         match self {
             InboundConfig::UnixSocket(cfg) => cfg.disabled,
             InboundConfig::NamedPipe(cfg) => cfg.disabled,
+            InboundConfig::File(cfg) => cfg.disabled,
+            InboundConfig::Tcp(cfg) => cfg.disabled,
+        }
+    }
+
+    /// The `type` of the inbound in the config.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            InboundConfig::UnixSocket(_) => "unix_socket",
+            InboundConfig::NamedPipe(_) => "named_pipe",
+            InboundConfig::File(_) => "file",
+            InboundConfig::Tcp(_) => "tcp",
+        }
+    }
+
+    /// Whether the inbound can run on this platform: a unix socket needs unix, a named pipe
+    /// is a FIFO on unix and a pipe server on windows.
+    pub fn is_supported(&self) -> bool {
+        match self {
+            InboundConfig::UnixSocket(_) => cfg!(unix),
+            InboundConfig::NamedPipe(_) => cfg!(any(unix, windows)),
+            InboundConfig::File(_) | InboundConfig::Tcp(_) => true,
+        }
+    }
+
+    pub fn channel_overflow(&self) -> Option<OverflowPolicy> {
+        match self {
+            InboundConfig::UnixSocket(cfg) => cfg.channel_overflow,
+            InboundConfig::NamedPipe(cfg) => cfg.channel_overflow,
+            InboundConfig::File(cfg) => cfg.channel_overflow,
+            InboundConfig::Tcp(cfg) => cfg.channel_overflow,
         }
     }
 }
@@ -40,6 +82,8 @@ impl Display for InboundConfig {
         match self {
             InboundConfig::UnixSocket(cfg) => write!(f, "{}", cfg),
             InboundConfig::NamedPipe(cfg) => write!(f, "{}", cfg),
+            InboundConfig::File(cfg) => write!(f, "{}", cfg),
+            InboundConfig::Tcp(cfg) => write!(f, "{}", cfg),
         }
     }
 }
@@ -49,12 +93,22 @@ impl HasTag for InboundConfig {
         match self {
             InboundConfig::UnixSocket(cfg) => &cfg.tag,
             InboundConfig::NamedPipe(cfg) => &cfg.tag,
+            InboundConfig::File(cfg) => &cfg.tag,
+            InboundConfig::Tcp(cfg) => &cfg.tag,
         }
     }
 }
 
 impl Verify for InboundConfig {
     fn verify(&mut self) -> Result<()> {
+        if !self.is_supported() {
+            return Err(super::Error::InvalidConfig(format!(
+                "{}: inbound type {} is not supported on this platform",
+                self.tag(),
+                self.kind()
+            )));
+        }
+
         match self {
             InboundConfig::UnixSocket(cfg) => {
                 cfg.verify()?;
@@ -64,6 +118,50 @@ impl Verify for InboundConfig {
                 cfg.verify()?;
                 Ok(())
             }
+            InboundConfig::File(cfg) => {
+                cfg.verify()?;
+                Ok(())
+            }
+            InboundConfig::Tcp(cfg) => {
+                cfg.verify()?;
+                Ok(())
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verify(body: &str) -> Result<()> {
+        let mut cfg: InboundConfig = toml::from_str(body).unwrap();
+        cfg.verify()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_verify_unix_only() {
+        assert!(verify(
+            "type = \"unix_socket\"\npath = \"/tmp/void.sock\"\nprotocol = \"graphite\""
+        )
+        .is_ok());
+        assert!(verify(
+            "type = \"named_pipe\"\npath = \"/tmp/void.fifo\"\nprotocol = \"graphite\""
+        )
+        .is_ok());
+    }
+
+    #[cfg(not(unix))]
+    #[test]
+    fn test_verify_unix_only() {
+        let err = verify("type = \"unix_socket\"\npath = \"void.sock\"\nprotocol = \"graphite\"")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("inbound type unix_socket is not supported on this platform"),
+            "{}",
+            err
+        );
+    }
+}
@@ -3,18 +3,48 @@ use std::{fmt::Display, path::PathBuf};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    config::Verify,
-    core::tag::{InboundTagId, ProtocolTagId},
+    config::{
+        env, global::OverflowPolicy, inbound::timestamp::TimestampBoundsConfig, retry::RetryConfig,
+        Verify,
+    },
+    core::tag::{InboundTagId, ProtocolTagId, TagId},
 };
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct NamedPipeConfig {
     #[serde(default = "default_named_pipe_tag")]
     pub tag: InboundTagId,
+    // A FIFO on unix, `\\.\pipe\<name>` on windows
     pub path: PathBuf,
     pub protocol: ProtocolTagId,
     #[serde(default)]
     pub disabled: bool,
+
+    // Overrides `global.channel_overflow` for the channel of this inbound
+    #[serde(default)]
+    pub channel_overflow: Option<OverflowPolicy>,
+
+    #[serde(default)]
+    pub timestamp_bounds: Option<TimestampBoundsConfig>,
+
+    // Send a `LifecycleEvent` record when a writer connects and goes away
+    #[serde(default)]
+    pub emit_lifecycle_events: bool,
+
+    // Backoff between two reopenings of the pipe once its writer went away,
+    // `max_attempts` is ignored as the pipe is reopened forever. Unix only, the windows
+    // pipe serves each client on its own instance.
+    #[serde(default)]
+    pub reopen: RetryConfig,
+
+    // Reopen the pipe once its writer closed it, otherwise stop reading
+    #[serde(default = "default_reopen_on_eof")]
+    pub reopen_on_eof: bool,
+
+    // Clients served at once on windows, the others are disconnected right away. Unlimited by
+    // default, the unix FIFO has a single reader anyway
+    #[serde(default)]
+    pub max_connections: Option<usize>,
 }
 
 impl Display for NamedPipeConfig {
@@ -30,10 +60,68 @@ impl Display for NamedPipeConfig {
 
 impl Verify for NamedPipeConfig {
     fn verify(&mut self) -> super::Result<()> {
+        env::interpolate_path(&mut self.path, TagId::from(&self.tag), "path")?;
+
+        if self.max_connections == Some(0) {
+            return Err(crate::config::Error::ZeroValue(
+                self.tag.as_ref().to_string(),
+                "max_connections",
+            ));
+        }
+
+        #[cfg(windows)]
+        if !is_local_pipe_name(&self.path) {
+            return Err(crate::config::Error::InvalidConfig(format!(
+                "{}: {} is not a pipe name, expected \\\\.\\pipe\\<name>",
+                self.tag.as_ref(),
+                self.path.display()
+            )));
+        }
+
+        if let Some(bounds) = &self.timestamp_bounds {
+            bounds.verify_for(&TagId::from(&self.tag))?;
+        }
+        self.reopen.verify_for(self.tag.as_ref(), "reopen")?;
         Ok(())
     }
 }
 
+/// Whether `path` names a pipe of this machine, e.g. `\\.\pipe\void-metrics`. A server
+/// can't create the pipes of other machines.
+#[cfg(windows)]
+fn is_local_pipe_name(path: &std::path::Path) -> bool {
+    const PREFIX: &str = r"\\.\pipe\";
+
+    let path = path.to_string_lossy();
+    path.get(..PREFIX.len())
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(PREFIX))
+        && path.len() > PREFIX.len()
+}
+
+fn default_reopen_on_eof() -> bool {
+    true
+}
+
 fn default_named_pipe_tag() -> InboundTagId {
     InboundTagId::new("named_pipe")
 }
+
+#[cfg(all(test, windows))]
+mod tests {
+    use super::*;
+
+    fn verify(path: &str) -> super::super::Result<()> {
+        let mut cfg: NamedPipeConfig =
+            toml::from_str(&format!("path = {:?}\nprotocol = \"graphite\"", path)).unwrap();
+        cfg.verify()
+    }
+
+    #[test]
+    fn test_verify_pipe_name() {
+        assert!(verify(r"\\.\pipe\void-metrics").is_ok());
+        assert!(verify(r"\\.\PIPE\void\metrics").is_ok());
+        assert!(verify(r"\\.\pipe\").is_err());
+        assert!(verify(r"\\server\pipe\void-metrics").is_err());
+        assert!(verify(r"C:\void\metrics").is_err());
+    }
+}
@@ -0,0 +1,123 @@
+use std::{fmt::Display, net::ToSocketAddrs};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{
+        global::OverflowPolicy,
+        inbound::{
+            accept::AcceptThrottleConfig, limits::ConnectionLimitsConfig,
+            timestamp::TimestampBoundsConfig,
+        },
+        Verify,
+    },
+    core::tag::{InboundTagId, ProtocolTagId, TagId},
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TcpConfig {
+    #[serde(default = "default_tcp_tag")]
+    pub tag: InboundTagId,
+    /// Address to listen on, e.g. `0.0.0.0:2003`
+    pub address: String,
+    pub protocol: ProtocolTagId,
+    #[serde(default)]
+    pub disabled: bool,
+
+    // Overrides `global.channel_overflow` for the channel of this inbound
+    #[serde(default)]
+    pub channel_overflow: Option<OverflowPolicy>,
+
+    #[serde(default)]
+    pub timestamp_bounds: Option<TimestampBoundsConfig>,
+
+    // Send a `LifecycleEvent` record when a connection opens and closes
+    #[serde(default)]
+    pub emit_lifecycle_events: bool,
+
+    /// Pause accepting while the pipeline is saturated, off by default
+    #[serde(default)]
+    pub accept_throttle: Option<AcceptThrottleConfig>,
+
+    #[serde(flatten)]
+    pub limits: ConnectionLimitsConfig,
+}
+
+impl Display for TcpConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "TcpConfig {{ tag: {}, address: {}}}",
+            self.tag.as_ref(),
+            self.address,
+        )
+    }
+}
+
+impl Verify for TcpConfig {
+    fn verify(&mut self) -> super::Result<()> {
+        let tag = TagId::from(&self.tag);
+
+        if self.address.is_empty() {
+            return Err(crate::config::Error::EmptyField(tag, "address"));
+        }
+
+        let resolved = self
+            .address
+            .to_socket_addrs()
+            .map(|mut addrs| addrs.next().is_some());
+        if !matches!(resolved, Ok(true)) {
+            return Err(crate::config::Error::InvalidConfig(format!(
+                "{}: invalid address {:?}",
+                tag, self.address
+            )));
+        }
+
+        if let Some(throttle) = &self.accept_throttle {
+            throttle.verify_for(&tag)?;
+        }
+        if let Some(bounds) = &self.timestamp_bounds {
+            bounds.verify_for(&tag)?;
+        }
+        self.limits.verify_for(&tag)
+    }
+}
+
+fn default_tcp_tag() -> InboundTagId {
+    InboundTagId::new("tcp")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(address: &str) -> TcpConfig {
+        TcpConfig {
+            tag: default_tcp_tag(),
+            address: address.to_string(),
+            protocol: ProtocolTagId::new("graphite"),
+            disabled: false,
+            channel_overflow: None,
+            timestamp_bounds: None,
+            emit_lifecycle_events: false,
+            accept_throttle: None,
+            limits: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_verify_address() {
+        for valid in [
+            "0.0.0.0:2003",
+            "127.0.0.1:0",
+            "[::1]:2003",
+            "localhost:2003",
+        ] {
+            assert!(config(valid).verify().is_ok(), "{}", valid);
+        }
+
+        for invalid in ["", "0.0.0.0", "0.0.0.0:99999", "1.2.3.4.5:2003", ":2003"] {
+            assert!(config(invalid).verify().is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::types::DurationValue,
+    core::{tag::TagId, types::Symbol},
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampBoundsMode {
+    /// Replace the timestamp with the ingest time
+    Clamp,
+    /// Drop the record
+    Drop,
+    /// Let the record pass, with a warning
+    #[default]
+    Warn,
+}
+
+/// Sanity bounds for the timestamps sent by the producers of an inbound.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimestampBoundsConfig {
+    /// Field holding the timestamp, records without it are not checked
+    #[serde(default = "default_timestamp_bounds_field")]
+    pub field: Symbol,
+
+    /// How far in the past a timestamp may be
+    #[serde(default)]
+    pub max_past: Option<DurationValue>,
+
+    /// How far in the future a timestamp may be
+    #[serde(default)]
+    pub max_future: Option<DurationValue>,
+
+    #[serde(default)]
+    pub mode: TimestampBoundsMode,
+
+    /// With `clamp`, keep the replaced timestamp in this field
+    #[serde(default)]
+    pub original_field: Option<Symbol>,
+
+    /// Disconnect a connection once this fraction of its records violated the bounds
+    #[serde(default)]
+    pub max_violation_rate: Option<f64>,
+
+    /// Number of records a connection must have sent before its violation rate is considered
+    #[serde(default = "default_timestamp_bounds_min_records")]
+    pub min_records: u64,
+}
+
+impl TimestampBoundsConfig {
+    pub fn verify_for(&self, tag: &TagId) -> crate::config::Result<()> {
+        if let Some(rate) = self.max_violation_rate {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(crate::config::Error::InvalidConfig(format!(
+                    "{}: max_violation_rate must be in [0, 1], got {}",
+                    tag, rate
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn default_timestamp_bounds_field() -> Symbol {
+    Symbol::from("timestamp")
+}
+
+fn default_timestamp_bounds_min_records() -> u64 {
+    100
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tag::InboundTagId;
+
+    fn verify(max_violation_rate: Option<f64>) -> bool {
+        let cfg = TimestampBoundsConfig {
+            field: default_timestamp_bounds_field(),
+            max_past: None,
+            max_future: None,
+            mode: TimestampBoundsMode::Warn,
+            original_field: None,
+            max_violation_rate,
+            min_records: default_timestamp_bounds_min_records(),
+        };
+        cfg.verify_for(&InboundTagId::new("tcp").into()).is_ok()
+    }
+
+    #[test]
+    fn test_max_violation_rate() {
+        assert!(verify(None));
+        assert!(verify(Some(0.0)));
+        assert!(verify(Some(0.5)));
+        assert!(verify(Some(1.0)));
+        assert!(!verify(Some(-0.1)));
+        assert!(!verify(Some(1.5)));
+        assert!(!verify(Some(f64::NAN)));
+    }
+}
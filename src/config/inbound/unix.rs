@@ -3,8 +3,16 @@ use std::{fmt::Display, path::PathBuf};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    config::Verify,
-    core::tag::{InboundTagId, ProtocolTagId},
+    config::{
+        env,
+        global::OverflowPolicy,
+        inbound::{
+            accept::AcceptThrottleConfig, limits::ConnectionLimitsConfig,
+            timestamp::TimestampBoundsConfig,
+        },
+        Verify,
+    },
+    core::tag::{InboundTagId, ProtocolTagId, TagId},
 };
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -15,6 +23,24 @@ pub struct UnixSocketConfig {
     pub protocol: ProtocolTagId,
     #[serde(default)]
     pub disabled: bool,
+
+    // Overrides `global.channel_overflow` for the channel of this inbound
+    #[serde(default)]
+    pub channel_overflow: Option<OverflowPolicy>,
+
+    #[serde(default)]
+    pub timestamp_bounds: Option<TimestampBoundsConfig>,
+
+    // Send a `LifecycleEvent` record when a connection opens and closes
+    #[serde(default)]
+    pub emit_lifecycle_events: bool,
+
+    /// Pause accepting while the pipeline is saturated, off by default
+    #[serde(default)]
+    pub accept_throttle: Option<AcceptThrottleConfig>,
+
+    #[serde(flatten)]
+    pub limits: ConnectionLimitsConfig,
 }
 
 impl Display for UnixSocketConfig {
@@ -30,7 +56,15 @@ impl Display for UnixSocketConfig {
 
 impl Verify for UnixSocketConfig {
     fn verify(&mut self) -> super::Result<()> {
-        Ok(())
+        env::interpolate_path(&mut self.path, TagId::from(&self.tag), "path")?;
+        let tag = TagId::from(&self.tag);
+        if let Some(throttle) = &self.accept_throttle {
+            throttle.verify_for(&tag)?;
+        }
+        if let Some(bounds) = &self.timestamp_bounds {
+            bounds.verify_for(&tag)?;
+        }
+        self.limits.verify_for(&tag)
     }
 }
 
@@ -0,0 +1,477 @@
+/*
+A config can be split across files: the top-level `include` of a file lists other files, or glob
+patterns, relative to its directory. The `inbounds`, `outbounds`, `protocols` and `pipes` of an
+included file are appended to those of the including one, before the whole config is verified,
+so duplicate tags are found across files. Included files may include others in turn, but may
+not set `global` nor anything else.
+
+A directory can be given instead of a file: its `*.toml` and `*.json` files are read in name
+order as if included, and one of them (e.g. `00-global.toml`) may set `global`.
+*/
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::BufReader,
+    path::{Component, Path, PathBuf},
+};
+
+use globset::GlobBuilder;
+use log::warn;
+use serde_json::{Map, Value};
+
+use super::{diagnostic, secret, Error, Result};
+
+const INCLUDE_KEY: &str = "include";
+const GLOBAL_KEY: &str = "global";
+
+// The arrays concatenated across the files
+const MERGED_KEYS: &[&str] = &["inbounds", "outbounds", "protocols", "pipes"];
+
+type Tree = Map<String, Value>;
+
+/// The file each part of a merged tree comes from, to point at it when it is invalid.
+#[derive(Debug, Clone)]
+pub struct Sources {
+    // The file setting `global` and the other top-level keys
+    root: PathBuf,
+    // For each merged array, the file of each item and the index of the item in that file
+    items: HashMap<String, Vec<(PathBuf, usize)>>,
+}
+
+impl Sources {
+    fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            items: HashMap::new(),
+        }
+    }
+
+    /// The items of the merged arrays of a file.
+    fn of_file(path: &Path, tree: &Tree) -> Self {
+        let mut sources = Self::new(path);
+        for key in MERGED_KEYS {
+            if let Some(Value::Array(items)) = tree.get(*key) {
+                sources.items.insert(
+                    key.to_string(),
+                    (0..items.len()).map(|i| (path.to_path_buf(), i)).collect(),
+                );
+            }
+        }
+        sources
+    }
+
+    fn extend(&mut self, included: Sources) {
+        for (key, items) in included.items {
+            self.items.entry(key).or_default().extend(items);
+        }
+    }
+
+    /// The root config, or the file setting `global` in a directory.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The file `<key>[<index>]` of the merged tree comes from, and its index in that file.
+    pub fn item(&self, key: &str, index: usize) -> Option<(&Path, usize)> {
+        let (path, index) = self.items.get(key)?.get(index)?;
+        Some((path, *index))
+    }
+}
+
+/// The tree of the config at `path`, a file or a directory, with the included files merged in.
+/// Secret references are resolved file by file.
+pub fn load_tree(path: &Path) -> Result<(Value, Sources)> {
+    let mut loader = Loader::default();
+    let (tree, sources) = if path.is_dir() {
+        loader.load_dir(path)?
+    } else {
+        loader.load(path, true)?
+    };
+    Ok((Value::Object(tree), sources))
+}
+
+#[derive(Default)]
+struct Loader {
+    // The files being loaded, the root first, to tell a cycle apart
+    stack: Vec<PathBuf>,
+}
+
+impl Loader {
+    fn load_dir(&mut self, dir: &Path) -> Result<(Tree, Sources)> {
+        let mut files = std::fs::read_dir(dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        files.retain(|path| path.is_file() && format_of(path).is_some());
+        files.sort();
+        if files.is_empty() {
+            return Err(include_error(dir, "no *.toml or *.json config file"));
+        }
+
+        let mut tree = Tree::new();
+        let mut sources = Sources::new(dir);
+        let mut global_from: Option<PathBuf> = None;
+        for file in files {
+            let (mut included, included_sources) = self.load(&file, true)?;
+            if let Some(global) = included.remove(GLOBAL_KEY) {
+                if let Some(ref first) = global_from {
+                    return Err(include_error(
+                        &file,
+                        format!("`global` is already set by {}", first.display()),
+                    ));
+                }
+                tree.insert(GLOBAL_KEY.to_string(), global);
+                global_from = Some(file.clone());
+                sources.root = file.clone();
+            }
+            merge(&mut tree, included, &file)?;
+            sources.extend(included_sources);
+        }
+
+        Ok((tree, sources))
+    }
+
+    /// Load a file along with its includes, `global` is only allowed in a root.
+    fn load(&mut self, path: &Path, root: bool) -> Result<(Tree, Sources)> {
+        let canonical = path
+            .canonicalize()
+            .map_err(|e| include_error(path, e.to_string()))?;
+        if let Some(start) = self.stack.iter().position(|p| *p == canonical) {
+            let cycle = self.stack[start..]
+                .iter()
+                .chain(std::iter::once(&canonical))
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>()
+                .join(" -> ");
+            return Err(Error::IncludeCycle(cycle));
+        }
+
+        let mut tree = read_file(path)?;
+        let mut sources = Sources::of_file(path, &tree);
+        if !root {
+            if let Some(key) = tree
+                .keys()
+                .find(|key| *key != INCLUDE_KEY && !MERGED_KEYS.contains(&key.as_str()))
+            {
+                return Err(include_error(
+                    path,
+                    format!(
+                        "`{}` is only allowed in the root config, an included file may only set {}",
+                        key,
+                        MERGED_KEYS.join(", ")
+                    ),
+                ));
+            }
+        }
+
+        let patterns = match tree.remove(INCLUDE_KEY) {
+            None => Vec::new(),
+            Some(Value::Array(patterns)) => patterns
+                .into_iter()
+                .map(|pattern| match pattern {
+                    Value::String(pattern) => Ok(pattern),
+                    other => Err(include_error(
+                        path,
+                        format!("`include` must list strings, got {}", other),
+                    )),
+                })
+                .collect::<Result<Vec<_>>>()?,
+            Some(other) => {
+                return Err(include_error(
+                    path,
+                    format!("`include` must be an array, got {}", other),
+                ))
+            }
+        };
+
+        self.stack.push(canonical);
+        let base = path.parent().unwrap_or(Path::new("."));
+        for pattern in patterns {
+            for file in expand(base, &pattern, path)? {
+                let (included, included_sources) = self.load(&file, false)?;
+                merge(&mut tree, included, &file)?;
+                sources.extend(included_sources);
+            }
+        }
+        self.stack.pop();
+
+        Ok((tree, sources))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Format {
+    Json,
+    Toml,
+}
+
+pub(super) fn format_of(path: &Path) -> Option<Format> {
+    match path.extension()?.to_str()? {
+        "json" => Some(Format::Json),
+        "toml" => Some(Format::Toml),
+        _ => None,
+    }
+}
+
+fn read_file(path: &Path) -> Result<Tree> {
+    let tree = match format_of(path) {
+        Some(Format::Json) => {
+            let mut tree: Value = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+            secret::resolve_json(&mut tree, "")?;
+            tree
+        }
+        Some(Format::Toml) => {
+            let text = std::fs::read_to_string(path)?;
+            let mut tree: toml::Value = toml::de::from_str(&text)
+                .map_err(|e| diagnostic::syntax_error(path, text.clone(), e))?;
+            secret::resolve_toml(&mut tree, "")?;
+            serde_json::to_value(tree)?
+        }
+        None => {
+            let ext = path
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_string());
+            return Err(Error::InvalidConfigFileFormat(ext.unwrap_or_default()));
+        }
+    };
+
+    match tree {
+        Value::Object(tree) => Ok(tree),
+        _ => Err(include_error(path, "the config must be a table")),
+    }
+}
+
+/// Append the arrays of an included file to those of the including one.
+fn merge(tree: &mut Tree, included: Tree, from: &Path) -> Result<()> {
+    for (key, value) in included {
+        let Value::Array(items) = value else {
+            return Err(include_error(from, format!("`{}` must be an array", key)));
+        };
+        match tree
+            .entry(key.clone())
+            .or_insert_with(|| Value::Array(Vec::new()))
+        {
+            Value::Array(existing) => existing.extend(items),
+            _ => return Err(include_error(from, format!("`{}` must be an array", key))),
+        }
+    }
+    Ok(())
+}
+
+/// The files matching an include, in name order. A plain path must exist, a pattern may
+/// match nothing. Symlinked directories are not followed, so that a link cycle cannot make the
+/// walk endless.
+fn expand(base: &Path, pattern: &str, from: &Path) -> Result<Vec<PathBuf>> {
+    // `./pipes/*.toml` is matched as `pipes/*.toml`
+    let full = base
+        .join(pattern)
+        .components()
+        .filter(|c| *c != Component::CurDir)
+        .collect::<PathBuf>();
+
+    let is_glob = |c: &Component| {
+        c.as_os_str()
+            .to_string_lossy()
+            .contains(['*', '?', '[', '{'])
+    };
+    if !full.components().any(|c| is_glob(&c)) {
+        if !full.is_file() {
+            return Err(include_error(
+                from,
+                format!("included file {} not found", full.display()),
+            ));
+        }
+        return Ok(vec![full]);
+    }
+
+    // Walk from the deepest directory without a wildcard
+    let root = full
+        .components()
+        .take_while(|c| !is_glob(c))
+        .collect::<PathBuf>();
+    let matcher = GlobBuilder::new(&full.to_string_lossy())
+        .literal_separator(true)
+        .build()
+        .map_err(|e| include_error(from, format!("invalid include {:?}: {}", pattern, e)))?
+        .compile_matcher();
+
+    let mut files = Vec::new();
+    let mut dirs = vec![root];
+    while let Some(dir) = dirs.pop() {
+        // A pattern relative to the working directory, e.g. `*.toml`, has no root
+        let listed = match dir.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => dir.as_path(),
+        };
+        let entries = match std::fs::read_dir(listed) {
+            Ok(entries) => entries,
+            // Nothing to match under a directory which does not exist
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                return Err(include_error(
+                    from,
+                    format!("cannot list {}: {}", listed.display(), e),
+                ))
+            }
+        };
+        for entry in entries {
+            let entry = entry?;
+            let path = dir.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+            } else if matcher.is_match(&path) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+
+    if files.is_empty() {
+        warn!("{}: include {:?} matches no file", from.display(), pattern);
+    }
+    Ok(files)
+}
+
+fn include_error(path: &Path, message: impl Into<String>) -> Error {
+    Error::Include(path.display().to_string(), message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, name: &str, content: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn tags(tree: &Value, key: &str) -> Vec<String> {
+        tree[key]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["tag"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = write(
+            dir.path(),
+            "config.toml",
+            r#"
+            include = ["./pipes/*.toml", "outbounds.json"]
+
+            [global]
+            time_tracing = true
+
+            [[pipes]]
+            type = "timeseries"
+            tag = "root"
+            "#,
+        );
+        write(
+            dir.path(),
+            "pipes/b.toml",
+            "[[pipes]]\ntype = \"filter\"\ntag = \"b\"",
+        );
+        write(
+            dir.path(),
+            "pipes/a.toml",
+            "include = [\"nested/*.toml\"]\n[[pipes]]\ntype = \"filter\"\ntag = \"a\"",
+        );
+        write(
+            dir.path(),
+            "pipes/nested/c.toml",
+            "[[inbounds]]\ntype = \"tcp\"\ntag = \"c\"",
+        );
+        write(
+            dir.path(),
+            "outbounds.json",
+            r#"{ "outbounds": [{ "type": "stdio", "tag": "out" }] }"#,
+        );
+
+        let (tree, sources) = load_tree(&root).unwrap();
+        // In include order, the files of a pattern sorted by name
+        assert_eq!(tags(&tree, "pipes"), vec!["root", "a", "b"]);
+        assert_eq!(tags(&tree, "inbounds"), vec!["c"]);
+        assert_eq!(tags(&tree, "outbounds"), vec!["out"]);
+        assert_eq!(tree["global"]["time_tracing"], true);
+        assert!(tree.get(INCLUDE_KEY).is_none());
+
+        let (file, index) = sources.item("pipes", 2).unwrap();
+        assert!(file.ends_with("pipes/b.toml"));
+        assert_eq!(index, 0);
+        assert_eq!(sources.item("pipes", 0), Some((root.as_path(), 0)));
+        assert_eq!(sources.item("pipes", 3), None);
+        assert_eq!(sources.root(), root);
+    }
+
+    #[test]
+    fn test_include_errors() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let root = write(dir.path(), "missing.toml", "include = [\"nope.toml\"]");
+        let err = load_tree(&root).unwrap_err().to_string();
+        assert!(err.contains("nope.toml"), "{}", err);
+
+        write(dir.path(), "global.toml", "[global]\ntime_tracing = true");
+        let root = write(dir.path(), "root.toml", "include = [\"global.toml\"]");
+        let err = load_tree(&root).unwrap_err().to_string();
+        assert!(err.contains("`global` is only allowed"), "{}", err);
+
+        let root = write(dir.path(), "self.toml", "include = [\"other.toml\"]");
+        write(dir.path(), "other.toml", "include = [\"self.toml\"]");
+        assert!(matches!(load_tree(&root), Err(Error::IncludeCycle(_))));
+    }
+
+    #[test]
+    fn test_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            "00-global.toml",
+            "[global]\ntime_tracing = true\n[[pipes]]\ntype = \"filter\"\ntag = \"a\"",
+        );
+        write(
+            dir.path(),
+            "10-pipes.toml",
+            "[[pipes]]\ntype = \"filter\"\ntag = \"b\"",
+        );
+        write(dir.path(), "README.md", "not a config");
+
+        let (tree, sources) = load_tree(dir.path()).unwrap();
+        assert_eq!(tags(&tree, "pipes"), vec!["a", "b"]);
+        assert_eq!(tree["global"]["time_tracing"], true);
+        assert!(sources.root().ends_with("00-global.toml"));
+
+        write(
+            dir.path(),
+            "20-global.toml",
+            "[global]\ntime_tracing = false",
+        );
+        let err = load_tree(dir.path()).unwrap_err().to_string();
+        assert!(err.contains("already set"), "{}", err);
+    }
+
+    #[test]
+    fn test_expand_relative_to_working_directory() {
+        // The tests run from the crate root
+        let files = expand(Path::new(""), "Cargo.t?ml", Path::new("config.toml")).unwrap();
+        assert_eq!(files, vec![PathBuf::from("Cargo.toml")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_expand_skips_symlinked_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "pipes/a.toml", "");
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("pipes/loop")).unwrap();
+
+        let files = expand(dir.path(), "**/*.toml", Path::new("config.toml")).unwrap();
+        assert_eq!(files, vec![dir.path().join("pipes/a.toml")]);
+    }
+}
@@ -0,0 +1,166 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::core::types::Symbol;
+
+/// Path to a value of a record: a field, then map keys or array indices, separated by
+/// dots, e.g. `host`, `labels.region` or `values.0`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct FieldPath {
+    field: Symbol,
+    segments: Vec<String>,
+}
+
+impl FieldPath {
+    pub fn field(&self) -> &Symbol {
+        &self.field
+    }
+
+    /// The map keys or array indices after the field.
+    pub fn segments(&self) -> &[String] {
+        &self.segments
+    }
+}
+
+impl TryFrom<String> for FieldPath {
+    type Error = String;
+
+    fn try_from(path: String) -> Result<Self, Self::Error> {
+        let mut segments = path.split('.');
+        let field = segments.next().unwrap_or_default();
+        let segments = segments.map(str::to_string).collect::<Vec<_>>();
+
+        if field.is_empty() || segments.iter().any(|s| s.is_empty()) {
+            return Err(format!("invalid field path {:?}", path));
+        }
+
+        Ok(Self {
+            field: Symbol::new(field),
+            segments,
+        })
+    }
+}
+
+impl From<FieldPath> for String {
+    fn from(path: FieldPath) -> Self {
+        path.to_string()
+    }
+}
+
+impl Display for FieldPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.field)?;
+        for segment in &self.segments {
+            write!(f, ".{}", segment)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyHash {
+    #[default]
+    Xxh3,
+    Fnv1a,
+}
+
+/// What to do when a record lacks one of the key fields.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingField {
+    /// The missing field is part of the key, as a marker distinct from any value (null included)
+    #[default]
+    Empty,
+    /// The record has no key, the feature leaves it alone
+    Skip,
+    /// The record is rejected
+    Error,
+}
+
+/// Which values of a record make up its key.
+///
+/// Shared by the features grouping records (routing, dedupe, rates, ordering...), so that they
+/// all read the same config and compute the same key out of the same record.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeySpec {
+    #[serde(default)]
+    pub fields: Vec<FieldPath>,
+
+    /// Put the metric name (the `name` field) first in the key
+    #[serde(default)]
+    pub include_name: bool,
+
+    #[serde(default)]
+    pub hash: KeyHash,
+
+    /// `empty` when not set, unless the feature reading the key has its own default
+    #[serde(default)]
+    pub missing: Option<MissingField>,
+}
+
+impl KeySpec {
+    pub fn verify_for(&self, owner: impl Display) -> super::Result<()> {
+        if self.fields.is_empty() && !self.include_name {
+            return Err(super::Error::InvalidConfig(format!(
+                "{}: the key has no field",
+                owner
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_field_path() {
+        let path = FieldPath::try_from("labels.region".to_string()).unwrap();
+        assert_eq!(path.field(), &Symbol::new("labels"));
+        assert_eq!(path.segments(), ["region"]);
+        assert_eq!(path.to_string(), "labels.region");
+
+        for invalid in ["", ".", "labels.", ".region", "a..b"] {
+            assert!(
+                FieldPath::try_from(invalid.to_string()).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_key_spec_serde() {
+        let spec: KeySpec = toml::from_str(
+            r#"
+            fields = ["host", "labels.region"]
+            include_name = true
+            missing = "skip"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(spec.fields.len(), 2);
+        assert_eq!(spec.hash, KeyHash::Xxh3);
+        assert_eq!(spec.missing, Some(MissingField::Skip));
+        assert!(spec.verify_for("test").is_ok());
+
+        let json = serde_json::to_string(&spec).unwrap();
+        assert!(
+            json.contains(r#""fields":["host","labels.region"]"#),
+            "{}",
+            json
+        );
+
+        assert!(toml::from_str::<KeySpec>(r#"fields = ["labels..a"]"#).is_err());
+        assert!(KeySpec {
+            include_name: false,
+            ..toml::from_str(r#"fields = []"#).unwrap()
+        }
+        .verify_for("test")
+        .is_err());
+    }
+}
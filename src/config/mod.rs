@@ -1,13 +1,19 @@
+mod diagnostic;
 pub mod env;
 pub mod error;
 pub mod global;
 pub mod inbound;
+pub mod include;
+pub mod keying;
 pub mod outbound;
 pub mod pipe;
 pub mod protocol;
+pub mod retry;
+pub mod secret;
 pub mod template;
+pub mod types;
 
-use std::path::PathBuf;
+use std::path::Path;
 
 pub use error::{Error, Result};
 use global::{GlobalConfig, GLOBAL_CONFIG};
@@ -15,7 +21,6 @@ pub use outbound::OutboundConfig;
 use pipe::PipeConfig;
 pub use protocol::ProtocolConfig;
 use serde::{Deserialize, Serialize};
-use std::{fs::File, io::BufReader};
 
 use crate::{config::inbound::InboundConfig, core::tag::find_duplicate_tags};
 
@@ -34,7 +39,19 @@ pub struct Config {
 }
 
 impl Config {
-    pub fn load_from_file(path: &PathBuf) -> error::Result<Self> {
+    pub fn load_from_file(path: &Path) -> error::Result<Self> {
+        let config = Self::read_from_file(path)?;
+
+        GLOBAL_CONFIG
+            .set(config.global.clone())
+            .expect("Failed to set global config");
+
+        Ok(config)
+    }
+
+    /// Read and verify the config, without setting the global config. Used on reload, where
+    /// the global config keeps its value.
+    pub fn read_from_file(path: &Path) -> error::Result<Self> {
         if !path.exists() {
             return Err(Error::Io(std::io::Error::new(
                 std::io::ErrorKind::NotFound,
@@ -42,36 +59,20 @@ impl Config {
             )));
         }
 
-        if !path.is_file() {
-            return Err(Error::Io(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                format!("Given config file path is not a file: {}", path.display()),
-            )));
-        }
-
-        let ext = path
-            .extension()
-            .expect("Invalid extension")
-            .to_str()
-            .expect("Invalid encoding");
-
-        let mut config: Config = match ext {
-            "json" => serde_json::from_reader(BufReader::new(File::open(path)?))?,
-            "toml" => {
-                let text = std::fs::read_to_string(path)?;
-                toml::de::from_str(&text)?
-            }
-            _ => return Err(Error::InvalidConfigFileFormat(ext.to_string())),
-        };
+        // A directory is read as its files included in name order
+        let (tree, sources) = include::load_tree(path)?;
+        let mut config: Config = diagnostic::deserialize(&tree, &sources)?;
 
         config.verify()?;
 
-        GLOBAL_CONFIG
-            .set(config.global.clone())
-            .expect("Failed to set global config");
-
         Ok(config)
     }
+
+    /// The loaded config as JSON, with the resolved secrets redacted.
+    pub fn effective_config(&self) -> error::Result<String> {
+        let text = serde_json::to_string_pretty(self)?;
+        Ok(secret::redact(&text))
+    }
 }
 
 macro_rules! check_empty {
@@ -1,12 +1,18 @@
+use std::{fmt::Display, path::PathBuf};
+
 use serde::{Deserialize, Serialize};
 
-use crate::config::env::Env;
+use crate::config::{
+    env::{interpolate_path, Env},
+    secret::read_secret_file,
+};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "type")]
 pub enum AuthConfig {
     /// No authentication
+    #[default]
     None,
     /// Basic authentication
     Basic {
@@ -20,10 +26,95 @@ pub enum AuthConfig {
         /// Token
         token: Env<String>,
     },
+    /// Basic authentication, the credentials being read from files
+    BasicFile {
+        username_file: PathBuf,
+        password_file: PathBuf,
+    },
+    /// Bearer token authentication, the token being read from a file
+    BearerFile { token_file: PathBuf },
+}
+
+impl AuthConfig {
+    /// Interpolate the environment variables in the credentials and the paths, see
+    /// [`crate::config::env::interpolate`], and make sure the files can be read, see
+    /// [`read_secret_file`].
+    pub fn verify_for(&mut self, owner: impl Display) -> super::Result<()> {
+        match self {
+            AuthConfig::None => Ok(()),
+            AuthConfig::Basic { username, password } => {
+                username.interpolate(&owner, "auth.username")?;
+                password.interpolate(&owner, "auth.password")
+            }
+            AuthConfig::Bearer { token } => token.interpolate(&owner, "auth.token"),
+            AuthConfig::BasicFile {
+                username_file,
+                password_file,
+            } => {
+                interpolate_path(username_file, &owner, "auth.username_file")?;
+                interpolate_path(password_file, &owner, "auth.password_file")?;
+                read_secret_file(username_file).map_err(|e| {
+                    super::Error::Secret(format!("{}.auth.username_file", owner), e)
+                })?;
+                read_secret_file(password_file).map_err(|e| {
+                    super::Error::Secret(format!("{}.auth.password_file", owner), e)
+                })?;
+                Ok(())
+            }
+            AuthConfig::BearerFile { token_file } => {
+                interpolate_path(token_file, &owner, "auth.token_file")?;
+                read_secret_file(token_file)
+                    .map_err(|e| super::Error::Secret(format!("{}.auth.token_file", owner), e))?;
+                Ok(())
+            }
+        }
+    }
+
+    /// The files the credentials are read from.
+    pub fn files(&self) -> Vec<&PathBuf> {
+        match self {
+            AuthConfig::BasicFile {
+                username_file,
+                password_file,
+            } => vec![username_file, password_file],
+            AuthConfig::BearerFile { token_file } => vec![token_file],
+            _ => Vec::new(),
+        }
+    }
 }
 
-impl Default for AuthConfig {
-    fn default() -> Self {
-        AuthConfig::None
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verify(body: &str) -> super::super::Result<AuthConfig> {
+        let mut cfg: AuthConfig = toml::from_str(body).unwrap();
+        cfg.verify_for("outbound:prometheus").map(|_| cfg)
+    }
+
+    #[test]
+    fn test_verify_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let token = dir.path().join("token");
+        std::fs::write(&token, "s3cret\n").unwrap();
+
+        // Unset, the path comes from the default, the environment is shared by the tests
+        let cfg = verify(&format!(
+            "type = \"bearer_file\"\ntoken_file = \"${{VOID_TEST_AUTH_DIR:-{}}}/token\"",
+            dir.path().display()
+        ))
+        .unwrap();
+        assert_eq!(cfg.files(), vec![&token]);
+        assert_eq!(read_secret_file(&token).unwrap(), "s3cret");
+
+        let err = verify(&format!(
+            "type = \"basic_file\"\nusername_file = \"{}\"\npassword_file = \"{}/missing\"",
+            token.display(),
+            dir.path().display()
+        ))
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("auth.password_file"), "{}", err);
+        assert!(err.contains("missing"), "{}", err);
     }
 }
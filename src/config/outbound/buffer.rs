@@ -0,0 +1,46 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::types::ByteSize, core::tag::TagId};
+
+/// Spool on disk of what an outbound failed to deliver, replayed in order once it recovers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiskBufferConfig {
+    /// Directory of the segment files, created if missing and replayed after a restart
+    pub path: PathBuf,
+
+    /// Cap on the size of the segments, the oldest ones are dropped beyond it
+    #[serde(default = "default_buffer_max_size")]
+    pub max_size: ByteSize,
+
+    /// Number of entries read back from the disk per replayed request
+    #[serde(default = "default_buffer_flush_batch_size")]
+    pub flush_batch_size: usize,
+}
+
+impl DiskBufferConfig {
+    pub fn verify_for(&self, tag: &TagId) -> super::Result<()> {
+        if self.path.as_os_str().is_empty() {
+            return Err(super::Error::EmptyField(tag.clone(), "buffer.path"));
+        }
+
+        self.max_size.ensure_non_zero(tag, "buffer.max_size")?;
+        if self.flush_batch_size == 0 {
+            return Err(super::Error::ZeroValue(
+                tag.to_string(),
+                "buffer.flush_batch_size",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+fn default_buffer_max_size() -> ByteSize {
+    ByteSize::from(1024 * 1024 * 1024)
+}
+
+fn default_buffer_flush_batch_size() -> usize {
+    1000
+}
@@ -0,0 +1,178 @@
+use std::{collections::HashSet, path::PathBuf};
+
+use chrono::format::{Item, StrftimeItems};
+use serde::{Deserialize, Serialize};
+
+use super::{LoadSheddingConfig, StableOrderConfig};
+use crate::{
+    config::{global::OverflowPolicy, template::Template, Verify},
+    core::{
+        tag::{OutboundTagId, TagId},
+        types::Symbol,
+    },
+};
+
+/// A column of the CSV output.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CsvColumn {
+    pub name: Symbol,
+
+    /// strftime format of a datetime value, RFC 3339 by default
+    #[serde(default)]
+    pub format: Option<String>,
+
+    /// Number of decimal places of a float value, as many as needed by default
+    #[serde(default)]
+    pub precision: Option<usize>,
+}
+
+/// Configuration for CSV outbound
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CsvOutboundConfig {
+    #[serde(default = "default_csv_tag")]
+    pub tag: OutboundTagId,
+
+    pub inbounds: Vec<TagId>,
+
+    /// Path to the output CSV file
+    pub path: Template<PathBuf>,
+
+    /// Columns of the output, in order
+    pub columns: Vec<CsvColumn>,
+
+    #[serde(default = "default_delimiter")]
+    pub delimiter: char,
+
+    #[serde(default = "default_write_header")]
+    pub write_header: bool,
+
+    /// Written in place of the fields a record does not have
+    #[serde(default)]
+    pub missing_value: String,
+
+    /// Maximum number of records to batch before writing
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+
+    #[serde(flatten)]
+    pub order: StableOrderConfig,
+
+    #[serde(flatten)]
+    pub shedding: LoadSheddingConfig,
+
+    #[serde(default)]
+    pub disabled: bool,
+
+    // How the channels this outbound receives from behave once it falls behind, overrides
+    // their `channel_overflow`
+    #[serde(default)]
+    pub inbound_overflow: Option<OverflowPolicy>,
+}
+
+fn default_csv_tag() -> OutboundTagId {
+    OutboundTagId::new("csv")
+}
+
+fn default_delimiter() -> char {
+    ','
+}
+
+fn default_write_header() -> bool {
+    true
+}
+
+fn default_batch_size() -> usize {
+    1000
+}
+
+impl CsvOutboundConfig {
+    pub fn channel_scale_factor(&self) -> usize {
+        8
+    }
+}
+
+impl Verify for CsvOutboundConfig {
+    fn verify(&mut self) -> super::Result<()> {
+        let tag = TagId::from(&self.tag);
+
+        if self.path.to_string_lossy().is_empty() {
+            return Err(super::Error::EmptyField(tag, "path"));
+        }
+
+        if self.inbounds.is_empty() {
+            return Err(super::Error::EmptyField(tag, "inbounds"));
+        }
+
+        if self.columns.is_empty() {
+            return Err(super::Error::EmptyField(tag, "columns"));
+        }
+
+        if matches!(self.delimiter, '"' | '\n' | '\r') {
+            return Err(super::Error::InvalidConfig(format!(
+                "{}: {:?} can not be used as delimiter",
+                tag, self.delimiter
+            )));
+        }
+
+        let mut names = HashSet::new();
+        for column in &self.columns {
+            if !names.insert(&column.name) {
+                return Err(super::Error::InvalidConfig(format!(
+                    "{}: duplicate column {}",
+                    tag, column.name
+                )));
+            }
+
+            let invalid_format = column
+                .format
+                .as_deref()
+                .is_some_and(|format| StrftimeItems::new(format).any(|i| i == Item::Error));
+            if invalid_format {
+                return Err(super::Error::InvalidConfig(format!(
+                    "{}: invalid datetime format of column {}",
+                    tag, column.name
+                )));
+            }
+        }
+
+        self.shedding.verify_for(&tag)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(columns: &str) -> CsvOutboundConfig {
+        let text = format!(
+            r#"
+            inbounds = ["pipe:timeseries"]
+            path = "/tmp/out.csv"
+            columns = {}
+            "#,
+            columns
+        );
+        toml::from_str(&text).unwrap()
+    }
+
+    #[test]
+    fn test_verify() {
+        let mut cfg =
+            config(r#"[{ name = "timestamp", format = "%Y-%m-%d" }, { name = "value" }]"#);
+        assert!(cfg.verify().is_ok());
+        assert_eq!(cfg.delimiter, ',');
+        assert!(cfg.write_header);
+
+        let mut cfg = config(r#"[{ name = "value" }, { name = "value" }]"#);
+        assert!(cfg.verify().is_err());
+
+        let mut cfg = config(r#"[{ name = "timestamp", format = "%Q" }]"#);
+        assert!(cfg.verify().is_err());
+
+        let mut cfg = config(r#"[{ name = "value" }]"#);
+        cfg.delimiter = '"';
+        assert!(cfg.verify().is_err());
+    }
+}
@@ -0,0 +1,95 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use super::{LoadSheddingConfig, StableOrderConfig};
+use crate::{
+    config::{global::OverflowPolicy, template::Template, types::ByteSize, Verify},
+    core::{
+        tag::{OutboundTagId, TagId},
+        types::Symbol,
+    },
+};
+
+/// Configuration for the JSON lines file outbound
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileOutboundConfig {
+    #[serde(default = "default_file_tag")]
+    pub tag: OutboundTagId,
+
+    pub inbounds: Vec<TagId>,
+
+    /// Path to the output file, appended to if it exists
+    pub path: Template<PathBuf>,
+
+    /// Rotate the file once it would grow beyond this size, never by default
+    #[serde(default)]
+    pub max_file_size: Option<ByteSize>,
+
+    /// Number of rotated files kept besides the current one, as `<path>.1` (the most recent)
+    /// to `<path>.<max_files>`
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+
+    /// Only write the records of this type, e.g. `TimeseriesRecord`
+    #[serde(default)]
+    pub record_type: Option<Symbol>,
+
+    /// Maximum number of records to batch before writing
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+
+    #[serde(flatten)]
+    pub order: StableOrderConfig,
+
+    #[serde(flatten)]
+    pub shedding: LoadSheddingConfig,
+
+    #[serde(default)]
+    pub disabled: bool,
+
+    // How the channels this outbound receives from behave once it falls behind, overrides
+    // their `channel_overflow`
+    #[serde(default)]
+    pub inbound_overflow: Option<OverflowPolicy>,
+}
+
+fn default_file_tag() -> OutboundTagId {
+    OutboundTagId::new("file")
+}
+
+fn default_max_files() -> usize {
+    5
+}
+
+fn default_batch_size() -> usize {
+    1000
+}
+
+impl FileOutboundConfig {
+    pub fn channel_scale_factor(&self) -> usize {
+        8
+    }
+}
+
+impl Verify for FileOutboundConfig {
+    fn verify(&mut self) -> super::Result<()> {
+        let tag = TagId::from(&self.tag);
+
+        if self.path.to_string_lossy().is_empty() {
+            return Err(super::Error::EmptyField(tag, "path"));
+        }
+
+        if self.inbounds.is_empty() {
+            return Err(super::Error::EmptyField(tag, "inbounds"));
+        }
+
+        if let Some(max_file_size) = self.max_file_size {
+            max_file_size.ensure_non_zero(&tag, "max_file_size")?;
+        }
+
+        self.shedding.verify_for(&tag)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{global::OverflowPolicy, types::DurationValue, Verify},
+    core::{
+        tag::{OutboundTagId, TagId},
+        types::Symbol,
+    },
+};
+
+use super::LoadSheddingConfig;
+
+/// How each record is encoded into a message
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KafkaFormat {
+    /// A JSON object, attributes included
+    #[default]
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KafkaCompression {
+    #[default]
+    None,
+    Gzip,
+    Snappy,
+    Lz4,
+}
+
+impl KafkaCompression {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KafkaCompression::None => "none",
+            KafkaCompression::Gzip => "gzip",
+            KafkaCompression::Snappy => "snappy",
+            KafkaCompression::Lz4 => "lz4",
+        }
+    }
+}
+
+/// What happens to a record the brokers did not acknowledge within `message_timeout`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryFailurePolicy {
+    /// Logged and dropped
+    #[default]
+    Drop,
+    /// Sent to the dead letter channel, dropped without one
+    DeadLetter,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KafkaOutboundConfig {
+    #[serde(default = "default_kafka_tag")]
+    pub tag: OutboundTagId,
+
+    pub inbounds: Vec<TagId>,
+
+    /// `host:port` of the bootstrap brokers
+    pub brokers: Vec<String>,
+    pub topic: String,
+
+    #[serde(default)]
+    pub format: KafkaFormat,
+
+    /// Field used as the message key, which picks the partition. The inbound of the record
+    /// by default.
+    #[serde(default)]
+    pub key_field: Option<Symbol>,
+
+    #[serde(default)]
+    pub compression: KafkaCompression,
+
+    /// How long the producer waits for a batch to fill up, `linger.ms`
+    #[serde(default = "default_kafka_linger")]
+    pub linger: DurationValue,
+
+    /// Maximum number of messages per batch, `batch.num.messages`
+    #[serde(default = "default_kafka_batch_size")]
+    pub batch_size: usize,
+
+    /// Maximum number of messages waiting to be delivered. Receiving stops while it is
+    /// reached, `queue.buffering.max.messages`
+    #[serde(default = "default_kafka_queue_size")]
+    pub queue_size: usize,
+
+    /// How long a message may take to be delivered, retries included, `message.timeout.ms`
+    #[serde(default = "default_kafka_message_timeout")]
+    pub message_timeout: DurationValue,
+
+    #[serde(default)]
+    pub on_delivery_failure: DeliveryFailurePolicy,
+
+    /// Extra librdkafka settings, e.g. `"security.protocol" = "ssl"`
+    #[serde(default)]
+    pub properties: BTreeMap<String, String>,
+
+    #[serde(default = "default_kafka_outbound_recv_timeout")]
+    pub recv_timeout: DurationValue,
+
+    #[serde(default = "default_kafka_outbound_recv_buffer_size")]
+    pub recv_buffer_size: usize,
+
+    #[serde(default = "default_kafka_outbound_max_batch_latency")]
+    pub max_batch_latency: DurationValue,
+
+    #[serde(flatten)]
+    pub shedding: LoadSheddingConfig,
+
+    #[serde(default)]
+    pub disabled: bool,
+
+    // How the channels this outbound receives from behave once it falls behind, overrides
+    // their `channel_overflow`
+    #[serde(default)]
+    pub inbound_overflow: Option<OverflowPolicy>,
+}
+
+impl KafkaOutboundConfig {
+    pub fn channel_scale_factor(&self) -> usize {
+        8
+    }
+}
+
+impl Verify for KafkaOutboundConfig {
+    fn verify(&mut self) -> super::Result<()> {
+        let tag = TagId::from(&self.tag);
+
+        if self.brokers.iter().all(|broker| broker.trim().is_empty()) {
+            return Err(super::Error::EmptyField(tag, "brokers"));
+        }
+        if self.topic.is_empty() {
+            return Err(super::Error::EmptyField(tag, "topic"));
+        }
+        if self.inbounds.is_empty() {
+            return Err(super::Error::EmptyField(tag, "inbounds"));
+        }
+
+        if self.batch_size == 0 {
+            return Err(super::Error::ZeroValue(tag.to_string(), "batch_size"));
+        }
+        if self.queue_size == 0 {
+            return Err(super::Error::ZeroValue(tag.to_string(), "queue_size"));
+        }
+        self.message_timeout
+            .ensure_non_zero(&tag, "message_timeout")?;
+        self.recv_timeout.ensure_non_zero(&tag, "recv_timeout")?;
+        self.max_batch_latency
+            .ensure_non_zero(&tag, "max_batch_latency")?;
+
+        self.shedding.verify_for(&tag)?;
+
+        Ok(())
+    }
+}
+
+fn default_kafka_tag() -> OutboundTagId {
+    OutboundTagId::new("kafka")
+}
+
+fn default_kafka_linger() -> DurationValue {
+    DurationValue::from_millis(5)
+}
+
+fn default_kafka_batch_size() -> usize {
+    10000
+}
+
+fn default_kafka_queue_size() -> usize {
+    100000
+}
+
+fn default_kafka_message_timeout() -> DurationValue {
+    DurationValue::from_secs(30)
+}
+
+fn default_kafka_outbound_recv_timeout() -> DurationValue {
+    DurationValue::from_millis(100)
+}
+
+fn default_kafka_outbound_recv_buffer_size() -> usize {
+    8192
+}
+
+fn default_kafka_outbound_max_batch_latency() -> DurationValue {
+    DurationValue::from_millis(5)
+}
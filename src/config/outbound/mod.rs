@@ -1,21 +1,158 @@
 use serde::{Deserialize, Serialize};
 
-use crate::core::tag::{HasTag, TagId};
+use crate::core::{
+    tag::{HasTag, TagId},
+    types::Symbol,
+};
 
-use super::Verify;
+use super::{global::OverflowPolicy, types::DurationValue, Verify};
 
 pub use super::{Error, Result};
 
 pub mod auth;
+pub mod buffer;
+pub mod csv;
+pub mod file;
+pub mod kafka;
+pub mod otlp;
 pub mod parquet;
 pub mod prometheus;
 pub mod stdio;
+pub mod tls;
 
 use self::{
-    parquet::ParquetOutboundConfig, prometheus::PrometheusOutboundConfig,
+    csv::CsvOutboundConfig, file::FileOutboundConfig, kafka::KafkaOutboundConfig,
+    otlp::OtlpOutboundConfig, parquet::ParquetOutboundConfig, prometheus::PrometheusOutboundConfig,
     stdio::StdioOutboundConfig,
 };
 
+/// Deterministic record order for the outbounds writing files or streams.
+///
+/// With `stable_order` enabled, every batch is buffered and sorted by `sort_keys` before it
+/// is written, records being finally ordered by a hash of their remaining fields. This makes
+/// the output independent of the arrival order across connections, at the price of some
+/// latency and of the memory held by the buffered batch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StableOrderConfig {
+    #[serde(default)]
+    pub stable_order: bool,
+
+    #[serde(default = "default_sort_keys")]
+    pub sort_keys: Vec<Symbol>,
+}
+
+impl Default for StableOrderConfig {
+    fn default() -> Self {
+        Self {
+            stable_order: false,
+            sort_keys: default_sort_keys(),
+        }
+    }
+}
+
+fn default_sort_keys() -> Vec<Symbol> {
+    vec![Symbol::new("timestamp"), Symbol::new("name")]
+}
+
+/// Dropping of the records too old to be worth writing, e.g. once the outbound fell behind.
+///
+/// A record is dated by its `age_field`, or without it by when it was received, which is only
+/// known with `global.time_tracing`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordAgeConfig {
+    /// Records older than this are dropped before being transformed, never by default
+    #[serde(default)]
+    pub max_record_age: Option<DurationValue>,
+
+    /// The field the timeseries pipe writes the time of the samples to by default
+    #[serde(default = "default_age_field")]
+    pub age_field: Symbol,
+
+    #[serde(default)]
+    pub undated_records: UndatedRecords,
+}
+
+/// What to do with the records which can't be dated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UndatedRecords {
+    #[default]
+    Keep,
+    Drop,
+}
+
+impl Default for RecordAgeConfig {
+    fn default() -> Self {
+        Self {
+            max_record_age: None,
+            age_field: default_age_field(),
+            undated_records: UndatedRecords::default(),
+        }
+    }
+}
+
+impl RecordAgeConfig {
+    pub fn verify_for(&self, tag: &TagId) -> Result<()> {
+        if let Some(age) = self.max_record_age {
+            age.ensure_non_zero(tag, "max_record_age")?;
+        }
+        Ok(())
+    }
+}
+
+fn default_age_field() -> Symbol {
+    Symbol::new("timestamp")
+}
+
+/// Shedding of the records by priority once an outbound falls behind, before the overflow
+/// policy of its inbound channels drops records whatever their priority.
+///
+/// The fill of a channel is the fraction (0.0 ~ 1.0) of its buffer not yet received, the
+/// fullest of the inbound channels counts. Over `shed_low_above` the `low` records are
+/// dropped, over `shed_normal_above` the `normal` ones too. The `high` ones are never shed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LoadSheddingConfig {
+    /// Never by default
+    #[serde(default)]
+    pub shed_low_above: Option<f64>,
+
+    /// Halfway between `shed_low_above` and a full channel by default
+    #[serde(default)]
+    pub shed_normal_above: Option<f64>,
+}
+
+impl LoadSheddingConfig {
+    pub fn verify_for(&mut self, tag: &TagId) -> Result<()> {
+        let in_range = |fill: f64| fill > 0.0 && fill <= 1.0;
+        let Some(low) = self.shed_low_above else {
+            if self.shed_normal_above.is_some() {
+                return Err(super::Error::InvalidConfig(format!(
+                    "{}: shed_normal_above requires shed_low_above",
+                    tag
+                )));
+            }
+            return Ok(());
+        };
+        if !in_range(low) {
+            return Err(super::Error::InvalidConfig(format!(
+                "{}: shed_low_above must be within (0, 1], got {}",
+                tag, low
+            )));
+        }
+
+        let normal = *self.shed_normal_above.get_or_insert((low + 1.0) / 2.0);
+        if !in_range(normal) || normal < low {
+            return Err(super::Error::InvalidConfig(format!(
+                "{}: shed_normal_above must be within [shed_low_above, 1], got {}",
+                tag, normal
+            )));
+        }
+        Ok(())
+    }
+}
+
+// Configs are only built once at startup, their size does not matter.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "type")]
@@ -24,6 +161,10 @@ pub enum OutboundConfig {
     Stdio(StdioOutboundConfig),
     Prometheus(PrometheusOutboundConfig),
     Parquet(ParquetOutboundConfig),
+    Csv(CsvOutboundConfig),
+    File(FileOutboundConfig),
+    Otlp(OtlpOutboundConfig),
+    Kafka(KafkaOutboundConfig),
 }
 
 impl HasTag for OutboundConfig {
@@ -32,6 +173,10 @@ impl HasTag for OutboundConfig {
             OutboundConfig::Stdio(cfg) => &cfg.tag,
             OutboundConfig::Prometheus(cfg) => &cfg.tag,
             OutboundConfig::Parquet(cfg) => &cfg.tag,
+            OutboundConfig::Csv(cfg) => &cfg.tag,
+            OutboundConfig::File(cfg) => &cfg.tag,
+            OutboundConfig::Otlp(cfg) => &cfg.tag,
+            OutboundConfig::Kafka(cfg) => &cfg.tag,
         }
     }
 }
@@ -42,6 +187,35 @@ impl OutboundConfig {
             OutboundConfig::Stdio(cfg) => cfg.disabled,
             OutboundConfig::Prometheus(cfg) => cfg.disabled,
             OutboundConfig::Parquet(cfg) => cfg.disabled,
+            OutboundConfig::Csv(cfg) => cfg.disabled,
+            OutboundConfig::File(cfg) => cfg.disabled,
+            OutboundConfig::Otlp(cfg) => cfg.disabled,
+            OutboundConfig::Kafka(cfg) => cfg.disabled,
+        }
+    }
+
+    pub fn inbounds(&self) -> Vec<&TagId> {
+        match self {
+            OutboundConfig::Stdio(cfg) => cfg.inbounds.iter().collect(),
+            OutboundConfig::Prometheus(cfg) => cfg.inbounds.iter().collect(),
+            OutboundConfig::Parquet(cfg) => cfg.inbounds.iter().collect(),
+            OutboundConfig::Csv(cfg) => cfg.inbounds.iter().collect(),
+            OutboundConfig::File(cfg) => cfg.inbounds.iter().collect(),
+            OutboundConfig::Otlp(cfg) => cfg.inbounds.iter().collect(),
+            OutboundConfig::Kafka(cfg) => cfg.inbounds.iter().collect(),
+        }
+    }
+
+    /// The overflow policy of the channels this outbound receives from, see `inbound_overflow`.
+    pub fn inbound_overflow(&self) -> Option<OverflowPolicy> {
+        match self {
+            OutboundConfig::Stdio(cfg) => cfg.inbound_overflow,
+            OutboundConfig::Prometheus(cfg) => cfg.inbound_overflow,
+            OutboundConfig::Parquet(cfg) => cfg.inbound_overflow,
+            OutboundConfig::Csv(cfg) => cfg.inbound_overflow,
+            OutboundConfig::File(cfg) => cfg.inbound_overflow,
+            OutboundConfig::Otlp(cfg) => cfg.inbound_overflow,
+            OutboundConfig::Kafka(cfg) => cfg.inbound_overflow,
         }
     }
 
@@ -50,6 +224,10 @@ impl OutboundConfig {
             OutboundConfig::Stdio(cfg) => cfg.channel_scale_factor(),
             OutboundConfig::Prometheus(cfg) => cfg.channel_scale_factor(),
             OutboundConfig::Parquet(cfg) => cfg.channel_scale_factor(),
+            OutboundConfig::Csv(cfg) => cfg.channel_scale_factor(),
+            OutboundConfig::File(cfg) => cfg.channel_scale_factor(),
+            OutboundConfig::Otlp(cfg) => cfg.channel_scale_factor(),
+            OutboundConfig::Kafka(cfg) => cfg.channel_scale_factor(),
         }
     }
 }
@@ -60,6 +238,10 @@ impl Verify for OutboundConfig {
             OutboundConfig::Stdio(cfg) => cfg.verify(),
             OutboundConfig::Prometheus(cfg) => cfg.verify(),
             OutboundConfig::Parquet(cfg) => cfg.verify(),
+            OutboundConfig::Csv(cfg) => cfg.verify(),
+            OutboundConfig::File(cfg) => cfg.verify(),
+            OutboundConfig::Otlp(cfg) => cfg.verify(),
+            OutboundConfig::Kafka(cfg) => cfg.verify(),
         }
     }
 }
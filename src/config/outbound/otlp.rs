@@ -0,0 +1,209 @@
+use std::collections::BTreeMap;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{env::Env, global::OverflowPolicy, retry::RetryConfig, types::DurationValue, Verify},
+    core::tag::{OutboundTagId, TagId},
+};
+
+use super::{auth::AuthConfig, LoadSheddingConfig};
+
+/// How the metrics are exported to the collector.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OtlpProtocol {
+    /// OTLP/HTTP, posted to `<endpoint>/v1/metrics`
+    #[default]
+    #[serde(alias = "http-proto", alias = "http/protobuf")]
+    HttpProto,
+    /// OTLP/gRPC, the `Export` call of the metrics service over HTTP/2
+    Grpc,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OtlpCompression {
+    #[default]
+    Gzip,
+    None,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OtlpOutboundConfig {
+    #[serde(default = "default_otlp_tag")]
+    pub tag: OutboundTagId,
+    /// Base URL of the collector, e.g. `http://localhost:4318` for `http_proto` and
+    /// `http://localhost:4317` for `grpc`
+    pub endpoint: Env<String>,
+
+    #[serde(default)]
+    pub protocol: OtlpProtocol,
+
+    #[serde(default)]
+    pub auth: AuthConfig,
+
+    // Sent with every request, e.g. the API key of a hosted collector
+    #[serde(default)]
+    pub headers: BTreeMap<String, Env<String>>,
+
+    // Of a single request, retries excluded
+    #[serde(default = "default_otlp_timeout")]
+    pub timeout: DurationValue,
+
+    pub inbounds: Vec<TagId>,
+
+    #[serde(flatten)]
+    pub shedding: LoadSheddingConfig,
+
+    #[serde(default)]
+    pub disabled: bool,
+
+    // How the channels this outbound receives from behave once it falls behind, overrides
+    // their `channel_overflow`
+    #[serde(default)]
+    pub inbound_overflow: Option<OverflowPolicy>,
+
+    #[serde(default)]
+    pub compression: OtlpCompression,
+
+    /// Deprecated, `gzip = false` is `compression = "none"`
+    #[serde(default, skip_serializing)]
+    pub gzip: Option<bool>,
+
+    #[serde(default = "default_otlp_outbound_recv_timeout")]
+    pub recv_timeout: DurationValue,
+
+    #[serde(default = "default_otlp_outbound_recv_buffer_size")]
+    pub recv_buffer_size: usize,
+
+    // How long a received record may wait for the batch to fill up
+    #[serde(default = "default_otlp_outbound_max_batch_latency")]
+    pub max_batch_latency: DurationValue,
+
+    /// Backoff of the failed exports, 3 retries starting at 1s by default
+    #[serde(default = "default_otlp_retry")]
+    pub retry: RetryConfig,
+}
+
+impl OtlpOutboundConfig {
+    pub fn channel_scale_factor(&self) -> usize {
+        8
+    }
+}
+
+impl Verify for OtlpOutboundConfig {
+    fn verify(&mut self) -> super::Result<()> {
+        let tag = TagId::from(&self.tag);
+        self.endpoint.interpolate(&tag, "endpoint")?;
+        self.auth.verify_for(&tag)?;
+
+        if self.endpoint.is_empty() {
+            return Err(super::Error::EmptyField((&self.tag).into(), "endpoint"));
+        }
+
+        if self.inbounds.is_empty() {
+            return Err(super::Error::EmptyField((&self.tag).into(), "inbounds"));
+        }
+
+        for (name, value) in &mut self.headers {
+            value.interpolate(&tag, &format!("headers.{}", name))?;
+            let valid = reqwest::header::HeaderName::from_bytes(name.as_bytes()).is_ok()
+                && reqwest::header::HeaderValue::from_str(value).is_ok();
+            if !valid {
+                return Err(super::Error::InvalidConfig(format!(
+                    "{}: invalid header {}",
+                    tag, name
+                )));
+            }
+        }
+
+        if let Some(gzip) = self.gzip.take() {
+            warn!("{}: `gzip` is deprecated, use `compression`", tag);
+            if !gzip {
+                self.compression = OtlpCompression::None;
+            }
+        }
+
+        self.timeout.ensure_non_zero(&tag, "timeout")?;
+        self.recv_timeout
+            .ensure_non_zero(TagId::from(&self.tag), "recv_timeout")?;
+        self.max_batch_latency
+            .ensure_non_zero(TagId::from(&self.tag), "max_batch_latency")?;
+
+        self.retry.verify_for(&tag, "retry")?;
+        self.shedding.verify_for(&tag)?;
+
+        Ok(())
+    }
+}
+
+fn default_otlp_tag() -> OutboundTagId {
+    OutboundTagId::new("otlp")
+}
+
+fn default_otlp_timeout() -> DurationValue {
+    DurationValue::from_secs(5)
+}
+
+fn default_otlp_retry() -> RetryConfig {
+    RetryConfig {
+        max_attempts: 4,
+        initial_delay: DurationValue::from_secs(1),
+        ..Default::default()
+    }
+}
+
+fn default_otlp_outbound_recv_timeout() -> DurationValue {
+    DurationValue::from_millis(5)
+}
+
+fn default_otlp_outbound_max_batch_latency() -> DurationValue {
+    DurationValue::from_millis(5)
+}
+
+fn default_otlp_outbound_recv_buffer_size() -> usize {
+    64 * 8192
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(extra: &str) -> super::super::Result<OtlpOutboundConfig> {
+        let mut cfg: OtlpOutboundConfig = toml::from_str(&format!(
+            "endpoint = \"http://localhost:4317\"\ninbounds = [\"pipe:timeseries\"]\n{}",
+            extra
+        ))
+        .unwrap();
+        cfg.verify().map(|_| cfg)
+    }
+
+    #[test]
+    fn test_verify() {
+        let cfg = config("").unwrap();
+        assert_eq!(cfg.protocol, OtlpProtocol::HttpProto);
+        assert_eq!(cfg.compression, OtlpCompression::Gzip);
+
+        let cfg = config(
+            r#"
+            protocol = "grpc"
+            compression = "none"
+            timeout = "10s"
+            headers = { "x-api-key" = "secret" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(cfg.protocol, OtlpProtocol::Grpc);
+        assert_eq!(cfg.compression, OtlpCompression::None);
+        assert_eq!(cfg.headers["x-api-key"].as_str(), "secret");
+
+        let cfg = config("protocol = \"http-proto\"\ngzip = false").unwrap();
+        assert_eq!(cfg.protocol, OtlpProtocol::HttpProto);
+        assert_eq!(cfg.compression, OtlpCompression::None);
+
+        assert!(config("headers = { \"bad header\" = \"x\" }").is_err());
+        assert!(config("timeout = \"0s\"").is_err());
+    }
+}
@@ -1,16 +1,22 @@
+use super::{LoadSheddingConfig, RecordAgeConfig, StableOrderConfig};
 use crate::{
-    config::{template::Template, Verify},
-    core::tag::{OutboundTagId, TagId},
+    config::{global::OverflowPolicy, template::Template, types::DurationValue, Verify},
+    core::{
+        tag::{OutboundTagId, TagId},
+        types::{Primitive, Symbol},
+    },
 };
+use chrono::format::{Item, StrftimeItems};
 use parquet::basic::{BrotliLevel, GzipLevel};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::{collections::HashSet, path::PathBuf};
 
 /// Parquet Compression options
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Compression {
     /// Snappy compression
+    #[default]
     Snappy,
     /// Gzip compression
     Gzip,
@@ -22,12 +28,6 @@ pub enum Compression {
     None,
 }
 
-impl Default for Compression {
-    fn default() -> Self {
-        Compression::Snappy
-    }
-}
-
 impl From<Compression> for parquet::basic::Compression {
     fn from(compression: Compression) -> Self {
         match compression {
@@ -40,6 +40,27 @@ impl From<Compression> for parquet::basic::Compression {
     }
 }
 
+/// How the schema of the written files is decided
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParquetSchemaMode {
+    /// Inferred from the first record of each file, the fields it lacks are not written
+    #[default]
+    FirstRecord,
+    /// The configured `fields`
+    Explicit,
+    /// The union of the fields seen so far. A new field, or a value which does not fit the
+    /// type of its column, starts a new file with the widened schema
+    Evolve,
+}
+
+/// A column of an explicit schema, e.g. `{ name = "value", type = "float" }`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParquetField {
+    pub name: Symbol,
+    pub r#type: Primitive,
+}
+
 /// Configuration for Parquet outbound
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ParquetOutboundConfig {
@@ -50,19 +71,52 @@ pub struct ParquetOutboundConfig {
     /// Input sources to read records from
     pub inbounds: Vec<TagId>,
 
-    /// Path to the output Parquet file
+    /// Path to the output Parquet file. With a `rotation_interval`, `%Y`, `%m`, `%d` and the
+    /// other strftime fields, and `{ts}` (Unix seconds) are expanded in UTC for each file,
+    /// e.g. `/data/metrics/%Y/%m/%d/part-{ts}.parquet`; `{ts}` is then required. Existing
+    /// files are never overwritten, a `-1`, `-2`... suffix is added instead
     pub path: Template<PathBuf>,
 
+    /// Close the current file and start a new one this often, never by default
+    #[serde(default)]
+    pub rotation_interval: Option<DurationValue>,
+
     /// Maximum number of records to batch before writing
     #[serde(default = "default_batch_size")]
     pub batch_size: usize,
 
+    /// How long a received record may wait for the batch to fill up
+    #[serde(default = "default_max_batch_latency")]
+    pub max_batch_latency: DurationValue,
+
     /// Compression codec to use
     #[serde(default)]
     pub compression: Compression,
 
+    /// How the schema of the files is decided
+    #[serde(default)]
+    pub schema_mode: ParquetSchemaMode,
+
+    /// The columns of the files with `schema_mode = "explicit"`
+    #[serde(default)]
+    pub fields: Vec<ParquetField>,
+
+    #[serde(flatten)]
+    pub order: StableOrderConfig,
+
+    #[serde(flatten)]
+    pub age: RecordAgeConfig,
+
+    #[serde(flatten)]
+    pub shedding: LoadSheddingConfig,
+
     #[serde(default)]
     pub disabled: bool,
+
+    // How the channels this outbound receives from behave once it falls behind, overrides
+    // their `channel_overflow`
+    #[serde(default)]
+    pub inbound_overflow: Option<OverflowPolicy>,
 }
 
 fn default_parquet_tag() -> OutboundTagId {
@@ -73,6 +127,10 @@ fn default_batch_size() -> usize {
     1000
 }
 
+fn default_max_batch_latency() -> DurationValue {
+    DurationValue::from_millis(100)
+}
+
 impl ParquetOutboundConfig {
     /// Returns the scale factor for the channel
     pub fn channel_scale_factor(&self) -> usize {
@@ -90,6 +148,75 @@ impl Verify for ParquetOutboundConfig {
             return Err(super::Error::EmptyField((&self.tag).into(), "inbounds"));
         }
 
+        self.max_batch_latency
+            .ensure_non_zero(TagId::from(&self.tag), "max_batch_latency")?;
+        self.age.verify_for(&(&self.tag).into())?;
+        self.shedding.verify_for(&(&self.tag).into())?;
+        self.verify_fields()?;
+
+        if let Some(interval) = self.rotation_interval {
+            let tag = TagId::from(&self.tag);
+            interval.ensure_non_zero(&tag, "rotation_interval")?;
+
+            let path = self.path.to_string_lossy();
+            if StrftimeItems::new(&path).any(|item| matches!(item, Item::Error)) {
+                return Err(super::Error::InvalidConfig(format!(
+                    "{}: invalid strftime field in path {:?}",
+                    tag, path
+                )));
+            }
+            // Otherwise the files of a day (or of any strftime period) share a path
+            if !path.contains("{ts}") {
+                return Err(super::Error::InvalidConfig(format!(
+                    "{}: path {:?} must contain {{ts}} to be rotated",
+                    tag, path
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl ParquetOutboundConfig {
+    fn verify_fields(&self) -> super::Result<()> {
+        let tag = TagId::from(&self.tag);
+        if self.schema_mode != ParquetSchemaMode::Explicit {
+            if !self.fields.is_empty() {
+                return Err(super::Error::InvalidConfig(format!(
+                    "{}: fields are only used with schema_mode = \"explicit\"",
+                    tag
+                )));
+            }
+            return Ok(());
+        }
+
+        if self.fields.is_empty() {
+            return Err(super::Error::EmptyField(tag, "fields"));
+        }
+
+        let mut names = HashSet::new();
+        for field in &self.fields {
+            if field.name.is_empty() {
+                return Err(super::Error::InvalidConfig(format!(
+                    "{}: field names cannot be empty",
+                    tag
+                )));
+            }
+            if field.r#type == Primitive::Null {
+                return Err(super::Error::InvalidConfig(format!(
+                    "{}: field {} cannot be of type null",
+                    tag, field.name
+                )));
+            }
+            if !names.insert(&field.name) {
+                return Err(super::Error::InvalidConfig(format!(
+                    "{}: duplicate field {}",
+                    tag, field.name
+                )));
+            }
+        }
+
         Ok(())
     }
 }
@@ -1,11 +1,16 @@
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    config::{env::Env, Verify},
-    core::tag::{OutboundTagId, TagId},
+    config::{env::Env, global::OverflowPolicy, retry::RetryConfig, types::DurationValue, Verify},
+    core::{
+        tag::{OutboundTagId, TagId},
+        types::conv::prometheus::Compression,
+    },
 };
 
-use super::auth::AuthConfig;
+use super::{
+    auth::AuthConfig, buffer::DiskBufferConfig, tls::TlsConfig, LoadSheddingConfig, RecordAgeConfig,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PrometheusOutboundConfig {
@@ -16,17 +21,85 @@ pub struct PrometheusOutboundConfig {
     #[serde(default)]
     pub auth: AuthConfig,
 
+    #[serde(default)]
+    pub tls: TlsConfig,
+
+    // Of a single request, retries excluded
+    #[serde(default = "default_prometheus_timeout")]
+    pub timeout: DurationValue,
+
+    // Of establishing a connection, bounded by `timeout` only by default
+    #[serde(default)]
+    pub connect_timeout: Option<DurationValue>,
+
+    // Carries the level of `zstd` once verified
+    #[serde(default)]
+    pub compression: Compression,
+
+    // Level of `zstd`, from 1 to 22, 3 by default
+    #[serde(default)]
+    pub compression_level: Option<i32>,
+
     pub inbounds: Vec<TagId>,
 
+    #[serde(flatten)]
+    pub shedding: LoadSheddingConfig,
+
     #[serde(default)]
     pub disabled: bool,
 
+    // How the channels this outbound receives from behave once it falls behind, overrides
+    // their `channel_overflow`
+    #[serde(default)]
+    pub inbound_overflow: Option<OverflowPolicy>,
+
     #[serde(default = "default_prometheus_outbound_recv_timeout")]
-    #[serde(deserialize_with = "crate::utils::parse_duration")]
-    pub recv_timeout: std::time::Duration,
+    pub recv_timeout: DurationValue,
 
     #[serde(default = "default_prometheus_outbound_recv_buffer_size")]
     pub recv_buffer_size: usize,
+
+    // How long a received record may wait for the batch to fill up
+    #[serde(default = "default_prometheus_outbound_max_batch_latency")]
+    pub max_batch_latency: DurationValue,
+
+    /// Backoff of the failed remote writes, 3 retries starting at 1s by default. A longer
+    /// `Retry-After` sent by the server is honored.
+    #[serde(default = "default_prometheus_retry")]
+    pub retry: RetryConfig,
+
+    /// Number of samples of the failed remote writes kept to be sent with the next one,
+    /// 0 to drop them
+    #[serde(default = "default_prometheus_retry_queue_size")]
+    pub retry_queue_size: usize,
+
+    /// Spool the samples given up to disk instead of the retry queue, and replay them in
+    /// order once the remote write succeeds again
+    #[serde(default)]
+    pub buffer: Option<DiskBufferConfig>,
+
+    /// Cap on the compressed size of a request, a larger batch is split into several
+    #[serde(default)]
+    pub max_request_bytes: Option<usize>,
+
+    /// Cap on the samples of a request, a larger batch is split into several
+    #[serde(default)]
+    pub max_samples_per_request: Option<usize>,
+
+    /// Cap on the requests sent per second, retries included
+    #[serde(default)]
+    pub max_requests_per_second: Option<f64>,
+
+    /// Cap on the requests sent at once, the next batch waits for one of them to complete
+    #[serde(default = "default_prometheus_max_in_flight")]
+    pub max_in_flight: usize,
+
+    /// On shutdown, how long to wait for the requests in flight, within `global.drain_timeout`
+    #[serde(default = "default_prometheus_shutdown_timeout")]
+    pub shutdown_timeout: DurationValue,
+
+    #[serde(flatten)]
+    pub age: RecordAgeConfig,
 }
 
 impl PrometheusOutboundConfig {
@@ -37,6 +110,11 @@ impl PrometheusOutboundConfig {
 
 impl Verify for PrometheusOutboundConfig {
     fn verify(&mut self) -> super::Result<()> {
+        let tag = TagId::from(&self.tag);
+        self.address.interpolate(&tag, "address")?;
+        self.auth.verify_for(&tag)?;
+        self.tls.verify_for(&tag)?;
+
         if self.address.is_empty() {
             return Err(super::Error::EmptyField((&self.tag).into(), "address"));
         }
@@ -45,6 +123,71 @@ impl Verify for PrometheusOutboundConfig {
             return Err(super::Error::EmptyField((&self.tag).into(), "inbounds"));
         }
 
+        self.timeout.ensure_non_zero(&tag, "timeout")?;
+        if let Some(connect_timeout) = self.connect_timeout {
+            connect_timeout.ensure_non_zero(&tag, "connect_timeout")?;
+        }
+
+        match (self.compression, self.compression_level) {
+            (_, None) => {}
+            (Compression::Zstd(_), Some(level)) if (1..=22).contains(&level) => {
+                self.compression = Compression::Zstd(level);
+            }
+            (Compression::Zstd(_), Some(level)) => {
+                return Err(super::Error::InvalidConfig(format!(
+                    "{}: compression_level must be between 1 and 22, got {}",
+                    tag, level
+                )));
+            }
+            (compression, Some(_)) => {
+                return Err(super::Error::InvalidConfig(format!(
+                    "{}: compression_level does not apply to {}",
+                    tag,
+                    compression.content_encoding()
+                )));
+            }
+        }
+
+        self.recv_timeout
+            .ensure_non_zero(TagId::from(&self.tag), "recv_timeout")?;
+        self.max_batch_latency
+            .ensure_non_zero(TagId::from(&self.tag), "max_batch_latency")?;
+
+        self.age.verify_for(&tag)?;
+        self.retry.verify_for(&tag, "retry")?;
+        if let Some(ref buffer) = self.buffer {
+            buffer.verify_for(&tag)?;
+        }
+
+        if self.max_request_bytes == Some(0) {
+            return Err(super::Error::ZeroValue(
+                tag.to_string(),
+                "max_request_bytes",
+            ));
+        }
+
+        if self.max_samples_per_request == Some(0) {
+            return Err(super::Error::ZeroValue(
+                tag.to_string(),
+                "max_samples_per_request",
+            ));
+        }
+
+        if self.max_in_flight == 0 {
+            return Err(super::Error::ZeroValue(tag.to_string(), "max_in_flight"));
+        }
+
+        if let Some(rate) = self.max_requests_per_second {
+            if !rate.is_finite() || rate <= 0.0 {
+                return Err(super::Error::InvalidConfig(format!(
+                    "{}: max_requests_per_second must be positive, got {}",
+                    tag, rate
+                )));
+            }
+        }
+
+        self.shedding.verify_for(&tag)?;
+
         Ok(())
     }
 }
@@ -53,13 +196,120 @@ fn default_prometheus_tag() -> OutboundTagId {
     OutboundTagId::new("prometheus")
 }
 
-fn default_prometheus_outbound_recv_timeout() -> std::time::Duration {
-    std::time::Duration::from_millis(5)
+fn default_prometheus_timeout() -> DurationValue {
+    DurationValue::from_secs(5)
+}
+
+fn default_prometheus_retry() -> RetryConfig {
+    RetryConfig {
+        max_attempts: 4,
+        initial_delay: DurationValue::from_secs(1),
+        ..Default::default()
+    }
+}
+
+fn default_prometheus_retry_queue_size() -> usize {
+    100_000
+}
+
+fn default_prometheus_max_in_flight() -> usize {
+    16
+}
+
+fn default_prometheus_shutdown_timeout() -> DurationValue {
+    DurationValue::from_secs(10)
+}
+
+fn default_prometheus_outbound_recv_timeout() -> DurationValue {
+    DurationValue::from_millis(5)
 }
 
 // We use a large buffer size and a long window interval to avoid
 // out of order time series data.
 // Which is sometimes acceptable, or you should enable serial mode in config
+fn default_prometheus_outbound_max_batch_latency() -> DurationValue {
+    DurationValue::from_millis(5)
+}
+
 fn default_prometheus_outbound_recv_buffer_size() -> usize {
     64 * 8192
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(body: &str) -> super::super::Result<PrometheusOutboundConfig> {
+        let mut cfg: PrometheusOutboundConfig = toml::from_str(&format!(
+            "address = \"http://localhost:9090\"\ninbounds = [\"pipe:timeseries\"]\n{}",
+            body
+        ))
+        .map_err(|e| super::super::Error::InvalidConfig(e.to_string()))?;
+        cfg.verify().map(|_| cfg)
+    }
+
+    #[test]
+    fn test_client_options() {
+        let cfg = config("").unwrap();
+        assert_eq!(cfg.compression, Compression::Snappy);
+        assert_eq!(
+            std::time::Duration::from(cfg.timeout),
+            std::time::Duration::from_secs(5)
+        );
+        assert!(cfg.connect_timeout.is_none());
+
+        let cfg = config(
+            "timeout = \"30s\"\nconnect_timeout = \"3s\"\ncompression = \"zstd\"\ncompression_level = 9",
+        )
+        .unwrap();
+        assert_eq!(cfg.compression, Compression::Zstd(9));
+
+        let cfg = config("compression = \"zstd\"").unwrap();
+        assert_eq!(cfg.compression, Compression::Zstd(3));
+
+        assert!(config("timeout = \"0s\"").is_err());
+        assert!(config("connect_timeout = \"0s\"").is_err());
+        assert!(config("compression = \"gzip\"").is_err());
+        assert!(config("compression_level = 3").is_err());
+        assert!(config("compression = \"zstd\"\ncompression_level = 23").is_err());
+        assert!(config("compression = \"gzip\"").is_err());
+    }
+
+    #[test]
+    fn test_tls() {
+        let dir = tempfile::tempdir().unwrap();
+        let empty = dir.path().join("empty.pem");
+        std::fs::write(&empty, "").unwrap();
+
+        let cfg = config("tls = { insecure_skip_verify = true }").unwrap();
+        assert!(cfg.tls.insecure_skip_verify);
+
+        let err = config(&format!("tls = {{ ca_file = \"{}\" }}", empty.display()))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("tls.ca_file"), "{}", err);
+        assert!(config(&format!(
+            "tls = {{ ca_file = \"{}/missing.pem\" }}",
+            dir.path().display()
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn test_retry() {
+        assert!(config("retry = { multiplier = 1.0 }").is_ok());
+        assert!(config("retry = { initial_delay = \"5s\", max_delay = \"5s\" }").is_ok());
+
+        let err = config("retry = { multiplier = 0.5 }")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("retry.multiplier"), "{}", err);
+        assert!(config("retry = { multiplier = nan }").is_err());
+        assert!(config("retry = { multiplier = inf }").is_err());
+
+        let err = config("retry = { initial_delay = \"10s\", max_delay = \"1s\" }")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("retry.initial_delay"), "{}", err);
+    }
+}
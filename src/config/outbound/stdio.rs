@@ -1,15 +1,38 @@
+use std::path::PathBuf;
+
+use log::warn;
 use serde::{Deserialize, Serialize};
 
+use super::{LoadSheddingConfig, StableOrderConfig};
 use crate::{
-    config::Verify,
-    core::tag::{OutboundTagId, TagId},
+    config::{env::interpolate_path, global::OverflowPolicy, types::DurationValue, Verify},
+    core::{
+        tag::{OutboundTagId, TagId},
+        types::Symbol,
+    },
 };
 
+/// Where the records are written, `"stdout"`, `"stderr"` or `{ file = "path" }`
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
-pub enum Io {
+pub enum Target {
     Stdout,
     Stderr,
+    /// Appended to, and opened again once moved or removed, e.g. by logrotate
+    File(PathBuf),
+}
+
+/// How each record is written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// One JSON object per line, attributes included
+    Json,
+    /// `key=value` pairs on a single line
+    Logfmt,
+    /// Aligned multi-line dump, attributes included
+    #[default]
+    Pretty,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -17,11 +40,41 @@ pub struct StdioOutboundConfig {
     #[serde(default = "default_stdio_tag")]
     pub tag: OutboundTagId,
     pub r#inbounds: Vec<TagId>,
-    #[serde(default = "default_io")]
-    pub io: Io,
+    #[serde(default = "default_target", alias = "io")]
+    pub target: Target,
+
+    #[serde(default)]
+    pub format: OutputFormat,
+
+    // Only write these fields, in this order. Attributes are left out.
+    #[serde(default)]
+    pub fields: Option<Vec<Symbol>>,
+
+    // Color the keys, pretty format only
+    #[serde(default)]
+    pub color: bool,
+
+    // Maximum number of records written at once
+    #[serde(default = "default_stdio_batch_size")]
+    pub batch_size: usize,
+
+    // How long a received record may wait for the batch to fill up
+    #[serde(default = "default_stdio_max_batch_latency")]
+    pub max_batch_latency: DurationValue,
+
+    #[serde(flatten)]
+    pub order: StableOrderConfig,
+
+    #[serde(flatten)]
+    pub shedding: LoadSheddingConfig,
 
     #[serde(default)]
     pub disabled: bool,
+
+    // How the channels this outbound receives from behave once it falls behind, overrides
+    // their `channel_overflow`
+    #[serde(default)]
+    pub inbound_overflow: Option<OverflowPolicy>,
 }
 
 impl Verify for StdioOutboundConfig {
@@ -30,6 +83,27 @@ impl Verify for StdioOutboundConfig {
             return Err(super::Error::EmptyField((&self.tag).into(), "inbounds"));
         }
 
+        let tag = TagId::from(&self.tag);
+        self.max_batch_latency
+            .ensure_non_zero(&tag, "max_batch_latency")?;
+
+        if let Target::File(ref mut path) = self.target {
+            interpolate_path(path, &tag, "target.file")?;
+            if path.as_os_str().is_empty() {
+                return Err(super::Error::EmptyField(tag, "target.file"));
+            }
+        }
+
+        if self.fields.as_ref().is_some_and(|fields| fields.is_empty()) {
+            return Err(super::Error::EmptyField(tag, "fields"));
+        }
+
+        if self.color && self.format != OutputFormat::Pretty {
+            warn!("{}: color only applies to the pretty format", tag);
+        }
+
+        self.shedding.verify_for(&tag)?;
+
         Ok(())
     }
 }
@@ -44,6 +118,14 @@ fn default_stdio_tag() -> OutboundTagId {
     OutboundTagId::new("stdio")
 }
 
-fn default_io() -> Io {
-    Io::Stdout
+fn default_stdio_batch_size() -> usize {
+    16
+}
+
+fn default_stdio_max_batch_latency() -> DurationValue {
+    DurationValue::from_millis(10)
+}
+
+fn default_target() -> Target {
+    Target::Stdout
 }
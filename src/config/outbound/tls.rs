@@ -0,0 +1,57 @@
+use std::{fmt::Display, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::env::interpolate_path;
+
+/// TLS options of the HTTP client of an outbound, the system roots are trusted by default.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// PEM bundle of the certificate authorities trusted besides the system roots, e.g. that
+    /// of a private endpoint
+    #[serde(default)]
+    pub ca_file: Option<PathBuf>,
+
+    /// Accept any certificate, for test environments only
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
+}
+
+impl TlsConfig {
+    /// Interpolate the environment variables in the path of the bundle and make sure it
+    /// holds certificates.
+    pub fn verify_for(&mut self, owner: impl Display) -> super::Result<()> {
+        if let Some(ref mut ca_file) = self.ca_file {
+            interpolate_path(ca_file, &owner, "tls.ca_file")?;
+            self.ca_certificates().map_err(|e| {
+                super::Error::InvalidConfig(format!("{}.tls.ca_file: {}", owner, e))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// The certificates of `ca_file`, none without one.
+    pub fn ca_certificates(&self) -> Result<Vec<reqwest::Certificate>, String> {
+        let Some(ref ca_file) = self.ca_file else {
+            return Ok(Vec::new());
+        };
+        let pem = std::fs::read(ca_file).map_err(|e| format!("{}: {}", ca_file.display(), e))?;
+        let certificates = reqwest::Certificate::from_pem_bundle(&pem)
+            .map_err(|e| format!("{}: {}", ca_file.display(), e))?;
+        if certificates.is_empty() {
+            return Err(format!("{}: no certificate found", ca_file.display()));
+        }
+        Ok(certificates)
+    }
+
+    /// Apply the options to a client.
+    pub fn apply(
+        &self,
+        mut builder: reqwest::ClientBuilder,
+    ) -> Result<reqwest::ClientBuilder, String> {
+        for certificate in self.ca_certificates()? {
+            builder = builder.add_root_certificate(certificate);
+        }
+        Ok(builder.danger_accept_invalid_certs(self.insecure_skip_verify))
+    }
+}
@@ -0,0 +1,196 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{global::OverflowPolicy, types::DurationValue, Verify},
+    core::{
+        tag::{PipeTagId, TagId},
+        types::Symbol,
+    },
+};
+
+/// How the values of a group are combined over a window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aggregation {
+    Sum,
+    Avg,
+    Min,
+    Max,
+    Count,
+    /// Nearest-rank percentile, `p95` and the like
+    Percentile(u8),
+}
+
+impl Aggregation {
+    fn parse(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "sum" => Ok(Aggregation::Sum),
+            "avg" => Ok(Aggregation::Avg),
+            "min" => Ok(Aggregation::Min),
+            "max" => Ok(Aggregation::Max),
+            "count" => Ok(Aggregation::Count),
+            _ => match s.strip_prefix('p').map(str::parse::<u8>) {
+                Some(Ok(p)) if (1..=99).contains(&p) => Ok(Aggregation::Percentile(p)),
+                Some(_) => Err(format!("{:?}: percentiles must be within p1 to p99", s)),
+                None => Err(format!("unknown aggregation {:?}", s)),
+            },
+        }
+    }
+}
+
+impl Display for Aggregation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Aggregation::Sum => write!(f, "sum"),
+            Aggregation::Avg => write!(f, "avg"),
+            Aggregation::Min => write!(f, "min"),
+            Aggregation::Max => write!(f, "max"),
+            Aggregation::Count => write!(f, "count"),
+            Aggregation::Percentile(p) => write!(f, "p{}", p),
+        }
+    }
+}
+
+impl Serialize for Aggregation {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Aggregation {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let str = String::deserialize(deserializer)?;
+        Aggregation::parse(&str).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Aggregates timeseries records over tumbling windows, one record per series, window and
+/// aggregation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AggregatePipeConfig {
+    #[serde(default = "default_aggregate_tag")]
+    pub tag: PipeTagId,
+    pub inbounds: Vec<TagId>,
+
+    #[serde(default)]
+    pub disabled: bool,
+
+    // Overrides `global.channel_overflow` for the channel of this pipe
+    #[serde(default)]
+    pub channel_overflow: Option<OverflowPolicy>,
+
+    // How the channels this pipe receives from behave once it falls behind, overrides
+    // their `channel_overflow`
+    #[serde(default)]
+    pub inbound_overflow: Option<OverflowPolicy>,
+
+    // The labels kept, the others are aggregated away. All of them when not set.
+    #[serde(default)]
+    pub group_by: Option<Vec<Symbol>>,
+
+    // Windows are aligned to multiples of it, by arrival time
+    pub window: DurationValue,
+
+    #[serde(default = "default_aggregations")]
+    pub aggregations: Vec<Aggregation>,
+
+    #[serde(default = "default_aggregate_recv_timeout")]
+    pub recv_timeout: DurationValue,
+
+    #[serde(default = "default_aggregate_recv_buffer_size")]
+    pub recv_buffer_size: usize,
+
+    #[serde(default = "default_aggregate_max_batch_latency")]
+    pub max_batch_latency: DurationValue,
+}
+
+impl Verify for AggregatePipeConfig {
+    fn verify(&mut self) -> super::Result<()> {
+        let tag = TagId::from(&self.tag);
+        if self.inbounds.is_empty() {
+            return Err(super::Error::EmptyField(tag, "inbounds"));
+        }
+
+        if self.aggregations.is_empty() {
+            return Err(super::Error::EmptyField(tag, "aggregations"));
+        }
+
+        self.window.ensure_non_zero(&tag, "window")?;
+        self.recv_timeout.ensure_non_zero(&tag, "recv_timeout")?;
+        self.max_batch_latency
+            .ensure_non_zero(&tag, "max_batch_latency")?;
+
+        Ok(())
+    }
+}
+
+impl AggregatePipeConfig {
+    pub fn channel_scale_factor(&self) -> usize {
+        8
+    }
+}
+
+fn default_aggregate_tag() -> PipeTagId {
+    PipeTagId::new("aggregate")
+}
+
+fn default_aggregations() -> Vec<Aggregation> {
+    vec![Aggregation::Avg]
+}
+
+fn default_aggregate_recv_timeout() -> DurationValue {
+    DurationValue::from_millis(100)
+}
+
+fn default_aggregate_recv_buffer_size() -> usize {
+    8192
+}
+
+fn default_aggregate_max_batch_latency() -> DurationValue {
+    DurationValue::from_millis(5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(body: &str) -> super::super::Result<AggregatePipeConfig> {
+        let mut cfg: AggregatePipeConfig =
+            toml::from_str(&format!("inbounds = [\"pipe:timeseries\"]\n{}", body))
+                .map_err(|e| super::super::Error::InvalidConfig(e.to_string()))?;
+        cfg.verify().map(|_| cfg)
+    }
+
+    #[test]
+    fn test_verify() {
+        let cfg = config(
+            "window = \"10s\"\ngroup_by = [\"route\"]\naggregations = [\"sum\", \"count\", \"p95\"]",
+        )
+        .unwrap();
+        assert_eq!(
+            cfg.aggregations,
+            vec![
+                Aggregation::Sum,
+                Aggregation::Count,
+                Aggregation::Percentile(95)
+            ]
+        );
+        assert_eq!(
+            config("window = \"10s\"").unwrap().aggregations,
+            vec![Aggregation::Avg]
+        );
+        assert!(config("").is_err());
+
+        assert!(config("window = \"10s\"\naggregations = []").is_err());
+        assert!(config("window = \"10s\"\naggregations = [\"median\"]").is_err());
+        assert!(config("window = \"10s\"\naggregations = [\"p100\"]").is_err());
+        assert!(config("window = \"0s\"").is_err());
+    }
+}
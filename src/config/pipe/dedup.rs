@@ -0,0 +1,173 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{
+        global::OverflowPolicy,
+        keying::{KeySpec, MissingField},
+        types::DurationValue,
+        Verify,
+    },
+    core::tag::{PipeTagId, TagId},
+};
+
+/// Drops timeseries samples whose value did not change since the previous one of the
+/// series, e.g. gauges scraped far more often than they move. With a `key`, drops the records
+/// whose key was already seen within `window` instead, e.g. the samples an upstream re-sends
+/// when it reconnects.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DedupPipeConfig {
+    #[serde(default = "default_dedup_tag")]
+    pub tag: PipeTagId,
+    pub inbounds: Vec<TagId>,
+
+    #[serde(default)]
+    pub disabled: bool,
+
+    // Overrides `global.channel_overflow` for the channel of this pipe
+    #[serde(default)]
+    pub channel_overflow: Option<OverflowPolicy>,
+
+    // How the channels this pipe receives from behave once it falls behind, overrides
+    // their `channel_overflow`
+    #[serde(default)]
+    pub inbound_overflow: Option<OverflowPolicy>,
+
+    // An unchanged sample is still emitted once this long passed since the last emitted one,
+    // so the series never looks stale downstream
+    #[serde(default = "default_max_suppress_duration")]
+    pub max_suppress_duration: DurationValue,
+
+    // Number of series remembered, the least recently seen ones are forgotten beyond it
+    #[serde(default = "default_dedup_max_series")]
+    pub max_series: usize,
+
+    // What makes two records duplicates, e.g. the name, the labels and the timestamp. The
+    // records lacking one of its fields are passed through unless `missing` is set
+    #[serde(default)]
+    pub key: Option<KeySpec>,
+
+    // How long a key is remembered once first seen, with `key`
+    #[serde(default = "default_dedup_window")]
+    pub window: DurationValue,
+
+    // Number of keys remembered with `key`, the oldest ones are forgotten beyond it
+    #[serde(default = "default_dedup_max_keys")]
+    pub max_keys: usize,
+
+    #[serde(default = "default_dedup_recv_timeout")]
+    pub recv_timeout: DurationValue,
+
+    #[serde(default = "default_dedup_recv_buffer_size")]
+    pub recv_buffer_size: usize,
+
+    #[serde(default = "default_dedup_max_batch_latency")]
+    pub max_batch_latency: DurationValue,
+}
+
+impl Verify for DedupPipeConfig {
+    fn verify(&mut self) -> super::Result<()> {
+        let tag = TagId::from(&self.tag);
+        if self.inbounds.is_empty() {
+            return Err(super::Error::EmptyField(tag, "inbounds"));
+        }
+
+        if self.max_series == 0 {
+            return Err(super::Error::ZeroValue(tag.to_string(), "max_series"));
+        }
+
+        if let Some(ref mut key) = self.key {
+            key.verify_for(&tag)?;
+            key.missing.get_or_insert(MissingField::Skip);
+        }
+        if self.max_keys == 0 {
+            return Err(super::Error::ZeroValue(tag.to_string(), "max_keys"));
+        }
+        self.window.ensure_non_zero(&tag, "window")?;
+
+        self.max_suppress_duration
+            .ensure_non_zero(&tag, "max_suppress_duration")?;
+        self.recv_timeout.ensure_non_zero(&tag, "recv_timeout")?;
+        self.max_batch_latency
+            .ensure_non_zero(&tag, "max_batch_latency")?;
+
+        Ok(())
+    }
+}
+
+impl DedupPipeConfig {
+    pub fn channel_scale_factor(&self) -> usize {
+        8
+    }
+}
+
+fn default_dedup_tag() -> PipeTagId {
+    PipeTagId::new("dedup")
+}
+
+fn default_max_suppress_duration() -> DurationValue {
+    DurationValue::from_secs(300)
+}
+
+fn default_dedup_max_series() -> usize {
+    100000
+}
+
+fn default_dedup_window() -> DurationValue {
+    DurationValue::from_secs(60)
+}
+
+fn default_dedup_max_keys() -> usize {
+    1_000_000
+}
+
+fn default_dedup_recv_timeout() -> DurationValue {
+    DurationValue::from_millis(100)
+}
+
+fn default_dedup_recv_buffer_size() -> usize {
+    8192
+}
+
+fn default_dedup_max_batch_latency() -> DurationValue {
+    DurationValue::from_millis(5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(body: &str) -> super::super::Result<DedupPipeConfig> {
+        let mut cfg: DedupPipeConfig =
+            toml::from_str(&format!("inbounds = [\"pipe:timeseries\"]\n{}", body))
+                .map_err(|e| super::super::Error::InvalidConfig(e.to_string()))?;
+        cfg.verify().map(|_| cfg)
+    }
+
+    #[test]
+    fn test_verify() {
+        let cfg = config("").unwrap();
+        assert_eq!(cfg.max_suppress_duration.get().as_secs(), 300);
+        assert_eq!(cfg.max_series, 100000);
+
+        let cfg = config("max_suppress_duration = \"1m\"\nmax_series = 10").unwrap();
+        assert_eq!(cfg.max_suppress_duration.get().as_secs(), 60);
+        assert_eq!(cfg.max_series, 10);
+
+        assert!(config("max_suppress_duration = \"0s\"").is_err());
+        assert!(config("max_series = 0").is_err());
+
+        let cfg =
+            config("key = { fields = [\"labels\", \"timestamp\"], include_name = true }").unwrap();
+        assert_eq!(cfg.key.unwrap().missing, Some(MissingField::Skip));
+        assert_eq!(cfg.window.get().as_secs(), 60);
+        let cfg = config("key = { fields = [\"timestamp\"], missing = \"empty\" }").unwrap();
+        assert_eq!(cfg.key.unwrap().missing, Some(MissingField::Empty));
+        assert!(config("key = { fields = [] }").is_err());
+        assert!(config("key = { fields = [\"timestamp\"] }\nwindow = \"0s\"").is_err());
+        assert!(config("max_keys = 0").is_err());
+        assert!(toml::from_str::<DedupPipeConfig>("")
+            .map_err(|e| super::super::Error::InvalidConfig(e.to_string()))
+            .and_then(|mut cfg| cfg.verify())
+            .is_err());
+    }
+}
@@ -0,0 +1,220 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{
+        global::OverflowPolicy, keying::FieldPath, pipe::Priority, types::DurationValue, Verify,
+    },
+    core::tag::{PipeTagId, TagId},
+};
+
+/// What happens to the records matching the conditions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterMode {
+    /// Forward the matching records, discard the others
+    #[default]
+    Keep,
+    /// Discard the matching records, forward the others
+    Drop,
+}
+
+/// How the conditions combine into a match
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterCombine {
+    /// Every condition holds
+    #[default]
+    All,
+    /// At least one condition holds
+    Any,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterOp {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    /// A substring of a string, an element of an array or a key of a map
+    Contains,
+    Regex,
+    Exists,
+    NotExists,
+}
+
+impl FilterOp {
+    fn needs_value(&self) -> bool {
+        !matches!(self, FilterOp::Exists | FilterOp::NotExists)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ConditionValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+/// A predicate on a single field, e.g. `{ field = "name", op = "regex", value = "^system\\." }`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilterCondition {
+    pub field: FieldPath,
+    pub op: FilterOp,
+
+    // Not used by `exists` and `not_exists`
+    #[serde(default)]
+    pub value: Option<ConditionValue>,
+}
+
+impl FilterCondition {
+    pub(super) fn verify_for(&self, tag: &TagId) -> super::Result<()> {
+        let invalid = |reason: &str| {
+            super::Error::InvalidConfig(format!(
+                "{}: invalid condition on {}: {}",
+                tag, self.field, reason
+            ))
+        };
+
+        match (&self.value, self.op) {
+            (None, op) if op.needs_value() => Err(invalid("missing value")),
+            (Some(_), op) if !op.needs_value() => Err(invalid("unexpected value")),
+            (Some(ConditionValue::String(pattern)), FilterOp::Regex) => regex::Regex::new(pattern)
+                .map(|_| ())
+                .map_err(|e| invalid(&e.to_string())),
+            (Some(_), FilterOp::Regex) => Err(invalid("regex must be a string")),
+            (
+                Some(ConditionValue::Bool(_)),
+                FilterOp::Gt | FilterOp::Ge | FilterOp::Lt | FilterOp::Le,
+            ) => Err(invalid("booleans are not ordered")),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Forwards or discards records depending on whether they match all the conditions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilterPipeConfig {
+    #[serde(default = "default_filter_tag")]
+    pub tag: PipeTagId,
+    pub inbounds: Vec<TagId>,
+
+    #[serde(default)]
+    pub disabled: bool,
+
+    // Overrides `global.channel_overflow` for the channel of this pipe
+    #[serde(default)]
+    pub channel_overflow: Option<OverflowPolicy>,
+
+    // How the channels this pipe receives from behave once it falls behind, overrides
+    // their `channel_overflow`
+    #[serde(default)]
+    pub inbound_overflow: Option<OverflowPolicy>,
+
+    // Set on the records forwarded, for the outbounds shedding load
+    #[serde(default)]
+    pub priority: Option<Priority>,
+
+    #[serde(default)]
+    pub mode: FilterMode,
+
+    // A record matches when all of them hold, or any of them with `combine = "any"`
+    pub conditions: Vec<FilterCondition>,
+
+    #[serde(default)]
+    pub combine: FilterCombine,
+
+    #[serde(default = "default_filter_recv_timeout")]
+    pub recv_timeout: DurationValue,
+
+    #[serde(default = "default_filter_recv_buffer_size")]
+    pub recv_buffer_size: usize,
+
+    #[serde(default = "default_filter_max_batch_latency")]
+    pub max_batch_latency: DurationValue,
+}
+
+impl Verify for FilterPipeConfig {
+    fn verify(&mut self) -> super::Result<()> {
+        let tag = TagId::from(&self.tag);
+        if self.inbounds.is_empty() {
+            return Err(super::Error::EmptyField(tag, "inbounds"));
+        }
+
+        if self.conditions.is_empty() {
+            return Err(super::Error::EmptyField(tag, "conditions"));
+        }
+
+        for condition in &self.conditions {
+            condition.verify_for(&tag)?;
+        }
+
+        self.recv_timeout.ensure_non_zero(&tag, "recv_timeout")?;
+        self.max_batch_latency
+            .ensure_non_zero(&tag, "max_batch_latency")?;
+
+        Ok(())
+    }
+}
+
+impl FilterPipeConfig {
+    pub fn channel_scale_factor(&self) -> usize {
+        8
+    }
+}
+
+fn default_filter_tag() -> PipeTagId {
+    PipeTagId::new("filter")
+}
+
+fn default_filter_recv_timeout() -> DurationValue {
+    DurationValue::from_millis(5)
+}
+
+fn default_filter_recv_buffer_size() -> usize {
+    8192
+}
+
+fn default_filter_max_batch_latency() -> DurationValue {
+    DurationValue::from_millis(5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(conditions: &str) -> super::super::Result<FilterPipeConfig> {
+        let mut cfg: FilterPipeConfig = toml::from_str(&format!(
+            "inbounds = [\"inbound:a\"]\nconditions = [{}]",
+            conditions
+        ))
+        .unwrap();
+        cfg.verify().map(|_| cfg)
+    }
+
+    #[test]
+    fn test_verify_conditions() {
+        let cfg = config(
+            r#"{ field = "name", op = "regex", value = "^system\\." },
+               { field = "labels.zone", op = "exists" },
+               { field = "value", op = "gt", value = 100 }"#,
+        )
+        .unwrap();
+        assert_eq!(cfg.mode, FilterMode::Keep);
+        assert_eq!(cfg.combine, FilterCombine::All);
+        assert_eq!(cfg.conditions[2].value, Some(ConditionValue::Int(100)));
+
+        assert!(config("").is_err());
+        assert!(config(r#"{ field = "value", op = "gt" }"#).is_err());
+        assert!(config(r#"{ field = "value", op = "exists", value = 1 }"#).is_err());
+        assert!(config(r#"{ field = "name", op = "regex", value = "(" }"#).is_err());
+        assert!(config(r#"{ field = "name", op = "regex", value = 1 }"#).is_err());
+        assert!(config(r#"{ field = "up", op = "lt", value = true }"#).is_err());
+        assert!(config(r#"{ field = "status", op = "contains", value = "DEBUG" }"#).is_ok());
+        assert!(config(r#"{ field = "status", op = "contains" }"#).is_err());
+    }
+}
@@ -0,0 +1,62 @@
+use std::fmt::Display;
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Verify;
+
+/// Label keys which must never leave the box, whatever the producers send.
+///
+/// Set in `global`, a pipe may override it with its own `label_policy`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LabelPolicyConfig {
+    // Exact keys or glob patterns, e.g. `user_email`, `*_token`
+    #[serde(default)]
+    pub deny_keys: Vec<String>,
+
+    // If set, the keys matching none of these are stripped as well
+    #[serde(default)]
+    pub allow_keys: Option<Vec<String>>,
+}
+
+impl LabelPolicyConfig {
+    pub fn deny_set(&self) -> super::Result<GlobSet> {
+        build_glob_set(&self.deny_keys)
+    }
+
+    pub fn allow_set(&self) -> super::Result<Option<GlobSet>> {
+        self.allow_keys.as_deref().map(build_glob_set).transpose()
+    }
+}
+
+fn build_glob_set(patterns: &[String]) -> super::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern).map_err(|e| {
+            super::Error::InvalidConfig(format!("Invalid label key pattern {}: {}", pattern, e))
+        })?;
+        builder.add(glob);
+    }
+
+    builder
+        .build()
+        .map_err(|e| super::Error::InvalidConfig(format!("Invalid label key patterns: {}", e)))
+}
+
+impl Verify for LabelPolicyConfig {
+    fn verify(&mut self) -> super::Result<()> {
+        self.deny_set()?;
+        self.allow_set()?;
+        Ok(())
+    }
+}
+
+impl Display for LabelPolicyConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "deny_keys: {:?}", self.deny_keys)?;
+        if let Some(ref allow_keys) = self.allow_keys {
+            write!(f, ", allow_keys: {:?}", allow_keys)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{global::OverflowPolicy, keying::KeySpec, types::DurationValue, Verify},
+    core::{
+        tag::{PipeTagId, TagId},
+        types::Symbol,
+    },
+};
+
+/// Merges several inbounds into a single stream ordered by event time, e.g. the two
+/// producers of an HA pair.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MergePipeConfig {
+    #[serde(default = "default_merge_tag")]
+    pub tag: PipeTagId,
+    pub inbounds: Vec<TagId>,
+
+    #[serde(default)]
+    pub disabled: bool,
+
+    // Overrides `global.channel_overflow` for the channel of this pipe
+    #[serde(default)]
+    pub channel_overflow: Option<OverflowPolicy>,
+
+    // How the channels this pipe receives from behave once it falls behind, overrides
+    // their `channel_overflow`
+    #[serde(default)]
+    pub inbound_overflow: Option<OverflowPolicy>,
+
+    // Field holding the event time of a record
+    #[serde(default = "default_time_field")]
+    pub time_field: Symbol,
+
+    // How much out of order the records of a single source may arrive
+    #[serde(default = "default_reorder_window")]
+    pub reorder_window: DurationValue,
+
+    // A source which has not sent anything for this long stops holding the merge back
+    #[serde(default = "default_source_idle_timeout")]
+    pub source_idle_timeout: DurationValue,
+
+    // Drop the records already seen from another source: same series, same timestamp,
+    // and a value within `dedupe_epsilon`. The first one is kept.
+    #[serde(default)]
+    pub dedupe: bool,
+
+    #[serde(default = "default_value_field")]
+    pub value_field: Symbol,
+
+    // What makes up the series of a record for dedupe, every field but the time and the value
+    // by default
+    #[serde(default)]
+    pub dedupe_key: Option<KeySpec>,
+
+    #[serde(default = "default_dedupe_epsilon")]
+    pub dedupe_epsilon: f64,
+
+    #[serde(default = "default_merge_recv_timeout")]
+    pub recv_timeout: DurationValue,
+}
+
+impl Verify for MergePipeConfig {
+    fn verify(&mut self) -> super::Result<()> {
+        if self.inbounds.is_empty() {
+            return Err(super::Error::EmptyField((&self.tag).into(), "inbounds"));
+        }
+
+        if self.dedupe_epsilon < 0.0 {
+            return Err(super::Error::InvalidConfig(format!(
+                "{}: dedupe_epsilon must not be negative",
+                TagId::from(&self.tag)
+            )));
+        }
+
+        let tag = TagId::from(&self.tag);
+        if let Some(ref key) = self.dedupe_key {
+            key.verify_for(&tag)?;
+        }
+
+        self.recv_timeout.ensure_non_zero(&tag, "recv_timeout")?;
+        self.source_idle_timeout
+            .ensure_non_zero(&tag, "source_idle_timeout")?;
+
+        Ok(())
+    }
+}
+
+impl MergePipeConfig {
+    pub fn channel_scale_factor(&self) -> usize {
+        8
+    }
+}
+
+fn default_merge_tag() -> PipeTagId {
+    PipeTagId::new("merge")
+}
+
+fn default_time_field() -> Symbol {
+    Symbol::new("timestamp")
+}
+
+fn default_value_field() -> Symbol {
+    Symbol::new("value")
+}
+
+fn default_reorder_window() -> DurationValue {
+    DurationValue::from_secs(1)
+}
+
+fn default_source_idle_timeout() -> DurationValue {
+    DurationValue::from_secs(5)
+}
+
+fn default_dedupe_epsilon() -> f64 {
+    1e-9
+}
+
+fn default_merge_recv_timeout() -> DurationValue {
+    DurationValue::from_millis(50)
+}
@@ -2,9 +2,24 @@ use serde::{Deserialize, Serialize};
 
 use crate::core::tag::{HasTag, TagId};
 
-use super::Verify;
+use super::{
+    global::OverflowPolicy,
+    types::{ByteSize, DurationValue},
+    Verify,
+};
 
+pub mod aggregate;
+pub mod dedup;
+pub mod filter;
+pub mod label_policy;
+pub mod merge;
+pub mod rate;
+pub mod route;
+pub mod sample;
+pub mod throttle;
 pub mod timeseries;
+pub mod transform;
+pub mod validate;
 pub use super::{Error, Result};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -15,6 +30,16 @@ pub enum PipeConfig {
     Timeseries(timeseries::TimeseriesPipeConfig),
     #[serde(rename = "timeseries_annotate")]
     TimeseriesAnnotate(timeseries::TimeseriesAnnotatePipeConfig),
+    Merge(merge::MergePipeConfig),
+    Filter(filter::FilterPipeConfig),
+    Aggregate(aggregate::AggregatePipeConfig),
+    Validate(validate::ValidatePipeConfig),
+    Dedup(dedup::DedupPipeConfig),
+    Route(route::RoutePipeConfig),
+    Rate(rate::RatePipeConfig),
+    Transform(transform::TransformPipeConfig),
+    Throttle(throttle::ThrottlePipeConfig),
+    Sample(sample::SamplePipeConfig),
 }
 
 impl Verify for PipeConfig {
@@ -22,6 +47,16 @@ impl Verify for PipeConfig {
         match self {
             PipeConfig::Timeseries(config) => config.verify(),
             PipeConfig::TimeseriesAnnotate(config) => config.verify(),
+            PipeConfig::Merge(config) => config.verify(),
+            PipeConfig::Filter(config) => config.verify(),
+            PipeConfig::Aggregate(config) => config.verify(),
+            PipeConfig::Validate(config) => config.verify(),
+            PipeConfig::Dedup(config) => config.verify(),
+            PipeConfig::Route(config) => config.verify(),
+            PipeConfig::Rate(config) => config.verify(),
+            PipeConfig::Transform(config) => config.verify(),
+            PipeConfig::Throttle(config) => config.verify(),
+            PipeConfig::Sample(config) => config.verify(),
         }
     }
 }
@@ -31,6 +66,16 @@ impl HasTag for PipeConfig {
         match self {
             PipeConfig::Timeseries(cfg) => &cfg.tag,
             PipeConfig::TimeseriesAnnotate(cfg) => &cfg.tag,
+            PipeConfig::Merge(cfg) => &cfg.tag,
+            PipeConfig::Filter(cfg) => &cfg.tag,
+            PipeConfig::Aggregate(cfg) => &cfg.tag,
+            PipeConfig::Validate(cfg) => &cfg.tag,
+            PipeConfig::Dedup(cfg) => &cfg.tag,
+            PipeConfig::Route(cfg) => &cfg.tag,
+            PipeConfig::Rate(cfg) => &cfg.tag,
+            PipeConfig::Transform(cfg) => &cfg.tag,
+            PipeConfig::Throttle(cfg) => &cfg.tag,
+            PipeConfig::Sample(cfg) => &cfg.tag,
         }
     }
 }
@@ -40,6 +85,37 @@ impl PipeConfig {
         match self {
             PipeConfig::Timeseries(cfg) => cfg.disabled,
             PipeConfig::TimeseriesAnnotate(cfg) => cfg.disabled,
+            PipeConfig::Merge(cfg) => cfg.disabled,
+            PipeConfig::Filter(cfg) => cfg.disabled,
+            PipeConfig::Aggregate(cfg) => cfg.disabled,
+            PipeConfig::Validate(cfg) => cfg.disabled,
+            PipeConfig::Dedup(cfg) => cfg.disabled,
+            PipeConfig::Route(cfg) => cfg.disabled,
+            PipeConfig::Rate(cfg) => cfg.disabled,
+            PipeConfig::Transform(cfg) => cfg.disabled,
+            PipeConfig::Throttle(cfg) => cfg.disabled,
+            PipeConfig::Sample(cfg) => cfg.disabled,
+        }
+    }
+
+    pub fn inbounds(&self) -> Vec<&TagId> {
+        match self {
+            PipeConfig::Timeseries(cfg) => cfg.inbounds.iter().collect(),
+            PipeConfig::TimeseriesAnnotate(cfg) => cfg
+                .data_inbounds
+                .iter()
+                .chain(&cfg.control_inbounds)
+                .collect(),
+            PipeConfig::Merge(cfg) => cfg.inbounds.iter().collect(),
+            PipeConfig::Filter(cfg) => cfg.inbounds.iter().collect(),
+            PipeConfig::Aggregate(cfg) => cfg.inbounds.iter().collect(),
+            PipeConfig::Validate(cfg) => cfg.inbounds.iter().collect(),
+            PipeConfig::Dedup(cfg) => cfg.inbounds.iter().collect(),
+            PipeConfig::Route(cfg) => cfg.inbounds.iter().collect(),
+            PipeConfig::Rate(cfg) => cfg.inbounds.iter().collect(),
+            PipeConfig::Transform(cfg) => cfg.inbounds.iter().collect(),
+            PipeConfig::Throttle(cfg) => cfg.inbounds.iter().collect(),
+            PipeConfig::Sample(cfg) => cfg.inbounds.iter().collect(),
         }
     }
 
@@ -47,6 +123,148 @@ impl PipeConfig {
         match self {
             PipeConfig::Timeseries(cfg) => cfg.channel_scale_factor(),
             PipeConfig::TimeseriesAnnotate(cfg) => cfg.channel_scale_factor(),
+            PipeConfig::Merge(cfg) => cfg.channel_scale_factor(),
+            PipeConfig::Filter(cfg) => cfg.channel_scale_factor(),
+            PipeConfig::Aggregate(cfg) => cfg.channel_scale_factor(),
+            PipeConfig::Validate(cfg) => cfg.channel_scale_factor(),
+            PipeConfig::Dedup(cfg) => cfg.channel_scale_factor(),
+            PipeConfig::Route(cfg) => cfg.channel_scale_factor(),
+            PipeConfig::Rate(cfg) => cfg.channel_scale_factor(),
+            PipeConfig::Transform(cfg) => cfg.channel_scale_factor(),
+            PipeConfig::Throttle(cfg) => cfg.channel_scale_factor(),
+            PipeConfig::Sample(cfg) => cfg.channel_scale_factor(),
         }
     }
+
+    pub fn channel_overflow(&self) -> Option<OverflowPolicy> {
+        match self {
+            PipeConfig::Timeseries(cfg) => cfg.channel_overflow,
+            PipeConfig::TimeseriesAnnotate(cfg) => cfg.channel_overflow,
+            PipeConfig::Merge(cfg) => cfg.channel_overflow,
+            PipeConfig::Filter(cfg) => cfg.channel_overflow,
+            PipeConfig::Aggregate(cfg) => cfg.channel_overflow,
+            PipeConfig::Validate(cfg) => cfg.channel_overflow,
+            PipeConfig::Dedup(cfg) => cfg.channel_overflow,
+            PipeConfig::Route(cfg) => cfg.channel_overflow,
+            PipeConfig::Rate(cfg) => cfg.channel_overflow,
+            PipeConfig::Transform(cfg) => cfg.channel_overflow,
+            PipeConfig::Throttle(cfg) => cfg.channel_overflow,
+            PipeConfig::Sample(cfg) => cfg.channel_overflow,
+        }
+    }
+
+    /// The overflow policy of the channels this pipe receives from, see `inbound_overflow`.
+    pub fn inbound_overflow(&self) -> Option<OverflowPolicy> {
+        match self {
+            PipeConfig::Timeseries(cfg) => cfg.inbound_overflow,
+            PipeConfig::TimeseriesAnnotate(cfg) => cfg.inbound_overflow,
+            PipeConfig::Merge(cfg) => cfg.inbound_overflow,
+            PipeConfig::Filter(cfg) => cfg.inbound_overflow,
+            PipeConfig::Aggregate(cfg) => cfg.inbound_overflow,
+            PipeConfig::Validate(cfg) => cfg.inbound_overflow,
+            PipeConfig::Dedup(cfg) => cfg.inbound_overflow,
+            PipeConfig::Route(cfg) => cfg.inbound_overflow,
+            PipeConfig::Rate(cfg) => cfg.inbound_overflow,
+            PipeConfig::Transform(cfg) => cfg.inbound_overflow,
+            PipeConfig::Throttle(cfg) => cfg.inbound_overflow,
+            PipeConfig::Sample(cfg) => cfg.inbound_overflow,
+        }
+    }
+
+    /// The channels of the pipe besides its own, which is then never sent to.
+    pub fn routes(&self) -> Vec<TagId> {
+        match self {
+            PipeConfig::Route(cfg) => cfg.route_tags(),
+            _ => vec![],
+        }
+    }
+}
+
+/// Record size observation shared by all pipes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordSizeConfig {
+    // Warn when a record is estimated to be larger than this many bytes.
+    #[serde(default)]
+    pub warn_record_bytes: Option<ByteSize>,
+
+    // Truncate the maps and arrays of records larger than this many bytes.
+    #[serde(default)]
+    pub enforce_record_bytes: Option<ByteSize>,
+
+    // Maximum number of entries kept in a truncated map or array.
+    #[serde(default = "default_max_record_entries")]
+    pub max_record_entries: usize,
+
+    // Oversized-record warnings are logged at most once per interval.
+    #[serde(default = "default_record_size_warn_interval")]
+    pub record_size_warn_interval: DurationValue,
+}
+
+impl Default for RecordSizeConfig {
+    fn default() -> Self {
+        Self {
+            warn_record_bytes: None,
+            enforce_record_bytes: None,
+            max_record_entries: default_max_record_entries(),
+            record_size_warn_interval: default_record_size_warn_interval(),
+        }
+    }
+}
+
+impl RecordSizeConfig {
+    pub fn verify_for(&self, tag: &TagId) -> super::Result<()> {
+        if let Some(limit) = self.warn_record_bytes {
+            limit.ensure_non_zero(tag, "warn_record_bytes")?;
+        }
+        if let Some(limit) = self.enforce_record_bytes {
+            limit.ensure_non_zero(tag, "enforce_record_bytes")?;
+        }
+        Ok(())
+    }
+}
+
+fn default_max_record_entries() -> usize {
+    1024
+}
+
+fn default_record_size_warn_interval() -> DurationValue {
+    DurationValue::from_secs(10)
+}
+
+/// How much a record matters when the outbounds fall behind, see `LoadSheddingConfig`. The
+/// records without one are `normal`.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl Priority {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "low" => Some(Priority::Low),
+            "normal" => Some(Priority::Normal),
+            "high" => Some(Priority::High),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Priority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
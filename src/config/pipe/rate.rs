@@ -0,0 +1,151 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{global::OverflowPolicy, types::DurationValue, Verify},
+    core::tag::{PipeTagId, TagId},
+};
+
+/// What the rate pipe emits for each sample of a counter.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateMode {
+    /// The increase since the previous sample
+    Delta,
+    /// The increase divided by the seconds elapsed since the previous sample, the unit
+    /// becomes per second, e.g. `bytes/s`
+    #[default]
+    Rate,
+}
+
+/// What to do with a sample lower than the previous one of its counter, i.e. a restart.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CounterReset {
+    /// The counter restarted from zero, its value is the increase
+    #[default]
+    FromZero,
+    /// Emit nothing, the sample is the start of the next increase
+    Drop,
+}
+
+/// Turns monotonic counters, e.g. `bytes_total`, into per-interval increases or rates.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RatePipeConfig {
+    #[serde(default = "default_rate_tag")]
+    pub tag: PipeTagId,
+    pub inbounds: Vec<TagId>,
+
+    #[serde(default)]
+    pub disabled: bool,
+
+    // Overrides `global.channel_overflow` for the channel of this pipe
+    #[serde(default)]
+    pub channel_overflow: Option<OverflowPolicy>,
+
+    // How the channels this pipe receives from behave once it falls behind, overrides
+    // their `channel_overflow`
+    #[serde(default)]
+    pub inbound_overflow: Option<OverflowPolicy>,
+
+    #[serde(default)]
+    pub mode: RateMode,
+
+    #[serde(default)]
+    pub on_reset: CounterReset,
+
+    // A series not seen for this long is forgotten, its next sample is a first one again
+    #[serde(default = "default_rate_series_ttl")]
+    pub series_ttl: DurationValue,
+
+    // Number of series remembered, the least recently seen ones are forgotten beyond it
+    #[serde(default = "default_rate_max_series")]
+    pub max_series: usize,
+
+    #[serde(default = "default_rate_recv_timeout")]
+    pub recv_timeout: DurationValue,
+
+    #[serde(default = "default_rate_recv_buffer_size")]
+    pub recv_buffer_size: usize,
+
+    #[serde(default = "default_rate_max_batch_latency")]
+    pub max_batch_latency: DurationValue,
+}
+
+impl Verify for RatePipeConfig {
+    fn verify(&mut self) -> super::Result<()> {
+        let tag = TagId::from(&self.tag);
+        if self.inbounds.is_empty() {
+            return Err(super::Error::EmptyField(tag, "inbounds"));
+        }
+
+        if self.max_series == 0 {
+            return Err(super::Error::ZeroValue(tag.to_string(), "max_series"));
+        }
+
+        self.series_ttl.ensure_non_zero(&tag, "series_ttl")?;
+        self.recv_timeout.ensure_non_zero(&tag, "recv_timeout")?;
+        self.max_batch_latency
+            .ensure_non_zero(&tag, "max_batch_latency")?;
+
+        Ok(())
+    }
+}
+
+impl RatePipeConfig {
+    pub fn channel_scale_factor(&self) -> usize {
+        8
+    }
+}
+
+fn default_rate_tag() -> PipeTagId {
+    PipeTagId::new("rate")
+}
+
+fn default_rate_series_ttl() -> DurationValue {
+    DurationValue::from_secs(600)
+}
+
+fn default_rate_max_series() -> usize {
+    100000
+}
+
+fn default_rate_recv_timeout() -> DurationValue {
+    DurationValue::from_millis(100)
+}
+
+fn default_rate_recv_buffer_size() -> usize {
+    8192
+}
+
+fn default_rate_max_batch_latency() -> DurationValue {
+    DurationValue::from_millis(5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(body: &str) -> super::super::Result<RatePipeConfig> {
+        let mut cfg: RatePipeConfig =
+            toml::from_str(&format!("inbounds = [\"pipe:timeseries\"]\n{}", body))
+                .map_err(|e| super::super::Error::InvalidConfig(e.to_string()))?;
+        cfg.verify().map(|_| cfg)
+    }
+
+    #[test]
+    fn test_verify() {
+        let cfg = config("").unwrap();
+        assert_eq!(cfg.mode, RateMode::Rate);
+        assert_eq!(cfg.on_reset, CounterReset::FromZero);
+        assert_eq!(cfg.series_ttl.get().as_secs(), 600);
+
+        let cfg = config("mode = \"delta\"\non_reset = \"drop\"\nseries_ttl = \"1m\"").unwrap();
+        assert_eq!(cfg.mode, RateMode::Delta);
+        assert_eq!(cfg.on_reset, CounterReset::Drop);
+        assert_eq!(cfg.series_ttl.get().as_secs(), 60);
+
+        assert!(config("mode = \"increase\"").is_err());
+        assert!(config("series_ttl = \"0s\"").is_err());
+        assert!(config("max_series = 0").is_err());
+    }
+}
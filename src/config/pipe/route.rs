@@ -0,0 +1,189 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{global::OverflowPolicy, pipe::filter::FilterCondition, types::DurationValue, Verify},
+    core::tag::{PipeTagId, TagId},
+};
+
+/// A named output of the route pipe, received from as `"<pipe tag>:<name>"`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteConfig {
+    pub name: String,
+
+    // A record goes to the route when all of them hold, always without any
+    #[serde(default)]
+    pub conditions: Vec<FilterCondition>,
+}
+
+/// Which of the routes a record matching several of them goes to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteMatching {
+    /// Every one, the record is copied
+    #[default]
+    All,
+    /// The first one in the order of `routes`, so that each record goes to exactly one route
+    First,
+}
+
+/// Splits one stream into several: each record goes to every route it matches, or to the
+/// first one with `matching = "first"`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoutePipeConfig {
+    #[serde(default = "default_route_tag")]
+    pub tag: PipeTagId,
+    pub inbounds: Vec<TagId>,
+
+    #[serde(default)]
+    pub disabled: bool,
+
+    // Overrides `global.channel_overflow` for the channels of this pipe
+    #[serde(default)]
+    pub channel_overflow: Option<OverflowPolicy>,
+
+    // How the channels this pipe receives from behave once it falls behind, overrides
+    // their `channel_overflow`
+    #[serde(default)]
+    pub inbound_overflow: Option<OverflowPolicy>,
+
+    pub routes: Vec<RouteConfig>,
+
+    #[serde(default)]
+    pub matching: RouteMatching,
+
+    // Route of the records matching none of the others, they are dropped without it
+    #[serde(default)]
+    pub default: Option<String>,
+
+    #[serde(default = "default_route_recv_timeout")]
+    pub recv_timeout: DurationValue,
+
+    #[serde(default = "default_route_recv_buffer_size")]
+    pub recv_buffer_size: usize,
+
+    #[serde(default = "default_route_max_batch_latency")]
+    pub max_batch_latency: DurationValue,
+}
+
+impl Verify for RoutePipeConfig {
+    fn verify(&mut self) -> super::Result<()> {
+        let tag = TagId::from(&self.tag);
+        if self.inbounds.is_empty() {
+            return Err(super::Error::EmptyField(tag, "inbounds"));
+        }
+        if self.routes.is_empty() {
+            return Err(super::Error::EmptyField(tag, "routes"));
+        }
+
+        let mut names = HashSet::new();
+        for name in self.route_names() {
+            if name.is_empty() || name.contains(':') {
+                return Err(super::Error::InvalidConfig(format!(
+                    "{}: invalid route name {:?}",
+                    tag, name
+                )));
+            }
+            if !names.insert(name) {
+                return Err(super::Error::InvalidConfig(format!(
+                    "{}: duplicate route {}",
+                    tag, name
+                )));
+            }
+        }
+
+        for route in &self.routes {
+            for condition in &route.conditions {
+                condition.verify_for(&tag)?;
+            }
+        }
+
+        self.recv_timeout.ensure_non_zero(&tag, "recv_timeout")?;
+        self.max_batch_latency
+            .ensure_non_zero(&tag, "max_batch_latency")?;
+
+        Ok(())
+    }
+}
+
+impl RoutePipeConfig {
+    pub fn channel_scale_factor(&self) -> usize {
+        8
+    }
+
+    fn route_names(&self) -> impl Iterator<Item = &str> {
+        self.routes
+            .iter()
+            .map(|route| route.name.as_str())
+            .chain(self.default.as_deref())
+    }
+
+    /// The channels the pipe sends to, one per route and the default one.
+    pub fn route_tags(&self) -> Vec<TagId> {
+        self.route_names()
+            .map(|name| self.tag.route(name))
+            .collect()
+    }
+}
+
+fn default_route_tag() -> PipeTagId {
+    PipeTagId::new("route")
+}
+
+fn default_route_recv_timeout() -> DurationValue {
+    DurationValue::from_millis(5)
+}
+
+fn default_route_recv_buffer_size() -> usize {
+    8192
+}
+
+fn default_route_max_batch_latency() -> DurationValue {
+    DurationValue::from_millis(5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(routes: &str, default: &str) -> super::super::Result<RoutePipeConfig> {
+        let mut cfg: RoutePipeConfig = toml::from_str(&format!(
+            "tag = \"split\"\ninbounds = [\"inbound:a\"]\n{}\nroutes = [{}]",
+            default, routes
+        ))
+        .unwrap();
+        cfg.verify().map(|_| cfg)
+    }
+
+    #[test]
+    fn test_verify_routes() {
+        let cfg = config(
+            r#"{ name = "infra", conditions = [{ field = "name", op = "regex", value = "^node_" }] },
+               { name = "all" }"#,
+            "default = \"other\"",
+        )
+        .unwrap();
+        assert_eq!(cfg.matching, RouteMatching::All);
+        let tags = cfg
+            .route_tags()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tags,
+            ["pipe:split:infra", "pipe:split:all", "pipe:split:other"]
+        );
+
+        assert!(config("", "").is_err());
+        assert!(config(r#"{ name = "" }"#, "").is_err());
+        assert!(config(r#"{ name = "a:b" }"#, "").is_err());
+        assert!(config(r#"{ name = "a" }, { name = "a" }"#, "").is_err());
+        assert!(config(r#"{ name = "a" }"#, "default = \"a\"").is_err());
+        assert!(config(
+            r#"{ name = "a", conditions = [{ field = "value", op = "gt" }] }"#,
+            ""
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,197 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{
+        global::OverflowPolicy,
+        keying::{KeySpec, MissingField},
+        types::DurationValue,
+        Verify,
+    },
+    core::tag::{PipeTagId, TagId},
+};
+
+/// Which records the sample pipe keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SampleMode {
+    /// The records whose key hashes into the first `ratio` of the hash space: a series is
+    /// either always kept or always dropped, across restarts too
+    Ratio,
+    /// The first `limit` records of each `interval`
+    Head,
+    /// The first `limit` records of each key in each `interval`
+    PerKey,
+}
+
+impl Display for SampleMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SampleMode::Ratio => write!(f, "ratio"),
+            SampleMode::Head => write!(f, "head"),
+            SampleMode::PerKey => write!(f, "per_key"),
+        }
+    }
+}
+
+/// Keeps a sample of the records, e.g. of a debug stream too chatty to be stored in full.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SamplePipeConfig {
+    #[serde(default = "default_sample_tag")]
+    pub tag: PipeTagId,
+    pub inbounds: Vec<TagId>,
+
+    #[serde(default)]
+    pub disabled: bool,
+
+    // Overrides `global.channel_overflow` for the channel of this pipe
+    #[serde(default)]
+    pub channel_overflow: Option<OverflowPolicy>,
+
+    // How the channels this pipe receives from behave once it falls behind, overrides
+    // their `channel_overflow`
+    #[serde(default)]
+    pub inbound_overflow: Option<OverflowPolicy>,
+
+    pub mode: SampleMode,
+
+    // Fraction of the keys kept, in (0, 1], with `mode = "ratio"`
+    #[serde(default)]
+    pub ratio: Option<f64>,
+
+    // Records kept per interval, or per key and interval, with `head` and `per_key`
+    #[serde(default)]
+    pub limit: Option<usize>,
+
+    #[serde(default = "default_sample_interval")]
+    pub interval: DurationValue,
+
+    // What a series is, with `ratio` and `per_key`. The records lacking one of its fields
+    // are kept unless `missing` is set
+    #[serde(default)]
+    pub key: Option<KeySpec>,
+
+    // Number of keys counted at once with `per_key`, the records of the other keys are
+    // dropped until the next interval
+    #[serde(default = "default_sample_max_keys")]
+    pub max_keys: usize,
+
+    #[serde(default = "default_sample_recv_timeout")]
+    pub recv_timeout: DurationValue,
+
+    #[serde(default = "default_sample_recv_buffer_size")]
+    pub recv_buffer_size: usize,
+
+    #[serde(default = "default_sample_max_batch_latency")]
+    pub max_batch_latency: DurationValue,
+}
+
+impl Verify for SamplePipeConfig {
+    fn verify(&mut self) -> super::Result<()> {
+        let tag = TagId::from(&self.tag);
+        if self.inbounds.is_empty() {
+            return Err(super::Error::EmptyField(tag, "inbounds"));
+        }
+
+        match self.mode {
+            SampleMode::Ratio => match self.ratio {
+                Some(ratio) if ratio > 0.0 && ratio <= 1.0 => {}
+                Some(ratio) => {
+                    return Err(super::Error::InvalidConfig(format!(
+                        "{}: ratio must be in (0, 1], got {}",
+                        tag, ratio
+                    )))
+                }
+                None => return Err(super::Error::EmptyField(tag, "ratio")),
+            },
+            SampleMode::Head | SampleMode::PerKey => match self.limit {
+                Some(0) => return Err(super::Error::ZeroValue(tag.to_string(), "limit")),
+                Some(_) => {}
+                None => return Err(super::Error::EmptyField(tag, "limit")),
+            },
+        }
+
+        match (self.mode, self.key.as_mut()) {
+            (SampleMode::Head, _) => {}
+            (_, Some(key)) => {
+                key.verify_for(&tag)?;
+                key.missing.get_or_insert(MissingField::Skip);
+            }
+            (_, None) => return Err(super::Error::EmptyField(tag, "key")),
+        }
+
+        if self.max_keys == 0 {
+            return Err(super::Error::ZeroValue(tag.to_string(), "max_keys"));
+        }
+        if self.recv_buffer_size == 0 {
+            return Err(super::Error::ZeroValue(tag.to_string(), "recv_buffer_size"));
+        }
+
+        self.interval.ensure_non_zero(&tag, "interval")?;
+        self.recv_timeout.ensure_non_zero(&tag, "recv_timeout")?;
+        self.max_batch_latency
+            .ensure_non_zero(&tag, "max_batch_latency")?;
+
+        Ok(())
+    }
+}
+
+impl SamplePipeConfig {
+    pub fn channel_scale_factor(&self) -> usize {
+        8
+    }
+}
+
+fn default_sample_tag() -> PipeTagId {
+    PipeTagId::new("sample")
+}
+
+fn default_sample_interval() -> DurationValue {
+    DurationValue::from_secs(60)
+}
+
+fn default_sample_max_keys() -> usize {
+    100000
+}
+
+fn default_sample_recv_timeout() -> DurationValue {
+    DurationValue::from_millis(100)
+}
+
+fn default_sample_recv_buffer_size() -> usize {
+    8192
+}
+
+fn default_sample_max_batch_latency() -> DurationValue {
+    DurationValue::from_millis(5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(body: &str) -> super::super::Result<SamplePipeConfig> {
+        let mut cfg: SamplePipeConfig =
+            toml::from_str(&format!("inbounds = [\"pipe:timeseries\"]\n{}", body))
+                .map_err(|e| super::super::Error::InvalidConfig(e.to_string()))?;
+        cfg.verify().map(|_| cfg)
+    }
+
+    #[test]
+    fn test_verify() {
+        let cfg = config("mode = \"ratio\"\nratio = 0.1\nkey = { include_name = true }").unwrap();
+        assert_eq!(cfg.key.unwrap().missing, Some(MissingField::Skip));
+        assert!(config("mode = \"head\"\nlimit = 100").is_ok());
+        assert!(config("mode = \"per_key\"\nlimit = 10\nkey = { fields = [\"host\"] }").is_ok());
+
+        assert!(config("mode = \"ratio\"\nkey = { include_name = true }").is_err());
+        assert!(config("mode = \"ratio\"\nratio = 0\nkey = { include_name = true }").is_err());
+        assert!(config("mode = \"ratio\"\nratio = 1.5\nkey = { include_name = true }").is_err());
+        assert!(config("mode = \"ratio\"\nratio = 0.5").is_err());
+        assert!(config("mode = \"head\"").is_err());
+        assert!(config("mode = \"head\"\nlimit = 0").is_err());
+        assert!(config("mode = \"per_key\"\nlimit = 10").is_err());
+        assert!(config("mode = \"random\"").is_err());
+    }
+}
@@ -0,0 +1,151 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{global::OverflowPolicy, types::DurationValue, Verify},
+    core::tag::{PipeTagId, TagId},
+};
+
+/// What the throttle pipe does with the records over the rate.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThrottleOverflow {
+    /// Drop them
+    Drop,
+    /// Hold them until there are tokens again, receiving nothing meanwhile, so that the
+    /// channels of the inbounds fill up and their `channel_overflow` applies
+    #[default]
+    Backpressure,
+}
+
+/// Caps the number of records forwarded per second, e.g. to protect a shared endpoint.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThrottlePipeConfig {
+    #[serde(default = "default_throttle_tag")]
+    pub tag: PipeTagId,
+    pub inbounds: Vec<TagId>,
+
+    #[serde(default)]
+    pub disabled: bool,
+
+    // Overrides `global.channel_overflow` for the channel of this pipe
+    #[serde(default)]
+    pub channel_overflow: Option<OverflowPolicy>,
+
+    // How the channels this pipe receives from behave once it falls behind, overrides
+    // their `channel_overflow`
+    #[serde(default)]
+    pub inbound_overflow: Option<OverflowPolicy>,
+
+    // Records per second
+    pub rate: f64,
+
+    // Records forwarded at once after an idle period, one second of `rate` by default
+    #[serde(default)]
+    pub burst: Option<usize>,
+
+    #[serde(default)]
+    pub overflow: ThrottleOverflow,
+
+    // The number of dropped records is logged at most once per interval
+    #[serde(default = "default_throttle_drop_warn_interval")]
+    pub drop_warn_interval: DurationValue,
+
+    #[serde(default = "default_throttle_recv_timeout")]
+    pub recv_timeout: DurationValue,
+
+    #[serde(default = "default_throttle_recv_buffer_size")]
+    pub recv_buffer_size: usize,
+
+    #[serde(default = "default_throttle_max_batch_latency")]
+    pub max_batch_latency: DurationValue,
+}
+
+impl Verify for ThrottlePipeConfig {
+    fn verify(&mut self) -> super::Result<()> {
+        let tag = TagId::from(&self.tag);
+        if self.inbounds.is_empty() {
+            return Err(super::Error::EmptyField(tag, "inbounds"));
+        }
+
+        if !self.rate.is_finite() || self.rate <= 0.0 {
+            return Err(super::Error::InvalidConfig(format!(
+                "{}: rate must be a positive number of records per second, got {}",
+                tag, self.rate
+            )));
+        }
+
+        match self.burst {
+            Some(0) => return Err(super::Error::ZeroValue(tag.to_string(), "burst")),
+            Some(_) => {}
+            None => self.burst = Some(self.rate.ceil() as usize),
+        }
+
+        if self.recv_buffer_size == 0 {
+            return Err(super::Error::ZeroValue(tag.to_string(), "recv_buffer_size"));
+        }
+
+        self.drop_warn_interval
+            .ensure_non_zero(&tag, "drop_warn_interval")?;
+        self.recv_timeout.ensure_non_zero(&tag, "recv_timeout")?;
+        self.max_batch_latency
+            .ensure_non_zero(&tag, "max_batch_latency")?;
+
+        Ok(())
+    }
+}
+
+impl ThrottlePipeConfig {
+    pub fn channel_scale_factor(&self) -> usize {
+        8
+    }
+}
+
+fn default_throttle_tag() -> PipeTagId {
+    PipeTagId::new("throttle")
+}
+
+fn default_throttle_drop_warn_interval() -> DurationValue {
+    DurationValue::from_secs(10)
+}
+
+fn default_throttle_recv_timeout() -> DurationValue {
+    DurationValue::from_millis(100)
+}
+
+fn default_throttle_recv_buffer_size() -> usize {
+    8192
+}
+
+fn default_throttle_max_batch_latency() -> DurationValue {
+    DurationValue::from_millis(5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(body: &str) -> super::super::Result<ThrottlePipeConfig> {
+        let mut cfg: ThrottlePipeConfig =
+            toml::from_str(&format!("inbounds = [\"pipe:timeseries\"]\n{}", body))
+                .map_err(|e| super::super::Error::InvalidConfig(e.to_string()))?;
+        cfg.verify().map(|_| cfg)
+    }
+
+    #[test]
+    fn test_verify() {
+        let cfg = config("rate = 2.5").unwrap();
+        assert_eq!(cfg.burst, Some(3));
+        assert_eq!(cfg.overflow, ThrottleOverflow::Backpressure);
+
+        let cfg = config("rate = 100\nburst = 10\noverflow = \"drop\"").unwrap();
+        assert_eq!(cfg.burst, Some(10));
+        assert_eq!(cfg.overflow, ThrottleOverflow::Drop);
+
+        assert!(config("").is_err());
+        assert!(config("rate = 0").is_err());
+        assert!(config("rate = -1").is_err());
+        assert!(config("rate = nan").is_err());
+        assert!(config("rate = 1\nburst = 0").is_err());
+        assert!(config("rate = 1\noverflow = \"block\"").is_err());
+    }
+}
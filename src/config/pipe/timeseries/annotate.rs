@@ -1,10 +1,19 @@
-use std::time::Duration;
+use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    config::Verify,
-    core::tag::{PipeTagId, TagId},
+    config::{
+        env::interpolate_path,
+        global::OverflowPolicy,
+        pipe::{label_policy::LabelPolicyConfig, RecordSizeConfig},
+        types::DurationValue,
+        Verify,
+    },
+    core::{
+        tag::{PipeTagId, TagId},
+        types::Symbol,
+    },
 };
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -17,12 +26,89 @@ pub struct TimeseriesAnnotatePipeConfig {
     #[serde(default)]
     pub disabled: bool,
 
+    // Overrides `global.channel_overflow` for the channel of this pipe
+    #[serde(default)]
+    pub channel_overflow: Option<OverflowPolicy>,
+
+    // How the channels this pipe receives from behave once it falls behind, overrides
+    // their `channel_overflow`
+    #[serde(default)]
+    pub inbound_overflow: Option<OverflowPolicy>,
+
+    // Overrides `global.label_policy`
+    #[serde(default)]
+    pub label_policy: Option<LabelPolicyConfig>,
+
+    // Labels looked up by the value of a label, e.g. the rack of a host
+    #[serde(default)]
+    pub lookup: Option<LabelLookupConfig>,
+
     #[serde(default = "default_timeseries_annotate_pipe_recv_timeout")]
-    #[serde(deserialize_with = "crate::utils::parse_duration")]
-    pub recv_timeout: Duration,
+    pub recv_timeout: DurationValue,
 
     #[serde(default = "default_timeseries_annotate_pipe_recv_size")]
     pub recv_buffer_size: usize,
+
+    #[serde(default = "default_timeseries_annotate_pipe_max_batch_latency")]
+    pub max_batch_latency: DurationValue,
+
+    #[serde(flatten)]
+    pub record_size: RecordSizeConfig,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MappingFormat {
+    /// A header row, the first column holds the keys and the others the labels
+    Csv,
+    /// An object of objects, e.g. `{"web01": {"rack": "r1"}}`
+    Json,
+}
+
+/// What to do with a record whose key is missing, or not in the mapping.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingKeyPolicy {
+    #[default]
+    Pass,
+    Drop,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LabelLookupConfig {
+    // The label holding the key, or a field of the record without such label
+    pub key_field: Symbol,
+    pub mapping_file: PathBuf,
+    pub mapping_format: MappingFormat,
+
+    // The columns added as labels, all of them when empty
+    #[serde(default)]
+    pub columns: Vec<Symbol>,
+
+    // How often the file is checked, it is read again once modified
+    #[serde(default = "default_lookup_refresh_interval")]
+    pub refresh_interval: DurationValue,
+
+    #[serde(default)]
+    pub on_missing: MissingKeyPolicy,
+}
+
+impl LabelLookupConfig {
+    fn verify_for(&mut self, tag: &TagId) -> super::Result<()> {
+        interpolate_path(&mut self.mapping_file, tag, "lookup.mapping_file")?;
+        if self.key_field.is_empty() {
+            return Err(super::Error::EmptyField(tag.clone(), "lookup.key_field"));
+        }
+        if self.columns.iter().any(|column| column.is_empty()) {
+            return Err(super::Error::InvalidConfig(format!(
+                "{}: lookup.columns holds an empty name",
+                tag
+            )));
+        }
+        self.refresh_interval
+            .ensure_non_zero(tag, "lookup.refresh_interval")?;
+        Ok(())
+    }
 }
 
 impl Verify for TimeseriesAnnotatePipeConfig {
@@ -37,6 +123,19 @@ impl Verify for TimeseriesAnnotatePipeConfig {
                 "control_inbounds",
             ));
         }
+
+        if let Some(ref mut label_policy) = self.label_policy {
+            label_policy.verify()?;
+        }
+
+        let tag = TagId::from(&self.tag);
+        if let Some(ref mut lookup) = self.lookup {
+            lookup.verify_for(&tag)?;
+        }
+        self.recv_timeout.ensure_non_zero(&tag, "recv_timeout")?;
+        self.max_batch_latency
+            .ensure_non_zero(&tag, "max_batch_latency")?;
+        self.record_size.verify_for(&tag)?;
         Ok(())
     }
 }
@@ -45,14 +144,22 @@ fn default_timeseries_annotate_tag() -> PipeTagId {
     PipeTagId::new("timeseries_annotate")
 }
 
-fn default_timeseries_annotate_pipe_recv_timeout() -> Duration {
-    Duration::from_millis(5)
+fn default_lookup_refresh_interval() -> DurationValue {
+    DurationValue::from_secs(30)
+}
+
+fn default_timeseries_annotate_pipe_recv_timeout() -> DurationValue {
+    DurationValue::from_millis(5)
 }
 
 fn default_timeseries_annotate_pipe_recv_size() -> usize {
     64 * 8192
 }
 
+fn default_timeseries_annotate_pipe_max_batch_latency() -> DurationValue {
+    DurationValue::from_millis(5)
+}
+
 impl TimeseriesAnnotatePipeConfig {
     pub fn channel_scale_factor(&self) -> usize {
         32
@@ -3,49 +3,66 @@ pub mod annotate;
 pub use super::{Error, Result};
 pub use annotate::TimeseriesAnnotatePipeConfig;
 use log::warn;
-use std::{collections::HashMap, time::Duration};
+use std::collections::HashMap;
 
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    config::Verify,
+    config::{
+        env,
+        global::OverflowPolicy,
+        pipe::{label_policy::LabelPolicyConfig, Priority, RecordSizeConfig},
+        types::DurationValue,
+        Verify,
+    },
     core::{
         tag::{PipeTagId, TagId},
-        types::Symbol,
+        types::{Symbol, Unit},
     },
 };
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum MetricType {
     Counter,
+    #[default]
     Gauge,
+    /// Cumulative counts of the observations less than or equal to each bucket boundary
+    Histogram {
+        buckets: Vec<f64>,
+    },
+    /// Quantiles over the most recent observations
+    Summary {
+        quantiles: Vec<f64>,
+    },
 }
 
-impl Default for MetricType {
-    fn default() -> Self {
-        MetricType::Gauge
-    }
-}
+/// The default buckets of the Prometheus clients
+pub const DEFAULT_HISTOGRAM_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+pub const DEFAULT_SUMMARY_QUANTILES: [f64; 3] = [0.5, 0.9, 0.99];
 
 impl AsRef<str> for MetricType {
     fn as_ref(&self) -> &str {
         match self {
             MetricType::Counter => "counter",
             MetricType::Gauge => "gauge",
+            MetricType::Histogram { .. } => "histogram",
+            MetricType::Summary { .. } => "summary",
         }
     }
 }
 
-impl ToString for MetricType {
-    fn to_string(&self) -> String {
-        self.as_ref().to_string()
+impl std::fmt::Display for MetricType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_ref())
     }
 }
 
-impl Into<Symbol> for MetricType {
-    fn into(self) -> Symbol {
-        Symbol::from(self.as_ref())
+impl From<MetricType> for Symbol {
+    fn from(metric_type: MetricType) -> Self {
+        Symbol::from(metric_type.as_ref())
     }
 }
 
@@ -54,15 +71,102 @@ impl From<&str> for MetricType {
         match s {
             "counter" => MetricType::Counter,
             "gauge" => MetricType::Gauge,
+            "histogram" => MetricType::Histogram {
+                buckets: DEFAULT_HISTOGRAM_BUCKETS.to_vec(),
+            },
+            "summary" => MetricType::Summary {
+                quantiles: DEFAULT_SUMMARY_QUANTILES.to_vec(),
+            },
             _ => MetricType::default(),
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+impl MetricType {
+    /// Parse `histogram(0.1,1,10)` or `summary(0.5,0.99)`, and the plain type names.
+    fn parse(s: &str) -> std::result::Result<Self, String> {
+        let Some((kind, args)) = s.strip_suffix(')').and_then(|s| s.split_once('(')) else {
+            return Ok(MetricType::from(s));
+        };
+
+        let args = args
+            .split(',')
+            .map(|arg| arg.trim().parse::<f64>())
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|e| format!("invalid {} argument in {:?}: {}", kind, s, e))?;
+        if !args.windows(2).all(|w| w[0] < w[1]) {
+            return Err(format!("{:?}: arguments must be increasing", s));
+        }
+
+        match kind {
+            "histogram" => {
+                if args.iter().any(|b| !b.is_finite()) {
+                    return Err(format!("{:?}: buckets must be finite", s));
+                }
+                Ok(MetricType::Histogram { buckets: args })
+            }
+            "summary" => {
+                if args.iter().any(|q| !(0.0..=1.0).contains(q)) {
+                    return Err(format!("{:?}: quantiles must be within [0, 1]", s));
+                }
+                Ok(MetricType::Summary { quantiles: args })
+            }
+            _ => Err(format!(
+                "{:?}: only histogram and summary take arguments",
+                s
+            )),
+        }
+    }
+}
+
+/// What to do with the fields of a record which are neither labels, values, nor the timestamp.
+///
+/// When `values` is not set, every numeric field is a value, so the unexpected fields are
+/// the ones which can not be turned into a sample (strings, maps, ...).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnexpectedFields {
+    /// Drop them silently
+    Ignore,
+    /// Drop them, logging their names (rate-limited)
+    Warn,
+    /// Reject the whole record
+    Error,
+    /// Fold them into the labels, stringified
+    Label,
+}
+
+/// The type the samples of a value field are kept as.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValueCast {
+    /// Large integers lose precision past 2^53
+    #[default]
+    Float,
+    /// Integer counters, e.g. sequence numbers, kept exact. Floats are truncated.
+    Int,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct ValueField {
     pub name: Symbol,
     pub r#type: MetricType,
+    #[serde(rename = "as")]
+    pub cast: ValueCast,
+}
+
+// Either `[type:]name`, or a table for the options the short form can not hold
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawValueField {
+    Short(String),
+    Table {
+        name: Symbol,
+        #[serde(default)]
+        r#type: Option<String>,
+        #[serde(default, rename = "as")]
+        cast: ValueCast,
+    },
 }
 
 impl<'de> Deserialize<'de> for ValueField {
@@ -70,18 +174,34 @@ impl<'de> Deserialize<'de> for ValueField {
     where
         D: serde::Deserializer<'de>,
     {
-        let str = String::deserialize(deserializer)?;
+        let str = match RawValueField::deserialize(deserializer)? {
+            RawValueField::Short(str) => str,
+            RawValueField::Table { name, r#type, cast } => {
+                let r#type = match r#type {
+                    Some(r#type) => MetricType::parse(&r#type).map_err(serde::de::Error::custom)?,
+                    None => MetricType::default(),
+                };
+                return Ok(ValueField { name, r#type, cast });
+            }
+        };
+
+        // Bucket boundaries never contain a `:`, so the name is after the first one
         let mut parts = str.splitn(2, ':');
         match (parts.next(), parts.next()) {
             (Some(r#type), Some(name)) => {
-                let r#type = MetricType::from(r#type);
+                let r#type = MetricType::parse(r#type).map_err(serde::de::Error::custom)?;
                 let name = Symbol::from(name);
 
-                Ok(ValueField { name, r#type })
+                Ok(ValueField {
+                    name,
+                    r#type,
+                    cast: ValueCast::default(),
+                })
             }
             (Some(name), None) => Ok(ValueField {
                 name: Symbol::from(name),
                 r#type: MetricType::default(),
+                cast: ValueCast::default(),
             }),
             _ => Err(serde::de::Error::custom("invalid value field format")),
         }
@@ -108,14 +228,47 @@ pub struct TimeseriesPipeConfig {
     #[serde(default)]
     pub extra_labels: HashMap<Symbol, String>,
 
+    // Defaults to `ignore` when the values field is set, `warn` otherwise.
+    #[serde(default)]
+    pub unexpected_fields: Option<UnexpectedFields>,
+
+    // Converts the named value fields into the unit, e.g. `{ latency = "s" }`. The units of
+    // the other fields only have their spelling normalized, `milliseconds` into `ms`.
+    #[serde(default)]
+    pub normalize_units: HashMap<Symbol, String>,
+
+    // Overrides `global.label_policy`
+    #[serde(default)]
+    pub label_policy: Option<LabelPolicyConfig>,
+
     #[serde(default)]
     pub disabled: bool,
 
+    // Overrides `global.channel_overflow` for the channel of this pipe
+    #[serde(default)]
+    pub channel_overflow: Option<OverflowPolicy>,
+
+    // How the channels this pipe receives from behave once it falls behind, overrides
+    // their `channel_overflow`
+    #[serde(default)]
+    pub inbound_overflow: Option<OverflowPolicy>,
+
+    // Set on the records forwarded, for the outbounds shedding load
+    #[serde(default)]
+    pub priority: Option<Priority>,
+
     #[serde(default = "default_timeseries_pipe_recv_timeout")]
-    pub recv_timeout: Duration,
+    pub recv_timeout: DurationValue,
 
     #[serde(default = "default_timeseries_pipe_recv_buffer_size")]
     pub recv_buffer_size: usize,
+
+    // How long a received record may wait for the batch to fill up
+    #[serde(default = "default_timeseries_pipe_max_batch_latency")]
+    pub max_batch_latency: DurationValue,
+
+    #[serde(flatten)]
+    pub record_size: RecordSizeConfig,
 }
 
 impl Verify for TimeseriesPipeConfig {
@@ -128,11 +281,47 @@ impl Verify for TimeseriesPipeConfig {
             return Err(super::Error::EmptyField((&self.tag).into(), "labels"));
         }
 
+        if let Some(ref mut label_policy) = self.label_policy {
+            label_policy.verify()?;
+        }
+
+        let tag = TagId::from(&self.tag);
+        for (field, unit) in &self.normalize_units {
+            if Unit::parse(unit).is_none() {
+                return Err(super::Error::InvalidConfig(format!(
+                    "{}: unknown unit {:?} in normalize_units.{}",
+                    tag, unit, field
+                )));
+            }
+        }
+        for (key, value) in self.extra_labels.iter_mut() {
+            env::interpolate_field(value, &tag, &format!("extra_labels.{}", key))?;
+        }
+        self.recv_timeout.ensure_non_zero(&tag, "recv_timeout")?;
+        self.max_batch_latency
+            .ensure_non_zero(&tag, "max_batch_latency")?;
+        self.record_size.verify_for(&tag)?;
+
         match self.values {
             Some(ref values) => {
                 if values.is_empty() {
                     return Err(super::Error::EmptyField((&self.tag).into(), "values"));
                 }
+                // The observations of a distribution are floats
+                if let Some(field) = values.iter().find(|field| {
+                    field.cast == ValueCast::Int
+                        && matches!(
+                            field.r#type,
+                            MetricType::Histogram { .. } | MetricType::Summary { .. }
+                        )
+                }) {
+                    return Err(super::Error::InvalidConfig(format!(
+                        "{}: {} is a {}, which can not be kept as int",
+                        tag,
+                        field.name,
+                        field.r#type.as_ref()
+                    )));
+                }
             }
             None => {
                 warn!("values is not set, all fields except the labels and timestamp will be treated as values");
@@ -147,16 +336,109 @@ impl TimeseriesPipeConfig {
     pub fn channel_scale_factor(&self) -> usize {
         32
     }
+
+    pub fn unexpected_fields(&self) -> UnexpectedFields {
+        match (self.unexpected_fields, &self.values) {
+            (Some(mode), _) => mode,
+            (None, Some(_)) => UnexpectedFields::Ignore,
+            (None, None) => UnexpectedFields::Warn,
+        }
+    }
 }
 
 fn default_timeseries_tag() -> PipeTagId {
     PipeTagId::new("timeseries")
 }
 
-fn default_timeseries_pipe_recv_timeout() -> Duration {
-    Duration::from_millis(5)
+fn default_timeseries_pipe_recv_timeout() -> DurationValue {
+    DurationValue::from_millis(5)
+}
+
+fn default_timeseries_pipe_max_batch_latency() -> DurationValue {
+    DurationValue::from_millis(5)
 }
 
 fn default_timeseries_pipe_recv_buffer_size() -> usize {
     64 * 8192
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value_field(s: &str) -> std::result::Result<ValueField, serde_json::Error> {
+        serde_json::from_value(serde_json::Value::String(s.to_string()))
+    }
+
+    #[test]
+    fn test_value_field_types() {
+        let field = value_field("histogram(0.1, 1,10):latency_ms").unwrap();
+        assert_eq!(field.name, Symbol::new("latency_ms"));
+        assert_eq!(
+            field.r#type,
+            MetricType::Histogram {
+                buckets: vec![0.1, 1.0, 10.0]
+            }
+        );
+
+        let field = value_field("summary(0.5,0.99):latency_ms").unwrap();
+        assert_eq!(
+            field.r#type,
+            MetricType::Summary {
+                quantiles: vec![0.5, 0.99]
+            }
+        );
+
+        let field = value_field("histogram:latency_ms").unwrap();
+        assert_eq!(
+            field.r#type,
+            MetricType::Histogram {
+                buckets: DEFAULT_HISTOGRAM_BUCKETS.to_vec()
+            }
+        );
+        assert_eq!(
+            value_field("counter:requests").unwrap().r#type,
+            MetricType::Counter
+        );
+        assert_eq!(value_field("cpu").unwrap().r#type, MetricType::Gauge);
+
+        assert!(value_field("histogram(1,0.5):latency_ms").is_err());
+        assert!(value_field("histogram(0.1,x):latency_ms").is_err());
+        assert!(value_field("histogram(1,inf):latency_ms").is_err());
+        assert!(value_field("summary(0.5,1.5):latency_ms").is_err());
+        assert!(value_field("gauge(1):latency_ms").is_err());
+    }
+
+    #[test]
+    fn test_value_field_table() {
+        let cfg: TimeseriesPipeConfig = toml::from_str(
+            r#"
+            inbounds = ["inbound:a"]
+            labels = ["host"]
+            values = [
+                "cpu",
+                { name = "seq", type = "counter", as = "int" },
+                { name = "load" },
+            ]
+            "#,
+        )
+        .unwrap();
+        let values = cfg.values.unwrap();
+        assert_eq!(values[0].cast, ValueCast::Float);
+        assert_eq!(values[1].name, Symbol::new("seq"));
+        assert_eq!(values[1].r#type, MetricType::Counter);
+        assert_eq!(values[1].cast, ValueCast::Int);
+        assert_eq!(values[2].r#type, MetricType::Gauge);
+        assert_eq!(values[2].cast, ValueCast::Float);
+
+        let mut cfg: TimeseriesPipeConfig = toml::from_str(
+            r#"
+            inbounds = ["inbound:a"]
+            labels = ["host"]
+            values = [{ name = "latency", type = "histogram", as = "int" }]
+            "#,
+        )
+        .unwrap();
+        assert!(cfg.verify().is_err());
+    }
+}
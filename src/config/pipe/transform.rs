@@ -0,0 +1,184 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{global::OverflowPolicy, pipe::Priority, types::DurationValue, Verify},
+    core::{
+        tag::{PipeTagId, TagId},
+        types::Symbol,
+    },
+};
+
+/// An operation on the fields of a record, e.g. `{ op = "rename", from = "hostName", to = "host" }`.
+/// The operations on a missing field do nothing, except a strict `rename`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum TransformOp {
+    Rename {
+        from: Symbol,
+        to: Symbol,
+        // Reject the records without the field instead of forwarding them unchanged
+        #[serde(default)]
+        strict: bool,
+    },
+    Drop {
+        field: Symbol,
+    },
+    Copy {
+        from: Symbol,
+        to: Symbol,
+    },
+    Set {
+        field: Symbol,
+        value: serde_json::Value,
+    },
+    // Set `into` to the first of the fields present and not null
+    Coalesce {
+        fields: Vec<Symbol>,
+        into: Symbol,
+    },
+}
+
+impl TransformOp {
+    fn fields(&self) -> Vec<&Symbol> {
+        match self {
+            TransformOp::Rename { from, to, .. } | TransformOp::Copy { from, to } => vec![from, to],
+            TransformOp::Drop { field } | TransformOp::Set { field, .. } => vec![field],
+            TransformOp::Coalesce { fields, into } => fields.iter().chain([into]).collect(),
+        }
+    }
+}
+
+/// Renames, drops and derives the fields of each record, applying the operations in order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransformPipeConfig {
+    #[serde(default = "default_transform_tag")]
+    pub tag: PipeTagId,
+    pub inbounds: Vec<TagId>,
+
+    #[serde(default)]
+    pub disabled: bool,
+
+    // Overrides `global.channel_overflow` for the channel of this pipe
+    #[serde(default)]
+    pub channel_overflow: Option<OverflowPolicy>,
+
+    // How the channels this pipe receives from behave once it falls behind, overrides
+    // their `channel_overflow`
+    #[serde(default)]
+    pub inbound_overflow: Option<OverflowPolicy>,
+
+    // Set on the records forwarded, for the outbounds shedding load
+    #[serde(default)]
+    pub priority: Option<Priority>,
+
+    pub operations: Vec<TransformOp>,
+
+    #[serde(default = "default_transform_recv_timeout")]
+    pub recv_timeout: DurationValue,
+
+    #[serde(default = "default_transform_recv_buffer_size")]
+    pub recv_buffer_size: usize,
+
+    #[serde(default = "default_transform_max_batch_latency")]
+    pub max_batch_latency: DurationValue,
+}
+
+impl Verify for TransformPipeConfig {
+    fn verify(&mut self) -> super::Result<()> {
+        let tag = TagId::from(&self.tag);
+        if self.inbounds.is_empty() {
+            return Err(super::Error::EmptyField(tag, "inbounds"));
+        }
+
+        if self.operations.is_empty() {
+            return Err(super::Error::EmptyField(tag, "operations"));
+        }
+
+        for op in &self.operations {
+            if op.fields().iter().any(|field| field.is_empty()) {
+                return Err(super::Error::InvalidConfig(format!(
+                    "{}: field names of the operations cannot be empty",
+                    tag
+                )));
+            }
+
+            match op {
+                TransformOp::Rename { from, to, .. } | TransformOp::Copy { from, to }
+                    if from == to =>
+                {
+                    return Err(super::Error::InvalidConfig(format!(
+                        "{}: operation from {} to itself",
+                        tag, from
+                    )));
+                }
+                TransformOp::Coalesce { fields, .. } if fields.is_empty() => {
+                    return Err(super::Error::EmptyField(tag, "coalesce.fields"));
+                }
+                _ => {}
+            }
+        }
+
+        self.recv_timeout.ensure_non_zero(&tag, "recv_timeout")?;
+        self.max_batch_latency
+            .ensure_non_zero(&tag, "max_batch_latency")?;
+
+        Ok(())
+    }
+}
+
+impl TransformPipeConfig {
+    pub fn channel_scale_factor(&self) -> usize {
+        8
+    }
+}
+
+fn default_transform_tag() -> PipeTagId {
+    PipeTagId::new("transform")
+}
+
+fn default_transform_recv_timeout() -> DurationValue {
+    DurationValue::from_millis(5)
+}
+
+fn default_transform_recv_buffer_size() -> usize {
+    8192
+}
+
+fn default_transform_max_batch_latency() -> DurationValue {
+    DurationValue::from_millis(5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(operations: &str) -> super::super::Result<TransformPipeConfig> {
+        let mut cfg: TransformPipeConfig = toml::from_str(&format!(
+            "inbounds = [\"inbound:a\"]\noperations = [{}]",
+            operations
+        ))
+        .unwrap();
+        cfg.verify().map(|_| cfg)
+    }
+
+    #[test]
+    fn test_verify_operations() {
+        let cfg = config(
+            r#"{ op = "rename", from = "hostName", to = "host", strict = true },
+               { op = "drop", field = "debug" },
+               { op = "set", field = "env", value = "prod" },
+               { op = "coalesce", fields = ["a", "b"], into = "c" }"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            &cfg.operations[0],
+            TransformOp::Rename { strict: true, .. }
+        ));
+        assert!(matches!(&cfg.operations[2], TransformOp::Set { value, .. } if value == "prod"));
+
+        assert!(config("").is_err());
+        assert!(config(r#"{ op = "drop", field = "" }"#).is_err());
+        assert!(config(r#"{ op = "copy", from = "a", to = "a" }"#).is_err());
+        assert!(config(r#"{ op = "coalesce", fields = [], into = "c" }"#).is_err());
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{global::OverflowPolicy, types::DurationValue, Verify},
+    core::{
+        tag::{PipeTagId, TagId},
+        types::{Primitive, Symbol},
+    },
+};
+
+/// A field of the schema, e.g. `{ name = "value", type = "float", required = true }`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SchemaField {
+    pub name: Symbol,
+    pub r#type: Primitive,
+    // A missing or null optional field is accepted, a present one must have the type
+    #[serde(default)]
+    pub required: bool,
+}
+
+/// What the validate pipe does with a value which is not of the declared type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MismatchPolicy {
+    /// Convert it, e.g. `"1.5"` into a float, the record is rejected if it does not convert
+    Coerce,
+    /// Remove the field, the record is rejected if the field is required
+    Drop,
+    /// Reject the record
+    DeadLetter,
+    /// Reject the record, logged and counted as a transform error instead of being sent to
+    /// the dead letter channel
+    Error,
+}
+
+/// What the validate pipe does with the fields not declared in the schema.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExtraFields {
+    #[default]
+    Pass,
+    Strip,
+}
+
+/// Checks the fields of each record against a schema. The records failing it are sent to the
+/// dead letter channel, or dropped without one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidatePipeConfig {
+    #[serde(default = "default_validate_tag")]
+    pub tag: PipeTagId,
+    pub inbounds: Vec<TagId>,
+
+    #[serde(default)]
+    pub disabled: bool,
+
+    // Overrides `global.channel_overflow` for the channel of this pipe
+    #[serde(default)]
+    pub channel_overflow: Option<OverflowPolicy>,
+
+    // How the channels this pipe receives from behave once it falls behind, overrides
+    // their `channel_overflow`
+    #[serde(default)]
+    pub inbound_overflow: Option<OverflowPolicy>,
+
+    pub fields: Vec<SchemaField>,
+
+    // Convert the values of another type instead of rejecting the record, e.g. `"1.5"` into
+    // a float. Only the values which do not convert are rejected. Same as
+    // `on_mismatch = "coerce"`
+    #[serde(default)]
+    pub coerce: bool,
+
+    // `dead_letter` unless `coerce` is set
+    #[serde(default)]
+    pub on_mismatch: Option<MismatchPolicy>,
+
+    #[serde(default)]
+    pub extra_fields: ExtraFields,
+
+    #[serde(default = "default_validate_recv_timeout")]
+    pub recv_timeout: DurationValue,
+
+    #[serde(default = "default_validate_recv_buffer_size")]
+    pub recv_buffer_size: usize,
+
+    #[serde(default = "default_validate_max_batch_latency")]
+    pub max_batch_latency: DurationValue,
+}
+
+impl Verify for ValidatePipeConfig {
+    fn verify(&mut self) -> super::Result<()> {
+        let tag = TagId::from(&self.tag);
+        if self.inbounds.is_empty() {
+            return Err(super::Error::EmptyField(tag, "inbounds"));
+        }
+
+        if self.fields.is_empty() {
+            return Err(super::Error::EmptyField(tag, "fields"));
+        }
+
+        let mut names = HashSet::new();
+        for field in &self.fields {
+            if field.name.is_empty() {
+                return Err(super::Error::InvalidConfig(format!(
+                    "{}: schema field name cannot be empty",
+                    tag
+                )));
+            }
+            if !names.insert(&field.name) {
+                return Err(super::Error::InvalidConfig(format!(
+                    "{}: schema field {} is declared twice",
+                    tag, field.name
+                )));
+            }
+        }
+
+        if self.coerce && self.on_mismatch() != MismatchPolicy::Coerce {
+            return Err(super::Error::InvalidConfig(format!(
+                "{}: coerce = true conflicts with on_mismatch",
+                tag
+            )));
+        }
+
+        self.recv_timeout.ensure_non_zero(&tag, "recv_timeout")?;
+        self.max_batch_latency
+            .ensure_non_zero(&tag, "max_batch_latency")?;
+
+        Ok(())
+    }
+}
+
+impl ValidatePipeConfig {
+    pub fn channel_scale_factor(&self) -> usize {
+        8
+    }
+
+    pub fn on_mismatch(&self) -> MismatchPolicy {
+        match self.on_mismatch {
+            Some(policy) => policy,
+            None if self.coerce => MismatchPolicy::Coerce,
+            None => MismatchPolicy::DeadLetter,
+        }
+    }
+}
+
+fn default_validate_tag() -> PipeTagId {
+    PipeTagId::new("validate")
+}
+
+fn default_validate_recv_timeout() -> DurationValue {
+    DurationValue::from_millis(5)
+}
+
+fn default_validate_recv_buffer_size() -> usize {
+    8192
+}
+
+fn default_validate_max_batch_latency() -> DurationValue {
+    DurationValue::from_millis(5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(fields: &str) -> super::super::Result<ValidatePipeConfig> {
+        let mut cfg: ValidatePipeConfig = toml::from_str(&format!(
+            "inbounds = [\"inbound:a\"]\nfields = [{}]",
+            fields
+        ))
+        .unwrap();
+        cfg.verify().map(|_| cfg)
+    }
+
+    #[test]
+    fn test_verify_fields() {
+        let cfg = config(
+            r#"{ name = "value", type = "float", required = true },
+               { name = "time", type = "datetime" }"#,
+        )
+        .unwrap();
+        assert!(cfg.fields[0].required);
+        assert_eq!(cfg.fields[1].r#type, Primitive::DateTime);
+        assert!(!cfg.coerce);
+        assert_eq!(cfg.on_mismatch(), MismatchPolicy::DeadLetter);
+        assert_eq!(cfg.extra_fields, ExtraFields::Pass);
+
+        assert!(config("").is_err());
+        assert!(config(r#"{ name = "", type = "int" }"#).is_err());
+        assert!(config(r#"{ name = "a", type = "int" }, { name = "a", type = "float" }"#).is_err());
+    }
+
+    #[test]
+    fn test_on_mismatch() {
+        let config = |body: &str| {
+            let mut cfg: ValidatePipeConfig = toml::from_str(&format!(
+                "inbounds = [\"inbound:a\"]\nfields = [{{ name = \"a\", type = \"int\" }}]\n{}",
+                body
+            ))
+            .unwrap();
+            cfg.verify().map(|_| cfg)
+        };
+
+        let cfg = config("coerce = true").unwrap();
+        assert_eq!(cfg.on_mismatch(), MismatchPolicy::Coerce);
+        let cfg = config("on_mismatch = \"drop\"\nextra_fields = \"strip\"").unwrap();
+        assert_eq!(cfg.on_mismatch(), MismatchPolicy::Drop);
+        assert_eq!(cfg.extra_fields, ExtraFields::Strip);
+        assert!(config("coerce = true\non_mismatch = \"coerce\"").is_ok());
+        assert!(config("coerce = true\non_mismatch = \"error\"").is_err());
+    }
+}
@@ -1,5 +1,6 @@
 use std::{collections::HashSet, fmt::Display};
 
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
 
 use crate::{
@@ -20,6 +21,18 @@ pub struct CSVField {
     pub optional: bool,
 }
 
+/// How the columns of a CSV line are mapped onto the configured fields.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchBy {
+    /// By the `index` of each field
+    #[default]
+    Index,
+    /// By looking up the field names in the header line. Header names are trimmed before
+    /// the comparison, and compared case-insensitively with `header_case_insensitive`.
+    Header,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub struct CSVProtocolConfig {
@@ -34,6 +47,20 @@ pub struct CSVProtocolConfig {
     pub fields: Vec<CSVField>,
     #[serde(default)]
     pub num_fields: usize,
+
+    #[serde(default)]
+    pub match_by: MatchBy,
+    #[serde(default)]
+    pub header_case_insensitive: bool,
+
+    /// Reject malformed quoting: text after a closing quote, an unterminated quote, or a
+    /// quote inside an unquoted field. Otherwise such fields are kept as they are.
+    #[serde(default)]
+    pub strict_quotes: bool,
+
+    /// Timezone of the datetimes without an offset, e.g. `Asia/Shanghai`
+    #[serde(default = "super::default_timezone")]
+    pub timezone: Tz,
 }
 
 impl Display for CSVField {
@@ -61,6 +88,12 @@ impl Display for CSVProtocolConfig {
 
 impl Verify for CSVProtocolConfig {
     fn verify(&mut self) -> crate::config::Result<()> {
+        if self.match_by == MatchBy::Header && !self.has_header {
+            return Err(crate::config::Error::InvalidConfig(
+                "CSV match_by = \"header\" requires has_header".to_string(),
+            ));
+        }
+
         // fill in the index if all are zero
         let is_all_zero = self.fields.iter().all(|c| c.index == 0);
         if is_all_zero {
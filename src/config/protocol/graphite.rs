@@ -1,13 +1,13 @@
 use std::{collections::HashMap, fmt::Display};
 
-use once_cell::sync::Lazy;
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
 
 use crate::{
     config::Verify,
     core::{
         tag::{HasTag, ProtocolTagId, TagId},
-        types::{Primitive, Symbol},
+        types::Primitive,
     },
 };
 
@@ -15,6 +15,10 @@ use crate::{
 pub struct GraphiteProtocolConfig {
     pub tag: ProtocolTagId,
     pub attributes: Option<HashMap<String, Primitive>>,
+
+    /// Timezone of the datetimes without an offset, e.g. `Asia/Shanghai`
+    #[serde(default = "super::default_timezone")]
+    pub timezone: Tz,
 }
 
 impl Verify for GraphiteProtocolConfig {
@@ -0,0 +1,61 @@
+use std::fmt::Display;
+
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Verify,
+    core::{
+        tag::{HasTag, ProtocolTagId, TagId},
+        types::Symbol,
+    },
+};
+
+/// Newline-delimited JSON, one object per line.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonProtocolConfig {
+    #[serde(default = "default_json_tag")]
+    pub tag: ProtocolTagId,
+
+    /// Only keep these fields, all of them by default
+    #[serde(default)]
+    pub fields: Option<Vec<Symbol>>,
+
+    /// Field parsed into a datetime: an RFC 3339 string, or a Unix timestamp in seconds,
+    /// milliseconds or nanoseconds. Records without it are rejected.
+    #[serde(default)]
+    pub timestamp_field: Option<Symbol>,
+
+    /// Timezone of the datetimes without an offset, e.g. `Asia/Shanghai`
+    #[serde(default = "super::default_timezone")]
+    pub timezone: Tz,
+}
+
+fn default_json_tag() -> ProtocolTagId {
+    ProtocolTagId::new("json")
+}
+
+impl Verify for JsonProtocolConfig {
+    fn verify(&mut self) -> crate::config::Result<()> {
+        if self.fields.as_ref().is_some_and(|fields| fields.is_empty()) {
+            return Err(crate::config::Error::EmptyField(
+                TagId::from(&self.tag),
+                "fields",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl HasTag for JsonProtocolConfig {
+    fn tag(&self) -> &TagId {
+        &self.tag
+    }
+}
+
+impl Display for JsonProtocolConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "JsonParserConfig {{ tag: {} }}", self.tag())
+    }
+}
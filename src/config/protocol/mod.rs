@@ -1,8 +1,10 @@
 pub mod csv;
 pub mod graphite;
+pub mod json;
 
 use std::fmt::Display;
 
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
 
 use crate::core::tag::{HasTag, TagId};
@@ -14,16 +16,19 @@ use super::Verify;
 #[serde(tag = "type")]
 pub enum ProtocolConfig {
     #[serde(rename = "csv")]
-    CSV(csv::CSVProtocolConfig),
+    Csv(csv::CSVProtocolConfig),
     #[serde(rename = "graphite")]
     Graphite(graphite::GraphiteProtocolConfig),
+    #[serde(rename = "json")]
+    Json(json::JsonProtocolConfig),
 }
 
 impl Display for ProtocolConfig {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            ProtocolConfig::CSV(config) => write!(f, "CSVParserConfig {{ {} }}", config),
+            ProtocolConfig::Csv(config) => write!(f, "CSVParserConfig {{ {} }}", config),
             ProtocolConfig::Graphite(config) => write!(f, "GraphiteParserConfig {{ {} }}", config),
+            ProtocolConfig::Json(config) => write!(f, "JsonParserConfig {{ {} }}", config),
         }
     }
 }
@@ -31,17 +36,23 @@ impl Display for ProtocolConfig {
 impl Verify for ProtocolConfig {
     fn verify(&mut self) -> crate::config::Result<()> {
         match self {
-            ProtocolConfig::CSV(config) => config.verify(),
+            ProtocolConfig::Csv(config) => config.verify(),
             ProtocolConfig::Graphite(config) => config.verify(),
+            ProtocolConfig::Json(config) => config.verify(),
         }
     }
 }
 
+fn default_timezone() -> Tz {
+    Tz::UTC
+}
+
 impl HasTag for ProtocolConfig {
     fn tag(&self) -> &TagId {
         match self {
-            ProtocolConfig::CSV(config) => &config.tag,
+            ProtocolConfig::Csv(config) => &config.tag,
             ProtocolConfig::Graphite(config) => &config.tag,
+            ProtocolConfig::Json(config) => &config.tag,
         }
     }
 }
@@ -0,0 +1,77 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::types::DurationValue, utils::retry::Jitter};
+
+/// Retry / reconnect backoff settings, shared by the network components.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetryConfig {
+    /// Maximum number of attempts, including the first one
+    #[serde(default = "default_retry_max_attempts")]
+    pub max_attempts: usize,
+
+    /// Delay before the first retry
+    #[serde(default = "default_retry_initial_delay")]
+    pub initial_delay: DurationValue,
+
+    /// Upper bound of the delay between two attempts
+    #[serde(default = "default_retry_max_delay")]
+    pub max_delay: DurationValue,
+
+    /// Factor applied to the delay after each attempt
+    #[serde(default = "default_retry_multiplier")]
+    pub multiplier: f64,
+
+    #[serde(default)]
+    pub jitter: Jitter,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_retry_max_attempts(),
+            initial_delay: default_retry_initial_delay(),
+            max_delay: default_retry_max_delay(),
+            multiplier: default_retry_multiplier(),
+            jitter: Jitter::default(),
+        }
+    }
+}
+
+impl RetryConfig {
+    /// `field` is where the settings sit in the owner, e.g. `retry` or `reopen`.
+    pub fn verify_for(&self, owner: impl Display, field: &str) -> super::Result<()> {
+        if !self.multiplier.is_finite() || self.multiplier < 1.0 {
+            return Err(super::Error::InvalidConfig(format!(
+                "{}: {}.multiplier must be a finite number >= 1, got {}",
+                owner, field, self.multiplier
+            )));
+        }
+
+        if self.initial_delay > self.max_delay {
+            return Err(super::Error::InvalidConfig(format!(
+                "{}: {}.initial_delay {} exceeds {}.max_delay {}",
+                owner, field, self.initial_delay, field, self.max_delay
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+fn default_retry_max_attempts() -> usize {
+    3
+}
+
+fn default_retry_initial_delay() -> DurationValue {
+    DurationValue::from_millis(100)
+}
+
+fn default_retry_max_delay() -> DurationValue {
+    DurationValue::from_secs(5)
+}
+
+fn default_retry_multiplier() -> f64 {
+    2.0
+}
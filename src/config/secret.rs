@@ -0,0 +1,390 @@
+/*
+A secret reference can be used in place of any string in the config file, it is resolved when
+the config is loaded:
+    - "@file:/run/secrets/prom_password": the content of the file, without the trailing newline
+    - "@exec:/usr/local/bin/get-secret prom": the standard output of the command
+Templates such as `{{env:VAR}}` are filled before the reference is resolved, and a `env:VAR`
+string whose variable holds a reference is resolved as well.
+
+Resolved values are registered for redaction, see [`redact`].
+*/
+
+use std::{
+    io::Read,
+    path::Path,
+    process::{Command, Stdio},
+    sync::RwLock,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use log::warn;
+use once_cell::sync::Lazy;
+
+use super::{template, Error};
+
+const FILE_PREFIX: &str = "@file:";
+const EXEC_PREFIX: &str = "@exec:";
+const ENV_PREFIX: &str = "env:";
+
+const EXEC_TIMEOUT: Duration = Duration::from_secs(5);
+const REDACTED: &str = "<redacted>";
+
+static SECRETS: Lazy<RwLock<Vec<String>>> = Lazy::new(|| RwLock::new(Vec::new()));
+
+fn register(secret: &str) {
+    if secret.is_empty() {
+        return;
+    }
+
+    let mut secrets = SECRETS.write().unwrap();
+    if !secrets.iter().any(|s| s == secret) {
+        secrets.push(secret.to_string());
+        // Longest first, so that a secret containing another one is fully redacted
+        secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+    }
+}
+
+/// Replace every resolved secret found in `text`.
+pub fn redact(text: &str) -> String {
+    let secrets = SECRETS.read().unwrap();
+    secrets.iter().fold(text.to_string(), |text, secret| {
+        if text.contains(secret.as_str()) {
+            text.replace(secret.as_str(), REDACTED)
+        } else {
+            text
+        }
+    })
+}
+
+pub fn has_secrets() -> bool {
+    !SECRETS.read().unwrap().is_empty()
+}
+
+fn is_reference(s: &str) -> bool {
+    s.starts_with(FILE_PREFIX) || s.starts_with(EXEC_PREFIX)
+}
+
+/// Resolve the string if it is a secret reference, `path` names the field for the errors.
+fn resolve(s: &str, path: &str) -> Result<Option<String>, Error> {
+    // a env:VAR string holding a reference
+    let s = match s.strip_prefix(ENV_PREFIX) {
+        Some(var) => match std::env::var(var) {
+            Ok(value) if is_reference(&value) => value,
+            _ => return Ok(None),
+        },
+        None => s.to_string(),
+    };
+
+    if !is_reference(&s) {
+        return Ok(None);
+    }
+
+    let fail = |reason: String| Error::Secret(path.to_string(), reason);
+    let reference = template::fill(&s).ok_or_else(|| fail("failed to fill template".into()))?;
+
+    let secret = if let Some(file) = reference.strip_prefix(FILE_PREFIX) {
+        read_file(Path::new(file)).map_err(fail)?
+    } else if let Some(command) = reference.strip_prefix(EXEC_PREFIX) {
+        exec(command, EXEC_TIMEOUT).map_err(fail)?
+    } else {
+        unreachable!()
+    };
+
+    register(&secret);
+    Ok(Some(secret))
+}
+
+/// Read a secret file, e.g. the credentials of an outbound, registering its content for
+/// redaction. The error names the path.
+pub fn read_secret_file(path: &Path) -> Result<String, String> {
+    let secret = read_file(path)?;
+    register(&secret);
+    Ok(secret)
+}
+
+fn read_file(path: &Path) -> Result<String, String> {
+    let metadata = std::fs::metadata(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if is_world_readable(&metadata) {
+        warn!("Secret file {} is world-readable", path.display());
+    }
+
+    let content =
+        std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(trim_newline(content))
+}
+
+#[cfg(unix)]
+fn is_world_readable(metadata: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o004 != 0
+}
+
+#[cfg(not(unix))]
+fn is_world_readable(_metadata: &std::fs::Metadata) -> bool {
+    false
+}
+
+fn exec(command: &str, timeout: Duration) -> Result<String, String> {
+    let args = shlex::split(command).ok_or_else(|| format!("invalid command: {}", command))?;
+    let (program, args) = args
+        .split_first()
+        .ok_or_else(|| "empty command".to_string())?;
+
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("{}: {}", program, e))?;
+
+    // Read while waiting, a command filling up a pipe would otherwise never exit
+    let stdout = child.stdout.take().map(read_on_thread);
+    let stderr = child.stderr.take().map(read_on_thread);
+
+    let start = Instant::now();
+    let status = loop {
+        match child.try_wait().map_err(|e| e.to_string())? {
+            Some(status) => break status,
+            None if start.elapsed() >= timeout => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("{} timed out after {:?}", program, timeout));
+            }
+            None => std::thread::sleep(Duration::from_millis(10)),
+        }
+    };
+
+    let stdout = match stdout {
+        Some(reader) => join_reader(reader).map_err(|e| e.to_string())?,
+        None => String::new(),
+    };
+    let stderr = stderr
+        .and_then(|reader| join_reader(reader).ok())
+        .unwrap_or_default();
+
+    if !status.success() {
+        return Err(format!(
+            "{} exited with {}: {}",
+            program,
+            status,
+            stderr.trim()
+        ));
+    }
+
+    Ok(trim_newline(stdout))
+}
+
+/// Read a pipe of a child to its end. Left behind on a timeout, the thread ends once the
+/// killed child closes the pipe.
+fn read_on_thread(mut pipe: impl Read + Send + 'static) -> JoinHandle<std::io::Result<String>> {
+    std::thread::spawn(move || {
+        let mut content = String::new();
+        pipe.read_to_string(&mut content)?;
+        Ok(content)
+    })
+}
+
+fn join_reader(reader: JoinHandle<std::io::Result<String>>) -> std::io::Result<String> {
+    reader.join().expect("pipe reader panicked")
+}
+
+fn trim_newline(mut s: String) -> String {
+    while s.ends_with('\n') || s.ends_with('\r') {
+        s.pop();
+    }
+    s
+}
+
+/// Resolve the secret references of a config parsed from JSON.
+pub fn resolve_json(value: &mut serde_json::Value, path: &str) -> Result<(), Error> {
+    match value {
+        serde_json::Value::String(s) => {
+            if let Some(secret) = resolve(s, path)? {
+                *s = secret;
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for (i, item) in items.iter_mut().enumerate() {
+                resolve_json(item, &format!("{}[{}]", path, i))?;
+            }
+        }
+        serde_json::Value::Object(map) => {
+            for (key, item) in map.iter_mut() {
+                resolve_json(item, &join(path, key))?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Resolve the secret references of a config parsed from TOML.
+pub fn resolve_toml(value: &mut toml::Value, path: &str) -> Result<(), Error> {
+    match value {
+        toml::Value::String(s) => {
+            if let Some(secret) = resolve(s, path)? {
+                *s = secret;
+            }
+        }
+        toml::Value::Array(items) => {
+            for (i, item) in items.iter_mut().enumerate() {
+                resolve_toml(item, &format!("{}[{}]", path, i))?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (key, item) in table.iter_mut() {
+                resolve_toml(item, &join(path, key))?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::config::Config;
+
+    fn secret_file(dir: &Path, name: &str, content: &str, mode: u32) -> String {
+        let path = dir.join(name);
+        let mut file = std::fs::File::create(&path).unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+        }
+        #[cfg(not(unix))]
+        let _ = mode;
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn test_file_provider() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = secret_file(dir.path(), "password", "file-secret-1\n", 0o600);
+
+        let mut value = serde_json::json!({ "auth": { "password": format!("@file:{}", path) } });
+        resolve_json(&mut value, "").unwrap();
+        assert_eq!(value["auth"]["password"], "file-secret-1");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_file_permission_warning() {
+        let dir = tempfile::tempdir().unwrap();
+        let private = secret_file(dir.path(), "private", "x", 0o600);
+        let public = secret_file(dir.path(), "public", "x", 0o644);
+
+        assert!(!is_world_readable(&std::fs::metadata(private).unwrap()));
+        assert!(is_world_readable(&std::fs::metadata(public).unwrap()));
+    }
+
+    // The scripts are run through sh
+    #[cfg(unix)]
+    #[test]
+    fn test_exec_provider() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = secret_file(dir.path(), "get-secret", "echo \"exec-secret-$1\"\n", 0o600);
+
+        // run through sh, executing a file just written may fail with ETXTBSY
+        let mut value = toml::Value::String(format!("@exec:/bin/sh {} prom", script));
+        resolve_toml(&mut value, "token").unwrap();
+        assert_eq!(value.as_str(), Some("exec-secret-prom"));
+
+        let failing = secret_file(dir.path(), "fail", "echo oops >&2\nexit 3\n", 0o600);
+        let err = exec(&format!("/bin/sh {}", failing), EXEC_TIMEOUT).unwrap_err();
+        assert!(err.contains("oops"), "{}", err);
+
+        // More than a pipe holds, on both outputs
+        let verbose = secret_file(
+            dir.path(),
+            "verbose",
+            "head -c 200000 /dev/zero | tr '\\0' e >&2\nhead -c 200000 /dev/zero | tr '\\0' o\n",
+            0o600,
+        );
+        let out = exec(&format!("/bin/sh {}", verbose), EXEC_TIMEOUT).unwrap();
+        assert_eq!(out.len(), 200000);
+        assert!(out.bytes().all(|b| b == b'o'));
+
+        let slow = secret_file(dir.path(), "slow", "sleep 5\n", 0o600);
+        let err = exec(&format!("/bin/sh {}", slow), Duration::from_millis(100)).unwrap_err();
+        assert!(err.contains("timed out"), "{}", err);
+    }
+
+    #[test]
+    fn test_env_composition() {
+        let dir = tempfile::tempdir().unwrap();
+        secret_file(dir.path(), "token", "env-secret-2", 0o600);
+
+        std::env::set_var("VOID_TEST_SECRET_DIR", dir.path());
+        let mut value = serde_json::json!("@file:{{env:VOID_TEST_SECRET_DIR}}/token");
+        resolve_json(&mut value, "").unwrap();
+        assert_eq!(value, "env-secret-2");
+
+        std::env::set_var(
+            "VOID_TEST_SECRET_REF",
+            format!("@file:{}/token", dir.path().display()),
+        );
+        let mut value = serde_json::json!("env:VOID_TEST_SECRET_REF");
+        resolve_json(&mut value, "").unwrap();
+        assert_eq!(value, "env-secret-2");
+    }
+
+    #[test]
+    fn test_resolution_failure_names_field() {
+        let mut value = serde_json::json!({
+            "outbounds": [
+                { "type": "stdio" },
+                { "auth": { "password": "@file:/nonexistent/void/secret" } }
+            ]
+        });
+
+        let err = resolve_json(&mut value, "").unwrap_err();
+        assert!(
+            err.to_string().contains("outbounds[1].auth.password"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_redacted_dump() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = secret_file(dir.path(), "password", "dump-secret-3", 0o600);
+
+        let mut value = serde_json::json!({
+            "inbounds": [],
+            "protocols": [],
+            "pipes": [],
+            "outbounds": [{
+                "type": "prometheus",
+                "address": "http://localhost:9090",
+                "inbounds": ["pipe:timeseries"],
+                "auth": { "type": "basic", "username": "admin", "password": format!("@file:{}", path) }
+            }]
+        });
+        resolve_json(&mut value, "").unwrap();
+        let config: Config = serde_json::from_value(value).unwrap();
+
+        let dump = config.effective_config().unwrap();
+        assert!(!dump.contains("dump-secret-3"), "{}", dump);
+        assert!(dump.contains(REDACTED));
+        assert_eq!(redact("password=dump-secret-3"), "password=<redacted>");
+    }
+}
@@ -80,7 +80,7 @@ where
     }
 }
 
-fn fill(st: &str) -> Option<String> {
+pub(crate) fn fill(st: &str) -> Option<String> {
     let mut filled_string = st.to_string();
 
     // 创建正则表达式来匹配模板标记
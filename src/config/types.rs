@@ -0,0 +1,564 @@
+/*
+Human-friendly config values:
+    - DurationValue: "250ms", "2h30m", "1.5s", units ns, us (µs), ms, s, m, h, d. A bare number
+      is read as seconds, with a deprecation warning since the unit is ambiguous.
+    - ByteSize: "512MiB", "1.5GiB", "64KB", units B, KB, MB, GB, TB (powers of 1000) and
+      KiB, MiB, GiB, TiB (powers of 1024), case-insensitive. A bare number is a number of bytes.
+
+Both are written back in the same form by the effective-config dump, so the dump can be
+loaded again.
+*/
+
+use std::{fmt::Display, ops::Deref, str::FromStr, time::Duration};
+
+use log::warn;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use super::Error;
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+const DURATION_UNITS: &[(&str, u128)] = &[
+    ("d", 24 * 3600 * NANOS_PER_SEC),
+    ("h", 3600 * NANOS_PER_SEC),
+    ("m", 60 * NANOS_PER_SEC),
+    ("s", NANOS_PER_SEC),
+    ("ms", 1_000_000),
+    ("us", 1_000),
+    ("µs", 1_000),
+    ("ns", 1),
+];
+
+// Units used by Display, the largest first
+const DURATION_DISPLAY_UNITS: &[(&str, u128)] = &[
+    ("h", 3600 * NANOS_PER_SEC),
+    ("m", 60 * NANOS_PER_SEC),
+    ("s", NANOS_PER_SEC),
+    ("ms", 1_000_000),
+    ("us", 1_000),
+    ("ns", 1),
+];
+
+const BYTE_UNITS: &[(&str, u128)] = &[
+    ("b", 1),
+    ("kb", 1_000),
+    ("mb", 1_000_000),
+    ("gb", 1_000_000_000),
+    ("tb", 1_000_000_000_000),
+    ("kib", 1 << 10),
+    ("mib", 1 << 20),
+    ("gib", 1 << 30),
+    ("tib", 1 << 40),
+];
+
+// Units used by Display, binary ones are preferred
+const BYTE_DISPLAY_UNITS: &[(&str, u64)] = &[
+    ("TiB", 1 << 40),
+    ("GiB", 1 << 30),
+    ("MiB", 1 << 20),
+    ("KiB", 1 << 10),
+    ("TB", 1_000_000_000_000),
+    ("GB", 1_000_000_000),
+    ("MB", 1_000_000),
+    ("KB", 1_000),
+];
+
+/// Parse a decimal number such as `1.5` scaled by `unit`, rounded down.
+fn scale_decimal(number: &str, unit: u128) -> Result<u128, String> {
+    let (int, frac) = number.split_once('.').unwrap_or((number, ""));
+    let is_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    if (int.is_empty() && frac.is_empty()) || !is_digits(int) || !is_digits(frac) {
+        return Err(format!("invalid number {:?}", number));
+    }
+
+    let overflow = || format!("{} is too large", number);
+    let int = if int.is_empty() {
+        0
+    } else {
+        int.parse::<u128>().map_err(|_| overflow())?
+    };
+    let mut value = int.checked_mul(unit).ok_or_else(overflow)?;
+
+    // digits past the 18th do not matter at the resolution of any unit
+    let frac = &frac[..frac.len().min(18)];
+    if !frac.is_empty() {
+        let numerator = frac.parse::<u128>().unwrap() * unit;
+        value = value
+            .checked_add(numerator / 10u128.pow(frac.len() as u32))
+            .ok_or_else(overflow)?;
+    }
+
+    Ok(value)
+}
+
+/// Split `s` into its `<number><unit>` parts.
+fn split_parts(s: &str) -> Result<Vec<(&str, &str)>, String> {
+    let mut parts = Vec::new();
+    let mut rest = s;
+    while !rest.is_empty() {
+        let number_end = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let (number, tail) = rest.split_at(number_end);
+        if number.is_empty() {
+            return Err(format!("expected a number at {:?}", rest));
+        }
+
+        let tail = tail.trim_start();
+        let unit_end = tail
+            .find(|c: char| c.is_ascii_digit() || c == '.' || c.is_whitespace())
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_end);
+
+        parts.push((number, unit));
+        rest = tail.trim_start();
+    }
+
+    if parts.is_empty() {
+        return Err("empty value".to_string());
+    }
+    Ok(parts)
+}
+
+/// A duration in the config, see the module documentation for the accepted forms.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DurationValue(Duration);
+
+impl DurationValue {
+    pub const fn from_secs(secs: u64) -> Self {
+        Self(Duration::from_secs(secs))
+    }
+
+    pub const fn from_millis(millis: u64) -> Self {
+        Self(Duration::from_millis(millis))
+    }
+
+    pub const fn get(self) -> Duration {
+        self.0
+    }
+
+    /// Fails with [`Error::ZeroValue`] for a zero duration.
+    pub fn ensure_non_zero(self, owner: impl Display, field: &'static str) -> super::Result<()> {
+        if self.0.is_zero() {
+            return Err(Error::ZeroValue(owner.to_string(), field));
+        }
+        Ok(())
+    }
+
+    fn from_bare_secs(secs: f64) -> Result<Self, String> {
+        if !secs.is_finite() || secs < 0.0 {
+            return Err(format!("invalid duration {}", secs));
+        }
+        let value = Duration::try_from_secs_f64(secs).map_err(|e| e.to_string())?;
+        let value = Self(value);
+
+        warn!(
+            "A bare number as a duration is deprecated, {} is read as seconds: write \"{}\" instead",
+            secs, value
+        );
+        Ok(value)
+    }
+}
+
+impl FromStr for DurationValue {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        // "0" is the only number which does not need a unit
+        if s == "0" {
+            return Ok(Self::default());
+        }
+
+        let mut nanos: u128 = 0;
+        for (number, unit) in split_parts(s)? {
+            if unit.is_empty() {
+                return Err(format!("missing unit after {:?} in {:?}", number, s));
+            }
+
+            let (_, unit_nanos) = DURATION_UNITS
+                .iter()
+                .find(|(name, _)| *name == unit)
+                .ok_or_else(|| format!("unknown duration unit {:?} in {:?}", unit, s))?;
+
+            nanos = scale_decimal(number, *unit_nanos)?
+                .checked_add(nanos)
+                .ok_or_else(|| format!("{} is too large", s))?;
+        }
+
+        let nanos = u64::try_from(nanos).map_err(|_| format!("{} is too large", s))?;
+        Ok(Self(Duration::from_nanos(nanos)))
+    }
+}
+
+impl Display for DurationValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut nanos = self.0.as_nanos();
+        if nanos == 0 {
+            return write!(f, "0s");
+        }
+
+        for (unit, unit_nanos) in DURATION_DISPLAY_UNITS {
+            if nanos >= *unit_nanos {
+                write!(f, "{}{}", nanos / unit_nanos, unit)?;
+                nanos %= unit_nanos;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Deref for DurationValue {
+    type Target = Duration;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl From<Duration> for DurationValue {
+    fn from(value: Duration) -> Self {
+        Self(value)
+    }
+}
+
+impl From<DurationValue> for Duration {
+    fn from(value: DurationValue) -> Self {
+        value.0
+    }
+}
+
+impl Serialize for DurationValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for DurationValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        // The form written by older versions for some fields, `{ secs = 5, nanos = 0 }`
+        #[derive(Deserialize)]
+        struct Legacy {
+            secs: u64,
+            nanos: u32,
+        }
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = DurationValue;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "a duration such as \"250ms\" or \"2h30m\"")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                v.parse().map_err(E::custom)
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                DurationValue::from_bare_secs(v as f64).map_err(E::custom)
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                DurationValue::from_bare_secs(v as f64).map_err(E::custom)
+            }
+
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<Self::Value, E> {
+                DurationValue::from_bare_secs(v).map_err(E::custom)
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                let legacy = Legacy::deserialize(de::value::MapAccessDeserializer::new(map))?;
+                // As many nanoseconds as the other forms take
+                legacy
+                    .secs
+                    .checked_mul(1_000_000_000)
+                    .and_then(|nanos| nanos.checked_add(legacy.nanos.into()))
+                    .map(|nanos| DurationValue(Duration::from_nanos(nanos)))
+                    .ok_or_else(|| {
+                        de::Error::custom(format!(
+                            "duration {{ secs = {}, nanos = {} }} is too long",
+                            legacy.secs, legacy.nanos
+                        ))
+                    })
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+/// A size in bytes in the config, see the module documentation for the accepted forms.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ByteSize(u64);
+
+impl ByteSize {
+    pub const fn get(self) -> u64 {
+        self.0
+    }
+
+    /// Fails with [`Error::ZeroValue`] for a zero size.
+    pub fn ensure_non_zero(self, owner: impl Display, field: &'static str) -> super::Result<()> {
+        if self.0 == 0 {
+            return Err(Error::ZeroValue(owner.to_string(), field));
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ByteSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let parts = split_parts(s)?;
+        let [(number, unit)] = parts.as_slice() else {
+            return Err(format!("invalid size {:?}", s));
+        };
+
+        let unit = unit.to_ascii_lowercase();
+        let unit_bytes = match unit.as_str() {
+            "" => 1,
+            unit => BYTE_UNITS
+                .iter()
+                .find(|(name, _)| *name == unit)
+                .map(|(_, bytes)| *bytes)
+                .ok_or_else(|| format!("unknown size unit {:?} in {:?}", unit, s))?,
+        };
+
+        let bytes = scale_decimal(number, unit_bytes)?;
+        let bytes = u64::try_from(bytes).map_err(|_| format!("{} is too large", s))?;
+        Ok(Self(bytes))
+    }
+}
+
+impl Display for ByteSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let unit = BYTE_DISPLAY_UNITS
+            .iter()
+            .find(|(_, bytes)| self.0 != 0 && self.0.is_multiple_of(*bytes));
+
+        match unit {
+            Some((unit, bytes)) => write!(f, "{}{}", self.0 / bytes, unit),
+            None => write!(f, "{}B", self.0),
+        }
+    }
+}
+
+impl From<u64> for ByteSize {
+    fn from(value: u64) -> Self {
+        Self(value)
+    }
+}
+
+impl Serialize for ByteSize {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ByteSize {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = ByteSize;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "a size such as 4096 or \"512MiB\"")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                v.parse().map_err(E::custom)
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                Ok(ByteSize(v))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                u64::try_from(v)
+                    .map(ByteSize)
+                    .map_err(|_| E::custom(format!("invalid size {}", v)))
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn duration(s: &str) -> Result<Duration, String> {
+        s.parse::<DurationValue>().map(Duration::from)
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(duration("2h30m"), Ok(Duration::from_secs(9000)));
+        assert_eq!(duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(duration(".5m"), Ok(Duration::from_secs(30)));
+        assert_eq!(duration("1d"), Ok(Duration::from_secs(86400)));
+        assert_eq!(duration("10us"), Ok(Duration::from_micros(10)));
+        assert_eq!(duration("10µs"), Ok(Duration::from_micros(10)));
+        assert_eq!(duration("1s 500ms"), Ok(Duration::from_millis(1500)));
+        assert_eq!(duration("0"), Ok(Duration::ZERO));
+        assert_eq!(duration("0ms"), Ok(Duration::ZERO));
+        // digits beyond a nanosecond are dropped
+        assert_eq!(duration("0.0000000019s"), Ok(Duration::from_nanos(1)));
+
+        for invalid in ["", "5", "1.5", "ms", "5 parsecs", "1..5s", "-5s", "1s5"] {
+            assert!(duration(invalid).is_err(), "{:?}", invalid);
+        }
+
+        // u64 nanoseconds is about 584 years
+        assert!(duration("213503d").is_ok());
+        assert!(duration("213504d").is_err());
+        assert!(duration("99999999999999999999999999999999999999999h").is_err());
+    }
+
+    #[test]
+    fn test_parse_byte_size() {
+        let size = |s: &str| s.parse::<ByteSize>().map(ByteSize::get);
+        assert_eq!(size("512MiB"), Ok(512 << 20));
+        assert_eq!(size("1.5GiB"), Ok(3 << 29));
+        assert_eq!(size("64KB"), Ok(64_000));
+        assert_eq!(size("64kb"), Ok(64_000));
+        assert_eq!(size("10 MiB"), Ok(10 << 20));
+        assert_eq!(size("4096"), Ok(4096));
+        assert_eq!(size("0"), Ok(0));
+        assert_eq!(size("1.5B"), Ok(1));
+
+        for invalid in ["", "MiB", "1 parsec", "1MiB2", "-1", "99999999TiB"] {
+            assert!(size(invalid).is_err(), "{:?}", invalid);
+        }
+        assert_eq!(size("16777215TiB"), Ok(16777215 << 40));
+        assert!(size("16777216TiB").is_err());
+    }
+
+    #[test]
+    fn test_bare_numbers() {
+        #[derive(Debug, Deserialize)]
+        struct Values {
+            timeout: DurationValue,
+            size: ByteSize,
+        }
+
+        let values: Values = toml::from_str("timeout = 5\nsize = 1024").unwrap();
+        assert_eq!(values.timeout.get(), Duration::from_secs(5));
+        assert_eq!(values.size.get(), 1024);
+
+        let values: Values = serde_json::from_str(r#"{"timeout": 0.25, "size": 1}"#).unwrap();
+        assert_eq!(values.timeout.get(), Duration::from_millis(250));
+
+        assert!(toml::from_str::<Values>("timeout = -5\nsize = 1").is_err());
+        assert!(toml::from_str::<Values>("timeout = 5\nsize = -1").is_err());
+
+        // the form written by the older versions
+        let values: Values =
+            toml::from_str("timeout = { secs = 1, nanos = 5000000 }\nsize = 1").unwrap();
+        assert_eq!(values.timeout.get(), Duration::from_millis(1005));
+        assert!(toml::from_str::<Values>(&format!(
+            "timeout = {{ secs = {}, nanos = 999999999 }}\nsize = 1",
+            i64::MAX
+        ))
+        .is_err());
+        assert!(toml::from_str::<Values>(
+            "timeout = { secs = 18446744073, nanos = 999999999 }\nsize = 1"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let durations = [
+            Duration::ZERO,
+            Duration::from_millis(250),
+            Duration::from_secs(9000),
+            Duration::from_millis(1500),
+            Duration::from_nanos(3_600_000_000_001),
+            Duration::from_secs(86400 * 3),
+        ];
+        for d in durations {
+            let value = DurationValue::from(d);
+            assert_eq!(value.to_string().parse::<DurationValue>(), Ok(value));
+        }
+        assert_eq!(DurationValue::from_secs(9000).to_string(), "2h30m");
+        assert_eq!(DurationValue::from_millis(1500).to_string(), "1s500ms");
+
+        for bytes in [0, 1, 1000, 1024, 1500, 3 << 29, 512 << 20, u64::MAX] {
+            let size = ByteSize::from(bytes);
+            assert_eq!(size.to_string().parse::<ByteSize>(), Ok(size));
+        }
+        assert_eq!(ByteSize::from(512 << 20).to_string(), "512MiB");
+        assert_eq!(ByteSize::from(1500).to_string(), "1500B");
+        assert_eq!(ByteSize::from(64_000).to_string(), "64KB");
+    }
+
+    #[test]
+    fn test_non_zero() {
+        assert!(DurationValue::default()
+            .ensure_non_zero("pipe:timeseries", "recv_timeout")
+            .is_err());
+        assert!(DurationValue::from_millis(1)
+            .ensure_non_zero("pipe:timeseries", "recv_timeout")
+            .is_ok());
+
+        let err = ByteSize::from(0)
+            .ensure_non_zero("pipe:timeseries", "warn_record_bytes")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "pipe:timeseries.warn_record_bytes must not be zero"
+        );
+    }
+
+    #[test]
+    fn test_effective_config_round_trip() {
+        let config: super::super::Config = toml::from_str(
+            r#"
+            inbounds = []
+            protocols = []
+
+            [global.max_poll_duration]
+            pipe = "150ms"
+
+            [[pipes]]
+            type = "timeseries"
+            inbounds = ["inbound:data"]
+            labels = ["host"]
+            recv_timeout = { secs = 0, nanos = 10000000 }
+            warn_record_bytes = "1.5MiB"
+            enforce_record_bytes = 4096
+
+            [[outbounds]]
+            type = "prometheus"
+            address = "http://localhost:9090"
+            inbounds = ["pipe:timeseries"]
+            recv_timeout = "2s"
+            retry = { initial_delay = "250ms", max_delay = "1m30s" }
+            "#,
+        )
+        .unwrap();
+
+        let dump = config.effective_config().unwrap();
+        for expected in [
+            r#""pipe": "150ms""#,
+            r#""outbound": "500ms""#,
+            r#""recv_timeout": "10ms""#,
+            r#""warn_record_bytes": "1536KiB""#,
+            r#""enforce_record_bytes": "4KiB""#,
+            r#""recv_timeout": "2s""#,
+            r#""max_delay": "1m30s""#,
+        ] {
+            assert!(dump.contains(expected), "{} not in {}", expected, dump);
+        }
+
+        let reloaded: super::super::Config = serde_json::from_str(&dump).unwrap();
+        assert_eq!(reloaded.effective_config().unwrap(), dump);
+    }
+}
@@ -1,10 +1,16 @@
+use std::panic::AssertUnwindSafe;
+
 use async_trait::async_trait;
-use log::{error, info};
+use futures::FutureExt;
 use miette::Diagnostic;
 use tokio::task::JoinHandle;
 use tokio_util::sync::CancellationToken;
 
-use super::tag::HasTag;
+use super::{metrics, tag::HasTag};
+use crate::{
+    actor_debug, actor_error, actor_info, actor_warn, config::global::drain_timeout,
+    utils::liveness::Heartbeat,
+};
 
 mod error;
 
@@ -14,42 +20,93 @@ pub use error::Error;
 pub trait Actor: HasTag + Send + 'static {
     type Error: Send + Sync + Diagnostic + 'static;
     async fn poll(&mut self, ctx: CancellationToken) -> miette::Result<(), Self::Error>;
+
+    /// Whether records received by a previous poll are still held, e.g. carried over once the
+    /// poll budget ran out. The shutdown drain waits until none are.
+    fn has_pending(&self) -> bool {
+        false
+    }
 }
 
-pub fn spawn<T, Error>(actor: Box<T>, ctx: CancellationToken) -> JoinHandle<()>
+/// Spawn the poll loop of an actor. With a heartbeat, the actor is watched for progress and
+/// beats once per completed poll.
+///
+/// The loop ends when cancelled, or when a poll panics: the actor is then handed back, so that
+/// the caller can decide whether to spawn it again.
+pub fn spawn<T, Error>(
+    actor: Box<T>,
+    ctx: CancellationToken,
+    heartbeat: Option<Heartbeat>,
+) -> JoinHandle<Option<Box<T>>>
 where
     T: Actor<Error = Error> + Send + ?Sized + 'static,
     Error: Send + Sync + Diagnostic + 'static,
 {
     let tag = actor.tag().clone();
+    let metrics = metrics::actor_metrics(&tag);
 
     let mut actor = actor;
 
     tokio::task::Builder::new()
         .name(&tag.to_string())
         .spawn(async move {
-            let actor = actor.as_mut();
-
             loop {
                 let poll_start = std::time::Instant::now();
+                let mut poll = AssertUnwindSafe(actor.poll(ctx.clone())).catch_unwind();
+                let mut panicked = false;
 
                 tokio::select! {
-                    _ = ctx.cancelled() => {
-                        info!("{}: cancelled", tag);
-                        return;
-                    }
-                    r = actor.poll(ctx.clone()) => match r {
-                        Ok(()) => {}
-                        Err(err) => {
+                    // Poll first, so that an actor noticing the cancellation gets to flush
+                    biased;
+                    r = &mut poll => match r {
+                        Err(panic) => {
+                            metrics.count_poll_error();
+                            actor_error!(tag, "panicked: {}", panic_message(&*panic));
+                            panicked = true;
+                        }
+                        Ok(Ok(())) => {}
+                        // Errors such as `Canceled` are expected while shutting down
+                        Ok(Err(err)) if ctx.is_cancelled() => {
+                            let report = miette::Report::new(err);
+                            actor_debug!(tag, "error while cancelled: {:?}", report);
+                        }
+                        Ok(Err(err)) => {
+                            metrics.count_poll_error();
                             let report = miette::Report::new(err);
-                            error!("{}: error: {:?}", tag, report);
+                            actor_error!(tag, "error: {:?}", report);
                         },
+                    },
+                    _ = ctx.cancelled() => {
+                        // A flush taking more than one poll, e.g. waiting for the requests in
+                        // flight, is given up to the drain timeout
+                        if tokio::time::timeout(drain_timeout().get(), poll).await.is_err() {
+                            actor_warn!(tag, "did not stop within the drain timeout");
+                        }
+                        actor_info!(tag, "cancelled");
+                        return None;
                     }
                 }
+                drop(poll);
+
+                if panicked {
+                    return Some(actor);
+                }
+
+                // The poll which noticed the cancellation was the last one
+                if ctx.is_cancelled() {
+                    actor_info!(tag, "cancelled");
+                    return None;
+                }
+
+                metrics.mark_polled();
+                if let Some(heartbeat) = &heartbeat {
+                    heartbeat.set_pending(actor.has_pending());
+                    heartbeat.beat();
+                }
 
                 let poll_elapsed = poll_start.elapsed();
                 if poll_elapsed > std::time::Duration::from_millis(200) {
-                    info!("{}: poll took {:?}", tag, poll_elapsed);
+                    actor_info!(tag, "poll took {:?}", poll_elapsed);
                 }
 
                 // Yield to allow other tasks to run
@@ -58,3 +115,95 @@ where
         })
         .expect("Failed to spawn actor")
 }
+
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::core::{
+        pipe,
+        tag::{PipeTagId, TagId},
+    };
+
+    /// A pipe which flushes once cancelled, and fails doing so if `fail` is set
+    struct FlushingPipe {
+        tag: TagId,
+        fail: bool,
+        flushes: Arc<AtomicUsize>,
+    }
+
+    impl HasTag for FlushingPipe {
+        fn tag(&self) -> &TagId {
+            &self.tag
+        }
+    }
+
+    #[async_trait]
+    impl Actor for FlushingPipe {
+        type Error = pipe::Error;
+
+        async fn poll(&mut self, ctx: CancellationToken) -> pipe::Result<()> {
+            ctx.cancelled().await;
+            self.flushes.fetch_add(1, Ordering::Relaxed);
+            if self.fail {
+                return Err(pipe::Error::InvalidRecord("channel closed".into()));
+            }
+            Ok(())
+        }
+    }
+
+    async fn run(name: &str, fail: bool) -> (TagId, usize) {
+        let tag: TagId = PipeTagId::new(name).into();
+        let flushes = Arc::new(AtomicUsize::new(0));
+        let ctx = CancellationToken::new();
+        let handle = spawn(
+            Box::new(FlushingPipe {
+                tag: tag.clone(),
+                fail,
+                flushes: flushes.clone(),
+            }),
+            ctx.clone(),
+            None,
+        );
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        ctx.cancel();
+        let actor = tokio::time::timeout(Duration::from_secs(5), handle)
+            .await
+            .expect("not stopped")
+            .unwrap();
+        assert!(actor.is_none());
+        (tag, flushes.load(Ordering::Relaxed))
+    }
+
+    #[tokio::test]
+    async fn test_single_poll_after_cancel() {
+        // The poll noticing the cancellation completes, and is not followed by another one
+        let (_, flushes) = run("flushing", false).await;
+        assert_eq!(flushes, 1);
+    }
+
+    #[tokio::test]
+    async fn test_error_while_cancelled() {
+        let (tag, flushes) = run("failing_flush", true).await;
+        assert_eq!(flushes, 1);
+        assert_eq!(metrics::actor_metrics(&tag).snapshot().poll_errors, 0);
+    }
+}
@@ -0,0 +1,95 @@
+use std::fmt::Display;
+
+use super::{
+    manager::{SendError, TaggedSender},
+    tag::TagId,
+    types::{Attribute, Record, Value},
+};
+use crate::{actor_debug, actor_warn};
+
+/// Where a pipe or an outbound sends the records it fails on, see `global.dead_letter`.
+/// Without it the records are dropped.
+#[derive(Debug, Clone)]
+pub struct DeadLetter {
+    who: TagId,
+    sender: Option<TaggedSender>,
+}
+
+impl DeadLetter {
+    pub fn new(who: TagId, sender: Option<TaggedSender>) -> Self {
+        Self { who, sender }
+    }
+
+    /// Callers keep a copy of the records they may fail on only when this is true.
+    pub fn is_enabled(&self) -> bool {
+        self.sender.is_some()
+    }
+
+    /// Send `record` with the error it failed on. A record which is already a dead letter is
+    /// dropped, so that a failing consumer of the dead letters does not loop.
+    pub fn send(&mut self, mut record: Record, error: &dyn Display) {
+        let Some(ref mut sender) = self.sender else {
+            return;
+        };
+
+        if record.get_attribute(&Attribute::Error).is_some() {
+            actor_debug!(self.who, "dropped a dead letter failing again: {}", error);
+            return;
+        }
+
+        record.set_attribute(Attribute::Error, Value::from(error.to_string()));
+        record.set_attribute(Attribute::FailedBy, (&self.who).into());
+        record.set_attribute(Attribute::FailedAt, Value::from(chrono::Utc::now()));
+        // Never waits, the callers may not await. A full channel has already logged the drop
+        if let Err(e @ SendError::Closed(_)) = sender.try_send(record) {
+            actor_warn!(self.who, "failed to send a dead letter: {}", e);
+        }
+    }
+
+    pub fn send_all(&mut self, records: Vec<Record>, error: &dyn Display) {
+        for record in records {
+            self.send(record, error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{manager::ActorChannel, tag::INTERNAL_TAG_SCOPE, types::Symbol};
+
+    #[tokio::test]
+    async fn test_send() {
+        let mut channel = ActorChannel::new(TagId::new(INTERNAL_TAG_SCOPE, "dead_letter"), 1);
+        let who = TagId::new("pipe", "timeseries");
+        let mut dead_letter = DeadLetter::new(who.clone(), Some(channel.sender()));
+        let mut receiver = channel.receiver(&TagId::new("outbound", "file"));
+
+        let mut record = Record::empty();
+        record.set(Symbol::new("name"), Value::from("cpu"));
+        dead_letter.send(record, &"invalid metric name");
+
+        let record = receiver.recv().await.unwrap();
+        assert_eq!(record.get(&Symbol::new("name")), Some(&Value::from("cpu")));
+        assert_eq!(
+            record.get_attribute(&Attribute::Error),
+            Some(&Value::from("invalid metric name"))
+        );
+        assert_eq!(
+            record.get_attribute(&Attribute::FailedBy),
+            Some(&Value::from("pipe:timeseries"))
+        );
+        assert!(record
+            .get_attribute(&Attribute::FailedAt)
+            .unwrap()
+            .is_datetime());
+
+        // Failing again drops it
+        dead_letter.send(record, &"again");
+        assert!(receiver.try_recv().is_err());
+
+        let mut disabled = DeadLetter::new(who, None);
+        assert!(!disabled.is_enabled());
+        disabled.send(Record::empty(), &"dropped");
+    }
+}
@@ -0,0 +1,108 @@
+use std::sync::Arc;
+
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    actor_info, actor_warn, config::inbound::accept::AcceptThrottleConfig,
+    core::manager::TaggedSender, core::tag::TagId,
+};
+
+/// Tells how saturated the downstream of an inbound is, from 0.0 (idle) to 1.0 (full).
+pub type SaturationProbe = Arc<dyn Fn() -> f64 + Send + Sync>;
+
+/// Decides whether an inbound may accept new connections, see [`AcceptThrottleConfig`].
+pub struct AcceptThrottle {
+    tag: TagId,
+    cfg: AcceptThrottleConfig,
+    probe: SaturationProbe,
+    paused: bool,
+}
+
+impl AcceptThrottle {
+    pub fn new(tag: TagId, cfg: AcceptThrottleConfig, probe: SaturationProbe) -> Self {
+        actor_info!(tag, "accept throttle {{ {} }}", cfg);
+        Self {
+            tag,
+            cfg,
+            probe,
+            paused: false,
+        }
+    }
+
+    /// Throttle on the occupancy of the outbound channel of the inbound.
+    pub fn on_channel(tag: TagId, cfg: AcceptThrottleConfig, outbound: TaggedSender) -> Self {
+        Self::new(tag, cfg, Arc::new(move || outbound.occupancy()))
+    }
+
+    fn update(&mut self, saturation: f64) -> bool {
+        if !self.paused && saturation >= self.cfg.accept_pause_threshold {
+            self.paused = true;
+            actor_warn!(
+                self.tag,
+                "pipeline saturated ({:.0}%), pause accepting new connections",
+                saturation * 100.0
+            );
+        } else if self.paused && saturation <= self.cfg.resume_threshold {
+            self.paused = false;
+            actor_info!(
+                self.tag,
+                "pipeline drained ({:.0}%), resume accepting new connections",
+                saturation * 100.0
+            );
+        }
+
+        self.paused
+    }
+
+    /// Wait until new connections may be accepted, returns false if cancelled meanwhile.
+    pub async fn ready(&mut self, ctx: &CancellationToken) -> bool {
+        while self.update((self.probe)()) {
+            tokio::select! {
+                _ = ctx.cancelled() => return false,
+                _ = tokio::time::sleep(*self.cfg.check_interval) => {}
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use super::*;
+    use crate::core::tag::InboundTagId;
+
+    #[tokio::test]
+    async fn test_hysteresis() {
+        let saturation = Arc::new(AtomicU64::new(0));
+        let probe = {
+            let saturation = saturation.clone();
+            Arc::new(move || f64::from_bits(saturation.load(Ordering::Relaxed)))
+        };
+        let set = |value: f64| saturation.store(value.to_bits(), Ordering::Relaxed);
+
+        let mut throttle = AcceptThrottle::new(
+            InboundTagId::new("unix_socket").into(),
+            AcceptThrottleConfig::default(),
+            probe,
+        );
+        let ctx = CancellationToken::new();
+
+        assert!(throttle.ready(&ctx).await);
+
+        set(0.95);
+        assert!(throttle.update(0.95));
+        // still above the resume threshold
+        assert!(throttle.update(0.7));
+        assert!(!throttle.update(0.5));
+        // below the pause threshold, not paused again
+        assert!(!throttle.update(0.7));
+
+        set(1.0);
+        ctx.cancel();
+        assert!(!throttle.ready(&ctx).await);
+        assert!(throttle.paused);
+    }
+}
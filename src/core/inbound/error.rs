@@ -8,8 +8,13 @@ pub enum Error {
     #[error(transparent)]
     #[diagnostic(transparent)]
     Protocol(#[from] crate::core::protocol::Error),
+    #[cfg(unix)]
     #[error(transparent)]
     Nix(#[from] nix::Error),
+    // Only the unix socket and the named pipe inbounds depend on the platform
+    #[cfg(not(unix))]
+    #[error("inbound type {0} is not supported on this platform")]
+    Unsupported(&'static str),
 }
 
 pub type Result<T> = miette::Result<T, Error>;
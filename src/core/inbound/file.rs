@@ -0,0 +1,149 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    actor_info,
+    config::{inbound::file::FileInboundConfig, ProtocolConfig},
+    core::{
+        actor::Actor,
+        inbound::instance::{InstanceOptions, ReaderBasedInstance},
+        manager::{ChannelGraph, TaggedSender},
+        protocol,
+        tag::{HasTag, TagId},
+    },
+};
+
+use super::base::Inbound;
+use super::error::Result;
+
+pub(crate) struct FileInbound {
+    tag: TagId,
+    path: PathBuf,
+    bulk_mode: bool,
+
+    handle: Option<JoinHandle<()>>,
+    started: bool,
+
+    outbound: TaggedSender,
+    protocol: ProtocolConfig,
+    options: InstanceOptions,
+}
+
+impl FileInbound {
+    pub fn try_create_from(
+        cfg: FileInboundConfig,
+        protocol_cfg: ProtocolConfig,
+        channel_graph: &mut ChannelGraph,
+    ) -> Result<Self> {
+        let tag = cfg.tag.into();
+        let outbound = channel_graph.sender(&tag);
+
+        Ok(FileInbound {
+            tag,
+            path: cfg.path,
+            bulk_mode: cfg.bulk_mode,
+            handle: None,
+            started: false,
+            outbound,
+            protocol: protocol_cfg,
+            options: InstanceOptions {
+                timestamp_bounds: cfg.timestamp_bounds,
+                lifecycle_events: cfg.emit_lifecycle_events,
+                quiet_eof: false,
+            },
+        })
+    }
+
+    /// The bulk parser, if enabled and usable for this input.
+    fn bulk_parser(
+        &self,
+        file: &std::fs::File,
+    ) -> Result<Option<Box<dyn protocol::ProtocolParser>>> {
+        if !self.bulk_mode {
+            return Ok(None);
+        }
+
+        // 只有普通文件可以 mmap, 管道等不可 seek 的输入回退到流式读取
+        if !file.metadata()?.is_file() {
+            actor_info!(
+                self.tag,
+                "{:?} is not a regular file, bulk mode disabled",
+                self.path
+            );
+            return Ok(None);
+        }
+
+        let parser = protocol::try_create_bulk_from(file, self.protocol.clone())?;
+        if parser.is_none() {
+            actor_info!(
+                self.tag,
+                "protocol has no bulk mode, reading {:?} as a stream",
+                self.path
+            );
+        }
+
+        Ok(parser)
+    }
+
+    fn start(&mut self, ctx: CancellationToken) -> Result<()> {
+        let file = std::fs::File::open(&self.path)?;
+        let id = self.path.display().to_string();
+
+        let handle = match self.bulk_parser(&file)? {
+            Some(parser) => ReaderBasedInstance::spawn_from_parser(
+                self.tag.clone(),
+                id,
+                parser,
+                self.outbound.clone(),
+                self.options.clone(),
+                ctx,
+            ),
+            None => ReaderBasedInstance::try_create_from(
+                self.tag.clone(),
+                id,
+                tokio::fs::File::from_std(file),
+                self.protocol.clone(),
+                self.outbound.clone(),
+                self.options.clone(),
+                ctx,
+            )?,
+        };
+
+        actor_info!(self.tag, "reading {:?}", self.path);
+
+        self.handle = Some(handle);
+        Ok(())
+    }
+}
+
+impl HasTag for FileInbound {
+    fn tag(&self) -> &TagId {
+        &self.tag
+    }
+}
+
+#[async_trait]
+impl Actor for FileInbound {
+    type Error = super::Error;
+    async fn poll(&mut self, ctx: CancellationToken) -> miette::Result<(), super::Error> {
+        if !self.started {
+            self.started = true;
+            self.start(ctx.clone())?;
+        }
+
+        // The file is read only once
+        if let Some(handle) = self.handle.as_mut() {
+            let _ = handle.await;
+            self.handle = None;
+            actor_info!(self.tag, "finished reading {:?}", self.path);
+        }
+
+        ctx.cancelled().await;
+        Ok(())
+    }
+}
+
+impl Inbound for FileInbound {}
@@ -1,23 +1,90 @@
-use log::{error, warn};
+use std::io::ErrorKind;
+
+use futures::FutureExt;
+use once_cell::sync::Lazy;
 use tokio::{io::AsyncRead, task::JoinHandle};
 use tokio_util::sync::CancellationToken;
 
-use crate::core::types::Attribute;
+use super::timestamp::{TimestampGuard, Verdict};
+
+use crate::core::types::{Attribute, Record, Symbol, Value};
 use crate::{
-    config::ProtocolConfig,
+    actor_debug, actor_error, actor_info, actor_warn,
+    config::{inbound::timestamp::TimestampBoundsConfig, ProtocolConfig},
     core::{
-        manager::TaggedSender,
+        manager::{SendError, TaggedSender},
+        metrics,
         protocol::{self, ProtocolParser},
         tag::TagId,
     },
+    utils::stats,
 };
 
+/// The `__type__` of the records sent when a connection opens and closes.
+pub static RECORD_TYPE_LIFECYCLE_VALUE: Lazy<Value> = Lazy::new(|| Value::from("LifecycleEvent"));
+
+/// What an instance does besides parsing, set per inbound.
+#[derive(Debug, Clone, Default)]
+pub struct InstanceOptions {
+    pub timestamp_bounds: Option<TimestampBoundsConfig>,
+    // Send a `LifecycleEvent` record once the instance starts reading, and another one with
+    // its counts once it stops
+    pub lifecycle_events: bool,
+    // Log the end of the input at debug level, for a named pipe whose writers come and go
+    pub quiet_eof: bool,
+}
+
+/// Counted over the life of an instance, for its closing `LifecycleEvent`.
+#[derive(Debug, Default)]
+struct InstanceCounts {
+    records: u64,
+    parse_errors: u64,
+}
+
+/// A `LifecycleEvent` record: `event` is `open` or `close`, only the latter carries the
+/// counts and the `reason`.
+fn lifecycle_record(tag: &TagId, peer: &str, closed: Option<(&InstanceCounts, &str)>) -> Record {
+    let mut record = Record::empty();
+    let event = if closed.is_some() { "close" } else { "open" };
+    record.set(Symbol::new("event"), Value::from(event));
+    record.set(Symbol::new("peer"), Value::from(peer));
+    if let Some((counts, reason)) = closed {
+        record.set(Symbol::new("records"), Value::from(counts.records as i64));
+        record.set(
+            Symbol::new("parse_errors"),
+            Value::from(counts.parse_errors as i64),
+        );
+        record.set(Symbol::new("reason"), Value::from(reason));
+    }
+    record.set_type(RECORD_TYPE_LIFECYCLE_VALUE.clone());
+    record.set_attribute(Attribute::Inbound, tag.into());
+    record.mark_received(tag);
+    record
+}
+
+/// Forget the connections already closed, so that a listener serving many short-lived ones
+/// keeps only the open ones around. The connections whose task panicked are logged.
+pub fn reap_connections(tag: &TagId, connections: &mut Vec<JoinHandle<()>>) {
+    connections.retain_mut(|handle| {
+        if !handle.is_finished() {
+            return true;
+        }
+        if let Some(Err(e)) = handle.now_or_never() {
+            if e.is_panic() {
+                actor_error!(tag, "connection task panicked: {}", e);
+            }
+        }
+        false
+    });
+}
+
 pub struct ReaderBasedInstance {
     tag: TagId,
     id: String,
 
     parser: Box<dyn ProtocolParser>,
     sender: TaggedSender,
+    options: InstanceOptions,
 
     ctx: CancellationToken,
 }
@@ -29,21 +96,34 @@ impl ReaderBasedInstance {
         reader: R,
         protocol: ProtocolConfig,
         sender: TaggedSender,
+        options: InstanceOptions,
         ctx: CancellationToken,
     ) -> super::Result<JoinHandle<()>> {
         let parser = protocol::try_create_from(reader, protocol)?;
 
+        Ok(Self::spawn_from_parser(
+            tag, id, parser, sender, options, ctx,
+        ))
+    }
+
+    pub fn spawn_from_parser(
+        tag: TagId,
+        id: String,
+        parser: Box<dyn ProtocolParser>,
+        sender: TaggedSender,
+        options: InstanceOptions,
+        ctx: CancellationToken,
+    ) -> JoinHandle<()> {
         let instance = ReaderBasedInstance {
             tag,
             id,
             parser,
             sender,
+            options,
             ctx,
         };
 
-        let handle = instance.spawn();
-
-        Ok(handle)
+        instance.spawn()
     }
 
     fn spawn(self) -> tokio::task::JoinHandle<()> {
@@ -54,36 +134,243 @@ impl ReaderBasedInstance {
             .spawn(async move {
                 let mut sender = self.sender;
                 let mut parser = self.parser;
+                let lifecycle_events = self.options.lifecycle_events;
+                let quiet_eof = self.options.quiet_eof;
+                let mut timestamp_guard = self
+                    .options
+                    .timestamp_bounds
+                    .map(|cfg| TimestampGuard::new(self.tag.clone(), name.clone(), cfg));
 
-                loop {
+                if lifecycle_events {
+                    let record = lifecycle_record(&self.tag, &self.id, None);
+                    if let Err(err @ SendError::Closed(_)) = sender.send(record).await {
+                        actor_error!(self.tag, "{} failed to send, err: {}", self.id, err);
+                        return;
+                    }
+                }
+
+                let mut counts = InstanceCounts::default();
+                let reason = loop {
                     let next_record = parser.read_next();
                     let cancelled = self.ctx.cancelled();
                     let mut record = tokio::select! {
                         // Instance has been dropped
-                        _ = cancelled => break,
+                        _ = cancelled => break "cancelled".to_string(),
                         record = next_record => match record {
                             Ok(record) => record,
-                            Err(err) => match err.is_eof(){
-                                true => {
-                                    warn!("{} has been closed", &name);
-                                    break;
-                                }
-                                false => {
-                                    error!("Error reading from {}, err: {}", &name, err);
-                                    break;
+                            Err(err) if err.is_eof() => {
+                                if quiet_eof {
+                                    actor_debug!(self.tag, "{} has been closed", self.id);
+                                } else {
+                                    actor_warn!(self.tag, "{} has been closed", self.id);
                                 }
+                                break "eof".to_string();
+                            }
+                            // 跳过格式错误的记录, 保留连接
+                            Err(err) if err.is_recoverable() => {
+                                actor_warn!(self.tag, "{} sent a malformed record, err: {}", self.id, err);
+                                stats::GLOBAL_STATS.incr(&format!("{} malformed records", self.tag), 1);
+                                metrics::count_transform_error(&self.tag);
+                                counts.parse_errors += 1;
+                                continue;
+                            }
+                            Err(protocol::Error::Io(err)) if err.kind() == ErrorKind::TimedOut => {
+                                actor_info!(self.tag, "{} is idle, closing, err: {}", self.id, err);
+                                break "idle".to_string();
+                            }
+                            Err(err) => {
+                                actor_error!(self.tag, "error reading from {}, err: {}", self.id, err);
+                                break format!("error: {}", err);
                             }
                         }
                     };
+                    counts.records += 1;
 
                     record.set_attribute(Attribute::Inbound, (&self.tag).into());
+                    record.mark_received(&self.tag);
+
+                    if let Some(ref mut guard) = timestamp_guard {
+                        match guard.check(&mut record) {
+                            Verdict::Keep => {}
+                            Verdict::Drop => continue,
+                            Verdict::Disconnect => {
+                                actor_error!(
+                                    self.tag,
+                                    "{} sent too many out of bounds timestamps, disconnecting",
+                                    self.id
+                                );
+                                break "too many out of bounds timestamps".to_string();
+                            }
+                        }
+                    }
 
-                    if let Err(err) = sender.send(record) {
-                        error!("{} failed to send, err: {}", &name, err);
-                        break;
+                    if let Err(err @ SendError::Closed(_)) = sender.send(record).await {
+                        actor_error!(self.tag, "{} failed to send, err: {}", self.id, err);
+                        // Nowhere to send the closing event either
+                        return;
+                    }
+                    stats::count_ingested();
+                };
+
+                if lifecycle_events {
+                    let record = lifecycle_record(&self.tag, &self.id, Some((&counts, &reason)));
+                    // Without waiting once cancelled, the pipeline is shutting down too
+                    let sent = if self.ctx.is_cancelled() {
+                        sender.try_send(record)
+                    } else {
+                        sender.send(record).await
+                    };
+                    if let Err(err @ SendError::Closed(_)) = sent {
+                        actor_error!(self.tag, "{} failed to send, err: {}", self.id, err);
                     }
                 }
             })
             .expect("Failed to spawn instance")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+    use crate::{
+        config::protocol::graphite::GraphiteProtocolConfig,
+        core::{
+            manager::{ActorChannel, TaggedReceiver},
+            tag::{InboundTagId, PipeTagId, ProtocolTagId},
+        },
+    };
+
+    fn spawn_with_events(
+        reader: tokio::io::DuplexStream,
+        ctx: CancellationToken,
+    ) -> (JoinHandle<()>, TaggedReceiver) {
+        let tag: TagId = InboundTagId::new("unix_socket").into();
+        let mut channel = ActorChannel::new(tag.clone(), 16);
+        let consumer = channel.receiver(&PipeTagId::new("timeseries").into());
+        let protocol = ProtocolConfig::Graphite(GraphiteProtocolConfig {
+            tag: ProtocolTagId::new("graphite"),
+            attributes: None,
+            timezone: chrono_tz::Tz::UTC,
+        });
+        let options = InstanceOptions {
+            timestamp_bounds: None,
+            lifecycle_events: true,
+            quiet_eof: false,
+        };
+        let handle = ReaderBasedInstance::try_create_from(
+            tag,
+            "peer-1".to_string(),
+            reader,
+            protocol,
+            channel.sender(),
+            options,
+            ctx,
+        )
+        .unwrap();
+        (handle, consumer)
+    }
+
+    fn field(record: &Record, name: &str) -> Option<Value> {
+        record.get(&Symbol::new(name)).cloned()
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle_events() {
+        let (mut client, server) = tokio::io::duplex(1024);
+        let (handle, mut consumer) = spawn_with_events(server, CancellationToken::new());
+
+        client
+            .write_all(
+                b"a.b 1 1743667743
+a.b x 1743667743
+a.b 2 1743667743
+",
+            )
+            .await
+            .unwrap();
+        drop(client);
+        tokio::time::timeout(Duration::from_secs(5), handle)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let mut records = Vec::new();
+        while let Ok(record) = consumer.try_recv() {
+            records.push(record);
+        }
+        assert_eq!(records.len(), 4);
+        let (open, close) = (&records[0], &records[3]);
+        for event in [open, close] {
+            assert_eq!(event.get_type(), Some(&*RECORD_TYPE_LIFECYCLE_VALUE));
+            assert_eq!(field(event, "peer"), Some(Value::from("peer-1")));
+        }
+        assert_eq!(field(open, "event"), Some(Value::from("open")));
+        assert_eq!(field(open, "records"), None);
+        assert_eq!(records[1].get_type(), None);
+
+        assert_eq!(field(close, "event"), Some(Value::from("close")));
+        assert_eq!(field(close, "records"), Some(Value::from(2i64)));
+        assert_eq!(field(close, "parse_errors"), Some(Value::from(1i64)));
+        assert_eq!(field(close, "reason"), Some(Value::from("eof")));
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle_cancelled() {
+        let (mut client, server) = tokio::io::duplex(1024);
+        let ctx = CancellationToken::new();
+        let (handle, mut consumer) = spawn_with_events(server, ctx.clone());
+
+        client
+            .write_all(
+                b"a.b 1 1743667743
+",
+            )
+            .await
+            .unwrap();
+        consumer.recv().await.unwrap();
+        consumer.recv().await.unwrap();
+        ctx.cancel();
+        tokio::time::timeout(Duration::from_secs(5), handle)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let close = consumer.try_recv().unwrap();
+        assert_eq!(field(&close, "reason"), Some(Value::from("cancelled")));
+        assert_eq!(field(&close, "records"), Some(Value::from(1i64)));
+        drop(client);
+    }
+
+    #[tokio::test]
+    async fn test_reap_connections() {
+        let tag: TagId = InboundTagId::new("unix_socket").into();
+        let ctx = CancellationToken::new();
+        let open = {
+            let ctx = ctx.clone();
+            tokio::spawn(async move { ctx.cancelled().await })
+        };
+        let mut connections = vec![
+            tokio::spawn(async {}),
+            open,
+            tokio::spawn(async { panic!("connection task") }),
+        ];
+        while connections
+            .iter()
+            .filter(|handle| handle.is_finished())
+            .count()
+            < 2
+        {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        reap_connections(&tag, &mut connections);
+        assert_eq!(connections.len(), 1);
+        assert!(!connections[0].is_finished());
+
+        ctx.cancel();
+    }
+}
@@ -0,0 +1,210 @@
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, ReadBuf},
+    time::{Instant, Sleep},
+};
+
+use crate::config::inbound::limits::ConnectionLimitsConfig;
+
+/// Fails the reads of a connection once a line grows past `max_line_bytes`, or once nothing
+/// arrived for `idle_timeout`, so that the parser reading it gives up on the connection.
+pub struct LimitedReader<R> {
+    inner: R,
+
+    max_line_bytes: u64,
+    // Bytes read since the last newline
+    line_bytes: u64,
+
+    idle_timeout: Option<Duration>,
+    idle: Option<Pin<Box<Sleep>>>,
+}
+
+impl<R> LimitedReader<R> {
+    pub fn new(inner: R, limits: &ConnectionLimitsConfig) -> Self {
+        let idle_timeout = limits.idle_timeout.map(|timeout| timeout.get());
+        Self {
+            inner,
+            max_line_bytes: limits.max_line_bytes.get(),
+            line_bytes: 0,
+            idle_timeout,
+            idle: idle_timeout.map(|timeout| Box::pin(tokio::time::sleep(timeout))),
+        }
+    }
+
+    fn observe(&mut self, read: &[u8]) -> io::Result<()> {
+        let mut lines = read.split(|b| *b == b'\n');
+        // The first one goes on with the current line
+        let mut longest = self.line_bytes + lines.next().map_or(0, <[u8]>::len) as u64;
+        let mut current = longest;
+        for line in lines {
+            current = line.len() as u64;
+            longest = longest.max(current);
+        }
+        self.line_bytes = current;
+
+        if longest > self.max_line_bytes {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line longer than {} bytes", self.max_line_bytes),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for LimitedReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+
+        match Pin::new(&mut this.inner).poll_read(cx, buf) {
+            Poll::Ready(Ok(())) => {
+                if let (Some(idle), Some(timeout)) = (this.idle.as_mut(), this.idle_timeout) {
+                    idle.as_mut().reset(Instant::now() + timeout);
+                }
+                Poll::Ready(this.observe(&buf.filled()[filled..]))
+            }
+            Poll::Pending => match this.idle.as_mut().map(|idle| idle.as_mut().poll(cx)) {
+                Some(Poll::Ready(())) => Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!(
+                        "nothing received for {:?}",
+                        this.idle_timeout.unwrap_or_default()
+                    ),
+                ))),
+                _ => Poll::Pending,
+            },
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+    use tokio_util::sync::CancellationToken;
+
+    use super::*;
+    use crate::{
+        config::{
+            protocol::graphite::GraphiteProtocolConfig, types::DurationValue, ProtocolConfig,
+        },
+        core::{
+            inbound::instance::ReaderBasedInstance,
+            manager::ActorChannel,
+            tag::{InboundTagId, PipeTagId, ProtocolTagId, TagId},
+        },
+    };
+
+    fn spawn(
+        reader: tokio::io::DuplexStream,
+        limits: ConnectionLimitsConfig,
+        channel: &mut ActorChannel,
+    ) -> tokio::task::JoinHandle<()> {
+        let protocol = ProtocolConfig::Graphite(GraphiteProtocolConfig {
+            tag: ProtocolTagId::new("graphite"),
+            attributes: None,
+            timezone: chrono_tz::Tz::UTC,
+        });
+        ReaderBasedInstance::try_create_from(
+            InboundTagId::new("unix_socket").into(),
+            "test".to_string(),
+            LimitedReader::new(reader, &limits),
+            protocol,
+            channel.sender(),
+            Default::default(),
+            CancellationToken::new(),
+        )
+        .unwrap()
+    }
+
+    fn channel() -> (ActorChannel, crate::core::manager::TaggedReceiver) {
+        let tag: TagId = InboundTagId::new("unix_socket").into();
+        let mut channel = ActorChannel::new(tag, 16);
+        let consumer = channel.receiver(&PipeTagId::new("timeseries").into());
+        (channel, consumer)
+    }
+
+    #[tokio::test]
+    async fn test_max_line_bytes() {
+        let (mut channel, mut consumer) = channel();
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let handle = spawn(server, ConnectionLimitsConfig::default(), &mut channel);
+
+        client.write_all(b"a.b 1 1743667743\n").await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), consumer.recv())
+            .await
+            .unwrap()
+            .unwrap();
+
+        // 10MiB without a newline, the connection is closed after about 1MiB
+        let writer = tokio::spawn(async move {
+            let chunk = vec![b'x'; 64 * 1024];
+            for _ in 0..160 {
+                if client.write_all(&chunk).await.is_err() {
+                    return false;
+                }
+            }
+            true
+        });
+        tokio::time::timeout(Duration::from_secs(5), handle)
+            .await
+            .expect("connection not closed")
+            .unwrap();
+        assert!(!writer.await.unwrap(), "the whole line was read");
+    }
+
+    #[test]
+    fn test_lines_within_limit() {
+        let mut reader = LimitedReader::new(
+            tokio::io::empty(),
+            &ConnectionLimitsConfig {
+                max_line_bytes: 4.into(),
+                idle_timeout: None,
+                max_connections: None,
+            },
+        );
+        assert!(reader.observe(b"abcd\nab").is_ok());
+        assert!(reader.observe(b"cd\n\nabcd").is_ok());
+        assert!(reader.observe(b"e").is_err());
+        reader.line_bytes = 0;
+        assert!(reader.observe(b"a\nabcde\na").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout() {
+        let (mut channel, mut consumer) = channel();
+        let (mut client, server) = tokio::io::duplex(1024);
+        let limits = ConnectionLimitsConfig {
+            idle_timeout: Some(DurationValue::from_millis(200)),
+            ..Default::default()
+        };
+        let handle = spawn(server, limits, &mut channel);
+
+        // Sending keeps the connection open
+        for _ in 0..4 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            client.write_all(b"a.b 1 1743667743\n").await.unwrap();
+            consumer.recv().await.unwrap();
+        }
+        assert!(!handle.is_finished());
+
+        // Then it goes silent
+        tokio::time::timeout(Duration::from_secs(5), handle)
+            .await
+            .expect("idle connection not closed")
+            .unwrap();
+        drop(client);
+    }
+}
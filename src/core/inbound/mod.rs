@@ -1,9 +1,16 @@
 use crate::config::{inbound::InboundConfig, ProtocolConfig};
 
+mod accept;
 mod base;
 mod error;
+mod file;
 mod instance;
+mod limits;
+#[cfg(any(unix, windows))]
 mod named_pipe;
+mod tcp;
+mod timestamp;
+#[cfg(unix)]
 mod unix;
 
 pub use base::Inbound;
@@ -18,14 +25,31 @@ pub fn try_create_from(
 ) -> Result<Box<dyn base::Inbound>> {
     let inbound: Box<dyn Inbound> =
         match inbound_config {
+            #[cfg(unix)]
             InboundConfig::UnixSocket(cfg) => Box::new(unix::UnixSocketInbound::try_create_from(
                 cfg,
                 protocol_config,
                 channel_graph,
             )?),
+            // Rejected by the verification of the config already
+            #[cfg(not(unix))]
+            InboundConfig::UnixSocket(_) => return Err(Error::Unsupported("unix_socket")),
+            #[cfg(any(unix, windows))]
             InboundConfig::NamedPipe(cfg) => Box::new(
                 named_pipe::NamedPipeInbound::try_create_from(cfg, protocol_config, channel_graph)?,
             ),
+            #[cfg(not(any(unix, windows)))]
+            InboundConfig::NamedPipe(_) => return Err(Error::Unsupported("named_pipe")),
+            InboundConfig::File(cfg) => Box::new(file::FileInbound::try_create_from(
+                cfg,
+                protocol_config,
+                channel_graph,
+            )?),
+            InboundConfig::Tcp(cfg) => Box::new(tcp::TcpInbound::try_create_from(
+                cfg,
+                protocol_config,
+                channel_graph,
+            )?),
         };
 
     Ok(inbound)
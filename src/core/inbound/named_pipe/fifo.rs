@@ -0,0 +1,361 @@
+use std::{
+    path::PathBuf,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Instant,
+};
+
+use async_trait::async_trait;
+use bytes::{Buf, BytesMut};
+use tokio::{
+    io::{AsyncRead, ReadBuf},
+    task::JoinHandle,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    actor_debug, actor_error, actor_info, actor_warn,
+    config::{inbound::named_pipe::NamedPipeConfig, ProtocolConfig},
+    core::{
+        actor::Actor,
+        inbound::instance::{InstanceOptions, ReaderBasedInstance},
+        manager::{ChannelGraph, TaggedSender},
+        tag::{HasTag, TagId},
+    },
+    utils::retry::Backoff,
+};
+
+use super::Inbound;
+use super::Result;
+
+pub(crate) struct NamedPipeInbound {
+    tag: TagId,
+    path: PathBuf,
+    // Removed on drop, unless created by a dry run which left the path alone
+    owns_path: bool,
+
+    ctx: CancellationToken,
+
+    handle: Option<JoinHandle<()>>,
+    opened_at: Option<Instant>,
+    backoff: Backoff,
+    reopen_on_eof: bool,
+
+    outbound: TaggedSender,
+    protocol: ProtocolConfig,
+    options: InstanceOptions,
+}
+
+/// Hands out whole lines only: the bytes following the last newline are held back until the
+/// line is complete, and dropped at EOF. A writer which died mid-line leaves no truncated
+/// record behind, nor is its line merged into the first one of the next writer.
+struct CompleteLines<R> {
+    tag: TagId,
+    inner: R,
+    buf: BytesMut,
+    // Bytes at the start of `buf` up to the last newline
+    complete: usize,
+}
+
+impl<R> CompleteLines<R> {
+    fn new(tag: TagId, inner: R) -> Self {
+        CompleteLines {
+            tag,
+            inner,
+            buf: BytesMut::new(),
+            complete: 0,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for CompleteLines<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        out: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.complete > 0 {
+                let n = this.complete.min(out.remaining());
+                out.put_slice(&this.buf[..n]);
+                this.buf.advance(n);
+                this.complete -= n;
+                return Poll::Ready(Ok(()));
+            }
+
+            let mut chunk = [0u8; 8192];
+            let mut read = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut read))?;
+            if read.filled().is_empty() {
+                if !this.buf.is_empty() {
+                    actor_warn!(
+                        this.tag,
+                        "writer closed the pipe mid-line, dropped {} bytes",
+                        this.buf.len()
+                    );
+                    this.buf.clear();
+                }
+                return Poll::Ready(Ok(()));
+            }
+
+            let start = this.buf.len();
+            this.buf.extend_from_slice(read.filled());
+            if let Some(pos) = this.buf[start..].iter().rposition(|&b| b == b'\n') {
+                this.complete = start + pos + 1;
+            }
+        }
+    }
+}
+
+impl NamedPipeInbound {
+    pub fn try_create_from(
+        cfg: NamedPipeConfig,
+        protocol_cfg: ProtocolConfig,
+        channel_graph: &mut ChannelGraph,
+    ) -> Result<Self> {
+        let path = &cfg.path;
+
+        if !channel_graph.is_dry_run() {
+            if path.exists() {
+                std::fs::remove_file(path)?;
+            }
+
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+
+        let tag = cfg.tag.clone().into();
+        let outbound = channel_graph.sender(&tag);
+
+        let mut inbound = Self::new(cfg, protocol_cfg, outbound);
+        inbound.owns_path = !channel_graph.is_dry_run();
+        Ok(inbound)
+    }
+
+    fn new(cfg: NamedPipeConfig, protocol_cfg: ProtocolConfig, outbound: TaggedSender) -> Self {
+        let backoff = Backoff::from_config(&cfg.reopen);
+
+        let inbound = NamedPipeInbound {
+            tag: cfg.tag.into(),
+            path: cfg.path,
+            owns_path: true,
+            handle: None,
+            opened_at: None,
+            backoff,
+            reopen_on_eof: cfg.reopen_on_eof,
+            ctx: CancellationToken::new(),
+            outbound,
+            protocol: protocol_cfg,
+            options: InstanceOptions {
+                timestamp_bounds: cfg.timestamp_bounds,
+                lifecycle_events: cfg.emit_lifecycle_events,
+                quiet_eof: true,
+            },
+        };
+
+        actor_info!(inbound.tag, "listening on {:?}", inbound.path);
+
+        inbound
+    }
+}
+
+impl Drop for NamedPipeInbound {
+    fn drop(&mut self) {
+        if self.owns_path {
+            if let Err(e) = std::fs::remove_file(&self.path) {
+                actor_error!(self.tag, "failed to remove named pipe file: {:?}", e);
+            }
+        }
+
+        self.ctx.cancel();
+    }
+}
+
+impl HasTag for NamedPipeInbound {
+    fn tag(&self) -> &TagId {
+        &self.tag
+    }
+}
+
+#[async_trait]
+impl Actor for NamedPipeInbound {
+    type Error = super::Error;
+    async fn poll(
+        &mut self,
+        ctx: tokio_util::sync::CancellationToken,
+    ) -> miette::Result<(), super::Error> {
+        // The writer closed the pipe and it is not to be reopened
+        if self.handle.is_none() && self.opened_at.is_some() && !self.reopen_on_eof {
+            ctx.cancelled().await;
+            return Ok(());
+        }
+
+        if let Some(handle) = self.handle.as_mut() {
+            // The reader ends once the writer closed the pipe
+            tokio::select! {
+                _ = ctx.cancelled() => return Ok(()),
+                result = handle => {
+                    match result {
+                        Err(e) if e.is_panic() => {
+                            actor_error!(self.tag, "reader task panicked: {}", e);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            self.handle = None;
+
+            if !self.reopen_on_eof {
+                actor_info!(self.tag, "reader closed, not reopening {:?}", self.path);
+                return Ok(());
+            }
+
+            // A reader which lived long enough was a healthy one, start the backoff over.
+            if self
+                .opened_at
+                .is_some_and(|t| t.elapsed() >= self.backoff.max_delay())
+            {
+                self.backoff.reset();
+            }
+
+            // Writers come and go, which may happen thousands of times a day
+            let delay = self.backoff.next().unwrap_or_default();
+            actor_debug!(
+                self.tag,
+                "reader closed, reopening {:?} in {:?}",
+                self.path,
+                delay
+            );
+
+            tokio::select! {
+                _ = ctx.cancelled() => return Ok(()),
+                _ = tokio::time::sleep(delay) => {}
+            }
+        }
+
+        if self.handle.is_none() {
+            // make fifo pipe
+            if !self.path.exists() {
+                nix::unistd::mkfifo(
+                    &self.path,
+                    nix::sys::stat::Mode::S_IRWXU
+                        | nix::sys::stat::Mode::S_IRWXG
+                        | nix::sys::stat::Mode::S_IRWXO,
+                )?;
+            }
+
+            let receiver = tokio::net::unix::pipe::OpenOptions::new().open_receiver(&self.path)?;
+
+            let reader = ReaderBasedInstance::try_create_from(
+                self.tag.clone(),
+                self.path.display().to_string(),
+                CompleteLines::new(self.tag.clone(), receiver),
+                self.protocol.clone(),
+                self.outbound.clone(),
+                self.options.clone(),
+                ctx.clone(),
+            )?;
+
+            self.handle = Some(reader);
+            self.opened_at = Some(Instant::now());
+        }
+
+        Ok(())
+    }
+}
+
+impl Inbound for NamedPipeInbound {}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::{
+        config::protocol::graphite::GraphiteProtocolConfig,
+        core::{
+            manager::ActorChannel,
+            tag::{InboundTagId, PipeTagId, ProtocolTagId},
+            types::Symbol,
+        },
+    };
+
+    async fn write_burst(path: &std::path::Path, seqs: std::ops::Range<i64>) {
+        // Opening for writing fails until the inbound has the pipe open for reading
+        let mut sender = loop {
+            match tokio::net::unix::pipe::OpenOptions::new().open_sender(path) {
+                Ok(sender) => break sender,
+                Err(_) => tokio::time::sleep(Duration::from_millis(5)).await,
+            }
+        };
+        for seq in seqs {
+            let line = format!("app.seq {} 1743667743\n", seq);
+            sender.write_all(line.as_bytes()).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reopen_on_eof() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data_np");
+
+        let tag: TagId = InboundTagId::new("named_pipe").into();
+        let mut channel = ActorChannel::new(tag.clone(), 16);
+        let mut consumer = channel.receiver(&PipeTagId::new("timeseries").into());
+        channel.seal();
+
+        let cfg: NamedPipeConfig = toml::from_str(&format!(
+            "path = {:?}\nprotocol = \"graphite\"\nreopen = {{ initial_delay = \"10ms\", max_delay = \"50ms\" }}",
+            path
+        ))
+        .unwrap();
+        assert!(cfg.reopen_on_eof);
+        let protocol = ProtocolConfig::Graphite(GraphiteProtocolConfig {
+            tag: ProtocolTagId::new("graphite"),
+            attributes: None,
+            timezone: chrono_tz::Tz::UTC,
+        });
+        let inbound = NamedPipeInbound::new(cfg, protocol, channel.sender());
+        let ctx = CancellationToken::new();
+        let handle = crate::core::actor::spawn(Box::new(inbound), ctx.clone(), None);
+
+        // The writer closes the pipe after each burst
+        write_burst(&path, 0..50).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        write_burst(&path, 50..100).await;
+
+        let mut seqs = Vec::new();
+        while seqs.len() < 100 {
+            let record = tokio::time::timeout(Duration::from_secs(5), consumer.recv())
+                .await
+                .expect("records lost")
+                .unwrap();
+            seqs.push(record.get(&Symbol::new("app.seq")).unwrap().to_string());
+        }
+        let expected = (0..100).map(|seq| seq.to_string()).collect::<Vec<_>>();
+        assert_eq!(seqs, expected);
+
+        ctx.cancel();
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_partial_line_dropped() {
+        let tag: TagId = InboundTagId::new("named_pipe").into();
+        let (mut writer, reader) = tokio::io::duplex(64);
+        let mut lines = CompleteLines::new(tag, reader);
+
+        writer
+            .write_all(b"app.seq 0 1743667743\napp.seq 1 17436")
+            .await
+            .unwrap();
+        drop(writer);
+        let mut read = String::new();
+        lines.read_to_string(&mut read).await.unwrap();
+        assert_eq!(read, "app.seq 0 1743667743\n");
+    }
+}
@@ -0,0 +1,14 @@
+//! The `named_pipe` inbound: a FIFO read by one writer at a time on unix, a named pipe
+//! server accepting any number of clients on windows.
+
+#[cfg(unix)]
+mod fifo;
+#[cfg(windows)]
+mod windows;
+
+#[cfg(unix)]
+pub(crate) use fifo::NamedPipeInbound;
+#[cfg(windows)]
+pub(crate) use windows::NamedPipeInbound;
+
+use super::{base::Inbound, Error, Result};
@@ -0,0 +1,260 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use tokio::{
+    net::windows::named_pipe::{NamedPipeServer, ServerOptions},
+    task::JoinHandle,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    actor_info, actor_warn,
+    config::{inbound::named_pipe::NamedPipeConfig, ProtocolConfig},
+    core::{
+        actor::Actor,
+        inbound::instance::{reap_connections, InstanceOptions, ReaderBasedInstance},
+        manager::{ChannelGraph, TaggedSender},
+        tag::{HasTag, TagId},
+    },
+};
+
+use super::Inbound;
+use super::Result;
+
+/// Serves a pipe like `\\.\pipe\void-metrics`. Each client gets its own server instance,
+/// read like a unix socket connection, while a new one waits for the next client.
+pub(crate) struct NamedPipeInbound {
+    tag: TagId,
+    path: PathBuf,
+
+    // The instance waiting for the next client, created by `listen`
+    server: Option<NamedPipeServer>,
+    // Whether an instance of the pipe was ever created, only the first one may claim it
+    created: bool,
+    ctx: CancellationToken,
+
+    connections: Vec<JoinHandle<()>>,
+    accepted: u64,
+    max_connections: Option<usize>,
+
+    outbound: TaggedSender,
+    protocol: ProtocolConfig,
+    options: InstanceOptions,
+}
+
+impl NamedPipeInbound {
+    pub fn try_create_from(
+        cfg: NamedPipeConfig,
+        protocol_cfg: ProtocolConfig,
+        channel_graph: &mut ChannelGraph,
+    ) -> Result<Self> {
+        let tag = cfg.tag.clone().into();
+        let outbound = channel_graph.sender(&tag);
+
+        let mut inbound = Self::new(cfg, protocol_cfg, outbound);
+        if !channel_graph.is_dry_run() {
+            inbound.listen()?;
+            actor_info!(inbound.tag, "listening on {:?}", inbound.path);
+        }
+
+        Ok(inbound)
+    }
+
+    fn new(cfg: NamedPipeConfig, protocol_cfg: ProtocolConfig, outbound: TaggedSender) -> Self {
+        NamedPipeInbound {
+            tag: cfg.tag.into(),
+            path: cfg.path,
+            server: None,
+            created: false,
+            ctx: CancellationToken::new(),
+            connections: Vec::new(),
+            accepted: 0,
+            max_connections: cfg.max_connections,
+            outbound,
+            protocol: protocol_cfg,
+            options: InstanceOptions {
+                timestamp_bounds: cfg.timestamp_bounds,
+                lifecycle_events: cfg.emit_lifecycle_events,
+                quiet_eof: true,
+            },
+        }
+    }
+
+    /// Create the instance the next client connects to, unless there is one.
+    fn listen(&mut self) -> Result<&mut NamedPipeServer> {
+        if self.server.is_none() {
+            // Fails when another process already serves the pipe
+            let server = ServerOptions::new()
+                .first_pipe_instance(!self.created)
+                .create(&self.path)?;
+            self.server = Some(server);
+            self.created = true;
+        }
+
+        Ok(self.server.as_mut().expect("created above"))
+    }
+}
+
+impl Drop for NamedPipeInbound {
+    fn drop(&mut self) {
+        // The pipe is gone with its last instance, there is no file to remove
+        self.ctx.cancel();
+    }
+}
+
+impl HasTag for NamedPipeInbound {
+    fn tag(&self) -> &TagId {
+        &self.tag
+    }
+}
+
+#[async_trait]
+impl Actor for NamedPipeInbound {
+    type Error = super::Error;
+    async fn poll(
+        &mut self,
+        ctx: tokio_util::sync::CancellationToken,
+    ) -> miette::Result<(), super::Error> {
+        let server = self.listen()?;
+
+        let connected = tokio::select! {
+            _ = ctx.cancelled() => return Ok(()),
+            connected = server.connect() => connected,
+        };
+        if let Err(e) = connected {
+            // The instance is broken, a new one is created on the next poll
+            self.server = None;
+            return Err(e.into());
+        }
+
+        let client = self.server.take().expect("connected above");
+        self.accepted += 1;
+        // Forget the clients already gone
+        reap_connections(&self.tag, &mut self.connections);
+        if self
+            .max_connections
+            .is_some_and(|max| self.connections.len() >= max)
+        {
+            actor_warn!(
+                self.tag,
+                "reject client #{}, {} clients connected",
+                self.accepted,
+                self.connections.len()
+            );
+            drop(client);
+            self.listen()?;
+            return Ok(());
+        }
+
+        let handle = ReaderBasedInstance::try_create_from(
+            self.tag.clone(),
+            format!("named_pipe({:?} #{})", self.path, self.accepted),
+            client,
+            self.protocol.clone(),
+            self.outbound.clone(),
+            self.options.clone(),
+            self.ctx.clone(),
+        )?;
+        self.connections.push(handle);
+        actor_info!(
+            self.tag,
+            "accept new client #{} on {:?}",
+            self.accepted,
+            self.path
+        );
+
+        // Ready for the next client before polling again
+        self.listen()?;
+
+        Ok(())
+    }
+}
+
+impl Inbound for NamedPipeInbound {}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{io::AsyncWriteExt, net::windows::named_pipe::ClientOptions};
+
+    use super::*;
+    use crate::{
+        config::protocol::graphite::GraphiteProtocolConfig,
+        core::{
+            manager::ActorChannel,
+            tag::{InboundTagId, PipeTagId, ProtocolTagId},
+            types::Symbol,
+        },
+    };
+
+    #[tokio::test]
+    async fn test_concurrent_clients() {
+        let path = format!(r"\\.\pipe\void-test-{}", std::process::id());
+
+        let tag: TagId = InboundTagId::new("named_pipe").into();
+        let mut channel = ActorChannel::new(tag.clone(), 16);
+        let mut consumer = channel.receiver(&PipeTagId::new("timeseries").into());
+        channel.seal();
+
+        let cfg: NamedPipeConfig =
+            toml::from_str(&format!("path = {:?}\nprotocol = \"graphite\"", path)).unwrap();
+        let protocol = ProtocolConfig::Graphite(GraphiteProtocolConfig {
+            tag: ProtocolTagId::new("graphite"),
+            attributes: None,
+            timezone: chrono_tz::Tz::UTC,
+        });
+        let mut inbound = NamedPipeInbound::new(cfg, protocol, channel.sender());
+        inbound.listen().unwrap();
+        let ctx = CancellationToken::new();
+        let handle = crate::core::actor::spawn(Box::new(inbound), ctx.clone(), None);
+
+        // Both clients stay connected while writing
+        let mut clients = Vec::new();
+        for _ in 0..2 {
+            let client = loop {
+                match ClientOptions::new().open(&path) {
+                    Ok(client) => break client,
+                    // All the instances are busy until the inbound creates the next one
+                    Err(_) => tokio::time::sleep(Duration::from_millis(5)).await,
+                }
+            };
+            clients.push(client);
+        }
+        for (id, client) in clients.iter_mut().enumerate() {
+            let line = format!("app.client {} 1743667743\n", id);
+            client.write_all(line.as_bytes()).await.unwrap();
+        }
+
+        let mut ids = Vec::new();
+        while ids.len() < 2 {
+            let record = tokio::time::timeout(Duration::from_secs(5), consumer.recv())
+                .await
+                .expect("records lost")
+                .unwrap();
+            ids.push(record.get(&Symbol::new("app.client")).unwrap().to_string());
+        }
+        ids.sort();
+        assert_eq!(ids, ["0", "1"]);
+
+        ctx.cancel();
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pipe_already_served() {
+        let path = format!(r"\\.\pipe\void-test-taken-{}", std::process::id());
+        let _taken = ServerOptions::new().create(&path).unwrap();
+
+        let cfg: NamedPipeConfig =
+            toml::from_str(&format!("path = {:?}\nprotocol = \"graphite\"", path)).unwrap();
+        let protocol = ProtocolConfig::Graphite(GraphiteProtocolConfig {
+            tag: ProtocolTagId::new("graphite"),
+            attributes: None,
+            timezone: chrono_tz::Tz::UTC,
+        });
+        let mut channel = ActorChannel::new(InboundTagId::new("named_pipe").into(), 1);
+        let mut inbound = NamedPipeInbound::new(cfg, protocol, channel.sender());
+        assert!(inbound.listen().is_err());
+    }
+}
@@ -0,0 +1,337 @@
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use tokio::{net::TcpListener, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    actor_info, actor_warn,
+    config::{
+        inbound::{limits::ConnectionLimitsConfig, tcp::TcpConfig},
+        ProtocolConfig,
+    },
+    core::{
+        actor::Actor,
+        inbound::{
+            accept::AcceptThrottle,
+            instance::{reap_connections, InstanceOptions, ReaderBasedInstance},
+            limits::LimitedReader,
+        },
+        manager::{ChannelGraph, TaggedSender},
+        tag::{HasTag, TagId},
+    },
+    utils::throttle::Throttle,
+};
+
+use super::base::Inbound;
+use super::error::Result;
+
+// Wait after a failed accept, e.g. out of file descriptors, before accepting again
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+// A failing accept is logged at most this often
+const ACCEPT_ERROR_WARN_INTERVAL: Duration = Duration::from_secs(10);
+
+pub(crate) struct TcpInbound {
+    tag: TagId,
+    address: String,
+
+    listener: Option<TcpListener>,
+    ctx: CancellationToken,
+
+    connections: Vec<JoinHandle<()>>,
+    accept_throttle: Option<AcceptThrottle>,
+    accept_error_throttle: Throttle,
+
+    outbound: TaggedSender,
+    protocol: ProtocolConfig,
+    options: InstanceOptions,
+    limits: ConnectionLimitsConfig,
+}
+
+impl TcpInbound {
+    pub fn try_create_from(
+        cfg: TcpConfig,
+        protocol_cfg: ProtocolConfig,
+        channel_graph: &mut ChannelGraph,
+    ) -> Result<Self> {
+        let tag = cfg.tag.clone().into();
+        let outbound = channel_graph.sender(&tag);
+
+        let mut inbound = Self::new(cfg, protocol_cfg, outbound);
+        if !channel_graph.is_dry_run() {
+            inbound.start()?;
+        }
+
+        Ok(inbound)
+    }
+
+    /// Nothing is bound until [`Self::start`].
+    fn new(cfg: TcpConfig, protocol_cfg: ProtocolConfig, outbound: TaggedSender) -> Self {
+        let tag: TagId = cfg.tag.into();
+        let accept_throttle = cfg
+            .accept_throttle
+            .map(|throttle| AcceptThrottle::on_channel(tag.clone(), throttle, outbound.clone()));
+
+        TcpInbound {
+            tag,
+            address: cfg.address,
+            listener: None,
+            ctx: CancellationToken::new(),
+            connections: Vec::new(),
+            accept_throttle,
+            accept_error_throttle: Throttle::new(ACCEPT_ERROR_WARN_INTERVAL),
+            outbound,
+            protocol: protocol_cfg,
+            options: InstanceOptions {
+                timestamp_bounds: cfg.timestamp_bounds,
+                lifecycle_events: cfg.emit_lifecycle_events,
+                quiet_eof: false,
+            },
+            limits: cfg.limits,
+        }
+    }
+
+    /// Bind the address, once.
+    fn start(&mut self) -> Result<()> {
+        if self.listener.is_some() {
+            return Ok(());
+        }
+
+        let listener = std::net::TcpListener::bind(&self.address)?;
+        listener.set_nonblocking(true)?;
+        let listener = TcpListener::from_std(listener)?;
+        actor_info!(self.tag, "listening on {}", listener.local_addr()?);
+        self.listener = Some(listener);
+
+        Ok(())
+    }
+}
+
+impl Drop for TcpInbound {
+    fn drop(&mut self) {
+        self.ctx.cancel();
+    }
+}
+
+impl HasTag for TcpInbound {
+    fn tag(&self) -> &TagId {
+        &self.tag
+    }
+}
+
+#[async_trait]
+impl Actor for TcpInbound {
+    type Error = super::Error;
+    async fn poll(
+        &mut self,
+        ctx: tokio_util::sync::CancellationToken,
+    ) -> miette::Result<(), super::Error> {
+        if let Some(throttle) = self.accept_throttle.as_mut() {
+            if !throttle.ready(&ctx).await {
+                self.ctx.cancel();
+                return Ok(());
+            }
+        }
+
+        self.start()?;
+        let listener = self.listener.as_ref().expect("bound by start");
+        let new_connection = listener.accept();
+
+        tokio::select! {
+            _ = ctx.cancelled() => {
+                // 关闭所有已建立的连接
+                self.ctx.cancel();
+                return Ok(());
+            }
+            accepted = new_connection => {
+                let (stream, addr) = match accepted {
+                    Ok(accepted) => accepted,
+                    // The listener stays usable, e.g. once file descriptors are released
+                    Err(e) => {
+                        if let Some(suppressed) = self.accept_error_throttle.check_at(Instant::now()) {
+                            actor_warn!(
+                                self.tag,
+                                "failed to accept a connection ({} more suppressed): {}",
+                                suppressed,
+                                e
+                            );
+                        }
+                        tokio::select! {
+                            _ = tokio::time::sleep(ACCEPT_ERROR_BACKOFF) => {}
+                            _ = ctx.cancelled() => {}
+                        }
+                        return Ok(());
+                    }
+                };
+
+                // Remote peers come and go, forget the connections already closed
+                reap_connections(&self.tag, &mut self.connections);
+                if !self.limits.accepts(self.connections.len()) {
+                    actor_warn!(
+                        self.tag,
+                        "reject connection \"{}\", {} connections open",
+                        addr,
+                        self.connections.len()
+                    );
+                    return Ok(());
+                }
+                actor_info!(self.tag, "accept new connection \"{}\"", addr);
+
+                let handle = ReaderBasedInstance::try_create_from(
+                    self.tag.clone(),
+                    format!("tcp({})", addr),
+                    LimitedReader::new(stream, &self.limits),
+                    self.protocol.clone(),
+                    self.outbound.clone(),
+                    self.options.clone(),
+                    self.ctx.clone(),
+                )?;
+                self.connections.push(handle);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Inbound for TcpInbound {}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use super::*;
+    use crate::{
+        config::protocol::graphite::GraphiteProtocolConfig,
+        core::{
+            manager::ActorChannel,
+            tag::{InboundTagId, PipeTagId, ProtocolTagId},
+            types::Symbol,
+        },
+    };
+
+    #[tokio::test]
+    async fn test_tcp_inbound() {
+        let tag: TagId = InboundTagId::new("tcp").into();
+        let mut channel = ActorChannel::new(tag.clone(), 16);
+        let mut consumer = channel.receiver(&PipeTagId::new("timeseries").into());
+        channel.seal();
+
+        let cfg = TcpConfig {
+            tag: InboundTagId::new("tcp"),
+            address: "127.0.0.1:0".to_string(),
+            protocol: ProtocolTagId::new("graphite"),
+            disabled: false,
+            channel_overflow: None,
+            timestamp_bounds: None,
+            emit_lifecycle_events: false,
+            accept_throttle: None,
+            limits: Default::default(),
+        };
+        let protocol = ProtocolConfig::Graphite(GraphiteProtocolConfig {
+            tag: ProtocolTagId::new("graphite"),
+            attributes: None,
+            timezone: chrono_tz::Tz::UTC,
+        });
+        let mut inbound = TcpInbound::new(cfg, protocol, channel.sender());
+        inbound.start().unwrap();
+        let address = inbound.listener.as_ref().unwrap().local_addr().unwrap();
+        let ctx = CancellationToken::new();
+
+        let mut client = TcpStream::connect(address).await.unwrap();
+        inbound.poll(ctx.clone()).await.unwrap();
+        assert_eq!(inbound.connections.len(), 1);
+
+        client
+            .write_all(b"collectd.host.cpu 42 1743667743\n")
+            .await
+            .unwrap();
+        let record = tokio::time::timeout(Duration::from_secs(5), consumer.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            record
+                .get(&Symbol::new("collectd.host.cpu"))
+                .map(|v| v.to_string()),
+            Some("42".to_string())
+        );
+
+        // Cancelling the inbound closes its connections
+        ctx.cancel();
+        inbound.poll(ctx.clone()).await.unwrap();
+        let handle = inbound.connections.pop().unwrap();
+        tokio::time::timeout(Duration::from_secs(5), handle)
+            .await
+            .expect("connection not closed")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_max_connections() {
+        let tag: TagId = InboundTagId::new("tcp").into();
+        let mut channel = ActorChannel::new(tag.clone(), 16);
+        let _consumer = channel.receiver(&PipeTagId::new("timeseries").into());
+        channel.seal();
+
+        let cfg = TcpConfig {
+            tag: InboundTagId::new("tcp"),
+            address: "127.0.0.1:0".to_string(),
+            protocol: ProtocolTagId::new("graphite"),
+            disabled: false,
+            channel_overflow: None,
+            timestamp_bounds: None,
+            emit_lifecycle_events: false,
+            accept_throttle: None,
+            limits: ConnectionLimitsConfig {
+                max_connections: Some(1),
+                ..Default::default()
+            },
+        };
+        let protocol = ProtocolConfig::Graphite(GraphiteProtocolConfig {
+            tag: ProtocolTagId::new("graphite"),
+            attributes: None,
+            timezone: chrono_tz::Tz::UTC,
+        });
+        let mut inbound = TcpInbound::new(cfg, protocol, channel.sender());
+        inbound.start().unwrap();
+        let address = inbound.listener.as_ref().unwrap().local_addr().unwrap();
+        let ctx = CancellationToken::new();
+
+        let first = TcpStream::connect(address).await.unwrap();
+        inbound.poll(ctx.clone()).await.unwrap();
+        assert_eq!(inbound.connections.len(), 1);
+
+        // Closed right away while the first one is open
+        let mut rejected = TcpStream::connect(address).await.unwrap();
+        inbound.poll(ctx.clone()).await.unwrap();
+        assert_eq!(inbound.connections.len(), 1);
+        let read = tokio::time::timeout(Duration::from_secs(5), rejected.read(&mut [0; 8]))
+            .await
+            .expect("connection not closed");
+        assert!(matches!(read, Ok(0) | Err(_)));
+
+        // A slot is free again once the first one is gone
+        drop(first);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !inbound.connections[0].is_finished() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("connection not closed");
+        let _second = TcpStream::connect(address).await.unwrap();
+        inbound.poll(ctx.clone()).await.unwrap();
+        assert_eq!(inbound.connections.len(), 1);
+        assert!(!inbound.connections[0].is_finished());
+
+        ctx.cancel();
+        inbound.ctx.cancel();
+    }
+}
@@ -0,0 +1,232 @@
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    actor_warn,
+    config::inbound::timestamp::{TimestampBoundsConfig, TimestampBoundsMode},
+    core::{
+        tag::TagId,
+        types::{Record, Value},
+    },
+    utils::{stats::GLOBAL_STATS, throttle::Throttle},
+};
+
+const VIOLATION_WARN_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Keep,
+    Drop,
+    /// The connection violated the bounds too often
+    Disconnect,
+}
+
+/// Checks the timestamps of the records read from a single connection.
+#[derive(Debug)]
+pub struct TimestampGuard {
+    tag: TagId,
+    // The connection, also the key of the stats
+    name: String,
+    cfg: TimestampBoundsConfig,
+
+    records: u64,
+    violations: u64,
+    throttle: Throttle,
+}
+
+impl TimestampGuard {
+    pub fn new(tag: TagId, name: String, cfg: TimestampBoundsConfig) -> Self {
+        Self {
+            tag,
+            name,
+            cfg,
+            records: 0,
+            violations: 0,
+            throttle: Throttle::new(VIOLATION_WARN_INTERVAL),
+        }
+    }
+
+    pub fn check(&mut self, record: &mut Record) -> Verdict {
+        self.check_at(record, Utc::now())
+    }
+
+    fn check_at(&mut self, record: &mut Record, now: DateTime<Utc>) -> Verdict {
+        self.records += 1;
+
+        let timestamp = match record.get(&self.cfg.field) {
+            Some(Value::DateTime(ts)) => *ts,
+            _ => return Verdict::Keep,
+        };
+
+        let too_old = self.cfg.max_past.is_some_and(|max| {
+            now.signed_duration_since(timestamp)
+                .to_std()
+                .is_ok_and(|d| d > *max)
+        });
+        let too_new = self.cfg.max_future.is_some_and(|max| {
+            timestamp
+                .signed_duration_since(now)
+                .to_std()
+                .is_ok_and(|d| d > *max)
+        });
+        if !too_old && !too_new {
+            return Verdict::Keep;
+        }
+
+        self.violations += 1;
+        GLOBAL_STATS.incr(&format!("{} timestamp violations", self.name), 1);
+
+        if let Some(rate) = self.cfg.max_violation_rate {
+            if self.records >= self.cfg.min_records
+                && self.violations as f64 / self.records as f64 > rate
+            {
+                return Verdict::Disconnect;
+            }
+        }
+
+        match self.cfg.mode {
+            TimestampBoundsMode::Clamp => {
+                if let Some(ref original) = self.cfg.original_field {
+                    record.set(original.clone(), Value::DateTime(timestamp));
+                }
+                record.set(self.cfg.field.clone(), Value::DateTime(now));
+                Verdict::Keep
+            }
+            TimestampBoundsMode::Drop => Verdict::Drop,
+            TimestampBoundsMode::Warn => {
+                if let Some(suppressed) = self.throttle.check_at(Instant::now()) {
+                    actor_warn!(
+                        self.tag,
+                        "{}: timestamp {} is out of bounds ({} similar warnings suppressed)",
+                        self.name,
+                        timestamp.to_rfc3339(),
+                        suppressed
+                    );
+                }
+                Verdict::Keep
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::types::DurationValue,
+        core::{tag::InboundTagId, types::Symbol},
+    };
+
+    fn guard(mode: TimestampBoundsMode, original_field: Option<&str>) -> TimestampGuard {
+        let cfg = TimestampBoundsConfig {
+            field: Symbol::from("timestamp"),
+            max_past: Some(DurationValue::from_secs(3600)),
+            max_future: Some(DurationValue::from_secs(300)),
+            mode,
+            original_field: original_field.map(Symbol::from),
+            max_violation_rate: None,
+            min_records: 100,
+        };
+        TimestampGuard::new(
+            InboundTagId::new("test").into(),
+            "inbound:test(1)".to_string(),
+            cfg,
+        )
+    }
+
+    fn record_at(ts: DateTime<Utc>) -> Record {
+        let mut record = Record::empty();
+        record.set(Symbol::from("timestamp"), Value::DateTime(ts));
+        record
+    }
+
+    fn now() -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000, 0).unwrap()
+    }
+
+    fn past() -> DateTime<Utc> {
+        now() - chrono::Duration::hours(2)
+    }
+
+    fn future() -> DateTime<Utc> {
+        now() + chrono::Duration::minutes(10)
+    }
+
+    #[test]
+    fn test_in_bounds() {
+        let mut guard = guard(TimestampBoundsMode::Drop, None);
+        for ts in [
+            now(),
+            now() - chrono::Duration::minutes(30),
+            now() + chrono::Duration::minutes(1),
+        ] {
+            assert_eq!(guard.check_at(&mut record_at(ts), now()), Verdict::Keep);
+        }
+        assert_eq!(guard.violations, 0);
+
+        // records without a timestamp are not checked
+        assert_eq!(guard.check_at(&mut Record::empty(), now()), Verdict::Keep);
+    }
+
+    #[test]
+    fn test_drop_mode() {
+        let mut guard = guard(TimestampBoundsMode::Drop, None);
+        assert_eq!(guard.check_at(&mut record_at(past()), now()), Verdict::Drop);
+        assert_eq!(
+            guard.check_at(&mut record_at(future()), now()),
+            Verdict::Drop
+        );
+        assert_eq!(guard.violations, 2);
+    }
+
+    #[test]
+    fn test_warn_mode() {
+        let mut guard = guard(TimestampBoundsMode::Warn, None);
+        for ts in [past(), future()] {
+            let mut record = record_at(ts);
+            assert_eq!(guard.check_at(&mut record, now()), Verdict::Keep);
+            assert_eq!(record[&Symbol::from("timestamp")], Value::DateTime(ts));
+        }
+
+        // the first warning has been logged, the next ones within the interval are suppressed
+        assert_eq!(guard.throttle.check_at(Instant::now()), None);
+    }
+
+    #[test]
+    fn test_clamp_mode() {
+        let mut guard = guard(TimestampBoundsMode::Clamp, None);
+        let mut record = record_at(past());
+        assert_eq!(guard.check_at(&mut record, now()), Verdict::Keep);
+        assert_eq!(record[&Symbol::from("timestamp")], Value::DateTime(now()));
+        assert!(record.get(&Symbol::from("original_timestamp")).is_none());
+    }
+
+    #[test]
+    fn test_clamp_mode_keeps_original() {
+        let mut guard = guard(TimestampBoundsMode::Clamp, Some("original_timestamp"));
+        let mut record = record_at(future());
+        assert_eq!(guard.check_at(&mut record, now()), Verdict::Keep);
+        assert_eq!(record[&Symbol::from("timestamp")], Value::DateTime(now()));
+        assert_eq!(
+            record[&Symbol::from("original_timestamp")],
+            Value::DateTime(future())
+        );
+    }
+
+    #[test]
+    fn test_disconnect_on_violation_rate() {
+        let mut guard = guard(TimestampBoundsMode::Clamp, None);
+        guard.cfg.max_violation_rate = Some(0.5);
+        guard.cfg.min_records = 4;
+
+        assert_eq!(guard.check_at(&mut record_at(now()), now()), Verdict::Keep);
+        assert_eq!(guard.check_at(&mut record_at(past()), now()), Verdict::Keep);
+        assert_eq!(guard.check_at(&mut record_at(past()), now()), Verdict::Keep);
+        // 3 violations out of 4 records
+        assert_eq!(
+            guard.check_at(&mut record_at(past()), now()),
+            Verdict::Disconnect
+        );
+    }
+}
@@ -1,15 +1,22 @@
 use std::path::PathBuf;
 
 use async_trait::async_trait;
-use log::info;
 use tokio::{net::UnixListener, task::JoinHandle};
 use tokio_util::sync::CancellationToken;
 
 use crate::{
-    config::{inbound::unix::UnixSocketConfig, ProtocolConfig},
+    actor_error, actor_info, actor_warn,
+    config::{
+        inbound::{limits::ConnectionLimitsConfig, unix::UnixSocketConfig},
+        ProtocolConfig,
+    },
     core::{
         actor::Actor,
-        inbound::instance::ReaderBasedInstance,
+        inbound::{
+            accept::AcceptThrottle,
+            instance::{reap_connections, InstanceOptions, ReaderBasedInstance},
+            limits::LimitedReader,
+        },
         manager::{ChannelGraph, TaggedSender},
         tag::{HasTag, TagId},
     },
@@ -24,13 +31,17 @@ pub(crate) struct UnixSocketInbound {
     tag: TagId,
     path: PathBuf,
 
-    listener: UnixListener,
+    // Bound by `start`
+    listener: Option<UnixListener>,
     ctx: CancellationToken,
 
     connections: Vec<JoinHandle<()>>,
+    accept_throttle: Option<AcceptThrottle>,
 
     outbound: TaggedSender,
     protocol: ProtocolConfig,
+    options: InstanceOptions,
+    limits: ConnectionLimitsConfig,
 }
 
 impl UnixSocketInbound {
@@ -39,44 +50,70 @@ impl UnixSocketInbound {
         protocol_cfg: ProtocolConfig,
         channel_graph: &mut ChannelGraph,
     ) -> Result<Self> {
-        let path = cfg.path;
-
-        if path.exists() {
-            std::fs::remove_file(&path)?;
-        }
+        let tag = cfg.tag.clone().into();
+        let outbound = channel_graph.sender(&tag);
 
-        if let Some(parent) = path.parent() {
-            std::fs::create_dir_all(parent)?;
+        let mut inbound = Self::new(cfg, protocol_cfg, outbound);
+        if !channel_graph.is_dry_run() {
+            inbound.start()?;
         }
 
-        let tag = cfg.tag.into();
+        Ok(inbound)
+    }
 
-        let socket = UnixListener::bind(&path)?;
-        let outbound = channel_graph.sender(&tag);
+    /// Nothing is touched on the filesystem until [`Self::start`].
+    fn new(cfg: UnixSocketConfig, protocol_cfg: ProtocolConfig, outbound: TaggedSender) -> Self {
+        let tag: TagId = cfg.tag.into();
+        let accept_throttle = cfg
+            .accept_throttle
+            .map(|throttle| AcceptThrottle::on_channel(tag.clone(), throttle, outbound.clone()));
 
-        let inbound = UnixSocketInbound {
+        UnixSocketInbound {
             tag,
-            path,
-            listener: socket,
+            path: cfg.path,
+            listener: None,
             ctx: CancellationToken::new(),
             connections: Vec::new(),
+            accept_throttle,
             outbound,
             protocol: protocol_cfg,
-        };
+            options: InstanceOptions {
+                timestamp_bounds: cfg.timestamp_bounds,
+                lifecycle_events: cfg.emit_lifecycle_events,
+                quiet_eof: false,
+            },
+            limits: cfg.limits,
+        }
+    }
 
-        info!(
-            "inbound \"{}\" listening on {:?}",
-            inbound.tag, inbound.path
-        );
+    /// Replace a stale socket file and bind, once.
+    fn start(&mut self) -> Result<()> {
+        if self.listener.is_some() {
+            return Ok(());
+        }
 
-        Ok(inbound)
+        if self.path.exists() {
+            std::fs::remove_file(&self.path)?;
+        }
+
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        self.listener = Some(UnixListener::bind(&self.path)?);
+        actor_info!(self.tag, "listening on {:?}", self.path);
+
+        Ok(())
     }
 }
 
 impl Drop for UnixSocketInbound {
     fn drop(&mut self) {
-        if let Err(e) = std::fs::remove_file(&self.path) {
-            log::error!("Failed to remove socket file: {:?}", e);
+        // The file may belong to someone else until bound
+        if self.listener.is_some() {
+            if let Err(e) = std::fs::remove_file(&self.path) {
+                actor_error!(self.tag, "failed to remove socket file: {:?}", e);
+            }
         }
 
         self.ctx.cancel();
@@ -96,22 +133,44 @@ impl Actor for UnixSocketInbound {
         &mut self,
         ctx: tokio_util::sync::CancellationToken,
     ) -> miette::Result<(), super::Error> {
-        let new_connection = self.listener.accept();
+        // 管道饱和时暂停 accept, 新连接在 listener backlog 中排队
+        if let Some(throttle) = self.accept_throttle.as_mut() {
+            if !throttle.ready(&ctx).await {
+                return Ok(());
+            }
+        }
+
+        self.start()?;
+        let listener = self.listener.as_ref().expect("bound by start");
+        let new_connection = listener.accept();
 
         tokio::select! {
             _ = ctx.cancelled() => return Ok(()),
             Ok((stream, addr)) = new_connection => {
-                info!("inbound \"{}\" accept new connection \"{:?}\" ", self.tag, addr);
+                // Forget the connections already closed, by the peer or for breaking the limits
+                reap_connections(&self.tag, &mut self.connections);
+                if !self.limits.accepts(self.connections.len()) {
+                    actor_warn!(
+                        self.tag,
+                        "reject connection \"{:?}\", {} connections open",
+                        addr,
+                        self.connections.len()
+                    );
+                    return Ok(());
+                }
+                actor_info!(self.tag, "accept new connection \"{:?}\"", addr);
+
                 let handle = ReaderBasedInstance::try_create_from(
                     self.tag.clone(),
                     format!("unix({:?})", addr),
-                    stream,
+                    LimitedReader::new(stream, &self.limits),
                     self.protocol.clone(),
                     self.outbound.clone(),
+                    self.options.clone(),
                     self.ctx.clone(),
                 )?;
                 self.connections.push(handle);
-                info!("inbound \"{}\" spawn a new connection \"{:?}\" ", self.tag, addr);
+                actor_info!(self.tag, "spawn a new connection \"{:?}\"", addr);
             }
         }
 
@@ -120,3 +179,210 @@ impl Actor for UnixSocketInbound {
 }
 
 impl Inbound for UnixSocketInbound {}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{io::AsyncWriteExt, net::UnixStream};
+
+    use super::*;
+    use crate::{
+        config::{
+            inbound::accept::AcceptThrottleConfig, protocol::graphite::GraphiteProtocolConfig,
+            types::DurationValue,
+        },
+        core::{
+            manager::ActorChannel,
+            tag::{InboundTagId, PipeTagId, ProtocolTagId},
+            types::Record,
+        },
+        utils::tracing::TracingContext,
+    };
+
+    #[tokio::test]
+    async fn test_accept_pauses_while_saturated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("void.sock");
+
+        let tag: TagId = InboundTagId::new("unix_socket").into();
+        let mut channel = ActorChannel::new(tag.clone(), 1);
+        // A consumer which does not receive until told to
+        let mut consumer = channel.receiver(&PipeTagId::new("timeseries").into());
+        channel.seal();
+        let mut producer = channel.sender();
+
+        let cfg = UnixSocketConfig {
+            tag: InboundTagId::new("unix_socket"),
+            path: path.clone(),
+            protocol: ProtocolTagId::new("graphite"),
+            disabled: false,
+            channel_overflow: None,
+            timestamp_bounds: None,
+            emit_lifecycle_events: false,
+            accept_throttle: Some(AcceptThrottleConfig {
+                check_interval: DurationValue::from_millis(10),
+                ..Default::default()
+            }),
+            limits: Default::default(),
+        };
+        let protocol = ProtocolConfig::Graphite(GraphiteProtocolConfig {
+            tag: ProtocolTagId::new("graphite"),
+            attributes: None,
+            timezone: chrono_tz::Tz::UTC,
+        });
+        let mut inbound = UnixSocketInbound::new(cfg, protocol, producer.clone());
+        assert!(!path.exists());
+        inbound.start().unwrap();
+        let ctx = CancellationToken::new();
+
+        // Fill the channel up
+        while producer.occupancy() < 1.0 {
+            producer
+                .send(Record::new(TracingContext::new_root()))
+                .await
+                .unwrap();
+        }
+
+        // connect() succeeds thanks to the backlog, but the connection is not accepted
+        let _client = UnixStream::connect(&path).await.unwrap();
+        let poll = tokio::time::timeout(Duration::from_millis(200), inbound.poll(ctx.clone()));
+        assert!(poll.await.is_err(), "accepted while saturated");
+        assert!(inbound.connections.is_empty());
+
+        // Still paused above the resume threshold
+        for _ in 0..32 {
+            consumer.try_recv().unwrap();
+        }
+        let poll = tokio::time::timeout(Duration::from_millis(100), inbound.poll(ctx.clone()));
+        assert!(poll.await.is_err(), "resumed above the resume threshold");
+
+        while consumer.try_recv().is_ok() {}
+        tokio::time::timeout(Duration::from_secs(5), inbound.poll(ctx.clone()))
+            .await
+            .expect("not resumed once drained")
+            .unwrap();
+        assert_eq!(inbound.connections.len(), 1);
+
+        ctx.cancel();
+        inbound.ctx.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_idle_connections_reaped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("void.sock");
+
+        let tag: TagId = InboundTagId::new("unix_socket").into();
+        let mut channel = ActorChannel::new(tag.clone(), 16);
+        let _consumer = channel.receiver(&PipeTagId::new("timeseries").into());
+        channel.seal();
+
+        let cfg = UnixSocketConfig {
+            tag: InboundTagId::new("unix_socket"),
+            path: path.clone(),
+            protocol: ProtocolTagId::new("graphite"),
+            disabled: false,
+            channel_overflow: None,
+            timestamp_bounds: None,
+            emit_lifecycle_events: false,
+            accept_throttle: None,
+            limits: ConnectionLimitsConfig {
+                idle_timeout: Some(DurationValue::from_millis(100)),
+                ..Default::default()
+            },
+        };
+        let protocol = ProtocolConfig::Graphite(GraphiteProtocolConfig {
+            tag: ProtocolTagId::new("graphite"),
+            attributes: None,
+            timezone: chrono_tz::Tz::UTC,
+        });
+        let mut inbound = UnixSocketInbound::new(cfg, protocol, channel.sender());
+        inbound.start().unwrap();
+        let ctx = CancellationToken::new();
+
+        // Connects and goes silent
+        let _silent = UnixStream::connect(&path).await.unwrap();
+        inbound.poll(ctx.clone()).await.unwrap();
+        assert_eq!(inbound.connections.len(), 1);
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(inbound.connections[0].is_finished());
+
+        let _client = UnixStream::connect(&path).await.unwrap();
+        inbound.poll(ctx.clone()).await.unwrap();
+        assert_eq!(inbound.connections.len(), 1);
+        assert!(!inbound.connections[0].is_finished());
+
+        ctx.cancel();
+        inbound.ctx.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_short_connections_reaped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("void.sock");
+
+        let tag: TagId = InboundTagId::new("unix_socket").into();
+        let mut channel = ActorChannel::new(tag.clone(), 1024);
+        let mut consumer = channel.receiver(&PipeTagId::new("timeseries").into());
+        channel.seal();
+
+        let cfg = UnixSocketConfig {
+            tag: InboundTagId::new("unix_socket"),
+            path: path.clone(),
+            protocol: ProtocolTagId::new("graphite"),
+            disabled: false,
+            channel_overflow: None,
+            timestamp_bounds: None,
+            emit_lifecycle_events: false,
+            accept_throttle: None,
+            limits: ConnectionLimitsConfig {
+                max_connections: Some(8),
+                ..Default::default()
+            },
+        };
+        let protocol = ProtocolConfig::Graphite(GraphiteProtocolConfig {
+            tag: ProtocolTagId::new("graphite"),
+            attributes: None,
+            timezone: chrono_tz::Tz::UTC,
+        });
+        let mut inbound = UnixSocketInbound::new(cfg, protocol, channel.sender());
+        inbound.start().unwrap();
+        let ctx = CancellationToken::new();
+
+        for i in 0..200 {
+            let mut client = UnixStream::connect(&path).await.unwrap();
+            inbound.poll(ctx.clone()).await.unwrap();
+            client
+                .write_all(format!("collectd.host.cpu {} 1743667743\n", i).as_bytes())
+                .await
+                .unwrap();
+            drop(client);
+            // Each one is read before the next connects, so none is rejected
+            tokio::time::timeout(Duration::from_secs(5), consumer.recv())
+                .await
+                .expect("record not received")
+                .unwrap();
+            assert!(inbound.connections.len() <= 8);
+        }
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while inbound
+                .connections
+                .iter()
+                .any(|handle| !handle.is_finished())
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("connections not closed");
+        let _client = UnixStream::connect(&path).await.unwrap();
+        inbound.poll(ctx.clone()).await.unwrap();
+        assert_eq!(inbound.connections.len(), 1);
+
+        ctx.cancel();
+        inbound.ctx.cancel();
+    }
+}
@@ -0,0 +1,408 @@
+/*
+Record keys, see [`KeySpec`].
+
+The key is a 128-bit hash of a canonical encoding of the selected values, which only depends on
+their content: map entries are sorted, numbers are written little-endian and lengths as u64, so
+that the same record gives the same key across runs and platforms. Each value is prefixed by its
+type and each variable-sized part by its length, so that different selections never encode to
+the same bytes.
+*/
+
+use miette::Diagnostic;
+use thiserror::Error;
+
+use crate::{
+    config::keying::{FieldPath, KeyHash, KeySpec, MissingField},
+    core::types::{Record, Symbol, Value},
+};
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum Error {
+    #[error("Missing key field: {0}")]
+    MissingField(FieldPath),
+}
+
+pub type Result<T> = miette::Result<T, Error>;
+
+const MISSING: u8 = 0xff;
+
+/// A compiled [`KeySpec`].
+#[derive(Debug, Clone)]
+pub struct KeyExtractor {
+    paths: Vec<CompiledPath>,
+    hash: KeyHash,
+    missing: MissingField,
+}
+
+/// A [`FieldPath`] ready to be looked up in records.
+#[derive(Debug, Clone)]
+pub struct CompiledPath {
+    path: FieldPath,
+    field: Symbol,
+    // Map keys, resolved once
+    segments: Vec<(Value, Option<usize>)>,
+}
+
+impl CompiledPath {
+    pub fn new(path: FieldPath) -> Self {
+        let segments = path
+            .segments()
+            .iter()
+            .map(|s| (Value::from(s.as_str()), s.parse().ok()))
+            .collect();
+
+        Self {
+            field: path.field().clone(),
+            segments,
+            path,
+        }
+    }
+
+    pub fn lookup<'a>(&self, record: &'a Record) -> Option<&'a Value> {
+        let mut value = record.get(&self.field)?;
+        for (key, index) in &self.segments {
+            value = match value {
+                Value::Map(map) => map.get(key)?,
+                Value::Array(array) => array.get((*index)?)?,
+                _ => return None,
+            };
+        }
+        Some(value)
+    }
+}
+
+impl KeyExtractor {
+    pub fn new(spec: KeySpec) -> Self {
+        let name = spec
+            .include_name
+            .then(|| FieldPath::try_from("name".to_string()).expect("valid path"));
+
+        Self {
+            paths: name
+                .into_iter()
+                .chain(spec.fields)
+                .map(CompiledPath::new)
+                .collect(),
+            hash: spec.hash,
+            missing: spec.missing.unwrap_or_default(),
+        }
+    }
+
+    /// The key of a record, `None` if it lacks a field and missing fields are skipped.
+    pub fn key(&self, record: &Record) -> Result<Option<u128>> {
+        let mut buf = Vec::with_capacity(64);
+        for path in &self.paths {
+            match path.lookup(record) {
+                Some(value) => encode(value, &mut buf),
+                None => match self.missing {
+                    MissingField::Empty => buf.push(MISSING),
+                    MissingField::Skip => return Ok(None),
+                    MissingField::Error => return Err(Error::MissingField(path.path.clone())),
+                },
+            }
+        }
+
+        let key = match self.hash {
+            KeyHash::Xxh3 => twox_hash::xxh3::hash128(&buf),
+            KeyHash::Fnv1a => fnv1a_128(&buf),
+        };
+        Ok(Some(key))
+    }
+
+    /// Human-readable form of the key of a record, for logging.
+    pub fn describe(&self, record: &Record) -> String {
+        self.paths
+            .iter()
+            .map(|path| match path.lookup(record) {
+                Some(value) => format!("{}={}", path.path, value),
+                None => format!("{}=<missing>", path.path),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+fn encode_str(s: &str, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&(s.len() as u64).to_le_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn encode_unit(unit: &Option<String>, buf: &mut Vec<u8>) {
+    match unit {
+        Some(unit) => {
+            buf.push(1);
+            encode_str(unit, buf);
+        }
+        None => buf.push(0),
+    }
+}
+
+fn encode(value: &Value, buf: &mut Vec<u8>) {
+    match value {
+        Value::Null => buf.push(0),
+        Value::Bool(b) => buf.extend_from_slice(&[1, *b as u8]),
+        Value::Int(n) => {
+            buf.push(2);
+            buf.extend_from_slice(&n.value.to_le_bytes());
+            encode_unit(&n.unit, buf);
+        }
+        Value::Float(n) => {
+            // Equal floats give the same key: -0.0 is 0.0, and all NaNs are the same
+            let value = if n.value == 0.0 {
+                0.0
+            } else if n.value.is_nan() {
+                f64::NAN
+            } else {
+                n.value
+            };
+            buf.push(3);
+            buf.extend_from_slice(&value.to_bits().to_le_bytes());
+            encode_unit(&n.unit, buf);
+        }
+        Value::String(s) => {
+            buf.push(4);
+            encode_str(s.as_str(), buf);
+        }
+        Value::DateTime(dt) => {
+            buf.push(5);
+            buf.extend_from_slice(&dt.timestamp().to_le_bytes());
+            buf.extend_from_slice(&dt.timestamp_subsec_nanos().to_le_bytes());
+        }
+        Value::Bytes(bytes) => {
+            buf.push(8);
+            buf.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+            buf.extend_from_slice(bytes);
+        }
+        Value::Array(array) => {
+            buf.push(6);
+            buf.extend_from_slice(&(array.len() as u64).to_le_bytes());
+            for value in array {
+                encode(value, buf);
+            }
+        }
+        Value::Map(map) => {
+            // 按编码后的字节排序, 与 HashMap 的遍历顺序无关
+            let mut entries = map
+                .iter()
+                .map(|(k, v)| {
+                    let mut entry = Vec::new();
+                    encode(k, &mut entry);
+                    encode(v, &mut entry);
+                    entry
+                })
+                .collect::<Vec<_>>();
+            entries.sort_unstable();
+
+            buf.push(7);
+            buf.extend_from_slice(&(map.len() as u64).to_le_bytes());
+            for entry in entries {
+                buf.extend_from_slice(&entry);
+            }
+        }
+    }
+}
+
+fn fnv1a_128(data: &[u8]) -> u128 {
+    const OFFSET: u128 = 0x6c62272e07bb014262b821756295c58d;
+    const PRIME: u128 = 0x0000000001000000000000000000013b;
+
+    data.iter()
+        .fold(OFFSET, |hash, &b| (hash ^ b as u128).wrapping_mul(PRIME))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use chrono::DateTime;
+    use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+    use super::*;
+
+    fn spec(fields: &[&str], hash: KeyHash, missing: MissingField) -> KeySpec {
+        KeySpec {
+            fields: fields
+                .iter()
+                .map(|f| FieldPath::try_from(f.to_string()).unwrap())
+                .collect(),
+            include_name: true,
+            hash,
+            missing: Some(missing),
+        }
+    }
+
+    fn labels(entries: &[(&str, &str)]) -> Value {
+        Value::Map(
+            entries
+                .iter()
+                .map(|(k, v)| (Value::from(*k), Value::from(*v)))
+                .collect(),
+        )
+    }
+
+    fn record(name: &str, host: &str, labels: Value) -> Record {
+        let mut record = Record::empty();
+        record.set(Symbol::new("name"), Value::from(name));
+        record.set(Symbol::new("host"), Value::from(host));
+        record.set(Symbol::new("labels"), labels);
+        record.set(
+            Symbol::new("timestamp"),
+            Value::DateTime(DateTime::from_timestamp(1_743_667_743, 0).unwrap()),
+        );
+        record
+    }
+
+    fn key(extractor: &KeyExtractor, record: &Record) -> u128 {
+        extractor.key(record).unwrap().unwrap()
+    }
+
+    // The keys must not change across runs, platforms or versions: they may be persisted or
+    // compared between instances.
+    #[test]
+    fn test_stable_keys() {
+        let record = record("cpu", "node-a", labels(&[("region", "eu"), ("zone", "b")]));
+        let fields = ["host", "labels"];
+
+        let xxh3 = KeyExtractor::new(spec(&fields, KeyHash::Xxh3, MissingField::Empty));
+        let fnv1a = KeyExtractor::new(spec(&fields, KeyHash::Fnv1a, MissingField::Empty));
+        assert_eq!(key(&xxh3, &record), XXH3_KEY);
+        assert_eq!(key(&fnv1a, &record), FNV1A_KEY);
+
+        // Maps do not depend on their insertion order
+        let mut entries = (0..64)
+            .map(|i| (format!("k{}", i), format!("v{}", i)))
+            .collect::<Vec<_>>();
+        let mut keys = HashSet::new();
+        for seed in 0..8 {
+            entries.shuffle(&mut StdRng::seed_from_u64(seed));
+            let map = entries
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str()))
+                .collect::<Vec<_>>();
+            keys.insert(key(&xxh3, &self::record("cpu", "a", labels(&map))));
+        }
+        assert_eq!(keys.len(), 1);
+    }
+
+    const XXH3_KEY: u128 = 0xca5eca1595cd0ad5fdac23d78e34c57e;
+    const FNV1A_KEY: u128 = 0x9c1180e0b39ab9f6ce7e258cb6ae578a;
+
+    #[test]
+    fn test_sensitivity() {
+        let extractor = KeyExtractor::new(spec(
+            &["host", "labels.region"],
+            KeyHash::Xxh3,
+            MissingField::Empty,
+        ));
+        let base = record("cpu", "node-a", labels(&[("region", "eu"), ("zone", "b")]));
+        let base_key = key(&extractor, &base);
+
+        let changed = [
+            record("mem", "node-a", labels(&[("region", "eu"), ("zone", "b")])),
+            record("cpu", "node-b", labels(&[("region", "eu"), ("zone", "b")])),
+            record("cpu", "node-a", labels(&[("region", "us"), ("zone", "b")])),
+            // region missing
+            record("cpu", "node-a", labels(&[("zone", "b")])),
+        ];
+        for record in &changed {
+            assert_ne!(
+                key(&extractor, record),
+                base_key,
+                "{}",
+                extractor.describe(record)
+            );
+        }
+
+        // Not selected
+        let same = record("cpu", "node-a", labels(&[("region", "eu"), ("zone", "c")]));
+        assert_eq!(key(&extractor, &same), base_key);
+
+        // Same value, different type
+        let mut int = base.clone();
+        int.set(Symbol::new("host"), Value::from(1i64));
+        let mut float = base.clone();
+        float.set(Symbol::new("host"), Value::from(1.0));
+        assert_ne!(key(&extractor, &int), key(&extractor, &float));
+
+        // Equal floats are the same key
+        float.set(Symbol::new("host"), Value::from(0.0));
+        let mut negative = base.clone();
+        negative.set(Symbol::new("host"), Value::from(-0.0));
+        assert_eq!(key(&extractor, &float), key(&extractor, &negative));
+    }
+
+    #[test]
+    fn test_random_sensitivity() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let fields = ["a", "b", "c.x", "d.1"];
+        for hash in [KeyHash::Xxh3, KeyHash::Fnv1a] {
+            let extractor = KeyExtractor::new(spec(&fields, hash, MissingField::Empty));
+
+            for _ in 0..1000 {
+                let mut record = Record::empty();
+                record.set(Symbol::new("name"), Value::from("m"));
+                record.set(Symbol::new("a"), Value::from(rng.random::<i64>()));
+                record.set(Symbol::new("b"), Value::from(rng.random::<f64>()));
+                record.set(
+                    Symbol::new("c"),
+                    labels(&[("x", &rng.random::<u32>().to_string())]),
+                );
+                record.set(
+                    Symbol::new("d"),
+                    Value::Array(vec![Value::Null, Value::from(rng.random::<bool>())]),
+                );
+                let before = key(&extractor, &record);
+
+                let mut changed = record.clone();
+                match rng.random_range(0..4) {
+                    0 => changed.set(Symbol::new("a"), Value::from(rng.random::<i64>())),
+                    1 => changed.set(Symbol::new("b"), Value::from(rng.random::<f64>())),
+                    2 => changed.set(Symbol::new("c"), labels(&[("x", "changed")])),
+                    _ => changed.set(Symbol::new("d"), Value::Array(vec![Value::Null])),
+                }
+                if changed.to_string() != record.to_string() {
+                    assert_ne!(key(&extractor, &changed), before);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_no_collisions() {
+        for hash in [KeyHash::Xxh3, KeyHash::Fnv1a] {
+            let extractor = KeyExtractor::new(spec(&["host", "labels"], hash, MissingField::Empty));
+
+            let mut keys = HashMap::new();
+            for i in 0..50_000 {
+                let host = format!("node-{}", i % 500);
+                let zone = (i / 500).to_string();
+                let record = record("cpu", &host, labels(&[("zone", &zone)]));
+                let key = key(&extractor, &record);
+                if let Some(other) = keys.insert(key, i) {
+                    panic!("{:?}: records {} and {} collide", hash, other, i);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_missing_policy() {
+        let bare = record("cpu", "node-a", labels(&[]));
+
+        let empty = KeyExtractor::new(spec(&["labels.region"], KeyHash::Xxh3, MissingField::Empty));
+        let mut null = bare.clone();
+        null.set(Symbol::new("labels"), {
+            let mut map = HashMap::new();
+            map.insert(Value::from("region"), Value::Null);
+            Value::Map(map)
+        });
+        assert_ne!(key(&empty, &bare), key(&empty, &null));
+        assert_eq!(empty.describe(&bare), "name=cpu, labels.region=<missing>");
+
+        let skip = KeyExtractor::new(spec(&["labels.region"], KeyHash::Xxh3, MissingField::Skip));
+        assert!(skip.key(&bare).unwrap().is_none());
+
+        let error = KeyExtractor::new(spec(&["labels.region"], KeyHash::Xxh3, MissingField::Error));
+        assert!(matches!(error.key(&bare), Err(Error::MissingField(_))));
+    }
+}
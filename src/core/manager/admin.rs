@@ -0,0 +1,210 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use log::{error, info};
+use serde::Serialize;
+use tokio::{net::TcpListener, sync::watch, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+
+use super::graph::{GraphSnapshot, GraphView};
+use crate::{
+    core::{metrics, tag::TagId},
+    utils::{liveness::Liveness, stats::STATS_INTERVAL},
+};
+
+#[derive(Clone)]
+struct AdminState {
+    // Replaced by the manager after each reload
+    topology: watch::Receiver<Arc<GraphSnapshot>>,
+    liveness: Arc<Liveness>,
+}
+
+#[derive(Debug, Serialize)]
+struct ActorStatus {
+    tag: TagId,
+    // RFC 3339, null before the first poll
+    last_poll: Option<String>,
+    poll_errors: u64,
+    transform_errors: u64,
+    received: u64,
+    sent: u64,
+    send_failures: u64,
+    stalled: bool,
+}
+
+/// Serve `/topology`, `/topology.dot`, `/status`, `/healthz` and `/metrics` until cancelled.
+pub fn spawn_admin_task(
+    listener: TcpListener,
+    topology: watch::Receiver<Arc<GraphSnapshot>>,
+    liveness: Arc<Liveness>,
+    ctx: CancellationToken,
+) -> JoinHandle<()> {
+    let router = Router::new()
+        .route("/topology", get(topology_json))
+        .route("/topology.dot", get(topology_dot))
+        .route("/status", get(status))
+        .route("/healthz", get(healthz))
+        .route("/metrics", get(prometheus_metrics))
+        .with_state(AdminState { topology, liveness });
+
+    tokio::task::Builder::new()
+        .name("admin")
+        .spawn(async move {
+            if let Ok(addr) = listener.local_addr() {
+                info!("Admin endpoint listening on http://{}", addr);
+            }
+            if let Err(e) = axum::serve(listener, router)
+                .with_graceful_shutdown(ctx.cancelled_owned())
+                .await
+            {
+                error!("Admin endpoint failed: {}", e);
+            }
+        })
+        .expect("Failed to spawn admin task")
+}
+
+async fn topology_json(State(state): State<AdminState>) -> Json<GraphView> {
+    Json(state.topology.borrow().view())
+}
+
+async fn topology_dot(State(state): State<AdminState>) -> impl IntoResponse {
+    let dot = state.topology.borrow().dot.clone();
+    ([(header::CONTENT_TYPE, "text/vnd.graphviz")], dot)
+}
+
+async fn status(State(state): State<AdminState>) -> Json<Vec<ActorStatus>> {
+    let graph = state.topology.borrow().clone();
+    let stalled = state.liveness.stalled(STATS_INTERVAL);
+
+    let status = graph
+        .nodes
+        .iter()
+        .map(|tag| {
+            let metrics = metrics::actor_metrics(tag).snapshot();
+            let last_poll = (metrics.last_poll > 0)
+                .then(|| chrono::DateTime::from_timestamp_millis(metrics.last_poll as i64))
+                .flatten()
+                .map(|at| at.to_rfc3339());
+
+            ActorStatus {
+                tag: tag.clone(),
+                last_poll,
+                poll_errors: metrics.poll_errors,
+                transform_errors: metrics.transform_errors,
+                received: metrics.received,
+                sent: metrics.sent,
+                send_failures: metrics.send_failures,
+                stalled: stalled.contains(tag),
+            }
+        })
+        .collect();
+
+    Json(status)
+}
+
+/// The counters and histograms of the actors, in the Prometheus text exposition format.
+async fn prometheus_metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics::render_prometheus(),
+    )
+}
+
+/// 503 with the stalled actors while any, see [`Liveness::stalled`].
+async fn healthz(State(state): State<AdminState>) -> (StatusCode, String) {
+    let stalled = state.liveness.stalled(STATS_INTERVAL);
+    if stalled.is_empty() {
+        return (StatusCode::OK, "ok".to_string());
+    }
+
+    let stalled = stalled
+        .iter()
+        .map(TagId::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        format!("stalled: {}", stalled),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{inbound::InboundConfig, OutboundConfig},
+        core::manager::ChannelGraph,
+    };
+
+    fn graph() -> ChannelGraph {
+        let inbound: InboundConfig = toml::from_str(
+            "type = \"tcp\"\ntag = \"a\"\naddress = \"127.0.0.1:0\"\nprotocol = \"json\"",
+        )
+        .unwrap();
+        let outbound: OutboundConfig =
+            toml::from_str("type = \"stdio\"\ninbounds = [\"inbound:a\"]").unwrap();
+
+        ChannelGraph::try_create_from(&[inbound], &[], &[outbound]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_admin_endpoint() {
+        let graph = graph();
+        let (_topology, receiver) = watch::channel(Arc::new(graph.snapshot()));
+        let liveness = Arc::new(Liveness::default());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let ctx = CancellationToken::new();
+        let handle = spawn_admin_task(listener, receiver, liveness, ctx.clone());
+
+        let client = reqwest::Client::new();
+        let get = |path: &str| client.get(format!("http://{}{}", addr, path)).send();
+
+        let topology: serde_json::Value =
+            serde_json::from_str(&get("/topology").await.unwrap().text().await.unwrap()).unwrap();
+        let nodes = topology["nodes"].as_array().unwrap();
+        assert_eq!(nodes.len(), 2);
+        assert!(nodes
+            .iter()
+            .any(|n| n["tag"] == "inbound:a" && n["kind"] == "inbound"));
+        assert_eq!(topology["edges"][0]["from"], "inbound:a");
+        assert_eq!(topology["edges"][0]["to"], "outbound:stdio");
+        assert_eq!(topology["edges"][0]["occupancy"], 0.0);
+        assert_eq!(topology["edges"][0]["dropped"], 0);
+
+        let dot = get("/topology.dot").await.unwrap().text().await.unwrap();
+        assert!(dot.starts_with("digraph"), "{}", dot);
+
+        let status: serde_json::Value =
+            serde_json::from_str(&get("/status").await.unwrap().text().await.unwrap()).unwrap();
+        assert_eq!(status.as_array().unwrap().len(), 2);
+        assert!(status[0].get("last_poll").is_some());
+        assert_eq!(status[0]["stalled"], false);
+
+        let healthz = get("/healthz").await.unwrap();
+        assert_eq!(healthz.status(), reqwest::StatusCode::OK);
+        assert_eq!(healthz.text().await.unwrap(), "ok");
+
+        let metrics = get("/metrics").await.unwrap();
+        assert_eq!(metrics.status(), reqwest::StatusCode::OK);
+        assert!(metrics
+            .text()
+            .await
+            .unwrap()
+            .contains("# TYPE void_records_received_total counter"));
+
+        assert_eq!(
+            get("/nope").await.unwrap().status(),
+            reqwest::StatusCode::NOT_FOUND
+        );
+
+        ctx.cancel();
+        handle.await.unwrap();
+    }
+}
@@ -7,17 +7,36 @@ use crate::core::tag::TagId;
 pub enum Error {
     #[error("Protocol not found: {0}")]
     ProtocolNotFound(TagId),
-    #[error("Unknown tag: {0}")]
-    UnknownTag(TagId),
-    #[error("Unknown tag {0} required by {1}")]
+    #[error("Unknown tag {0} in the inbounds of {1}")]
     UnknownTagRequired(TagId, TagId),
     #[error("Duplicate tag: {0}")]
     DuplicateTag(TagId),
+    #[error("Nothing sends to {0}, required by {1}")]
+    NoProducer(TagId, TagId),
+    #[error("{0} sends to its routes only, required by {1}")]
+    RoutedPipe(TagId, TagId),
+    #[error("Nothing receives from {0}, it is in the inbounds of no pipe nor outbound")]
+    NoConsumer(TagId),
+    #[error("{0} receives from nothing, its inbounds are empty")]
+    NoInput(TagId),
+    #[error("Cycle in the dataflow between {}", format_tags(.0))]
+    Cycle(Vec<TagId>),
     #[error(transparent)]
     #[diagnostic(transparent)]
     Actor(#[from] crate::core::actor::Error),
+    #[error("{0} panicked too many times")]
+    TooManyRestarts(TagId),
     #[error(transparent)]
     Join(#[from] tokio::task::JoinError),
+    #[error("Failed to serialize the config: {0}")]
+    Serialize(#[from] serde_json::Error),
+}
+
+fn format_tags(tags: &[TagId]) -> String {
+    tags.iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
 pub type Result<T> = miette::Result<T, Error>;
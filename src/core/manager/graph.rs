@@ -1,37 +1,247 @@
-use log::info;
+use log::{info, warn};
 use petgraph::csr::DefaultIx;
-use std::collections::HashMap;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
 use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
 use tokio::sync::broadcast;
 
-use crate::config::global::{self};
+use crate::config::global::{self, OverflowPolicy};
 use crate::utils::tracing::Direction;
 use crate::{
+    actor_warn,
     config::{inbound::InboundConfig, pipe::PipeConfig, OutboundConfig},
     core::{
+        dead_letter::DeadLetter,
+        metrics::{self, ActorMetrics, INTERNAL_METRICS_TAG},
         tag::{HasTag, TagId},
         types::Record,
     },
 };
 
+// The records dropped by the overflow policy of a channel are logged at most this often
+const OVERFLOW_WARN_INTERVAL: Duration = Duration::from_secs(10);
+// How often a sender blocked on a full channel checks whether it has room again
+const BLOCKED_CHECK_INTERVAL: Duration = Duration::from_millis(1);
+// A sender blocked for longer warns, at most once per `OVERFLOW_WARN_INTERVAL`
+const BLOCKED_WARN_AFTER: Duration = Duration::from_secs(1);
+
+/// Shared by a channel, its senders and its probes.
+#[derive(Debug, Default)]
+struct ChannelState {
+    consumers: AtomicUsize,
+    // Of the producer unless its consumers override it, see `ActorChannel::update_overflow`
+    overflow: spin::Mutex<OverflowPolicy>,
+    // Records dropped by the overflow policy so far
+    dropped: AtomicU64,
+    // Those dropped since the last warning
+    unreported: AtomicU64,
+    last_warned: spin::Mutex<Option<Instant>>,
+    last_blocked_warned: spin::Mutex<Option<Instant>>,
+    // Records each consumer has missed, by consumer
+    edges: spin::Mutex<HashMap<TagId, Arc<AtomicU64>>>,
+}
+
+impl ChannelState {
+    fn overflow(&self) -> OverflowPolicy {
+        *self.overflow.lock()
+    }
+}
+
 #[derive(Debug)]
 pub struct ActorChannel {
     tag: TagId,
 
+    capacity: usize,
+    // Of the producer, `global.channel_overflow` unless it overrides it
+    overflow: OverflowPolicy,
+    // Of the consumers overriding it with their `inbound_overflow`
+    edge_overflows: HashMap<TagId, OverflowPolicy>,
+    state: Arc<ChannelState>,
     sender: Option<broadcast::Sender<Record>>,
-    receiver: broadcast::Receiver<Record>,
+    // Only used to subscribe the consumers, released once the graph is built
+    receiver: Option<broadcast::Receiver<Record>>,
+    // Kept to tell how many records the consumers have yet to receive. It also keeps the channel
+    // open once its producer has stopped, so that the consumers wait instead of failing while
+    // the graph shuts down.
+    probe: broadcast::Sender<Record>,
 }
 
 #[derive(Debug, Clone)]
 pub struct TaggedSender {
     tag: TagId,
+    capacity: usize,
+    state: Arc<ChannelState>,
     sender: broadcast::Sender<Record>,
+    metrics: Arc<ActorMetrics>,
+}
+
+/// The record could not be sent, it is handed back.
+#[derive(Debug, Error)]
+pub enum SendError {
+    /// Dropped by the overflow policy of the channel, which has already counted and logged it
+    #[error("channel full")]
+    Full(Record),
+    #[error("channel closed")]
+    Closed(Record),
 }
 
 impl TaggedSender {
-    pub fn send(&mut self, record: Record) -> Result<usize, broadcast::error::SendError<Record>> {
+    /// Send `record` according to the overflow policy of the channel once it is full: wait for
+    /// room with `block`, drop it with `drop_newest`, overwrite the oldest one with
+    /// `drop_oldest`.
+    pub async fn send(&mut self, record: Record) -> Result<usize, SendError> {
+        if self.state.overflow() == OverflowPolicy::Block {
+            return self.send_with_backpressure(record).await;
+        }
+        self.offer(record)
+    }
+
+    /// Wait for room whatever the overflow policy of the channel, then send `record`. Never
+    /// drops it, for the producers which would rather slow down.
+    pub async fn send_with_backpressure(&mut self, record: Record) -> Result<usize, SendError> {
+        let since = Instant::now();
+        while self.is_full() {
+            if since.elapsed() >= BLOCKED_WARN_AFTER {
+                self.warn_blocked(since);
+            }
+            tokio::time::sleep(BLOCKED_CHECK_INTERVAL).await;
+        }
+        self.deliver(record)
+    }
+
+    /// Same as [`TaggedSender::send`] without ever waiting, a full channel whose policy is
+    /// `block` drops the record like `drop_newest`.
+    pub fn try_send(&mut self, record: Record) -> Result<usize, SendError> {
+        let result = self.offer(record);
+        if let Err(SendError::Full(_)) = result {
+            if self.state.overflow() == OverflowPolicy::Block {
+                self.count_dropped(true);
+            }
+        }
+        result
+    }
+
+    fn is_full(&self) -> bool {
+        // Nothing to wait for without consumers, the spare receiver never receives
+        self.state.consumers.load(Ordering::Relaxed) > 0 && self.sender.len() >= self.capacity
+    }
+
+    /// Send unless the channel is full, the record is handed back with `block` and dropped
+    /// with `drop_newest`.
+    fn offer(&mut self, record: Record) -> Result<usize, SendError> {
+        if self.is_full() {
+            match self.state.overflow() {
+                OverflowPolicy::Block => return Err(SendError::Full(record)),
+                OverflowPolicy::DropNewest => {
+                    self.count_dropped(true);
+                    return Err(SendError::Full(record));
+                }
+                // The consumers count what they skip, see `TaggedReceiver::recv`
+                OverflowPolicy::DropOldest => self.count_dropped(false),
+            }
+        }
+
+        self.deliver(record)
+    }
+
+    fn deliver(&mut self, record: Record) -> Result<usize, SendError> {
         record.mark_timestamp(&self.tag, Direction::Outgoing);
-        self.sender.send(record)
+        match self.sender.send(record) {
+            Ok(receivers) => {
+                self.metrics.count_sent();
+                Ok(receivers)
+            }
+            Err(broadcast::error::SendError(record)) => {
+                self.metrics.count_send_failure();
+                Err(SendError::Closed(record))
+            }
+        }
+    }
+
+    /// Count a record dropped by the overflow policy, warning once per interval at most.
+    /// `missed` when no consumer will receive it.
+    fn count_dropped(&self, missed: bool) {
+        self.state.dropped.fetch_add(1, Ordering::Relaxed);
+        self.state.unreported.fetch_add(1, Ordering::Relaxed);
+        if missed {
+            for dropped in self.state.edges.lock().values() {
+                dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let mut last_warned = self.state.last_warned.lock();
+        if last_warned.is_some_and(|at| at.elapsed() < OVERFLOW_WARN_INTERVAL) {
+            return;
+        }
+        *last_warned = Some(Instant::now());
+
+        let dropped = self.state.unreported.swap(0, Ordering::Relaxed);
+        actor_warn!(
+            self.tag,
+            "channel full, dropped {} records ({})",
+            dropped,
+            self.state.overflow()
+        );
+    }
+
+    fn warn_blocked(&self, since: Instant) {
+        let mut last_warned = self.state.last_blocked_warned.lock();
+        if last_warned.is_some_and(|at| at.elapsed() < OVERFLOW_WARN_INTERVAL) {
+            return;
+        }
+        *last_warned = Some(Instant::now());
+
+        actor_warn!(
+            self.tag,
+            "channel full for {:.1}s, waiting for its slowest consumer",
+            since.elapsed().as_secs_f64()
+        );
+    }
+
+    /// Fraction (0.0 ~ 1.0) of the channel buffer not yet received by the slowest consumer.
+    pub fn occupancy(&self) -> f64 {
+        self.sender.len() as f64 / self.capacity as f64
+    }
+}
+
+/// Reads the occupancy of a channel, for the internal metrics.
+#[derive(Debug, Clone)]
+pub struct ChannelProbe {
+    tag: TagId,
+    capacity: usize,
+    state: Arc<ChannelState>,
+    sender: broadcast::Sender<Record>,
+}
+
+impl ChannelProbe {
+    pub fn tag(&self) -> &TagId {
+        &self.tag
+    }
+
+    /// Same as [`TaggedSender::occupancy`], 0.0 without consumers.
+    pub fn occupancy(&self) -> f64 {
+        if self.state.consumers.load(Ordering::Relaxed) == 0 {
+            return 0.0;
+        }
+        self.sender.len() as f64 / self.capacity as f64
+    }
+
+    /// Number of records dropped by the overflow policy of the channel.
+    pub fn dropped(&self) -> u64 {
+        self.state.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Number of records the consumer `who` has missed, dropped before it received them.
+    pub fn dropped_for(&self, who: &TagId) -> u64 {
+        self.state
+            .edges
+            .lock()
+            .get(who)
+            .map_or(0, |dropped| dropped.load(Ordering::Relaxed))
     }
 }
 
@@ -39,7 +249,11 @@ impl TaggedSender {
 pub struct TaggedReceiver {
     tag: TagId,
     who: TagId,
+    capacity: usize,
     receiver: broadcast::Receiver<Record>,
+    metrics: Arc<ActorMetrics>,
+    // Shared with the channel, see `ChannelProbe::dropped_for`
+    dropped: Arc<AtomicU64>,
 }
 
 impl TaggedReceiver {
@@ -47,15 +261,37 @@ impl TaggedReceiver {
         &self.tag
     }
 
+    /// Number of records sent but not yet received by this consumer.
+    pub fn depth(&self) -> usize {
+        self.receiver.len()
+    }
+
+    /// Fraction (0.0 ~ 1.0) of the channel buffer not yet received by this consumer.
+    pub fn occupancy(&self) -> f64 {
+        self.depth() as f64 / self.capacity as f64
+    }
+
+    /// `Lagged` when records were dropped before this consumer received them, which are
+    /// counted for its edge.
     pub async fn recv(&mut self) -> Result<Record, broadcast::error::RecvError> {
-        let record = self.receiver.recv().await?;
+        let record = self.receiver.recv().await.inspect_err(|e| {
+            if let broadcast::error::RecvError::Lagged(n) = e {
+                self.dropped.fetch_add(*n, Ordering::Relaxed);
+            }
+        })?;
         record.mark_timestamp(&self.who, Direction::Incoming);
+        self.metrics.count_received();
         Ok(record)
     }
 
     pub fn try_recv(&mut self) -> Result<Record, broadcast::error::TryRecvError> {
-        let record = self.receiver.try_recv()?;
+        let record = self.receiver.try_recv().inspect_err(|e| {
+            if let broadcast::error::TryRecvError::Lagged(n) = e {
+                self.dropped.fetch_add(*n, Ordering::Relaxed);
+            }
+        })?;
         record.mark_timestamp(&self.who, Direction::Incoming);
+        self.metrics.count_received();
         Ok(record)
     }
 }
@@ -121,31 +357,143 @@ impl ActorChannel {
         let (sender, receiver) = broadcast::channel(cap);
         info!("Created channel {} with buffer size {}", tag, cap);
 
-        ActorChannel {
+        let channel = ActorChannel {
             tag,
+            // broadcast 通道的容量会向上取整到 2 的幂
+            capacity: cap.next_power_of_two(),
+            overflow: global::channel_overflow(),
+            edge_overflows: HashMap::new(),
+            state: Arc::default(),
+            probe: sender.clone(),
             sender: Some(sender),
-            receiver,
+            receiver: Some(receiver),
+        };
+        channel.update_overflow();
+        channel
+    }
+
+    /// Override `global.channel_overflow`.
+    pub fn with_overflow(mut self, overflow: OverflowPolicy) -> Self {
+        self.set_overflow(overflow);
+        self
+    }
+
+    fn set_overflow(&mut self, overflow: OverflowPolicy) {
+        self.overflow = overflow;
+        self.update_overflow();
+    }
+
+    /// Override the policy of the producer for the consumer `who`, or stop overriding it.
+    pub fn set_edge_overflow(&mut self, who: &TagId, overflow: Option<OverflowPolicy>) {
+        match overflow {
+            Some(overflow) => self.edge_overflows.insert(who.clone(), overflow),
+            None => self.edge_overflows.remove(who),
+        };
+        self.update_overflow();
+    }
+
+    /// A broadcast channel has a single buffer, so the strictest policy of its consumers
+    /// applies to all of them, the producer's for those not overriding it. The senders read it
+    /// whenever the channel is full.
+    fn update_overflow(&self) {
+        let overflow = self
+            .state
+            .edges
+            .lock()
+            .keys()
+            .map(|who| {
+                self.edge_overflows
+                    .get(who)
+                    .copied()
+                    .unwrap_or(self.overflow)
+            })
+            .reduce(OverflowPolicy::strictest)
+            .unwrap_or(self.overflow);
+        *self.state.overflow.lock() = overflow;
+    }
+
+    fn consumers(&self) -> usize {
+        self.state.consumers.load(Ordering::Relaxed)
+    }
+
+    /// Drop the spare receiver once the consumers have subscribed, it never receives
+    /// and would otherwise keep the channel looking full.
+    ///
+    /// A channel without consumers keeps it, so that sending into it is not an error.
+    pub fn seal(&mut self) {
+        if self.consumers() > 0 {
+            self.receiver = None;
         }
     }
 
-    pub fn tag(&self) -> &TagId {
-        &self.tag
+    /// A consumer has stopped. The channel gets its spare receiver back once the last one is
+    /// gone, see [`ActorChannel::seal`].
+    fn unsubscribe(&mut self, who: &TagId) {
+        self.state.edges.lock().remove(who);
+        self.set_edge_overflow(who, None);
+        self.release_receiver();
+    }
+
+    /// A receiver is gone while its consumer keeps receiving through another one, see
+    /// [`ChannelGraph::hand_over`].
+    fn release_receiver(&mut self) {
+        let consumers = self.consumers().saturating_sub(1);
+        self.state.consumers.store(consumers, Ordering::Relaxed);
+        if consumers == 0 && self.receiver.is_none() {
+            self.receiver = Some(self.probe.subscribe());
+        }
+    }
+
+    /// Number of records not yet received by the slowest consumer, 0 without consumers.
+    pub fn depth(&self) -> usize {
+        if self.consumers() == 0 {
+            return 0;
+        }
+        self.probe.len()
+    }
+
+    pub fn probe(&self) -> ChannelProbe {
+        ChannelProbe {
+            tag: self.tag.clone(),
+            capacity: self.capacity,
+            state: self.state.clone(),
+            sender: self.probe.clone(),
+        }
     }
 
     pub fn sender(&mut self) -> TaggedSender {
         let sender = self.sender.take().expect("Sender already taken");
         TaggedSender {
             tag: self.tag.clone(),
+            capacity: self.capacity,
+            state: self.state.clone(),
             sender,
+            metrics: metrics::actor_metrics(&self.tag),
         }
     }
 
-    pub fn receiver(&self, who: &TagId) -> TaggedReceiver {
-        let receiver = self.receiver.resubscribe();
+    pub fn receiver(&mut self, who: &TagId) -> TaggedReceiver {
+        self.state.consumers.fetch_add(1, Ordering::Relaxed);
+        let receiver = match self.receiver {
+            Some(ref receiver) => receiver.resubscribe(),
+            // Subscribing after the graph is sealed, on reload
+            None => self.probe.subscribe(),
+        };
+        let dropped = self
+            .state
+            .edges
+            .lock()
+            .entry(who.clone())
+            .or_default()
+            .clone();
+        self.update_overflow();
         TaggedReceiver {
             tag: self.tag.clone(),
             who: who.clone(),
+            capacity: self.capacity,
             receiver,
+            metrics: metrics::actor_metrics(who),
+            dropped,
         }
     }
 }
@@ -156,6 +504,16 @@ pub struct ChannelGraph {
 
     graph: petgraph::Graph<TagId, (), petgraph::Directed, DefaultIx>,
     tag_2_idx: HashMap<TagId, petgraph::graph::NodeIndex<DefaultIx>>,
+    // Set once the actors have subscribed, later consumers come from a reload
+    sealed: bool,
+    // Shared by the pipes and the outbounds, see `global.dead_letter`
+    dead_letter: Option<TaggedSender>,
+    // The pipes sending to their routes instead of their own channel, see `PipeConfig::routes`
+    routes: HashMap<TagId, Vec<TagId>>,
+    // The consumers overriding the overflow policy of the channels they receive from
+    inbound_overflows: HashMap<TagId, OverflowPolicy>,
+    // The actors are only created to be checked, nothing is bound nor written on disk
+    dry_run: bool,
 }
 
 impl ChannelGraph {
@@ -164,47 +522,265 @@ impl ChannelGraph {
         pipes: &[PipeConfig],
         outbounds: &[OutboundConfig],
     ) -> super::Result<Self> {
-        let tags = inbounds
+        // The producers overriding `global.channel_overflow`, the inbounds block by default
+        let mut overflows = inbounds
+            .iter()
+            .map(|e| {
+                let overflow = e
+                    .channel_overflow()
+                    .unwrap_or_else(global::inbound_channel_overflow);
+                (e.tag().clone(), overflow)
+            })
+            .collect::<HashMap<_, _>>();
+        for pipe in pipes {
+            if let Some(overflow) = pipe.channel_overflow() {
+                for tag in std::iter::once(pipe.tag().clone()).chain(pipe.routes()) {
+                    overflows.insert(tag, overflow);
+                }
+            }
+        }
+
+        let inbounds = inbounds
             .iter()
             .filter(|e| !e.disabled())
-            .map(|e| (e.tag().clone(), 1))
+            .map(|e| e.tag().clone())
+            .collect::<Vec<_>>();
+        let (mut graph, factors) = Self::build(&inbounds, pipes, outbounds)?;
+        for tag in graph.dead_ends() {
+            warn!("Nothing receives from {}, its records are dropped", tag);
+        }
+
+        for (tag, factor) in factors {
+            let mut channel = ActorChannel::new(tag.clone(), factor);
+            if let Some(&overflow) = overflows.get(&tag) {
+                channel = channel.with_overflow(overflow);
+            }
+            graph.channels.insert(tag, channel);
+        }
+
+        let consumers = pipes
+            .iter()
+            .map(|e| (e.tag(), e.inbound_overflow()))
+            .chain(outbounds.iter().map(|e| (e.tag(), e.inbound_overflow())));
+        for (tag, overflow) in consumers {
+            graph.set_inbound_overflow(tag, overflow);
+        }
+
+        Ok(graph)
+    }
+
+    /// Check the dataflow of a reloaded config against the running inbounds, without creating
+    /// any channel.
+    pub fn verify(
+        inbounds: &[TagId],
+        pipes: &[PipeConfig],
+        outbounds: &[OutboundConfig],
+    ) -> super::Result<()> {
+        Self::build(inbounds, pipes, outbounds).map(|_| ())
+    }
+
+    /// The graph without its channels, and the scale factor of each channel.
+    fn build(
+        inbounds: &[TagId],
+        pipes: &[PipeConfig],
+        outbounds: &[OutboundConfig],
+    ) -> super::Result<(Self, Vec<(TagId, usize)>)> {
+        let tags = inbounds
+            .iter()
+            .map(|tag| (tag.clone(), 1))
+            .chain(pipes.iter().filter(|e| !e.disabled()).flat_map(|e| {
+                let factor = e.channel_scale_factor();
+                std::iter::once(e.tag().clone())
+                    .chain(e.routes())
+                    .map(move |tag| (tag, factor))
+            }))
             .chain(
-                pipes
+                outbounds
                     .iter()
                     .filter(|e| !e.disabled())
                     .map(|e| (e.tag().clone(), e.channel_scale_factor())),
             )
+            .collect::<Vec<_>>();
+
+        let mut graph = ChannelGraph {
+            channels: HashMap::new(),
+            graph: petgraph::Graph::new(),
+            tag_2_idx: HashMap::new(),
+            sealed: false,
+            dead_letter: None,
+            routes: HashMap::new(),
+            inbound_overflows: HashMap::new(),
+            dry_run: false,
+        };
+        for (tag, _) in &tags {
+            if graph.tag_2_idx.contains_key(tag) {
+                return Err(super::Error::DuplicateTag(tag.clone()));
+            }
+
+            let node = graph.graph.add_node(tag.clone());
+            graph.tag_2_idx.insert(tag.clone(), node);
+        }
+        for pipe in pipes.iter().filter(|e| !e.disabled()) {
+            let routes = pipe.routes();
+            if !routes.is_empty() {
+                graph.add_routes(pipe.tag(), routes);
+            }
+        }
+
+        let consumers = pipes
+            .iter()
+            .filter(|e| !e.disabled())
+            .map(|e| (e.tag(), e.inbounds()))
             .chain(
                 outbounds
                     .iter()
                     .filter(|e| !e.disabled())
-                    .map(|e| (e.tag().clone(), e.channel_scale_factor())),
-            )
-            .collect::<Vec<_>>();
+                    .map(|e| (e.tag(), e.inbounds())),
+            );
+        // Those receiving from an internal channel, which has no node yet
+        let mut internal_fed = HashSet::new();
+        for (who, inbounds) in consumers {
+            for tag in inbounds {
+                if tag.is_internal() {
+                    internal_fed.insert(who.clone());
+                }
+                graph.add_dataflow(tag, who)?;
+            }
+        }
 
-        let mut graph = petgraph::Graph::<TagId, (), petgraph::Directed, DefaultIx>::new();
-        let mut tag_to_idx = HashMap::new();
+        graph.check_cycles()?;
+        graph.check_orphans(&tags, &internal_fed)?;
+        Ok((graph, tags))
+    }
 
-        let mut channels = HashMap::new();
-        for (tag, factor) in tags {
-            if channels.contains_key(&tag) {
-                return Err(super::Error::DuplicateTag(tag));
-            }
+    /// Add the edge of `who` receiving from `tag`, which must be sent to by an inbound or a pipe.
+    fn add_dataflow(&mut self, tag: &TagId, who: &TagId) -> super::Result<()> {
+        // The internal channels are added once the graph is built
+        if tag.is_internal() {
+            return Ok(());
+        }
+
+        let Some(&src) = self.tag_2_idx.get(tag) else {
+            return Err(super::Error::UnknownTagRequired(tag.clone(), who.clone()));
+        };
+        if !tag.is_inbound() && !tag.is_pipe() {
+            return Err(super::Error::NoProducer(tag.clone(), who.clone()));
+        }
+        if self.routes.contains_key(tag) {
+            return Err(super::Error::RoutedPipe(tag.clone(), who.clone()));
+        }
 
-            let node = graph.add_node(tag.clone());
-            tag_to_idx.insert(tag.clone(), node);
+        let dst = self.tag_2_idx[who];
+        self.graph.update_edge(src, dst, ());
+        Ok(())
+    }
 
-            let channel = ActorChannel::new(tag.clone(), factor);
-            channels.insert(tag, channel);
+    /// A cycle never gets any record, or keeps sending the same ones around.
+    fn check_cycles(&self) -> super::Result<()> {
+        for scc in petgraph::algo::tarjan_scc(&self.graph) {
+            let self_loop = scc.len() == 1 && self.graph.contains_edge(scc[0], scc[0]);
+            if scc.len() > 1 || self_loop {
+                let mut tags = scc
+                    .into_iter()
+                    .map(|idx| self.graph[idx].clone())
+                    .collect::<Vec<_>>();
+                tags.sort_by_key(|tag| tag.to_string());
+                return Err(super::Error::Cycle(tags));
+            }
         }
 
-        let graph = ChannelGraph {
-            channels,
-            graph,
-            tag_2_idx: tag_to_idx,
+        Ok(())
+    }
+
+    /// Every inbound and pipe must be received from, by one of its routes at least for a routed
+    /// pipe, and every pipe and outbound must receive from something.
+    fn check_orphans(
+        &self,
+        tags: &[(TagId, usize)],
+        internal_fed: &HashSet<TagId>,
+    ) -> super::Result<()> {
+        let routes = self.routes.values().flatten().collect::<HashSet<_>>();
+        let has_edge = |tag: &TagId, direction| {
+            self.graph
+                .neighbors_directed(self.tag_2_idx[tag], direction)
+                .next()
+                .is_some()
         };
 
-        Ok(graph)
+        for (tag, _) in tags.iter().filter(|(tag, _)| !routes.contains(tag)) {
+            if !tag.is_inbound()
+                && !internal_fed.contains(tag)
+                && !has_edge(tag, petgraph::Direction::Incoming)
+            {
+                return Err(super::Error::NoInput(tag.clone()));
+            }
+
+            let consumed = match self.routes.get(tag) {
+                Some(routes) => routes
+                    .iter()
+                    .any(|route| has_edge(route, petgraph::Direction::Outgoing)),
+                None => has_edge(tag, petgraph::Direction::Outgoing),
+            };
+            if (tag.is_inbound() || tag.is_pipe()) && !consumed {
+                return Err(super::Error::NoConsumer(tag.clone()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The inbounds, pipes and routes nothing receives from.
+    fn dead_ends(&self) -> Vec<TagId> {
+        let mut tags = self
+            .graph
+            .node_indices()
+            .filter(|idx| {
+                let tag = &self.graph[*idx];
+                (tag.is_inbound() || tag.is_pipe())
+                    && self
+                        .graph
+                        .neighbors_directed(*idx, petgraph::Direction::Outgoing)
+                        .next()
+                        .is_none()
+            })
+            .map(|idx| self.graph[idx].clone())
+            .collect::<Vec<_>>();
+        tags.sort_by_key(|tag| tag.to_string());
+        tags
+    }
+
+    /// The pipes and outbounds receiving from nothing, after a reload removed what fed them.
+    fn starved(&self) -> Vec<TagId> {
+        let mut tags = self
+            .graph
+            .node_indices()
+            .filter(|idx| {
+                let tag = &self.graph[*idx];
+                !tag.is_inbound()
+                    && !tag.is_internal()
+                    && self
+                        .graph
+                        .neighbors_directed(*idx, petgraph::Direction::Incoming)
+                        .next()
+                        .is_none()
+            })
+            .map(|idx| self.graph[idx].clone())
+            .collect::<Vec<_>>();
+        tags.sort_by_key(|tag| tag.to_string());
+        tags
+    }
+
+    /// The channels of the inbounds and pipes whose sender no actor has taken, nothing is ever
+    /// sent into them. Checked once every actor is created, the outbounds never send.
+    pub fn unused_senders(&self) -> Vec<TagId> {
+        let mut tags = self
+            .channels
+            .values()
+            .filter(|channel| !channel.tag.is_outbound() && channel.sender.is_some())
+            .map(|channel| channel.tag.clone())
+            .collect::<Vec<_>>();
+        tags.sort_by_key(|tag| tag.to_string());
+        tags
     }
 
     pub fn sender(&mut self, tag: &TagId) -> TaggedSender {
@@ -216,22 +792,274 @@ impl ChannelGraph {
         channel.sender()
     }
 
+    /// The sender of the route `route` of `tag`, received from as `"<tag>:<route>"`.
+    pub fn sender_for(&mut self, tag: &TagId, route: &str) -> TaggedSender {
+        let mut sender = self.sender(&tag.route(route));
+        // Counted as sent by the pipe itself, the routes are not actors
+        sender.metrics = metrics::actor_metrics(tag);
+        sender
+    }
+
     pub fn recv_from(&mut self, tag: &TagId, who: &TagId) -> TaggedReceiver {
-        let channel = self.channels.get(tag).expect(&format!(
-            "Channel not found in DAG, {} wants to receive from {}",
-            who, tag,
-        ));
+        let channel = self.channels.get_mut(tag).unwrap_or_else(|| {
+            panic!(
+                "Channel not found in DAG, {} wants to receive from {}",
+                who, tag
+            )
+        });
         let receiver = channel.receiver(who);
+        channel.set_edge_overflow(who, self.inbound_overflows.get(who).copied());
+        if self.sealed {
+            channel.seal();
+        }
 
         let src = self.tag_2_idx.get(tag).expect("Tag not found in DAG");
         let dst = self.tag_2_idx.get(who).expect("Tag not found in DAG");
 
-        self.graph.add_edge(*src, *dst, ());
+        self.graph.update_edge(*src, *dst, ());
         info!("Found dataflow {} --> {}", tag, who);
 
         receiver
     }
 
+    /// Add the channel of the internal metrics source, see [`crate::core::metrics`].
+    pub fn add_internal_metrics(&mut self) {
+        self.add_internal(INTERNAL_METRICS_TAG.clone());
+    }
+
+    /// Add the dead letter channel, see [`ChannelGraph::dead_letter`].
+    /// Create the actors without their side effects, see `void validate`. Such actors must not
+    /// be polled.
+    pub fn set_dry_run(&mut self) {
+        self.dry_run = true;
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    pub fn add_dead_letter(&mut self, tag: &TagId) {
+        self.add_internal(tag.clone());
+        self.dead_letter = Some(self.sender(tag));
+    }
+
+    fn add_internal(&mut self, tag: TagId) {
+        let node = self.graph.add_node(tag.clone());
+        self.tag_2_idx.insert(tag.clone(), node);
+        self.channels.insert(tag.clone(), ActorChannel::new(tag, 1));
+    }
+
+    /// Where `who` sends the records it fails on, nowhere without a dead letter channel.
+    pub fn dead_letter(&self, who: &TagId) -> DeadLetter {
+        DeadLetter::new(who.clone(), self.dead_letter.clone())
+    }
+
+    pub fn probes(&self) -> Vec<ChannelProbe> {
+        self.channels.values().map(ActorChannel::probe).collect()
+    }
+
+    /// Called once every actor has subscribed to its inbounds.
+    pub fn seal(&mut self) {
+        self.channels.values_mut().for_each(ActorChannel::seal);
+        self.sealed = true;
+    }
+
+    /// How the channels `who` receives from from now on behave once it falls behind, `None`
+    /// to leave it to their producers. Set before it subscribes, see `inbound_overflow`.
+    pub fn set_inbound_overflow(&mut self, who: &TagId, overflow: Option<OverflowPolicy>) {
+        match overflow {
+            Some(overflow) => self.inbound_overflows.insert(who.clone(), overflow),
+            None => self.inbound_overflows.remove(who),
+        };
+    }
+
+    /// Add the channel of a pipe or an outbound created by a reload, if it is new. The overflow
+    /// policy of an existing one is updated.
+    pub fn add_channel(&mut self, tag: &TagId, factor: usize, overflow: Option<OverflowPolicy>) {
+        let overflow = overflow.unwrap_or_else(global::channel_overflow);
+        if let Some(channel) = self.channels.get_mut(tag) {
+            channel.set_overflow(overflow);
+            return;
+        }
+
+        let node = self.graph.add_node(tag.clone());
+        self.tag_2_idx.insert(tag.clone(), node);
+        let channel = ActorChannel::new(tag.clone(), factor).with_overflow(overflow);
+        self.channels.insert(tag.clone(), channel);
+    }
+
+    /// Add the route channels of a pipe created by a reload, those it no longer has are removed.
+    pub fn add_route_channels(
+        &mut self,
+        tag: &TagId,
+        routes: Vec<TagId>,
+        factor: usize,
+        overflow: Option<OverflowPolicy>,
+    ) {
+        for route in &routes {
+            self.add_channel(route, factor, overflow);
+        }
+        for stale in self.routes.get(tag).cloned().unwrap_or_default() {
+            if !routes.contains(&stale) {
+                self.remove(&stale);
+            }
+        }
+
+        if routes.is_empty() {
+            self.routes.remove(tag);
+        } else {
+            self.add_routes(tag, routes);
+        }
+    }
+
+    fn add_routes(&mut self, tag: &TagId, routes: Vec<TagId>) {
+        let src = self.tag_2_idx[tag];
+        for route in &routes {
+            let dst = self.tag_2_idx[route];
+            self.graph.update_edge(src, dst, ());
+        }
+        self.routes.insert(tag.clone(), routes);
+    }
+
+    /// Forget what `who` receives, once its actor has stopped. Its own channel is kept with a
+    /// new sender, for the actor replacing it.
+    pub fn detach(&mut self, who: &TagId) {
+        let Some(&dst) = self.tag_2_idx.get(who) else {
+            return;
+        };
+
+        let sources = self
+            .graph
+            .neighbors_directed(dst, petgraph::Direction::Incoming)
+            .collect::<Vec<_>>();
+        for src in sources {
+            if let Some(edge) = self.graph.find_edge(src, dst) {
+                self.graph.remove_edge(edge);
+            }
+            if let Some(channel) = self.channels.get_mut(&self.graph[src]) {
+                channel.unsubscribe(who);
+            }
+        }
+
+        self.release_senders(who);
+    }
+
+    fn release_senders(&mut self, who: &TagId) {
+        let routes = self.routes.get(who).cloned().unwrap_or_default();
+        for tag in std::iter::once(who).chain(&routes) {
+            if let Some(channel) = self.channels.get_mut(tag) {
+                channel.sender = Some(channel.probe.clone());
+            }
+        }
+    }
+
+    /// Let the actor replacing `who` be created while the running one keeps going: the edges
+    /// of `who` are left to the new one, which subscribes on its own. Returns what the running
+    /// one receives from, to be handed to [`ChannelGraph::retire`] once it has stopped, or to
+    /// [`ChannelGraph::take_back`] if the new one can't be created.
+    pub fn hand_over(&mut self, who: &TagId) -> Vec<TagId> {
+        let Some(&dst) = self.tag_2_idx.get(who) else {
+            return Vec::new();
+        };
+
+        let sources = self
+            .graph
+            .neighbors_directed(dst, petgraph::Direction::Incoming)
+            .collect::<Vec<_>>();
+        let mut tags = Vec::with_capacity(sources.len());
+        for src in sources {
+            if let Some(edge) = self.graph.find_edge(src, dst) {
+                self.graph.remove_edge(edge);
+            }
+            tags.push(self.graph[src].clone());
+        }
+
+        self.release_senders(who);
+        tags
+    }
+
+    fn has_edge(&self, src: &TagId, dst: &TagId) -> bool {
+        match (self.tag_2_idx.get(src), self.tag_2_idx.get(dst)) {
+            (Some(&src), Some(&dst)) => self.graph.find_edge(src, dst).is_some(),
+            _ => false,
+        }
+    }
+
+    /// Forget the receivers of an actor replaced by a reload, once it has stopped. The channels
+    /// among `sources` the new actor does not receive from lose `who` as a consumer.
+    pub fn retire(&mut self, who: &TagId, sources: &[TagId]) {
+        for src in sources {
+            let replaced = self.has_edge(src, who);
+            if let Some(channel) = self.channels.get_mut(src) {
+                if replaced {
+                    channel.release_receiver();
+                } else {
+                    channel.unsubscribe(who);
+                }
+            }
+        }
+    }
+
+    /// Give the channels of `who` back to the running actor, once the one replacing it failed
+    /// to be created. `sources` is what [`ChannelGraph::hand_over`] returned.
+    pub fn take_back(&mut self, who: &TagId, sources: &[TagId]) {
+        let Some(&dst) = self.tag_2_idx.get(who) else {
+            return;
+        };
+
+        // Subscribed by the failed actor before it was dropped
+        let subscribed = self
+            .graph
+            .neighbors_directed(dst, petgraph::Direction::Incoming)
+            .collect::<Vec<_>>();
+        for src in subscribed {
+            if let Some(edge) = self.graph.find_edge(src, dst) {
+                self.graph.remove_edge(edge);
+            }
+            let tag = self.graph[src].clone();
+            if let Some(channel) = self.channels.get_mut(&tag) {
+                if sources.contains(&tag) {
+                    channel.release_receiver();
+                } else {
+                    channel.unsubscribe(who);
+                }
+            }
+        }
+
+        for src in sources {
+            if let Some(&src) = self.tag_2_idx.get(src) {
+                self.graph.update_edge(src, dst, ());
+            }
+        }
+    }
+
+    /// Remove the channel of a detached actor which is gone for good.
+    pub fn remove(&mut self, tag: &TagId) {
+        for route in self.routes.remove(tag).unwrap_or_default() {
+            self.remove(&route);
+        }
+
+        self.channels.remove(tag);
+        if let Some(idx) = self.tag_2_idx.remove(tag) {
+            self.graph.remove_node(idx);
+            // The last node takes the index of the removed one
+            if let Some(moved) = self.graph.node_weight(idx) {
+                self.tag_2_idx.insert(moved.clone(), idx);
+            }
+        }
+    }
+
+    /// Whether the consumers have received everything sent into these channels.
+    pub fn drained(&self, tags: &[TagId]) -> bool {
+        tags.iter()
+            .filter_map(|tag| self.channels.get(tag))
+            .all(|channel| channel.depth() == 0)
+    }
+
+    pub fn contains(&self, tag: &TagId) -> bool {
+        self.tag_2_idx.contains_key(tag)
+    }
+
     pub fn query_inbounds(&self, tag: &TagId) -> Vec<TagId> {
         let node = self.tag_2_idx.get(tag).expect("Tag not found in DAG");
         let mut inbounds = vec![];
@@ -247,6 +1075,7 @@ impl ChannelGraph {
         inbounds
     }
 
+    #[cfg(test)]
     pub fn query_outbounds(&self, tag: &TagId) -> Vec<TagId> {
         let node = self.tag_2_idx.get(tag).expect("Tag not found in DAG");
         let mut outbounds = vec![];
@@ -262,9 +1091,567 @@ impl ChannelGraph {
         outbounds
     }
 
-    pub fn dump_to_dot(&self) {
-        let graph = petgraph::dot::Dot::with_config(&self.graph, &[]);
-        let graph = format!("{:?}", graph);
-        std::fs::write("graph.dot", graph).expect("Unable to write file");
+    /// The graph in the DOT format. Dead ends are drawn in red, the nodes receiving from
+    /// nothing dashed.
+    pub fn to_dot(&self) -> String {
+        let dead_ends = self.dead_ends();
+        let starved = self.starved();
+        let node_attrs = |_: &_, (_, tag): (_, &TagId)| {
+            let mut attrs = Vec::new();
+            if dead_ends.contains(tag) {
+                attrs.push("color=red");
+            }
+            if starved.contains(tag) {
+                attrs.push("style=dashed");
+            }
+            attrs.join(", ")
+        };
+        let graph = petgraph::dot::Dot::with_attr_getters(
+            &self.graph,
+            &[],
+            &|_, _| String::new(),
+            &node_attrs,
+        );
+        format!("{:?}", graph)
+    }
+
+    /// The dataflow as it is now, for the admin endpoint. The probes keep reading the live
+    /// occupancy of the channels.
+    pub fn snapshot(&self) -> GraphSnapshot {
+        let mut nodes = self.graph.node_weights().cloned().collect::<Vec<_>>();
+        nodes.sort_by_key(|tag| tag.to_string());
+        let mut edges = self
+            .graph
+            .edge_indices()
+            .filter_map(|edge| self.graph.edge_endpoints(edge))
+            .map(|(src, dst)| (self.graph[src].clone(), self.graph[dst].clone()))
+            .collect::<Vec<_>>();
+        edges.sort_by_key(|(src, dst)| (src.to_string(), dst.to_string()));
+
+        GraphSnapshot {
+            nodes,
+            edges,
+            dead_ends: self.dead_ends(),
+            probes: self
+                .probes()
+                .into_iter()
+                .map(|probe| (probe.tag().clone(), probe))
+                .collect(),
+            dot: self.to_dot(),
+        }
+    }
+}
+
+/// See [`ChannelGraph::snapshot`].
+#[derive(Debug, Clone)]
+pub struct GraphSnapshot {
+    pub nodes: Vec<TagId>,
+    // From the producer to the consumer
+    pub edges: Vec<(TagId, TagId)>,
+    pub dead_ends: Vec<TagId>,
+    pub probes: HashMap<TagId, ChannelProbe>,
+    pub dot: String,
+}
+
+impl GraphSnapshot {
+    /// The nodes and the edges with the occupancy of their channel, as served on `/topology`
+    /// and printed by `void graph --format json`.
+    pub fn view(&self) -> GraphView {
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|tag| GraphNode {
+                tag: tag.clone(),
+                kind: tag.scope(),
+                dead_end: self.dead_ends.contains(tag),
+            })
+            .collect();
+        let edges = self
+            .edges
+            .iter()
+            .map(|(from, to)| GraphEdge {
+                from: from.clone(),
+                to: to.clone(),
+                occupancy: self.probes.get(from).map_or(0.0, |p| p.occupancy()),
+                dropped: self.probes.get(from).map_or(0, |p| p.dropped_for(to)),
+            })
+            .collect();
+
+        GraphView { nodes, edges }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct GraphView {
+    nodes: Vec<GraphNode>,
+    edges: Vec<GraphEdge>,
+}
+
+#[derive(Debug, Serialize)]
+struct GraphNode {
+    tag: TagId,
+    kind: &'static str,
+    dead_end: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct GraphEdge {
+    from: TagId,
+    to: TagId,
+    // Of the channel of `from`, between 0 and 1
+    occupancy: f64,
+    // Records `to` has missed, dropped by the overflow policy of the channel
+    dropped: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tag::{InboundTagId, OutboundTagId, PipeTagId};
+
+    fn pipe(tag: &str, inbounds: &str) -> PipeConfig {
+        toml::from_str(&format!(
+            "type = \"filter\"\ntag = \"{}\"\ninbounds = [{}]\nconditions = [{{ field = \"name\", op = \"exists\" }}]",
+            tag, inbounds
+        ))
+        .unwrap()
+    }
+
+    fn inbound(tag: &str) -> InboundConfig {
+        toml::from_str(&format!(
+            "type = \"tcp\"\ntag = \"{}\"\naddress = \"127.0.0.1:0\"\nprotocol = \"json\"",
+            tag
+        ))
+        .unwrap()
+    }
+
+    fn outbound(inbounds: &str) -> OutboundConfig {
+        toml::from_str(&format!("type = \"stdio\"\ninbounds = [{}]", inbounds)).unwrap()
+    }
+
+    fn cycle(err: super::super::Error) -> Vec<String> {
+        match err {
+            super::super::Error::Cycle(tags) => tags.iter().map(ToString::to_string).collect(),
+            other => panic!("unexpected error: {}", other),
+        }
+    }
+
+    #[test]
+    fn test_self_loop() {
+        let err = ChannelGraph::try_create_from(
+            &[inbound("a")],
+            &[pipe("p", "\"inbound:a\", \"pipe:p\"")],
+            &[outbound("\"pipe:p\"")],
+        )
+        .unwrap_err();
+        assert_eq!(cycle(err), vec!["pipe:p"]);
+    }
+
+    #[test]
+    fn test_two_node_cycle() {
+        let err = ChannelGraph::try_create_from(
+            &[inbound("a")],
+            &[
+                pipe("p", "\"inbound:a\", \"pipe:q\""),
+                pipe("q", "\"pipe:p\""),
+            ],
+            &[outbound("\"pipe:q\"")],
+        )
+        .unwrap_err();
+        assert_eq!(cycle(err), vec!["pipe:p", "pipe:q"]);
+    }
+
+    #[test]
+    fn test_dangling_reference() {
+        let err = ChannelGraph::try_create_from(
+            &[inbound("a")],
+            &[pipe("p", "\"inbound:missing\"")],
+            &[outbound("\"pipe:p\"")],
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, super::super::Error::UnknownTagRequired(ref tag, ref who)
+                if tag.to_string() == "inbound:missing" && who.to_string() == "pipe:p"),
+            "{}",
+            err
+        );
+
+        let err = ChannelGraph::try_create_from(
+            &[inbound("a")],
+            &[pipe("p", "\"outbound:stdio\"")],
+            &[outbound("\"pipe:p\"")],
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, super::super::Error::NoProducer(ref tag, _) if tag.to_string() == "outbound:stdio"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_typo() {
+        let err = ChannelGraph::try_create_from(
+            &[inbound("web")],
+            &[pipe("p", "\"inbound:wbe\"")],
+            &[outbound("\"pipe:p\"")],
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown tag inbound:wbe in the inbounds of pipe:p"
+        );
+    }
+
+    #[test]
+    fn test_orphans() {
+        let err = ChannelGraph::try_create_from(
+            &[inbound("a"), inbound("b")],
+            &[pipe("p", "\"inbound:a\"")],
+            &[outbound("\"pipe:p\"")],
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, super::super::Error::NoConsumer(ref tag) if tag.to_string() == "inbound:b"),
+            "{}",
+            err
+        );
+
+        // A pipe with no consumer
+        let err = ChannelGraph::try_create_from(
+            &[inbound("a")],
+            &[pipe("p", "\"inbound:a\""), pipe("q", "\"inbound:a\"")],
+            &[outbound("\"pipe:p\"")],
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, super::super::Error::NoConsumer(ref tag) if tag.to_string() == "pipe:q"),
+            "{}",
+            err
+        );
+
+        let starved: OutboundConfig =
+            toml::from_str("type = \"stdio\"\ntag = \"idle\"\ninbounds = []").unwrap();
+        let err = ChannelGraph::try_create_from(
+            &[inbound("a")],
+            &[pipe("p", "\"inbound:a\"")],
+            &[outbound("\"pipe:p\""), starved],
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, super::super::Error::NoInput(ref tag) if tag.to_string() == "outbound:idle"),
+            "{}",
+            err
+        );
+
+        // Fed by an internal channel, added later
+        let metrics = pipe("m", "\"internal:metrics\"");
+        ChannelGraph::try_create_from(
+            &[inbound("a")],
+            &[pipe("p", "\"inbound:a\""), metrics],
+            &[outbound("\"pipe:p\", \"pipe:m\"")],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_valid_graph() {
+        let mut graph = ChannelGraph::try_create_from(
+            &[inbound("a"), inbound("b")],
+            &[pipe("p", "\"inbound:a\", \"inbound:b\"")],
+            &[outbound("\"pipe:p\"")],
+        )
+        .unwrap();
+        assert!(graph.dead_ends().is_empty());
+        assert!(graph.starved().is_empty());
+        let mut inbounds = graph.query_inbounds(&PipeTagId::new("p").into());
+        inbounds.sort_by_key(ToString::to_string);
+        assert_eq!(
+            inbounds,
+            vec![InboundTagId::new("a").into(), InboundTagId::new("b").into()]
+        );
+
+        // Only the inbound has taken its sender
+        let _sender = graph.sender(&InboundTagId::new("a").into());
+        let unused = graph
+            .unused_senders()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(unused, vec!["inbound:b", "pipe:p"]);
+
+        // Left receiving from nothing once its producer is gone
+        let p: TagId = PipeTagId::new("p").into();
+        graph.detach(&p);
+        assert!(graph.to_dot().contains("color=red"));
+        assert_eq!(graph.starved(), vec![p.clone()]);
+        assert!(graph.to_dot().contains("style=dashed"));
+    }
+
+    #[test]
+    fn test_hand_over() {
+        let (a, b): (TagId, TagId) = (InboundTagId::new("a").into(), InboundTagId::new("b").into());
+        let p: TagId = PipeTagId::new("p").into();
+        let mut graph = ChannelGraph::try_create_from(
+            &[inbound("a"), inbound("b")],
+            &[pipe("p", "\"inbound:a\", \"inbound:b\"")],
+            &[outbound("\"pipe:p\"")],
+        )
+        .unwrap();
+        let running = (graph.recv_from(&a, &p), graph.recv_from(&b, &p));
+        let _sender = graph.sender(&p);
+        graph.seal();
+
+        // The replacement receives from `a` only
+        let sources = graph.hand_over(&p);
+        assert_eq!(sources.len(), 2);
+        let _replacement = (graph.recv_from(&a, &p), graph.sender(&p));
+        drop(running);
+        graph.retire(&p, &sources);
+        assert_eq!(graph.query_inbounds(&p), vec![a.clone()]);
+        assert_eq!(graph.channels[&a].consumers(), 1);
+        assert_eq!(graph.channels[&b].consumers(), 0);
+
+        // A replacement failing to be created leaves the running one as it was
+        let sources = graph.hand_over(&p);
+        let failed = graph.recv_from(&a, &p);
+        drop(failed);
+        graph.take_back(&p, &sources);
+        assert_eq!(graph.query_inbounds(&p), vec![a.clone()]);
+        assert_eq!(graph.channels[&a].consumers(), 1);
+    }
+
+    fn route_pipe() -> PipeConfig {
+        toml::from_str(
+            r#"
+            type = "route"
+            tag = "split"
+            inbounds = ["inbound:a"]
+            default = "other"
+            routes = [{ name = "infra", conditions = [{ field = "name", op = "exists" }] }]
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_routes() {
+        let split: TagId = PipeTagId::new("split").into();
+        let mut graph = ChannelGraph::try_create_from(
+            &[inbound("a")],
+            &[route_pipe()],
+            &[outbound("\"pipe:split:infra\"")],
+        )
+        .unwrap();
+        // The default route is left without a consumer
+        let dead_ends = graph
+            .dead_ends()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(dead_ends, vec!["pipe:split:other"]);
+        let mut routes = graph.query_outbounds(&split);
+        routes.sort_by_key(ToString::to_string);
+        assert_eq!(routes, vec![split.route("infra"), split.route("other")]);
+
+        // Each route has its own channel
+        let mut receiver =
+            graph.recv_from(&split.route("infra"), &OutboundTagId::new("stdio").into());
+        let mut infra = graph.sender_for(&split, "infra");
+        let mut other = graph.sender_for(&split, "other");
+        other.try_send(Record::empty()).unwrap();
+        infra.try_send(Record::empty()).unwrap();
+        assert!(receiver.try_recv().is_ok());
+        assert!(receiver.try_recv().is_err());
+
+        // Gone with the pipe
+        graph.detach(&split);
+        graph.remove(&split);
+        assert!(!graph.contains(&split.route("infra")));
+        assert!(!graph.contains(&split.route("other")));
+
+        let err = ChannelGraph::try_create_from(
+            &[inbound("a")],
+            &[route_pipe()],
+            &[outbound("\"pipe:split\"")],
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, super::super::Error::RoutedPipe(ref tag, _) if *tag == split),
+            "{}",
+            err
+        );
+        let err = ChannelGraph::try_create_from(
+            &[inbound("a")],
+            &[route_pipe()],
+            &[outbound("\"pipe:split:app\"")],
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, super::super::Error::UnknownTagRequired(ref tag, _) if *tag == split.route("app")),
+            "{}",
+            err
+        );
+    }
+
+    /// A channel with a consumer which has yet to receive, filled up.
+    fn full_channel(overflow: OverflowPolicy) -> (TaggedSender, TaggedReceiver, ChannelProbe) {
+        let mut channel =
+            ActorChannel::new(InboundTagId::new("a").into(), 1).with_overflow(overflow);
+        let receiver = channel.receiver(&PipeTagId::new("p").into());
+        channel.seal();
+        let mut sender = channel.sender();
+        for _ in 0..channel.capacity {
+            sender.try_send(Record::empty()).unwrap();
+        }
+        (sender, receiver, channel.probe())
+    }
+
+    #[tokio::test]
+    async fn test_overflow_drop() {
+        let (mut sender, mut receiver, probe) = full_channel(OverflowPolicy::DropNewest);
+        assert!(matches!(
+            sender.send(Record::empty()).await,
+            Err(SendError::Full(_))
+        ));
+        assert!(matches!(
+            sender.try_send(Record::empty()),
+            Err(SendError::Full(_))
+        ));
+        assert_eq!(probe.dropped(), 2);
+        receiver.recv().await.unwrap();
+
+        let (mut sender, mut receiver, probe) = full_channel(OverflowPolicy::DropOldest);
+        sender.send(Record::empty()).await.unwrap();
+        assert_eq!(probe.dropped(), 1);
+        // The consumer skips what was overwritten
+        assert!(matches!(
+            receiver.recv().await,
+            Err(broadcast::error::RecvError::Lagged(1))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_overflow_block() {
+        let (mut sender, mut receiver, probe) = full_channel(OverflowPolicy::Block);
+        let blocked = tokio::spawn(async move { sender.send(Record::empty()).await.map(|_| ()) });
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!blocked.is_finished());
+
+        // Room for one more once the consumer received one
+        receiver.recv().await.unwrap();
+        blocked.await.unwrap().unwrap();
+        assert_eq!(probe.dropped(), 0);
+
+        // Dropped when the caller can't wait
+        let (mut sender, _receiver, probe) = full_channel(OverflowPolicy::Block);
+        assert!(matches!(
+            sender.try_send(Record::empty()),
+            Err(SendError::Full(_))
+        ));
+        assert_eq!(probe.dropped(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_send_with_backpressure() {
+        let (mut sender, mut receiver, probe) = full_channel(OverflowPolicy::DropNewest);
+        let blocked = tokio::spawn(async move {
+            sender
+                .send_with_backpressure(Record::empty())
+                .await
+                .map(|_| ())
+        });
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!blocked.is_finished());
+
+        receiver.recv().await.unwrap();
+        blocked.await.unwrap().unwrap();
+        assert_eq!(probe.dropped(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_edge_overflow() {
+        let a: TagId = InboundTagId::new("a").into();
+        let (p, q): (TagId, TagId) = (PipeTagId::new("p").into(), PipeTagId::new("q").into());
+        let mut channel = ActorChannel::new(a, 1).with_overflow(OverflowPolicy::DropOldest);
+        let mut fast = channel.receiver(&p);
+        let mut slow = channel.receiver(&q);
+        channel.seal();
+        let probe = channel.probe();
+        let mut sender = channel.sender();
+
+        // The strictest of the consumers applies to the whole channel
+        channel.set_edge_overflow(&p, Some(OverflowPolicy::DropNewest));
+        assert_eq!(channel.state.overflow(), OverflowPolicy::DropNewest);
+        channel.set_edge_overflow(&q, Some(OverflowPolicy::Block));
+        assert_eq!(channel.state.overflow(), OverflowPolicy::Block);
+        channel.set_edge_overflow(&q, None);
+        assert_eq!(channel.state.overflow(), OverflowPolicy::DropNewest);
+
+        for _ in 0..channel.capacity {
+            sender.try_send(Record::empty()).unwrap();
+        }
+        while fast.try_recv().is_ok() {}
+
+        // Missed by both consumers
+        assert!(matches!(
+            sender.send(Record::empty()).await,
+            Err(SendError::Full(_))
+        ));
+        assert_eq!((probe.dropped_for(&p), probe.dropped_for(&q)), (1, 1));
+
+        // Only the slow one skips what is overwritten
+        channel.set_edge_overflow(&p, None);
+        assert_eq!(channel.state.overflow(), OverflowPolicy::DropOldest);
+        sender.send(Record::empty()).await.unwrap();
+        fast.recv().await.unwrap();
+        assert!(matches!(
+            slow.recv().await,
+            Err(broadcast::error::RecvError::Lagged(1))
+        ));
+        assert_eq!((probe.dropped_for(&p), probe.dropped_for(&q)), (1, 2));
+
+        channel.unsubscribe(&q);
+        assert_eq!(probe.dropped_for(&q), 0);
+    }
+
+    #[test]
+    fn test_inbound_overflow_config() {
+        let mut outbound = outbound("\"inbound:a\", \"pipe:p\"");
+        if let OutboundConfig::Stdio(ref mut cfg) = outbound {
+            cfg.inbound_overflow = Some(OverflowPolicy::DropNewest);
+        }
+        let mut graph = ChannelGraph::try_create_from(
+            &[inbound("a")],
+            &[pipe("p", "\"inbound:a\"")],
+            &[outbound],
+        )
+        .unwrap();
+
+        // The inbounds block unless told otherwise, the pipes drop the oldest records
+        let a: TagId = InboundTagId::new("a").into();
+        let p: TagId = PipeTagId::new("p").into();
+        assert_eq!(graph.channels[&a].state.overflow(), OverflowPolicy::Block);
+        assert_eq!(
+            graph.channels[&p].state.overflow(),
+            OverflowPolicy::DropOldest
+        );
+
+        let stdio: TagId = OutboundTagId::new("stdio").into();
+        let _receiver = graph.recv_from(&p, &stdio);
+        assert_eq!(
+            graph.channels[&p].state.overflow(),
+            OverflowPolicy::DropNewest
+        );
+        let _receiver = graph.recv_from(&a, &stdio);
+        assert_eq!(
+            graph.channels[&a].state.overflow(),
+            OverflowPolicy::DropNewest
+        );
+        // The pipe keeps the policy of the inbound, stricter than the outbound's
+        let _receiver = graph.recv_from(&a, &p);
+        assert_eq!(graph.channels[&a].state.overflow(), OverflowPolicy::Block);
     }
 }
@@ -1,45 +1,164 @@
+mod admin;
 pub mod error;
 mod graph;
+mod reload;
+mod shutdown;
+mod supervisor;
 
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
-use futures::{StreamExt, TryFutureExt};
+use async_trait::async_trait;
+use tokio::{
+    sync::{mpsc, watch},
+    task::JoinHandle,
+};
 use tokio_util::sync::CancellationToken;
 
 use crate::{
-    config::Config,
+    config::{global, Config},
     core::{
         actor,
         inbound::{self, Inbound},
+        metrics::InternalMetricsSource,
         outbound,
         pipe::{self},
+        tag::TagId,
     },
     timeit,
+    utils::liveness::Liveness,
 };
-use log::info;
+use log::{error, info, warn};
 
+#[cfg(unix)]
+use crate::utils::systemd;
 pub use error::{Error, Result};
-pub use graph::{ChannelGraph, TaggedReceiver, TaggedSender};
+#[cfg(test)]
+pub(crate) use graph::ActorChannel;
+use graph::GraphSnapshot;
+pub use graph::{ChannelGraph, ChannelProbe, SendError, TaggedReceiver, TaggedSender};
+pub use reload::spawn_reload_task;
+use reload::Topology;
+use shutdown::Stage;
+use supervisor::Supervisor;
 
 use super::{outbound::Outbound, pipe::Pipe, tag::HasTag};
 
+/// An actor owned by the manager.
+pub enum ManagedActor {
+    Inbound(Box<dyn Inbound + 'static>),
+    Pipe(Box<dyn Pipe + 'static>),
+    Outbound(Box<dyn Outbound + 'static>),
+}
+
+impl HasTag for ManagedActor {
+    fn tag(&self) -> &TagId {
+        match self {
+            ManagedActor::Inbound(inbound) => inbound.tag(),
+            ManagedActor::Pipe(pipe) => pipe.tag(),
+            ManagedActor::Outbound(outbound) => outbound.tag(),
+        }
+    }
+}
+
+#[async_trait]
+impl actor::Actor for ManagedActor {
+    type Error = actor::Error;
+
+    async fn poll(&mut self, ctx: CancellationToken) -> miette::Result<(), actor::Error> {
+        match self {
+            ManagedActor::Inbound(inbound) => inbound.poll(ctx).await?,
+            ManagedActor::Pipe(pipe) => pipe.poll(ctx).await?,
+            ManagedActor::Outbound(outbound) => outbound.poll(ctx).await?,
+        }
+        Ok(())
+    }
+
+    fn has_pending(&self) -> bool {
+        match self {
+            ManagedActor::Inbound(inbound) => inbound.has_pending(),
+            ManagedActor::Pipe(pipe) => pipe.has_pending(),
+            ManagedActor::Outbound(outbound) => outbound.has_pending(),
+        }
+    }
+}
+
+/// A spawned actor, with its own token so that a reload can stop it alone. The actor is handed
+/// back by its handle when a poll panics.
+struct Running {
+    ctx: CancellationToken,
+    handle: JoinHandle<Option<Box<ManagedActor>>>,
+}
+
+/// The admin endpoint, see [`admin::spawn_admin_task`].
+struct Admin {
+    topology: watch::Sender<Arc<GraphSnapshot>>,
+    ctx: CancellationToken,
+    handle: JoinHandle<()>,
+}
+
 pub struct Manager {
-    inbounds: Vec<Box<dyn Inbound + 'static>>,
-    pipes: Vec<Box<dyn Pipe + 'static>>,
-    outbounds: Vec<Box<dyn Outbound + 'static>>,
+    // Created, not yet spawned
+    actors: HashMap<TagId, ManagedActor>,
+    running: HashMap<TagId, Running>,
     // We hold the channels here to prevent them from being dropped
     // before the pipes are done using them.
     channel_graph: ChannelGraph,
+
+    // Cancelled in order on shutdown, so that the records in flight reach the outbounds
+    inbound_stage: Stage,
+    pipe_stage: Stage,
+    outbound_stage: Stage,
+
+    // The running config, None when the manager is not built from a config
+    topology: Option<Topology>,
+    reloads: Option<mpsc::Receiver<Config>>,
+
+    supervisor: Supervisor,
+    liveness: Arc<Liveness>,
+    admin: Option<Admin>,
+    // systemd is only notified on unix, there is no notify socket elsewhere
+    #[cfg(unix)]
+    notifier: Option<Arc<systemd::Notifier>>,
+}
+
+/// Build and check the data flow of the config, nothing is created or bound.
+pub fn channel_graph_from_config(cfg: &Config) -> Result<ChannelGraph> {
+    let mut channel_graph =
+        ChannelGraph::try_create_from(&cfg.inbounds, &cfg.pipes, &cfg.outbounds)?;
+    if cfg.global.internal_metrics.is_some() {
+        channel_graph.add_internal_metrics();
+    }
+    if let Some(ref dead_letter) = cfg.global.dead_letter {
+        channel_graph.add_dead_letter(dead_letter);
+    }
+
+    Ok(channel_graph)
 }
 
 pub fn try_create_from_config(cfg: Config) -> Result<Manager> {
+    let mut mgr = create_from_config(cfg, false)?;
+    mgr.connect_systemd();
+    Ok(mgr)
+}
+
+/// Create every actor of the config without binding any address nor touching the disk, to
+/// check the config as a whole. The manager returned is only meant to be inspected.
+pub fn dry_run_from_config(cfg: Config) -> Result<Manager> {
+    create_from_config(cfg, true)
+}
+
+fn create_from_config(cfg: Config, dry_run: bool) -> Result<Manager> {
     info!("Creating manager from config...");
 
+    let topology = Topology::new(&cfg)?;
     let mut channel_graph = timeit! { "Creating channel graph", {
-            ChannelGraph::try_create_from(&cfg.inbounds, &cfg.pipes, &cfg.outbounds)?
+            channel_graph_from_config(&cfg)?
     }};
+    if dry_run {
+        channel_graph.set_dry_run();
+    }
 
-    let inbounds = timeit! { "Creating inbounds", {
+    let mut inbounds = timeit! { "Creating inbounds", {
         let protocols = cfg
             .protocols
             .into_iter()
@@ -85,12 +204,21 @@ pub fn try_create_from_config(cfg: Config) -> Result<Manager> {
             .collect::<Result<Vec<_>>>()?
     }};
 
-    let mgr = Manager {
-        inbounds,
-        pipes,
-        outbounds,
-        channel_graph,
-    };
+    if let Some(internal_metrics) = cfg.global.internal_metrics {
+        let source = InternalMetricsSource::try_create_from(internal_metrics, &mut channel_graph);
+        inbounds.push(Box::new(source));
+    }
+
+    channel_graph.seal();
+    for tag in channel_graph.unused_senders() {
+        warn!(
+            "Nothing sends to {}, its consumers never receive anything",
+            tag
+        );
+    }
+
+    let mut mgr = Manager::new(channel_graph, inbounds, pipes, outbounds);
+    mgr.topology = Some(topology);
 
     info!(
         "Total interned strings: {}",
@@ -101,34 +229,931 @@ pub fn try_create_from_config(cfg: Config) -> Result<Manager> {
 }
 
 impl Manager {
-    pub async fn run(self, ctx: CancellationToken) -> Result<()> {
-        info!("Starting manager...");
-
-        let mut handles = vec![];
+    fn new(
+        channel_graph: ChannelGraph,
+        inbounds: Vec<Box<dyn Inbound + 'static>>,
+        pipes: Vec<Box<dyn Pipe + 'static>>,
+        outbounds: Vec<Box<dyn Outbound + 'static>>,
+    ) -> Self {
+        let actors = inbounds
+            .into_iter()
+            .map(ManagedActor::Inbound)
+            .chain(pipes.into_iter().map(ManagedActor::Pipe))
+            .chain(outbounds.into_iter().map(ManagedActor::Outbound))
+            .map(|actor| (actor.tag().clone(), actor))
+            .collect();
 
-        let outbounds = self.outbounds;
-        for outbound in outbounds {
-            let handle = actor::spawn(outbound, ctx.child_token());
-            handles.push(handle);
+        Manager {
+            actors,
+            running: HashMap::new(),
+            channel_graph,
+            inbound_stage: Stage::new("inbounds"),
+            pipe_stage: Stage::new("pipes"),
+            outbound_stage: Stage::new("outbounds"),
+            topology: None,
+            reloads: None,
+            supervisor: Supervisor::new(global::restart_policy()),
+            liveness: Arc::new(Liveness::default()),
+            admin: None,
+            #[cfg(unix)]
+            notifier: None,
         }
+    }
+
+    /// The actors created and not yet spawned, by kind then tag.
+    pub fn components(&self) -> Vec<(&'static str, &TagId)> {
+        let mut components = self
+            .actors
+            .values()
+            .map(|actor| {
+                let kind = match actor {
+                    ManagedActor::Inbound(_) => "inbound",
+                    ManagedActor::Pipe(_) => "pipe",
+                    ManagedActor::Outbound(_) => "outbound",
+                };
+                (kind, actor.tag())
+            })
+            .collect::<Vec<_>>();
+        components.sort_by_key(|(kind, tag)| {
+            let rank = ["inbound", "pipe", "outbound"]
+                .iter()
+                .position(|k| k == kind);
+            (rank, tag.to_string())
+        });
+        components
+    }
 
-        let pipes = self.pipes;
-        for pipe in pipes {
-            let handle = actor::spawn(pipe, ctx.child_token());
-            handles.push(handle);
+    pub fn snapshot(&self) -> GraphSnapshot {
+        self.channel_graph.snapshot()
+    }
+
+    /// Notify systemd when started by it, nothing to notify outside of unix.
+    fn connect_systemd(&mut self) {
+        #[cfg(unix)]
+        {
+            self.notifier = systemd::Notifier::from_env().map(Arc::new);
         }
+    }
+
+    /// Apply the configs received, see [`spawn_reload_task`].
+    pub fn with_reloads(mut self, reloads: mpsc::Receiver<Config>) -> Self {
+        self.reloads = Some(reloads);
+        self
+    }
+
+    pub async fn run(mut self, ctx: CancellationToken) -> Result<()> {
+        info!("Starting manager...");
 
-        let inbounds = self.inbounds;
-        for inbound in inbounds {
-            let handle = actor::spawn(inbound, ctx.child_token());
-            handles.push(handle);
+        let actors = std::mem::take(&mut self.actors);
+        for actor in actors.into_values() {
+            self.spawn(actor);
         }
 
         crate::utils::spawn_tracing_task();
+        crate::utils::spawn_stats_task();
+        crate::utils::spawn_interner_report_task();
+
+        #[cfg(unix)]
+        if let Some(notifier) = self.notifier.clone() {
+            systemd::spawn_systemd_task(notifier, self.liveness.clone(), ctx.clone());
+        }
+        if let Some(addr) = global::admin_listen() {
+            self.start_admin(addr, &ctx).await;
+        }
+
+        // Set when an actor cannot be restarted, the others are shut down as usual
+        let mut failure = None;
+        loop {
+            // The actors only stop when cancelled, unless one of them panics
+            if self.running.is_empty() {
+                return Ok(());
+            }
+
+            let tags = self.running.keys().cloned().collect::<Vec<_>>();
+            let finished =
+                futures::future::select_all(self.running.values_mut().map(|r| &mut r.handle));
+            let reload = async {
+                match self.reloads {
+                    Some(ref mut reloads) => reloads.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+
+            tokio::select! {
+                (r, idx, _) = finished => {
+                    let tag = &tags[idx];
+                    self.running.remove(tag);
+                    match r {
+                        Ok(None) => {}
+                        Ok(Some(actor)) if self.supervisor.restart(tag) => {
+                            warn!("{}: restarting after a panic", tag);
+                            self.liveness.unregister(tag);
+                            self.spawn(*actor);
+                        }
+                        Ok(Some(_)) => {
+                            error!("{}: panicked too often, shutting down", tag);
+                            self.release(tag);
+                            failure = Some(Error::TooManyRestarts(tag.clone()));
+                            break;
+                        }
+                        Err(err) => {
+                            failure = Some(Error::Join(err));
+                            break;
+                        }
+                    }
+                }
+                Some(cfg) = reload => self.reload(cfg).await,
+                _ = ctx.cancelled() => break,
+            }
+        }
+
+        let drain_timeout = global::drain_timeout();
+        info!(
+            "Shutting down, draining the records in flight for up to {}",
+            drain_timeout
+        );
+        let deadline = tokio::time::Instant::now() + drain_timeout.get();
+        self.inbound_stage.cancel();
+        self.pipe_stage
+            .drain_and_cancel(&self.channel_graph, deadline)
+            .await;
+        self.outbound_stage
+            .drain_and_cancel(&self.channel_graph, deadline)
+            .await;
 
         // Wait for all handles to finish
-        futures::future::try_join_all(handles).await?;
+        futures::future::try_join_all(self.running.into_values().map(|r| r.handle)).await?;
 
-        Ok(())
+        if let Some(admin) = self.admin {
+            admin.ctx.cancel();
+            admin.handle.await?;
+        }
+
+        match failure {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Serve the admin endpoint, the pipeline runs without it if the address cannot be bound.
+    async fn start_admin(&mut self, addr: std::net::SocketAddr, ctx: &CancellationToken) {
+        let listener = match tokio::net::TcpListener::bind(addr).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Failed to bind the admin endpoint to {}: {}", addr, e);
+                return;
+            }
+        };
+
+        let (topology, receiver) = watch::channel(Arc::new(self.channel_graph.snapshot()));
+        let ctx = ctx.child_token();
+        let handle =
+            admin::spawn_admin_task(listener, receiver, self.liveness.clone(), ctx.clone());
+        self.admin = Some(Admin {
+            topology,
+            ctx,
+            handle,
+        });
+    }
+
+    fn spawn(&mut self, actor: ManagedActor) {
+        let tag = actor.tag().clone();
+
+        // Pipes and outbounds poll with a timeout, so that a poll which never ends means a
+        // stuck actor. Inbounds wait for their producers and are not watched.
+        let (ctx, heartbeat) = match actor {
+            ManagedActor::Inbound(_) => (self.inbound_stage.ctx().child_token(), None),
+            ManagedActor::Pipe(_) => {
+                let heartbeat = self.liveness.register(tag.clone());
+                self.pipe_stage
+                    .watch(&self.channel_graph, &tag, heartbeat.clone());
+                (self.pipe_stage.ctx().child_token(), Some(heartbeat))
+            }
+            ManagedActor::Outbound(_) => {
+                let heartbeat = self.liveness.register(tag.clone());
+                self.outbound_stage
+                    .watch(&self.channel_graph, &tag, heartbeat.clone());
+                (self.outbound_stage.ctx().child_token(), Some(heartbeat))
+            }
+        };
+
+        let handle = actor::spawn(Box::new(actor), ctx.clone(), heartbeat);
+        self.running.insert(tag, Running { ctx, handle });
+    }
+
+    /// Cancel an actor removed by a reload, and wait for it to release its channels.
+    async fn stop(&mut self, tag: &TagId) {
+        self.cancel(tag).await;
+        self.release(tag);
+    }
+
+    /// Cancel an actor replaced by a reload. The channels it receives from are kept for the
+    /// new one, see [`ChannelGraph::hand_over`].
+    async fn replace(&mut self, tag: &TagId, sources: &[TagId]) {
+        self.cancel(tag).await;
+        self.forget(tag);
+        self.channel_graph.retire(tag, sources);
+    }
+
+    async fn cancel(&mut self, tag: &TagId) {
+        if let Some(running) = self.running.remove(tag) {
+            running.ctx.cancel();
+            if let Err(err) = running.handle.await {
+                warn!("{}: failed while stopping: {}", tag, err);
+            }
+        }
+    }
+
+    /// Forget a stopped actor, so that the shutdown does not wait for it.
+    fn release(&mut self, tag: &TagId) {
+        self.forget(tag);
+        self.channel_graph.detach(tag);
+    }
+
+    fn forget(&mut self, tag: &TagId) {
+        self.pipe_stage.unwatch(tag);
+        self.outbound_stage.unwatch(tag);
+        self.liveness.unregister(tag);
+        self.supervisor.forget(tag);
+    }
+
+    /// Apply a reloaded config. Only the pipes and the outbounds change, the others keep
+    /// running as they are.
+    async fn reload(&mut self, cfg: Config) {
+        let Some(running) = self.topology.take() else {
+            warn!("Not built from a config, ignoring the reload");
+            return;
+        };
+
+        let log = cfg.global.log.clone();
+        let (mut topology, diff) = match running.reload(cfg) {
+            Ok(reload) => reload,
+            Err(err) => {
+                error!(
+                    "Rejected the reloaded config, keeping the running one: {:?}",
+                    miette::Report::new(err)
+                );
+                self.topology = Some(running);
+                return;
+            }
+        };
+        // Unlike the rest of the global config, the log levels apply on reload
+        crate::utils::logging::apply(&log);
+        if diff.is_empty() {
+            info!("Reloaded config, nothing has changed");
+            self.topology = Some(running);
+            return;
+        }
+        info!("Reloading config: {}", diff);
+
+        // The channels first, the new actors may receive from each other
+        let started = diff.changed.iter().chain(&diff.added).collect::<Vec<_>>();
+        for tag in &started {
+            if let Some(cfg) = topology.pipe(tag) {
+                let (factor, overflow) = (cfg.channel_scale_factor(), cfg.channel_overflow());
+                self.channel_graph.add_channel(tag, factor, overflow);
+                self.channel_graph
+                    .add_route_channels(tag, cfg.routes(), factor, overflow);
+                self.channel_graph
+                    .set_inbound_overflow(tag, cfg.inbound_overflow());
+            } else if let Some(cfg) = topology.outbound(tag) {
+                self.channel_graph
+                    .add_channel(tag, cfg.channel_scale_factor(), None);
+                self.channel_graph
+                    .set_inbound_overflow(tag, cfg.inbound_overflow());
+            }
+        }
+
+        // Created while the running actors keep going, those replaced are only stopped once
+        // their replacement exists
+        let mut replacements = Vec::with_capacity(started.len());
+        for tag in started {
+            let changed = diff.changed.contains(tag);
+            let sources = if changed {
+                self.channel_graph.hand_over(tag)
+            } else {
+                Vec::new()
+            };
+
+            match topology.create(tag, &mut self.channel_graph) {
+                Some(Err(err)) if changed => {
+                    error!(
+                        "{}: failed to start on reload, keeping the running one: {:?}",
+                        tag,
+                        miette::Report::new(err)
+                    );
+                    self.channel_graph.take_back(tag, &sources);
+                    topology.revert(&running, tag);
+                }
+                // Retried once its config changes again
+                Some(Err(err)) => error!(
+                    "{}: failed to start on reload: {:?}",
+                    tag,
+                    miette::Report::new(err)
+                ),
+                Some(Ok(actor)) => replacements.push((tag, sources, Some(actor))),
+                // Disabled, only stopped
+                None => replacements.push((tag, sources, None)),
+            }
+        }
+
+        for tag in &diff.removed {
+            self.stop(tag).await;
+            self.channel_graph.remove(tag);
+        }
+        for (tag, sources, actor) in replacements {
+            self.replace(tag, &sources).await;
+            if let Some(actor) = actor {
+                info!("{}: started by reload", tag);
+                self.spawn(actor);
+            }
+        }
+
+        self.topology = Some(topology);
+        if let Some(ref admin) = self.admin {
+            admin
+                .topology
+                .send_replace(Arc::new(self.channel_graph.snapshot()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+    #[cfg(unix)]
+    use tokio::net::UnixDatagram;
+
+    use super::*;
+    use crate::{
+        config::{
+            global::RestartPolicyConfig, inbound::InboundConfig, pipe::PipeConfig,
+            types::DurationValue, OutboundConfig,
+        },
+        core::{
+            actor::Actor,
+            outbound::stdio::StdioOutbound,
+            tag::{InboundTagId, OutboundTagId, TagId},
+            types::{Record, Symbol, Value},
+        },
+    };
+
+    /// An outbound which gets stuck on demand
+    struct FakeOutbound {
+        tag: TagId,
+        stuck: Arc<AtomicBool>,
+    }
+
+    impl HasTag for FakeOutbound {
+        fn tag(&self) -> &TagId {
+            &self.tag
+        }
+    }
+
+    #[async_trait]
+    impl Actor for FakeOutbound {
+        type Error = outbound::Error;
+
+        async fn poll(&mut self, _ctx: CancellationToken) -> outbound::Result<()> {
+            if self.stuck.load(Ordering::Relaxed) {
+                std::future::pending::<()>().await;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Ok(())
+        }
+    }
+
+    impl Outbound for FakeOutbound {
+        fn inbounds(&mut self) -> &mut [TaggedReceiver] {
+            &mut []
+        }
+    }
+
+    /// An inbound waiting for a client which never connects
+    struct IdleInbound {
+        tag: TagId,
+    }
+
+    impl HasTag for IdleInbound {
+        fn tag(&self) -> &TagId {
+            &self.tag
+        }
+    }
+
+    #[async_trait]
+    impl Actor for IdleInbound {
+        type Error = inbound::Error;
+
+        async fn poll(&mut self, _ctx: CancellationToken) -> inbound::Result<()> {
+            std::future::pending().await
+        }
+    }
+
+    impl Inbound for IdleInbound {}
+
+    /// An outbound whose every third poll panics
+    struct PanickyOutbound {
+        tag: TagId,
+        polls: Arc<AtomicUsize>,
+    }
+
+    impl HasTag for PanickyOutbound {
+        fn tag(&self) -> &TagId {
+            &self.tag
+        }
+    }
+
+    #[async_trait]
+    impl Actor for PanickyOutbound {
+        type Error = outbound::Error;
+
+        async fn poll(&mut self, _ctx: CancellationToken) -> outbound::Result<()> {
+            let polls = self.polls.fetch_add(1, Ordering::Relaxed) + 1;
+            if polls.is_multiple_of(3) {
+                panic!("poll {}", polls);
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            Ok(())
+        }
+    }
+
+    impl Outbound for PanickyOutbound {
+        fn inbounds(&mut self) -> &mut [TaggedReceiver] {
+            &mut []
+        }
+    }
+
+    fn panicky_manager(polls: Arc<AtomicUsize>, max_restarts: usize) -> Manager {
+        let mut mgr = Manager::new(
+            ChannelGraph::try_create_from(&[], &[], &[]).unwrap(),
+            Vec::new(),
+            Vec::new(),
+            vec![
+                Box::new(PanickyOutbound {
+                    tag: OutboundTagId::new("panicky").into(),
+                    polls,
+                }),
+                Box::new(FakeOutbound {
+                    tag: OutboundTagId::new("fake").into(),
+                    stuck: Arc::new(AtomicBool::new(false)),
+                }),
+            ],
+        );
+        mgr.supervisor = Supervisor::new(RestartPolicyConfig {
+            max_restarts,
+            restart_window: DurationValue::from_secs(60),
+        });
+        mgr
+    }
+
+    #[tokio::test]
+    async fn test_restart_after_panic() {
+        let polls = Arc::new(AtomicUsize::new(0));
+        let ctx = CancellationToken::new();
+        let run = tokio::spawn(panicky_manager(polls.clone(), 3).run(ctx.clone()));
+
+        // Panicked on the third and the sixth poll, and kept going
+        while polls.load(Ordering::Relaxed) < 8 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert!(!run.is_finished());
+
+        ctx.cancel();
+        run.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_too_many_restarts() {
+        let polls = Arc::new(AtomicUsize::new(0));
+        let ctx = CancellationToken::new();
+        let run = panicky_manager(polls.clone(), 1).run(ctx.clone());
+
+        // The second panic shuts everything down, without being cancelled
+        let err = tokio::time::timeout(Duration::from_secs(5), run)
+            .await
+            .expect("not shut down")
+            .unwrap_err();
+        assert!(
+            matches!(err, Error::TooManyRestarts(ref tag) if tag.to_string() == "outbound:panicky"),
+            "{:?}",
+            err
+        );
+        assert_eq!(polls.load(Ordering::Relaxed), 6);
+        assert!(!ctx.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn test_actors_poll_independently() {
+        let polls = Arc::new(AtomicUsize::new(0));
+        let mut mgr = panicky_manager(polls.clone(), 3);
+        // A sibling whose poll never ends
+        let idle: TagId = InboundTagId::new("idle").into();
+        mgr.actors.insert(
+            idle.clone(),
+            ManagedActor::Inbound(Box::new(IdleInbound { tag: idle })),
+        );
+        let ctx = CancellationToken::new();
+        let run = tokio::spawn(mgr.run(ctx.clone()));
+
+        // Each actor has its own task, the panicky one keeps its cadence and is restarted alone
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while polls.load(Ordering::Relaxed) < 5 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("held back by a sibling");
+        assert!(!run.is_finished());
+
+        ctx.cancel();
+        run.await.unwrap().unwrap();
+    }
+
+    #[cfg(unix)]
+    async fn messages(server: &UnixDatagram, duration: Duration) -> Vec<String> {
+        let mut messages = Vec::new();
+        let mut buf = [0; 256];
+        let deadline = tokio::time::Instant::now() + duration;
+        while let Ok(n) = tokio::time::timeout_at(deadline, server.recv(&mut buf)).await {
+            messages.push(String::from_utf8_lossy(&buf[..n.unwrap()]).to_string());
+        }
+        messages
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_systemd_lifecycle() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify.sock");
+        let server = UnixDatagram::bind(&path).unwrap();
+
+        let notifier = systemd::Notifier::connect(
+            path.as_os_str(),
+            Some(Duration::from_millis(100)),
+            Duration::from_millis(50),
+        )
+        .unwrap();
+        let tag: TagId = OutboundTagId::new("fake").into();
+        let stuck = Arc::new(AtomicBool::new(false));
+
+        let mut mgr = Manager::new(
+            ChannelGraph::try_create_from(&[], &[], &[]).unwrap(),
+            Vec::new(),
+            Vec::new(),
+            vec![Box::new(FakeOutbound {
+                tag: tag.clone(),
+                stuck: stuck.clone(),
+            })],
+        );
+        mgr.notifier = Some(Arc::new(notifier));
+        let ctx = CancellationToken::new();
+        let run = tokio::spawn(mgr.run(ctx.clone()));
+
+        let started = messages(&server, Duration::from_millis(300)).await;
+        assert_eq!(started[0], "READY=1");
+        assert!(started.iter().any(|m| m == "WATCHDOG=1"), "{:?}", started);
+        assert!(
+            started
+                .iter()
+                .any(|m| m.starts_with("STATUS=") && m.ends_with("all actors healthy")),
+            "{:?}",
+            started
+        );
+
+        // A stuck outbound stops the watchdog pings
+        stuck.store(true, Ordering::Relaxed);
+        messages(&server, Duration::from_millis(200)).await;
+        let stalled = messages(&server, Duration::from_millis(300)).await;
+        assert!(!stalled.iter().any(|m| m == "WATCHDOG=1"), "{:?}", stalled);
+        assert!(
+            stalled
+                .iter()
+                .any(|m| m.ends_with(&format!("stalled: {}", tag))),
+            "{:?}",
+            stalled
+        );
+
+        ctx.cancel();
+        let stopping = messages(&server, Duration::from_millis(300)).await;
+        assert!(stopping.iter().any(|m| m == "STOPPING=1"), "{:?}", stopping);
+        run.await.unwrap().unwrap();
+    }
+
+    /// An inbound which sends a burst of records on its first poll, once started if given
+    struct BurstInbound {
+        tag: TagId,
+        sender: TaggedSender,
+        count: i64,
+        start: Option<tokio::sync::oneshot::Receiver<()>>,
+        sent: Option<tokio::sync::oneshot::Sender<()>>,
+    }
+
+    impl HasTag for BurstInbound {
+        fn tag(&self) -> &TagId {
+            &self.tag
+        }
+    }
+
+    #[async_trait]
+    impl Actor for BurstInbound {
+        type Error = inbound::Error;
+
+        async fn poll(&mut self, ctx: CancellationToken) -> inbound::Result<()> {
+            if let Some(start) = self.start.take() {
+                start.await.unwrap();
+            }
+            if let Some(sent) = self.sent.take() {
+                for seq in 0..self.count {
+                    let mut record = Record::empty();
+                    record.set(Symbol::new("seq"), Value::from(seq));
+                    self.sender.send(record).await.unwrap();
+                }
+                sent.send(()).unwrap();
+            }
+            ctx.cancelled().await;
+            Ok(())
+        }
+    }
+
+    impl Inbound for BurstInbound {}
+
+    #[tokio::test]
+    async fn test_drain_on_shutdown() {
+        let inbound_cfg: InboundConfig = toml::from_str(
+            "type = \"tcp\"\ntag = \"burst\"\naddress = \"127.0.0.1:0\"\nprotocol = \"json\"",
+        )
+        .unwrap();
+        let pipe_cfg: PipeConfig = toml::from_str(
+            "type = \"filter\"\ninbounds = [\"inbound:burst\"]\nconditions = [{ field = \"seq\", op = \"exists\" }]",
+        )
+        .unwrap();
+        let outbound_cfg: OutboundConfig =
+            toml::from_str("type = \"stdio\"\ninbounds = [\"pipe:filter\"]\nformat = \"json\"")
+                .unwrap();
+
+        let mut graph = ChannelGraph::try_create_from(
+            std::slice::from_ref(&inbound_cfg),
+            std::slice::from_ref(&pipe_cfg),
+            std::slice::from_ref(&outbound_cfg),
+        )
+        .unwrap();
+
+        let tag: TagId = InboundTagId::new("burst").into();
+        let (sent, burst_sent) = tokio::sync::oneshot::channel();
+        let inbound = BurstInbound {
+            sender: graph.sender(&tag),
+            tag,
+            count: 100,
+            start: None,
+            sent: Some(sent),
+        };
+        let pipe = pipe::try_create_from(pipe_cfg, &mut graph).unwrap();
+        let (writer, mut reader) = tokio::io::duplex(1 << 20);
+        let OutboundConfig::Stdio(stdio_cfg) = outbound_cfg else {
+            unreachable!()
+        };
+        let outbound = StdioOutbound::with_writer(stdio_cfg, &mut graph, Box::new(writer)).unwrap();
+        graph.seal();
+
+        let mgr = Manager::new(
+            graph,
+            vec![Box::new(inbound)],
+            vec![pipe],
+            vec![Box::new(outbound)],
+        );
+        let ctx = CancellationToken::new();
+        let run = tokio::spawn(mgr.run(ctx.clone()));
+
+        // Shut down while the records are still on their way
+        burst_sent.await.unwrap();
+        ctx.cancel();
+        run.await.unwrap().unwrap();
+
+        let mut output = String::new();
+        tokio::io::AsyncReadExt::read_to_string(&mut reader, &mut output)
+            .await
+            .unwrap();
+        assert_eq!(output.lines().count(), 100);
+        assert_eq!(output.matches("\"seq\":").count(), 100);
+    }
+
+    #[tokio::test]
+    async fn test_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.jsonl");
+        let cfg = |outbounds: &str| -> Config {
+            toml::from_str(&format!(
+                "{}\n{}",
+                r#"
+                [[inbounds]]
+                type = "tcp"
+                tag = "burst"
+                address = "127.0.0.1:0"
+                protocol = "json"
+
+                [[protocols]]
+                type = "json"
+
+                [[pipes]]
+                type = "filter"
+                inbounds = ["inbound:burst"]
+                conditions = [{ field = "seq", op = "exists" }]
+                "#,
+                outbounds
+            ))
+            .unwrap()
+        };
+        let running = cfg("[[outbounds]]\ntype = \"stdio\"\ninbounds = [\"pipe:filter\"]");
+
+        let mut graph =
+            ChannelGraph::try_create_from(&running.inbounds, &running.pipes, &running.outbounds)
+                .unwrap();
+        let tag: TagId = InboundTagId::new("burst").into();
+        let (start, started) = tokio::sync::oneshot::channel();
+        let (sent, burst_sent) = tokio::sync::oneshot::channel();
+        let inbound = BurstInbound {
+            sender: graph.sender(&tag),
+            tag,
+            count: 100,
+            start: Some(started),
+            sent: Some(sent),
+        };
+        let pipe = pipe::try_create_from(running.pipes[0].clone(), &mut graph).unwrap();
+        let (writer, mut reader) = tokio::io::duplex(1 << 20);
+        let OutboundConfig::Stdio(stdio_cfg) = running.outbounds[0].clone() else {
+            unreachable!()
+        };
+        let outbound = StdioOutbound::with_writer(stdio_cfg, &mut graph, Box::new(writer)).unwrap();
+        graph.seal();
+
+        let (reload, reloads) = mpsc::channel(1);
+        let mut mgr = Manager::new(
+            graph,
+            vec![Box::new(inbound)],
+            vec![pipe],
+            vec![Box::new(outbound)],
+        )
+        .with_reloads(reloads);
+        mgr.topology = Some(Topology::new(&running).unwrap());
+        let ctx = CancellationToken::new();
+        let run = tokio::spawn(mgr.run(ctx.clone()));
+
+        // Replace the stdio outbound by a file one
+        reload
+            .send(cfg(&format!(
+                "[[outbounds]]\ntype = \"file\"\ninbounds = [\"pipe:filter\"]\npath = {:?}",
+                path
+            )))
+            .await
+            .unwrap();
+
+        // The stdio outbound has stopped once its writer is dropped
+        let mut output = String::new();
+        tokio::io::AsyncReadExt::read_to_string(&mut reader, &mut output)
+            .await
+            .unwrap();
+        assert!(output.is_empty(), "{}", output);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        start.send(()).unwrap();
+        burst_sent.await.unwrap();
+        ctx.cancel();
+        run.await.unwrap().unwrap();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(written.lines().count(), 100);
+    }
+
+    #[tokio::test]
+    async fn test_reload_keeps_outbound_failing_to_start() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.jsonl");
+        let cfg = |path: &std::path::Path| -> Config {
+            toml::from_str(&format!(
+                r#"
+                pipes = []
+
+                [[inbounds]]
+                type = "tcp"
+                tag = "burst"
+                address = "127.0.0.1:0"
+                protocol = "json"
+
+                [[protocols]]
+                type = "json"
+
+                [[outbounds]]
+                type = "file"
+                inbounds = ["inbound:burst"]
+                path = {:?}
+                "#,
+                path
+            ))
+            .unwrap()
+        };
+        let running = cfg(&path);
+
+        let mut graph =
+            ChannelGraph::try_create_from(&running.inbounds, &running.pipes, &running.outbounds)
+                .unwrap();
+        let tag: TagId = InboundTagId::new("burst").into();
+        let (start, started) = tokio::sync::oneshot::channel();
+        let (sent, burst_sent) = tokio::sync::oneshot::channel();
+        let inbound = BurstInbound {
+            sender: graph.sender(&tag),
+            tag,
+            count: 100,
+            start: Some(started),
+            sent: Some(sent),
+        };
+        let outbound = outbound::try_create_from(running.outbounds[0].clone(), &mut graph).unwrap();
+        graph.seal();
+
+        let (reload, reloads) = mpsc::channel(1);
+        let mut mgr = Manager::new(graph, vec![Box::new(inbound)], vec![], vec![outbound])
+            .with_reloads(reloads);
+        mgr.topology = Some(Topology::new(&running).unwrap());
+        let ctx = CancellationToken::new();
+        let run = tokio::spawn(mgr.run(ctx.clone()));
+
+        // Its parent is a file, the new outbound only fails once it opens it
+        reload.send(cfg(&path.join("out.jsonl"))).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        start.send(()).unwrap();
+        burst_sent.await.unwrap();
+        ctx.cancel();
+        run.await.unwrap().unwrap();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(written.lines().count(), 100);
+    }
+
+    #[tokio::test]
+    async fn test_dry_run() {
+        let dir = tempfile::tempdir().unwrap();
+        // Already taken, a dry run must not try to bind it
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let cfg: Config = toml::from_str(&format!(
+            r#"
+            [[inbounds]]
+            type = "unix_socket"
+            tag = "socket"
+            path = "{dir}/run/void.sock"
+            protocol = "json"
+
+            [[inbounds]]
+            type = "tcp"
+            tag = "tcp"
+            address = "{addr}"
+            protocol = "json"
+
+            [[protocols]]
+            type = "json"
+
+            [[pipes]]
+            type = "filter"
+            inbounds = ["inbound:socket", "inbound:tcp"]
+            conditions = [{{ field = "seq", op = "exists" }}]
+
+            [[outbounds]]
+            type = "file"
+            inbounds = ["pipe:filter"]
+            path = "{dir}/out/records.jsonl"
+
+            [[outbounds]]
+            type = "csv"
+            inbounds = ["pipe:filter"]
+            path = "{dir}/records.csv"
+            columns = [{{ name = "seq" }}]
+            "#,
+            dir = dir.path().display(),
+            addr = taken.local_addr().unwrap(),
+        ))
+        .unwrap();
+
+        let mgr = dry_run_from_config(cfg).unwrap();
+        let components = mgr
+            .components()
+            .into_iter()
+            .map(|(kind, tag)| format!("{} {}", kind, tag))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            components,
+            [
+                "inbound inbound:socket",
+                "inbound inbound:tcp",
+                "pipe pipe:filter",
+                "outbound outbound:csv",
+                "outbound outbound:file",
+            ]
+        );
+        assert_eq!(mgr.snapshot().edges.len(), 4);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
     }
 }
@@ -0,0 +1,389 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use log::{error, info, warn};
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use tokio::sync::mpsc;
+
+use crate::{
+    config::{pipe::PipeConfig, Config, OutboundConfig},
+    core::{
+        actor, outbound, pipe,
+        tag::{HasTag, TagId},
+    },
+};
+
+use super::{ChannelGraph, ManagedActor};
+
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The config of the running actors, to tell what a reloaded config changes.
+#[derive(Debug)]
+pub struct Topology {
+    // Changing the inbounds, the protocols or the global config needs a restart
+    inbounds: HashMap<TagId, JsonValue>,
+    protocols: HashMap<TagId, JsonValue>,
+    global: JsonValue,
+
+    pipes: HashMap<TagId, PipeConfig>,
+    outbounds: HashMap<TagId, OutboundConfig>,
+}
+
+/// The pipes and outbounds a reload stops, starts, or both.
+#[derive(Debug, Default, PartialEq)]
+pub struct Diff {
+    pub removed: Vec<TagId>,
+    pub changed: Vec<TagId>,
+    pub added: Vec<TagId>,
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.changed.is_empty() && self.added.is_empty()
+    }
+}
+
+impl Display for Diff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "removed [{}], changed [{}], added [{}]",
+            format_tags(&self.removed),
+            format_tags(&self.changed),
+            format_tags(&self.added)
+        )
+    }
+}
+
+impl Topology {
+    pub fn new(cfg: &Config) -> super::Result<Self> {
+        Ok(Self {
+            inbounds: cfg
+                .inbounds
+                .iter()
+                .map(|c| Ok((c.tag().clone(), json(c)?)))
+                .collect::<super::Result<_>>()?,
+            protocols: cfg
+                .protocols
+                .iter()
+                .map(|c| Ok((c.tag().clone(), json(c)?)))
+                .collect::<super::Result<_>>()?,
+            global: json(&cfg.global)?,
+            pipes: cfg
+                .pipes
+                .iter()
+                .map(|c| (c.tag().clone(), c.clone()))
+                .collect(),
+            outbounds: cfg
+                .outbounds
+                .iter()
+                .map(|c| (c.tag().clone(), c.clone()))
+                .collect(),
+        })
+    }
+
+    pub fn pipe(&self, tag: &TagId) -> Option<&PipeConfig> {
+        self.pipes.get(tag)
+    }
+
+    pub fn outbound(&self, tag: &TagId) -> Option<&OutboundConfig> {
+        self.outbounds.get(tag)
+    }
+
+    /// Keep the config `running` has for `tag`, e.g. its new one failed to start. It is
+    /// tried again on the next reload.
+    pub fn revert(&mut self, running: &Topology, tag: &TagId) {
+        if let Some(cfg) = running.pipe(tag) {
+            self.pipes.insert(tag.clone(), cfg.clone());
+        }
+        if let Some(cfg) = running.outbound(tag) {
+            self.outbounds.insert(tag.clone(), cfg.clone());
+        }
+    }
+
+    /// Create the actor of a pipe or an outbound, none for the other tags and the disabled
+    /// ones.
+    pub fn create(
+        &self,
+        tag: &TagId,
+        channels: &mut ChannelGraph,
+    ) -> Option<Result<ManagedActor, actor::Error>> {
+        if let Some(cfg) = self.pipe(tag).filter(|cfg| !cfg.disabled()) {
+            let actor = pipe::try_create_from(cfg.clone(), channels);
+            Some(actor.map(ManagedActor::Pipe).map_err(actor::Error::from))
+        } else if let Some(cfg) = self.outbound(tag).filter(|cfg| !cfg.disabled()) {
+            let actor = outbound::try_create_from(cfg.clone(), channels);
+            Some(
+                actor
+                    .map(ManagedActor::Outbound)
+                    .map_err(actor::Error::from),
+            )
+        } else {
+            None
+        }
+    }
+
+    /// The topology once `cfg` is applied, and what it changes. Only the pipes and the
+    /// outbounds are taken from `cfg`, the other changes are logged and ignored.
+    ///
+    /// The pipes and outbounds to start are created once beforehand, without binding nor
+    /// writing anything: a config they reject is rejected as a whole, before any running
+    /// actor is stopped.
+    pub fn reload(&self, cfg: Config) -> super::Result<(Self, Diff)> {
+        let inbounds = cfg
+            .inbounds
+            .iter()
+            .map(|c| Ok((c.tag().clone(), json(c)?)))
+            .collect::<super::Result<HashMap<_, _>>>()?;
+        let protocols = cfg
+            .protocols
+            .iter()
+            .map(|c| Ok((c.tag().clone(), json(c)?)))
+            .collect::<super::Result<HashMap<_, _>>>()?;
+        for (kind, running, reloaded) in [
+            ("inbounds", &self.inbounds, &inbounds),
+            ("protocols", &self.protocols, &protocols),
+        ] {
+            let mut ignored = Diff::default();
+            diff_configs(running, reloaded, &mut ignored)?;
+            if !ignored.is_empty() {
+                warn!(
+                    "Changes to the {} ignored, restart required: {}",
+                    kind, ignored
+                );
+            }
+        }
+        if json(&cfg.global)? != self.global {
+            warn!("Changes to the global config ignored, restart required");
+        }
+
+        // The new pipes and outbounds receive from the running inbounds
+        let inbound_tags = self.inbounds.keys().cloned().collect::<Vec<_>>();
+        ChannelGraph::verify(&inbound_tags, &cfg.pipes, &cfg.outbounds)?;
+        let mut scratch = super::channel_graph_from_config(&cfg)?;
+        scratch.set_dry_run();
+
+        let topology = Topology {
+            inbounds: self.inbounds.clone(),
+            protocols: self.protocols.clone(),
+            global: self.global.clone(),
+            pipes: cfg
+                .pipes
+                .into_iter()
+                .map(|c| (c.tag().clone(), c))
+                .collect(),
+            outbounds: cfg
+                .outbounds
+                .into_iter()
+                .map(|c| (c.tag().clone(), c))
+                .collect(),
+        };
+
+        let mut diff = Diff::default();
+        diff_configs(&self.pipes, &topology.pipes, &mut diff)?;
+        diff_configs(&self.outbounds, &topology.outbounds, &mut diff)?;
+
+        for tag in diff.changed.iter().chain(&diff.added) {
+            if let Some(Err(err)) = topology.create(tag, &mut scratch) {
+                return Err(err.into());
+            }
+        }
+
+        Ok((topology, diff))
+    }
+}
+
+/// Configs are compared by their serialized form.
+fn json<T: Serialize>(cfg: &T) -> super::Result<JsonValue> {
+    Ok(serde_json::to_value(cfg)?)
+}
+
+fn diff_configs<T: Serialize>(
+    old: &HashMap<TagId, T>,
+    new: &HashMap<TagId, T>,
+    diff: &mut Diff,
+) -> super::Result<()> {
+    for (tag, cfg) in old {
+        match new.get(tag) {
+            None => diff.removed.push(tag.clone()),
+            Some(new_cfg) if json(new_cfg)? != json(cfg)? => diff.changed.push(tag.clone()),
+            Some(_) => {}
+        }
+    }
+    for tag in new.keys() {
+        if !old.contains_key(tag) {
+            diff.added.push(tag.clone());
+        }
+    }
+
+    for tags in [&mut diff.removed, &mut diff.changed, &mut diff.added] {
+        tags.sort_by_key(|tag| tag.to_string());
+    }
+    Ok(())
+}
+
+fn format_tags(tags: &[TagId]) -> String {
+    tags.iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(unix)]
+type Hangup = tokio::signal::unix::Signal;
+
+#[cfg(unix)]
+fn hangup() -> std::io::Result<Hangup> {
+    tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+}
+
+/// Stands for SIGHUP where there is none, only the watch reloads.
+#[cfg(not(unix))]
+struct Hangup;
+
+#[cfg(not(unix))]
+impl Hangup {
+    async fn recv(&mut self) -> Option<()> {
+        std::future::pending().await
+    }
+}
+
+#[cfg(not(unix))]
+fn hangup() -> std::io::Result<Hangup> {
+    Ok(Hangup)
+}
+
+/// Re-read the config file on SIGHUP, and whenever it changes if `watch` is set. The configs
+/// failing to load are logged and skipped, the running ones are sent to the manager.
+pub fn spawn_reload_task(path: PathBuf, watch: bool) -> std::io::Result<mpsc::Receiver<Config>> {
+    let mut hangup = hangup()?;
+    let (tx, rx) = mpsc::channel(1);
+
+    tokio::spawn(async move {
+        let mut modified = modified_time(&path);
+        let mut interval = tokio::time::interval(WATCH_INTERVAL);
+
+        loop {
+            tokio::select! {
+                Some(()) = hangup.recv() => {
+                    info!("Received SIGHUP, reloading {}", path.display());
+                }
+                _ = interval.tick(), if watch => {
+                    let now = modified_time(&path);
+                    if now == modified {
+                        continue;
+                    }
+                    modified = now;
+                    info!("{} has changed, reloading", path.display());
+                }
+                else => return,
+            }
+
+            match Config::read_from_file(&path) {
+                Ok(cfg) => {
+                    if tx.send(cfg).await.is_err() {
+                        return;
+                    }
+                }
+                Err(err) => error!(
+                    "Failed to reload {}, keeping the running config: {:?}",
+                    path.display(),
+                    miette::Report::new(err)
+                ),
+            }
+        }
+    });
+
+    Ok(rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(outbounds: &str) -> Config {
+        toml::from_str(&format!(
+            r#"
+            [[inbounds]]
+            type = "tcp"
+            tag = "a"
+            address = "127.0.0.1:0"
+            protocol = "json"
+
+            [[protocols]]
+            type = "json"
+
+            [[pipes]]
+            type = "filter"
+            inbounds = ["inbound:a"]
+            conditions = [{{ field = "name", op = "exists" }}]
+
+            {}
+            "#,
+            outbounds
+        ))
+        .unwrap()
+    }
+
+    fn tags(tags: &[TagId]) -> Vec<String> {
+        tags.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_reload() {
+        let running = Topology::new(&config(
+            "[[outbounds]]\ntype = \"stdio\"\ninbounds = [\"pipe:filter\"]\n\
+             [[outbounds]]\ntype = \"stdio\"\ntag = \"old\"\ninbounds = [\"pipe:filter\"]",
+        ))
+        .unwrap();
+
+        let (topology, diff) = running
+            .reload(config(
+                "[[outbounds]]\ntype = \"stdio\"\ninbounds = [\"pipe:filter\"]\nformat = \"json\"\n\
+                 [[outbounds]]\ntype = \"stdio\"\ntag = \"new\"\ninbounds = [\"pipe:filter\"]",
+            ))
+            .unwrap();
+        assert_eq!(tags(&diff.removed), vec!["outbound:old"]);
+        assert_eq!(tags(&diff.changed), vec!["outbound:stdio"]);
+        assert_eq!(tags(&diff.added), vec!["outbound:new"]);
+        assert!(topology.outbound(&diff.added[0]).is_some());
+
+        let (_, diff) = topology
+            .reload(config(
+                "[[outbounds]]\ntype = \"stdio\"\ninbounds = [\"pipe:filter\"]\nformat = \"json\"\n\
+                 [[outbounds]]\ntype = \"stdio\"\ntag = \"new\"\ninbounds = [\"pipe:filter\"]",
+            ))
+            .unwrap();
+        assert!(diff.is_empty());
+
+        // Receiving from an inbound which is not running
+        let err = running
+            .reload(config(
+                "[[outbounds]]\ntype = \"stdio\"\ninbounds = [\"inbound:b\"]",
+            ))
+            .unwrap_err();
+        assert!(
+            matches!(err, super::super::Error::UnknownTagRequired(..)),
+            "{}",
+            err
+        );
+
+        // The data flow is fine, the outbound is not
+        let err = running
+            .reload(config(
+                "[[outbounds]]\ntype = \"otlp\"\nendpoint = \"http://127.0.0.1:4318\"\n\
+                 inbounds = [\"pipe:filter\"]\nheaders = { \"bad header\" = \"x\" }",
+            ))
+            .unwrap_err();
+        assert!(matches!(err, super::super::Error::Actor(..)), "{}", err);
+    }
+}
@@ -0,0 +1,138 @@
+use std::{collections::HashMap, time::Duration};
+
+use log::{info, warn};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use crate::{core::tag::TagId, utils::liveness::Heartbeat};
+
+use super::ChannelGraph;
+
+const DRAIN_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Actors of the same kind, cancelled together on shutdown.
+#[derive(Debug)]
+pub struct Stage {
+    name: &'static str,
+    ctx: CancellationToken,
+    // The channels the actors receive from
+    channels: Vec<TagId>,
+    heartbeats: HashMap<TagId, Heartbeat>,
+}
+
+impl Stage {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            ctx: CancellationToken::new(),
+            channels: Vec::new(),
+            heartbeats: HashMap::new(),
+        }
+    }
+
+    pub fn ctx(&self) -> CancellationToken {
+        self.ctx.clone()
+    }
+
+    pub fn watch(&mut self, graph: &ChannelGraph, tag: &TagId, heartbeat: Heartbeat) {
+        if graph.contains(tag) {
+            for channel in graph.query_inbounds(tag) {
+                if !self.channels.contains(&channel) {
+                    self.channels.push(channel);
+                }
+            }
+        }
+        self.heartbeats.insert(tag.clone(), heartbeat);
+    }
+
+    /// Stop waiting for an actor removed by a reload. The channels it received from are still
+    /// checked, they are drained once the remaining consumers catch up.
+    pub fn unwatch(&mut self, tag: &TagId) {
+        self.heartbeats.remove(tag);
+    }
+
+    /// Wait until the actors have received everything sent to them and passed it on, then
+    /// cancel them. Gives up at `deadline`.
+    ///
+    /// Once the channels are drained, the poll in progress may still hold records: the actors
+    /// are given two more polls, the second one starting with nothing left to receive, which
+    /// is when the outbounds write out their buffers. Records carried over by an actor, see
+    /// [`Actor::has_pending`](crate::core::actor::Actor::has_pending), are waited for as well.
+    pub async fn drain_and_cancel(&self, graph: &ChannelGraph, deadline: Instant) {
+        let drain = async {
+            loop {
+                while !graph.drained(&self.channels) {
+                    tokio::time::sleep(DRAIN_CHECK_INTERVAL).await;
+                }
+
+                let beats = self
+                    .heartbeats
+                    .values()
+                    .map(Heartbeat::beats)
+                    .collect::<Vec<_>>();
+                while self
+                    .heartbeats
+                    .values()
+                    .zip(&beats)
+                    .any(|(heartbeat, beats)| {
+                        heartbeat.beats() < beats + 2 || heartbeat.is_pending()
+                    })
+                {
+                    tokio::time::sleep(DRAIN_CHECK_INTERVAL).await;
+                }
+
+                // A pipe may have fed another one meanwhile
+                if graph.drained(&self.channels) {
+                    break;
+                }
+            }
+        };
+
+        match tokio::time::timeout_at(deadline, drain).await {
+            Ok(()) => info!("Drained {}", self.name),
+            Err(_) => warn!(
+                "Timed out draining {}, the records in flight are dropped",
+                self.name
+            ),
+        }
+        self.ctx.cancel();
+    }
+
+    pub fn cancel(&self) {
+        self.ctx.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{core::tag::PipeTagId, utils::liveness::Liveness};
+
+    #[tokio::test]
+    async fn test_drain_waits_for_pending() {
+        let graph = ChannelGraph::try_create_from(&[], &[], &[]).unwrap();
+        let liveness = Liveness::default();
+        let tag: TagId = PipeTagId::new("timeseries").into();
+        let heartbeat = liveness.register(tag.clone());
+        let mut stage = Stage::new("pipes");
+        stage.watch(&graph, &tag, heartbeat.clone());
+
+        // Stands for a pipe carrying records over ten more polls
+        let ctx = stage.ctx();
+        let polls = tokio::spawn(async move {
+            let mut carried = 10usize;
+            while !ctx.is_cancelled() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                carried = carried.saturating_sub(1);
+                heartbeat.set_pending(carried > 0);
+                heartbeat.beat();
+            }
+            carried
+        });
+
+        stage
+            .drain_and_cancel(&graph, Instant::now() + Duration::from_secs(5))
+            .await;
+        assert_eq!(polls.await.unwrap(), 0);
+    }
+}
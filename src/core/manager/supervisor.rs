@@ -0,0 +1,77 @@
+use std::collections::{HashMap, VecDeque};
+
+use tokio::time::Instant;
+
+use crate::{config::global::RestartPolicyConfig, core::tag::TagId};
+
+/// Counts the restarts of the actors whose poll panicked, within a sliding window.
+#[derive(Debug)]
+pub struct Supervisor {
+    policy: RestartPolicyConfig,
+    restarts: HashMap<TagId, VecDeque<Instant>>,
+}
+
+impl Supervisor {
+    pub fn new(policy: RestartPolicyConfig) -> Self {
+        Self {
+            policy,
+            restarts: HashMap::new(),
+        }
+    }
+
+    /// Whether the actor may be restarted once more, counting this restart if so.
+    pub fn restart(&mut self, tag: &TagId) -> bool {
+        let now = Instant::now();
+        let window = self.policy.restart_window.get();
+        let restarts = self.restarts.entry(tag.clone()).or_default();
+        while restarts
+            .front()
+            .is_some_and(|restart| now.duration_since(*restart) >= window)
+        {
+            restarts.pop_front();
+        }
+
+        if restarts.len() >= self.policy.max_restarts {
+            return false;
+        }
+        restarts.push_back(now);
+        true
+    }
+
+    /// Start over for an actor replaced by a reload.
+    pub fn forget(&mut self, tag: &TagId) {
+        self.restarts.remove(tag);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{config::types::DurationValue, core::tag::PipeTagId};
+
+    #[tokio::test]
+    async fn test_restart_window() {
+        let mut supervisor = Supervisor::new(RestartPolicyConfig {
+            max_restarts: 2,
+            restart_window: DurationValue::from_millis(100),
+        });
+        let tag: TagId = PipeTagId::new("a").into();
+        let other: TagId = PipeTagId::new("b").into();
+
+        assert!(supervisor.restart(&tag));
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(supervisor.restart(&tag));
+        assert!(!supervisor.restart(&tag));
+        assert!(supervisor.restart(&other));
+
+        // The first restart has left the window
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(supervisor.restart(&tag));
+        assert!(!supervisor.restart(&tag));
+
+        supervisor.forget(&tag);
+        assert!(supervisor.restart(&tag));
+    }
+}
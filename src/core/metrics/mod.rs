@@ -0,0 +1,344 @@
+mod source;
+
+pub use source::InternalMetricsSource;
+
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+
+use super::tag::{TagId, INTERNAL_TAG_SCOPE};
+
+/// The channel the internal metrics are sent into.
+pub static INTERNAL_METRICS_TAG: Lazy<TagId> =
+    Lazy::new(|| TagId::new(INTERNAL_TAG_SCOPE, "metrics"));
+
+// Records per batch received by the batching actors
+const BATCH_SIZE_BOUNDS: &[f64] = &[1.0, 8.0, 64.0, 512.0, 4096.0, 32768.0];
+
+// Seconds per request sent by the outbounds, retries are requests of their own
+const REQUEST_SECONDS_BOUNDS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// A histogram with fixed bounds, exposed cumulatively as in Prometheus.
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    // One per bound, then the values above the last one
+    buckets: Box<[AtomicU64]>,
+    // Bits of the `f64` sum
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum: AtomicU64::new(0f64.to_bits()),
+        }
+    }
+
+    pub fn observe(&self, value: f64) {
+        let bucket = self.bounds.partition_point(|&bound| bound < value);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        let _ = self
+            .sum
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |sum| {
+                Some((f64::from_bits(sum) + value).to_bits())
+            });
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let mut count = 0;
+        let buckets = self
+            .bounds
+            .iter()
+            .zip(self.buckets.iter())
+            .map(|(&bound, bucket)| {
+                count += bucket.load(Ordering::Relaxed);
+                (bound, count)
+            })
+            .collect();
+        count += self.buckets[self.bounds.len()].load(Ordering::Relaxed);
+
+        HistogramSnapshot {
+            buckets,
+            count,
+            sum: f64::from_bits(self.sum.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HistogramSnapshot {
+    // Upper bounds with the cumulative counts
+    pub buckets: Vec<(f64, u64)>,
+    pub count: u64,
+    pub sum: f64,
+}
+
+/// Counters of a single actor, always counted whether the internal metrics are sent or not.
+#[derive(Debug)]
+pub struct ActorMetrics {
+    received: AtomicU64,
+    sent: AtomicU64,
+    transform_errors: AtomicU64,
+    send_failures: AtomicU64,
+    poll_errors: AtomicU64,
+    // Unix timestamp in milliseconds of the last completed poll, 0 before the first one
+    last_poll: AtomicU64,
+
+    batch_sizes: Histogram,
+    request_seconds: Histogram,
+}
+
+impl Default for ActorMetrics {
+    fn default() -> Self {
+        ActorMetrics {
+            received: AtomicU64::new(0),
+            sent: AtomicU64::new(0),
+            transform_errors: AtomicU64::new(0),
+            send_failures: AtomicU64::new(0),
+            poll_errors: AtomicU64::new(0),
+            last_poll: AtomicU64::new(0),
+            batch_sizes: Histogram::new(BATCH_SIZE_BOUNDS),
+            request_seconds: Histogram::new(REQUEST_SECONDS_BOUNDS),
+        }
+    }
+}
+
+impl ActorMetrics {
+    pub fn count_received(&self) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count_sent(&self) {
+        self.sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count_transform_error(&self) {
+        self.transform_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count_send_failure(&self) {
+        self.send_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count_poll_error(&self) {
+        self.poll_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn observe_batch_size(&self, records: usize) {
+        self.batch_sizes.observe(records as f64);
+    }
+
+    pub fn observe_request(&self, elapsed: Duration) {
+        self.request_seconds.observe(elapsed.as_secs_f64());
+    }
+
+    pub fn mark_polled(&self) {
+        let now = chrono::Utc::now().timestamp_millis().max(0) as u64;
+        self.last_poll.store(now, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> ActorMetricsSnapshot {
+        ActorMetricsSnapshot {
+            received: self.received.load(Ordering::Relaxed),
+            sent: self.sent.load(Ordering::Relaxed),
+            transform_errors: self.transform_errors.load(Ordering::Relaxed),
+            send_failures: self.send_failures.load(Ordering::Relaxed),
+            poll_errors: self.poll_errors.load(Ordering::Relaxed),
+            last_poll: self.last_poll.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ActorMetricsSnapshot {
+    pub received: u64,
+    pub sent: u64,
+    pub transform_errors: u64,
+    pub send_failures: u64,
+    pub poll_errors: u64,
+    pub last_poll: u64,
+}
+
+static REGISTRY: Lazy<DashMap<TagId, Arc<ActorMetrics>>> = Lazy::new(DashMap::new);
+
+/// The counters of an actor, hold on to them instead of looking them up for every record.
+pub fn actor_metrics(tag: &TagId) -> Arc<ActorMetrics> {
+    if let Some(metrics) = REGISTRY.get(tag) {
+        return metrics.clone();
+    }
+
+    REGISTRY.entry(tag.clone()).or_default().clone()
+}
+
+/// Count a record an actor failed to parse or transform.
+pub fn count_transform_error(tag: &TagId) {
+    actor_metrics(tag).count_transform_error();
+}
+
+/// The counters of every actor seen so far, sorted by tag.
+pub fn snapshot() -> Vec<(TagId, ActorMetricsSnapshot)> {
+    let mut metrics = REGISTRY
+        .iter()
+        .map(|e| (e.key().clone(), e.value().snapshot()))
+        .collect::<Vec<_>>();
+    metrics.sort_by_key(|(tag, _)| tag.to_string());
+    metrics
+}
+
+// Name, help and value of an exposed metric
+type CounterMetric = (&'static str, &'static str, fn(&ActorMetricsSnapshot) -> u64);
+type HistogramMetric = (&'static str, &'static str, fn(&ActorMetrics) -> &Histogram);
+
+/// Escape a label value of the text exposition format.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// The metrics of every actor seen so far, in the Prometheus text exposition format.
+pub fn render_prometheus() -> String {
+    let actors = REGISTRY
+        .iter()
+        .map(|e| (e.key().to_string(), e.value().clone()))
+        .collect::<std::collections::BTreeMap<_, _>>();
+    let mut out = String::new();
+
+    let counters: [CounterMetric; 5] = [
+        (
+            "void_records_received_total",
+            "Records received by the actor",
+            |m| m.received,
+        ),
+        (
+            "void_records_sent_total",
+            "Records sent by the actor",
+            |m| m.sent,
+        ),
+        (
+            "void_transform_errors_total",
+            "Records the actor failed to parse or transform",
+            |m| m.transform_errors,
+        ),
+        (
+            "void_send_failures_total",
+            "Records the actor failed to send",
+            |m| m.send_failures,
+        ),
+        (
+            "void_poll_errors_total",
+            "Polls of the actor which failed",
+            |m| m.poll_errors,
+        ),
+    ];
+    let snapshots = actors
+        .iter()
+        .map(|(tag, metrics)| (escape_label(tag), metrics.snapshot()))
+        .collect::<Vec<_>>();
+    for (name, help, value) in counters {
+        let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+        for (tag, metrics) in &snapshots {
+            let _ = writeln!(out, "{}{{actor=\"{}\"}} {}", name, tag, value(metrics));
+        }
+    }
+
+    let histograms: [HistogramMetric; 2] = [
+        (
+            "void_batch_size",
+            "Records per batch received by the actor",
+            |m| &m.batch_sizes,
+        ),
+        (
+            "void_request_duration_seconds",
+            "Duration of the requests sent by the outbound",
+            |m| &m.request_seconds,
+        ),
+    ];
+    for (name, help, histogram) in histograms {
+        let _ = writeln!(out, "# HELP {} {}\n# TYPE {} histogram", name, help, name);
+        for (tag, metrics) in &actors {
+            let snapshot = histogram(metrics).snapshot();
+            // Only the actors which observed anything
+            if snapshot.count == 0 {
+                continue;
+            }
+            let tag = escape_label(tag);
+            for (bound, count) in &snapshot.buckets {
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{{actor=\"{}\",le=\"{}\"}} {}",
+                    name, tag, bound, count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{}_bucket{{actor=\"{}\",le=\"+Inf\"}} {}",
+                name, tag, snapshot.count
+            );
+            let _ = writeln!(out, "{}_sum{{actor=\"{}\"}} {}", name, tag, snapshot.sum);
+            let _ = writeln!(
+                out,
+                "{}_count{{actor=\"{}\"}} {}",
+                name, tag, snapshot.count
+            );
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tag::{OutboundTagId, PipeTagId};
+
+    #[test]
+    fn test_histogram() {
+        let histogram = Histogram::new(&[1.0, 10.0]);
+        for value in [0.5, 1.0, 5.0, 50.0] {
+            histogram.observe(value);
+        }
+
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.buckets, vec![(1.0, 2), (10.0, 3)]);
+        assert_eq!(snapshot.count, 4);
+        assert_eq!(snapshot.sum, 56.5);
+    }
+
+    #[test]
+    fn test_render_prometheus() {
+        let pipe: TagId = PipeTagId::new("render_test").into();
+        let metrics = actor_metrics(&pipe);
+        metrics.count_received();
+        metrics.count_received();
+        metrics.observe_batch_size(2);
+        let outbound: TagId = OutboundTagId::new("render_test").into();
+        actor_metrics(&outbound).observe_request(Duration::from_millis(30));
+
+        let text = render_prometheus();
+        assert!(text.contains("# TYPE void_records_received_total counter\n"));
+        assert!(text.contains("void_records_received_total{actor=\"pipe:render_test\"} 2\n"));
+        assert!(text.contains("void_batch_size_bucket{actor=\"pipe:render_test\",le=\"8\"} 1\n"));
+        assert!(text.contains("void_batch_size_count{actor=\"pipe:render_test\"} 1\n"));
+        assert!(text.contains(
+            "void_request_duration_seconds_bucket{actor=\"outbound:render_test\",le=\"0.05\"} 1\n"
+        ));
+        assert!(!text.contains("void_request_duration_seconds_count{actor=\"pipe:render_test\"}"));
+
+        assert_eq!(escape_label("a\"b\\c"), "a\\\"b\\\\c");
+    }
+}
@@ -0,0 +1,176 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use async_trait::async_trait;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    actor_warn,
+    config::global::InternalMetricsConfig,
+    core::{
+        actor::Actor,
+        inbound::{self, Inbound},
+        manager::{ChannelGraph, ChannelProbe, SendError, TaggedSender},
+        tag::{HasTag, TagId},
+        types::{Attribute, Record, Symbol, Value},
+    },
+};
+
+use super::{ActorMetricsSnapshot, INTERNAL_METRICS_TAG};
+
+pub const ACTOR_FIELD_STR: &str = "actor";
+
+/// Sends the metrics of every actor into `internal:metrics`, one record per actor shaped for
+/// the timeseries pipe: the `actor` field is the label, the others are the values.
+pub struct InternalMetricsSource {
+    tag: TagId,
+    interval: Duration,
+
+    outbound: TaggedSender,
+    probes: Vec<ChannelProbe>,
+}
+
+impl InternalMetricsSource {
+    /// Must be created once every actor has subscribed to its inbounds, so that the channel
+    /// probes know their consumers.
+    pub fn try_create_from(cfg: InternalMetricsConfig, channels: &mut ChannelGraph) -> Self {
+        let tag = INTERNAL_METRICS_TAG.clone();
+        let outbound = channels.sender(&tag);
+
+        InternalMetricsSource {
+            tag,
+            interval: cfg.interval.into(),
+            outbound,
+            probes: channels.probes(),
+        }
+    }
+
+    fn records(&self) -> Vec<Record> {
+        let mut actors = super::snapshot()
+            .into_iter()
+            .map(|(tag, metrics)| (tag.to_string(), (Some(metrics), None)))
+            .collect::<BTreeMap<_, (Option<ActorMetricsSnapshot>, Option<&ChannelProbe>)>>();
+        for probe in &self.probes {
+            actors.entry(probe.tag().to_string()).or_default().1 = Some(probe);
+        }
+
+        actors
+            .into_iter()
+            .map(|(actor, (metrics, probe))| {
+                let mut record = Record::empty();
+                record.set(Symbol::new(ACTOR_FIELD_STR), Value::from(actor));
+
+                let metrics = metrics.unwrap_or_default();
+                for (name, value) in [
+                    ("void_records_received_total", metrics.received),
+                    ("void_records_sent_total", metrics.sent),
+                    ("void_transform_errors_total", metrics.transform_errors),
+                    ("void_send_failures_total", metrics.send_failures),
+                ] {
+                    record.set(Symbol::new(name), Value::from(value as i64));
+                }
+                if let Some(probe) = probe {
+                    record.set(
+                        Symbol::new("void_channel_occupancy"),
+                        Value::from(probe.occupancy()),
+                    );
+                    // By the overflow policy of the channel, see `global.channel_overflow`
+                    record.set(
+                        Symbol::new("void_channel_dropped_total"),
+                        Value::from(probe.dropped() as i64),
+                    );
+                }
+
+                record.set_attribute(Attribute::Inbound, (&self.tag).into());
+                record
+            })
+            .collect()
+    }
+}
+
+impl HasTag for InternalMetricsSource {
+    fn tag(&self) -> &TagId {
+        &self.tag
+    }
+}
+
+#[async_trait]
+impl Actor for InternalMetricsSource {
+    type Error = inbound::Error;
+
+    async fn poll(&mut self, ctx: CancellationToken) -> inbound::Result<()> {
+        tokio::select! {
+            _ = ctx.cancelled() => return Ok(()),
+            _ = tokio::time::sleep(self.interval) => {}
+        }
+
+        for record in self.records() {
+            if let Err(e @ SendError::Closed(_)) = self.outbound.send(record).await {
+                actor_warn!(self.tag, "error sending record: {}", e);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Inbound for InternalMetricsSource {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{pipe::PipeConfig, OutboundConfig},
+        core::tag::PipeTagId,
+    };
+
+    #[test]
+    fn test_records() {
+        let pipe_cfg: PipeConfig = toml::from_str(
+            "type = \"filter\"\ntag = \"metrics_test\"\ninbounds = [\"internal:metrics\"]\nconditions = [{ field = \"actor\", op = \"exists\" }]",
+        )
+        .unwrap();
+        let outbound_cfg: OutboundConfig =
+            toml::from_str("type = \"stdio\"\ninbounds = [\"pipe:metrics_test\"]").unwrap();
+        let mut graph = ChannelGraph::try_create_from(&[], &[pipe_cfg], &[outbound_cfg]).unwrap();
+        graph.add_internal_metrics();
+        let pipe: TagId = PipeTagId::new("metrics_test").into();
+        let mut received = graph.recv_from(&INTERNAL_METRICS_TAG, &pipe);
+
+        let source = InternalMetricsSource::try_create_from(Default::default(), &mut graph);
+        graph.seal();
+        let mut sender = source.outbound.clone();
+        sender.try_send(Record::empty()).unwrap();
+        received.try_recv().unwrap();
+
+        let records = source.records();
+        let record = records
+            .iter()
+            .find(|r| {
+                r.get(&Symbol::new(ACTOR_FIELD_STR)) == Some(&Value::from("pipe:metrics_test"))
+            })
+            .unwrap();
+        assert_eq!(
+            record.get(&Symbol::new("void_records_received_total")),
+            Some(&Value::from(1i64))
+        );
+
+        let record = records
+            .iter()
+            .find(|r| {
+                r.get(&Symbol::new(ACTOR_FIELD_STR)) == Some(&Value::from("internal:metrics"))
+            })
+            .unwrap();
+        assert_eq!(
+            record.get(&Symbol::new("void_records_sent_total")),
+            Some(&Value::from(1i64))
+        );
+        assert_eq!(
+            record.get(&Symbol::new("void_channel_occupancy")),
+            Some(&Value::from(0.0))
+        );
+        assert_eq!(
+            record.get(&Symbol::new("void_channel_dropped_total")),
+            Some(&Value::from(0i64))
+        );
+    }
+}
@@ -1,6 +1,9 @@
 pub mod actor;
+pub mod dead_letter;
 pub mod inbound;
+pub mod keying;
 pub mod manager;
+pub mod metrics;
 pub mod outbound;
 pub mod pipe;
 pub mod protocol;
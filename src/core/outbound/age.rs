@@ -0,0 +1,137 @@
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    actor_warn,
+    config::outbound::{RecordAgeConfig, UndatedRecords},
+    core::{
+        tag::TagId,
+        types::{Record, Symbol},
+    },
+    utils::{stats::GLOBAL_STATS, throttle::Throttle},
+};
+
+const STALE_WARN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Drops the records older than `max_record_age` before an outbound transforms them.
+#[derive(Debug)]
+pub struct AgeFilter {
+    tag: TagId,
+    max_age: Duration,
+    field: Symbol,
+    undated: UndatedRecords,
+    throttle: Throttle,
+}
+
+impl AgeFilter {
+    /// None without a `max_record_age`.
+    pub fn from_config(tag: &TagId, cfg: &RecordAgeConfig) -> Option<Self> {
+        Some(AgeFilter {
+            tag: tag.clone(),
+            max_age: cfg.max_record_age?.get(),
+            field: cfg.age_field.clone(),
+            undated: cfg.undated_records,
+            throttle: Throttle::new(STALE_WARN_INTERVAL),
+        })
+    }
+
+    /// Drop the stale records, counted in `<tag> stale records`.
+    pub fn retain(&mut self, records: &mut Vec<Record>) {
+        let now = Utc::now();
+        let before = records.len();
+        records.retain(|record| self.is_fresh(record, now));
+
+        let dropped = before - records.len();
+        if dropped == 0 {
+            return;
+        }
+        GLOBAL_STATS.incr(&format!("{} stale records", self.tag), dropped as u64);
+        if let Some(suppressed) = self.throttle.check_at(Instant::now()) {
+            actor_warn!(
+                self.tag,
+                "dropped {} records older than {:?} ({} similar warnings suppressed)",
+                dropped,
+                self.max_age,
+                suppressed
+            );
+        }
+    }
+
+    fn is_fresh(&self, record: &Record, now: DateTime<Utc>) -> bool {
+        if let Some(timestamp) = record.get(&self.field).and_then(|v| v.datetime().ok()) {
+            // A timestamp ahead of the clock is as fresh as it gets
+            return now
+                .signed_duration_since(*timestamp.as_datetime())
+                .to_std()
+                .map_or(true, |age| age <= self.max_age);
+        }
+
+        match record.ctx().received() {
+            Some(received) => received.elapsed() <= self.max_age,
+            None => self.undated == UndatedRecords::Keep,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{tag::OutboundTagId, types::Value};
+
+    fn age_filter(body: &str) -> AgeFilter {
+        let cfg: RecordAgeConfig = toml::from_str(body).unwrap();
+        AgeFilter::from_config(&OutboundTagId::new("prometheus").into(), &cfg).unwrap()
+    }
+
+    fn record(field: &str, age_secs: i64) -> Record {
+        let mut record = Record::empty();
+        record.set(Symbol::new("name"), Value::from(format!("{}s", age_secs)));
+        record.set(
+            Symbol::new(field),
+            Value::from(Utc::now() - chrono::Duration::seconds(age_secs)),
+        );
+        record
+    }
+
+    fn names(records: &[Record]) -> Vec<&Value> {
+        records
+            .iter()
+            .map(|r| r.get(&Symbol::new("name")).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_age_filter() {
+        assert!(AgeFilter::from_config(
+            &OutboundTagId::new("prometheus").into(),
+            &RecordAgeConfig::default()
+        )
+        .is_none());
+
+        let mut filter = age_filter("max_record_age = \"1m\"");
+        let mut records = vec![
+            record("timestamp", 10),
+            record("timestamp", 3600),
+            record("timestamp", -30),
+            Record::empty(),
+        ];
+        filter.retain(&mut records);
+        // Records which can't be dated are kept by default
+        assert_eq!(records.len(), 3);
+        assert_eq!(
+            names(&records[..2]),
+            vec![&Value::from("10s"), &Value::from("-30s")]
+        );
+
+        let mut filter =
+            age_filter("max_record_age = \"1m\"\nage_field = \"ts\"\nundated_records = \"drop\"");
+        let mut records = vec![
+            record("ts", 10),
+            record("ts", 3600),
+            record("timestamp", 10),
+        ];
+        filter.retain(&mut records);
+        assert_eq!(names(&records), vec![&Value::from("10s")]);
+    }
+}
@@ -0,0 +1,103 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+use crate::{
+    actor_info, actor_warn,
+    config::{outbound::auth::AuthConfig, secret::read_secret_file},
+    core::tag::TagId,
+};
+
+/// The credentials as sent, those of the `*_file` variants as last read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Resolved {
+    None,
+    Basic { username: String, password: String },
+    Bearer(String),
+}
+
+/// The credentials of an outbound, shared by its send tasks. The files of `basic_file` and
+/// `bearer_file` are read once at start, and again by [`Credentials::reload`] when the
+/// endpoint answers 401, so that a rotated token is picked up without a restart.
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    tag: TagId,
+    config: AuthConfig,
+    resolved: Arc<RwLock<Resolved>>,
+}
+
+impl Credentials {
+    /// The files were read once by the verification of the config, an error reading them
+    /// now is logged and the credentials left empty until a reload.
+    pub fn new(tag: &TagId, config: &AuthConfig) -> Self {
+        let resolved = match config {
+            AuthConfig::None => Resolved::None,
+            AuthConfig::Basic { username, password } => Resolved::Basic {
+                username: username.get().clone(),
+                password: password.get().clone(),
+            },
+            AuthConfig::Bearer { token } => Resolved::Bearer(token.get().clone()),
+            _ => Self::read(tag, config).unwrap_or(Resolved::None),
+        };
+
+        Credentials {
+            tag: tag.clone(),
+            config: config.clone(),
+            resolved: Arc::new(RwLock::new(resolved)),
+        }
+    }
+
+    /// Read the files of the `*_file` variants, None if one cannot be read.
+    fn read(tag: &TagId, config: &AuthConfig) -> Option<Resolved> {
+        let read = |path: &PathBuf| match read_secret_file(path) {
+            Ok(content) => Some(content),
+            Err(e) => {
+                actor_warn!(tag, "error reading the credentials: {}", e);
+                None
+            }
+        };
+
+        match config {
+            AuthConfig::BasicFile {
+                username_file,
+                password_file,
+            } => Some(Resolved::Basic {
+                username: read(username_file)?,
+                password: read(password_file)?,
+            }),
+            AuthConfig::BearerFile { token_file } => Some(Resolved::Bearer(read(token_file)?)),
+            _ => None,
+        }
+    }
+
+    /// Read the credential files again, keeping the credentials as they were if one cannot
+    /// be read. Returns whether they changed, i.e. whether a request is worth sending again.
+    pub fn reload(&self) -> bool {
+        if self.config.files().is_empty() {
+            return false;
+        }
+        let Some(resolved) = Self::read(&self.tag, &self.config) else {
+            return false;
+        };
+
+        let mut current = self.resolved.write().unwrap();
+        if *current == resolved {
+            return false;
+        }
+        actor_info!(self.tag, "credentials changed, reloaded from their files");
+        *current = resolved;
+        true
+    }
+
+    /// Add the credentials to an HTTP request.
+    pub fn apply(&self, builder: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &*self.resolved.read().unwrap() {
+            Resolved::None => builder,
+            Resolved::Basic { username, password } => {
+                builder.basic_auth(username, Some(password.as_str()))
+            }
+            Resolved::Bearer(token) => builder.bearer_auth(token),
+        }
+    }
+}
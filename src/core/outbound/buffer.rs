@@ -0,0 +1,444 @@
+//! Spool on disk of the entries an outbound failed to deliver.
+//!
+//! Entries are appended length-delimited to numbered segment files. They are read back oldest
+//! first, in batches which stay claimed until acked, so that a single replay runs at a time
+//! and nothing is skipped. A segment is deleted once all its entries are acked. The acked
+//! offset is only kept in memory: after a restart, the oldest segment is replayed from its
+//! start again.
+
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    marker::PhantomData,
+    path::PathBuf,
+};
+
+use crate::{actor_warn, config::outbound::buffer::DiskBufferConfig, core::tag::TagId};
+
+const SEGMENT_EXTENSION: &str = "seg";
+
+// The cap is split into this many segments, the oldest one is dropped at a time
+const SEGMENTS: u64 = 8;
+
+#[derive(Debug)]
+struct Segment {
+    id: u64,
+    path: PathBuf,
+    size: u64,
+    // Offset of the first entry not delivered yet
+    acked: u64,
+}
+
+/// The batch returned by the last peek, up to `end` in the oldest segment.
+#[derive(Debug, Clone, Copy)]
+struct Claim {
+    segment: u64,
+    end: u64,
+}
+
+#[derive(Debug)]
+pub struct DiskBuffer<T> {
+    tag: TagId,
+    dir: PathBuf,
+    max_size: u64,
+    segment_size: u64,
+    batch_size: usize,
+
+    // Oldest first, entries are appended to the last one
+    segments: VecDeque<Segment>,
+    // Bytes of the segments on disk
+    size: u64,
+    claim: Option<Claim>,
+
+    _entries: PhantomData<fn() -> T>,
+}
+
+impl<T: prost::Message + Default> DiskBuffer<T> {
+    /// Open the buffer, the segments left by a previous run are replayed first.
+    pub fn open(tag: TagId, cfg: &DiskBufferConfig) -> io::Result<Self> {
+        fs::create_dir_all(&cfg.path)?;
+
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(&cfg.path)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != SEGMENT_EXTENSION) {
+                continue;
+            }
+            let Some(id) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse().ok())
+            else {
+                continue;
+            };
+            let size = fs::metadata(&path)?.len();
+            segments.push(Segment {
+                id,
+                path,
+                size,
+                acked: 0,
+            });
+        }
+        segments.sort_by_key(|segment| segment.id);
+
+        let max_size = cfg.max_size.get();
+        Ok(DiskBuffer {
+            tag,
+            dir: cfg.path.clone(),
+            max_size,
+            segment_size: (max_size / SEGMENTS).max(1),
+            batch_size: cfg.flush_batch_size,
+            size: segments.iter().map(|segment| segment.size).sum(),
+            segments: segments.into(),
+            claim: None,
+            _entries: PhantomData,
+        })
+    }
+
+    /// Whether everything has been delivered.
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Append the entries, returns the bytes of the oldest entries dropped to stay under the
+    /// cap, all of them when they do not fit at all.
+    pub fn push(&mut self, entries: &[T]) -> io::Result<u64> {
+        let mut buf = Vec::new();
+        for entry in entries {
+            entry
+                .encode_length_delimited(&mut buf)
+                .expect("a Vec grows as needed");
+        }
+        let len = buf.len() as u64;
+        if len == 0 {
+            return Ok(0);
+        }
+        if len > self.max_size {
+            return Ok(len);
+        }
+
+        let mut dropped = 0;
+        while self.size + len > self.max_size {
+            dropped += self.remove_oldest()?;
+        }
+
+        if self
+            .segments
+            .back()
+            .is_none_or(|segment| segment.size >= self.segment_size)
+        {
+            let id = self.segments.back().map_or(0, |segment| segment.id + 1);
+            self.segments.push_back(Segment {
+                id,
+                path: self.dir.join(format!("{:016}.{}", id, SEGMENT_EXTENSION)),
+                size: 0,
+                acked: 0,
+            });
+        }
+
+        let segment = self.segments.back_mut().expect("a segment was just added");
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&segment.path)?;
+        if let Err(e) = file.write_all(&buf) {
+            // Do not leave half an entry behind
+            let _ = file.set_len(segment.size);
+            return Err(e);
+        }
+        segment.size += len;
+        self.size += len;
+
+        Ok(dropped)
+    }
+
+    /// Claim the oldest entries, at most `flush_batch_size` of them, until they are acked or
+    /// released. Nothing while the previous claim is pending.
+    pub fn peek(&mut self) -> io::Result<Vec<T>> {
+        if self.claim.is_some() {
+            return Ok(Vec::new());
+        }
+
+        while let Some(segment) = self.segments.front() {
+            let (entries, end) = self.read(segment)?;
+            if entries.is_empty() {
+                // Nothing readable is left in it
+                self.remove_oldest()?;
+                continue;
+            }
+
+            self.claim = Some(Claim {
+                segment: segment.id,
+                end,
+            });
+            return Ok(entries);
+        }
+
+        Ok(Vec::new())
+    }
+
+    /// The claimed entries were delivered, they are not returned again.
+    pub fn ack(&mut self) -> io::Result<()> {
+        let Some(claim) = self.claim.take() else {
+            return Ok(());
+        };
+        // Dropped in the meantime to stay under the cap
+        let Some(segment) = self
+            .segments
+            .front_mut()
+            .filter(|segment| segment.id == claim.segment)
+        else {
+            return Ok(());
+        };
+
+        segment.acked = claim.end;
+        if segment.acked >= segment.size {
+            self.remove_oldest()?;
+        }
+        Ok(())
+    }
+
+    /// The claimed entries could not be delivered, the next peek returns them again.
+    pub fn release(&mut self) {
+        self.claim = None;
+    }
+
+    /// Read a batch from the first entry not acked, along with the offset following it. The
+    /// rest of a segment which cannot be decoded, e.g. cut short by a crash, is skipped.
+    fn read(&self, segment: &Segment) -> io::Result<(Vec<T>, u64)> {
+        let mut reader = BufReader::new(File::open(&segment.path)?);
+        reader.seek(SeekFrom::Start(segment.acked))?;
+
+        let mut entries = Vec::new();
+        let mut offset = segment.acked;
+        while entries.len() < self.batch_size && offset < segment.size {
+            let entry = read_entry(&mut reader, segment.size - offset).and_then(|(len, buf)| {
+                T::decode(buf.as_slice())
+                    .map(|entry| (len, entry))
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            });
+            match entry {
+                Ok((len, entry)) => {
+                    entries.push(entry);
+                    offset += len;
+                }
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+                    ) =>
+                {
+                    actor_warn!(
+                        self.tag,
+                        "skipped {} unreadable bytes of {}: {}",
+                        segment.size - offset,
+                        segment.path.display(),
+                        e
+                    );
+                    offset = segment.size;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok((entries, offset))
+    }
+
+    /// Delete the oldest segment, returns the bytes not delivered from it.
+    fn remove_oldest(&mut self) -> io::Result<u64> {
+        let Some(segment) = self.segments.pop_front() else {
+            return Ok(0);
+        };
+        self.size -= segment.size;
+
+        match fs::remove_file(&segment.path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(segment.size - segment.acked)
+    }
+}
+
+/// Read a length-delimited entry out of the `remaining` bytes of its segment, returns its
+/// length with the prefix and its bytes.
+fn read_entry(reader: &mut impl Read, remaining: u64) -> io::Result<(u64, Vec<u8>)> {
+    let mut len = 0u64;
+    // A varint of 64 bits takes at most 10 bytes
+    for shift in 0..10 {
+        let mut byte = [0u8];
+        reader.read_exact(&mut byte)?;
+        len |= u64::from(byte[0] & 0x7f) << (7 * shift);
+        if byte[0] & 0x80 == 0 {
+            // A corrupted length must not allocate more than the segment holds
+            if len > remaining.saturating_sub(shift + 1) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("entry of {} bytes past the end of the segment", len),
+                ));
+            }
+            let mut buf = vec![0; len as usize];
+            reader.read_exact(&mut buf)?;
+            return Ok((shift + 1 + len, buf));
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "invalid entry length",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use super::*;
+    use crate::core::{
+        tag::OutboundTagId,
+        types::conv::prometheus::{Label, Sample, TimeSeries},
+    };
+
+    fn series(name: &str, timestamp: i64) -> TimeSeries {
+        TimeSeries {
+            labels: vec![Label {
+                name: "__name__".to_string(),
+                value: name.to_string(),
+            }],
+            samples: vec![Sample {
+                value: 1.0,
+                timestamp,
+            }],
+        }
+    }
+
+    fn open(dir: &std::path::Path, max_size: u64, batch_size: usize) -> DiskBuffer<TimeSeries> {
+        let cfg: DiskBufferConfig = toml::from_str(&format!(
+            "path = {:?}\nmax_size = {}\nflush_batch_size = {}",
+            dir.to_str().unwrap(),
+            max_size,
+            batch_size
+        ))
+        .unwrap();
+        DiskBuffer::open(OutboundTagId::new("prometheus").into(), &cfg).unwrap()
+    }
+
+    fn timestamps(entries: &[TimeSeries]) -> Vec<i64> {
+        entries.iter().map(|ts| ts.samples[0].timestamp).collect()
+    }
+
+    fn segment_files(dir: &std::path::Path) -> usize {
+        fs::read_dir(dir).unwrap().count()
+    }
+
+    #[test]
+    fn test_replay_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut buffer = open(dir.path(), 1024 * 1024, 2);
+        assert!(buffer.peek().unwrap().is_empty());
+
+        buffer.push(&[series("a", 1), series("a", 2)]).unwrap();
+        buffer.push(&[series("b", 3)]).unwrap();
+
+        assert_eq!(timestamps(&buffer.peek().unwrap()), vec![1, 2]);
+        // Claimed until acked or released
+        assert!(buffer.peek().unwrap().is_empty());
+        buffer.release();
+        assert_eq!(timestamps(&buffer.peek().unwrap()), vec![1, 2]);
+        buffer.ack().unwrap();
+
+        assert_eq!(timestamps(&buffer.peek().unwrap()), vec![3]);
+        buffer.release();
+
+        // Kept across restarts, the oldest segment is replayed from its start
+        drop(buffer);
+        let mut buffer = open(dir.path(), 1024 * 1024, 2);
+        assert_eq!(timestamps(&buffer.peek().unwrap()), vec![1, 2]);
+        buffer.ack().unwrap();
+        assert_eq!(timestamps(&buffer.peek().unwrap()), vec![3]);
+        buffer.ack().unwrap();
+
+        assert!(buffer.is_empty());
+        assert_eq!(segment_files(dir.path()), 0);
+    }
+
+    #[test]
+    fn test_max_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let len = series("a", 1).encoded_len() as u64 + 1;
+        // Two entries per segment
+        let mut buffer = open(dir.path(), len * 2 * SEGMENTS, 100);
+
+        for timestamp in 1..=(2 * SEGMENTS as i64) {
+            assert_eq!(buffer.push(&[series("a", timestamp)]).unwrap(), 0);
+        }
+        assert_eq!(segment_files(dir.path()), SEGMENTS as usize);
+
+        // The oldest segment makes room
+        assert_eq!(buffer.push(&[series("a", 100)]).unwrap(), len * 2);
+        assert_eq!(segment_files(dir.path()), SEGMENTS as usize);
+        assert_eq!(timestamps(&buffer.peek().unwrap()), vec![3, 4]);
+
+        // Never fits
+        let entries = vec![series("a", 1); 2 * SEGMENTS as usize + 1];
+        assert_eq!(buffer.push(&entries).unwrap(), len * entries.len() as u64);
+    }
+
+    #[test]
+    fn test_corrupted_length() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut buffer = open(dir.path(), 1024 * 1024, 100);
+        buffer.push(&[series("a", 1)]).unwrap();
+        drop(buffer);
+
+        // A length of 2^63 follows the first entry
+        let path = fs::read_dir(dir.path())
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00,
+        ])
+        .unwrap();
+        drop(file);
+
+        let mut buffer = open(dir.path(), 1024 * 1024, 100);
+        assert_eq!(timestamps(&buffer.peek().unwrap()), vec![1]);
+        buffer.ack().unwrap();
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_truncated_segment() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut buffer = open(dir.path(), 1024 * 1024, 100);
+        buffer
+            .push(&[series("a", 1), series("a", 2), series("a", 3)])
+            .unwrap();
+        drop(buffer);
+
+        // Cut short in the middle of the last entry
+        let path = fs::read_dir(dir.path())
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let size = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(size - 3)
+            .unwrap();
+
+        let mut buffer = open(dir.path(), 1024 * 1024, 100);
+        assert_eq!(timestamps(&buffer.peek().unwrap()), vec![1, 2]);
+        buffer.ack().unwrap();
+        assert!(buffer.is_empty());
+    }
+}
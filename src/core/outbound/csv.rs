@@ -0,0 +1,409 @@
+use std::{borrow::Cow, io::Write, path::PathBuf};
+
+use async_trait::async_trait;
+use log::info;
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    actor_info,
+    config::outbound::{
+        csv::{CsvColumn, CsvOutboundConfig},
+        StableOrderConfig,
+    },
+    core::{
+        actor::Actor,
+        manager::{ChannelGraph, TaggedReceiver},
+        tag::{HasTag, TagId},
+        types::{Record, Value},
+    },
+    utils::recv::recv_batch,
+};
+
+use super::{base::Outbound, order::sort_records, shed::LoadShedder};
+
+/// Renders records as CSV lines.
+///
+/// Fields are quoted as the CSV protocol parser expects them: a field holding the delimiter,
+/// a quote, a line break or surrounding spaces is wrapped in quotes, with its quotes doubled.
+struct CsvEncoder {
+    columns: Vec<CsvColumn>,
+    delimiter: char,
+    missing_value: String,
+}
+
+impl CsvEncoder {
+    fn header(&self, out: &mut String) {
+        for (i, column) in self.columns.iter().enumerate() {
+            if i > 0 {
+                out.push(self.delimiter);
+            }
+            self.push_field(out, column.name.as_str());
+        }
+        out.push('\n');
+    }
+
+    fn encode(&self, record: &Record, out: &mut String) {
+        for (i, column) in self.columns.iter().enumerate() {
+            if i > 0 {
+                out.push(self.delimiter);
+            }
+
+            match record.get(&column.name) {
+                None | Some(Value::Null) => self.push_field(out, &self.missing_value),
+                Some(value) => self.push_field(out, &render(column, value)),
+            }
+        }
+        out.push('\n');
+    }
+
+    fn push_field(&self, out: &mut String, field: &str) {
+        let needs_quotes =
+            field.contains([self.delimiter, '"', '\n', '\r']) || field.trim() != field;
+        if !needs_quotes {
+            out.push_str(field);
+            return;
+        }
+
+        out.push('"');
+        out.push_str(&field.replace('"', "\"\""));
+        out.push('"');
+    }
+}
+
+fn render<'a>(column: &CsvColumn, value: &'a Value) -> Cow<'a, str> {
+    match (value, column.format.as_deref(), column.precision) {
+        (Value::String(s), _, _) => Cow::Borrowed(s.as_str()),
+        (Value::DateTime(dt), Some(format), _) => Cow::Owned(dt.format(format).to_string()),
+        (Value::Float(n), _, Some(precision)) => match &n.unit {
+            Some(unit) => Cow::Owned(format!("{:.*} {}", precision, n.value, unit)),
+            None => Cow::Owned(format!("{:.*}", precision, n.value)),
+        },
+        // 与 CSV 协议解析器的格式一致: 带单位的数值为 "1.5 ms", 时间为 RFC 3339
+        (value, _, _) => match value.cast_string() {
+            Ok(s) => Cow::Owned(s.to_string()),
+            Err(_) => Cow::Owned(value.to_string()),
+        },
+    }
+}
+
+pub struct CsvOutbound {
+    tag: TagId,
+    path: PathBuf,
+    batch_size: usize,
+    inbounds: Vec<TaggedReceiver>,
+    shedder: Option<LoadShedder>,
+
+    encoder: CsvEncoder,
+    file: BufWriter<Box<dyn AsyncWrite + Send + Unpin>>,
+    buffer: Vec<Record>,
+    order: StableOrderConfig,
+}
+
+impl HasTag for CsvOutbound {
+    fn tag(&self) -> &TagId {
+        &self.tag
+    }
+}
+
+impl CsvOutbound {
+    pub fn try_create_from(
+        cfg: CsvOutboundConfig,
+        channels: &mut ChannelGraph,
+    ) -> super::Result<Self> {
+        let tag: TagId = (&cfg.tag).into();
+        let inbounds = cfg
+            .inbounds
+            .iter()
+            .map(|inbound| channels.recv_from(inbound, &tag))
+            .collect::<Vec<_>>();
+
+        Self::new(cfg, inbounds, channels.is_dry_run())
+    }
+
+    /// Nothing is written on a dry run, the file is not even created.
+    fn new(
+        cfg: CsvOutboundConfig,
+        inbounds: Vec<TaggedReceiver>,
+        dry_run: bool,
+    ) -> super::Result<Self> {
+        let tag: TagId = cfg.tag.into();
+        let path = cfg.path.take();
+        let encoder = CsvEncoder {
+            columns: cfg.columns,
+            delimiter: cfg.delimiter,
+            missing_value: cfg.missing_value,
+        };
+
+        let file: Box<dyn AsyncWrite + Send + Unpin> = match dry_run {
+            true => Box::new(tokio::io::sink()),
+            false => {
+                let mut file = std::fs::File::create(&path)?;
+                if cfg.write_header {
+                    let mut header = String::new();
+                    encoder.header(&mut header);
+                    file.write_all(header.as_bytes())?;
+                }
+                actor_info!(tag, "writing CSV to {:?}", path);
+                Box::new(tokio::fs::File::from_std(file))
+            }
+        };
+
+        let shedder = LoadShedder::from_config(&tag, &cfg.shedding);
+        Ok(CsvOutbound {
+            tag,
+            path,
+            batch_size: cfg.batch_size,
+            inbounds,
+            shedder,
+            encoder,
+            file: BufWriter::new(file),
+            buffer: Vec::with_capacity(cfg.batch_size),
+            order: cfg.order,
+        })
+    }
+
+    /// Write the buffered records and flush the file.
+    async fn flush_records(&mut self) -> super::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let mut records = std::mem::replace(&mut self.buffer, Vec::with_capacity(self.batch_size));
+        if self.order.stable_order {
+            sort_records(&mut records, &self.order.sort_keys);
+        }
+
+        let mut text = String::new();
+        for record in &records {
+            self.encoder.encode(record, &mut text);
+        }
+
+        self.file.write_all(text.as_bytes()).await?;
+        self.file.flush().await?;
+        info!("Wrote {} records to {:?}", records.len(), self.path);
+        for record in &records {
+            record.mark_record_release(&self.tag);
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Actor for CsvOutbound {
+    type Error = super::Error;
+
+    async fn poll(&mut self, ctx: CancellationToken) -> super::Result<()> {
+        let tag = self.tag.clone();
+        let batch_size = self.batch_size;
+
+        let mut records = match recv_batch(
+            &tag,
+            self.inbounds(),
+            Some(std::time::Duration::from_millis(100)),
+            batch_size,
+            std::time::Duration::from_millis(100),
+            ctx,
+        )
+        .await
+        {
+            Ok(records) => records,
+            Err(crate::utils::recv::Error::Timeout | crate::utils::recv::Error::Canceled) => {
+                return self.flush_records().await;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(ref mut shedder) = self.shedder {
+            shedder.retain(&mut records, crate::utils::recv::occupancy(&self.inbounds));
+        }
+
+        self.buffer.extend(records);
+        if self.buffer.len() >= self.batch_size {
+            self.flush_records().await?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Outbound for CsvOutbound {
+    fn inbounds(&mut self) -> &mut [TaggedReceiver] {
+        &mut self.inbounds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+
+    use super::*;
+    use crate::{
+        config::{
+            protocol::csv::{CSVField, CSVProtocolConfig, MatchBy},
+            ProtocolConfig, Verify,
+        },
+        core::{
+            protocol,
+            tag::{TagId, PROTOCOL_TAG_SCOPE},
+            types::{parse_value, Primitive, Symbol, ValueType},
+        },
+    };
+
+    fn column(name: &str) -> CsvColumn {
+        CsvColumn {
+            name: Symbol::new(name),
+            format: None,
+            precision: None,
+        }
+    }
+
+    fn encoder(columns: Vec<CsvColumn>) -> CsvEncoder {
+        CsvEncoder {
+            columns,
+            delimiter: ',',
+            missing_value: String::new(),
+        }
+    }
+
+    fn record(fields: &[(&str, Value)]) -> Record {
+        let mut record = Record::empty();
+        for (name, value) in fields {
+            record.set(Symbol::new(name), value.clone());
+        }
+        record
+    }
+
+    fn datetime() -> DateTime<Utc> {
+        DateTime::from_timestamp(1_743_667_743, 452_000_123).unwrap()
+    }
+
+    #[test]
+    fn test_quoting() {
+        let encoder = encoder(vec![column("a"), column("b"), column("c"), column("d")]);
+        let mut out = String::new();
+        encoder.encode(
+            &record(&[
+                ("a", Value::from("x,y")),
+                ("b", Value::from("say \"hi\"")),
+                ("c", Value::from(" padded")),
+                ("d", Value::from("plain")),
+            ]),
+            &mut out,
+        );
+        assert_eq!(out, "\"x,y\",\"say \"\"hi\"\"\",\" padded\",plain\n");
+    }
+
+    #[test]
+    fn test_column_formats() {
+        let mut encoder = encoder(vec![
+            CsvColumn {
+                format: Some("%Y-%m-%d %H:%M".to_string()),
+                ..column("timestamp")
+            },
+            CsvColumn {
+                precision: Some(2),
+                ..column("value")
+            },
+            column("host"),
+        ]);
+        encoder.missing_value = "NA".to_string();
+
+        let mut out = String::new();
+        encoder.header(&mut out);
+        encoder.encode(
+            &record(&[
+                ("timestamp", Value::DateTime(datetime())),
+                ("value", Value::from(1.0 / 3.0)),
+            ]),
+            &mut out,
+        );
+        assert_eq!(out, "timestamp,value,host\n2025-04-03 08:09,0.33,NA\n");
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.csv");
+
+        let fields = [
+            ("name", Primitive::String, false),
+            ("count", Primitive::Int, false),
+            ("value", Primitive::Float, false),
+            ("up", Primitive::Bool, false),
+            ("timestamp", Primitive::DateTime, false),
+            ("note", Primitive::String, true),
+        ];
+
+        let records = vec![
+            record(&[
+                ("name", Value::from("cpu,total")),
+                ("count", Value::from(-42i64)),
+                ("value", Value::from(0.1 + 0.2)),
+                ("up", Value::from(true)),
+                ("timestamp", Value::DateTime(datetime())),
+                ("note", Value::from("say \"hi\"\r\non two lines")),
+            ]),
+            record(&[
+                ("name", Value::from("温度")),
+                ("count", Value::from(i64::MAX)),
+                ("value", parse_value("1.5e-7 ms", ValueType::Float).unwrap()),
+                ("up", Value::from(false)),
+                ("timestamp", Value::DateTime(DateTime::UNIX_EPOCH)),
+            ]),
+        ];
+
+        let cfg: CsvOutboundConfig = toml::from_str(&format!(
+            r#"
+            inbounds = ["pipe:timeseries"]
+            path = "{}"
+            columns = [{}]
+            "#,
+            path.display(),
+            fields
+                .iter()
+                .map(|(name, _, _)| format!("{{ name = \"{}\" }}", name))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+        .unwrap();
+        let mut outbound = CsvOutbound::new(cfg, Vec::new(), false).unwrap();
+        outbound.buffer.extend(records.clone());
+        outbound.flush_records().await.unwrap();
+        drop(outbound);
+
+        let mut cfg = CSVProtocolConfig {
+            tag: TagId::new(PROTOCOL_TAG_SCOPE, "csv").into(),
+            has_header: true,
+            delimiter: ',',
+            fields: fields
+                .iter()
+                .enumerate()
+                .map(|(index, (name, r#type, optional))| CSVField {
+                    name: Symbol::new(name),
+                    r#type: r#type.clone(),
+                    index,
+                    optional: *optional,
+                })
+                .collect(),
+            num_fields: fields.len(),
+            match_by: MatchBy::Header,
+            header_case_insensitive: false,
+            strict_quotes: false,
+            timezone: chrono_tz::Tz::UTC,
+        };
+        cfg.verify().unwrap();
+
+        let file = tokio::fs::File::open(&path).await.unwrap();
+        let mut parser = protocol::try_create_from(file, ProtocolConfig::Csv(cfg)).unwrap();
+        for expected in &records {
+            let actual = parser.read_next().await.unwrap();
+            assert_eq!(actual.len(), expected.len());
+            for (name, _, _) in &fields {
+                let name = Symbol::new(name);
+                assert_eq!(actual.get(&name), expected.get(&name), "field {}", name);
+            }
+        }
+        assert!(parser.read_next().await.is_err());
+    }
+}
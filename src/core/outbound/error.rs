@@ -7,6 +7,12 @@ pub enum Error {
     #[diagnostic(transparent)]
     Prometheus(#[from] super::prometheus::Error),
     #[error(transparent)]
+    #[diagnostic(transparent)]
+    Otlp(#[from] super::otlp::Error),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Kafka(#[from] super::kafka::Error),
+    #[error(transparent)]
     Recv(#[from] crate::utils::recv::Error),
     #[error(transparent)]
     Io(#[from] std::io::Error),
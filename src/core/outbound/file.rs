@@ -0,0 +1,330 @@
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use log::info;
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    actor_info, actor_warn,
+    config::outbound::{file::FileOutboundConfig, StableOrderConfig},
+    core::{
+        actor::Actor,
+        dead_letter::DeadLetter,
+        manager::{ChannelGraph, TaggedReceiver},
+        tag::{HasTag, TagId},
+        types::{Record, Value},
+    },
+    utils::recv::recv_batch,
+};
+
+use super::{base::Outbound, order::sort_records, shed::LoadShedder};
+
+/// Appends records to a file as JSON lines, see [`Record::to_json`].
+pub struct FileOutbound {
+    tag: TagId,
+    path: PathBuf,
+    batch_size: usize,
+    inbounds: Vec<TaggedReceiver>,
+    shedder: Option<LoadShedder>,
+
+    max_file_size: Option<u64>,
+    max_files: usize,
+    record_type: Option<Value>,
+
+    file: BufWriter<Box<dyn AsyncWrite + Send + Unpin>>,
+    size: u64,
+    buffer: Vec<Record>,
+    order: StableOrderConfig,
+    dead_letter: DeadLetter,
+}
+
+impl HasTag for FileOutbound {
+    fn tag(&self) -> &TagId {
+        &self.tag
+    }
+}
+
+fn open(path: &Path) -> std::io::Result<(tokio::fs::File, u64)> {
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    let size = file.metadata()?.len();
+    Ok((tokio::fs::File::from_std(file), size))
+}
+
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+impl FileOutbound {
+    pub fn try_create_from(
+        cfg: FileOutboundConfig,
+        channels: &mut ChannelGraph,
+    ) -> super::Result<Self> {
+        let tag: TagId = (&cfg.tag).into();
+        let inbounds = cfg
+            .inbounds
+            .iter()
+            .map(|inbound| channels.recv_from(inbound, &tag))
+            .collect::<Vec<_>>();
+
+        let mut outbound = Self::new(cfg, inbounds, channels.is_dry_run())?;
+        outbound.dead_letter = channels.dead_letter(&tag);
+        Ok(outbound)
+    }
+
+    /// Nothing is written on a dry run, the file is not even created.
+    fn new(
+        cfg: FileOutboundConfig,
+        inbounds: Vec<TaggedReceiver>,
+        dry_run: bool,
+    ) -> super::Result<Self> {
+        let tag: TagId = cfg.tag.into();
+        let path = cfg.path.take();
+
+        let (file, size): (Box<dyn AsyncWrite + Send + Unpin>, _) = match dry_run {
+            true => (Box::new(tokio::io::sink()), 0),
+            false => {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                let (file, size) = open(&path)?;
+                actor_info!(tag, "writing JSON lines to {:?}", path);
+                (Box::new(file), size)
+            }
+        };
+
+        let shedder = LoadShedder::from_config(&tag, &cfg.shedding);
+        Ok(FileOutbound {
+            dead_letter: DeadLetter::new(tag.clone(), None),
+            tag,
+            path,
+            batch_size: cfg.batch_size,
+            inbounds,
+            shedder,
+            max_file_size: cfg.max_file_size.map(|size| size.get()),
+            max_files: cfg.max_files,
+            record_type: cfg.record_type.map(Value::from),
+            file: BufWriter::new(file),
+            size,
+            buffer: Vec::with_capacity(cfg.batch_size),
+            order: cfg.order,
+        })
+    }
+
+    /// Close the current file, shift the rotated ones and start a new file.
+    async fn rotate(&mut self) -> super::Result<()> {
+        self.file.flush().await?;
+
+        if self.max_files == 0 {
+            tokio::fs::remove_file(&self.path).await?;
+        } else {
+            for n in (1..self.max_files).rev() {
+                let from = rotated(&self.path, n);
+                if tokio::fs::try_exists(&from).await? {
+                    tokio::fs::rename(&from, rotated(&self.path, n + 1)).await?;
+                }
+            }
+            tokio::fs::rename(&self.path, rotated(&self.path, 1)).await?;
+        }
+
+        let (file, size) = open(&self.path)?;
+        self.file = BufWriter::new(Box::new(file));
+        self.size = size;
+        actor_info!(self.tag, "rotated {:?}", self.path);
+
+        Ok(())
+    }
+
+    /// Write the buffered records and flush the file.
+    async fn flush_records(&mut self) -> super::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let mut records = std::mem::replace(&mut self.buffer, Vec::with_capacity(self.batch_size));
+        if self.order.stable_order {
+            sort_records(&mut records, &self.order.sort_keys);
+        }
+
+        let mut written = 0;
+        for record in &records {
+            let line = match record.to_json() {
+                Ok(json) => format!("{}\n", json),
+                Err(e) => {
+                    actor_warn!(self.tag, "record not written: {}", e);
+                    self.dead_letter.send(record.clone(), &e);
+                    continue;
+                }
+            };
+
+            // A single line larger than max_file_size still goes to a file of its own
+            let size = self.size + line.len() as u64;
+            if self
+                .max_file_size
+                .is_some_and(|max| self.size > 0 && size > max)
+            {
+                self.rotate().await?;
+            }
+
+            self.file.write_all(line.as_bytes()).await?;
+            self.size += line.len() as u64;
+            written += 1;
+            record.mark_record_release(&self.tag);
+        }
+
+        self.file.flush().await?;
+        info!("Wrote {} records to {:?}", written, self.path);
+
+        Ok(())
+    }
+
+    fn accepts(&self, record: &Record) -> bool {
+        match &self.record_type {
+            Some(r#type) => record.get_type() == Some(r#type),
+            None => true,
+        }
+    }
+}
+
+#[async_trait]
+impl Actor for FileOutbound {
+    type Error = super::Error;
+
+    async fn poll(&mut self, ctx: CancellationToken) -> super::Result<()> {
+        let tag = self.tag.clone();
+        let batch_size = self.batch_size;
+
+        let mut records = match recv_batch(
+            &tag,
+            self.inbounds(),
+            Some(std::time::Duration::from_millis(100)),
+            batch_size,
+            std::time::Duration::from_millis(100),
+            ctx,
+        )
+        .await
+        {
+            Ok(records) => records,
+            // 退出时写出剩余的记录
+            Err(crate::utils::recv::Error::Timeout | crate::utils::recv::Error::Canceled) => {
+                return self.flush_records().await;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(ref mut shedder) = self.shedder {
+            shedder.retain(&mut records, crate::utils::recv::occupancy(&self.inbounds));
+        }
+
+        let records = records
+            .into_iter()
+            .filter(|record| self.accepts(record))
+            .collect::<Vec<_>>();
+        self.buffer.extend(records);
+        if self.buffer.len() >= self.batch_size {
+            self.flush_records().await?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Outbound for FileOutbound {
+    fn inbounds(&mut self) -> &mut [TaggedReceiver] {
+        &mut self.inbounds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::{
+            manager::ActorChannel, pipe::RECORD_TYPE_TIMESERIES_VALUE, tag::PipeTagId,
+            types::Symbol,
+        },
+        utils::tracing::TracingContext,
+    };
+
+    fn config(path: &Path, extra: &str) -> FileOutboundConfig {
+        toml::from_str(&format!(
+            "inbounds = [\"pipe:timeseries\"]\npath = \"{}\"\n{}",
+            path.display(),
+            extra
+        ))
+        .unwrap()
+    }
+
+    fn record(i: i64) -> Record {
+        let mut record = Record::new(TracingContext::new_root());
+        record.set(Symbol::new("name"), Value::from("cpu"));
+        record.set(Symbol::new("value"), Value::from(i));
+        record
+    }
+
+    fn values(path: &Path) -> Vec<i64> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let json: serde_json::Value = serde_json::from_str(line).unwrap();
+                json["value"].as_i64().unwrap()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.jsonl");
+        // {"name":"cpu","value":N}\n is 25 bytes for a single digit
+        let cfg = config(&path, "max_file_size = 60\nmax_files = 2");
+        let mut outbound = FileOutbound::new(cfg, Vec::new(), false).unwrap();
+
+        for i in 0..9 {
+            outbound.buffer.push(record(i));
+            outbound.flush_records().await.unwrap();
+        }
+        drop(outbound);
+
+        assert_eq!(values(&path), vec![8]);
+        assert_eq!(values(&rotated(&path, 1)), vec![6, 7]);
+        assert_eq!(values(&rotated(&path, 2)), vec![4, 5]);
+        assert!(!rotated(&path, 3).exists());
+
+        // Appended to after a restart
+        let cfg = config(&path, "max_file_size = 60\nmax_files = 2");
+        let mut outbound = FileOutbound::new(cfg, Vec::new(), false).unwrap();
+        outbound.buffer.extend([record(9), record(10)]);
+        outbound.flush_records().await.unwrap();
+        assert_eq!(values(&path), vec![10]);
+        assert_eq!(values(&rotated(&path, 1)), vec![8, 9]);
+        assert_eq!(values(&rotated(&path, 2)), vec![6, 7]);
+    }
+
+    #[tokio::test]
+    async fn test_record_type_and_flush_on_cancel() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.jsonl");
+        let cfg = config(&path, "record_type = \"TimeseriesRecord\"");
+        let mut channel = ActorChannel::new(PipeTagId::new("timeseries").into(), 16);
+        let inbound = channel.receiver(&TagId::from(&cfg.tag));
+        let mut outbound = FileOutbound::new(cfg, vec![inbound], false).unwrap();
+
+        let mut timeseries = record(1);
+        timeseries.set_type(RECORD_TYPE_TIMESERIES_VALUE.clone());
+        assert!(outbound.accepts(&timeseries));
+        assert!(!outbound.accepts(&record(2)));
+
+        // Buffered records are written when the outbound is cancelled
+        outbound.buffer.push(timeseries);
+        let ctx = CancellationToken::new();
+        ctx.cancel();
+        outbound.poll(ctx).await.unwrap();
+        assert_eq!(values(&path), vec![1]);
+    }
+}
@@ -0,0 +1,273 @@
+use std::fmt::Write;
+
+use serde_json::{Map, Value as JsonValue};
+
+use crate::{
+    config::outbound::stdio::OutputFormat,
+    core::types::{conv::json::ConversionError, Record, Symbol, Value},
+};
+
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const CYAN: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+/// Keys and values of a record, in the order they are written
+type Entries<'a> = Vec<(String, &'a Value)>;
+
+/// Turns records into text, one line per record except in the pretty format.
+#[derive(Debug, Clone)]
+pub struct RecordFormatter {
+    format: OutputFormat,
+    fields: Option<Vec<Symbol>>,
+    color: bool,
+}
+
+impl RecordFormatter {
+    pub fn new(format: OutputFormat, fields: Option<Vec<Symbol>>, color: bool) -> Self {
+        Self {
+            format,
+            fields,
+            color,
+        }
+    }
+
+    /// The record followed by a newline.
+    pub fn format(&self, record: &Record) -> Result<String, ConversionError> {
+        let (fields, attrs) = self.entries(record);
+        let mut out = match self.format {
+            OutputFormat::Json => self.json(record, &fields)?,
+            OutputFormat::Logfmt => logfmt(&fields, &attrs),
+            OutputFormat::Pretty => self.pretty(record, &fields, &attrs),
+        };
+        out.push('\n');
+        Ok(out)
+    }
+
+    /// The fields to write, and the attributes unless the fields are projected.
+    fn entries<'a>(&self, record: &'a Record) -> (Entries<'a>, Entries<'a>) {
+        match self.fields {
+            Some(ref fields) => {
+                let fields = fields
+                    .iter()
+                    .filter_map(|key| record.get(key).map(|v| (key.as_str().to_string(), v)))
+                    .collect();
+                (fields, Vec::new())
+            }
+            None => {
+                let fields = record
+                    .iter()
+                    .map(|(k, v)| (k.as_str().to_string(), v))
+                    .collect();
+                let attrs = record
+                    .attributes()
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v))
+                    .collect();
+                (fields, attrs)
+            }
+        }
+    }
+
+    fn json(
+        &self,
+        record: &Record,
+        fields: &[(String, &Value)],
+    ) -> Result<String, ConversionError> {
+        if self.fields.is_none() {
+            return Ok(record.to_json()?.to_string());
+        }
+
+        let mut map = Map::new();
+        for (key, value) in fields {
+            map.insert(key.clone(), JsonValue::try_from(*value)?);
+        }
+        Ok(JsonValue::Object(map).to_string())
+    }
+
+    fn pretty(
+        &self,
+        record: &Record,
+        fields: &[(String, &Value)],
+        attrs: &[(String, &Value)],
+    ) -> String {
+        let paint = |s: &str, color: &str| match self.color {
+            true => format!("{}{}{}", color, s, RESET),
+            false => s.to_string(),
+        };
+
+        let r#type = record
+            .get_type()
+            .map(|t| t.to_string())
+            .unwrap_or("Record".to_string());
+        let width = fields
+            .iter()
+            .chain(attrs)
+            .map(|(k, _)| k.len())
+            .max()
+            .unwrap_or(0);
+
+        let mut out = format!("{} {{\n", paint(&r#type, BOLD));
+        for ((key, value), color) in fields
+            .iter()
+            .map(|e| (e, CYAN))
+            .chain(attrs.iter().map(|e| (e, DIM)))
+        {
+            let _ = writeln!(
+                out,
+                "  {}{} = {}",
+                paint(key, color),
+                " ".repeat(width - key.len()),
+                render(value, true)
+            );
+        }
+        out.push('}');
+        out
+    }
+}
+
+fn logfmt(fields: &[(String, &Value)], attrs: &[(String, &Value)]) -> String {
+    fields
+        .iter()
+        .chain(attrs)
+        .map(|(key, value)| {
+            let value = match value {
+                Value::String(s) => s.as_str().to_string(),
+                other => render(other, true),
+            };
+            format!("{}={}", key, quote_logfmt(&value))
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Quote a logfmt value when it is empty or contains spaces, `=`, quotes or control characters.
+fn quote_logfmt(value: &str) -> String {
+    let needs_quotes = value.is_empty()
+        || value
+            .chars()
+            .any(|c| c.is_whitespace() || c == '=' || c == '"' || c.is_control());
+    if !needs_quotes {
+        return value.to_string();
+    }
+
+    JsonValue::String(value.to_string()).to_string()
+}
+
+/// Human readable form of a value. Map keys are sorted, numbers keep their unit.
+fn render(value: &Value, quote_strings: bool) -> String {
+    match value {
+        Value::String(s) if quote_strings => JsonValue::String(s.as_str().to_string()).to_string(),
+        Value::DateTime(dt) => dt.to_rfc3339(),
+        Value::Map(map) => {
+            let mut entries = map
+                .iter()
+                .map(|(k, v)| (render(k, false), render(v, true)))
+                .collect::<Vec<_>>();
+            entries.sort();
+            let entries = entries
+                .into_iter()
+                .map(|(k, v)| format!("{}: {}", k, v))
+                .collect::<Vec<_>>();
+            format!("{{{}}}", entries.join(", "))
+        }
+        Value::Array(array) => {
+            let items = array.iter().map(|v| render(v, true)).collect::<Vec<_>>();
+            format!("[{}]", items.join(", "))
+        }
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::types::{parse_value, Attribute, ValueType};
+
+    fn record() -> Record {
+        let mut record = Record::empty();
+        record.set(Symbol::new("name"), Value::from("http requests"));
+        record.set(
+            Symbol::new("latency"),
+            parse_value("12.5 ms", ValueType::Float).unwrap(),
+        );
+        record.set(
+            Symbol::new("labels"),
+            Value::from(vec![
+                (Symbol::new("zone"), Value::from("a")),
+                (Symbol::new("az"), Value::from("b c")),
+            ]),
+        );
+        record.set(
+            Symbol::new("values"),
+            Value::from(vec![Value::from(1i64), Value::from("x")]),
+        );
+        record.set_attribute(Attribute::Type, Value::from("TimeseriesRecord"));
+        record
+    }
+
+    #[test]
+    fn test_logfmt() {
+        let formatter = RecordFormatter::new(OutputFormat::Logfmt, None, false);
+        assert_eq!(
+            formatter.format(&record()).unwrap(),
+            concat!(
+                r#"labels="{az: \"b c\", zone: \"a\"}" latency="12.5 ms" name="http requests" "#,
+                r#"values="[1, \"x\"]" __type__=TimeseriesRecord"#,
+                "\n"
+            )
+        );
+
+        let formatter = RecordFormatter::new(
+            OutputFormat::Logfmt,
+            Some(vec![Symbol::new("latency"), Symbol::new("missing")]),
+            false,
+        );
+        assert_eq!(
+            formatter.format(&record()).unwrap(),
+            "latency=\"12.5 ms\"\n"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let formatter = RecordFormatter::new(OutputFormat::Json, None, false);
+        let line = formatter.format(&record()).unwrap();
+        assert_eq!(line.lines().count(), 1);
+        let json: JsonValue = serde_json::from_str(&line).unwrap();
+        assert_eq!(json["labels"]["az"], "b c");
+        assert_eq!(json["values"][1], "x");
+        assert_eq!(json["__type__"], "TimeseriesRecord");
+
+        let formatter =
+            RecordFormatter::new(OutputFormat::Json, Some(vec![Symbol::new("name")]), false);
+        assert_eq!(
+            formatter.format(&record()).unwrap(),
+            "{\"name\":\"http requests\"}\n"
+        );
+    }
+
+    #[test]
+    fn test_pretty() {
+        let formatter = RecordFormatter::new(OutputFormat::Pretty, None, false);
+        assert_eq!(
+            formatter.format(&record()).unwrap(),
+            concat!(
+                "TimeseriesRecord {\n",
+                "  labels   = {az: \"b c\", zone: \"a\"}\n",
+                "  latency  = 12.5 ms\n",
+                "  name     = \"http requests\"\n",
+                "  values   = [1, \"x\"]\n",
+                "  __type__ = \"TimeseriesRecord\"\n",
+                "}\n"
+            )
+        );
+
+        let formatter =
+            RecordFormatter::new(OutputFormat::Pretty, Some(vec![Symbol::new("name")]), true);
+        assert_eq!(
+            formatter.format(&record()).unwrap(),
+            "\x1b[1mTimeseriesRecord\x1b[0m {\n  \x1b[36mname\x1b[0m = \"http requests\"\n}\n"
+        );
+    }
+}
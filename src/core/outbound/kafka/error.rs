@@ -0,0 +1,17 @@
+use miette::Diagnostic;
+use thiserror::Error;
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum Error {
+    #[error(transparent)]
+    Kafka(#[from] rdkafka::error::KafkaError),
+    #[error(transparent)]
+    Json(#[from] crate::core::types::conv::json::ConversionError),
+    #[error("Delivery cancelled, the producer is gone")]
+    Cancelled,
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Recv(#[from] crate::utils::recv::Error),
+}
+
+pub type Result<T> = miette::Result<T, Error>;
@@ -0,0 +1,499 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use futures::{future::BoxFuture, stream::FuturesUnordered, FutureExt, StreamExt};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    actor_debug, actor_info, actor_warn,
+    config::outbound::kafka::{DeliveryFailurePolicy, KafkaFormat, KafkaOutboundConfig},
+    core::{
+        actor::Actor,
+        dead_letter::DeadLetter,
+        manager::{ChannelGraph, TaggedReceiver},
+        tag::{HasTag, TagId},
+        types::{Attribute, Record, Symbol, Value},
+    },
+    utils::{recv::recv_batch, stats::GLOBAL_STATS},
+};
+
+pub mod error;
+mod producer;
+
+pub use error::{Error, Result};
+use producer::{Message, Producer, RdKafkaProducer, SendError};
+
+use super::{shed::LoadShedder, Outbound};
+
+/// A message waiting to be acknowledged, with its record when it goes to the dead letter
+/// channel on failure.
+type Pending = BoxFuture<'static, (Option<Record>, Result<()>)>;
+
+/// Publishes every record as a message to a Kafka topic.
+///
+/// The producer queue is bounded by `queue_size`: once full, the outbound waits for the
+/// pending deliveries instead of receiving more records.
+pub struct KafkaOutbound {
+    tag: TagId,
+    producer: Arc<dyn Producer>,
+    format: KafkaFormat,
+    key_field: Option<Symbol>,
+    on_delivery_failure: DeliveryFailurePolicy,
+
+    inbounds: Vec<TaggedReceiver>,
+    shedder: Option<LoadShedder>,
+    dead_letter: DeadLetter,
+
+    pending: FuturesUnordered<Pending>,
+    // Since the last poll
+    failed: u64,
+
+    recv_timeout: Duration,
+    recv_buffer_size: usize,
+    max_batch_latency: Duration,
+}
+
+impl KafkaOutbound {
+    pub fn try_create_from(cfg: KafkaOutboundConfig, channels: &mut ChannelGraph) -> Result<Self> {
+        let tag: TagId = (&cfg.tag).into();
+        let producer = RdKafkaProducer::try_create_from(&cfg)?;
+        actor_info!(
+            tag,
+            "publishing to {} on {}",
+            cfg.topic,
+            cfg.brokers.join(",")
+        );
+
+        let inbounds = cfg
+            .inbounds
+            .iter()
+            .map(|inbound| channels.recv_from(inbound, &tag))
+            .collect::<Vec<_>>();
+        let dead_letter = channels.dead_letter(&tag);
+
+        Ok(Self::new(cfg, Arc::new(producer), inbounds, dead_letter))
+    }
+
+    fn new(
+        cfg: KafkaOutboundConfig,
+        producer: Arc<dyn Producer>,
+        inbounds: Vec<TaggedReceiver>,
+        dead_letter: DeadLetter,
+    ) -> Self {
+        let shedder = LoadShedder::from_config(&(&cfg.tag).into(), &cfg.shedding);
+        KafkaOutbound {
+            tag: cfg.tag.into(),
+            producer,
+            format: cfg.format,
+            key_field: cfg.key_field,
+            on_delivery_failure: cfg.on_delivery_failure,
+            inbounds,
+            shedder,
+            dead_letter,
+            pending: FuturesUnordered::new(),
+            failed: 0,
+            recv_timeout: cfg.recv_timeout.into(),
+            recv_buffer_size: cfg.recv_buffer_size,
+            max_batch_latency: cfg.max_batch_latency.into(),
+        }
+    }
+
+    fn key(&self, record: &Record) -> Option<String> {
+        let key = match self.key_field {
+            Some(ref field) => record.get(field),
+            None => record.get_attribute(&Attribute::Inbound),
+        };
+
+        match key? {
+            Value::Null => None,
+            Value::String(s) => Some(s.as_str().to_string()),
+            key => Some(key.to_string()),
+        }
+    }
+
+    fn encode(&self, record: &Record) -> Result<Vec<u8>> {
+        match self.format {
+            KafkaFormat::Json => Ok(record.to_json()?.to_string().into_bytes()),
+        }
+    }
+
+    async fn publish(&mut self, record: Record) {
+        let payload = match self.encode(&record) {
+            Ok(payload) => payload,
+            Err(e) => {
+                actor_warn!(self.tag, "record not published: {}", e);
+                self.dead_letter.send(record, &e);
+                return;
+            }
+        };
+        let mut message = Message {
+            key: self.key(&record),
+            payload,
+        };
+
+        loop {
+            match self.producer.send(message) {
+                Ok(delivery) => {
+                    record.mark_record_release(&self.tag);
+                    let keep = self.on_delivery_failure == DeliveryFailurePolicy::DeadLetter
+                        && self.dead_letter.is_enabled();
+                    let record = keep.then_some(record);
+                    self.pending
+                        .push(delivery.map(move |result| (record, result)).boxed());
+                    return;
+                }
+                Err(SendError::QueueFull(returned)) => {
+                    message = returned;
+                    self.wait_for_room().await;
+                }
+                Err(SendError::Failed(e)) => {
+                    self.delivered(Some(record), Err(e));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Wait for a pending delivery to complete, which frees a slot of the producer queue.
+    async fn wait_for_room(&mut self) {
+        match self.pending.next().await {
+            Some((record, result)) => self.delivered(record, result),
+            None => tokio::time::sleep(Duration::from_millis(10)).await,
+        }
+    }
+
+    /// Handle the deliveries completed so far, without waiting.
+    fn collect_deliveries(&mut self) {
+        while let Some(Some((record, result))) = self.pending.next().now_or_never() {
+            self.delivered(record, result);
+        }
+    }
+
+    fn delivered(&mut self, record: Option<Record>, result: Result<()>) {
+        let Err(e) = result else {
+            return;
+        };
+
+        self.failed += 1;
+        actor_debug!(self.tag, "delivery failed: {}", e);
+        if let Some(record) = record {
+            if self.on_delivery_failure == DeliveryFailurePolicy::DeadLetter {
+                self.dead_letter.send(record, &e);
+            }
+        }
+    }
+
+    fn report_failures(&mut self) {
+        if self.failed == 0 {
+            return;
+        }
+
+        actor_warn!(self.tag, "{} records failed to be delivered", self.failed);
+        GLOBAL_STATS.incr(&format!("{} failed deliveries", self.tag), self.failed);
+        self.failed = 0;
+    }
+
+    /// Wait for every pending delivery, bounded by `message_timeout`.
+    async fn flush(&mut self) {
+        if !self.pending.is_empty() {
+            actor_info!(
+                self.tag,
+                "waiting for {} pending deliveries",
+                self.pending.len()
+            );
+        }
+        while let Some((record, result)) = self.pending.next().await {
+            self.delivered(record, result);
+        }
+        self.report_failures();
+    }
+}
+
+impl HasTag for KafkaOutbound {
+    fn tag(&self) -> &TagId {
+        &self.tag
+    }
+}
+
+#[async_trait]
+impl Actor for KafkaOutbound {
+    type Error = super::Error;
+
+    async fn poll(&mut self, ctx: CancellationToken) -> super::Result<()> {
+        self.collect_deliveries();
+
+        let tag = self.tag.clone();
+        let interval = self.recv_timeout;
+        let buffer_size = self.recv_buffer_size;
+        let max_batch_latency = self.max_batch_latency;
+        let mut records = match recv_batch(
+            &tag,
+            self.inbounds(),
+            Some(interval),
+            buffer_size,
+            max_batch_latency,
+            ctx,
+        )
+        .await
+        {
+            Ok(records) => records,
+            Err(crate::utils::recv::Error::Timeout) => {
+                self.report_failures();
+                return Ok(());
+            }
+            // 退出前等待已发送的消息完成
+            Err(crate::utils::recv::Error::Canceled) => {
+                self.flush().await;
+                return Ok(());
+            }
+            Err(e) => return Err(Error::from(e).into()),
+        };
+        if let Some(ref mut shedder) = self.shedder {
+            shedder.retain(&mut records, crate::utils::recv::occupancy(&self.inbounds));
+        }
+
+        for record in records {
+            self.publish(record).await;
+        }
+        self.report_failures();
+
+        Ok(())
+    }
+}
+
+impl Outbound for KafkaOutbound {
+    fn inbounds(&mut self) -> &mut [TaggedReceiver] {
+        &mut self.inbounds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Mutex,
+        },
+    };
+
+    use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+    use tokio::sync::oneshot;
+
+    use super::{producer::Delivery, *};
+    use crate::core::{
+        manager::ActorChannel,
+        tag::{InboundTagId, PipeTagId, INTERNAL_TAG_SCOPE},
+    };
+
+    /// Accepts up to `capacity` unacknowledged messages, acknowledged by [`MockProducer::ack`]
+    #[derive(Default)]
+    struct MockProducer {
+        capacity: usize,
+        fail: AtomicBool,
+        sent: Mutex<Vec<Message>>,
+        inflight: Mutex<VecDeque<oneshot::Sender<Result<()>>>>,
+        max_inflight: AtomicUsize,
+    }
+
+    impl MockProducer {
+        fn new(capacity: usize) -> Arc<Self> {
+            Arc::new(Self {
+                capacity,
+                ..Default::default()
+            })
+        }
+
+        /// Complete the oldest delivery, returns false if there is none.
+        fn ack(&self) -> bool {
+            let Some(delivery) = self.inflight.lock().unwrap().pop_front() else {
+                return false;
+            };
+            let result = if self.fail.load(Ordering::Relaxed) {
+                Err(Error::Kafka(KafkaError::MessageProduction(
+                    RDKafkaErrorCode::MessageTimedOut,
+                )))
+            } else {
+                Ok(())
+            };
+            let _ = delivery.send(result);
+            true
+        }
+
+        fn sent(&self) -> Vec<Message> {
+            self.sent.lock().unwrap().clone()
+        }
+    }
+
+    impl Producer for MockProducer {
+        fn send(&self, message: Message) -> std::result::Result<Delivery, SendError> {
+            let mut inflight = self.inflight.lock().unwrap();
+            if inflight.len() >= self.capacity {
+                return Err(SendError::QueueFull(message));
+            }
+
+            let (tx, rx) = oneshot::channel();
+            inflight.push_back(tx);
+            self.max_inflight
+                .fetch_max(inflight.len(), Ordering::Relaxed);
+            self.sent.lock().unwrap().push(message);
+            Ok(async move { rx.await.unwrap_or(Err(Error::Cancelled)) }.boxed())
+        }
+    }
+
+    fn config(extra: &str) -> KafkaOutboundConfig {
+        toml::from_str(&format!(
+            "brokers = [\"localhost:9092\"]\ntopic = \"metrics\"\ninbounds = [\"inbound:a\"]\nrecv_timeout = \"10ms\"\n{}",
+            extra
+        ))
+        .unwrap()
+    }
+
+    fn record(host: &str, value: f64) -> Record {
+        let mut record = Record::empty();
+        record.set(Symbol::new("host"), Value::from(host));
+        record.set(Symbol::new("value"), Value::from(value));
+        record.set_attribute(Attribute::Inbound, Value::from("inbound:a"));
+        record
+    }
+
+    struct Setup {
+        outbound: KafkaOutbound,
+        input: ActorChannel,
+        rejected: TaggedReceiver,
+    }
+
+    fn setup(cfg: KafkaOutboundConfig, producer: Arc<MockProducer>) -> Setup {
+        let tag: TagId = (&cfg.tag).into();
+        let mut input = ActorChannel::new(InboundTagId::new("a").into(), 16);
+        let mut dead_letters = ActorChannel::new(TagId::new(INTERNAL_TAG_SCOPE, "dead_letter"), 16);
+        let rejected = dead_letters.receiver(&PipeTagId::new("dump").into());
+        let outbound = KafkaOutbound::new(
+            cfg,
+            producer,
+            vec![input.receiver(&tag)],
+            DeadLetter::new(tag, Some(dead_letters.sender())),
+        );
+
+        Setup {
+            outbound,
+            input,
+            rejected,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_publish() {
+        let producer = MockProducer::new(16);
+        let Setup {
+            mut outbound,
+            mut input,
+            ..
+        } = setup(config(""), producer.clone());
+
+        let mut sender = input.sender();
+        sender.send(record("web01", 1.5)).await.unwrap();
+        let mut without_inbound = Record::empty();
+        without_inbound.set(Symbol::new("host"), Value::from("web02"));
+        sender.send(without_inbound).await.unwrap();
+        outbound.poll(CancellationToken::new()).await.unwrap();
+
+        let sent = producer.sent();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].key.as_deref(), Some("inbound:a"));
+        assert_eq!(sent[1].key, None);
+        let json: serde_json::Value = serde_json::from_slice(&sent[0].payload).unwrap();
+        assert_eq!(json["host"], "web01");
+        assert_eq!(json["value"], 1.5);
+
+        // Keyed by a field
+        let producer = MockProducer::new(16);
+        let Setup {
+            mut outbound,
+            mut input,
+            ..
+        } = setup(config("key_field = \"host\""), producer.clone());
+        input.sender().send(record("web01", 1.5)).await.unwrap();
+        outbound.poll(CancellationToken::new()).await.unwrap();
+        assert_eq!(producer.sent()[0].key.as_deref(), Some("web01"));
+    }
+
+    #[tokio::test]
+    async fn test_backpressure() {
+        let producer = MockProducer::new(2);
+        let Setup {
+            mut outbound,
+            mut input,
+            ..
+        } = setup(config(""), producer.clone());
+
+        let mut sender = input.sender();
+        for i in 0..6 {
+            sender.send(record("web01", i as f64)).await.unwrap();
+        }
+
+        let acks = {
+            let producer = producer.clone();
+            tokio::spawn(async move {
+                let mut acked = 0;
+                while acked < 4 {
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                    if producer.ack() {
+                        acked += 1;
+                    }
+                }
+            })
+        };
+        outbound.poll(CancellationToken::new()).await.unwrap();
+        acks.await.unwrap();
+
+        // The poll waited for the deliveries instead of queueing more
+        assert_eq!(producer.sent().len(), 6);
+        assert_eq!(producer.max_inflight.load(Ordering::Relaxed), 2);
+        assert_eq!(outbound.pending.len(), 2);
+
+        // The rest are waited for on shutdown
+        let ctx = CancellationToken::new();
+        ctx.cancel();
+        let flush = tokio::spawn(async move {
+            outbound.poll(ctx).await.unwrap();
+            outbound
+        });
+        while !flush.is_finished() {
+            producer.ack();
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert!(flush.await.unwrap().pending.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_delivery_failure() {
+        for (policy, dead_letters) in [("drop", 0), ("dead_letter", 2)] {
+            let producer = MockProducer::new(16);
+            producer.fail.store(true, Ordering::Relaxed);
+            let Setup {
+                mut outbound,
+                mut input,
+                mut rejected,
+            } = setup(
+                config(&format!("on_delivery_failure = \"{}\"", policy)),
+                producer.clone(),
+            );
+
+            let mut sender = input.sender();
+            sender.send(record("web01", 1.0)).await.unwrap();
+            sender.send(record("web02", 2.0)).await.unwrap();
+            outbound.poll(CancellationToken::new()).await.unwrap();
+            while producer.ack() {}
+            outbound.collect_deliveries();
+            assert_eq!(outbound.failed, 2, "{}", policy);
+
+            let mut received = 0;
+            while let Ok(record) = rejected.try_recv() {
+                assert!(record.get_attribute(&Attribute::Error).is_some());
+                received += 1;
+            }
+            assert_eq!(received, dead_letters, "{}", policy);
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use futures::{future::BoxFuture, FutureExt};
+use rdkafka::{
+    error::{KafkaError, RDKafkaErrorCode},
+    producer::{FutureProducer, FutureRecord},
+    ClientConfig,
+};
+
+use crate::config::outbound::kafka::KafkaOutboundConfig;
+
+use super::{Error, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub key: Option<String>,
+    pub payload: Vec<u8>,
+}
+
+/// Resolves once the brokers acknowledged the message, or gave up on it.
+pub type Delivery = BoxFuture<'static, Result<()>>;
+
+#[derive(Debug)]
+pub enum SendError {
+    /// The queue of the producer is full, the message is handed back to be sent again
+    QueueFull(Message),
+    Failed(Error),
+}
+
+/// Queues the messages to be published to the topic, implemented by rdkafka outside of
+/// the tests.
+pub trait Producer: Send + Sync {
+    fn send(&self, message: Message) -> std::result::Result<Delivery, SendError>;
+}
+
+pub struct RdKafkaProducer {
+    topic: String,
+    producer: FutureProducer,
+}
+
+impl RdKafkaProducer {
+    pub fn try_create_from(cfg: &KafkaOutboundConfig) -> Result<Self> {
+        let mut config = ClientConfig::new();
+        config
+            .set("bootstrap.servers", cfg.brokers.join(","))
+            .set("compression.type", cfg.compression.as_str())
+            .set("linger.ms", cfg.linger.get().as_millis().to_string())
+            .set("batch.num.messages", cfg.batch_size.to_string())
+            .set("queue.buffering.max.messages", cfg.queue_size.to_string())
+            .set(
+                "message.timeout.ms",
+                cfg.message_timeout.get().as_millis().to_string(),
+            );
+        for (key, value) in &cfg.properties {
+            config.set(key, value);
+        }
+
+        Ok(Self {
+            topic: cfg.topic.clone(),
+            producer: config.create()?,
+        })
+    }
+}
+
+impl Producer for RdKafkaProducer {
+    fn send(&self, message: Message) -> std::result::Result<Delivery, SendError> {
+        let mut record = FutureRecord::to(&self.topic).payload(&message.payload);
+        if let Some(ref key) = message.key {
+            record = record.key(key);
+        }
+
+        match self.producer.send_result(record).map_err(|(e, _)| e) {
+            Ok(delivery) => Ok(async move {
+                match delivery.await {
+                    Ok(Ok(_)) => Ok(()),
+                    Ok(Err((e, _))) => Err(Error::from(e)),
+                    Err(_) => Err(Error::Cancelled),
+                }
+            }
+            .boxed()),
+            Err(KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull)) => {
+                Err(SendError::QueueFull(message))
+            }
+            Err(e) => Err(SendError::Failed(e.into())),
+        }
+    }
+}
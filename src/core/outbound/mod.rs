@@ -1,7 +1,17 @@
+mod age;
+mod auth;
 mod base;
+pub mod buffer;
+pub mod csv;
 mod error;
+pub mod file;
+mod format;
+pub mod kafka;
+mod order;
+pub mod otlp;
 pub mod parquet;
 pub mod prometheus;
+mod shed;
 pub mod stdio;
 
 pub use base::Outbound;
@@ -25,5 +35,15 @@ pub fn try_create_from(
         OutboundConfig::Parquet(cfg) => Ok(Box::new(parquet::ParquetOutbound::try_create_from(
             cfg, channels,
         )?)),
+        OutboundConfig::Csv(cfg) => Ok(Box::new(csv::CsvOutbound::try_create_from(cfg, channels)?)),
+        OutboundConfig::File(cfg) => Ok(Box::new(file::FileOutbound::try_create_from(
+            cfg, channels,
+        )?)),
+        OutboundConfig::Otlp(cfg) => Ok(Box::new(otlp::OtlpOutbound::try_create_from(
+            cfg, channels,
+        )?)),
+        OutboundConfig::Kafka(cfg) => Ok(Box::new(kafka::KafkaOutbound::try_create_from(
+            cfg, channels,
+        )?)),
     }
 }
@@ -0,0 +1,137 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use chrono::{DateTime, Utc};
+
+use crate::core::types::{Record, Symbol, Value};
+
+/// One component of the canonical sort key of a record.
+///
+/// Values of different types are ordered by type, records lacking the key come first.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum KeyPart {
+    Missing,
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(i64),
+    String(String),
+    DateTime(DateTime<Utc>),
+    Bytes(Vec<u8>),
+    Other(u64),
+}
+
+impl From<&Value> for KeyPart {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Null => KeyPart::Null,
+            Value::Bool(b) => KeyPart::Bool(*b),
+            Value::Int(n) => KeyPart::Int(n.value),
+            Value::Float(n) => KeyPart::Float(total_order_bits(n.value)),
+            Value::String(s) => KeyPart::String(s.to_string()),
+            Value::DateTime(dt) => KeyPart::DateTime(*dt),
+            Value::Bytes(bytes) => KeyPart::Bytes(bytes.clone()),
+            Value::Map(_) | Value::Array(_) => KeyPart::Other(value.content_hash()),
+        }
+    }
+}
+
+// Maps the float onto an integer with the same order, see `f64::total_cmp`.
+fn total_order_bits(value: f64) -> i64 {
+    let bits = value.to_bits() as i64;
+    bits ^ (((bits >> 63) as u64) >> 1) as i64
+}
+
+fn sort_key(record: &Record, keys: &[Symbol]) -> (Vec<KeyPart>, u64) {
+    let parts = keys
+        .iter()
+        .map(|key| {
+            record
+                .get(key)
+                .map(KeyPart::from)
+                .unwrap_or(KeyPart::Missing)
+        })
+        .collect();
+
+    let mut hasher = DefaultHasher::new();
+    for (key, value) in record.iter().filter(|(k, _)| !keys.contains(k)) {
+        key.as_str().hash(&mut hasher);
+        value.content_hash().hash(&mut hasher);
+    }
+
+    (parts, hasher.finish())
+}
+
+/// Sort the records by the given keys, then by a hash of their remaining fields, so that
+/// the result does not depend on the order the records came in.
+pub fn sort_records(records: &mut [Record], keys: &[Symbol]) {
+    records.sort_by_cached_key(|record| sort_key(record, keys));
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+    use super::*;
+
+    fn keys() -> Vec<Symbol> {
+        vec![Symbol::new("timestamp"), Symbol::new("name")]
+    }
+
+    fn record(ts: i64, name: &str, value: f64) -> Record {
+        let mut record = Record::empty();
+        record.set(
+            Symbol::new("timestamp"),
+            Value::DateTime(DateTime::from_timestamp(ts, 0).unwrap()),
+        );
+        record.set(Symbol::new("name"), Value::from(name));
+        record.set(Symbol::new("value"), Value::from(value));
+        record
+    }
+
+    fn fingerprint(records: &[Record]) -> Vec<String> {
+        records.iter().map(|r| r.to_string()).collect()
+    }
+
+    #[test]
+    fn test_sort_by_keys() {
+        let mut records = vec![
+            record(2, "a", 1.0),
+            record(1, "b", 1.0),
+            record(1, "a", 1.0),
+        ];
+        sort_records(&mut records, &keys());
+        assert_eq!(
+            fingerprint(&records),
+            fingerprint(&[
+                record(1, "a", 1.0),
+                record(1, "b", 1.0),
+                record(2, "a", 1.0)
+            ])
+        );
+    }
+
+    #[test]
+    fn test_order_independent_of_arrival() {
+        let mut expected = (0..50)
+            .map(|i| record(i % 5, ["x", "y"][i as usize % 2], i as f64))
+            .collect::<Vec<_>>();
+        sort_records(&mut expected, &keys());
+
+        for seed in 0..10 {
+            let mut records = expected.clone();
+            records.shuffle(&mut StdRng::seed_from_u64(seed));
+            sort_records(&mut records, &keys());
+            assert_eq!(fingerprint(&records), fingerprint(&expected));
+        }
+    }
+
+    #[test]
+    fn test_missing_keys_first() {
+        let mut bare = Record::empty();
+        bare.set(Symbol::new("value"), Value::from(1.0));
+
+        let mut records = vec![record(0, "a", 1.0), bare.clone()];
+        sort_records(&mut records, &keys());
+        assert_eq!(records[0].to_string(), bare.to_string());
+    }
+}
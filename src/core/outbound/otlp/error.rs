@@ -0,0 +1,49 @@
+use std::time::Duration;
+
+use miette::Diagnostic;
+use thiserror::Error;
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum Error {
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Conv(#[from] crate::core::types::conv::otlp::Error),
+    #[error(transparent)]
+    Reqwest(#[from] reqwest::Error),
+    /// Status, body and `Retry-After` of the response
+    #[error("OTLP export failed ({0}): {1}")]
+    Status(reqwest::StatusCode, String, Option<Duration>),
+    /// Status code and message of a gRPC call
+    #[error("OTLP export failed (gRPC status {0}): {1}")]
+    Grpc(u32, String),
+    #[error("Invalid header {0}")]
+    Header(String),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Recv(#[from] crate::utils::recv::Error),
+}
+
+impl Error {
+    /// The collector answers 429, 502, 503 and 504 to the requests worth retrying, see
+    /// <https://opentelemetry.io/docs/specs/otlp/#failures-1>, and the gRPC statuses
+    /// `CANCELLED`, `DEADLINE_EXCEEDED`, `ABORTED`, `OUT_OF_RANGE`, `UNAVAILABLE` and
+    /// `DATA_LOSS` to the calls. Connection failures are retried as well.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Status(status, _, _) => matches!(status.as_u16(), 429 | 502 | 503 | 504),
+            Error::Grpc(code, _) => matches!(code, 1 | 4 | 10 | 11 | 14 | 15),
+            Error::Reqwest(e) => e.is_timeout() || e.is_connect(),
+            _ => false,
+        }
+    }
+
+    /// How long the collector asked to wait before retrying.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::Status(_, _, retry_after) => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+pub type Result<T> = miette::Result<T, Error>;
@@ -0,0 +1,326 @@
+use reqwest::header::{self, HeaderMap, HeaderName, HeaderValue};
+
+use crate::{
+    config::outbound::otlp::{OtlpCompression, OtlpOutboundConfig, OtlpProtocol},
+    core::{
+        outbound::{auth::Credentials, prometheus::error::parse_retry_after},
+        tag::TagId,
+        types::conv::otlp::{ExportMetricsServiceRequest, SCOPE_NAME},
+    },
+};
+
+use super::{Error, Result};
+
+/// Path of the `Export` call of the metrics service, see `metrics_service.proto`
+const GRPC_EXPORT_PATH: &str = "opentelemetry.proto.collector.metrics.v1.MetricsService/Export";
+
+/// Where and how the metrics are sent, shared by the export tasks.
+#[derive(Debug, Clone)]
+pub struct Exporter {
+    client: reqwest::Client,
+    url: String,
+    protocol: OtlpProtocol,
+    gzip: bool,
+    auth: Credentials,
+    headers: HeaderMap,
+}
+
+impl Exporter {
+    pub fn try_create_from(cfg: &OtlpOutboundConfig) -> Result<Self> {
+        let endpoint = cfg.endpoint.trim_end_matches('/');
+        let mut client = reqwest::Client::builder().timeout(cfg.timeout.into());
+        let url = match cfg.protocol {
+            OtlpProtocol::HttpProto => format!("{}/v1/metrics", endpoint),
+            OtlpProtocol::Grpc => {
+                // HTTP/2 is negotiated over TLS, a plain text collector is assumed to speak it
+                if !endpoint.starts_with("https://") {
+                    client = client.http2_prior_knowledge();
+                }
+                format!("{}/{}", endpoint, GRPC_EXPORT_PATH)
+            }
+        };
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in &cfg.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| Error::Header(format!("{}: {}", name, e)))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|e| Error::Header(format!("{}: {}", name, e)))?;
+            headers.insert(name, value);
+        }
+
+        Ok(Exporter {
+            client: client.build()?,
+            url,
+            protocol: cfg.protocol,
+            gzip: cfg.compression == OtlpCompression::Gzip,
+            auth: Credentials::new(&TagId::from(&cfg.tag), &cfg.auth),
+            headers,
+        })
+    }
+
+    /// The request exporting `metrics`, without the credentials. Its body is a plain buffer, so
+    /// it can be cloned to be retried.
+    pub fn request(
+        &self,
+        metrics: &ExportMetricsServiceRequest,
+    ) -> Result<reqwest::RequestBuilder> {
+        let builder = self
+            .client
+            .post(&self.url)
+            .headers(self.headers.clone())
+            .header(header::USER_AGENT, SCOPE_NAME);
+
+        let builder = match self.protocol {
+            OtlpProtocol::HttpProto => {
+                let builder = builder.header(header::CONTENT_TYPE, "application/x-protobuf");
+                match self.gzip {
+                    true => builder
+                        .header(header::CONTENT_ENCODING, "gzip")
+                        .body(metrics.encode_gzip()?),
+                    false => builder.body(metrics.encode_proto3()),
+                }
+            }
+            OtlpProtocol::Grpc => {
+                let builder = builder
+                    .header(header::CONTENT_TYPE, "application/grpc")
+                    .header(header::TE, "trailers");
+                let builder = match self.gzip {
+                    true => builder.header("grpc-encoding", "gzip"),
+                    false => builder,
+                };
+                builder.body(metrics.encode_grpc(self.gzip)?)
+            }
+        };
+
+        Ok(builder)
+    }
+
+    /// Send a request built by [`Exporter::request`], once more if the collector answers 401
+    /// and the credential files changed since they were last read.
+    ///
+    /// The status of a gRPC call is only seen when the collector answers with no message at
+    /// all, which is how it rejects a call outright: it is then in the headers instead of the
+    /// trailers.
+    pub async fn send(&self, request: reqwest::RequestBuilder) -> Result<()> {
+        let retried = request.try_clone();
+        let mut response = self.auth.apply(request).send().await?;
+        if response.status() == reqwest::StatusCode::UNAUTHORIZED && self.auth.reload() {
+            if let Some(request) = retried {
+                response = self.auth.apply(request).send().await?;
+            }
+        }
+        let status = response.status();
+        if !status.is_success() {
+            let retry_after = response
+                .headers()
+                .get(header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_retry_after);
+            return Err(Error::Status(
+                status,
+                response.text().await.unwrap_or_default(),
+                retry_after,
+            ));
+        }
+
+        if self.protocol == OtlpProtocol::Grpc {
+            let header = |name| response.headers().get(name).and_then(|v| v.to_str().ok());
+            let code = header("grpc-status").and_then(|code| code.parse::<u32>().ok());
+            if let Some(code) = code.filter(|&code| code != 0) {
+                let message = header("grpc-message").unwrap_or_default().to_string();
+                return Err(Error::Grpc(code, message));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Read,
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{body::Bytes, http::StatusCode, routing::post, Router};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::{
+        config::Verify,
+        core::{
+            pipe::{LABELS_FIELD, METRIC_TYPE_FIELD, NAME_FIELD, TIMESTAMP_FIELD, VALUE_FIELD},
+            types::{conv::otlp::transform_metrics, Record, Value},
+        },
+    };
+
+    // The headers and the body of each request received
+    type Received = Arc<Mutex<Vec<(HeaderMap, Bytes)>>>;
+
+    /// A collector answering every request with `status` and `headers`, over HTTP/1 or
+    /// HTTP/2 without TLS.
+    async fn collector(
+        path: &'static str,
+        status: StatusCode,
+        headers: &'static [(&'static str, &'static str)],
+    ) -> (SocketAddr, Received) {
+        let received = Received::default();
+        let state = received.clone();
+        let router = Router::new().route(
+            path,
+            post(move |request_headers: HeaderMap, body: Bytes| async move {
+                state.lock().unwrap().push((request_headers, body));
+                let mut response_headers = HeaderMap::new();
+                for (name, value) in headers {
+                    response_headers.insert(*name, HeaderValue::from_static(value));
+                }
+                (status, response_headers)
+            }),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        (addr, received)
+    }
+
+    fn new_exporter(addr: SocketAddr, extra: &str) -> Exporter {
+        let mut cfg: OtlpOutboundConfig = toml::from_str(&format!(
+            "endpoint = \"http://{}\"\ninbounds = [\"pipe:timeseries\"]\nheaders = {{ \"x-api-key\" = \"secret\" }}\n{}",
+            addr, extra
+        ))
+        .unwrap();
+        cfg.verify().unwrap();
+        Exporter::try_create_from(&cfg).unwrap()
+    }
+
+    fn metrics() -> ExportMetricsServiceRequest {
+        let mut record = Record::new_root();
+        record.set(NAME_FIELD.clone(), Value::from("up"));
+        record.set(METRIC_TYPE_FIELD.clone(), Value::from("gauge"));
+        record.set(VALUE_FIELD.clone(), Value::from(1.0));
+        record.set(TIMESTAMP_FIELD.clone(), Value::from(chrono::Utc::now()));
+        record.set(
+            LABELS_FIELD.clone(),
+            Value::from(Vec::<(Value, Value)>::new()),
+        );
+        transform_metrics(vec![record]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_http_proto() {
+        let (addr, received) = collector("/v1/metrics", StatusCode::OK, &[]).await;
+        let exporter = new_exporter(addr, "");
+        let metrics = metrics();
+        exporter
+            .send(exporter.request(&metrics).unwrap())
+            .await
+            .unwrap();
+
+        let (headers, body) = received.lock().unwrap().pop().unwrap();
+        assert_eq!(headers["x-api-key"], "secret");
+        assert_eq!(headers[header::CONTENT_TYPE], "application/x-protobuf");
+        assert_eq!(headers[header::CONTENT_ENCODING], "gzip");
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(&body[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, metrics.encode_proto3());
+    }
+
+    #[tokio::test]
+    async fn test_grpc() {
+        let path = "/opentelemetry.proto.collector.metrics.v1.MetricsService/Export";
+        let (addr, received) = collector(path, StatusCode::OK, &[]).await;
+        let exporter = new_exporter(addr, "protocol = \"grpc\"\ncompression = \"none\"");
+        let metrics = metrics();
+        exporter
+            .send(exporter.request(&metrics).unwrap())
+            .await
+            .unwrap();
+
+        let (headers, body) = received.lock().unwrap().pop().unwrap();
+        assert_eq!(headers["x-api-key"], "secret");
+        assert_eq!(headers[header::CONTENT_TYPE], "application/grpc");
+        assert_eq!(body, metrics.encode_grpc(false).unwrap());
+
+        // Rejected outright, with the status in the headers
+        let (addr, _) = collector(
+            path,
+            StatusCode::OK,
+            &[("grpc-status", "14"), ("grpc-message", "overloaded")],
+        )
+        .await;
+        let exporter = new_exporter(addr, "protocol = \"grpc\"");
+        let err = exporter
+            .send(exporter.request(&metrics).unwrap())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Grpc(14, ref message) if message == "overloaded"));
+        assert!(err.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn test_rotated_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let token_file = dir.path().join("token");
+        std::fs::write(&token_file, "old\n").unwrap();
+
+        // Only the rotated token is accepted
+        let tokens = Arc::new(Mutex::new(Vec::new()));
+        let state = tokens.clone();
+        let router = Router::new().route(
+            "/v1/metrics",
+            post(move |headers: HeaderMap| async move {
+                let token = headers[header::AUTHORIZATION].to_str().unwrap().to_string();
+                let status = match token.as_str() {
+                    "Bearer new" => StatusCode::OK,
+                    _ => StatusCode::UNAUTHORIZED,
+                };
+                state.lock().unwrap().push(token);
+                status
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let exporter = new_exporter(
+            addr,
+            &format!(
+                "auth = {{ type = \"bearer_file\", token_file = \"{}\" }}",
+                token_file.display()
+            ),
+        );
+        let metrics = metrics();
+
+        // Unchanged, the 401 is given back without sending the request again
+        let err = exporter
+            .send(exporter.request(&metrics).unwrap())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Status(StatusCode::UNAUTHORIZED, _, _)));
+        assert_eq!(*tokens.lock().unwrap(), vec!["Bearer old"]);
+
+        std::fs::write(&token_file, "new\n").unwrap();
+        exporter
+            .send(exporter.request(&metrics).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(
+            *tokens.lock().unwrap(),
+            vec!["Bearer old", "Bearer old", "Bearer new"]
+        );
+
+        // Unreadable, the token read last is kept
+        std::fs::remove_file(&token_file).unwrap();
+        assert!(!exporter.auth.reload());
+        exporter
+            .send(exporter.request(&metrics).unwrap())
+            .await
+            .unwrap();
+    }
+}
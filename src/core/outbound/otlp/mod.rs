@@ -0,0 +1,227 @@
+use std::ops::Deref;
+
+use crate::{
+    actor_debug, actor_error, actor_info, actor_warn,
+    config::{global::use_time_tracing, outbound::otlp::OtlpOutboundConfig},
+    core::{
+        actor::Actor,
+        dead_letter::DeadLetter,
+        manager::{ChannelGraph, TaggedReceiver},
+        metrics,
+        pipe::RECORD_TYPE_TIMESERIES_VALUE,
+        tag::{HasTag, TagId},
+        types::conv::otlp::transform_metrics,
+    },
+    utils::{
+        recv::recv_batch,
+        retry::{retry, RetryOutcome, RetryPolicy},
+    },
+};
+
+pub mod error;
+mod export;
+
+use async_trait::async_trait;
+pub use error::{Error, Result};
+use export::Exporter;
+use tokio_util::sync::CancellationToken;
+
+use super::{shed::LoadShedder, Outbound};
+
+/// Exports the timeseries records to an OpenTelemetry collector over OTLP/HTTP or OTLP/gRPC,
+/// one request per received batch.
+pub struct OtlpOutbound {
+    tag: TagId,
+
+    recv_timeout: std::time::Duration,
+
+    exporter: Exporter,
+    retry: RetryPolicy<Error>,
+
+    inbounds: Vec<TaggedReceiver>,
+    shedder: Option<LoadShedder>,
+    dead_letter: DeadLetter,
+
+    recv_buffer_size: usize,
+    max_batch_latency: std::time::Duration,
+}
+
+impl OtlpOutbound {
+    pub fn try_create_from(cfg: OtlpOutboundConfig, channels: &mut ChannelGraph) -> Result<Self> {
+        let exporter = Exporter::try_create_from(&cfg)?;
+
+        let tag = cfg.tag.into();
+        let retry = RetryPolicy::from_config(&cfg.retry)
+            .with_classifier(Error::is_retryable)
+            .with_delay_hint(Error::retry_after);
+
+        let inbounds = cfg
+            .inbounds
+            .iter()
+            .map(|inbound| channels.recv_from(inbound, &tag))
+            .collect::<Vec<_>>();
+        let dead_letter = channels.dead_letter(&tag);
+
+        let shedder = LoadShedder::from_config(&tag, &cfg.shedding);
+        Ok(OtlpOutbound {
+            tag,
+            recv_timeout: cfg.recv_timeout.into(),
+            exporter,
+            retry,
+            inbounds,
+            shedder,
+            dead_letter,
+            recv_buffer_size: cfg.recv_buffer_size,
+            max_batch_latency: cfg.max_batch_latency.into(),
+        })
+    }
+}
+
+impl HasTag for OtlpOutbound {
+    fn tag(&self) -> &TagId {
+        &self.tag
+    }
+}
+
+#[async_trait]
+impl Actor for OtlpOutbound {
+    type Error = super::Error;
+
+    async fn poll(&mut self, ctx: CancellationToken) -> std::result::Result<(), Self::Error> {
+        let tag = self.tag.clone();
+        let interval = self.recv_timeout;
+        let buffer_size = self.recv_buffer_size;
+        let max_batch_latency = self.max_batch_latency;
+
+        let mut records = match recv_batch(
+            &tag,
+            self.inbounds(),
+            Some(interval),
+            buffer_size,
+            max_batch_latency,
+            ctx.clone(),
+        )
+        .await
+        {
+            Ok(records) => records,
+            Err(crate::utils::recv::Error::Timeout) => return Ok(()),
+            Err(e) => return Err(Error::from(e).into()),
+        };
+        if let Some(ref mut shedder) = self.shedder {
+            shedder.retain(&mut records, crate::utils::recv::occupancy(&self.inbounds));
+        }
+
+        let before_len = records.len();
+        let records = records
+            .into_iter()
+            .filter(|record| record.get_type() == Some(RECORD_TYPE_TIMESERIES_VALUE.deref()))
+            .collect::<Vec<_>>();
+        if records.len() != before_len {
+            actor_warn!(
+                tag,
+                "filtered {} records with wrong types, {} left",
+                before_len - records.len(),
+                records.len()
+            );
+        }
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        for record in &records {
+            record.mark_record_release(&tag);
+        }
+
+        let exporter = self.exporter.clone();
+        let policy = self.retry.clone();
+        let metrics = metrics::actor_metrics(&self.tag);
+        // Kept to be sent to the dead letter channel if the export is given up
+        let mut dead_letter = self.dead_letter.clone();
+        let rejected = dead_letter.is_enabled().then(|| records.clone());
+        let transform_start_timestamp = std::time::Instant::now();
+
+        tokio::task::spawn(async move {
+            let request = transform_metrics(records)
+                .map_err(Error::from)
+                .and_then(|request| {
+                    let num_data_points = request.num_data_points();
+                    Ok((exporter.request(&request)?, num_data_points))
+                });
+            let (request, num_data_points) = match request {
+                Ok(request) => request,
+                Err(e) => {
+                    if let Some(records) = rejected {
+                        dead_letter.send_all(records, &e);
+                    }
+                    return Err(super::Error::from(e));
+                }
+            };
+
+            let outcome = retry(&policy, ctx, |_| {
+                // The body is a plain buffer, so the request can always be cloned.
+                let request = request
+                    .try_clone()
+                    .expect("OTLP export request is not cloneable");
+                let metrics = metrics.clone();
+                let exporter = &exporter;
+
+                async move {
+                    let start = std::time::Instant::now();
+                    let result = exporter.send(request).await;
+                    metrics.observe_request(start.elapsed());
+                    result
+                }
+            })
+            .await;
+
+            match outcome {
+                RetryOutcome::Succeeded {
+                    value: (),
+                    attempts,
+                } => {
+                    if attempts > 1 {
+                        actor_info!(tag, "export succeeded after {} attempts", attempts);
+                    }
+                }
+                RetryOutcome::GaveUp { error, attempts } => {
+                    actor_error!(
+                        tag,
+                        "export of {} data points failed after {} attempts: {}",
+                        num_data_points,
+                        attempts,
+                        error
+                    );
+                    if let Some(records) = rejected {
+                        dead_letter.send_all(records, &error);
+                    }
+                }
+                RetryOutcome::Cancelled {
+                    attempts,
+                    last_error,
+                } => {
+                    actor_warn!(
+                        tag,
+                        "export cancelled after {} attempts, last error: {:?}",
+                        attempts,
+                        last_error
+                    );
+                }
+            }
+
+            if use_time_tracing() {
+                let elapsed = transform_start_timestamp.elapsed();
+                actor_debug!(tag, "OTLP export took {:?}", elapsed);
+            }
+
+            Ok::<(), super::Error>(())
+        });
+
+        Ok(())
+    }
+}
+
+impl Outbound for OtlpOutbound {
+    fn inbounds(&mut self) -> &mut [TaggedReceiver] {
+        &mut self.inbounds
+    }
+}
@@ -0,0 +1,958 @@
+mod schema;
+
+use std::{collections::VecDeque, path::Path, time::Instant};
+
+use arrow::datatypes::SchemaRef;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use tokio::{sync::mpsc, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+
+use crate::config::{
+    global,
+    outbound::{parquet::ParquetOutboundConfig, StableOrderConfig},
+};
+use crate::core::types::conv::parquet::ParquetWriter;
+use crate::core::{
+    actor::Actor,
+    manager::{ChannelGraph, TaggedReceiver},
+    tag::{HasTag, TagId},
+    types::Record,
+};
+use crate::utils::{budget::PollBudget, recv::recv_batch, stats::GLOBAL_STATS};
+use crate::{actor_debug, actor_error};
+
+use super::{age::AgeFilter, base::Outbound, order::sort_records, shed::LoadShedder};
+use schema::SchemaTracker;
+
+// Number of batches queued for the writer before the outbound waits for it.
+const WRITER_QUEUE_SIZE: usize = 4;
+
+// A flush is handed to the writer in chunks of this many records, the poll budget is checked
+// between chunks.
+const WRITE_CHUNK_SIZE: usize = 8192;
+
+/// Owns the parquet writer on a blocking thread, so that slow disks never stall the async
+/// workers. Batches are written in the order they were sent; dropping the handle drains the
+/// queue and closes the file.
+struct BlockingWriter {
+    sender: mpsc::Sender<Vec<Record>>,
+    handle: JoinHandle<SchemaTracker>,
+}
+
+impl BlockingWriter {
+    /// `open` is called with the schema and the number of the part: a schema evolving while
+    /// the file is written closes it and continues in a new part.
+    fn spawn<W, F>(tag: TagId, mut schema: SchemaTracker, mut open: F) -> Self
+    where
+        W: std::io::Write + Send + 'static,
+        F: FnMut(SchemaRef, usize) -> super::Result<ParquetWriter<W>> + Send + 'static,
+    {
+        let (sender, mut receiver) = mpsc::channel::<Vec<Record>>(WRITER_QUEUE_SIZE);
+
+        let handle = tokio::task::spawn_blocking(move || {
+            let mut writer: Option<ParquetWriter<W>> = None;
+            let mut part = 0;
+
+            while let Some(mut records) = receiver.blocking_recv() {
+                let changed = match schema.update(&records) {
+                    Ok(changed) => changed,
+                    Err(e) => {
+                        actor_error!(tag, "failed to infer parquet schema: {}", e);
+                        continue;
+                    }
+                };
+
+                if changed {
+                    if let Some(w) = writer.take() {
+                        actor_debug!(tag, "parquet schema evolved, closing {}", w.path());
+                        if let Err(e) = w.close() {
+                            error!("Error closing parquet writer: {}", e);
+                        }
+                        part += 1;
+                    }
+                }
+
+                if writer.is_none() {
+                    let Some(s) = schema.schema() else {
+                        continue;
+                    };
+                    match open(s.clone(), part) {
+                        Ok(w) => writer = Some(w),
+                        Err(e) => {
+                            actor_error!(tag, "failed to open parquet writer: {}", e);
+                            return schema;
+                        }
+                    }
+                }
+
+                let Some(w) = writer.as_mut() else {
+                    continue;
+                };
+
+                let mismatches = schema.coerce(&mut records);
+                if mismatches > 0 {
+                    actor_debug!(
+                        tag,
+                        "{} values do not fit the parquet schema, written as nulls",
+                        mismatches
+                    );
+                    GLOBAL_STATS.incr(&format!("{} mismatched values", tag), mismatches);
+                }
+
+                match w.write_records(&records) {
+                    Ok(()) => info!("Wrote {} records to {}", records.len(), w.path()),
+                    Err(e) => actor_error!(tag, "failed to write records: {}", e),
+                }
+            }
+
+            if let Some(w) = writer {
+                if let Err(e) = w.close() {
+                    error!("Error closing parquet writer: {}", e);
+                }
+            }
+            schema
+        });
+
+        Self { sender, handle }
+    }
+
+    async fn write(&self, records: Vec<Record>) -> super::Result<()> {
+        self.sender.send(records).await.map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::BrokenPipe, "parquet writer has stopped").into()
+        })
+    }
+
+    /// Drain the queued batches and close the file, handing back the schema for the next one.
+    async fn close(self) -> super::Result<SchemaTracker> {
+        drop(self.sender);
+        Ok(self.handle.await.map_err(std::io::Error::from)?)
+    }
+}
+
+/// Expand the strftime fields and `{ts}` of a rotated path.
+fn expand_path(template: &str, now: DateTime<Utc>) -> String {
+    let path = template.replace("{ts}", &now.timestamp().to_string());
+    now.format(&path).to_string()
+}
+
+/// The path of a part after the first, numbered before the extension, e.g.
+/// `metrics.1.parquet`.
+fn part_path(path: &str, part: usize) -> String {
+    if part == 0 {
+        return path.to_string();
+    }
+
+    let path = Path::new(path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, part, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, part),
+    };
+    path.with_file_name(name).to_string_lossy().to_string()
+}
+
+// Suffixes tried when the path of a new file is taken, before giving up.
+const MAX_PATH_SUFFIX: usize = 1000;
+
+/// Create the file at `path`, or at `metrics-1.parquet`, `metrics-2.parquet`... if it is
+/// taken: an existing file is never truncated. Returns the file and the path used.
+fn create_new_file(path: &str) -> std::io::Result<(std::fs::File, String)> {
+    for suffix in 0..=MAX_PATH_SUFFIX {
+        let path = suffixed_path(path, suffix);
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(file) => return Ok((file, path)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(std::io::Error::new(
+        std::io::ErrorKind::AlreadyExists,
+        format!(
+            "{} and its {} suffixed paths all exist",
+            path, MAX_PATH_SUFFIX
+        ),
+    ))
+}
+
+/// `metrics.parquet` with a `-suffix` before the extension, e.g. `metrics-1.parquet`.
+fn suffixed_path(path: &str, suffix: usize) -> String {
+    if suffix == 0 {
+        return path.to_string();
+    }
+
+    let path = Path::new(path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, suffix, ext.to_string_lossy()),
+        None => format!("{}-{}", stem, suffix),
+    };
+    path.with_file_name(name).to_string_lossy().to_string()
+}
+
+fn writer_properties(compression: Compression) -> WriterProperties {
+    WriterProperties::builder()
+        .set_compression(compression)
+        .build()
+}
+
+pub struct ParquetOutbound {
+    tag: TagId,
+    path: String,
+    compression: Compression,
+    // Start a new file this often, expanding the path each time
+    rotation_interval: Option<std::time::Duration>,
+    batch_size: usize,
+    max_batch_latency: std::time::Duration,
+    inbounds: Vec<TaggedReceiver>,
+    shedder: Option<LoadShedder>,
+    records_buffer: Vec<Record>,
+    // Flushed chunks not yet handed to the writer, left over by a poll which ran out of budget
+    pending: VecDeque<Vec<Record>>,
+    max_poll_duration: std::time::Duration,
+    writer: Option<BlockingWriter>,
+    writer_started: Instant,
+    order: StableOrderConfig,
+    age: Option<AgeFilter>,
+}
+
+impl HasTag for ParquetOutbound {
+    fn tag(&self) -> &TagId {
+        &self.tag
+    }
+}
+
+impl ParquetOutbound {
+    pub fn try_create_from(
+        cfg: ParquetOutboundConfig,
+        channels: &mut ChannelGraph,
+    ) -> super::Result<Self> {
+        let tag: TagId = (&cfg.tag).into();
+        let inbounds = cfg
+            .inbounds
+            .iter()
+            .map(|inbound| channels.recv_from(inbound, &tag))
+            .collect::<Vec<_>>();
+
+        Ok(Self::new(cfg, inbounds))
+    }
+
+    fn new(cfg: ParquetOutboundConfig, inbounds: Vec<TaggedReceiver>) -> Self {
+        let tag: TagId = cfg.tag.into();
+        let shedder = LoadShedder::from_config(&tag, &cfg.shedding);
+        let mut outbound = ParquetOutbound {
+            age: AgeFilter::from_config(&tag, &cfg.age),
+            tag,
+            // Convert path to String for easier manipulation
+            path: cfg.path.to_string_lossy().to_string(),
+            // Use direct conversion from enum
+            compression: cfg.compression.into(),
+            rotation_interval: cfg.rotation_interval.map(Into::into),
+            batch_size: cfg.batch_size,
+            max_batch_latency: cfg.max_batch_latency.into(),
+            inbounds,
+            shedder,
+            records_buffer: Vec::with_capacity(cfg.batch_size),
+            pending: VecDeque::new(),
+            max_poll_duration: global::max_poll_duration().outbound.into(),
+            writer: None,
+            writer_started: Instant::now(),
+            order: cfg.order,
+        };
+        let schema = SchemaTracker::new(cfg.schema_mode, &cfg.fields);
+        outbound.writer = Some(outbound.spawn_writer(schema));
+
+        outbound
+    }
+
+    /// The file is only created once the first batch arrives, so that an interval without
+    /// records leaves no empty file behind.
+    fn spawn_writer(&mut self, schema: SchemaTracker) -> BlockingWriter {
+        let template = self.path.clone();
+        let rotated = self.rotation_interval.is_some();
+        let compression = self.compression;
+        self.writer_started = Instant::now();
+        // Expanded once for all the parts of the file
+        let mut expanded = None;
+
+        BlockingWriter::spawn(self.tag.clone(), schema, move |schema, part| {
+            let path = expanded.get_or_insert_with(|| match rotated {
+                true => expand_path(&template, Utc::now()),
+                false => template.clone(),
+            });
+            let path = part_path(path, part);
+            if let Some(parent) = Path::new(&path).parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+
+            let props = writer_properties(compression);
+            let (file, path) = create_new_file(&path)?;
+            if part == 0 {
+                // The later parts are numbered after the path actually used
+                expanded = Some(path.clone());
+            }
+            Ok(ParquetWriter::from_writer(
+                file,
+                &path,
+                schema,
+                Some(props),
+            )?)
+        })
+    }
+
+    /// Write out everything received so far, close the file and start a new one.
+    async fn rotate(&mut self) -> super::Result<()> {
+        self.flush_records();
+        self.write_pending(None).await?;
+        let Some(writer) = self.writer.take() else {
+            return Ok(());
+        };
+        let schema = writer.close().await?.next_file();
+
+        actor_debug!(self.tag, "rotating parquet file");
+        self.writer = Some(self.spawn_writer(schema));
+        Ok(())
+    }
+
+    /// Sort the buffered records if needed and queue them for the writer.
+    fn flush_records(&mut self) {
+        if self.records_buffer.is_empty() {
+            return;
+        }
+
+        let mut records = std::mem::replace(
+            &mut self.records_buffer,
+            Vec::with_capacity(self.batch_size),
+        );
+        if self.order.stable_order {
+            sort_records(&mut records, &self.order.sort_keys);
+        }
+
+        while records.len() > WRITE_CHUNK_SIZE {
+            let rest = records.split_off(WRITE_CHUNK_SIZE);
+            self.pending.push_back(records);
+            records = rest;
+        }
+        self.pending.push_back(records);
+    }
+
+    /// Hand the queued chunks to the writer, until the budget is exhausted if any.
+    async fn write_pending(&mut self, budget: Option<&PollBudget>) -> super::Result<()> {
+        let Some(ref writer) = self.writer else {
+            self.pending.clear();
+            return Ok(());
+        };
+
+        while let Some(records) = self.pending.pop_front() {
+            writer.write(records).await?;
+
+            if budget.is_some_and(|budget| budget.exhausted()) {
+                break;
+            }
+        }
+
+        if !self.pending.is_empty() {
+            actor_debug!(
+                self.tag,
+                "poll budget exhausted, {} chunks carried over",
+                self.pending.len()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Actor for ParquetOutbound {
+    type Error = super::Error;
+
+    async fn poll(&mut self, ctx: CancellationToken) -> super::Result<()> {
+        let tag = self.tag.clone();
+        let batch_size = self.batch_size;
+        let max_batch_latency = self.max_batch_latency;
+
+        let rotation_due = self
+            .rotation_interval
+            .is_some_and(|interval| self.writer_started.elapsed() >= interval);
+        if rotation_due && self.writer.is_some() && !ctx.is_cancelled() {
+            self.rotate().await?;
+        }
+
+        // Finish the chunks left over by the last poll before receiving new records
+        if !self.pending.is_empty() && !ctx.is_cancelled() {
+            let budget = PollBudget::new(self.max_poll_duration, ctx);
+            return self.write_pending(Some(&budget)).await;
+        }
+
+        let mut records = match recv_batch(
+            &tag,
+            self.inbounds(),
+            Some(std::time::Duration::from_millis(100)),
+            batch_size,
+            max_batch_latency,
+            ctx.clone(),
+        )
+        .await
+        {
+            Ok(records) => records,
+            Err(crate::utils::recv::Error::Timeout) => {
+                // On timeout, flush any buffered records
+                if !self.records_buffer.is_empty() {
+                    self.flush_records();
+                    let budget = PollBudget::new(self.max_poll_duration, ctx);
+                    self.write_pending(Some(&budget)).await?;
+                }
+                return Ok(());
+            }
+            Err(crate::utils::recv::Error::Canceled) => {
+                // Drain what is left and close the file
+                self.flush_records();
+                self.write_pending(None).await?;
+                if let Some(writer) = self.writer.take() {
+                    writer.close().await?;
+                }
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(ref mut shedder) = self.shedder {
+            shedder.retain(&mut records, crate::utils::recv::occupancy(&self.inbounds));
+        }
+
+        if let Some(ref mut age) = self.age {
+            age.retain(&mut records);
+        }
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        // Add records to buffer
+        self.records_buffer.extend(records);
+
+        // If we've reached our batch size, flush the records
+        if self.records_buffer.len() >= self.batch_size {
+            self.flush_records();
+            let budget = PollBudget::new(self.max_poll_duration, ctx);
+            self.write_pending(Some(&budget)).await?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Outbound for ParquetOutbound {
+    fn inbounds(&mut self) -> &mut [TaggedReceiver] {
+        &mut self.inbounds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        time::{Duration, Instant},
+    };
+
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+    use super::*;
+    use crate::{
+        config::{
+            protocol::csv::{CSVField, CSVProtocolConfig, MatchBy},
+            ProtocolConfig, Verify,
+        },
+        core::{
+            manager::ActorChannel,
+            protocol,
+            tag::{OutboundTagId, PipeTagId, TagId, PROTOCOL_TAG_SCOPE},
+            types::{conv::parquet::ParquetReader, Primitive, Symbol, Value},
+        },
+    };
+
+    const GOLDEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/stable_order");
+
+    /// A file which takes its time on every write
+    struct SlowFile {
+        file: std::fs::File,
+        delay: Duration,
+    }
+
+    impl Write for SlowFile {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            std::thread::sleep(self.delay);
+            self.file.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.file.flush()
+        }
+    }
+
+    fn batch(start: i64, len: i64) -> Vec<Record> {
+        (start..start + len)
+            .map(|i| {
+                let mut record = Record::empty();
+                record.set(Symbol::from("seq"), Value::from(i));
+                record
+            })
+            .collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_slow_writes_do_not_stall_workers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("slow.parquet");
+        let path_str = path.to_string_lossy().to_string();
+
+        let tag = OutboundTagId::new("parquet").into();
+        let writer = BlockingWriter::spawn(tag, SchemaTracker::default(), {
+            let path_str = path_str.clone();
+            move |schema, _| {
+                let file = std::fs::File::create(&path_str)?;
+                let sink = SlowFile {
+                    file,
+                    delay: Duration::from_millis(20),
+                };
+                Ok(ParquetWriter::from_writer(sink, &path_str, schema, None)?)
+            }
+        });
+
+        // Stands for an inbound polling at a steady pace while the writer is busy
+        let ticker = tokio::spawn(async {
+            let mut worst = Duration::ZERO;
+            for _ in 0..50 {
+                let start = Instant::now();
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                worst = worst.max(start.elapsed());
+            }
+            worst
+        });
+
+        let write_start = Instant::now();
+        for i in 0..8 {
+            writer.write(batch(i * 10, 10)).await.unwrap();
+        }
+        writer.close().await.unwrap();
+        let write_elapsed = write_start.elapsed();
+
+        let worst = ticker.await.unwrap();
+        assert!(
+            worst < Duration::from_millis(100),
+            "poll latency {:?} while writes took {:?}",
+            worst,
+            write_elapsed
+        );
+
+        // Batches are written in order, and the file has been closed properly
+        let records = ParquetReader::new(&path_str, 1024).read_all().unwrap();
+        let seqs = records
+            .iter()
+            .map(|r| r[&Symbol::from("seq")].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(seqs, (0..80i64).map(Value::from).collect::<Vec<_>>());
+    }
+
+    async fn replay_fixture() -> Vec<Record> {
+        let field = |name: &str, r#type| CSVField {
+            name: Symbol::new(name),
+            r#type,
+            index: 0,
+            optional: false,
+        };
+        let mut cfg = CSVProtocolConfig {
+            tag: TagId::new(PROTOCOL_TAG_SCOPE, "csv").into(),
+            has_header: true,
+            delimiter: ',',
+            fields: vec![
+                field("timestamp", Primitive::DateTime),
+                field("name", Primitive::String),
+                field("host", Primitive::String),
+                field("value", Primitive::Float),
+            ],
+            num_fields: 4,
+            match_by: MatchBy::Index,
+            header_case_insensitive: false,
+            strict_quotes: false,
+            timezone: chrono_tz::Tz::UTC,
+        };
+        cfg.verify().unwrap();
+
+        let data = std::fs::read(format!("{}/metrics.csv", GOLDEN_DIR)).unwrap();
+        let mut parser =
+            protocol::try_create_from(std::io::Cursor::new(data), ProtocolConfig::Csv(cfg))
+                .unwrap();
+
+        let mut records = Vec::new();
+        while let Ok(record) = parser.read_next().await {
+            records.push(record);
+        }
+        records
+    }
+
+    /// Byte comparison of two parquet files, except for the `created_by` entry of the footer.
+    fn assert_parquet_eq(actual: &[u8], expected: &[u8]) {
+        let data_len = |bytes: &[u8]| {
+            let n = bytes.len();
+            let footer = u32::from_le_bytes(bytes[n - 8..n - 4].try_into().unwrap()) as usize;
+            n - 8 - footer
+        };
+        assert_eq!(
+            actual[..data_len(actual)],
+            expected[..data_len(expected)],
+            "column chunks differ"
+        );
+
+        let metadata = |bytes: &[u8]| {
+            let reader = SerializedFileReader::new(bytes::Bytes::copy_from_slice(bytes)).unwrap();
+            let metadata = reader.metadata();
+            let file = metadata.file_metadata();
+            format!(
+                "{} {} {:?} {:?} {:?}",
+                file.version(),
+                file.num_rows(),
+                file.schema(),
+                file.key_value_metadata(),
+                metadata.row_groups()
+            )
+        };
+        assert_eq!(metadata(actual), metadata(expected), "footers differ");
+    }
+
+    // Set UPDATE_GOLDEN=1 to regenerate the expected file.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_stable_order_golden() {
+        let dir = tempfile::tempdir().unwrap();
+        let records = replay_fixture().await;
+        assert_eq!(records.len(), 24);
+
+        let mut outputs = Vec::new();
+        for seed in 0..3 {
+            // Stands for the records of several connections arriving interleaved
+            let mut shuffled = records.clone();
+            shuffled.shuffle(&mut StdRng::seed_from_u64(seed));
+
+            let path = dir.path().join(format!("{}.parquet", seed));
+            let path_str = path.to_string_lossy().to_string();
+            let mut outbound = ParquetOutbound {
+                tag: OutboundTagId::new("parquet").into(),
+                path: path_str.clone(),
+                compression: Compression::SNAPPY,
+                rotation_interval: None,
+                batch_size: 1000,
+                max_batch_latency: Duration::from_millis(100),
+                inbounds: Vec::new(),
+                shedder: None,
+                records_buffer: shuffled,
+                pending: VecDeque::new(),
+                max_poll_duration: Duration::from_secs(1),
+                writer: Some(BlockingWriter::spawn(
+                    OutboundTagId::new("parquet").into(),
+                    SchemaTracker::default(),
+                    move |schema, _| {
+                        let props = writer_properties(Compression::SNAPPY);
+                        Ok(ParquetWriter::with_properties(
+                            &path_str,
+                            schema,
+                            Some(props),
+                        )?)
+                    },
+                )),
+                writer_started: Instant::now(),
+                order: StableOrderConfig {
+                    stable_order: true,
+                    ..Default::default()
+                },
+                age: None,
+            };
+            outbound.flush_records();
+            outbound.write_pending(None).await.unwrap();
+            outbound.writer.take().unwrap().close().await.unwrap();
+
+            outputs.push(std::fs::read(&path).unwrap());
+        }
+
+        let expected_path = format!("{}/expected.parquet", GOLDEN_DIR);
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(&expected_path, &outputs[0]).unwrap();
+        }
+
+        let expected = std::fs::read(&expected_path).unwrap();
+        for output in &outputs {
+            assert_parquet_eq(output, &expected);
+        }
+    }
+
+    fn read_seqs(path: &std::path::Path) -> Vec<Value> {
+        ParquetReader::new(&path.to_string_lossy(), 1024)
+            .read_all()
+            .unwrap()
+            .iter()
+            .map(|r| r[&Symbol::from("seq")].clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg: ParquetOutboundConfig = toml::from_str(&format!(
+            "inbounds = [\"pipe:timeseries\"]\npath = \"{}/%Y/part-{{ts}}.parquet\"\nrotation_interval = \"1s\"",
+            dir.path().display()
+        ))
+        .unwrap();
+        let tag: TagId = (&cfg.tag).into();
+        let mut channel = ActorChannel::new(PipeTagId::new("timeseries").into(), 16);
+        let inbound = channel.receiver(&tag);
+        let mut sender = channel.sender();
+        channel.seal();
+        let mut outbound = ParquetOutbound::new(cfg, vec![inbound]);
+        let ctx = CancellationToken::new();
+
+        for record in batch(0, 3) {
+            sender.send(record).await.unwrap();
+        }
+        outbound.poll(ctx.clone()).await.unwrap();
+        // Flushed on the idle poll
+        outbound.poll(ctx.clone()).await.unwrap();
+
+        // An interval without records leaves no file behind
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        outbound.poll(ctx.clone()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+
+        // The schema may change from one file to the next
+        let mut record = Record::empty();
+        record.set(Symbol::from("seq"), Value::from(3i64));
+        record.set(Symbol::from("host"), Value::from("a"));
+        sender.send(record).await.unwrap();
+        outbound.poll(ctx.clone()).await.unwrap();
+        // The file is closed on cancellation
+        ctx.cancel();
+        outbound.poll(ctx.clone()).await.unwrap();
+
+        let year_dir = dir.path().join(Utc::now().format("%Y").to_string());
+        let mut files = std::fs::read_dir(&year_dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(files.len(), 2, "{:?}", files);
+        assert_eq!(read_seqs(&files[0]), batch_values(0, 3));
+        assert_eq!(read_seqs(&files[1]), batch_values(3, 1));
+    }
+
+    async fn write_batches(outbound: &mut ParquetOutbound, batches: Vec<Vec<Record>>) {
+        for records in batches {
+            outbound.records_buffer = records;
+            outbound.flush_records();
+            outbound.write_pending(None).await.unwrap();
+        }
+        outbound.writer.take().unwrap().close().await.unwrap();
+    }
+
+    fn parquet_config(dir: &std::path::Path, extra: &str) -> ParquetOutboundConfig {
+        let mut cfg: ParquetOutboundConfig = toml::from_str(&format!(
+            "inbounds = [\"pipe:timeseries\"]\npath = \"{}/metrics.parquet\"\n{}",
+            dir.display(),
+            extra
+        ))
+        .unwrap();
+        cfg.verify().unwrap();
+        cfg
+    }
+
+    fn host_record(seq: Value, host: Option<&str>) -> Record {
+        let mut record = Record::empty();
+        record.set(Symbol::from("seq"), seq);
+        if let Some(host) = host {
+            record.set(Symbol::from("host"), Value::from(host));
+        }
+        record
+    }
+
+    #[tokio::test]
+    async fn test_evolve_schema() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = parquet_config(dir.path(), "schema_mode = \"evolve\"");
+        let mut outbound = ParquetOutbound::new(cfg, Vec::new());
+
+        write_batches(
+            &mut outbound,
+            vec![
+                batch(0, 2),
+                // Fits the schema, the missing host is not a new field
+                vec![host_record(Value::from(2i64), None)],
+                // A new field and a float, in a new part
+                vec![
+                    host_record(Value::from(3i64), Some("a")),
+                    host_record(Value::from(3.5), None),
+                ],
+                // The integers are cast to the widened column
+                vec![host_record(Value::from(4i64), Some("b"))],
+            ],
+        )
+        .await;
+
+        let first = ParquetReader::new(&dir.path().join("metrics.parquet").to_string_lossy(), 1024)
+            .read_all()
+            .unwrap();
+        assert_eq!(
+            first
+                .iter()
+                .map(|r| r[&Symbol::from("seq")].clone())
+                .collect::<Vec<_>>(),
+            batch_values(0, 3)
+        );
+
+        let second = ParquetReader::new(
+            &dir.path().join("metrics.1.parquet").to_string_lossy(),
+            1024,
+        )
+        .read_all()
+        .unwrap();
+        let rows = second
+            .iter()
+            .map(|r| {
+                (
+                    r[&Symbol::from("seq")].clone(),
+                    r.get(&Symbol::from("host")).cloned(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            vec![
+                (Value::from(3.0), Some(Value::from("a"))),
+                (Value::from(3.5), None),
+                (Value::from(4.0), Some(Value::from("b"))),
+            ]
+        );
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_explicit_schema() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = parquet_config(
+            dir.path(),
+            "schema_mode = \"explicit\"\nfields = [{ name = \"seq\", type = \"float\" }, { name = \"host\", type = \"string\" }]",
+        );
+        let mut outbound = ParquetOutbound::new(cfg, Vec::new());
+
+        let mut extra = host_record(Value::from(2i64), Some("a"));
+        extra.set(Symbol::from("extra"), Value::from(true));
+        write_batches(
+            &mut outbound,
+            vec![
+                batch(0, 2),
+                vec![
+                    extra,
+                    host_record(Value::from("3.5"), None),
+                    host_record(Value::from("n/a"), Some("b")),
+                ],
+            ],
+        )
+        .await;
+
+        let path = dir.path().join("metrics.parquet");
+        let reader = ParquetReader::new(&path.to_string_lossy(), 1024);
+        let columns = reader
+            .schema()
+            .unwrap()
+            .fields()
+            .iter()
+            .map(|f| f.name().clone())
+            .collect::<Vec<_>>();
+        assert_eq!(columns, vec!["seq", "host"]);
+
+        let rows = reader
+            .read_all()
+            .unwrap()
+            .iter()
+            .map(|r| {
+                (
+                    r.get(&Symbol::from("seq")).cloned(),
+                    r.get(&Symbol::from("host")).cloned(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            vec![
+                (Some(Value::from(0.0)), None),
+                (Some(Value::from(1.0)), None),
+                (Some(Value::from(2.0)), Some(Value::from("a"))),
+                (Some(Value::from(3.5)), None),
+                (None, Some(Value::from("b"))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_create_new_file_never_truncates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("metrics.parquet");
+        let path = path.to_string_lossy().to_string();
+        std::fs::write(&path, b"existing").unwrap();
+
+        let (_, first) = create_new_file(&path).unwrap();
+        let (_, second) = create_new_file(&path).unwrap();
+        assert_eq!(first, suffixed_path(&path, 1));
+        assert_eq!(second, suffixed_path(&path, 2));
+        assert_eq!(std::fs::read(&path).unwrap(), b"existing");
+        assert_eq!(suffixed_path("/data/metrics", 3), "/data/metrics-3");
+    }
+
+    #[test]
+    fn test_rotated_path_requires_ts() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cfg: ParquetOutboundConfig = toml::from_str(&format!(
+            "inbounds = [\"pipe:timeseries\"]\npath = \"{}/%Y/%m/%d.parquet\"\nrotation_interval = \"1h\"",
+            dir.path().display()
+        ))
+        .unwrap();
+        assert!(cfg.verify().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_restart_keeps_existing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("metrics.parquet");
+        for start in [0, 10] {
+            let mut outbound = ParquetOutbound::new(parquet_config(dir.path(), ""), Vec::new());
+            write_batches(&mut outbound, vec![batch(start, 2)]).await;
+        }
+
+        assert_eq!(read_seqs(&path), batch_values(0, 2));
+        assert_eq!(
+            read_seqs(&dir.path().join("metrics-1.parquet")),
+            batch_values(10, 2)
+        );
+    }
+
+    #[test]
+    fn test_part_path() {
+        assert_eq!(
+            part_path("/data/metrics.parquet", 0),
+            "/data/metrics.parquet"
+        );
+        assert_eq!(
+            part_path("/data/metrics.parquet", 2),
+            "/data/metrics.2.parquet"
+        );
+        assert_eq!(part_path("/data/metrics", 1), "/data/metrics.1");
+    }
+
+    fn batch_values(start: i64, len: i64) -> Vec<Value> {
+        (start..start + len).map(Value::from).collect()
+    }
+}
@@ -0,0 +1,301 @@
+use std::sync::Arc;
+
+use arrow::datatypes::{DataType as ArrowDataType, Field, Schema, SchemaRef};
+
+use crate::{
+    config::outbound::parquet::{ParquetField, ParquetSchemaMode},
+    core::types::{
+        conv::parquet::{
+            primitive_to_data_type, record_to_schema, value_to_data_type, widen_data_type, Error,
+        },
+        intern, Record, Value,
+    },
+};
+
+/// Decides the schema of the files written, following the `schema_mode` of the outbound, and
+/// fits the records into it.
+#[derive(Debug)]
+pub(super) struct SchemaTracker {
+    mode: ParquetSchemaMode,
+    // None until the first batch, except for an explicit schema
+    schema: Option<SchemaRef>,
+}
+
+impl Default for SchemaTracker {
+    fn default() -> Self {
+        Self::new(ParquetSchemaMode::FirstRecord, &[])
+    }
+}
+
+impl SchemaTracker {
+    pub fn new(mode: ParquetSchemaMode, fields: &[ParquetField]) -> Self {
+        let schema = (mode == ParquetSchemaMode::Explicit).then(|| {
+            let fields = fields
+                .iter()
+                .map(|field| {
+                    Field::new(
+                        field.name.as_str(),
+                        primitive_to_data_type(&field.r#type),
+                        true,
+                    )
+                })
+                .collect::<Vec<_>>();
+            Arc::new(Schema::new(fields))
+        });
+
+        Self { mode, schema }
+    }
+
+    pub fn schema(&self) -> Option<&SchemaRef> {
+        self.schema.as_ref()
+    }
+
+    /// Take the fields of a batch into account, returns whether the schema changed. Only the
+    /// first batch of a file changes it, except when evolving.
+    pub fn update(&mut self, records: &[Record]) -> Result<bool, Error> {
+        match self.mode {
+            ParquetSchemaMode::Explicit => Ok(false),
+            ParquetSchemaMode::FirstRecord if self.schema.is_some() => Ok(false),
+            ParquetSchemaMode::FirstRecord => {
+                let first = records.first().ok_or(Error::EmptyRecordSet)?;
+                self.schema = Some(record_to_schema(first)?);
+                Ok(true)
+            }
+            ParquetSchemaMode::Evolve => Ok(self.widen(records)),
+        }
+    }
+
+    /// Add the new fields of the records to the schema, and widen the columns their values
+    /// do not fit in. The null values tell nothing of the type of a column.
+    fn widen(&mut self, records: &[Record]) -> bool {
+        let mut fields: Vec<Field> = self
+            .schema
+            .as_ref()
+            .map(|schema| schema.fields().iter().map(|f| f.as_ref().clone()).collect())
+            .unwrap_or_default();
+        let mut changed = self.schema.is_none();
+
+        for record in records {
+            for (key, value) in record.iter() {
+                if matches!(value, Value::Null) {
+                    continue;
+                }
+                // Left to the conversion, which counts it as a mismatch
+                let Ok(data_type) = value_to_data_type(value) else {
+                    continue;
+                };
+
+                match fields.iter_mut().find(|f| f.name() == key.as_str()) {
+                    None => {
+                        fields.push(Field::new(key.as_str(), data_type, true));
+                        changed = true;
+                    }
+                    Some(field) if !fits(value, field.data_type()) => {
+                        let widened = widen_data_type(field.data_type(), &data_type);
+                        if &widened != field.data_type() {
+                            *field = Field::new(key.as_str(), widened, true);
+                            changed = true;
+                        }
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+
+        if changed {
+            self.schema = Some(Arc::new(Schema::new(fields)));
+        }
+        changed
+    }
+
+    /// Cast the values which do not fit the type of their column, to a float or a string,
+    /// and remove those which cannot be: they are written as nulls. Returns the number of
+    /// values removed.
+    pub fn coerce(&self, records: &mut [Record]) -> u64 {
+        let Some(ref schema) = self.schema else {
+            return 0;
+        };
+
+        let mut mismatches = 0;
+        for field in schema.fields() {
+            let name = intern(field.name());
+            for record in records.iter_mut() {
+                let Some(value) = record.get(&name) else {
+                    continue;
+                };
+                if fits(value, field.data_type()) {
+                    continue;
+                }
+
+                let cast = match field.data_type() {
+                    ArrowDataType::Float64 => value.cast_float().ok(),
+                    ArrowDataType::Utf8 => value.cast_string().ok(),
+                    _ => None,
+                };
+                match cast {
+                    Some(value) => record.set(name.clone(), value),
+                    None => {
+                        record.remove(&name);
+                        mismatches += 1;
+                    }
+                }
+            }
+        }
+
+        mismatches
+    }
+
+    /// Called when a new file is started on rotation: only an evolved schema carries over.
+    pub fn next_file(mut self) -> Self {
+        if self.mode == ParquetSchemaMode::FirstRecord {
+            self.schema = None;
+        }
+        self
+    }
+}
+
+/// Whether a column of the type holds the value as it is.
+fn fits(value: &Value, data_type: &ArrowDataType) -> bool {
+    matches!(
+        (value, data_type),
+        (Value::Null, _)
+            | (Value::String(_), ArrowDataType::Utf8)
+            | (Value::Int(_) | Value::DateTime(_), ArrowDataType::Int64)
+            | (Value::Float(_), ArrowDataType::Float64)
+            | (Value::Bool(_), ArrowDataType::Boolean)
+            | (Value::Bytes(_), ArrowDataType::Binary)
+            | (Value::Map(_), ArrowDataType::Struct(_))
+            | (Value::Array(_), ArrowDataType::List(_))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::types::{Primitive, Symbol};
+
+    fn record(fields: &[(&str, Value)]) -> Record {
+        let mut record = Record::empty();
+        for (name, value) in fields {
+            record.set(Symbol::new(name), value.clone());
+        }
+        record
+    }
+
+    fn columns(tracker: &SchemaTracker) -> Vec<(String, ArrowDataType)> {
+        tracker
+            .schema()
+            .unwrap()
+            .fields()
+            .iter()
+            .map(|f| (f.name().clone(), f.data_type().clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_first_record() {
+        let mut tracker = SchemaTracker::default();
+        let mut records = vec![
+            record(&[("value", Value::from(1i64))]),
+            record(&[("value", Value::from(1.5)), ("host", Value::from("a"))]),
+        ];
+        assert!(tracker.update(&records).unwrap());
+        assert!(!tracker.update(&records).unwrap());
+        assert_eq!(
+            columns(&tracker),
+            vec![("value".to_string(), ArrowDataType::Int64)]
+        );
+
+        // No cast to an integer, the float is written as a null
+        assert_eq!(tracker.coerce(&mut records), 1);
+        assert_eq!(records[1].get(&Symbol::new("value")), None);
+
+        assert!(tracker.next_file().schema().is_none());
+    }
+
+    #[test]
+    fn test_explicit() {
+        let fields = [
+            ParquetField {
+                name: Symbol::new("value"),
+                r#type: Primitive::Float,
+            },
+            ParquetField {
+                name: Symbol::new("host"),
+                r#type: Primitive::String,
+            },
+        ];
+        let mut tracker = SchemaTracker::new(ParquetSchemaMode::Explicit, &fields);
+        let mut records = vec![
+            record(&[("value", Value::from(1i64)), ("host", Value::from(7i64))]),
+            record(&[("value", Value::from("2.5")), ("extra", Value::from(true))]),
+            record(&[("value", Value::from("n/a"))]),
+        ];
+        assert!(!tracker.update(&records).unwrap());
+
+        assert_eq!(tracker.coerce(&mut records), 1);
+        assert_eq!(
+            records[0].get(&Symbol::new("value")),
+            Some(&Value::from(1.0))
+        );
+        assert_eq!(
+            records[0].get(&Symbol::new("host")),
+            Some(&Value::from("7"))
+        );
+        assert_eq!(
+            records[1].get(&Symbol::new("value")),
+            Some(&Value::from(2.5))
+        );
+        assert_eq!(records[2].get(&Symbol::new("value")), None);
+        assert!(tracker.next_file().schema().is_some());
+    }
+
+    #[test]
+    fn test_evolve() {
+        let mut tracker = SchemaTracker::new(ParquetSchemaMode::Evolve, &[]);
+        assert!(tracker
+            .update(&[record(&[
+                ("value", Value::from(1i64)),
+                ("host", Value::Null)
+            ])])
+            .unwrap());
+        assert_eq!(
+            columns(&tracker),
+            vec![("value".to_string(), ArrowDataType::Int64)]
+        );
+
+        // Nothing new
+        assert!(!tracker
+            .update(&[record(&[("value", Value::from(2i64))])])
+            .unwrap());
+
+        // A new field and a wider type, in the same batch
+        assert!(tracker
+            .update(&[
+                record(&[("value", Value::from(1.5)), ("host", Value::from("a"))]),
+                record(&[("ok", Value::from(true))]),
+            ])
+            .unwrap());
+        assert_eq!(
+            columns(&tracker),
+            vec![
+                ("value".to_string(), ArrowDataType::Float64),
+                ("host".to_string(), ArrowDataType::Utf8),
+                ("ok".to_string(), ArrowDataType::Boolean),
+            ]
+        );
+
+        // An integer fits in the widened column once cast
+        let mut records = vec![record(&[("value", Value::from(3i64))])];
+        assert!(!tracker.update(&records).unwrap());
+        assert_eq!(tracker.coerce(&mut records), 0);
+        assert_eq!(
+            records[0].get(&Symbol::new("value")),
+            Some(&Value::from(3.0))
+        );
+
+        // The union schema carries over to the next file
+        let tracker = tracker.next_file();
+        assert_eq!(columns(&tracker).len(), 3);
+    }
+}
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use miette::Diagnostic;
 use thiserror::Error;
 
@@ -11,11 +13,75 @@ pub enum Error {
     Conv(#[from] crate::core::types::conv::prometheus::Error),
     #[error(transparent)]
     Reqwuest(#[from] reqwest::Error),
+    /// Status, body and `Retry-After` of the response
+    #[error("Remote write failed ({0}): {1}")]
+    Status(reqwest::StatusCode, String, Option<Duration>),
     #[error(transparent)]
     Snap(#[from] snap::Error),
     #[error(transparent)]
     #[diagnostic(transparent)]
     Recv(#[from] crate::utils::recv::Error),
+    #[error("Disk buffer: {0}")]
+    Buffer(#[from] std::io::Error),
+    #[error("Invalid TLS options: {0}")]
+    Tls(String),
+}
+
+impl Error {
+    /// Server errors, throttling and connection failures are worth retrying,
+    /// anything else (e.g. a 4xx for a malformed request) will fail again.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Status(status, _, _) => {
+                status.is_server_error() || *status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            }
+            Error::Reqwuest(e) => e.is_timeout() || e.is_connect(),
+            _ => false,
+        }
+    }
+
+    /// How long the server asked to wait before retrying.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::Status(_, _, retry_after) => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+/// Parse a `Retry-After` header, either a number of seconds or an HTTP date.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let delay = at.with_timezone(&chrono::Utc) - chrono::Utc::now();
+    // A date in the past means no wait at all
+    Some(delay.to_std().unwrap_or_default())
 }
 
 pub type Result<T> = miette::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 0 "), Some(Duration::ZERO));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+
+        let later = (chrono::Utc::now() + chrono::Duration::seconds(30)).to_rfc2822();
+        let delay = parse_retry_after(&later).unwrap();
+        assert!(delay > Duration::from_secs(20) && delay <= Duration::from_secs(30));
+
+        assert_eq!(parse_retry_after("soon"), None);
+        assert_eq!(parse_retry_after("-1"), None);
+    }
+}
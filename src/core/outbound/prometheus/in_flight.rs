@@ -0,0 +1,175 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::{actor_info, actor_warn, core::tag::TagId, utils::throttle::Throttle};
+
+const SATURATED_WARN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The start of each request in flight, by the order they were sent in.
+type Started = Arc<Mutex<BTreeMap<u64, Instant>>>;
+
+/// Caps the requests sent at once: each send task holds a [`Ticket`] until it completes, and
+/// the poll loop waits for one once `max` of them are out, so that a slow endpoint slows down
+/// the outbound instead of piling up requests.
+pub(super) struct InFlight {
+    tag: TagId,
+    max: usize,
+    semaphore: Arc<Semaphore>,
+    started: Started,
+    next_id: u64,
+    saturated_throttle: Throttle,
+}
+
+/// Held by a send task, the slot is given back when dropped.
+pub(super) struct Ticket {
+    id: u64,
+    started: Started,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        self.started.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl InFlight {
+    pub fn new(tag: TagId, max: usize) -> Self {
+        Self {
+            tag,
+            max,
+            semaphore: Arc::new(Semaphore::new(max)),
+            started: Arc::default(),
+            next_id: 0,
+            saturated_throttle: Throttle::new(SATURATED_WARN_INTERVAL),
+        }
+    }
+
+    /// The number of requests in flight, and how long ago the oldest one was sent.
+    pub fn snapshot(&self) -> (usize, Option<Duration>) {
+        let started = self.started.lock().unwrap();
+        let oldest = started.values().next().map(Instant::elapsed);
+        (started.len(), oldest)
+    }
+
+    /// A slot for the next request, waiting for one to complete if all are taken.
+    pub async fn acquire(&mut self) -> Ticket {
+        let permit = match self.semaphore.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                self.warn_saturated();
+                self.semaphore
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("in flight semaphore closed")
+            }
+        };
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.started.lock().unwrap().insert(id, Instant::now());
+        Ticket {
+            id,
+            started: self.started.clone(),
+            _permit: permit,
+        }
+    }
+
+    fn warn_saturated(&mut self) {
+        if let Some(suppressed) = self.saturated_throttle.check_at(Instant::now()) {
+            let (count, oldest) = self.snapshot();
+            actor_warn!(
+                self.tag,
+                "{} requests in flight, the oldest sent {:?} ago, waiting for one to complete ({} similar warnings suppressed)",
+                count,
+                oldest.unwrap_or_default(),
+                suppressed
+            );
+        }
+    }
+
+    /// Wait for the requests in flight to complete, up to `timeout`. Returns whether they all
+    /// did.
+    pub async fn wait(&self, timeout: Duration) -> bool {
+        let (count, _) = self.snapshot();
+        if count == 0 {
+            return true;
+        }
+
+        actor_info!(
+            self.tag,
+            "waiting up to {:?} for {} requests in flight",
+            timeout,
+            count
+        );
+        let all = self.semaphore.acquire_many(self.max as u32);
+        match tokio::time::timeout(timeout, all).await {
+            Ok(_) => true,
+            Err(_) => {
+                let (count, oldest) = self.snapshot();
+                actor_warn!(
+                    self.tag,
+                    "abandoned {} requests in flight, the oldest sent {:?} ago",
+                    count,
+                    oldest.unwrap_or_default()
+                );
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tag::OutboundTagId;
+
+    fn in_flight(max: usize) -> InFlight {
+        InFlight::new(OutboundTagId::new("prometheus").into(), max)
+    }
+
+    #[tokio::test]
+    async fn test_max_in_flight() {
+        let mut in_flight = in_flight(2);
+        let first = in_flight.acquire().await;
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let second = in_flight.acquire().await;
+
+        let (count, oldest) = in_flight.snapshot();
+        assert_eq!(count, 2);
+        assert!(oldest.unwrap() >= Duration::from_millis(10));
+
+        // The third waits for a slot
+        let third = tokio::time::timeout(Duration::from_millis(50), in_flight.acquire()).await;
+        assert!(third.is_err());
+
+        drop(first);
+        let third = in_flight.acquire().await;
+        assert_eq!(in_flight.snapshot().0, 2);
+        drop((second, third));
+        assert_eq!(in_flight.snapshot(), (0, None));
+    }
+
+    #[tokio::test]
+    async fn test_wait() {
+        let mut in_flight = in_flight(4);
+        assert!(in_flight.wait(Duration::from_millis(10)).await);
+
+        let ticket = in_flight.acquire().await;
+        let sender = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            drop(ticket);
+        });
+        assert!(in_flight.wait(Duration::from_secs(5)).await);
+        sender.await.unwrap();
+
+        let _stuck = in_flight.acquire().await;
+        assert!(!in_flight.wait(Duration::from_millis(20)).await);
+    }
+}
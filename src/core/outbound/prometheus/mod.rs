@@ -1,40 +1,280 @@
-use std::ops::Deref;
+use std::{
+    collections::HashSet,
+    ops::Deref,
+    sync::{Arc, Mutex},
+};
 
 use crate::{
-    config::{
-        global::use_time_tracing,
-        outbound::{auth::AuthConfig, prometheus::PrometheusOutboundConfig},
-    },
+    actor_debug, actor_error, actor_info, actor_warn,
+    config::{global::use_time_tracing, outbound::prometheus::PrometheusOutboundConfig},
     core::{
         actor::Actor,
+        dead_letter::DeadLetter,
         manager::{ChannelGraph, TaggedReceiver},
+        metrics::{self, ActorMetrics},
         pipe::RECORD_TYPE_TIMESERIES_VALUE,
         tag::{HasTag, TagId},
-        types::conv::prometheus::WriteRequest,
+        types::{
+            conv::prometheus::{
+                combine_timeseries, transform_timeseries, Compression, EncodedRequest, Label,
+                RequestLimits, TimeSeries, WriteRequest,
+            },
+            Record,
+        },
+    },
+    utils::{
+        rate_limit::RateLimiter,
+        recv::{self, recv_batch},
+        retry::{retry, RetryOutcome, RetryPolicy},
+        stats::GLOBAL_STATS,
     },
-    utils::recv::recv_batch,
 };
 
 pub mod error;
+mod in_flight;
+mod queue;
 
 use async_trait::async_trait;
 pub use error::{Error, Result};
-use log::{debug, error, warn};
+use in_flight::InFlight;
+use queue::RetryQueue;
 use tokio_util::sync::CancellationToken;
 
-use super::Outbound;
+use super::{age::AgeFilter, auth::Credentials, buffer::DiskBuffer, shed::LoadShedder, Outbound};
+
+// Wait after a failed replay, the buffer is not read again meanwhile
+const REPLAY_PAUSE: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// The remote write endpoint, shared by the send tasks.
+#[derive(Clone)]
+struct Remote {
+    address: String,
+    auth: Credentials,
+    client: reqwest::Client,
+    compression: Compression,
+    retry: RetryPolicy<Error>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    metrics: Arc<ActorMetrics>,
+}
+
+impl Remote {
+    /// Send an encoded request, retried according to the policy.
+    async fn write(&self, body: Vec<u8>, ctx: CancellationToken) -> RetryOutcome<(), Error> {
+        let request =
+            WriteRequest::request(body, self.compression, &self.client, &self.address, "void");
+
+        retry(&self.retry, ctx, |_| {
+            // The body is a plain buffer, so the request can always be cloned.
+            let request = request
+                .try_clone()
+                .expect("remote write request is not cloneable");
+            let auth = self.auth.clone();
+            let rate_limiter = self.rate_limiter.clone();
+            let metrics = self.metrics.clone();
+
+            async move {
+                if let Some(rate_limiter) = rate_limiter {
+                    rate_limiter.acquire().await;
+                }
+
+                let start = std::time::Instant::now();
+                let mut response = auth
+                    .apply(
+                        request
+                            .try_clone()
+                            .expect("remote write request is not cloneable"),
+                    )
+                    .send()
+                    .await;
+                // Sent once more if the credential files changed since they were last read
+                if matches!(response, Ok(ref r) if r.status() == reqwest::StatusCode::UNAUTHORIZED)
+                    && auth.reload()
+                {
+                    response = auth.apply(request).send().await;
+                }
+                metrics.observe_request(start.elapsed());
+                let response = response?;
+                let status = response.status();
+                if status.is_success() {
+                    Ok(())
+                } else {
+                    let retry_after = response
+                        .headers()
+                        .get(reqwest::header::RETRY_AFTER)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(error::parse_retry_after);
+                    Err(Error::Status(
+                        status,
+                        response.text().await.unwrap_or_default(),
+                        retry_after,
+                    ))
+                }
+            }
+        })
+        .await
+    }
+}
+
+// Only the calls which stay in memory (`is_empty`, `release`) lock it on the async workers,
+// those reading or writing the segments go through `with_buffer`.
+type SharedBuffer = Arc<Mutex<DiskBuffer<TimeSeries>>>;
+
+/// Run `f` on the disk buffer on a blocking thread, so that a slow disk never stalls the async
+/// workers.
+async fn with_buffer<R, F>(buffer: &SharedBuffer, f: F) -> R
+where
+    R: Send + 'static,
+    F: FnOnce(&mut DiskBuffer<TimeSeries>) -> R + Send + 'static,
+{
+    let buffer = buffer.clone();
+    tokio::task::spawn_blocking(move || f(&mut buffer.lock().unwrap()))
+        .await
+        .expect("disk buffer access panicked")
+}
+
+/// Append the series given up to the disk buffer.
+async fn spool(tag: &TagId, buffer: &SharedBuffer, tss: Vec<TimeSeries>) {
+    let samples = tss.iter().map(|ts| ts.samples.len()).sum::<usize>();
+    match with_buffer(buffer, move |buffer| buffer.push(&tss)).await {
+        Ok(0) => {}
+        Ok(dropped) => {
+            actor_warn!(
+                tag,
+                "disk buffer full, dropped {} bytes of the oldest samples",
+                dropped
+            );
+            GLOBAL_STATS.incr(&format!("{} dropped buffer bytes", tag), dropped);
+        }
+        Err(e) => {
+            actor_error!(
+                tag,
+                "error writing to the disk buffer, dropped {} samples: {}",
+                samples,
+                e
+            );
+            GLOBAL_STATS.incr(&format!("{} dropped samples", tag), samples as u64);
+        }
+    }
+}
+
+/// Send the buffered series, oldest first, until the buffer is empty or a write fails.
+async fn replay(
+    tag: TagId,
+    remote: Remote,
+    buffer: SharedBuffer,
+    limits: RequestLimits,
+    ctx: CancellationToken,
+) {
+    'batches: loop {
+        let tss = match with_buffer(&buffer, |buffer| buffer.peek()).await {
+            Ok(tss) => tss,
+            Err(e) => {
+                actor_error!(tag, "error reading the disk buffer: {}", e);
+                return;
+            }
+        };
+        // Empty, or replayed by another task
+        if tss.is_empty() {
+            return;
+        }
+
+        let count = tss.len();
+        let requests = match WriteRequest::from(tss).encode_split(limits, remote.compression) {
+            Ok(requests) => requests,
+            Err(e) => {
+                actor_error!(tag, "dropped {} buffered series: {}", count, e);
+                ack_replayed(&tag, &buffer).await;
+                continue;
+            }
+        };
+
+        for part in requests {
+            match remote.write(part.body, ctx.clone()).await {
+                RetryOutcome::Succeeded { .. } => {}
+                RetryOutcome::GaveUp { error, .. } if error.is_retryable() => {
+                    actor_warn!(tag, "replay of the disk buffer failed: {}", error);
+                    tokio::select! {
+                        _ = tokio::time::sleep(REPLAY_PAUSE) => {}
+                        _ = ctx.cancelled() => {}
+                    }
+                    buffer.lock().unwrap().release();
+                    return;
+                }
+                // A rejected request would be rejected again
+                RetryOutcome::GaveUp { error, .. } => {
+                    actor_error!(tag, "dropped buffered series: {}", error);
+                    ack_replayed(&tag, &buffer).await;
+                    continue 'batches;
+                }
+                RetryOutcome::Cancelled { .. } => {
+                    buffer.lock().unwrap().release();
+                    return;
+                }
+            }
+        }
+
+        actor_debug!(tag, "replayed {} series from the disk buffer", count);
+        ack_replayed(&tag, &buffer).await;
+    }
+}
+
+/// The records whose samples were sent in `tss`, i.e. in one part of a split batch.
+fn records_in(records: &[Record], tss: &[TimeSeries]) -> Vec<Record> {
+    let series_key = |ts: &TimeSeries| {
+        let mut labels = ts.labels.clone();
+        labels.sort_by(|a, b| a.name.cmp(&b.name));
+        labels
+    };
+    let sent = tss
+        .iter()
+        .flat_map(|ts| {
+            let labels = series_key(ts);
+            ts.samples
+                .iter()
+                .map(move |sample| (labels.clone(), sample.timestamp))
+        })
+        .collect::<HashSet<(Vec<Label>, i64)>>();
+
+    records
+        .iter()
+        .filter(|record| {
+            TimeSeries::try_from((*record).clone()).is_ok_and(|ts| {
+                let labels = series_key(&ts);
+                ts.samples
+                    .iter()
+                    .any(|sample| sent.contains(&(labels.clone(), sample.timestamp)))
+            })
+        })
+        .cloned()
+        .collect()
+}
+
+async fn ack_replayed(tag: &TagId, buffer: &SharedBuffer) {
+    if let Err(e) = with_buffer(buffer, |buffer| buffer.ack()).await {
+        actor_error!(tag, "error removing a replayed segment: {}", e);
+    }
+}
+
 pub struct PrometheusOutbound {
     tag: TagId,
-    address: String,
 
     recv_timeout: std::time::Duration,
 
-    auth: AuthConfig,
-    client: reqwest::Client,
+    remote: Remote,
+    retry_queue: Arc<RetryQueue>,
+    buffer: Option<SharedBuffer>,
+    age: Option<AgeFilter>,
+
+    limits: RequestLimits,
+    in_flight: InFlight,
+    shutdown_timeout: std::time::Duration,
 
     inbounds: Vec<TaggedReceiver>,
+    shedder: Option<LoadShedder>,
+    dead_letter: DeadLetter,
 
     recv_buffer_size: usize,
+    max_batch_latency: std::time::Duration,
 }
 
 impl PrometheusOutbound {
@@ -42,28 +282,65 @@ impl PrometheusOutbound {
         cfg: PrometheusOutboundConfig,
         channels: &mut ChannelGraph,
     ) -> Result<Self> {
-        let client = reqwest::Client::builder()
-            .timeout(std::time::Duration::from_secs(5))
+        let tag: TagId = cfg.tag.into();
+        let mut client = reqwest::Client::builder().timeout(cfg.timeout.into());
+        if let Some(connect_timeout) = cfg.connect_timeout {
+            client = client.connect_timeout(connect_timeout.into());
+        }
+        let client = cfg
+            .tls
+            .apply(client)
+            .map_err(|e| Error::Tls(format!("{}.tls: {}", tag, e)))?
             .build()?;
-
-        let address = cfg.address.to_string();
-        let auth = cfg.auth;
-        let tag = cfg.tag.into();
+        let remote = Remote {
+            address: cfg.address.to_string(),
+            auth: Credentials::new(&tag, &cfg.auth),
+            client,
+            compression: cfg.compression,
+            retry: RetryPolicy::from_config(&cfg.retry)
+                .with_classifier(Error::is_retryable)
+                .with_delay_hint(Error::retry_after),
+            rate_limiter: cfg
+                .max_requests_per_second
+                .map(|rate| Arc::new(RateLimiter::new(rate))),
+            metrics: metrics::actor_metrics(&tag),
+        };
+        // The directory of the buffer is left alone on a dry run
+        let buffer = cfg
+            .buffer
+            .as_ref()
+            .filter(|_| !channels.is_dry_run())
+            .map(|buffer| DiskBuffer::open(tag.clone(), buffer))
+            .transpose()?
+            .map(|buffer| Arc::new(Mutex::new(buffer)));
 
         let inbounds = cfg
             .inbounds
             .iter()
             .map(|inbound| channels.recv_from(inbound, &tag))
             .collect::<Vec<_>>();
+        let dead_letter = channels.dead_letter(&tag);
+        let age = AgeFilter::from_config(&tag, &cfg.age);
 
+        let shedder = LoadShedder::from_config(&tag, &cfg.shedding);
         Ok(PrometheusOutbound {
-            tag,
-            address,
-            recv_timeout: cfg.recv_timeout,
-            auth,
-            client,
+            tag: tag.clone(),
+            recv_timeout: cfg.recv_timeout.into(),
+            remote,
+            retry_queue: Arc::new(RetryQueue::new(cfg.retry_queue_size)),
+            buffer,
+            age,
+            limits: RequestLimits {
+                max_bytes: cfg.max_request_bytes.unwrap_or(usize::MAX),
+                max_samples: cfg.max_samples_per_request.unwrap_or(usize::MAX),
+            },
+            in_flight: InFlight::new(tag.clone(), cfg.max_in_flight),
+            shutdown_timeout: cfg.shutdown_timeout.into(),
             inbounds,
+            shedder,
+            dead_letter,
             recv_buffer_size: cfg.recv_buffer_size,
+            max_batch_latency: cfg.max_batch_latency.into(),
         })
     }
 }
@@ -80,24 +357,36 @@ impl Actor for PrometheusOutbound {
 
     async fn poll(&mut self, ctx: CancellationToken) -> std::result::Result<(), Self::Error> {
         let tag = self.tag.clone();
-        let interval = (&self.recv_timeout).clone();
+        let interval = self.recv_timeout;
         let buffer_size = self.recv_buffer_size;
+        let max_batch_latency = self.max_batch_latency;
 
-        let records =
-            match recv_batch(&tag, self.inbounds(), Some(interval), buffer_size, ctx).await {
-                Ok(records) => records,
-                Err(crate::utils::recv::Error::Timeout) => {
-                    return Ok(());
-                }
-                Err(e) => return Err(e.into()),
-            };
-
-        if records.is_empty() {
-            return Ok(());
+        let mut records = match recv_batch(
+            &tag,
+            self.inbounds(),
+            Some(interval),
+            buffer_size,
+            max_batch_latency,
+            ctx.clone(),
+        )
+        .await
+        {
+            Ok(records) => records,
+            // 没有新数据时也重发之前失败的样本
+            Err(recv::Error::Timeout) => Vec::new(),
+            // 退出前等待发送中的请求完成
+            Err(recv::Error::Canceled) => {
+                self.in_flight.wait(self.shutdown_timeout).await;
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(ref mut shedder) = self.shedder {
+            shedder.retain(&mut records, recv::occupancy(&self.inbounds));
         }
 
         let before_len = records.len();
-        let records = records
+        let mut records = records
             .into_iter()
             .filter_map(|record| {
                 let r#type = record.get_type()?;
@@ -111,27 +400,65 @@ impl Actor for PrometheusOutbound {
             .collect::<Vec<_>>();
         let after_len = records.len();
         if after_len != before_len {
-            warn!(
-                "{}: filtered {} records with wrong types, {} left",
+            actor_warn!(
                 tag,
+                "filtered {} records with wrong types, {} left",
                 before_len - after_len,
                 after_len
             );
         }
 
+        if let Some(ref mut age) = self.age {
+            age.retain(&mut records);
+        }
+
+        if let Some(ref buffer) = self.buffer {
+            if !buffer.lock().unwrap().is_empty() {
+                tokio::task::spawn(replay(
+                    self.tag.clone(),
+                    self.remote.clone(),
+                    buffer.clone(),
+                    self.limits,
+                    ctx.clone(),
+                ));
+            }
+        }
+
+        let queued = self.retry_queue.take();
+        if records.is_empty() && queued.is_empty() {
+            return Ok(());
+        }
+
         for record in &records {
-            record.mark_record_release();
+            record.mark_record_release(&tag);
         }
 
-        let client = self.client.clone();
-        let auth = self.auth.clone();
-        let address = self.address.clone();
+        let remote = self.remote.clone();
+        let retry_queue = self.retry_queue.clone();
+        let buffer = self.buffer.clone();
+        let limits = self.limits;
         let tag = self.tag.clone();
+        // Kept to be sent to the dead letter channel if the write is given up
+        let mut dead_letter = self.dead_letter.clone();
+        let rejected = dead_letter.is_enabled().then(|| records.clone());
         let transform_start_timestamp = std::time::Instant::now();
 
-        let _ = tokio::task::spawn(async move {
-            let tss = crate::core::types::conv::prometheus::transform_timeseries(records)
-                .map_err(error::Error::from)?;
+        // Waits while `max_in_flight` requests are out, the inbound channels fill up meanwhile
+        let ticket = self.in_flight.acquire().await;
+        tokio::task::spawn(async move {
+            let _ticket = ticket;
+            let mut tss = queued;
+            if !records.is_empty() {
+                let converted = transform_timeseries(records).map_err(error::Error::from);
+                if let Err(ref e) = converted {
+                    if let Some(ref records) = rejected {
+                        dead_letter.send_all(records.clone(), e);
+                    }
+                }
+                tss.extend(converted?);
+            }
+            // The samples of a failed push are merged into the series they belong to
+            let tss = combine_timeseries(tss).map_err(error::Error::from)?;
 
             let last_timestamp = tss
                 .iter()
@@ -145,39 +472,127 @@ impl Actor for PrometheusOutbound {
             let time_diff = now.signed_duration_since(last_timestamp);
             let time_diff = time_diff.num_milliseconds();
             if time_diff > 1000 {
-                warn!(
-                    "{}: last timestamp is {:+4} seconds ago, lagging...",
+                actor_warn!(
                     tag,
+                    "last timestamp is {:+4} seconds ago, lagging...",
                     (time_diff as f64) / 1000.0
                 );
             }
-            let request: WriteRequest = tss.into();
-            let request = request
-                .build_request(&client, &auth, &address, "void")
+            // While older samples wait on disk, the new ones are queued behind them
+            if let Some(ref buffer) = buffer {
+                if !buffer.lock().unwrap().is_empty() {
+                    spool(&tag, buffer, tss).await;
+                    return Ok(());
+                }
+            }
+
+            // Without a cap, a single request
+            let requests = WriteRequest::from(tss)
+                .encode_split(limits, remote.compression)
                 .map_err(Error::from)?;
+            if requests.len() > 1 {
+                actor_debug!(tag, "split the batch into {} requests", requests.len());
+            }
+            for part in requests.iter().filter(|part| part.oversized) {
+                actor_warn!(
+                    tag,
+                    "a single sample takes {} bytes, too large for max_request_bytes, sent on its own",
+                    part.body.len()
+                );
+            }
 
-            // spawn a task to send the request
-            let response = request.send().await;
+            let total = requests.len();
+            let mut failed = 0;
+            let mut requests = requests.into_iter();
+            while let Some(EncodedRequest { request, body, .. }) = requests.next() {
+                let samples = request.num_samples();
+                let kept = (buffer.is_some() || retry_queue.is_enabled() || rejected.is_some())
+                    .then_some(request.timeseries);
 
-            match response {
-                Ok(response) => {
-                    if response.status() != reqwest::StatusCode::NO_CONTENT {
-                        error!(
-                            "{}: request failed ({}): {}",
+                match remote.write(body, ctx.clone()).await {
+                    RetryOutcome::Succeeded {
+                        value: (),
+                        attempts,
+                    } => {
+                        if attempts > 1 {
+                            actor_info!(tag, "request succeeded after {} attempts", attempts);
+                        }
+                    }
+                    RetryOutcome::GaveUp { error, attempts } => {
+                        actor_error!(tag, "request failed after {} attempts: {}", attempts, error);
+                        failed += 1;
+
+                        // A rejected request would be rejected again
+                        match (kept, &buffer) {
+                            (Some(tss), Some(buffer)) if error.is_retryable() => {
+                                spool(&tag, buffer, tss).await
+                            }
+                            (Some(tss), None)
+                                if error.is_retryable() && retry_queue.is_enabled() =>
+                            {
+                                let dropped = retry_queue.push(tss);
+                                if dropped > 0 {
+                                    actor_warn!(
+                                        tag,
+                                        "retry queue full, dropped {} samples",
+                                        dropped
+                                    );
+                                    GLOBAL_STATS
+                                        .incr(&format!("{} dropped samples", tag), dropped as u64);
+                                }
+                            }
+                            // Only the records of the part given up, the others were sent
+                            (kept, _) => {
+                                if let (Some(records), Some(tss)) = (&rejected, kept) {
+                                    dead_letter.send_all(records_in(records, &tss), &error);
+                                }
+                            }
+                        }
+                    }
+                    RetryOutcome::Cancelled {
+                        attempts,
+                        last_error,
+                    } => {
+                        actor_warn!(
                             tag,
-                            response.status(),
-                            response.text().await.unwrap_or_default()
+                            "request cancelled after {} attempts, last error: {:?}",
+                            attempts,
+                            last_error
                         );
+                        // Replayed after the restart
+                        if let Some(ref buffer) = buffer {
+                            for tss in kept
+                                .into_iter()
+                                .chain(requests.by_ref().map(|part| part.request.timeseries))
+                            {
+                                spool(&tag, buffer, tss).await;
+                            }
+                        } else {
+                            let dropped = samples
+                                + requests
+                                    .by_ref()
+                                    .map(|part| part.request.num_samples())
+                                    .sum::<usize>();
+                            actor_warn!(tag, "dropped {} samples on cancellation", dropped);
+                            GLOBAL_STATS.incr(&format!("{} dropped samples", tag), dropped as u64);
+                        }
+                        break;
                     }
                 }
-                Err(e) => {
-                    error!("{}: request failed: {}", tag, e);
-                }
+            }
+
+            if total > 1 && failed > 0 {
+                actor_warn!(
+                    tag,
+                    "{} of the {} requests of the batch failed",
+                    failed,
+                    total
+                );
             }
 
             if use_time_tracing() {
                 let elapsed = transform_start_timestamp.elapsed();
-                debug!("{}: prometheus request took {:?}", tag, elapsed);
+                actor_debug!(tag, "prometheus request took {:?}", elapsed);
             }
 
             Ok::<(), super::Error>(())
@@ -192,3 +607,46 @@ impl Outbound for PrometheusOutbound {
         &mut self.inbounds
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    use crate::core::{
+        pipe::{
+            LABELS_FIELD, METRIC_TYPE_FIELD, NAME_FIELD, RECORD_TYPE_TIMESERIES_VALUE,
+            TIMESTAMP_FIELD, VALUE_FIELD,
+        },
+        types::Value,
+    };
+
+    fn record(name: &str, host: &str) -> Record {
+        let mut record = Record::new_root();
+        record.set(NAME_FIELD.clone(), Value::from(name));
+        record.set(METRIC_TYPE_FIELD.clone(), Value::from("gauge"));
+        record.set(VALUE_FIELD.clone(), Value::from(1.0));
+        record.set(TIMESTAMP_FIELD.clone(), Value::from(chrono::Utc::now()));
+        let labels = [(Value::from("host"), Value::from(host))]
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        record.set(LABELS_FIELD.clone(), Value::from(labels));
+        record.set_type(RECORD_TYPE_TIMESERIES_VALUE.clone());
+        record
+    }
+
+    #[test]
+    fn test_records_in_part() {
+        let records = vec![record("up", "a"), record("up", "b"), record("load", "a")];
+        let mut part = TimeSeries::try_from(records[1].clone()).unwrap();
+        part.labels.reverse();
+
+        let rejected = records_in(&records, &[part]);
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(
+            TimeSeries::try_from(rejected[0].clone()).unwrap().labels,
+            TimeSeries::try_from(records[1].clone()).unwrap().labels
+        );
+        assert!(records_in(&records, &[]).is_empty());
+    }
+}
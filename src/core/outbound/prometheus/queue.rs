@@ -0,0 +1,107 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+use crate::core::types::conv::prometheus::TimeSeries;
+
+/// Samples of the remote writes which failed, to be sent along with the next one.
+///
+/// Bounded by a number of samples: once full, the oldest batches are dropped first.
+#[derive(Debug)]
+pub struct RetryQueue {
+    capacity: usize,
+    inner: Mutex<Batches>,
+}
+
+#[derive(Debug, Default)]
+struct Batches {
+    batches: VecDeque<(Vec<TimeSeries>, usize)>,
+    samples: usize,
+}
+
+fn num_samples(tss: &[TimeSeries]) -> usize {
+    tss.iter().map(|ts| ts.samples.len()).sum()
+}
+
+impl RetryQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(Batches::default()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Queue a batch, returns the number of samples dropped to make room for it.
+    pub fn push(&self, tss: Vec<TimeSeries>) -> usize {
+        let samples = num_samples(&tss);
+        if samples > self.capacity {
+            return samples;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        let mut dropped = 0;
+        while inner.samples + samples > self.capacity {
+            let (_, n) = inner.batches.pop_front().expect("queue is over capacity");
+            inner.samples -= n;
+            dropped += n;
+        }
+
+        inner.samples += samples;
+        inner.batches.push_back((tss, samples));
+        dropped
+    }
+
+    /// Take all the queued series, oldest first.
+    pub fn take(&self) -> Vec<TimeSeries> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.samples = 0;
+        inner.batches.drain(..).flat_map(|(tss, _)| tss).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::types::conv::prometheus::{Label, Sample};
+
+    fn series(name: &str, timestamps: &[i64]) -> TimeSeries {
+        TimeSeries {
+            labels: vec![Label {
+                name: "__name__".to_string(),
+                value: name.to_string(),
+            }],
+            samples: timestamps
+                .iter()
+                .map(|&timestamp| Sample {
+                    value: 1.0,
+                    timestamp,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_retry_queue() {
+        let queue = RetryQueue::new(4);
+        assert_eq!(queue.push(vec![series("a", &[1, 2])]), 0);
+        assert_eq!(queue.push(vec![series("b", &[1]), series("c", &[1])]), 0);
+        // The oldest batch makes room
+        assert_eq!(queue.push(vec![series("d", &[3, 4])]), 2);
+        // Larger than the whole queue
+        assert_eq!(queue.push(vec![series("e", &[1, 2, 3, 4, 5])]), 5);
+
+        let names = queue
+            .take()
+            .iter()
+            .map(|ts| ts.labels[0].value.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["b", "c", "d"]);
+        assert!(queue.take().is_empty());
+
+        let disabled = RetryQueue::new(0);
+        assert!(!disabled.is_enabled());
+        assert_eq!(disabled.push(vec![series("a", &[1])]), 1);
+    }
+}
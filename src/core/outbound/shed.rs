@@ -0,0 +1,211 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    actor_warn,
+    config::{outbound::LoadSheddingConfig, pipe::Priority},
+    core::{tag::TagId, types::Record},
+    utils::{stats::GLOBAL_STATS, throttle::Throttle},
+};
+
+const SHED_WARN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Drops the `low`, then the `normal` priority records once the channels an outbound
+/// receives from fill up, so that the records which matter get the room left.
+#[derive(Debug)]
+pub struct LoadShedder {
+    tag: TagId,
+    low_above: f64,
+    normal_above: f64,
+
+    // Shed since the last warning
+    shed_low: u64,
+    shed_normal: u64,
+    throttle: Throttle,
+}
+
+impl LoadShedder {
+    /// None without a `shed_low_above`.
+    pub fn from_config(tag: &TagId, cfg: &LoadSheddingConfig) -> Option<Self> {
+        let low_above = cfg.shed_low_above?;
+        Some(LoadShedder {
+            tag: tag.clone(),
+            low_above,
+            normal_above: cfg.shed_normal_above.unwrap_or((low_above + 1.0) / 2.0),
+            shed_low: 0,
+            shed_normal: 0,
+            throttle: Throttle::new(SHED_WARN_INTERVAL),
+        })
+    }
+
+    /// The lowest priority kept with the channels filled to `occupancy`.
+    fn kept_from(&self, occupancy: f64) -> Priority {
+        if occupancy >= self.normal_above {
+            Priority::High
+        } else if occupancy >= self.low_above {
+            Priority::Normal
+        } else {
+            Priority::Low
+        }
+    }
+
+    /// Drop the records not worth the room left, `occupancy` being the fill of the fullest
+    /// inbound channel once the batch was received. Counted in `<tag> shed <priority> records`.
+    pub fn retain(&mut self, records: &mut Vec<Record>, occupancy: f64) {
+        let kept_from = self.kept_from(occupancy);
+        if kept_from > Priority::Low {
+            let (mut low, mut normal) = (0, 0);
+            records.retain(|record| match record.priority() {
+                priority if priority >= kept_from => true,
+                Priority::Low => {
+                    low += 1;
+                    false
+                }
+                _ => {
+                    normal += 1;
+                    false
+                }
+            });
+
+            for (priority, shed) in [(Priority::Low, low), (Priority::Normal, normal)] {
+                if shed > 0 {
+                    GLOBAL_STATS.incr(&format!("{} shed {} records", self.tag, priority), shed);
+                }
+            }
+            self.shed_low += low;
+            self.shed_normal += normal;
+        }
+
+        self.warn_shed(occupancy);
+    }
+
+    fn warn_shed(&mut self, occupancy: f64) {
+        if self.shed_low == 0 && self.shed_normal == 0 {
+            return;
+        }
+        if let Some(suppressed) = self.throttle.check_at(Instant::now()) {
+            actor_warn!(
+                self.tag,
+                "inbound channels {:.0}% full, shed {} low and {} normal priority records ({} similar warnings suppressed)",
+                occupancy * 100.0,
+                self.shed_low,
+                self.shed_normal,
+                suppressed
+            );
+            self.shed_low = 0;
+            self.shed_normal = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{
+        manager::ActorChannel,
+        tag::{OutboundTagId, PipeTagId},
+        types::{Symbol, Value},
+    };
+
+    fn shedder(body: &str) -> LoadShedder {
+        let mut cfg: LoadSheddingConfig = toml::from_str(body).unwrap();
+        let tag: TagId = OutboundTagId::new("prometheus").into();
+        cfg.verify_for(&tag).unwrap();
+        LoadShedder::from_config(&tag, &cfg).unwrap()
+    }
+
+    fn record(seq: i64, priority: Option<Priority>) -> Record {
+        let mut record = Record::empty();
+        record.set(Symbol::new("seq"), Value::from(seq));
+        if let Some(priority) = priority {
+            record.set_priority(priority);
+        }
+        record
+    }
+
+    fn priorities(records: &[Record]) -> Vec<Priority> {
+        records.iter().map(Record::priority).collect()
+    }
+
+    #[test]
+    fn test_thresholds() {
+        let mut shedder = shedder("shed_low_above = 0.5");
+        assert_eq!(shedder.normal_above, 0.75);
+
+        let batch = || {
+            vec![
+                record(0, Some(Priority::Low)),
+                record(1, None),
+                record(2, Some(Priority::High)),
+                record(3, Some(Priority::Normal)),
+            ]
+        };
+
+        let mut records = batch();
+        shedder.retain(&mut records, 0.4);
+        assert_eq!(records.len(), 4);
+
+        let mut records = batch();
+        shedder.retain(&mut records, 0.5);
+        assert_eq!(
+            priorities(&records),
+            vec![Priority::Normal, Priority::High, Priority::Normal]
+        );
+
+        let mut records = batch();
+        shedder.retain(&mut records, 0.9);
+        assert_eq!(priorities(&records), vec![Priority::High]);
+
+        assert!(LoadShedder::from_config(
+            &OutboundTagId::new("prometheus").into(),
+            &LoadSheddingConfig::default()
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_verify() {
+        let verify = |body: &str| {
+            let mut cfg: LoadSheddingConfig = toml::from_str(body).unwrap();
+            cfg.verify_for(&OutboundTagId::new("prometheus").into())
+        };
+        assert!(verify("").is_ok());
+        assert!(verify("shed_low_above = 0.6\nshed_normal_above = 0.6").is_ok());
+        assert!(verify("shed_low_above = 0").is_err());
+        assert!(verify("shed_low_above = 1.5").is_err());
+        assert!(verify("shed_low_above = 0.8\nshed_normal_above = 0.5").is_err());
+        assert!(verify("shed_normal_above = 0.5").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_saturated_channel() {
+        let who: TagId = OutboundTagId::new("prometheus").into();
+        let mut channel = ActorChannel::new(PipeTagId::new("timeseries").into(), 1);
+        let mut inbounds = vec![channel.receiver(&who)];
+        let mut sender = channel.sender();
+
+        // A slow outbound lets the channel fill up, with one record in four of low priority
+        let mut sent = 0;
+        while sender.occupancy() < 1.0 {
+            let priority = match sent % 4 {
+                0 => Priority::Low,
+                1 => Priority::High,
+                _ => Priority::Normal,
+            };
+            sender.send(record(sent, Some(priority))).await.unwrap();
+            sent += 1;
+        }
+
+        let mut shedder = shedder("shed_low_above = 0.5\nshed_normal_above = 1.0");
+        // The first few records of the backlog
+        let mut records = Vec::new();
+        for _ in 0..4 {
+            records.push(inbounds[0].try_recv().unwrap());
+        }
+        let occupancy = crate::utils::recv::occupancy(&inbounds);
+        assert!((0.5..1.0).contains(&occupancy), "{}", occupancy);
+        shedder.retain(&mut records, occupancy);
+
+        assert_eq!(records.len(), 3);
+        assert!(records.iter().all(|r| r.priority() != Priority::Low));
+    }
+}
@@ -1,25 +1,48 @@
+use std::path::{Path, PathBuf};
+
 use async_trait::async_trait;
-use log::error;
 use tokio::io::{AsyncWrite, AsyncWriteExt};
 use tokio_util::sync::CancellationToken;
 
 use crate::{
-    config::outbound::stdio::{Io, StdioOutboundConfig},
+    actor_error, actor_info,
+    config::outbound::{
+        stdio::{StdioOutboundConfig, Target},
+        StableOrderConfig,
+    },
     core::{
         actor::Actor,
+        dead_letter::DeadLetter,
         manager::{ChannelGraph, TaggedReceiver},
         tag::{HasTag, TagId},
+        types::Record,
     },
     utils::recv::recv_batch,
 };
 
-use super::base::Outbound;
+use super::{base::Outbound, format::RecordFormatter, order::sort_records, shed::LoadShedder};
+
+// With a stable order, records are held until the inbounds are idle or this many are buffered.
+const MAX_ORDERED_BUFFER: usize = 4096;
+
+type Writer = tokio::io::BufWriter<Box<dyn AsyncWrite + Send + Unpin>>;
 
 pub struct StdioOutbound {
     tag: TagId,
 
-    io: tokio::io::BufWriter<Box<dyn AsyncWrite + Send + Unpin>>,
+    io: Writer,
+    // The path of a file target, with the identity of the file being written
+    file: Option<(PathBuf, u64)>,
+    formatter: RecordFormatter,
     inbounds: Vec<TaggedReceiver>,
+    shedder: Option<LoadShedder>,
+
+    order: StableOrderConfig,
+    buffer: Vec<Record>,
+    dead_letter: DeadLetter,
+
+    batch_size: usize,
+    max_batch_latency: std::time::Duration,
 }
 
 impl HasTag for StdioOutbound {
@@ -32,6 +55,30 @@ impl StdioOutbound {
     pub fn try_create_from(
         cfg: StdioOutboundConfig,
         channels: &mut ChannelGraph,
+    ) -> super::Result<Self> {
+        let (io, file): (Box<dyn AsyncWrite + Send + Unpin>, _) = match cfg.target {
+            Target::Stdout => (Box::new(tokio::io::stdout()), None),
+            Target::Stderr => (Box::new(tokio::io::stderr()), None),
+            Target::File(_) if channels.is_dry_run() => (Box::new(tokio::io::sink()), None),
+            Target::File(ref path) => {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                let (file, id) = open(path)?;
+                (Box::new(file), Some((path.clone(), id)))
+            }
+        };
+
+        let mut outbound = Self::with_writer(cfg, channels, io)?;
+        outbound.file = file;
+        Ok(outbound)
+    }
+
+    /// Write to `io` instead of the standard stream of the config.
+    pub fn with_writer(
+        cfg: StdioOutboundConfig,
+        channels: &mut ChannelGraph,
+        io: Box<dyn AsyncWrite + Send + Unpin>,
     ) -> super::Result<Self> {
         let tag = cfg.tag.into();
         let inbounds = cfg
@@ -39,44 +86,169 @@ impl StdioOutbound {
             .iter()
             .map(|inbound| channels.recv_from(inbound, &tag))
             .collect::<Vec<_>>();
+        let dead_letter = channels.dead_letter(&tag);
+
+        let shedder = LoadShedder::from_config(&tag, &cfg.shedding);
+        Ok(StdioOutbound {
+            tag,
+            dead_letter,
+            io: tokio::io::BufWriter::new(io),
+            file: None,
+            formatter: RecordFormatter::new(cfg.format, cfg.fields, cfg.color),
+            inbounds,
+            shedder,
+            order: cfg.order,
+            buffer: Vec::new(),
+            batch_size: cfg.batch_size,
+            max_batch_latency: cfg.max_batch_latency.into(),
+        })
+    }
 
-        let io: tokio::io::BufWriter<Box<dyn tokio::io::AsyncWrite + Send + Unpin>> = match cfg.io {
-            Io::Stdout => tokio::io::BufWriter::new(Box::new(tokio::io::stdout())),
-            Io::Stderr => tokio::io::BufWriter::new(Box::new(tokio::io::stderr())),
+    async fn write_records(&mut self, mut records: Vec<Record>) {
+        self.reopen_if_replaced().await;
+        if self.order.stable_order {
+            sort_records(&mut records, &self.order.sort_keys);
+        }
+
+        for record in &records {
+            let s = match self.formatter.format(record) {
+                Ok(s) => s,
+                Err(e) => {
+                    actor_error!(self.tag, "record not written: {}", e);
+                    self.dead_letter.send(record.clone(), &e);
+                    continue;
+                }
+            };
+            match self.io.write_all(s.as_bytes()).await {
+                Ok(()) => record.mark_record_release(&self.tag),
+                Err(e) => actor_error!(self.tag, "failed to write record: {:?}", e),
+            }
+        }
+
+        self.flush().await;
+    }
+
+    /// Write out the held records, and whatever the writer buffers.
+    async fn flush_buffer(&mut self) {
+        if !self.buffer.is_empty() {
+            let records = std::mem::take(&mut self.buffer);
+            self.write_records(records).await;
+        }
+        self.flush().await;
+    }
+
+    /// Flush the writer, once per batch.
+    async fn flush(&mut self) {
+        if let Err(e) = self.io.flush().await {
+            actor_error!(self.tag, "failed to flush: {:?}", e);
+        }
+    }
+
+    /// Open a file target again if it was moved or removed since the last batch, which was
+    /// written to the old file.
+    async fn reopen_if_replaced(&mut self) {
+        let Some((ref path, id)) = self.file else {
+            return;
+        };
+        let replaced = match std::fs::metadata(path) {
+            Ok(metadata) => file_id(&metadata) != id,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => true,
+            Err(e) => {
+                actor_error!(self.tag, "failed to check {:?}: {:?}", path, e);
+                false
+            }
         };
+        if !replaced {
+            return;
+        }
 
-        Ok(StdioOutbound { tag, io, inbounds })
+        let path = path.clone();
+        match open(&path) {
+            Ok((file, id)) => {
+                if let Err(e) = self.io.shutdown().await {
+                    actor_error!(self.tag, "failed to close {:?}: {:?}", path, e);
+                }
+                self.io = tokio::io::BufWriter::new(Box::new(file));
+                actor_info!(self.tag, "reopened {:?}", path);
+                self.file = Some((path, id));
+            }
+            Err(e) => actor_error!(self.tag, "failed to reopen {:?}: {:?}", path, e),
+        }
+    }
+
+    /// Write out everything and close the writer, once cancelled.
+    async fn close(&mut self) {
+        self.flush_buffer().await;
+        if let Err(e) = self.io.shutdown().await {
+            actor_error!(self.tag, "failed to close: {:?}", e);
+        }
     }
 }
 
+fn open(path: &Path) -> std::io::Result<(tokio::fs::File, u64)> {
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    let id = file_id(&file.metadata()?);
+    Ok((tokio::fs::File::from_std(file), id))
+}
+
+/// Tells a file apart from the one replacing it at the same path.
+#[cfg(unix)]
+fn file_id(metadata: &std::fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.ino()
+}
+
+/// Only a removed file is noticed without inodes.
+#[cfg(not(unix))]
+fn file_id(_: &std::fs::Metadata) -> u64 {
+    0
+}
+
 #[async_trait]
 impl Actor for StdioOutbound {
     type Error = super::Error;
 
     async fn poll(&mut self, ctx: CancellationToken) -> super::Result<()> {
         let tag = self.tag.clone();
+        let batch_size = self.batch_size;
+        let max_batch_latency = self.max_batch_latency;
 
-        let records = match recv_batch(
+        let mut records = match recv_batch(
             &tag,
             self.inbounds(),
             Some(std::time::Duration::from_millis(100)),
-            16,
+            batch_size,
+            max_batch_latency,
             ctx,
         )
         .await
         {
             Ok(records) => records,
             Err(crate::utils::recv::Error::Timeout) => {
+                self.flush_buffer().await;
+                return Ok(());
+            }
+            Err(crate::utils::recv::Error::Canceled) => {
+                self.close().await;
                 return Ok(());
             }
             Err(e) => return Err(e.into()),
         };
+        if let Some(ref mut shedder) = self.shedder {
+            shedder.retain(&mut records, crate::utils::recv::occupancy(&self.inbounds));
+        }
 
-        for record in &records {
-            let s = record.to_string();
-            if let Err(e) = self.io.write_all(s.as_bytes()).await {
-                error!("{}: failed to write record: {:?}", tag, e);
-            }
+        if !self.order.stable_order {
+            self.write_records(records).await;
+            return Ok(());
+        }
+
+        self.buffer.extend(records);
+        if self.buffer.len() >= MAX_ORDERED_BUFFER {
+            self.flush_buffer().await;
         }
 
         Ok(())
@@ -88,3 +260,75 @@ impl Outbound for StdioOutbound {
         &mut self.inbounds
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{inbound::InboundConfig, OutboundConfig},
+        core::{tag::InboundTagId, types::Symbol, types::Value},
+        utils::tracing::TracingContext,
+    };
+
+    fn record(i: i64) -> Record {
+        let mut record = Record::new(TracingContext::new_root());
+        record.set(Symbol::new("name"), Value::from("cpu"));
+        record.set(Symbol::new("value"), Value::from(i));
+        record
+    }
+
+    fn lines(path: &Path) -> Vec<String> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_file_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("logs/out.log");
+        let rotated = dir.path().join("logs/out.log.1");
+
+        let inbound: InboundConfig = toml::from_str(
+            "type = \"tcp\"\ntag = \"a\"\naddress = \"127.0.0.1:0\"\nprotocol = \"json\"",
+        )
+        .unwrap();
+        let outbound: OutboundConfig = toml::from_str(&format!(
+            "type = \"stdio\"\ninbounds = [\"inbound:a\"]\nformat = \"logfmt\"\ntarget = {{ file = \"{}\" }}",
+            path.display()
+        ))
+        .unwrap();
+        let mut graph =
+            ChannelGraph::try_create_from(&[inbound], &[], std::slice::from_ref(&outbound))
+                .unwrap();
+        let OutboundConfig::Stdio(cfg) = outbound else {
+            unreachable!()
+        };
+        let mut sender = graph.sender(&InboundTagId::new("a").into());
+        let mut outbound = StdioOutbound::try_create_from(cfg, &mut graph).unwrap();
+        graph.seal();
+
+        sender.try_send(record(1)).unwrap();
+        outbound.poll(CancellationToken::new()).await.unwrap();
+        assert_eq!(lines(&path), vec!["name=cpu value=1"]);
+
+        // Moved away, the next batch goes to a new file
+        std::fs::rename(&path, &rotated).unwrap();
+        sender.try_send(record(2)).unwrap();
+        outbound.poll(CancellationToken::new()).await.unwrap();
+        assert_eq!(lines(&rotated), vec!["name=cpu value=1"]);
+        assert_eq!(lines(&path), vec!["name=cpu value=2"]);
+
+        // Removed
+        std::fs::remove_file(&path).unwrap();
+        sender.try_send(record(3)).unwrap();
+        outbound.poll(CancellationToken::new()).await.unwrap();
+        assert_eq!(lines(&path), vec!["name=cpu value=3"]);
+
+        let ctx = CancellationToken::new();
+        ctx.cancel();
+        outbound.poll(ctx).await.unwrap();
+    }
+}
@@ -0,0 +1,401 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, TimeDelta, Utc};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    actor_debug, actor_warn,
+    config::pipe::aggregate::{AggregatePipeConfig, Aggregation},
+    core::{
+        actor::Actor,
+        manager::{ChannelGraph, SendError, TaggedReceiver, TaggedSender},
+        metrics,
+        tag::{HasTag, TagId},
+        types::{Attribute, Record, Value},
+    },
+    utils::recv::{self, recv_batch},
+};
+
+use super::{
+    timeseries::{NAME_FIELD_STR, VALUE_FIELD_STR},
+    Pipe, LABELS_FIELD, METRIC_TYPE_FIELD, NAME_FIELD, RECORD_TYPE_TIMESERIES_VALUE,
+    TIMESTAMP_FIELD, VALUE_FIELD,
+};
+
+/// The name and the kept labels of a series, labels sorted by key.
+type GroupKey = (String, Vec<(Value, Value)>);
+
+#[derive(Debug, Default)]
+struct Group {
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+    // Only kept when a percentile is asked for
+    values: Vec<f64>,
+}
+
+impl Group {
+    fn observe(&mut self, value: f64, keep: bool) {
+        if self.count == 0 {
+            self.min = value;
+            self.max = value;
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        self.count += 1;
+        self.sum += value;
+        if keep {
+            self.values.push(value);
+        }
+    }
+
+    fn aggregate(&mut self, aggregation: Aggregation) -> f64 {
+        match aggregation {
+            Aggregation::Sum => self.sum,
+            Aggregation::Avg => self.sum / self.count as f64,
+            Aggregation::Min => self.min,
+            Aggregation::Max => self.max,
+            Aggregation::Count => self.count as f64,
+            Aggregation::Percentile(p) => {
+                self.values.sort_by(f64::total_cmp);
+                // Nearest rank
+                let rank = (p as f64 / 100.0 * self.values.len() as f64).ceil() as usize;
+                self.values[rank.clamp(1, self.values.len()) - 1]
+            }
+        }
+    }
+}
+
+/// Tumbling windows aligned to multiples of `window`, by arrival time.
+#[derive(Debug)]
+struct Aggregator {
+    group_by: Option<Vec<Value>>,
+    aggregations: Vec<Aggregation>,
+    keep_values: bool,
+    window: TimeDelta,
+
+    // Keyed by the end of the window
+    windows: BTreeMap<DateTime<Utc>, HashMap<GroupKey, Group>>,
+}
+
+impl Aggregator {
+    fn new(cfg: &AggregatePipeConfig) -> Self {
+        Self {
+            group_by: cfg
+                .group_by
+                .as_ref()
+                .map(|labels| labels.iter().map(Value::from).collect()),
+            aggregations: cfg.aggregations.clone(),
+            keep_values: cfg
+                .aggregations
+                .iter()
+                .any(|a| matches!(a, Aggregation::Percentile(_))),
+            window: TimeDelta::from_std(cfg.window.get()).unwrap_or(TimeDelta::MAX),
+            windows: BTreeMap::new(),
+        }
+    }
+
+    fn window_end(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let window = self.window.num_milliseconds().max(1);
+        let start = now.timestamp_millis().div_euclid(window) * window;
+        DateTime::from_timestamp_millis(start + window).unwrap_or(DateTime::<Utc>::MAX_UTC)
+    }
+
+    fn push(&mut self, record: &Record, now: DateTime<Utc>) -> super::Result<()> {
+        let name = record
+            .get(&NAME_FIELD)
+            .ok_or(super::Error::FieldNotFound(NAME_FIELD_STR))?
+            .string()?
+            .to_string();
+        let value = record
+            .get(&VALUE_FIELD)
+            .ok_or(super::Error::FieldNotFound(VALUE_FIELD_STR))?
+            .cast_float()?
+            .float()?
+            .value();
+
+        let mut labels = match record.get(&LABELS_FIELD) {
+            Some(labels) => labels
+                .map()?
+                .as_hashmap()
+                .iter()
+                .filter(|(k, _)| self.group_by.as_ref().is_none_or(|keep| keep.contains(k)))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect::<Vec<_>>(),
+            None => Vec::new(),
+        };
+        labels.sort_by_cached_key(|(k, _)| k.to_string());
+
+        let end = self.window_end(now);
+        self.windows
+            .entry(end)
+            .or_default()
+            .entry((name, labels))
+            .or_default()
+            .observe(value, self.keep_values);
+
+        Ok(())
+    }
+
+    /// Emit the windows which have ended by `now`.
+    fn drain(&mut self, now: DateTime<Utc>) -> Vec<Record> {
+        let open = self.windows.split_off(&(now + TimeDelta::milliseconds(1)));
+        let closed = std::mem::replace(&mut self.windows, open);
+        self.emit(closed)
+    }
+
+    /// Emit every window, ended or not.
+    fn flush(&mut self) -> Vec<Record> {
+        let windows = std::mem::take(&mut self.windows);
+        self.emit(windows)
+    }
+
+    fn emit(&self, windows: BTreeMap<DateTime<Utc>, HashMap<GroupKey, Group>>) -> Vec<Record> {
+        let mut records = Vec::new();
+        for (end, groups) in windows {
+            let mut groups = groups.into_iter().collect::<Vec<_>>();
+            groups.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+
+            for ((name, labels), mut group) in groups {
+                let labels = Value::from(labels);
+                for aggregation in &self.aggregations {
+                    let mut record = Record::empty();
+                    record.set(
+                        NAME_FIELD.clone(),
+                        Value::from(format!("{}_{}", name, aggregation)),
+                    );
+                    record.set(METRIC_TYPE_FIELD.clone(), Value::from("gauge"));
+                    record.set(TIMESTAMP_FIELD.clone(), Value::from(end));
+                    record.set(
+                        VALUE_FIELD.clone(),
+                        Value::from(group.aggregate(*aggregation)),
+                    );
+                    record.set(LABELS_FIELD.clone(), labels.clone());
+                    record.set_attribute(Attribute::Type, RECORD_TYPE_TIMESERIES_VALUE.clone());
+                    records.push(record);
+                }
+            }
+        }
+
+        records
+    }
+}
+
+/// Pre-aggregates timeseries records, e.g. per-request latencies before remote write.
+pub struct AggregatePipe {
+    tag: TagId,
+    aggregator: Aggregator,
+
+    inbounds: Vec<TaggedReceiver>,
+    outbound: TaggedSender,
+
+    interval: Duration,
+    buffer_size: usize,
+    max_batch_latency: Duration,
+}
+
+impl AggregatePipe {
+    pub fn try_create_from(
+        cfg: AggregatePipeConfig,
+        channels: &mut ChannelGraph,
+    ) -> super::Result<Self> {
+        let tag: TagId = (&cfg.tag).into();
+        let inbounds = cfg
+            .inbounds
+            .iter()
+            .map(|inbound| channels.recv_from(inbound, &tag))
+            .collect::<Vec<_>>();
+        let outbound = channels.sender(&tag);
+
+        Ok(Self::new(cfg, inbounds, outbound))
+    }
+
+    fn new(
+        cfg: AggregatePipeConfig,
+        inbounds: Vec<TaggedReceiver>,
+        outbound: TaggedSender,
+    ) -> Self {
+        AggregatePipe {
+            tag: cfg.tag.clone().into(),
+            aggregator: Aggregator::new(&cfg),
+            inbounds,
+            outbound,
+            interval: cfg.recv_timeout.into(),
+            buffer_size: cfg.recv_buffer_size,
+            max_batch_latency: cfg.max_batch_latency.into(),
+        }
+    }
+
+    async fn send(&mut self, records: Vec<Record>) {
+        if !records.is_empty() {
+            actor_debug!(self.tag, "emitting {} aggregated records", records.len());
+        }
+
+        for mut record in records {
+            record.set_attribute(Attribute::Inbound, (&self.tag).into());
+            if let Err(e @ SendError::Closed(_)) = self.outbound.send(record).await {
+                actor_warn!(self.tag, "error sending record: {}", e);
+            }
+        }
+    }
+}
+
+impl HasTag for AggregatePipe {
+    fn tag(&self) -> &TagId {
+        &self.tag
+    }
+}
+
+#[async_trait]
+impl Actor for AggregatePipe {
+    type Error = super::Error;
+
+    async fn poll(&mut self, ctx: CancellationToken) -> super::Result<()> {
+        let tag = self.tag.clone();
+        let records = match recv_batch(
+            &tag,
+            &mut self.inbounds,
+            Some(self.interval),
+            self.buffer_size,
+            self.max_batch_latency,
+            ctx,
+        )
+        .await
+        {
+            Ok(records) => records,
+            Err(recv::Error::Timeout) => Vec::new(),
+            Err(recv::Error::Canceled) => {
+                let records = self.aggregator.flush();
+                self.send(records).await;
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let now = Utc::now();
+        for record in &records {
+            if let Err(e) = self.aggregator.push(record, now) {
+                actor_warn!(self.tag, "record not aggregated: {}", e);
+                metrics::count_transform_error(&self.tag);
+            }
+        }
+
+        let records = self.aggregator.drain(now);
+        self.send(records).await;
+
+        Ok(())
+    }
+}
+
+impl Pipe for AggregatePipe {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{manager::ActorChannel, tag::PipeTagId, types::Symbol};
+
+    fn config(aggregations: &str) -> AggregatePipeConfig {
+        toml::from_str(&format!(
+            "inbounds = [\"pipe:timeseries\"]\nwindow = \"10s\"\ngroup_by = [\"route\"]\naggregations = [{}]",
+            aggregations
+        ))
+        .unwrap()
+    }
+
+    fn sample(route: &str, instance: i64, value: f64) -> Record {
+        let mut record = Record::empty();
+        record.set(NAME_FIELD.clone(), Value::from("latency"));
+        record.set(VALUE_FIELD.clone(), Value::from(value));
+        record.set(
+            LABELS_FIELD.clone(),
+            Value::from(vec![
+                (Symbol::new("route"), Value::from(route)),
+                (Symbol::new("instance"), Value::from(instance.to_string())),
+            ]),
+        );
+        record
+    }
+
+    fn at(millis: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp_millis(millis).unwrap()
+    }
+
+    fn find<'a>(records: &'a [Record], name: &str, route: &str) -> &'a Record {
+        records
+            .iter()
+            .find(|r| {
+                r[&NAME_FIELD] == Value::from(name)
+                    && r[&LABELS_FIELD].map().unwrap().as_hashmap()[&Value::from("route")]
+                        == Value::from(route)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_windows() {
+        let mut aggregator = Aggregator::new(&config(
+            "\"sum\", \"avg\", \"min\", \"max\", \"count\", \"p95\"",
+        ));
+
+        // 10k records over 10s, two routes, spread over many instances
+        for i in 0..10_000 {
+            let route = if i % 2 == 0 { "/a" } else { "/b" };
+            let record = sample(route, i % 100, (i % 100) as f64);
+            aggregator.push(&record, at(i)).unwrap();
+        }
+        assert!(aggregator.drain(at(9_999)).is_empty());
+
+        let records = aggregator.drain(at(10_000));
+        // One record per route and aggregation
+        assert_eq!(records.len(), 2 * 6);
+        for record in &records {
+            assert_eq!(record[&TIMESTAMP_FIELD], Value::from(at(10_000)));
+            assert_eq!(record[&LABELS_FIELD].map().unwrap().as_hashmap().len(), 1);
+        }
+
+        let value = |name: &str, route: &str| find(&records, name, route)[&VALUE_FIELD].clone();
+        assert_eq!(value("latency_count", "/a"), Value::from(5_000.0));
+        assert_eq!(value("latency_sum", "/a"), Value::from(245_000.0));
+        assert_eq!(value("latency_avg", "/b"), Value::from(50.0));
+        assert_eq!(value("latency_min", "/b"), Value::from(1.0));
+        assert_eq!(value("latency_max", "/a"), Value::from(98.0));
+        assert_eq!(value("latency_p95", "/a"), Value::from(94.0));
+
+        // The next window starts empty
+        aggregator.push(&sample("/a", 0, 1.0), at(10_001)).unwrap();
+        assert!(aggregator.drain(at(10_001)).is_empty());
+        let records = aggregator.flush();
+        assert_eq!(records.len(), 6);
+        assert_eq!(records[0][&TIMESTAMP_FIELD], Value::from(at(20_000)));
+    }
+
+    #[tokio::test]
+    async fn test_flush_on_cancel() {
+        let cfg = config("\"count\"");
+        let tag: TagId = (&cfg.tag).into();
+
+        let mut input = ActorChannel::new(PipeTagId::new("timeseries").into(), 16);
+        let mut output = ActorChannel::new(tag.clone(), 16);
+        let mut received = output.receiver(&PipeTagId::new("next").into());
+        let mut pipe = AggregatePipe::new(cfg, vec![input.receiver(&tag)], output.sender());
+
+        let mut sender = input.sender();
+        sender.send(sample("/a", 0, 1.0)).await.unwrap();
+        sender.send(sample("/a", 1, 2.0)).await.unwrap();
+        pipe.poll(CancellationToken::new()).await.unwrap();
+        assert!(received.try_recv().is_err());
+
+        let ctx = CancellationToken::new();
+        ctx.cancel();
+        pipe.poll(ctx).await.unwrap();
+        let record = received.try_recv().unwrap();
+        assert_eq!(record[&NAME_FIELD], Value::from("latency_count"));
+        assert_eq!(record[&VALUE_FIELD], Value::from(2.0));
+    }
+}
@@ -0,0 +1,464 @@
+mod window;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    actor_debug, actor_warn,
+    config::pipe::dedup::DedupPipeConfig,
+    core::{
+        actor::Actor,
+        manager::{ChannelGraph, SendError, TaggedReceiver, TaggedSender},
+        metrics,
+        tag::{HasTag, TagId},
+        types::{Attribute, Record, Value},
+    },
+    utils::{
+        recv::{self, recv_batch},
+        stats::GLOBAL_STATS,
+    },
+};
+
+use super::{
+    timeseries::{NAME_FIELD_STR, VALUE_FIELD_STR},
+    Pipe, LABELS_FIELD, NAME_FIELD, VALUE_FIELD,
+};
+use window::WindowDeduplicator;
+
+/// The name and the hash of the labels of a series.
+type SeriesKey = (String, u64);
+
+#[derive(Debug)]
+struct Series {
+    value: Value,
+    last_emit: Instant,
+    // The latest sample dropped since `last_emit`, emitted when the series is forgotten
+    suppressed: Option<Record>,
+    // Position in `Deduplicator::recency`
+    seen: u64,
+}
+
+#[derive(Debug)]
+struct Deduplicator {
+    max_suppress: Duration,
+    max_series: usize,
+
+    series: HashMap<SeriesKey, Series>,
+    // Least recently seen series first
+    recency: BTreeMap<u64, SeriesKey>,
+    clock: u64,
+}
+
+impl Deduplicator {
+    fn new(cfg: &DedupPipeConfig) -> Self {
+        Self {
+            max_suppress: cfg.max_suppress_duration.get(),
+            max_series: cfg.max_series,
+            series: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// Push the records to emit to `out`: the sample itself unless it repeats the previous
+    /// value of its series, and the pending sample of a series evicted to make room.
+    fn push(&mut self, record: Record, now: Instant, out: &mut Vec<Record>) -> super::Result<()> {
+        let name = record
+            .get(&NAME_FIELD)
+            .ok_or(super::Error::FieldNotFound(NAME_FIELD_STR))?
+            .string()?
+            .to_string();
+        let value = record
+            .get(&VALUE_FIELD)
+            .ok_or(super::Error::FieldNotFound(VALUE_FIELD_STR))?
+            .clone();
+        let labels = record.get(&LABELS_FIELD).map_or(0, Value::content_hash);
+        let key = (name, labels);
+
+        self.clock += 1;
+        let seen = self.clock;
+
+        if let Some(series) = self.series.get_mut(&key) {
+            self.recency.remove(&series.seen);
+            self.recency.insert(seen, key);
+            series.seen = seen;
+
+            if series.value == value && now.duration_since(series.last_emit) < self.max_suppress {
+                series.suppressed = Some(record);
+            } else {
+                series.value = value;
+                series.last_emit = now;
+                series.suppressed = None;
+                out.push(record);
+            }
+            return Ok(());
+        }
+
+        if self.series.len() >= self.max_series {
+            self.evict(out);
+        }
+        self.recency.insert(seen, key.clone());
+        self.series.insert(
+            key,
+            Series {
+                value,
+                last_emit: now,
+                suppressed: None,
+                seen,
+            },
+        );
+        out.push(record);
+
+        Ok(())
+    }
+
+    fn evict(&mut self, out: &mut Vec<Record>) {
+        let Some((_, key)) = self.recency.pop_first() else {
+            return;
+        };
+        if let Some(record) = self.series.remove(&key).and_then(|s| s.suppressed) {
+            out.push(record);
+        }
+    }
+
+    /// Forget every series, emitting the samples dropped since their last emitted one.
+    fn flush(&mut self) -> Vec<Record> {
+        self.recency.clear();
+        let mut series = std::mem::take(&mut self.series)
+            .into_values()
+            .filter_map(|s| s.suppressed.map(|record| (s.seen, record)))
+            .collect::<Vec<_>>();
+        series.sort_by_key(|(seen, _)| *seen);
+        series.into_iter().map(|(_, record)| record).collect()
+    }
+}
+
+enum Mode {
+    Series(Deduplicator),
+    Window(WindowDeduplicator),
+}
+
+/// Drops unchanged consecutive samples of each series, or the records whose key was already
+/// seen within a window.
+pub struct DedupPipe {
+    tag: TagId,
+    mode: Mode,
+
+    inbounds: Vec<TaggedReceiver>,
+    outbound: TaggedSender,
+
+    interval: Duration,
+    buffer_size: usize,
+    max_batch_latency: Duration,
+}
+
+impl DedupPipe {
+    pub fn try_create_from(
+        cfg: DedupPipeConfig,
+        channels: &mut ChannelGraph,
+    ) -> super::Result<Self> {
+        let tag: TagId = (&cfg.tag).into();
+        let inbounds = cfg
+            .inbounds
+            .iter()
+            .map(|inbound| channels.recv_from(inbound, &tag))
+            .collect::<Vec<_>>();
+        let outbound = channels.sender(&tag);
+
+        Ok(Self::new(cfg, inbounds, outbound))
+    }
+
+    fn new(cfg: DedupPipeConfig, inbounds: Vec<TaggedReceiver>, outbound: TaggedSender) -> Self {
+        let mode = match cfg.key {
+            Some(ref key) => Mode::Window(WindowDeduplicator::new(
+                key.clone(),
+                cfg.window.get(),
+                cfg.max_keys,
+            )),
+            None => Mode::Series(Deduplicator::new(&cfg)),
+        };
+
+        DedupPipe {
+            tag: cfg.tag.clone().into(),
+            mode,
+            inbounds,
+            outbound,
+            interval: cfg.recv_timeout.into(),
+            buffer_size: cfg.recv_buffer_size,
+            max_batch_latency: cfg.max_batch_latency.into(),
+        }
+    }
+
+    async fn send(&mut self, records: Vec<Record>) {
+        for mut record in records {
+            record.set_attribute(Attribute::Inbound, (&self.tag).into());
+            if let Err(e @ SendError::Closed(_)) = self.outbound.send(record).await {
+                actor_warn!(self.tag, "error sending record: {}", e);
+            }
+        }
+    }
+}
+
+impl HasTag for DedupPipe {
+    fn tag(&self) -> &TagId {
+        &self.tag
+    }
+}
+
+#[async_trait]
+impl Actor for DedupPipe {
+    type Error = super::Error;
+
+    async fn poll(&mut self, ctx: CancellationToken) -> super::Result<()> {
+        let tag = self.tag.clone();
+        let records = match recv_batch(
+            &tag,
+            &mut self.inbounds,
+            Some(self.interval),
+            self.buffer_size,
+            self.max_batch_latency,
+            ctx,
+        )
+        .await
+        {
+            Ok(records) => records,
+            Err(recv::Error::Timeout) => return Ok(()),
+            Err(recv::Error::Canceled) => {
+                if let Mode::Series(ref mut dedup) = self.mode {
+                    let records = dedup.flush();
+                    self.send(records).await;
+                }
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let now = Instant::now();
+        let received = records.len();
+        let mut out = Vec::with_capacity(received);
+        // Duplicates by the inbound they came from
+        let mut duplicates = HashMap::<String, u64>::new();
+        for record in records {
+            let result = match self.mode {
+                Mode::Series(ref mut dedup) => dedup.push(record, now, &mut out),
+                Mode::Window(ref mut dedup) => match dedup.is_duplicate(&record, now) {
+                    Ok(true) => {
+                        let inbound = record
+                            .get_attribute(&Attribute::Inbound)
+                            .map_or_else(|| "unknown".to_string(), Value::to_string);
+                        *duplicates.entry(inbound).or_default() += 1;
+                        Ok(())
+                    }
+                    Ok(false) => {
+                        out.push(record);
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                },
+            };
+            if let Err(e) = result {
+                actor_warn!(self.tag, "record not deduplicated: {}", e);
+                metrics::count_transform_error(&self.tag);
+            }
+        }
+
+        for (inbound, count) in duplicates {
+            GLOBAL_STATS.incr(&format!("{} duplicates from {}", self.tag, inbound), count);
+        }
+
+        actor_debug!(
+            self.tag,
+            "{} of {} records unchanged or duplicated",
+            received.saturating_sub(out.len()),
+            received
+        );
+        self.send(out).await;
+
+        Ok(())
+    }
+}
+
+impl Pipe for DedupPipe {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Verify,
+        core::{manager::ActorChannel, tag::PipeTagId, types::Symbol},
+    };
+
+    fn config(body: &str) -> DedupPipeConfig {
+        toml::from_str(&format!("inbounds = [\"pipe:timeseries\"]\n{}", body)).unwrap()
+    }
+
+    fn sample(name: &str, host: &str, value: f64) -> Record {
+        let mut record = Record::empty();
+        record.set(NAME_FIELD.clone(), Value::from(name));
+        record.set(VALUE_FIELD.clone(), Value::from(value));
+        record.set(
+            LABELS_FIELD.clone(),
+            Value::from(vec![(Symbol::new("host"), Value::from(host))]),
+        );
+        record
+    }
+
+    fn push(dedup: &mut Deduplicator, record: Record, now: Instant) -> Vec<Record> {
+        let mut out = Vec::new();
+        dedup.push(record, now, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_value_change() {
+        let mut dedup = Deduplicator::new(&config(""));
+        let now = Instant::now();
+
+        assert_eq!(push(&mut dedup, sample("up", "a", 1.0), now).len(), 1);
+        assert!(push(&mut dedup, sample("up", "a", 1.0), now).is_empty());
+        // Other series are tracked on their own
+        assert_eq!(push(&mut dedup, sample("up", "b", 1.0), now).len(), 1);
+        assert_eq!(push(&mut dedup, sample("down", "a", 1.0), now).len(), 1);
+
+        let out = push(&mut dedup, sample("up", "a", 0.0), now);
+        assert_eq!(out[0][&VALUE_FIELD], Value::from(0.0));
+        assert!(push(&mut dedup, sample("up", "a", 0.0), now).is_empty());
+        // Flapping back is a change too
+        assert_eq!(push(&mut dedup, sample("up", "a", 1.0), now).len(), 1);
+
+        let mut record = sample("up", "a", 1.0);
+        record.set(VALUE_FIELD.clone(), Value::Null);
+        let mut out = Vec::new();
+        assert!(dedup.push(Record::empty(), now, &mut out).is_err());
+        assert_eq!(push(&mut dedup, record, now).len(), 1);
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        let mut dedup = Deduplicator::new(&config("max_suppress_duration = \"10s\""));
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        assert_eq!(push(&mut dedup, sample("up", "a", 1.0), at(0)).len(), 1);
+        assert!(push(&mut dedup, sample("up", "a", 1.0), at(5)).is_empty());
+        assert!(push(&mut dedup, sample("up", "a", 1.0), at(9)).is_empty());
+        // Re-emitted once 10s passed since the last emitted sample
+        assert_eq!(push(&mut dedup, sample("up", "a", 1.0), at(10)).len(), 1);
+        assert!(push(&mut dedup, sample("up", "a", 1.0), at(19)).is_empty());
+        assert_eq!(push(&mut dedup, sample("up", "a", 1.0), at(20)).len(), 1);
+
+        // A change restarts the period
+        assert_eq!(push(&mut dedup, sample("up", "a", 2.0), at(25)).len(), 1);
+        assert!(push(&mut dedup, sample("up", "a", 2.0), at(30)).is_empty());
+        assert_eq!(push(&mut dedup, sample("up", "a", 2.0), at(35)).len(), 1);
+    }
+
+    #[test]
+    fn test_eviction() {
+        let mut dedup = Deduplicator::new(&config("max_series = 2"));
+        let now = Instant::now();
+
+        push(&mut dedup, sample("m", "a", 1.0), now);
+        push(&mut dedup, sample("m", "b", 1.0), now);
+        // Seeing `a` again makes `b` the least recently seen
+        assert!(push(&mut dedup, sample("m", "a", 1.0), now).is_empty());
+        assert!(push(&mut dedup, sample("m", "a", 1.0), now).is_empty());
+
+        // `b` is forgotten, nothing pending for it
+        assert_eq!(push(&mut dedup, sample("m", "c", 1.0), now).len(), 1);
+        assert_eq!(dedup.series.len(), 2);
+        assert_eq!(dedup.recency.len(), 2);
+        // `b` is new again and evicts `a`, whose dropped sample is emitted first
+        let out = push(&mut dedup, sample("m", "b", 1.0), now);
+        assert_eq!(out.len(), 2);
+        let host = |record: &Record| {
+            record[&LABELS_FIELD].map().unwrap().as_hashmap()[&Value::from("host")].clone()
+        };
+        assert_eq!(host(&out[0]), Value::from("a"));
+        assert_eq!(host(&out[1]), Value::from("b"));
+        assert_eq!(dedup.series.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_flush_on_cancel() {
+        let cfg = config("");
+        let tag: TagId = (&cfg.tag).into();
+
+        let mut input = ActorChannel::new(PipeTagId::new("timeseries").into(), 16);
+        let mut output = ActorChannel::new(tag.clone(), 16);
+        let mut received = output.receiver(&PipeTagId::new("next").into());
+        let mut pipe = DedupPipe::new(cfg, vec![input.receiver(&tag)], output.sender());
+
+        let mut sender = input.sender();
+        sender.send(sample("up", "a", 1.0)).await.unwrap();
+        sender.send(sample("up", "a", 1.0)).await.unwrap();
+        sender.send(sample("up", "b", 1.0)).await.unwrap();
+        pipe.poll(CancellationToken::new()).await.unwrap();
+        assert_eq!(received.try_recv().unwrap()[&NAME_FIELD], Value::from("up"));
+        assert!(received.try_recv().is_ok());
+        assert!(received.try_recv().is_err());
+
+        let ctx = CancellationToken::new();
+        ctx.cancel();
+        pipe.poll(ctx).await.unwrap();
+        // Only `a` had a sample dropped since the last one emitted
+        let record = received.try_recv().unwrap();
+        assert_eq!(
+            record[&LABELS_FIELD].map().unwrap().as_hashmap()[&Value::from("host")],
+            Value::from("a")
+        );
+        assert!(received.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_key_window() {
+        let mut cfg = config(
+            r#"
+            key = { fields = ["labels", "timestamp"], include_name = true }
+            window = "1m"
+            "#,
+        );
+        // Records lacking a key field are passed through
+        cfg.verify().unwrap();
+        let tag: TagId = (&cfg.tag).into();
+
+        let mut input = ActorChannel::new(PipeTagId::new("timeseries").into(), 16);
+        let mut output = ActorChannel::new(tag.clone(), 16);
+        let mut received = output.receiver(&PipeTagId::new("next").into());
+        let mut pipe = DedupPipe::new(cfg, vec![input.receiver(&tag)], output.sender());
+
+        let at = |mut record: Record, timestamp: i64| {
+            record.set(Symbol::new("timestamp"), Value::from(timestamp));
+            record
+        };
+        let mut sender = input.sender();
+        sender.send(at(sample("up", "a", 1.0), 1)).await.unwrap();
+        // Re-sent, even with another value
+        sender.send(at(sample("up", "a", 2.0), 1)).await.unwrap();
+        sender.send(at(sample("up", "a", 1.0), 2)).await.unwrap();
+        // No timestamp, passed through every time
+        sender.send(sample("up", "a", 1.0)).await.unwrap();
+        sender.send(sample("up", "a", 1.0)).await.unwrap();
+        pipe.poll(CancellationToken::new()).await.unwrap();
+
+        let mut timestamps = Vec::new();
+        while let Ok(record) = received.try_recv() {
+            timestamps.push(record.get(&Symbol::new("timestamp")).cloned());
+        }
+        assert_eq!(
+            timestamps,
+            [Some(Value::from(1)), Some(Value::from(2)), None, None]
+        );
+
+        // Nothing held back
+        let ctx = CancellationToken::new();
+        ctx.cancel();
+        pipe.poll(ctx).await.unwrap();
+        assert!(received.try_recv().is_err());
+    }
+}
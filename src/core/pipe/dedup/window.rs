@@ -0,0 +1,132 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::keying::KeySpec,
+    core::{keying::KeyExtractor, types::Record},
+};
+
+/// The keys seen within the window. Keys are remembered from the first time they are seen,
+/// so that they expire in the order they were inserted: both expiring and evicting pop the
+/// oldest key, without scanning the others.
+#[derive(Debug)]
+pub(super) struct WindowDeduplicator {
+    key: KeyExtractor,
+    window: Duration,
+    max_keys: usize,
+
+    seen: HashSet<u128>,
+    // Oldest first, each key of `seen` once
+    order: VecDeque<(Instant, u128)>,
+}
+
+impl WindowDeduplicator {
+    pub fn new(key: KeySpec, window: Duration, max_keys: usize) -> Self {
+        Self {
+            key: KeyExtractor::new(key),
+            window,
+            max_keys,
+            seen: HashSet::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Whether the key of the record was already seen within the window, remembering it
+    /// otherwise. The records without a key are never duplicates.
+    pub fn is_duplicate(&mut self, record: &Record, now: Instant) -> super::super::Result<bool> {
+        let Some(key) = self.key.key(record).map_err(|e| {
+            super::super::Error::InvalidRecord(format!("{}: {}", e, self.key.describe(record)))
+        })?
+        else {
+            return Ok(false);
+        };
+
+        self.expire(now);
+        if self.seen.contains(&key) {
+            return Ok(true);
+        }
+
+        if self.seen.len() >= self.max_keys {
+            if let Some((_, oldest)) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        self.seen.insert(key);
+        self.order.push_back((now, key));
+
+        Ok(false)
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some(&(first_seen, key)) = self.order.front() {
+            if now.duration_since(first_seen) < self.window {
+                break;
+            }
+            self.order.pop_front();
+            self.seen.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::types::{Symbol, Value};
+
+    fn dedup(window_secs: u64, max_keys: usize) -> WindowDeduplicator {
+        let key = toml::from_str("fields = [\"host\", \"timestamp\"]\nmissing = \"skip\"").unwrap();
+        WindowDeduplicator::new(key, Duration::from_secs(window_secs), max_keys)
+    }
+
+    fn record(host: &str, timestamp: i64) -> Record {
+        let mut record = Record::empty();
+        record.set(Symbol::new("host"), Value::from(host));
+        record.set(Symbol::new("timestamp"), Value::from(timestamp));
+        record
+    }
+
+    #[test]
+    fn test_window() {
+        let mut dedup = dedup(10, 100);
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        assert!(!dedup.is_duplicate(&record("a", 1), at(0)).unwrap());
+        assert!(dedup.is_duplicate(&record("a", 1), at(5)).unwrap());
+        assert!(!dedup.is_duplicate(&record("b", 1), at(5)).unwrap());
+        assert!(!dedup.is_duplicate(&record("a", 2), at(5)).unwrap());
+        // Seeing it again does not extend the window
+        assert!(dedup.is_duplicate(&record("a", 1), at(9)).unwrap());
+        assert!(!dedup.is_duplicate(&record("a", 1), at(10)).unwrap());
+        assert_eq!(dedup.seen.len(), 3);
+        assert_eq!(dedup.order.len(), 3);
+
+        // Everything but the key seen last expired
+        assert!(!dedup.is_duplicate(&record("c", 1), at(16)).unwrap());
+        assert_eq!(dedup.seen.len(), 2);
+
+        // No key, never a duplicate
+        let mut bare = Record::empty();
+        bare.set(Symbol::new("host"), Value::from("a"));
+        assert!(!dedup.is_duplicate(&bare, at(16)).unwrap());
+        assert!(!dedup.is_duplicate(&bare, at(16)).unwrap());
+        assert_eq!(dedup.seen.len(), 2);
+    }
+
+    #[test]
+    fn test_max_keys() {
+        let mut dedup = dedup(60, 2);
+        let now = Instant::now();
+
+        assert!(!dedup.is_duplicate(&record("a", 1), now).unwrap());
+        assert!(!dedup.is_duplicate(&record("b", 1), now).unwrap());
+        // `a` is forgotten to make room
+        assert!(!dedup.is_duplicate(&record("c", 1), now).unwrap());
+        assert!(dedup.is_duplicate(&record("b", 1), now).unwrap());
+        assert!(!dedup.is_duplicate(&record("a", 1), now).unwrap());
+        assert_eq!(dedup.seen.len(), 2);
+        assert_eq!(dedup.order.len(), 2);
+    }
+}
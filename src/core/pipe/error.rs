@@ -9,11 +9,18 @@ pub enum Error {
     InvalidRecord(String),
     #[error("Invalid action: {0}")]
     InvalidAction(String),
+    #[error("Invalid filter condition: {0}")]
+    InvalidCondition(String),
+    #[error("Invalid label mapping: {0}")]
+    InvalidMapping(String),
     #[error("Field not found: {0}")]
     FieldNotFound(&'static str),
     #[error(transparent)]
     #[diagnostic(transparent)]
-    TypeError(#[from] crate::core::types::Error),
+    Type(#[from] crate::core::types::Error),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Config(#[from] crate::config::Error),
     #[error(transparent)]
     #[diagnostic(transparent)]
     Recv(#[from] crate::utils::recv::Error),
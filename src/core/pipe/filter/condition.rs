@@ -0,0 +1,226 @@
+use std::cmp::Ordering;
+
+use regex::Regex;
+
+use crate::{
+    config::pipe::filter::{ConditionValue, FilterCondition, FilterOp},
+    core::{
+        keying::CompiledPath,
+        types::{parse_value, Attribute, Record, Value, ValueType},
+    },
+};
+
+#[derive(Debug)]
+enum Predicate {
+    Exists,
+    NotExists,
+    Compare(FilterOp, Value),
+    Contains(Value),
+    Regex(Regex),
+}
+
+/// A compiled [`FilterCondition`].
+#[derive(Debug)]
+pub struct Condition {
+    path: CompiledPath,
+    // Set when the field names an attribute, e.g. `__type__`
+    attribute: Option<Attribute>,
+    predicate: Predicate,
+}
+
+impl Condition {
+    pub fn try_create_from(cfg: FilterCondition) -> super::Result<Self> {
+        let predicate = match (cfg.op, cfg.value) {
+            (FilterOp::Exists, _) => Predicate::Exists,
+            (FilterOp::NotExists, _) => Predicate::NotExists,
+            (FilterOp::Regex, Some(ConditionValue::String(pattern))) => {
+                let regex = Regex::new(&pattern)
+                    .map_err(|e| super::Error::InvalidCondition(format!("{}: {}", cfg.field, e)))?;
+                Predicate::Regex(regex)
+            }
+            (FilterOp::Contains, Some(value)) => Predicate::Contains(value.into()),
+            (op, Some(value)) if op != FilterOp::Regex => Predicate::Compare(op, value.into()),
+            (op, _) => {
+                return Err(super::Error::InvalidCondition(format!(
+                    "{}: invalid value for {:?}",
+                    cfg.field, op
+                )))
+            }
+        };
+
+        let attribute = cfg
+            .field
+            .segments()
+            .is_empty()
+            .then(|| Attribute::parse(cfg.field.field().as_str()))
+            .flatten();
+        Ok(Self {
+            path: CompiledPath::new(cfg.field),
+            attribute,
+            predicate,
+        })
+    }
+
+    /// A missing field only matches `not_exists`.
+    pub fn matches(&self, record: &Record) -> bool {
+        let value = match self.attribute {
+            Some(ref attribute) => record.get_attribute(attribute),
+            None => self.path.lookup(record),
+        };
+        match (&self.predicate, value) {
+            (Predicate::Exists, value) => value.is_some(),
+            (Predicate::NotExists, value) => value.is_none(),
+            (_, None) => false,
+            (Predicate::Regex(regex), Some(Value::String(s))) => regex.is_match(s.as_str()),
+            (Predicate::Regex(_), Some(_)) => false,
+            (Predicate::Contains(expected), Some(value)) => match (value, expected) {
+                (Value::String(s), Value::String(part)) => s.as_str().contains(part.as_str()),
+                (Value::Array(items), expected) => items
+                    .iter()
+                    .any(|item| compare(item, expected) == Some(Ordering::Equal)),
+                (Value::Map(map), key) => map.contains_key(key),
+                _ => false,
+            },
+            (Predicate::Compare(op, expected), Some(value)) => {
+                let ordering = compare(value, expected);
+                match op {
+                    FilterOp::Eq => ordering == Some(Ordering::Equal),
+                    FilterOp::Ne => ordering != Some(Ordering::Equal),
+                    FilterOp::Gt => ordering == Some(Ordering::Greater),
+                    FilterOp::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+                    FilterOp::Lt => ordering == Some(Ordering::Less),
+                    FilterOp::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+                    FilterOp::Contains
+                    | FilterOp::Regex
+                    | FilterOp::Exists
+                    | FilterOp::NotExists => unreachable!(),
+                }
+            }
+        }
+    }
+}
+
+impl From<ConditionValue> for Value {
+    fn from(value: ConditionValue) -> Self {
+        match value {
+            ConditionValue::Bool(b) => Value::from(b),
+            ConditionValue::Int(n) => Value::from(n),
+            ConditionValue::Float(n) => Value::from(n),
+            ConditionValue::String(s) => Value::from(s),
+        }
+    }
+}
+
+fn as_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(n) => Some(n.value as f64),
+        Value::Float(n) => Some(n.value),
+        _ => None,
+    }
+}
+
+/// Ints and floats compare with each other, datetimes with the strings they parse from
+/// (e.g. `"2025-04-03T08:09:03Z"`), other values only with values of the same type.
+fn compare(value: &Value, expected: &Value) -> Option<Ordering> {
+    match (value, expected) {
+        (Value::DateTime(_), Value::String(s)) => {
+            let expected = parse_value(s.as_str(), ValueType::DateTime).ok()?;
+            value.partial_cmp(&expected)
+        }
+        _ if value.is_number() && expected.is_number() => {
+            as_f64(value)?.partial_cmp(&as_f64(expected)?)
+        }
+        _ => value.partial_cmp(expected),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::core::types::Symbol;
+
+    fn condition(toml: &str) -> Condition {
+        Condition::try_create_from(toml::from_str(toml).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_conditions() {
+        let mut record = Record::empty();
+        record.set(Symbol::new("name"), Value::from("system.cpu"));
+        record.set(Symbol::new("value"), Value::from(150i64));
+        record.set(Symbol::new("ratio"), Value::from(0.5));
+        record.set(
+            Symbol::new("tags"),
+            Value::Array(vec![Value::from(1i64), Value::from(2.0)]),
+        );
+        record.set(
+            Symbol::new("timestamp"),
+            Value::DateTime(DateTime::from_timestamp(1743667743, 0).unwrap()),
+        );
+        record.set_attribute(Attribute::Type, Value::from("metric"));
+        record.set(
+            Symbol::new("labels"),
+            [(Value::from("zone"), Value::from("b"))]
+                .into_iter()
+                .collect(),
+        );
+
+        let cases = [
+            (
+                "field = \"name\"\nop = \"regex\"\nvalue = \"^system\\\\.\"",
+                true,
+            ),
+            (
+                "field = \"name\"\nop = \"regex\"\nvalue = \"^user\\\\.\"",
+                false,
+            ),
+            ("field = \"value\"\nop = \"regex\"\nvalue = \"1\"", false),
+            ("field = \"value\"\nop = \"gt\"\nvalue = 100", true),
+            ("field = \"value\"\nop = \"le\"\nvalue = 149.5", false),
+            ("field = \"value\"\nop = \"eq\"\nvalue = 150.0", true),
+            ("field = \"ratio\"\nop = \"lt\"\nvalue = 1", true),
+            (
+                "field = \"name\"\nop = \"eq\"\nvalue = \"system.cpu\"",
+                true,
+            ),
+            ("field = \"name\"\nop = \"ne\"\nvalue = 1", true),
+            ("field = \"name\"\nop = \"gt\"\nvalue = 1", false),
+            ("field = \"labels.zone\"\nop = \"eq\"\nvalue = \"b\"", true),
+            ("field = \"labels.zone\"\nop = \"exists\"", true),
+            ("field = \"name\"\nop = \"contains\"\nvalue = \"cpu\"", true),
+            (
+                "field = \"name\"\nop = \"contains\"\nvalue = \"mem\"",
+                false,
+            ),
+            ("field = \"value\"\nop = \"contains\"\nvalue = \"1\"", false),
+            (
+                "field = \"labels\"\nop = \"contains\"\nvalue = \"zone\"",
+                true,
+            ),
+            ("field = \"tags\"\nop = \"contains\"\nvalue = 2", true),
+            ("field = \"tags\"\nop = \"contains\"\nvalue = 3", false),
+            ("field = \"labels.region\"\nop = \"not_exists\"", true),
+            ("field = \"missing\"\nop = \"ne\"\nvalue = 1", false),
+            (
+                "field = \"timestamp\"\nop = \"ge\"\nvalue = \"2025-04-03T08:09:03Z\"",
+                true,
+            ),
+            (
+                "field = \"timestamp\"\nop = \"gt\"\nvalue = \"2025-04-03T08:09:03Z\"",
+                false,
+            ),
+            // Attributes, by their name
+            (
+                "field = \"__type__\"\nop = \"eq\"\nvalue = \"metric\"",
+                true,
+            ),
+            ("field = \"__type__\"\nop = \"eq\"\nvalue = \"log\"", false),
+            ("field = \"__inbound__\"\nop = \"not_exists\"", true),
+        ];
+        for (toml, expected) in cases {
+            assert_eq!(condition(toml).matches(&record), expected, "{}", toml);
+        }
+    }
+}
@@ -0,0 +1,242 @@
+pub(super) mod condition;
+
+pub use super::{Error, Result};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    actor_debug, actor_warn,
+    config::pipe::{
+        filter::{FilterCombine, FilterMode, FilterPipeConfig},
+        Priority,
+    },
+    core::{
+        actor::Actor,
+        manager::{ChannelGraph, SendError, TaggedReceiver, TaggedSender},
+        tag::{HasTag, TagId},
+        types::{Attribute, Record},
+    },
+    utils::{recv::recv_batch, stats::GLOBAL_STATS},
+};
+
+use super::Pipe;
+use condition::Condition;
+
+/// Forwards the records matching the conditions (`keep`) or all the others (`drop`).
+pub struct FilterPipe {
+    tag: TagId,
+    conditions: Vec<Condition>,
+    combine: FilterCombine,
+    mode: FilterMode,
+    priority: Option<Priority>,
+
+    inbounds: Vec<TaggedReceiver>,
+    outbound: TaggedSender,
+
+    interval: Duration,
+    buffer_size: usize,
+    max_batch_latency: Duration,
+}
+
+impl FilterPipe {
+    pub fn try_create_from(
+        cfg: FilterPipeConfig,
+        channels: &mut ChannelGraph,
+    ) -> super::Result<Self> {
+        let tag: TagId = (&cfg.tag).into();
+        let inbounds = cfg
+            .inbounds
+            .iter()
+            .map(|inbound| channels.recv_from(inbound, &tag))
+            .collect::<Vec<_>>();
+        let outbound = channels.sender(&tag);
+
+        Self::new(cfg, inbounds, outbound)
+    }
+
+    fn new(
+        cfg: FilterPipeConfig,
+        inbounds: Vec<TaggedReceiver>,
+        outbound: TaggedSender,
+    ) -> super::Result<Self> {
+        let conditions = cfg
+            .conditions
+            .into_iter()
+            .map(Condition::try_create_from)
+            .collect::<super::Result<Vec<_>>>()?;
+
+        Ok(FilterPipe {
+            tag: cfg.tag.into(),
+            conditions,
+            combine: cfg.combine,
+            mode: cfg.mode,
+            priority: cfg.priority,
+            inbounds,
+            outbound,
+            interval: cfg.recv_timeout.into(),
+            buffer_size: cfg.recv_buffer_size,
+            max_batch_latency: cfg.max_batch_latency.into(),
+        })
+    }
+
+    fn accepts(&self, record: &Record) -> bool {
+        let matched = match self.combine {
+            FilterCombine::All => self.conditions.iter().all(|c| c.matches(record)),
+            FilterCombine::Any => self.conditions.iter().any(|c| c.matches(record)),
+        };
+        match self.mode {
+            FilterMode::Keep => matched,
+            FilterMode::Drop => !matched,
+        }
+    }
+}
+
+impl HasTag for FilterPipe {
+    fn tag(&self) -> &TagId {
+        &self.tag
+    }
+}
+
+#[async_trait]
+impl Actor for FilterPipe {
+    type Error = super::Error;
+
+    async fn poll(&mut self, ctx: CancellationToken) -> super::Result<()> {
+        let tag = self.tag.clone();
+        let records = match recv_batch(
+            &tag,
+            &mut self.inbounds,
+            Some(self.interval),
+            self.buffer_size,
+            self.max_batch_latency,
+            ctx,
+        )
+        .await
+        {
+            Ok(records) => records,
+            Err(crate::utils::recv::Error::Timeout) => {
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let total = records.len();
+        let mut discarded = 0;
+        for mut record in records {
+            if !self.accepts(&record) {
+                discarded += 1;
+                continue;
+            }
+
+            // Keep the inbound the record came from, downstream stats are keyed by it
+            record.set_attribute(Attribute::Inbound, (&self.tag).into());
+            if let Some(priority) = self.priority {
+                record.set_priority(priority);
+            }
+            if let Err(e @ SendError::Closed(_)) = self.outbound.send(record).await {
+                actor_warn!(self.tag, "error sending record: {}", e);
+            }
+        }
+
+        if discarded > 0 {
+            actor_debug!(self.tag, "discarded {} of {} records", discarded, total);
+            GLOBAL_STATS.incr(&format!("{} discarded records", self.tag), discarded);
+        }
+
+        Ok(())
+    }
+}
+
+impl Pipe for FilterPipe {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{
+        manager::ActorChannel,
+        tag::{InboundTagId, PipeTagId},
+        types::{Symbol, Value},
+    };
+
+    fn record(name: &str, inbound: Option<&str>) -> Record {
+        let mut record = Record::empty();
+        record.set(Symbol::new("name"), Value::from(name));
+        if let Some(inbound) = inbound {
+            let tag: TagId = InboundTagId::new(inbound).into();
+            record.set_attribute(Attribute::Inbound, (&tag).into());
+        }
+        record
+    }
+
+    #[tokio::test]
+    async fn test_filter_pipe() {
+        let cfg: FilterPipeConfig = toml::from_str(
+            "inbounds = [\"inbound:a\"]\nmode = \"drop\"\npriority = \"high\"\nconditions = [{ field = \"name\", op = \"regex\", value = \"^system\\\\.\" }]",
+        )
+        .unwrap();
+        let tag: TagId = (&cfg.tag).into();
+
+        let mut input = ActorChannel::new(InboundTagId::new("a").into(), 16);
+        let mut output = ActorChannel::new(tag.clone(), 16);
+        let mut received = output.receiver(&PipeTagId::new("next").into());
+        let mut pipe = FilterPipe::new(cfg, vec![input.receiver(&tag)], output.sender()).unwrap();
+
+        let mut sender = input.sender();
+        sender.send(record("system.cpu", Some("a"))).await.unwrap();
+        sender
+            .send(record("app.requests", Some("a")))
+            .await
+            .unwrap();
+        sender.send(record("app.errors", None)).await.unwrap();
+        pipe.poll(CancellationToken::new()).await.unwrap();
+
+        let first = received.recv().await.unwrap();
+        assert_eq!(
+            first.get(&Symbol::new("name")),
+            Some(&Value::from("app.requests"))
+        );
+        let inbound: TagId = InboundTagId::new("a").into();
+        assert_eq!(
+            first.get_attribute(&Attribute::Inbound),
+            Some(&(&inbound).into())
+        );
+        assert_eq!(first.priority(), Priority::High);
+
+        // Records from nowhere are attributed to the pipe
+        let second = received.recv().await.unwrap();
+        assert_eq!(
+            second.get(&Symbol::new("name")),
+            Some(&Value::from("app.errors"))
+        );
+        assert_eq!(
+            second.get_attribute(&Attribute::Inbound),
+            Some(&(&tag).into())
+        );
+        assert!(received.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_filter_any() {
+        let cfg: FilterPipeConfig = toml::from_str(
+            r#"
+            inbounds = ["inbound:a"]
+            combine = "any"
+            conditions = [
+                { field = "name", op = "contains", value = "cpu" },
+                { field = "name", op = "eq", value = "app.errors" },
+            ]
+            "#,
+        )
+        .unwrap();
+        let tag: TagId = (&cfg.tag).into();
+        let mut input = ActorChannel::new(InboundTagId::new("a").into(), 16);
+        let mut output = ActorChannel::new(tag.clone(), 16);
+        let pipe = FilterPipe::new(cfg, vec![input.receiver(&tag)], output.sender()).unwrap();
+
+        assert!(pipe.accepts(&record("system.cpu", None)));
+        assert!(pipe.accepts(&record("app.errors", None)));
+        assert!(!pipe.accepts(&record("app.requests", None)));
+    }
+}
@@ -0,0 +1,139 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use globset::GlobSet;
+
+use crate::{
+    actor_info,
+    config::{global, pipe::label_policy::LabelPolicyConfig},
+    core::{tag::TagId, types::Value},
+    utils::stats::GLOBAL_STATS,
+};
+
+/// Strips the label keys denied by the policy, see [`LabelPolicyConfig`].
+#[derive(Debug)]
+pub struct LabelPolicy {
+    tag: TagId,
+    deny: GlobSet,
+    allow: Option<GlobSet>,
+    stripped: AtomicU64,
+}
+
+impl LabelPolicy {
+    /// The policy of a pipe: its own one if set, the global one otherwise.
+    pub fn resolve(tag: &TagId, cfg: Option<LabelPolicyConfig>) -> super::Result<Option<Self>> {
+        let Some(cfg) = cfg.or_else(global::label_policy) else {
+            return Ok(None);
+        };
+        actor_info!(tag, "label policy {{ {} }}", cfg);
+
+        Ok(Some(Self {
+            tag: tag.clone(),
+            deny: cfg.deny_set()?,
+            allow: cfg.allow_set()?,
+            stripped: AtomicU64::new(0),
+        }))
+    }
+
+    pub fn is_allowed(&self, key: &str) -> bool {
+        if self.deny.is_match(key) {
+            return false;
+        }
+
+        self.allow.as_ref().is_none_or(|allow| allow.is_match(key))
+    }
+
+    /// Strip the denied keys off a labels map, returns how many were stripped.
+    pub fn apply(&self, labels: &mut Value) -> super::Result<usize> {
+        let mut guard = labels.map_mut()?;
+        let labels = guard.as_hashmap_mut();
+        let before = labels.len();
+        labels.retain(|key, _| match key {
+            Value::String(key) => self.is_allowed(key.as_str()),
+            key => self.is_allowed(&key.to_string()),
+        });
+
+        let stripped = before - labels.len();
+        if stripped > 0 {
+            self.count(stripped);
+        }
+
+        Ok(stripped)
+    }
+
+    pub fn count(&self, n: usize) {
+        self.stripped.fetch_add(n as u64, Ordering::Relaxed);
+        GLOBAL_STATS.incr(&format!("{} stripped labels", self.tag), n as u64);
+    }
+
+    /// Total number of labels stripped so far.
+    #[cfg(test)]
+    pub fn stripped(&self) -> u64 {
+        self.stripped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::core::tag::PipeTagId;
+
+    fn policy(deny: &[&str], allow: Option<&[&str]>) -> LabelPolicy {
+        let cfg = LabelPolicyConfig {
+            deny_keys: deny.iter().map(|s| s.to_string()).collect(),
+            allow_keys: allow.map(|keys| keys.iter().map(|s| s.to_string()).collect()),
+        };
+        LabelPolicy::resolve(&PipeTagId::new("timeseries").into(), Some(cfg))
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_globs() {
+        let policy = policy(&["user_email", "*_token"], None);
+        assert!(!policy.is_allowed("user_email"));
+        assert!(!policy.is_allowed("api_token"));
+        assert!(!policy.is_allowed("_token"));
+        assert!(policy.is_allowed("token_count"));
+        assert!(policy.is_allowed("host"));
+    }
+
+    #[test]
+    fn test_allow_keys() {
+        let policy = policy(&["host_secret"], Some(&["host*", "region"]));
+        assert!(policy.is_allowed("host"));
+        assert!(policy.is_allowed("hostname"));
+        assert!(policy.is_allowed("region"));
+        assert!(!policy.is_allowed("zone"));
+        // deny wins over allow
+        assert!(!policy.is_allowed("host_secret"));
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let cfg = LabelPolicyConfig {
+            deny_keys: vec!["user_[".to_string()],
+            allow_keys: None,
+        };
+        let err = LabelPolicy::resolve(&PipeTagId::new("timeseries").into(), Some(cfg))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("user_["), "{}", err);
+    }
+
+    #[test]
+    fn test_apply_counts() {
+        let policy = policy(&["*_token"], None);
+        let mut labels = Value::Map(HashMap::from([
+            (Value::from("host"), Value::from("a")),
+            (Value::from("api_token"), Value::from("x")),
+            (Value::from("refresh_token"), Value::from("y")),
+        ]));
+
+        assert_eq!(policy.apply(&mut labels).unwrap(), 2);
+        assert_eq!(policy.apply(&mut labels).unwrap(), 0);
+        assert_eq!(labels.map().unwrap().len(), 1);
+        assert_eq!(policy.stripped(), 2);
+    }
+}
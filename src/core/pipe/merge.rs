@@ -0,0 +1,565 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BTreeMap, BinaryHeap},
+    hash::{DefaultHasher, Hash, Hasher},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    actor_error, actor_info, actor_warn,
+    config::pipe::merge::MergePipeConfig,
+    core::{
+        actor::Actor,
+        keying::KeyExtractor,
+        manager::{ChannelGraph, SendError, TaggedReceiver, TaggedSender},
+        pipe::Pipe,
+        tag::{HasTag, TagId},
+        types::{Record, Symbol, Value},
+    },
+    utils::stats::GLOBAL_STATS,
+};
+
+#[derive(Debug)]
+struct Pending {
+    time: DateTime<Utc>,
+    seq: u64,
+    record: Record,
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    // Ties are broken by arrival order
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.time, self.seq).cmp(&(other.time, other.seq))
+    }
+}
+
+#[derive(Debug)]
+struct Source {
+    tag: TagId,
+    max_time: Option<DateTime<Utc>>,
+    last_seen: Instant,
+    idle: bool,
+}
+
+/// Remembers the records emitted recently, to drop the copies sent by redundant sources.
+#[derive(Debug)]
+struct Dedupe {
+    key: Option<KeyExtractor>,
+    epsilon: f64,
+    retention: chrono::Duration,
+    seen: BTreeMap<(DateTime<Utc>, u128), Vec<Option<Value>>>,
+}
+
+impl Dedupe {
+    /// Returns whether an equivalent record has already been seen.
+    fn check(&mut self, time: DateTime<Utc>, series: u128, value: Option<&Value>) -> bool {
+        let values = self.seen.entry((time, series)).or_default();
+        if values
+            .iter()
+            .any(|seen| values_match(seen.as_ref(), value, self.epsilon))
+        {
+            return true;
+        }
+
+        values.push(value.cloned());
+        false
+    }
+
+    fn evict(&mut self, watermark: DateTime<Utc>) {
+        let cutoff = watermark - self.retention;
+        self.seen = self.seen.split_off(&(cutoff, 0));
+    }
+}
+
+fn values_match(a: Option<&Value>, b: Option<&Value>, epsilon: f64) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) if a.is_number() && b.is_number() => {
+            match (a.cast_float(), b.cast_float()) {
+                (Ok(Value::Float(a)), Ok(Value::Float(b))) => (a.value - b.value).abs() <= epsilon,
+                _ => false,
+            }
+        }
+        (a, b) => a == b,
+    }
+}
+
+/// Event-time merge of several sources.
+///
+/// Each source is allowed to be out of order by `reorder_window`, so a record is released once
+/// every active source has seen a record at least `reorder_window` later. Sources silent for
+/// `source_idle_timeout` no longer hold the merge back, and records arriving behind what has
+/// already been released are passed through as they come.
+#[derive(Debug)]
+struct Merger {
+    tag: TagId,
+    time_field: Symbol,
+    value_field: Symbol,
+    window: chrono::Duration,
+    idle_timeout: Duration,
+
+    sources: Vec<Source>,
+    pending: BinaryHeap<Reverse<Pending>>,
+    seq: u64,
+    released: Option<DateTime<Utc>>,
+    ready: Vec<Record>,
+
+    dedupe: Option<Dedupe>,
+}
+
+impl Merger {
+    fn new(tag: TagId, sources: Vec<TagId>, cfg: &MergePipeConfig, now: Instant) -> Self {
+        let window =
+            chrono::Duration::from_std(cfg.reorder_window.get()).unwrap_or(chrono::Duration::MAX);
+        let dedupe = cfg.dedupe.then(|| Dedupe {
+            key: cfg.dedupe_key.clone().map(KeyExtractor::new),
+            epsilon: cfg.dedupe_epsilon,
+            // The copies of a lagging source may come until it is considered idle
+            retention: window
+                + chrono::Duration::from_std(cfg.source_idle_timeout.get())
+                    .unwrap_or(chrono::Duration::MAX),
+            seen: BTreeMap::new(),
+        });
+
+        Self {
+            tag,
+            time_field: cfg.time_field.clone(),
+            value_field: cfg.value_field.clone(),
+            window,
+            idle_timeout: cfg.source_idle_timeout.into(),
+            sources: sources
+                .into_iter()
+                .map(|tag| Source {
+                    tag,
+                    max_time: None,
+                    last_seen: now,
+                    idle: false,
+                })
+                .collect(),
+            pending: BinaryHeap::new(),
+            seq: 0,
+            released: None,
+            ready: Vec::new(),
+            dedupe,
+        }
+    }
+
+    fn push(&mut self, source: usize, record: Record, now: Instant) {
+        let src = &mut self.sources[source];
+        src.last_seen = now;
+        if src.idle {
+            src.idle = false;
+            actor_info!(self.tag, "source {} is active again", src.tag);
+        }
+
+        let time = match record.get(&self.time_field) {
+            Some(Value::DateTime(time)) => *time,
+            // Nothing to order by
+            _ => {
+                self.ready.push(record);
+                return;
+            }
+        };
+        src.max_time = src.max_time.max(Some(time));
+
+        if self.released.is_some_and(|released| time < released) {
+            GLOBAL_STATS.incr(&format!("{} late records", self.tag), 1);
+            self.release(time, record);
+            return;
+        }
+
+        self.seq += 1;
+        self.pending.push(Reverse(Pending {
+            time,
+            seq: self.seq,
+            record,
+        }));
+    }
+
+    /// Event time up to which records can be released, `None` when no source holds the merge.
+    fn watermark(&self) -> Option<DateTime<Utc>> {
+        self.sources
+            .iter()
+            .filter(|s| !s.idle)
+            .map(|s| match s.max_time {
+                Some(time) => time - self.window,
+                None => DateTime::<Utc>::MIN_UTC,
+            })
+            .min()
+    }
+
+    fn drain(&mut self, now: Instant) -> Vec<Record> {
+        for src in self.sources.iter_mut().filter(|s| !s.idle) {
+            if now.saturating_duration_since(src.last_seen) >= self.idle_timeout {
+                src.idle = true;
+                actor_warn!(
+                    self.tag,
+                    "source {} has been idle for {:?}, merging without it",
+                    src.tag,
+                    self.idle_timeout
+                );
+            }
+        }
+
+        let watermark = self.watermark();
+        while let Some(Reverse(top)) = self.pending.peek() {
+            if watermark.is_some_and(|w| top.time > w) {
+                break;
+            }
+
+            let Reverse(Pending { time, record, .. }) = self.pending.pop().unwrap();
+            self.release(time, record);
+        }
+
+        std::mem::take(&mut self.ready)
+    }
+
+    /// Release everything, whatever the watermark.
+    fn flush(&mut self) -> Vec<Record> {
+        while let Some(Reverse(Pending { time, record, .. })) = self.pending.pop() {
+            self.release(time, record);
+        }
+
+        std::mem::take(&mut self.ready)
+    }
+
+    fn release(&mut self, time: DateTime<Utc>, record: Record) {
+        if let Some(ref mut dedupe) = self.dedupe {
+            let series = match dedupe.key {
+                Some(ref key) => key.key(&record).unwrap_or_else(|e| {
+                    actor_warn!(
+                        self.tag,
+                        "record {} not deduped: {}",
+                        key.describe(&record),
+                        e
+                    );
+                    None
+                }),
+                None => {
+                    let mut hasher = DefaultHasher::new();
+                    for (key, value) in record
+                        .iter()
+                        .filter(|(k, _)| **k != self.time_field && **k != self.value_field)
+                    {
+                        key.as_str().hash(&mut hasher);
+                        value.content_hash().hash(&mut hasher);
+                    }
+                    Some(hasher.finish() as u128)
+                }
+            };
+
+            // Records without a key are never considered duplicates
+            let duplicate = series
+                .is_some_and(|series| dedupe.check(time, series, record.get(&self.value_field)));
+            if duplicate {
+                GLOBAL_STATS.incr(&format!("{} duplicates", self.tag), 1);
+                return;
+            }
+        }
+
+        if self.released.is_none_or(|released| time > released) {
+            self.released = Some(time);
+            if let Some(ref mut dedupe) = self.dedupe {
+                dedupe.evict(time);
+            }
+        }
+        self.ready.push(record);
+    }
+}
+
+#[derive(Debug)]
+pub struct MergePipe {
+    tag: TagId,
+    inbounds: Vec<TaggedReceiver>,
+    outbound: TaggedSender,
+    merger: Merger,
+    recv_timeout: Duration,
+}
+
+impl MergePipe {
+    pub fn try_create_from(
+        cfg: MergePipeConfig,
+        channels: &mut ChannelGraph,
+    ) -> super::Result<Self> {
+        let tag: TagId = (&cfg.tag).into();
+        let inbounds = cfg
+            .inbounds
+            .iter()
+            .map(|inbound| channels.recv_from(inbound, &tag))
+            .collect::<Vec<_>>();
+        let outbound = channels.sender(&tag);
+        let merger = Merger::new(tag.clone(), cfg.inbounds.clone(), &cfg, Instant::now());
+
+        Ok(MergePipe {
+            tag,
+            inbounds,
+            outbound,
+            merger,
+            recv_timeout: cfg.recv_timeout.into(),
+        })
+    }
+
+    async fn send(&mut self, records: Vec<Record>) {
+        for record in records {
+            if let Err(e @ SendError::Closed(_)) = self.outbound.send(record).await {
+                actor_error!(self.tag, "failed to send record: {}", e);
+            }
+        }
+    }
+}
+
+impl HasTag for MergePipe {
+    fn tag(&self) -> &TagId {
+        &self.tag
+    }
+}
+
+#[async_trait]
+impl Actor for MergePipe {
+    type Error = super::Error;
+
+    async fn poll(&mut self, ctx: CancellationToken) -> super::Result<()> {
+        let futs = self.inbounds.iter_mut().enumerate().map(|(i, inbound)| {
+            let fut = async move { (i, inbound.recv().await) };
+            Box::pin(fut)
+        });
+
+        let received = tokio::select! {
+            ((i, record), _, _) = futures::future::select_all(futs) => Some(Some((i, record))),
+            _ = tokio::time::sleep(self.recv_timeout) => Some(None),
+            _ = ctx.cancelled() => None,
+        };
+
+        let Some(received) = received else {
+            let records = self.merger.flush();
+            self.send(records).await;
+            return Ok(());
+        };
+
+        let now = Instant::now();
+        match received {
+            Some((i, Ok(record))) => {
+                self.merger.push(i, record, now);
+                while let Ok(record) = self.inbounds[i].try_recv() {
+                    self.merger.push(i, record, now);
+                }
+            }
+            Some((i, Err(RecvError::Lagged(n)))) => {
+                actor_warn!(self.tag, "inbound {} lagged {}", self.inbounds[i].tag(), n);
+            }
+            Some((i, Err(RecvError::Closed))) => {
+                let tag = self.inbounds[i].tag().clone();
+                return Err(crate::utils::recv::Error::ChannelClosed(tag).into());
+            }
+            None => {}
+        }
+
+        let records = self.merger.drain(now);
+        self.send(records).await;
+
+        Ok(())
+    }
+}
+
+impl Pipe for MergePipe {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::types::DurationValue,
+        core::tag::{InboundTagId, PipeTagId},
+    };
+
+    fn config(dedupe: bool) -> MergePipeConfig {
+        MergePipeConfig {
+            tag: PipeTagId::new("merge"),
+            inbounds: vec![],
+            disabled: false,
+            channel_overflow: None,
+            inbound_overflow: None,
+            time_field: Symbol::new("timestamp"),
+            reorder_window: DurationValue::from_secs(1),
+            source_idle_timeout: DurationValue::from_secs(5),
+            dedupe,
+            value_field: Symbol::new("value"),
+            dedupe_key: None,
+            dedupe_epsilon: 1e-6,
+            recv_timeout: DurationValue::from_millis(50),
+        }
+    }
+
+    fn new_merger(dedupe: bool, start: Instant) -> Merger {
+        let sources = vec![InboundTagId::new("a").into(), InboundTagId::new("b").into()];
+        Merger::new(
+            PipeTagId::new("merge").into(),
+            sources,
+            &config(dedupe),
+            start,
+        )
+    }
+
+    fn sample(ts: i64, value: f64) -> Record {
+        let mut record = Record::empty();
+        record.set(
+            Symbol::new("timestamp"),
+            Value::DateTime(DateTime::from_timestamp(ts, 0).unwrap()),
+        );
+        record.set(Symbol::new("name"), Value::from("cpu"));
+        record.set(Symbol::new("value"), Value::from(value));
+        record
+    }
+
+    fn times(records: &[Record]) -> Vec<i64> {
+        records
+            .iter()
+            .map(|r| match r[&Symbol::new("timestamp")] {
+                Value::DateTime(ts) => ts.timestamp(),
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    fn values(records: &[Record]) -> Vec<f64> {
+        records
+            .iter()
+            .map(|r| r[&Symbol::new("value")].float().unwrap().value())
+            .collect()
+    }
+
+    #[test]
+    fn test_lagging_source_ordering() {
+        let start = Instant::now();
+        let mut merger = new_merger(false, start);
+
+        // a is ahead, nothing can be released until b shows up
+        for ts in [0, 2, 1, 3, 5, 4, 6] {
+            merger.push(0, sample(ts, 0.0), start);
+        }
+        assert!(merger.drain(start).is_empty());
+
+        let now = start + Duration::from_millis(100);
+        for ts in [0, 1, 3] {
+            merger.push(1, sample(ts, 1.0), now);
+        }
+        // up to min(6, 3) - 1s
+        let out = merger.drain(now);
+        assert_eq!(times(&out), vec![0, 0, 1, 1, 2]);
+        assert_eq!(values(&out[..2]), vec![0.0, 1.0]);
+
+        for ts in [4, 5, 7] {
+            merger.push(1, sample(ts, 1.0), now);
+        }
+        let mut out = merger.drain(now);
+        assert_eq!(times(&out), vec![3, 3, 4, 4, 5, 5]);
+
+        out.extend(merger.flush());
+        assert_eq!(times(&out[6..]), vec![6, 7]);
+    }
+
+    #[test]
+    fn test_dedupe_redundant_sources() {
+        let start = Instant::now();
+        let mut merger = new_merger(true, start);
+
+        for ts in 0..5 {
+            merger.push(0, sample(ts, ts as f64), start);
+            // same sample within epsilon
+            merger.push(1, sample(ts, ts as f64 + 1e-9), start);
+        }
+        // a diverging value is kept
+        merger.push(1, sample(2, 42.0), start);
+
+        let out = merger.flush();
+        assert_eq!(times(&out), vec![0, 1, 2, 2, 3, 4]);
+        // the first copy wins
+        assert_eq!(values(&out), vec![0.0, 1.0, 2.0, 42.0, 3.0, 4.0]);
+
+        // without dedupe every copy goes through
+        let mut merger = new_merger(false, start);
+        for ts in 0..5 {
+            merger.push(0, sample(ts, ts as f64), start);
+            merger.push(1, sample(ts, ts as f64), start);
+        }
+        assert_eq!(merger.flush().len(), 10);
+    }
+
+    #[test]
+    fn test_dedupe_key() {
+        let start = Instant::now();
+        let mut cfg = config(true);
+        // each source stamps its own name on the records
+        cfg.dedupe_key = Some(toml::from_str("include_name = true").unwrap());
+        let sources = vec![InboundTagId::new("a").into(), InboundTagId::new("b").into()];
+        let mut merger = Merger::new(PipeTagId::new("merge").into(), sources, &cfg, start);
+
+        for (source, name) in ["a", "b"].into_iter().enumerate() {
+            for ts in 0..3 {
+                let mut record = sample(ts, ts as f64);
+                record.set(Symbol::new("source"), Value::from(name));
+                merger.push(source, record, start);
+            }
+        }
+        assert_eq!(times(&merger.flush()), vec![0, 1, 2]);
+
+        // records lacking the key are let through
+        cfg.dedupe_key = Some(
+            toml::from_str(
+                r#"fields = ["host"]
+missing = "skip""#,
+            )
+            .unwrap(),
+        );
+        let sources = vec![InboundTagId::new("a").into(), InboundTagId::new("b").into()];
+        let mut merger = Merger::new(PipeTagId::new("merge").into(), sources, &cfg, start);
+        merger.push(0, sample(0, 0.0), start);
+        merger.push(1, sample(0, 0.0), start);
+        assert_eq!(merger.flush().len(), 2);
+    }
+
+    #[test]
+    fn test_idle_source_does_not_block() {
+        let start = Instant::now();
+        let mut merger = new_merger(true, start);
+
+        let now = start + Duration::from_secs(4);
+        for ts in 0..10 {
+            merger.push(0, sample(ts, ts as f64), now);
+        }
+        assert!(merger.drain(now).is_empty());
+
+        // b has been silent for source_idle_timeout
+        let now = start + Duration::from_secs(5);
+        let out = merger.drain(now);
+        assert_eq!(times(&out), (0..=8).collect::<Vec<_>>());
+        assert!(merger.sources[1].idle);
+
+        // b comes back late: its copies are dropped, new samples pass through
+        for ts in 7..11 {
+            merger.push(1, sample(ts, ts as f64), now);
+        }
+        assert!(!merger.sources[1].idle);
+        assert!(merger.drain(now).is_empty());
+
+        let out = merger.flush();
+        assert_eq!(times(&out), vec![9, 10]);
+    }
+}
@@ -1,9 +1,23 @@
+mod aggregate;
 mod base;
+mod dedup;
 mod error;
+mod filter;
+mod label_policy;
+mod merge;
+mod rate;
+mod route;
+mod sample;
+mod size;
+mod throttle;
 mod timeseries;
+mod transform;
+mod validate;
 
 pub use base::Pipe;
 pub use error::{Error, Result};
+pub use label_policy::LabelPolicy;
+pub use size::RecordSizeObserver;
 
 use crate::config::pipe::PipeConfig;
 pub use timeseries::{
@@ -21,6 +35,24 @@ pub fn try_create_from(cfg: PipeConfig, channels: &mut ChannelGraph) -> Result<B
         PipeConfig::TimeseriesAnnotate(cfg) => Box::new(
             timeseries::TimeseriesAnnotatePipe::try_create_from(cfg, channels)?,
         ),
+        PipeConfig::Merge(cfg) => Box::new(merge::MergePipe::try_create_from(cfg, channels)?),
+        PipeConfig::Filter(cfg) => Box::new(filter::FilterPipe::try_create_from(cfg, channels)?),
+        PipeConfig::Aggregate(cfg) => {
+            Box::new(aggregate::AggregatePipe::try_create_from(cfg, channels)?)
+        }
+        PipeConfig::Validate(cfg) => {
+            Box::new(validate::ValidatePipe::try_create_from(cfg, channels)?)
+        }
+        PipeConfig::Dedup(cfg) => Box::new(dedup::DedupPipe::try_create_from(cfg, channels)?),
+        PipeConfig::Route(cfg) => Box::new(route::RoutePipe::try_create_from(cfg, channels)?),
+        PipeConfig::Rate(cfg) => Box::new(rate::RatePipe::try_create_from(cfg, channels)?),
+        PipeConfig::Transform(cfg) => {
+            Box::new(transform::TransformPipe::try_create_from(cfg, channels)?)
+        }
+        PipeConfig::Throttle(cfg) => {
+            Box::new(throttle::ThrottlePipe::try_create_from(cfg, channels)?)
+        }
+        PipeConfig::Sample(cfg) => Box::new(sample::SamplePipe::try_create_from(cfg, channels)?),
     };
 
     Ok(pipe)
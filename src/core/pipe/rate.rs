@@ -0,0 +1,485 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    actor_debug, actor_warn,
+    config::pipe::rate::{CounterReset, RateMode, RatePipeConfig},
+    core::{
+        actor::Actor,
+        manager::{ChannelGraph, SendError, TaggedReceiver, TaggedSender},
+        metrics,
+        tag::{HasTag, TagId},
+        types::{Attribute, Record, Value},
+    },
+    utils::{
+        recv::{self, recv_batch},
+        throttle::Throttle,
+    },
+};
+
+use super::{
+    timeseries::{NAME_FIELD_STR, TIMESTAMP_FIELD_STR, UNIT_FIELD, VALUE_FIELD_STR},
+    Pipe, LABELS_FIELD, METRIC_TYPE_FIELD, NAME_FIELD, TIMESTAMP_FIELD, VALUE_FIELD,
+};
+
+const OUT_OF_ORDER_WARN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The name and the hash of the labels of a series.
+type SeriesKey = (String, u64);
+
+#[derive(Debug)]
+struct Series {
+    value: f64,
+    timestamp: DateTime<Utc>,
+    seen_at: Instant,
+    // Position in `RateCalculator::recency`
+    seen: u64,
+}
+
+#[derive(Debug)]
+struct RateCalculator {
+    mode: RateMode,
+    on_reset: CounterReset,
+    ttl: Duration,
+    max_series: usize,
+
+    series: HashMap<SeriesKey, Series>,
+    // Least recently seen series first
+    recency: BTreeMap<u64, SeriesKey>,
+    clock: u64,
+
+    // Samples dropped for not being newer than the previous one of their series
+    out_of_order: usize,
+}
+
+impl RateCalculator {
+    fn new(cfg: &RatePipeConfig) -> Self {
+        Self {
+            mode: cfg.mode,
+            on_reset: cfg.on_reset,
+            ttl: cfg.series_ttl.get(),
+            max_series: cfg.max_series,
+            series: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            out_of_order: 0,
+        }
+    }
+
+    /// The record carrying the increase since the previous sample of the series, none for
+    /// the first sample, an out of order one, or a reset when those are dropped.
+    fn push(&mut self, mut record: Record, now: Instant) -> super::Result<Option<Record>> {
+        let name = record
+            .get(&NAME_FIELD)
+            .ok_or(super::Error::FieldNotFound(NAME_FIELD_STR))?
+            .string()?
+            .to_string();
+        let value = record
+            .get(&VALUE_FIELD)
+            .ok_or(super::Error::FieldNotFound(VALUE_FIELD_STR))?
+            .cast_float()?;
+        let mut value = value.float()?.as_number().clone();
+        let timestamp = *record
+            .get(&TIMESTAMP_FIELD)
+            .ok_or(super::Error::FieldNotFound(TIMESTAMP_FIELD_STR))?
+            .datetime()?
+            .as_datetime();
+        let labels = record.get(&LABELS_FIELD).map_or(0, Value::content_hash);
+        let key = (name, labels);
+
+        self.expire(now);
+        self.clock += 1;
+        let seen = self.clock;
+
+        let Some(series) = self.series.get_mut(&key) else {
+            if self.series.len() >= self.max_series {
+                self.evict();
+            }
+            self.recency.insert(seen, key.clone());
+            self.series.insert(
+                key,
+                Series {
+                    value: value.value,
+                    timestamp,
+                    seen_at: now,
+                    seen,
+                },
+            );
+            return Ok(None);
+        };
+
+        self.recency.remove(&series.seen);
+        self.recency.insert(seen, key);
+        series.seen = seen;
+        series.seen_at = now;
+
+        // Out of order or repeated, the previous sample stays the reference
+        if timestamp <= series.timestamp {
+            self.out_of_order += 1;
+            return Ok(None);
+        }
+        let elapsed = (timestamp - series.timestamp)
+            .to_std()
+            .map_or(0.0, |elapsed| elapsed.as_secs_f64());
+        let previous = std::mem::replace(&mut series.value, value.value);
+        series.timestamp = timestamp;
+
+        let delta = if value.value >= previous {
+            value.value - previous
+        } else {
+            match self.on_reset {
+                CounterReset::FromZero => value.value,
+                CounterReset::Drop => return Ok(None),
+            }
+        };
+
+        match self.mode {
+            RateMode::Delta => value.value = delta,
+            RateMode::Rate => {
+                if let Some(labels) = record.get_mut(&LABELS_FIELD) {
+                    let mut labels = labels.map_mut()?;
+                    if let Some(unit) = labels.get_mut(&Value::from(UNIT_FIELD.as_ref())) {
+                        if let Ok(name) = unit.string().map(|name| per_second(name.as_str())) {
+                            *unit = Value::from(name);
+                        }
+                    }
+                }
+                value.value = delta / elapsed;
+                value.unit = value.unit.as_deref().map(per_second);
+            }
+        }
+        record.set(VALUE_FIELD.clone(), Value::Float(value));
+        // Increases and rates go up and down
+        record.set(METRIC_TYPE_FIELD.clone(), Value::from("gauge"));
+
+        Ok(Some(record))
+    }
+
+    /// Forget the series not seen within the TTL.
+    fn expire(&mut self, now: Instant) {
+        while let Some((_, key)) = self.recency.first_key_value() {
+            let expired = self
+                .series
+                .get(key)
+                .is_none_or(|series| now.saturating_duration_since(series.seen_at) >= self.ttl);
+            if !expired {
+                break;
+            }
+            self.evict();
+        }
+    }
+
+    fn evict(&mut self) {
+        if let Some((_, key)) = self.recency.pop_first() {
+            self.series.remove(&key);
+        }
+    }
+}
+
+fn per_second(unit: &str) -> String {
+    format!("{}/s", unit)
+}
+
+/// Emits the increase or the rate of each counter between consecutive samples.
+pub struct RatePipe {
+    tag: TagId,
+    rates: RateCalculator,
+    out_of_order_throttle: Throttle,
+
+    inbounds: Vec<TaggedReceiver>,
+    outbound: TaggedSender,
+
+    interval: Duration,
+    buffer_size: usize,
+    max_batch_latency: Duration,
+}
+
+impl RatePipe {
+    pub fn try_create_from(
+        cfg: RatePipeConfig,
+        channels: &mut ChannelGraph,
+    ) -> super::Result<Self> {
+        let tag: TagId = (&cfg.tag).into();
+        let inbounds = cfg
+            .inbounds
+            .iter()
+            .map(|inbound| channels.recv_from(inbound, &tag))
+            .collect::<Vec<_>>();
+        let outbound = channels.sender(&tag);
+
+        Ok(Self::new(cfg, inbounds, outbound))
+    }
+
+    fn new(cfg: RatePipeConfig, inbounds: Vec<TaggedReceiver>, outbound: TaggedSender) -> Self {
+        RatePipe {
+            tag: cfg.tag.clone().into(),
+            rates: RateCalculator::new(&cfg),
+            out_of_order_throttle: Throttle::new(OUT_OF_ORDER_WARN_INTERVAL),
+            inbounds,
+            outbound,
+            interval: cfg.recv_timeout.into(),
+            buffer_size: cfg.recv_buffer_size,
+            max_batch_latency: cfg.max_batch_latency.into(),
+        }
+    }
+}
+
+impl HasTag for RatePipe {
+    fn tag(&self) -> &TagId {
+        &self.tag
+    }
+}
+
+#[async_trait]
+impl Actor for RatePipe {
+    type Error = super::Error;
+
+    async fn poll(&mut self, ctx: CancellationToken) -> super::Result<()> {
+        let tag = self.tag.clone();
+        let records = match recv_batch(
+            &tag,
+            &mut self.inbounds,
+            Some(self.interval),
+            self.buffer_size,
+            self.max_batch_latency,
+            ctx,
+        )
+        .await
+        {
+            Ok(records) => records,
+            Err(recv::Error::Timeout) | Err(recv::Error::Canceled) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let now = Instant::now();
+        let received = records.len();
+        let mut out = Vec::with_capacity(received);
+        for record in records {
+            match self.rates.push(record, now) {
+                Ok(Some(record)) => out.push(record),
+                Ok(None) => {}
+                Err(e) => {
+                    actor_warn!(self.tag, "record not converted: {}", e);
+                    metrics::count_transform_error(&self.tag);
+                }
+            }
+        }
+
+        if self.rates.out_of_order > 0 {
+            if let Some(suppressed) = self.out_of_order_throttle.check_at(now) {
+                actor_warn!(
+                    self.tag,
+                    "dropped {} samples not newer than the previous one of their series ({} similar warnings suppressed)",
+                    self.rates.out_of_order,
+                    suppressed
+                );
+            }
+            self.rates.out_of_order = 0;
+        }
+
+        actor_debug!(self.tag, "{} of {} records converted", out.len(), received);
+        for mut record in out {
+            record.set_attribute(Attribute::Inbound, (&self.tag).into());
+            if let Err(e @ SendError::Closed(_)) = self.outbound.send(record).await {
+                actor_warn!(self.tag, "error sending record: {}", e);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Pipe for RatePipe {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{
+        manager::ActorChannel,
+        tag::PipeTagId,
+        types::{parse_value, Symbol, ValueType},
+    };
+
+    fn config(body: &str) -> RatePipeConfig {
+        toml::from_str(&format!("inbounds = [\"pipe:timeseries\"]\n{}", body)).unwrap()
+    }
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_743_667_743 + secs, 0).unwrap()
+    }
+
+    fn sample(host: &str, value: f64, secs: i64) -> Record {
+        let mut record = Record::empty();
+        record.set(NAME_FIELD.clone(), Value::from("bytes_total"));
+        record.set(
+            VALUE_FIELD.clone(),
+            parse_value(&format!("{} bytes", value), ValueType::Float).unwrap(),
+        );
+        record.set(TIMESTAMP_FIELD.clone(), Value::from(at(secs)));
+        record.set(
+            LABELS_FIELD.clone(),
+            Value::from(vec![
+                (Symbol::new("host"), Value::from(host)),
+                (UNIT_FIELD.clone(), Value::from("bytes")),
+            ]),
+        );
+        record
+    }
+
+    fn value(record: &Record) -> f64 {
+        record[&VALUE_FIELD].float().unwrap().value()
+    }
+
+    fn push(rates: &mut RateCalculator, record: Record) -> Option<Record> {
+        rates.push(record, Instant::now()).unwrap()
+    }
+
+    #[test]
+    fn test_rate() {
+        let mut rates = RateCalculator::new(&config(""));
+
+        // The first sample only sets the reference
+        assert!(push(&mut rates, sample("a", 100.0, 0)).is_none());
+        let record = push(&mut rates, sample("a", 400.0, 10)).unwrap();
+        assert_eq!(value(&record), 30.0);
+        assert_eq!(record[&METRIC_TYPE_FIELD], Value::from("gauge"));
+        // The unit is per second, in the value and in the labels
+        assert_eq!(
+            record[&VALUE_FIELD].float().unwrap().unit(),
+            Some(&"bytes/s".to_string())
+        );
+        assert_eq!(
+            record[&LABELS_FIELD].map().unwrap().as_hashmap()[&Value::from("unit")],
+            Value::from("bytes/s")
+        );
+
+        // Other series are tracked on their own
+        assert!(push(&mut rates, sample("b", 1000.0, 10)).is_none());
+        assert_eq!(
+            value(&push(&mut rates, sample("a", 500.0, 20)).unwrap()),
+            10.0
+        );
+    }
+
+    #[test]
+    fn test_delta() {
+        let mut rates = RateCalculator::new(&config("mode = \"delta\""));
+
+        assert!(push(&mut rates, sample("a", 100.0, 0)).is_none());
+        let record = push(&mut rates, sample("a", 400.0, 10)).unwrap();
+        assert_eq!(value(&record), 300.0);
+        // The unit is left as is
+        assert_eq!(
+            record[&VALUE_FIELD].float().unwrap().unit(),
+            Some(&"bytes".to_string())
+        );
+        assert_eq!(
+            record[&LABELS_FIELD].map().unwrap().as_hashmap()[&Value::from("unit")],
+            Value::from("bytes")
+        );
+    }
+
+    #[test]
+    fn test_counter_reset() {
+        let mut rates = RateCalculator::new(&config("mode = \"delta\""));
+        push(&mut rates, sample("a", 100.0, 0));
+        // Restarted from zero, 30 since then
+        assert_eq!(
+            value(&push(&mut rates, sample("a", 30.0, 10)).unwrap()),
+            30.0
+        );
+        assert_eq!(
+            value(&push(&mut rates, sample("a", 50.0, 20)).unwrap()),
+            20.0
+        );
+
+        let mut rates = RateCalculator::new(&config("mode = \"delta\"\non_reset = \"drop\""));
+        push(&mut rates, sample("a", 100.0, 0));
+        assert!(push(&mut rates, sample("a", 30.0, 10)).is_none());
+        // The reset sample is the next reference
+        assert_eq!(
+            value(&push(&mut rates, sample("a", 50.0, 20)).unwrap()),
+            20.0
+        );
+    }
+
+    #[test]
+    fn test_out_of_order() {
+        let mut rates = RateCalculator::new(&config("mode = \"delta\""));
+        push(&mut rates, sample("a", 100.0, 10));
+
+        assert!(push(&mut rates, sample("a", 50.0, 5)).is_none());
+        assert!(push(&mut rates, sample("a", 150.0, 10)).is_none());
+        assert_eq!(rates.out_of_order, 2);
+        // Neither replaced the previous sample
+        assert_eq!(
+            value(&push(&mut rates, sample("a", 160.0, 20)).unwrap()),
+            60.0
+        );
+
+        assert!(rates.push(Record::empty(), Instant::now()).is_err());
+    }
+
+    #[test]
+    fn test_series_expiry() {
+        let mut rates = RateCalculator::new(&config("series_ttl = \"1m\"\nmax_series = 2"));
+        let start = Instant::now();
+        let later = |secs: u64| start + Duration::from_secs(secs);
+
+        rates.push(sample("a", 100.0, 0), start).unwrap();
+        rates.push(sample("b", 100.0, 0), later(30)).unwrap();
+        // `a` has not been seen for a minute, its sample is a first one again
+        assert!(rates
+            .push(sample("a", 200.0, 60), later(60))
+            .unwrap()
+            .is_none());
+        assert!(rates
+            .push(sample("b", 200.0, 60), later(60))
+            .unwrap()
+            .is_some());
+
+        // Beyond `max_series`, the least recently seen is forgotten
+        rates.push(sample("c", 100.0, 60), later(61)).unwrap();
+        assert_eq!(rates.series.len(), 2);
+        assert!(rates
+            .push(sample("a", 300.0, 70), later(62))
+            .unwrap()
+            .is_none());
+        assert!(rates
+            .push(sample("c", 300.0, 70), later(62))
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_rate_pipe() {
+        let cfg = config("");
+        let tag: TagId = (&cfg.tag).into();
+
+        let mut input = ActorChannel::new(PipeTagId::new("timeseries").into(), 16);
+        let mut output = ActorChannel::new(tag.clone(), 16);
+        let mut received = output.receiver(&PipeTagId::new("next").into());
+        let mut pipe = RatePipe::new(cfg, vec![input.receiver(&tag)], output.sender());
+
+        let mut sender = input.sender();
+        sender.send(sample("a", 100.0, 0)).await.unwrap();
+        sender.send(sample("a", 200.0, 4)).await.unwrap();
+        sender.send(sample("a", 100.0, 2)).await.unwrap();
+        pipe.poll(CancellationToken::new()).await.unwrap();
+
+        let record = received.try_recv().unwrap();
+        assert_eq!(value(&record), 25.0);
+        assert_eq!(
+            record.get_attribute(&Attribute::Inbound),
+            Some(&(&tag).into())
+        );
+        assert!(received.try_recv().is_err());
+        assert_eq!(pipe.rates.out_of_order, 0);
+    }
+}
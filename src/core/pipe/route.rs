@@ -0,0 +1,336 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    actor_debug, actor_warn,
+    config::pipe::route::{RouteMatching, RoutePipeConfig},
+    core::{
+        actor::Actor,
+        manager::{ChannelGraph, SendError, TaggedReceiver, TaggedSender},
+        tag::{HasTag, TagId},
+        types::{Attribute, Record},
+    },
+    utils::{recv::recv_batch, stats::GLOBAL_STATS},
+};
+
+use super::{filter::condition::Condition, Pipe};
+
+struct Route {
+    conditions: Vec<Condition>,
+    sender: TaggedSender,
+}
+
+impl Route {
+    fn matches(&self, record: &Record) -> bool {
+        self.conditions.iter().all(|c| c.matches(record))
+    }
+}
+
+/// Sends each record to every route it matches, or to the first one only, and to the default
+/// route when none does.
+pub struct RoutePipe {
+    tag: TagId,
+    routes: Vec<Route>,
+    matching: RouteMatching,
+    default: Option<TaggedSender>,
+
+    inbounds: Vec<TaggedReceiver>,
+
+    interval: Duration,
+    buffer_size: usize,
+    max_batch_latency: Duration,
+}
+
+impl RoutePipe {
+    pub fn try_create_from(
+        cfg: RoutePipeConfig,
+        channels: &mut ChannelGraph,
+    ) -> super::Result<Self> {
+        let tag: TagId = (&cfg.tag).into();
+        let inbounds = cfg
+            .inbounds
+            .iter()
+            .map(|inbound| channels.recv_from(inbound, &tag))
+            .collect::<Vec<_>>();
+
+        let routes = cfg
+            .routes
+            .into_iter()
+            .map(|route| {
+                let conditions = route
+                    .conditions
+                    .into_iter()
+                    .map(Condition::try_create_from)
+                    .collect::<super::Result<Vec<_>>>()?;
+                Ok(Route {
+                    conditions,
+                    sender: channels.sender_for(&tag, &route.name),
+                })
+            })
+            .collect::<super::Result<Vec<_>>>()?;
+        let default = cfg
+            .default
+            .as_deref()
+            .map(|route| channels.sender_for(&tag, route));
+
+        Ok(RoutePipe {
+            tag,
+            routes,
+            matching: cfg.matching,
+            default,
+            inbounds,
+            interval: cfg.recv_timeout.into(),
+            buffer_size: cfg.recv_buffer_size,
+            max_batch_latency: cfg.max_batch_latency.into(),
+        })
+    }
+
+    async fn send(tag: &TagId, sender: &mut TaggedSender, record: Record) {
+        if let Err(e @ SendError::Closed(_)) = sender.send(record).await {
+            actor_warn!(tag, "error sending record: {}", e);
+        }
+    }
+}
+
+impl HasTag for RoutePipe {
+    fn tag(&self) -> &TagId {
+        &self.tag
+    }
+}
+
+#[async_trait]
+impl Actor for RoutePipe {
+    type Error = super::Error;
+
+    async fn poll(&mut self, ctx: CancellationToken) -> super::Result<()> {
+        let tag = self.tag.clone();
+        let records = match recv_batch(
+            &tag,
+            &mut self.inbounds,
+            Some(self.interval),
+            self.buffer_size,
+            self.max_batch_latency,
+            ctx,
+        )
+        .await
+        {
+            Ok(records) => records,
+            Err(crate::utils::recv::Error::Timeout) => {
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let total = records.len();
+        let mut unrouted = 0;
+        for mut record in records {
+            // Keep the inbound the record came from, downstream stats are keyed by it
+            record.set_attribute(Attribute::Inbound, (&self.tag).into());
+
+            let mut matched = self
+                .routes
+                .iter()
+                .enumerate()
+                .filter(|(_, route)| route.matches(&record))
+                .map(|(idx, _)| idx);
+            let matched = match self.matching {
+                RouteMatching::All => matched.collect::<Vec<_>>(),
+                RouteMatching::First => matched.next().into_iter().collect(),
+            };
+            match matched.split_last() {
+                Some((&last, others)) => {
+                    for &idx in others {
+                        Self::send(&self.tag, &mut self.routes[idx].sender, record.clone()).await;
+                    }
+                    Self::send(&self.tag, &mut self.routes[last].sender, record).await;
+                }
+                None => match self.default {
+                    Some(ref mut sender) => Self::send(&self.tag, sender, record).await,
+                    None => unrouted += 1,
+                },
+            }
+        }
+
+        if unrouted > 0 {
+            actor_debug!(
+                self.tag,
+                "dropped {} of {} records matching no route",
+                unrouted,
+                total
+            );
+            GLOBAL_STATS.incr(&format!("{} unrouted records", self.tag), unrouted);
+        }
+
+        Ok(())
+    }
+}
+
+impl Pipe for RoutePipe {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{inbound::InboundConfig, pipe::PipeConfig, OutboundConfig},
+        core::{
+            tag::{InboundTagId, OutboundTagId},
+            types::{Symbol, Value},
+        },
+    };
+
+    fn record(name: &str) -> Record {
+        let mut record = Record::empty();
+        record.set(Symbol::new("name"), Value::from(name));
+        record
+    }
+
+    fn name(record: &Record) -> Option<&Value> {
+        record.get(&Symbol::new("name"))
+    }
+
+    fn graph(pipe: &PipeConfig, routes: &[&str]) -> ChannelGraph {
+        let inbound: InboundConfig = toml::from_str(
+            "type = \"tcp\"\ntag = \"a\"\naddress = \"127.0.0.1:0\"\nprotocol = \"json\"",
+        )
+        .unwrap();
+        let outbounds = routes
+            .iter()
+            .map(|route| {
+                toml::from_str(&format!(
+                    "type = \"stdio\"\ntag = \"{}\"\ninbounds = [\"pipe:split:{}\"]",
+                    route, route
+                ))
+                .unwrap()
+            })
+            .collect::<Vec<OutboundConfig>>();
+
+        ChannelGraph::try_create_from(&[inbound], std::slice::from_ref(pipe), &outbounds).unwrap()
+    }
+
+    fn pipe_config(options: &str) -> PipeConfig {
+        toml::from_str(&format!(
+            r#"
+            type = "route"
+            tag = "split"
+            inbounds = ["inbound:a"]
+            {}
+            routes = [
+                {{ name = "infra", conditions = [{{ field = "name", op = "regex", value = "^node_" }}] }},
+                {{ name = "app", conditions = [{{ field = "name", op = "regex", value = "(^app_|_requests$)" }}] }},
+            ]
+            "#,
+            options
+        ))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_route_pipe_fan_out() {
+        let cfg = pipe_config("default = \"other\"");
+        let mut channels = graph(&cfg, &["infra", "app", "other"]);
+        let PipeConfig::Route(cfg) = cfg else {
+            unreachable!()
+        };
+        let tag: TagId = (&cfg.tag).into();
+        let mut pipe = RoutePipe::try_create_from(cfg, &mut channels).unwrap();
+
+        let mut receivers = ["infra", "app", "other"].map(|route| {
+            let who: TagId = OutboundTagId::new(route).into();
+            channels.recv_from(&tag.route(route), &who)
+        });
+        let mut sender = channels.sender(&InboundTagId::new("a").into());
+        for name in ["node_cpu", "app_errors", "node_requests", "disk_free"] {
+            sender.send(record(name)).await.unwrap();
+        }
+        pipe.poll(CancellationToken::new()).await.unwrap();
+
+        let [infra, app, other] = &mut receivers;
+        assert_eq!(
+            name(&infra.recv().await.unwrap()),
+            Some(&Value::from("node_cpu"))
+        );
+        // Sent to every route it matches
+        assert_eq!(
+            name(&infra.recv().await.unwrap()),
+            Some(&Value::from("node_requests"))
+        );
+        assert_eq!(
+            name(&app.recv().await.unwrap()),
+            Some(&Value::from("app_errors"))
+        );
+        assert_eq!(
+            name(&app.recv().await.unwrap()),
+            Some(&Value::from("node_requests"))
+        );
+        let unmatched = other.recv().await.unwrap();
+        assert_eq!(name(&unmatched), Some(&Value::from("disk_free")));
+        assert_eq!(
+            unmatched.get_attribute(&Attribute::Inbound),
+            Some(&(&tag).into())
+        );
+        assert!(receivers.iter_mut().all(|r| r.try_recv().is_err()));
+    }
+
+    #[tokio::test]
+    async fn test_route_pipe_first_match() {
+        let cfg = pipe_config("matching = \"first\"\ndefault = \"other\"");
+        let mut channels = graph(&cfg, &["infra", "app", "other"]);
+        let PipeConfig::Route(cfg) = cfg else {
+            unreachable!()
+        };
+        let tag: TagId = (&cfg.tag).into();
+        let mut pipe = RoutePipe::try_create_from(cfg, &mut channels).unwrap();
+
+        let mut receivers = ["infra", "app", "other"].map(|route| {
+            let who: TagId = OutboundTagId::new(route).into();
+            channels.recv_from(&tag.route(route), &who)
+        });
+        let mut sender = channels.sender(&InboundTagId::new("a").into());
+        for name in ["node_requests", "app_errors", "disk_free"] {
+            sender.send(record(name)).await.unwrap();
+        }
+        pipe.poll(CancellationToken::new()).await.unwrap();
+
+        // Each record goes to exactly one route
+        let [infra, app, other] = &mut receivers;
+        assert_eq!(
+            name(&infra.recv().await.unwrap()),
+            Some(&Value::from("node_requests"))
+        );
+        assert_eq!(
+            name(&app.recv().await.unwrap()),
+            Some(&Value::from("app_errors"))
+        );
+        assert_eq!(
+            name(&other.recv().await.unwrap()),
+            Some(&Value::from("disk_free"))
+        );
+        assert!(receivers.iter_mut().all(|r| r.try_recv().is_err()));
+    }
+
+    #[tokio::test]
+    async fn test_route_pipe_drops_unmatched() {
+        let cfg = pipe_config("");
+        let mut channels = graph(&cfg, &["infra", "app"]);
+        let PipeConfig::Route(cfg) = cfg else {
+            unreachable!()
+        };
+        let tag: TagId = (&cfg.tag).into();
+        let mut pipe = RoutePipe::try_create_from(cfg, &mut channels).unwrap();
+        let mut infra =
+            channels.recv_from(&tag.route("infra"), &OutboundTagId::new("infra").into());
+
+        let mut sender = channels.sender(&InboundTagId::new("a").into());
+        sender.send(record("disk_free")).await.unwrap();
+        sender.send(record("node_cpu")).await.unwrap();
+        pipe.poll(CancellationToken::new()).await.unwrap();
+
+        assert_eq!(
+            name(&infra.recv().await.unwrap()),
+            Some(&Value::from("node_cpu"))
+        );
+        assert!(infra.try_recv().is_err());
+    }
+}
@@ -0,0 +1,338 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    actor_debug, actor_warn,
+    config::pipe::sample::{SampleMode, SamplePipeConfig},
+    core::{
+        actor::Actor,
+        keying::KeyExtractor,
+        manager::{ChannelGraph, SendError, TaggedReceiver, TaggedSender},
+        metrics,
+        tag::{HasTag, TagId},
+        types::Record,
+    },
+    utils::{
+        recv::{self, recv_batch},
+        stats::GLOBAL_STATS,
+    },
+};
+
+use super::Pipe;
+
+/// 2^53, the integers up to which are exact as a float.
+const RATIO_SCALE: f64 = (1u64 << 53) as f64;
+
+/// Decides which records are kept, the counts start over at each interval.
+#[derive(Debug)]
+struct Sampler {
+    mode: SampleMode,
+    key: Option<KeyExtractor>,
+    ratio: f64,
+    limit: usize,
+    interval: Duration,
+    max_keys: usize,
+
+    started_at: Instant,
+    // Kept in the current interval, with `head`
+    kept: usize,
+    // Kept in the current interval by key, with `per_key`
+    kept_by_key: HashMap<u128, usize>,
+}
+
+impl Sampler {
+    fn new(cfg: &SamplePipeConfig, now: Instant) -> Self {
+        Self {
+            mode: cfg.mode,
+            key: cfg.key.clone().map(KeyExtractor::new),
+            ratio: cfg.ratio.unwrap_or(1.0),
+            limit: cfg.limit.unwrap_or(usize::MAX),
+            interval: cfg.interval.into(),
+            max_keys: cfg.max_keys,
+            started_at: now,
+            kept: 0,
+            kept_by_key: HashMap::new(),
+        }
+    }
+
+    /// The key of the record, `None` when it has none and is to be kept as it is.
+    fn key(&self, record: &Record) -> super::Result<Option<u128>> {
+        let Some(ref key) = self.key else {
+            return Ok(None);
+        };
+        key.key(record)
+            .map_err(|e| super::Error::InvalidRecord(format!("{}: {}", e, key.describe(record))))
+    }
+
+    /// Whether the record is kept.
+    fn keep(&mut self, record: &Record, now: Instant) -> super::Result<bool> {
+        if now.saturating_duration_since(self.started_at) >= self.interval {
+            self.started_at = now;
+            self.kept = 0;
+            self.kept_by_key.clear();
+        }
+
+        match self.mode {
+            SampleMode::Head => {
+                if self.kept >= self.limit {
+                    return Ok(false);
+                }
+                self.kept += 1;
+                Ok(true)
+            }
+            // The top 53 bits of the hash, uniform in [0, 1) once scaled: the same key always
+            // falls on the same side of the ratio
+            SampleMode::Ratio => Ok(self
+                .key(record)?
+                .is_none_or(|key| ((key >> 75) as f64 / RATIO_SCALE) < self.ratio)),
+            SampleMode::PerKey => {
+                let Some(key) = self.key(record)? else {
+                    return Ok(true);
+                };
+                if !self.kept_by_key.contains_key(&key) && self.kept_by_key.len() >= self.max_keys {
+                    return Ok(false);
+                }
+                let kept = self.kept_by_key.entry(key).or_default();
+                if *kept >= self.limit {
+                    return Ok(false);
+                }
+                *kept += 1;
+                Ok(true)
+            }
+        }
+    }
+}
+
+/// Forwards a sample of the records, see [`SampleMode`].
+pub struct SamplePipe {
+    tag: TagId,
+    sampler: Sampler,
+
+    inbounds: Vec<TaggedReceiver>,
+    outbound: TaggedSender,
+
+    interval: Duration,
+    buffer_size: usize,
+    max_batch_latency: Duration,
+}
+
+impl SamplePipe {
+    pub fn try_create_from(
+        cfg: SamplePipeConfig,
+        channels: &mut ChannelGraph,
+    ) -> super::Result<Self> {
+        let tag: TagId = (&cfg.tag).into();
+        let inbounds = cfg
+            .inbounds
+            .iter()
+            .map(|inbound| channels.recv_from(inbound, &tag))
+            .collect::<Vec<_>>();
+        let outbound = channels.sender(&tag);
+
+        Ok(Self::new(cfg, inbounds, outbound))
+    }
+
+    fn new(cfg: SamplePipeConfig, inbounds: Vec<TaggedReceiver>, outbound: TaggedSender) -> Self {
+        SamplePipe {
+            tag: cfg.tag.clone().into(),
+            sampler: Sampler::new(&cfg, Instant::now()),
+            inbounds,
+            outbound,
+            interval: cfg.recv_timeout.into(),
+            buffer_size: cfg.recv_buffer_size,
+            max_batch_latency: cfg.max_batch_latency.into(),
+        }
+    }
+
+    async fn send_all(&mut self, records: Vec<Record>) {
+        for record in records {
+            if let Err(e @ SendError::Closed(_)) = self.outbound.send(record).await {
+                actor_warn!(self.tag, "error sending record: {}", e);
+            }
+        }
+    }
+}
+
+impl HasTag for SamplePipe {
+    fn tag(&self) -> &TagId {
+        &self.tag
+    }
+}
+
+#[async_trait]
+impl Actor for SamplePipe {
+    type Error = super::Error;
+
+    async fn poll(&mut self, ctx: CancellationToken) -> super::Result<()> {
+        let tag = self.tag.clone();
+        let records = match recv_batch(
+            &tag,
+            &mut self.inbounds,
+            Some(self.interval),
+            self.buffer_size,
+            self.max_batch_latency,
+            ctx,
+        )
+        .await
+        {
+            Ok(records) => records,
+            Err(recv::Error::Timeout) | Err(recv::Error::Canceled) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let now = Instant::now();
+        let received = records.len();
+        let mut out = Vec::with_capacity(received);
+        for record in records {
+            match self.sampler.keep(&record, now) {
+                Ok(true) => out.push(record),
+                Ok(false) => {}
+                Err(e) => {
+                    actor_warn!(self.tag, "record not sampled: {}", e);
+                    metrics::count_transform_error(&self.tag);
+                }
+            }
+        }
+
+        let mode = self.sampler.mode;
+        let (kept, dropped) = (out.len() as u64, (received - out.len()) as u64);
+        if kept > 0 {
+            GLOBAL_STATS.incr(&format!("{} {} kept records", self.tag, mode), kept);
+        }
+        if dropped > 0 {
+            GLOBAL_STATS.incr(&format!("{} {} dropped records", self.tag, mode), dropped);
+        }
+        actor_debug!(self.tag, "kept {} of {} records", kept, received);
+
+        self.send_all(out).await;
+
+        Ok(())
+    }
+}
+
+impl Pipe for SamplePipe {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Verify,
+        core::{
+            manager::ActorChannel,
+            tag::{PipeTagId, INTERNAL_TAG_SCOPE},
+            types::{Symbol, Value},
+        },
+    };
+
+    fn config(body: &str) -> SamplePipeConfig {
+        let mut cfg: SamplePipeConfig =
+            toml::from_str(&format!("inbounds = [\"pipe:timeseries\"]\n{}", body)).unwrap();
+        cfg.verify().unwrap();
+        cfg
+    }
+
+    fn record(host: &str, seq: i64) -> Record {
+        let mut record = Record::empty();
+        record.set(Symbol::new("host"), Value::from(host));
+        record.set(Symbol::new("seq"), Value::from(seq));
+        record
+    }
+
+    #[test]
+    fn test_ratio() {
+        let cfg = config("mode = \"ratio\"\nratio = 0.25\nkey = { fields = [\"host\"] }");
+        let now = Instant::now();
+        let mut sampler = Sampler::new(&cfg, now);
+
+        let kept = (0..4000)
+            .filter(|i| {
+                sampler
+                    .keep(&record(&format!("host-{}", i), 0), now)
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert!((800..1200).contains(&kept.len()), "{}", kept.len());
+
+        // The same hosts again, later and by another sampler: the same decisions
+        let mut again = Sampler::new(&cfg, now);
+        let later = now + Duration::from_secs(3600);
+        for i in 0..4000 {
+            let keep = again
+                .keep(&record(&format!("host-{}", i), 1), later)
+                .unwrap();
+            assert_eq!(keep, kept.binary_search(&i).is_ok());
+        }
+
+        // No key, kept
+        let mut bare = Record::empty();
+        bare.set(Symbol::new("seq"), Value::from(0i64));
+        assert!(sampler.keep(&bare, now).unwrap());
+    }
+
+    #[test]
+    fn test_head() {
+        let cfg = config("mode = \"head\"\nlimit = 3\ninterval = \"10s\"");
+        let start = Instant::now();
+        let mut sampler = Sampler::new(&cfg, start);
+
+        let kept = |sampler: &mut Sampler, at: Instant| {
+            (0..5)
+                .filter(|&i| sampler.keep(&record("a", i), at).unwrap())
+                .count()
+        };
+        assert_eq!(kept(&mut sampler, start), 3);
+        assert_eq!(kept(&mut sampler, start + Duration::from_secs(9)), 0);
+        assert_eq!(kept(&mut sampler, start + Duration::from_secs(10)), 3);
+    }
+
+    #[test]
+    fn test_per_key() {
+        let cfg = config(
+            "mode = \"per_key\"\nlimit = 2\nmax_keys = 2\ninterval = \"10s\"\nkey = { fields = [\"host\"] }",
+        );
+        let start = Instant::now();
+        let mut sampler = Sampler::new(&cfg, start);
+
+        let mut keep = |host: &str, secs: u64| {
+            sampler
+                .keep(&record(host, 0), start + Duration::from_secs(secs))
+                .unwrap()
+        };
+        assert!(keep("a", 0));
+        assert!(keep("a", 0));
+        assert!(!keep("a", 0));
+        assert!(keep("b", 1));
+        // Over `max_keys`, dropped until the next interval
+        assert!(!keep("c", 1));
+        assert!(!keep("a", 9));
+        assert!(keep("c", 10));
+        assert!(keep("a", 10));
+    }
+
+    #[tokio::test]
+    async fn test_pipe() {
+        let cfg = config("mode = \"per_key\"\nlimit = 1\nkey = { fields = [\"host\"] }");
+        let tag: TagId = (&cfg.tag).into();
+        let mut input = ActorChannel::new(PipeTagId::new("timeseries").into(), 64);
+        let mut output = ActorChannel::new(tag.clone(), 64);
+        let mut received = output.receiver(&TagId::new(INTERNAL_TAG_SCOPE, "sink"));
+        let mut pipe = SamplePipe::new(cfg, vec![input.receiver(&tag)], output.sender());
+
+        let mut sender = input.sender();
+        for (seq, host) in ["a", "b", "a", "c", "b"].into_iter().enumerate() {
+            sender.send(record(host, seq as i64)).await.unwrap();
+        }
+        pipe.poll(CancellationToken::new()).await.unwrap();
+
+        let mut seqs = Vec::new();
+        while let Ok(record) = received.try_recv() {
+            seqs.push(record.get(&Symbol::new("seq")).unwrap().clone());
+        }
+        assert_eq!(seqs, [0i64, 1, 3].map(Value::from).to_vec());
+    }
+}
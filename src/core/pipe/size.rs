@@ -0,0 +1,149 @@
+use std::time::Instant;
+
+use crate::{
+    actor_warn,
+    config::{global::use_stats, pipe::RecordSizeConfig},
+    core::{
+        tag::TagId,
+        types::{Attribute, Record},
+    },
+    utils::{stats::GLOBAL_STATS, throttle::Throttle},
+};
+
+/// Observes the size of the records entering a pipe: feeds the per-inbound size histogram,
+/// warns about oversized records and optionally truncates them.
+#[derive(Debug)]
+pub struct RecordSizeObserver {
+    tag: TagId,
+    cfg: RecordSizeConfig,
+    throttle: Throttle,
+}
+
+impl RecordSizeObserver {
+    pub fn new(tag: TagId, cfg: RecordSizeConfig) -> Self {
+        let throttle = Throttle::new(cfg.record_size_warn_interval.into());
+        Self { tag, cfg, throttle }
+    }
+
+    pub fn observe(&mut self, record: &mut Record) {
+        self.observe_at(record, Instant::now());
+    }
+
+    /// Returns whether a warning has been logged for this record.
+    fn observe_at(&mut self, record: &mut Record, now: Instant) -> bool {
+        let stats = use_stats();
+        if !stats && self.cfg.warn_record_bytes.is_none() && self.cfg.enforce_record_bytes.is_none()
+        {
+            return false;
+        }
+
+        let bytes = record.estimated_bytes();
+        if stats {
+            let inbound = record
+                .get_attribute(&Attribute::Inbound)
+                .map(|v| v.to_string())
+                .unwrap_or_default();
+            let key = format!("{} record bytes <- {}", self.tag, inbound);
+            GLOBAL_STATS.observe(&key, bytes as u64);
+        }
+
+        let mut warned = false;
+        if self
+            .cfg
+            .warn_record_bytes
+            .is_some_and(|limit| bytes as u64 > limit.get())
+        {
+            if let Some(suppressed) = self.throttle.check_at(now) {
+                actor_warn!(self.tag, "record of ~{} bytes exceeds warn_record_bytes ({} similar warnings suppressed)", bytes, suppressed
+                );
+                warned = true;
+            }
+        }
+
+        if self
+            .cfg
+            .enforce_record_bytes
+            .is_some_and(|limit| bytes as u64 > limit.get())
+        {
+            let n = record.truncate_entries(self.cfg.max_record_entries);
+            if n > 0 {
+                GLOBAL_STATS.incr(&format!("{} truncated records", self.tag), 1);
+            }
+        }
+
+        warned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        config::types::{ByteSize, DurationValue},
+        core::{
+            tag::PipeTagId,
+            types::{Symbol, Value},
+        },
+    };
+
+    fn big_record() -> Record {
+        let mut record = Record::empty();
+        let values = (0..100i64).map(Value::from).collect::<Vec<_>>();
+        record.set(Symbol::from("values"), Value::Array(values));
+        record
+    }
+
+    fn new_observer(enforce: bool) -> RecordSizeObserver {
+        let cfg = RecordSizeConfig {
+            warn_record_bytes: Some(ByteSize::from(64)),
+            enforce_record_bytes: enforce.then_some(ByteSize::from(64)),
+            max_record_entries: 4,
+            record_size_warn_interval: DurationValue::from_secs(10),
+        };
+        RecordSizeObserver::new(PipeTagId::new("test").into(), cfg)
+    }
+
+    #[test]
+    fn test_warn_once_per_interval() {
+        let mut observer = new_observer(false);
+        let start = Instant::now();
+
+        let warnings = (0..100)
+            .filter(|i| observer.observe_at(&mut big_record(), start + Duration::from_millis(*i)))
+            .count();
+        assert_eq!(warnings, 1);
+
+        // next interval
+        assert!(observer.observe_at(&mut big_record(), start + Duration::from_secs(11)));
+        assert!(!observer.observe_at(&mut big_record(), start + Duration::from_secs(12)));
+    }
+
+    #[test]
+    fn test_small_record_not_warned() {
+        let mut observer = new_observer(true);
+        let mut record = Record::empty();
+        record.set(Symbol::from("a"), Value::from(1i64));
+
+        assert!(!observer.observe_at(&mut record, Instant::now()));
+        assert_eq!(record[&Symbol::from("a")], Value::from(1i64));
+    }
+
+    #[test]
+    fn test_enforce_truncates() {
+        let mut observer = new_observer(true);
+        let mut record = big_record();
+        observer.observe_at(&mut record, Instant::now());
+
+        let values = record[&Symbol::from("values")].array().unwrap();
+        assert_eq!(values.len(), 5);
+        assert_eq!(values.get(3), Some(&Value::from(3i64)));
+
+        // without enforce_record_bytes, records are left untouched
+        let mut observer = new_observer(false);
+        let mut record = big_record();
+        observer.observe_at(&mut record, Instant::now());
+        assert_eq!(record[&Symbol::from("values")].array().unwrap().len(), 100);
+    }
+}
@@ -0,0 +1,352 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    actor_debug, actor_warn,
+    config::pipe::throttle::{ThrottleOverflow, ThrottlePipeConfig},
+    core::{
+        actor::Actor,
+        manager::{ChannelGraph, SendError, TaggedReceiver, TaggedSender},
+        tag::{HasTag, TagId},
+        types::Record,
+    },
+    utils::{
+        recv::{self, recv_batch},
+        stats::GLOBAL_STATS,
+        throttle::Throttle,
+    },
+};
+
+use super::Pipe;
+
+/// Tokens are refilled at `rate` per second up to `burst`, a record takes one.
+///
+/// The time is that of the monotonic clock, which stands still while the host is
+/// suspended: resuming does not refill the bucket for the time spent asleep, and it never
+/// holds more than `burst` tokens anyway.
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    /// Full at first, the first `burst` records go through at once.
+    fn new(rate: f64, burst: usize, now: Instant) -> Self {
+        Self {
+            rate,
+            burst: burst as f64,
+            tokens: burst as f64,
+            refilled_at: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.burst);
+        self.refilled_at = self.refilled_at.max(now);
+    }
+
+    /// Take up to `wanted` tokens, returns how many were taken.
+    fn take(&mut self, wanted: usize, now: Instant) -> usize {
+        self.refill(now);
+        let taken = (self.tokens.floor() as usize).min(wanted);
+        self.tokens -= taken as f64;
+        taken
+    }
+
+    /// How long until the next token, as of the last refill. Forever at a rate too small
+    /// for the wait to fit in a `Duration`, which tokio sleeps as long as it can.
+    fn next_token_in(&self) -> Duration {
+        let missing = (1.0 - self.tokens).max(0.0);
+        Duration::try_from_secs_f64(missing / self.rate).unwrap_or(Duration::MAX)
+    }
+}
+
+/// Forwards at most `rate` records per second, after a burst of `burst` records. The records
+/// over the rate are dropped or held, see [`ThrottleOverflow`].
+pub struct ThrottlePipe {
+    tag: TagId,
+    bucket: TokenBucket,
+    overflow: ThrottleOverflow,
+    // Received but not forwarded yet, with `overflow = "backpressure"`
+    held: VecDeque<Record>,
+
+    // Dropped since the last warning
+    dropped: usize,
+    drop_throttle: Throttle,
+
+    inbounds: Vec<TaggedReceiver>,
+    outbound: TaggedSender,
+
+    interval: Duration,
+    buffer_size: usize,
+    max_batch_latency: Duration,
+}
+
+impl ThrottlePipe {
+    pub fn try_create_from(
+        cfg: ThrottlePipeConfig,
+        channels: &mut ChannelGraph,
+    ) -> super::Result<Self> {
+        let tag: TagId = (&cfg.tag).into();
+        let inbounds = cfg
+            .inbounds
+            .iter()
+            .map(|inbound| channels.recv_from(inbound, &tag))
+            .collect::<Vec<_>>();
+        let outbound = channels.sender(&tag);
+
+        Ok(Self::new(cfg, inbounds, outbound))
+    }
+
+    fn new(cfg: ThrottlePipeConfig, inbounds: Vec<TaggedReceiver>, outbound: TaggedSender) -> Self {
+        let burst = cfg.burst.unwrap_or(cfg.rate.ceil() as usize).max(1);
+        ThrottlePipe {
+            tag: cfg.tag.into(),
+            bucket: TokenBucket::new(cfg.rate, burst, Instant::now()),
+            overflow: cfg.overflow,
+            held: VecDeque::new(),
+            dropped: 0,
+            drop_throttle: Throttle::new(cfg.drop_warn_interval.into()),
+            inbounds,
+            outbound,
+            interval: cfg.recv_timeout.into(),
+            buffer_size: cfg.recv_buffer_size,
+            max_batch_latency: cfg.max_batch_latency.into(),
+        }
+    }
+
+    /// Forward as many held records as there are tokens.
+    async fn forward_held(&mut self) {
+        let allowed = self.bucket.take(self.held.len(), Instant::now());
+        let records = self.held.drain(..allowed).collect::<Vec<_>>();
+        self.send_all(records).await;
+    }
+
+    async fn send_all(&mut self, records: Vec<Record>) {
+        for record in records {
+            if let Err(e @ SendError::Closed(_)) = self.outbound.send(record).await {
+                actor_warn!(self.tag, "error sending record: {}", e);
+            }
+        }
+    }
+
+    fn warn_dropped(&mut self) {
+        if self.dropped == 0 {
+            return;
+        }
+        if let Some(suppressed) = self.drop_throttle.check_at(Instant::now()) {
+            actor_warn!(
+                self.tag,
+                "dropped {} records over the rate ({} similar warnings suppressed)",
+                self.dropped,
+                suppressed
+            );
+            self.dropped = 0;
+        }
+    }
+}
+
+impl HasTag for ThrottlePipe {
+    fn tag(&self) -> &TagId {
+        &self.tag
+    }
+}
+
+#[async_trait]
+impl Actor for ThrottlePipe {
+    type Error = super::Error;
+
+    async fn poll(&mut self, ctx: CancellationToken) -> super::Result<()> {
+        // Nothing is received until the held records are forwarded
+        if !self.held.is_empty() {
+            if ctx.is_cancelled() {
+                actor_debug!(self.tag, "forwarding {} held records", self.held.len());
+                let records = self.held.drain(..).collect();
+                self.send_all(records).await;
+                return Ok(());
+            }
+
+            self.forward_held().await;
+            if !self.held.is_empty() {
+                tokio::select! {
+                    _ = tokio::time::sleep(self.bucket.next_token_in()) => {}
+                    _ = ctx.cancelled() => {}
+                }
+            }
+            return Ok(());
+        }
+
+        let tag = self.tag.clone();
+        let records = match recv_batch(
+            &tag,
+            &mut self.inbounds,
+            Some(self.interval),
+            self.buffer_size,
+            self.max_batch_latency,
+            ctx,
+        )
+        .await
+        {
+            Ok(records) => records,
+            Err(recv::Error::Timeout) | Err(recv::Error::Canceled) => {
+                self.warn_dropped();
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        match self.overflow {
+            ThrottleOverflow::Backpressure => {
+                self.held.extend(records);
+                self.forward_held().await;
+            }
+            ThrottleOverflow::Drop => {
+                let mut records = records;
+                let allowed = self.bucket.take(records.len(), Instant::now());
+                let dropped = records.len() - allowed;
+                records.truncate(allowed);
+                self.send_all(records).await;
+
+                if dropped > 0 {
+                    self.dropped += dropped;
+                    GLOBAL_STATS.incr(&format!("{} dropped records", self.tag), dropped as u64);
+                }
+                self.warn_dropped();
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Pipe for ThrottlePipe {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Verify,
+        core::{
+            manager::ActorChannel,
+            tag::{PipeTagId, INTERNAL_TAG_SCOPE},
+            types::{Symbol, Value},
+        },
+    };
+
+    #[test]
+    fn test_bucket_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(200.0, 20, start);
+
+        // A producer always ahead of the rate, simulated over a second in 1ms steps
+        let mut forwarded = 0;
+        for ms in 0..1000 {
+            forwarded += bucket.take(50, start + Duration::from_millis(ms));
+        }
+        // The burst, then the rate
+        assert!((215..=221).contains(&forwarded), "{}", forwarded);
+
+        // Idle for a while, the bucket only fills up to the burst
+        let later = start + Duration::from_secs(3600);
+        assert_eq!(bucket.take(1000, later), 20);
+        assert_eq!(bucket.take(1, later), 0);
+        assert_eq!(bucket.next_token_in(), Duration::from_millis(5));
+
+        // An earlier time adds nothing, nor does it count twice
+        assert_eq!(bucket.take(1, later - Duration::from_secs(1)), 0);
+        assert_eq!(bucket.take(1, later + Duration::from_millis(4)), 0);
+        assert_eq!(bucket.take(1, later + Duration::from_millis(6)), 1);
+    }
+
+    #[test]
+    fn test_bucket_tiny_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(f64::MIN_POSITIVE, 1, start);
+        assert_eq!(bucket.take(2, start), 1);
+        assert_eq!(bucket.next_token_in(), Duration::MAX);
+    }
+
+    fn pipe(body: &str) -> (ThrottlePipe, TaggedSender, TaggedReceiver) {
+        let mut cfg: ThrottlePipeConfig =
+            toml::from_str(&format!("inbounds = [\"pipe:timeseries\"]\n{}", body)).unwrap();
+        cfg.verify().unwrap();
+        let tag: TagId = (&cfg.tag).into();
+
+        let mut input = ActorChannel::new(PipeTagId::new("timeseries").into(), 64);
+        let mut output = ActorChannel::new(tag.clone(), 64);
+        let received = output.receiver(&TagId::new(INTERNAL_TAG_SCOPE, "sink"));
+        let pipe = ThrottlePipe::new(cfg, vec![input.receiver(&tag)], output.sender());
+        (pipe, input.sender(), received)
+    }
+
+    fn record(seq: i64) -> Record {
+        let mut record = Record::empty();
+        record.set(Symbol::new("seq"), Value::from(seq));
+        record
+    }
+
+    fn drain(receiver: &mut TaggedReceiver) -> Vec<Value> {
+        let mut seqs = Vec::new();
+        while let Ok(record) = receiver.try_recv() {
+            seqs.push(record.get(&Symbol::new("seq")).unwrap().clone());
+        }
+        seqs
+    }
+
+    #[tokio::test]
+    async fn test_drop() {
+        let (mut pipe, mut sender, mut received) = pipe("rate = 1\nburst = 5\noverflow = \"drop\"");
+        for seq in 0..10 {
+            sender.send(record(seq)).await.unwrap();
+        }
+        pipe.poll(CancellationToken::new()).await.unwrap();
+
+        assert_eq!(
+            drain(&mut received),
+            (0..5).map(Value::from).collect::<Vec<_>>()
+        );
+        assert!(pipe.held.is_empty());
+        // Logged at once, then counted until the next warning
+        assert_eq!(pipe.dropped, 0);
+    }
+
+    #[tokio::test]
+    async fn test_backpressure() {
+        let (mut pipe, mut sender, mut received) = pipe("rate = 50\nburst = 5");
+        for seq in 0..10 {
+            sender.send(record(seq)).await.unwrap();
+        }
+        let ctx = CancellationToken::new();
+        pipe.poll(ctx.clone()).await.unwrap();
+        assert_eq!(drain(&mut received).len(), 5);
+        assert_eq!(pipe.held.len(), 5);
+
+        // Sleeps until the next token, about 20ms away
+        let start = std::time::Instant::now();
+        pipe.poll(ctx.clone()).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(10));
+        pipe.poll(ctx.clone()).await.unwrap();
+        let forwarded = drain(&mut received);
+        assert!(
+            !forwarded.is_empty() && forwarded.len() < 5,
+            "{:?}",
+            forwarded
+        );
+
+        // The rest is forwarded on cancellation, in order
+        ctx.cancel();
+        pipe.poll(ctx).await.unwrap();
+        let rest = drain(&mut received);
+        assert_eq!(forwarded.len() + rest.len(), 5);
+        assert_eq!(rest.last(), Some(&Value::from(9i64)));
+    }
+}
@@ -1,45 +1,76 @@
-use std::{ops::Deref, sync::Arc, time::Duration};
+use std::{collections::VecDeque, ops::Deref, sync::Arc, time::Duration};
 
 use async_trait::async_trait;
 use dashmap::{DashMap, DashSet};
-use log::{error, info};
+use log::info;
 use once_cell::sync::Lazy;
 
 use crate::{
-    config::pipe::timeseries::TimeseriesAnnotatePipeConfig,
+    actor_debug, actor_error, actor_info,
+    config::{
+        global,
+        pipe::timeseries::{annotate::MissingKeyPolicy, TimeseriesAnnotatePipeConfig},
+    },
     core::{
         actor::Actor,
-        manager::{ChannelGraph, TaggedReceiver, TaggedSender},
-        pipe::Pipe,
+        dead_letter::DeadLetter,
+        manager::{ChannelGraph, SendError, TaggedReceiver, TaggedSender},
+        metrics,
+        pipe::{LabelPolicy, Pipe, RecordSizeObserver},
         tag::{HasTag, TagId},
         types::{Record, Symbol, Value},
     },
-    utils::recv::{recv, recv_batch},
+    utils::{
+        budget::{drain_carry, PollBudget},
+        recv::{recv, recv_batch},
+        stats::GLOBAL_STATS,
+    },
 };
 
-use super::{LABELS_FIELD, LABELS_FIELD_STR};
+use super::{lookup::LabelLookup, LABELS_FIELD, LABELS_FIELD_STR};
 
 #[derive(Debug)]
 struct InnerState {
     tag: TagId,
     labels_to_add: DashMap<Symbol, Value>,
     labels_to_remove: DashSet<Symbol>,
+    label_policy: Option<LabelPolicy>,
 }
 
 impl InnerState {
-    fn new(tag: TagId) -> Self {
+    fn new(tag: TagId, label_policy: Option<LabelPolicy>) -> Self {
         Self {
             tag,
             labels_to_add: DashMap::new(),
             labels_to_remove: DashSet::new(),
+            label_policy,
         }
     }
 
-    fn transform(&self, mut record: Record) -> super::Result<Record> {
-        let mut labels = record
+    /// `None` when the record is dropped for lacking a mapping.
+    fn transform(
+        &self,
+        mut record: Record,
+        lookup: Option<&LabelLookup>,
+    ) -> super::Result<Option<Record>> {
+        let looked_up = match lookup {
+            Some(lookup) => match lookup.labels(&record) {
+                Some(labels) => labels,
+                None if lookup.on_missing() == MissingKeyPolicy::Drop => return Ok(None),
+                None => &[],
+            },
+            None => &[],
+        };
+
+        let labels_value = record
             .get_mut(&LABELS_FIELD)
-            .ok_or_else(|| super::Error::FieldNotFound(LABELS_FIELD_STR))?
-            .map_mut()?;
+            .ok_or_else(|| super::Error::FieldNotFound(LABELS_FIELD_STR))?;
+        let mut labels = labels_value.map_mut()?;
+
+        // The static labels take precedence over the looked up ones
+        for (name, value) in looked_up {
+            labels.set(name.clone(), value.clone());
+        }
 
         for label in self.labels_to_add.iter() {
             let name = label.key().into();
@@ -53,7 +84,12 @@ impl InnerState {
             let _ = labels.remove(&name);
         }
 
-        Ok(record)
+        // The labels set by the control actions are subject to the policy as well
+        if let Some(ref policy) = self.label_policy {
+            policy.apply(labels_value)?;
+        }
+
+        Ok(Some(record))
     }
 
     fn handle_action(&self, record: Record) -> super::Result<()> {
@@ -141,11 +177,20 @@ pub struct TimeseriesAnnotatePipe {
     control_inbounds: Vec<TaggedReceiver>,
 
     inner: Arc<InnerState>,
+    lookup: Option<LabelLookup>,
 
     outbound: TaggedSender,
+    dead_letter: DeadLetter,
+
+    size_observer: RecordSizeObserver,
+
+    // Received but not yet transformed, left over by a poll which ran out of budget
+    carry: VecDeque<Record>,
+    max_poll_duration: Duration,
 
     interval: Duration,
     buffer_size: usize,
+    max_batch_latency: Duration,
 }
 
 pub const ACTION_FIELD_STR: &str = "action";
@@ -156,11 +201,11 @@ pub static ACTION_FIELD: Lazy<Symbol> = Lazy::new(|| Symbol::intern(ACTION_FIELD
 pub static NAME_FIELD: Lazy<Symbol> = Lazy::new(|| Symbol::intern(NAME_FIELD_STR));
 pub static VALUE_FIELD: Lazy<Symbol> = Lazy::new(|| Symbol::intern(VALUE_FIELD_STR));
 
-pub const ACTION_SET: &'static str = "set";
-pub const ACTION_UNSET: &'static str = "unset";
-pub const ACTION_DELETE: &'static str = "delete";
-pub const ACTION_UNDELETE: &'static str = "undelete";
-pub const ACTION_CLEAR: &'static str = "clear";
+pub const ACTION_SET: &str = "set";
+pub const ACTION_UNSET: &str = "unset";
+pub const ACTION_DELETE: &str = "delete";
+pub const ACTION_UNDELETE: &str = "undelete";
+pub const ACTION_CLEAR: &str = "clear";
 
 impl TimeseriesAnnotatePipe {
     pub fn try_create_from(
@@ -178,43 +223,93 @@ impl TimeseriesAnnotatePipe {
             .map(|inbound| channels.recv_from(inbound, &cfg.tag))
             .collect::<Vec<_>>();
         let outbound = channels.sender(&cfg.tag);
-        let inner = Arc::new(InnerState::new((&cfg.tag).into()));
+        let dead_letter = channels.dead_letter(&(&cfg.tag).into());
+
+        Self::new(cfg, data_inbounds, control_inbounds, outbound, dead_letter)
+    }
+
+    fn new(
+        cfg: TimeseriesAnnotatePipeConfig,
+        data_inbounds: Vec<TaggedReceiver>,
+        control_inbounds: Vec<TaggedReceiver>,
+        outbound: TaggedSender,
+        dead_letter: DeadLetter,
+    ) -> super::Result<Self> {
+        let tag: TagId = (&cfg.tag).into();
+        let label_policy = LabelPolicy::resolve(&tag, cfg.label_policy)?;
+        let inner = Arc::new(InnerState::new(tag, label_policy));
+        let lookup = cfg.lookup.map(LabelLookup::load).transpose()?;
+        let size_observer = RecordSizeObserver::new((&cfg.tag).into(), cfg.record_size);
 
         let pipe = TimeseriesAnnotatePipe {
             tag: cfg.tag.into(),
             data_inbounds,
             control_inbounds,
             inner,
+            lookup,
             outbound,
-            interval: cfg.recv_timeout,
+            dead_letter,
+            size_observer,
+            carry: VecDeque::new(),
+            max_poll_duration: global::max_poll_duration().pipe.into(),
+            interval: cfg.recv_timeout.into(),
             buffer_size: cfg.recv_buffer_size,
+            max_batch_latency: cfg.max_batch_latency.into(),
         };
 
         Ok(pipe)
     }
 
-    fn transform_records(&mut self, records: Vec<Record>) -> super::Result<()> {
+    async fn transform_records(&mut self, budget: &PollBudget) -> super::Result<()> {
         let inner = self.inner.clone();
-        let outbound = &mut self.outbound;
-
-        // transform records
-        let transformed_records: Vec<_> = records
-            .into_iter()
-            .filter_map(|record| match inner.transform(record) {
-                Ok(record) => Some(record),
+        let lookup = self.lookup.as_ref();
+        let mut out = Vec::new();
+        let size_observer = &mut self.size_observer;
+        let dead_letter = &mut self.dead_letter;
+        let mut dropped = 0;
+
+        drain_carry(&mut self.carry, budget, |mut record| {
+            size_observer.observe(&mut record);
+            let original = dead_letter.is_enabled().then(|| record.clone());
+            let record = match inner.transform(record, lookup) {
+                Ok(Some(record)) => record,
+                Ok(None) => {
+                    dropped += 1;
+                    return;
+                }
                 Err(e) => {
-                    error!("{}: failed to transform record: {:?}", inner.tag, e);
-                    None
+                    actor_error!(inner.tag, "failed to transform record: {:?}", e);
+                    metrics::count_transform_error(&inner.tag);
+                    if let Some(original) = original {
+                        dead_letter.send(original, &e);
+                    }
+                    return;
                 }
-            })
-            .collect();
+            };
 
-        transformed_records.into_iter().for_each(|r| {
-            if let Err(e) = outbound.send(r) {
-                error!("{}: failed to send record: {:?}", inner.tag, e);
-            }
+            out.push(record);
         });
 
+        // Sent once transformed, the channel may have to wait for room
+        for record in out {
+            if let Err(e @ SendError::Closed(_)) = self.outbound.send(record).await {
+                actor_error!(self.tag, "failed to send record: {}", e);
+            }
+        }
+
+        if dropped > 0 {
+            actor_debug!(self.tag, "dropped {} records without a mapping", dropped);
+            GLOBAL_STATS.incr(&format!("{} unmapped records", self.tag), dropped);
+        }
+
+        if !self.carry.is_empty() {
+            actor_debug!(
+                self.tag,
+                "poll budget exhausted, {} records carried over",
+                self.carry.len()
+            );
+        }
+
         Ok(())
     }
 }
@@ -234,6 +329,17 @@ impl Actor for TimeseriesAnnotatePipe {
         ctx: tokio_util::sync::CancellationToken,
     ) -> std::result::Result<(), super::Error> {
         let tag = self.tag().clone();
+
+        if let Some(ref mut lookup) = self.lookup {
+            lookup.refresh();
+        }
+
+        // Finish the records left over by the last poll before receiving new ones
+        if !self.carry.is_empty() {
+            let budget = PollBudget::new(self.max_poll_duration, ctx);
+            return self.transform_records(&budget).await;
+        }
+
         let control_inbounds = &mut self.control_inbounds;
         let data_inbounds = &mut self.data_inbounds;
 
@@ -245,11 +351,14 @@ impl Actor for TimeseriesAnnotatePipe {
                 data_inbounds,
                 Some(self.interval),
                 self.buffer_size,
+                self.max_batch_latency,
                 ctx.clone(),
             ) => {
                 match records {
                     Ok(records) => {
-                        self.transform_records(records)?;
+                        self.carry.extend(records);
+                        let budget = PollBudget::new(self.max_poll_duration, ctx.clone());
+                        self.transform_records(&budget).await?;
                     }
                     Err(crate::utils::recv::Error::Timeout) => {}
                     Err(e) => return Err(e.into()),
@@ -258,22 +367,173 @@ impl Actor for TimeseriesAnnotatePipe {
             control_record = recv(&tag, control_inbounds, None, ctx.clone()) => match control_record {
                 Ok(record) => {
                     if let Err(e) = self.inner.handle_action(record) {
-                        error!("{}: failed to handle action record: {:?}", tag, e);
+                        actor_error!(tag, "failed to handle action record: {:?}", e);
                     }
                 }
                 Err(crate::utils::recv::Error::Timeout) => {}
                 Err(e) => {
-                    error!("{}: failed to receive control record: {:?}", tag, e);
+                    actor_error!(tag, "failed to receive control record: {:?}", e);
                 }
             },
             _ = ctx.cancelled() => {
-                info!("{}: cancelled", tag);
+                actor_info!(tag, "cancelled");
                 return Ok(());
             }
         }
 
         Ok(())
     }
+
+    fn has_pending(&self) -> bool {
+        !self.carry.is_empty()
+    }
 }
 
 impl Pipe for TimeseriesAnnotatePipe {}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use tokio_util::sync::CancellationToken;
+
+    use super::*;
+    use crate::core::{
+        manager::ActorChannel,
+        tag::{InboundTagId, PipeTagId},
+    };
+
+    fn record(host: &str) -> Record {
+        let mut record = Record::empty();
+        record.set(
+            LABELS_FIELD.clone(),
+            Value::from(vec![(Symbol::new("host"), Value::from(host))]),
+        );
+        record
+    }
+
+    fn label(record: &Record, name: &str) -> Option<String> {
+        record
+            .get(&LABELS_FIELD)
+            .unwrap()
+            .map()
+            .unwrap()
+            .get(&Value::from(name))
+            .map(ToString::to_string)
+    }
+
+    fn config(path: &Path) -> TimeseriesAnnotatePipeConfig {
+        toml::from_str(&format!(
+            r#"
+            data_inbounds = ["inbound:data"]
+            control_inbounds = ["inbound:control"]
+            lookup = {{ key_field = "host", mapping_file = {:?}, mapping_format = "json", refresh_interval = "10ms", on_missing = "drop" }}
+            "#,
+            path
+        ))
+        .unwrap()
+    }
+
+    /// Past the refresh interval, read the file again and wait for it.
+    async fn refresh(pipe: &mut TimeseriesAnnotatePipe) {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let lookup = pipe.lookup.as_mut().unwrap();
+        lookup.refresh();
+        lookup.wait_pending().await;
+    }
+
+    #[tokio::test]
+    async fn test_lookup_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hosts.json");
+        std::fs::write(&path, r#"{"web01": {"rack": "r1"}}"#).unwrap();
+
+        let cfg = config(&path);
+        let tag: TagId = (&cfg.tag).into();
+        let mut data = ActorChannel::new(InboundTagId::new("data").into(), 16);
+        let mut control = ActorChannel::new(InboundTagId::new("control").into(), 16);
+        let mut output = ActorChannel::new(tag.clone(), 16);
+        let mut received = output.receiver(&PipeTagId::new("next").into());
+        let mut pipe = TimeseriesAnnotatePipe::new(
+            cfg,
+            vec![data.receiver(&tag)],
+            vec![control.receiver(&tag)],
+            output.sender(),
+            DeadLetter::new(tag, None),
+        )
+        .unwrap();
+        let mut sender = data.sender();
+        let ctx = CancellationToken::new();
+
+        sender.send(record("web01")).await.unwrap();
+        // Not in the mapping, dropped
+        sender.send(record("web02")).await.unwrap();
+        pipe.poll(ctx.clone()).await.unwrap();
+        let annotated = received.recv().await.unwrap();
+        assert_eq!(label(&annotated, "rack").as_deref(), Some("r1"));
+        assert_eq!(label(&annotated, "host").as_deref(), Some("web01"));
+        assert!(received.try_recv().is_err());
+
+        std::fs::write(
+            &path,
+            r#"{"web01": {"rack": "r2", "datacenter": "eu"}, "web02": {"rack": "r3"}}"#,
+        )
+        .unwrap();
+        refresh(&mut pipe).await;
+
+        sender.send(record("web01")).await.unwrap();
+        sender.send(record("web02")).await.unwrap();
+        pipe.poll(ctx.clone()).await.unwrap();
+        let annotated = received.recv().await.unwrap();
+        assert_eq!(label(&annotated, "rack").as_deref(), Some("r2"));
+        assert_eq!(label(&annotated, "datacenter").as_deref(), Some("eu"));
+        let annotated = received.recv().await.unwrap();
+        assert_eq!(label(&annotated, "rack").as_deref(), Some("r3"));
+
+        // A broken file keeps the previous mapping
+        std::fs::write(&path, "{").unwrap();
+        refresh(&mut pipe).await;
+        refresh(&mut pipe).await;
+
+        sender.send(record("web02")).await.unwrap();
+        pipe.poll(ctx).await.unwrap();
+        let annotated = received.recv().await.unwrap();
+        assert_eq!(label(&annotated, "rack").as_deref(), Some("r3"));
+    }
+
+    #[test]
+    fn test_lookup_pass_through() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hosts.csv");
+        std::fs::write(&path, "host,rack\nweb01,r1\n").unwrap();
+
+        let mut cfg = config(&path);
+        let lookup = cfg.lookup.as_mut().unwrap();
+        lookup.mapping_format = crate::config::pipe::timeseries::annotate::MappingFormat::Csv;
+        lookup.on_missing = MissingKeyPolicy::Pass;
+        let lookup = LabelLookup::load(cfg.lookup.unwrap()).unwrap();
+
+        let inner = InnerState::new(PipeTagId::new("annotate").into(), None);
+        inner
+            .labels_to_add
+            .insert(Symbol::new("rack"), Value::from("static"));
+
+        let annotated = inner
+            .transform(record("web02"), Some(&lookup))
+            .unwrap()
+            .unwrap();
+        assert_eq!(label(&annotated, "rack").as_deref(), Some("static"));
+
+        inner.labels_to_add.clear();
+        let annotated = inner
+            .transform(record("web01"), Some(&lookup))
+            .unwrap()
+            .unwrap();
+        assert_eq!(label(&annotated, "rack").as_deref(), Some("r1"));
+        let annotated = inner
+            .transform(record("web02"), Some(&lookup))
+            .unwrap()
+            .unwrap();
+        assert_eq!(label(&annotated, "rack"), None);
+    }
+}
@@ -0,0 +1,314 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Instant,
+};
+
+use crate::{
+    config::pipe::timeseries::MetricType,
+    core::{
+        tag::TagId,
+        types::{conv::prometheus::ExtraLabel, Value},
+    },
+    utils::stats::GLOBAL_STATS,
+};
+
+/// Observations kept per summary series to compute its quantiles
+const SUMMARY_WINDOW: usize = 1024;
+
+/// Series kept per pipe, the least recently observed one is evicted beyond
+const MAX_SERIES: usize = 10_000;
+
+/// A sample of a histogram or a summary: the suffix of the metric name, the label telling
+/// apart its series and the value.
+pub type DistributionSample = (&'static str, Option<ExtraLabel>, f64);
+
+#[derive(Debug)]
+enum State {
+    // Cumulative, one per bucket boundary
+    Histogram(Vec<u64>),
+    Summary {
+        // In the order observed, to evict the oldest
+        window: VecDeque<f64>,
+        sorted: Vec<f64>,
+    },
+}
+
+impl State {
+    fn new(metric_type: &MetricType) -> Self {
+        match metric_type {
+            MetricType::Histogram { buckets } => State::Histogram(vec![0; buckets.len()]),
+            _ => State::Summary {
+                window: VecDeque::with_capacity(SUMMARY_WINDOW),
+                sorted: Vec::with_capacity(SUMMARY_WINDOW),
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Distribution {
+    state: State,
+    sum: f64,
+    count: u64,
+    last_seen: Instant,
+}
+
+/// The histograms and summaries of a pipe, which aggregate the observations of each series
+/// (a metric name and its labels) like the Prometheus clients do.
+#[derive(Debug)]
+pub struct Distributions {
+    tag: TagId,
+    series: HashMap<(String, u64), Distribution>,
+}
+
+impl Distributions {
+    pub fn new(tag: TagId) -> Self {
+        Distributions {
+            tag,
+            series: HashMap::new(),
+        }
+    }
+
+    /// Add an observation to the series `name{labels}`, returns all of its samples.
+    pub fn observe(
+        &mut self,
+        name: &str,
+        labels: &Value,
+        metric_type: &MetricType,
+        value: f64,
+    ) -> super::super::Result<Vec<DistributionSample>> {
+        let key = (name.to_string(), labels.content_hash());
+        if !self.series.contains_key(&key) && self.series.len() >= MAX_SERIES {
+            self.evict_least_recent();
+        }
+
+        let dist = self.series.entry(key).or_insert_with(|| Distribution {
+            state: State::new(metric_type),
+            sum: 0.0,
+            count: 0,
+            last_seen: Instant::now(),
+        });
+
+        // The buckets or the quantiles of a series can't change
+        let matches = match (&dist.state, metric_type) {
+            (State::Histogram(counts), MetricType::Histogram { buckets }) => {
+                counts.len() == buckets.len()
+            }
+            (State::Summary { .. }, MetricType::Summary { .. }) => true,
+            _ => false,
+        };
+        if !matches {
+            GLOBAL_STATS.incr(&format!("{} distribution type changes", self.tag), 1);
+            return Err(super::super::Error::InvalidRecord(format!(
+                "{} is observed as a {} with other buckets or type than before",
+                name,
+                metric_type.as_ref()
+            )));
+        }
+
+        dist.sum += value;
+        dist.count += 1;
+        dist.last_seen = Instant::now();
+
+        let mut samples = match (&mut dist.state, metric_type) {
+            (State::Histogram(counts), MetricType::Histogram { buckets }) => {
+                let mut samples = Vec::with_capacity(buckets.len() + 3);
+                for (count, bound) in counts.iter_mut().zip(buckets) {
+                    if value <= *bound {
+                        *count += 1;
+                    }
+                    samples.push(("_bucket", Some(ExtraLabel::LessThan(*bound)), *count as f64));
+                }
+                samples.push((
+                    "_bucket",
+                    Some(ExtraLabel::LessThan(f64::INFINITY)),
+                    dist.count as f64,
+                ));
+                samples
+            }
+            (State::Summary { window, sorted }, MetricType::Summary { quantiles }) => {
+                if window.len() == SUMMARY_WINDOW {
+                    if let Some(oldest) = window.pop_front() {
+                        let i = sorted.partition_point(|v| v.total_cmp(&oldest).is_lt());
+                        sorted.remove(i);
+                    }
+                }
+                window.push_back(value);
+                let i = sorted.partition_point(|v| v.total_cmp(&value).is_lt());
+                sorted.insert(i, value);
+
+                quantiles
+                    .iter()
+                    .map(|&q| {
+                        // Nearest rank
+                        let rank = (q * sorted.len() as f64).ceil() as usize;
+                        let value = sorted[rank.clamp(1, sorted.len()) - 1];
+                        ("", Some(ExtraLabel::Quantile(q)), value)
+                    })
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+
+        samples.push(("_sum", None, dist.sum));
+        samples.push(("_count", None, dist.count as f64));
+        Ok(samples)
+    }
+
+    fn evict_least_recent(&mut self) {
+        let oldest = self
+            .series
+            .iter()
+            .min_by_key(|(_, dist)| dist.last_seen)
+            .map(|(key, _)| key.clone());
+        if let Some(key) = oldest {
+            self.series.remove(&key);
+            GLOBAL_STATS.incr(&format!("{} evicted distribution series", self.tag), 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tag::PipeTagId;
+
+    fn labels(zone: &str) -> Value {
+        [(Value::from("zone"), Value::from(zone))]
+            .into_iter()
+            .collect()
+    }
+
+    fn render(samples: &[DistributionSample]) -> Vec<String> {
+        samples
+            .iter()
+            .map(|(suffix, label, value)| match label {
+                Some(label) => {
+                    format!("{}{{{}={}}} {}", suffix, label.name(), label.value(), value)
+                }
+                None => format!("{} {}", suffix, value),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_histogram() {
+        let histogram = MetricType::Histogram {
+            buckets: vec![0.1, 1.0, 10.0],
+        };
+        let mut dists = Distributions::new(PipeTagId::new("timeseries").into());
+
+        dists
+            .observe("latency_ms", &labels("a"), &histogram, 0.5)
+            .unwrap();
+        dists
+            .observe("latency_ms", &labels("a"), &histogram, 0.1)
+            .unwrap();
+        // Another series
+        dists
+            .observe("latency_ms", &labels("b"), &histogram, 0.05)
+            .unwrap();
+        let samples = dists
+            .observe("latency_ms", &labels("a"), &histogram, 20.0)
+            .unwrap();
+
+        assert_eq!(
+            render(&samples),
+            vec![
+                "_bucket{le=0.1} 1",
+                "_bucket{le=1} 2",
+                "_bucket{le=10} 2",
+                "_bucket{le=+Inf} 3",
+                "_sum 20.6",
+                "_count 3",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_summary() {
+        let summary = MetricType::Summary {
+            quantiles: vec![0.0, 0.5, 0.9, 1.0],
+        };
+        let mut dists = Distributions::new(PipeTagId::new("timeseries").into());
+
+        let mut samples = Vec::new();
+        for value in (1..=10).rev() {
+            samples = dists
+                .observe("latency_ms", &labels("a"), &summary, value as f64)
+                .unwrap();
+        }
+        assert_eq!(
+            render(&samples),
+            vec![
+                "{quantile=0} 1",
+                "{quantile=0.5} 5",
+                "{quantile=0.9} 9",
+                "{quantile=1} 10",
+                "_sum 55",
+                "_count 10",
+            ]
+        );
+
+        // Only the most recent observations make up the quantiles
+        for _ in 0..SUMMARY_WINDOW {
+            samples = dists
+                .observe("latency_ms", &labels("a"), &summary, 100.0)
+                .unwrap();
+        }
+        assert_eq!(render(&samples)[0], "{quantile=0} 100");
+    }
+
+    #[test]
+    fn test_type_change() {
+        let histogram = MetricType::Histogram {
+            buckets: vec![0.1, 1.0],
+        };
+        let summary = MetricType::Summary {
+            quantiles: vec![0.5],
+        };
+        let mut dists = Distributions::new(PipeTagId::new("timeseries").into());
+
+        dists
+            .observe("latency_ms", &labels("a"), &histogram, 0.5)
+            .unwrap();
+        assert!(dists
+            .observe("latency_ms", &labels("a"), &summary, 0.5)
+            .is_err());
+        let other_buckets = MetricType::Histogram {
+            buckets: vec![0.1, 1.0, 10.0],
+        };
+        assert!(dists
+            .observe("latency_ms", &labels("a"), &other_buckets, 0.5)
+            .is_err());
+        // The series is left as it was
+        let samples = dists
+            .observe("latency_ms", &labels("a"), &histogram, 0.5)
+            .unwrap();
+        assert_eq!(render(&samples).last().unwrap(), "_count 2");
+    }
+
+    #[test]
+    fn test_max_series() {
+        let histogram = MetricType::Histogram { buckets: vec![1.0] };
+        let mut dists = Distributions::new(PipeTagId::new("timeseries").into());
+
+        for i in 0..MAX_SERIES {
+            dists
+                .observe("latency_ms", &labels(&i.to_string()), &histogram, 0.5)
+                .unwrap();
+        }
+        // Observed again, "0" is no longer the least recent
+        dists
+            .observe("latency_ms", &labels("0"), &histogram, 0.5)
+            .unwrap();
+        dists
+            .observe("latency_ms", &labels("new"), &histogram, 0.5)
+            .unwrap();
+
+        assert_eq!(dists.series.len(), MAX_SERIES);
+        let key = |zone: &str| ("latency_ms".to_string(), labels(zone).content_hash());
+        assert!(dists.series.contains_key(&key("0")));
+        assert!(!dists.series.contains_key(&key("1")));
+    }
+}
@@ -0,0 +1,379 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use log::{error, info};
+use tokio::sync::oneshot::{self, error::TryRecvError};
+
+use crate::{
+    config::pipe::timeseries::annotate::{LabelLookupConfig, MappingFormat, MissingKeyPolicy},
+    core::types::{Record, Symbol, Value},
+};
+
+use super::LABELS_FIELD;
+
+type Mapping = HashMap<String, Vec<(Value, Value)>>;
+
+// Modification time and size of a file, to tell whether it has changed
+type Version = Option<(SystemTime, u64)>;
+
+// What reading the file again gives, `None` when it is unchanged
+type Reloaded = super::Result<Option<(Mapping, Version)>>;
+
+/// Labels looked up by the value of a label, read from a mapping file which is read again
+/// once modified.
+///
+/// The file is read again in a blocking task, the poll loop only swaps the mapping once read.
+#[derive(Debug)]
+pub struct LabelLookup {
+    key_field: Symbol,
+    key_label: Value,
+    path: PathBuf,
+    format: MappingFormat,
+    // All of them when empty
+    columns: Vec<Value>,
+    on_missing: MissingKeyPolicy,
+
+    mapping: Arc<Mapping>,
+    version: Version,
+
+    refresh_interval: Duration,
+    last_refresh: Instant,
+    // The file being read again
+    pending: Option<oneshot::Receiver<Reloaded>>,
+    // Logged once, until the file can be read again
+    last_error: Option<String>,
+}
+
+impl LabelLookup {
+    pub fn load(cfg: LabelLookupConfig) -> super::Result<Self> {
+        let columns = cfg.columns.iter().map(Value::from).collect::<Vec<_>>();
+        let (mapping, version) = read_mapping(&cfg.mapping_file, cfg.mapping_format, &columns)?;
+        info!(
+            "Loaded {} label mappings from {}",
+            mapping.len(),
+            cfg.mapping_file.display()
+        );
+
+        Ok(Self {
+            key_label: Value::from(&cfg.key_field),
+            key_field: cfg.key_field,
+            path: cfg.mapping_file,
+            format: cfg.mapping_format,
+            columns,
+            on_missing: cfg.on_missing,
+            mapping: Arc::new(mapping),
+            version,
+            refresh_interval: cfg.refresh_interval.into(),
+            last_refresh: Instant::now(),
+            pending: None,
+            last_error: None,
+        })
+    }
+
+    /// Swap the mapping read since the last call, and start reading the file again once per
+    /// `refresh_interval`. The mapping is kept as is when the file cannot be read.
+    pub fn refresh(&mut self) {
+        if let Some(ref mut pending) = self.pending {
+            match pending.try_recv() {
+                Ok(result) => {
+                    self.pending = None;
+                    self.reloaded(result);
+                }
+                Err(TryRecvError::Empty) => return,
+                // The task panicked, tried again on the next interval
+                Err(TryRecvError::Closed) => self.pending = None,
+            }
+        }
+
+        if self.last_refresh.elapsed() < self.refresh_interval {
+            return;
+        }
+        self.last_refresh = Instant::now();
+
+        let (tx, rx) = oneshot::channel();
+        let (path, format, columns, version) = (
+            self.path.clone(),
+            self.format,
+            self.columns.clone(),
+            self.version,
+        );
+        tokio::task::spawn_blocking(move || {
+            let result = match version_of(&path) {
+                Ok(current) if current.is_some() && current == version => Ok(None),
+                Ok(_) => read_mapping(&path, format, &columns).map(Some),
+                Err(e) => Err(e),
+            };
+            let _ = tx.send(result);
+        });
+        self.pending = Some(rx);
+    }
+
+    fn reloaded(&mut self, result: Reloaded) {
+        match result {
+            Ok(Some((mapping, version))) => {
+                info!(
+                    "Reloaded {} label mappings from {}",
+                    mapping.len(),
+                    self.path.display()
+                );
+                self.mapping = Arc::new(mapping);
+                self.version = version;
+                self.last_error = None;
+            }
+            Ok(None) => self.last_error = None,
+            Err(e) => {
+                let e = e.to_string();
+                if self.last_error.as_ref() != Some(&e) {
+                    error!(
+                        "Failed to reload label mappings from {}, keeping the previous ones: {}",
+                        self.path.display(),
+                        e
+                    );
+                    self.last_error = Some(e);
+                }
+            }
+        }
+    }
+
+    /// Wait for the file being read again, then swap the mapping.
+    #[cfg(test)]
+    pub async fn wait_pending(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            if let Ok(result) = pending.await {
+                self.reloaded(result);
+            }
+        }
+    }
+
+    pub fn on_missing(&self) -> MissingKeyPolicy {
+        self.on_missing
+    }
+
+    /// The labels of the record's key, `None` when it has no key or the key is unknown.
+    pub fn labels(&self, record: &Record) -> Option<&[(Value, Value)]> {
+        let label = match record.get(&LABELS_FIELD) {
+            Some(Value::Map(labels)) => labels.get(&self.key_label),
+            _ => None,
+        };
+        let key = label.or_else(|| record.get(&self.key_field))?;
+
+        let labels = match key {
+            Value::String(s) => self.mapping.get(s.as_str()),
+            key => self.mapping.get(&key.to_string()),
+        }?;
+        Some(labels.as_slice())
+    }
+}
+
+fn version_of(path: &Path) -> super::Result<Version> {
+    Ok(version(&std::fs::metadata(path)?))
+}
+
+/// Read the mapping and the version of the file, keeping only `columns` unless empty.
+fn read_mapping(
+    path: &Path,
+    format: MappingFormat,
+    columns: &[Value],
+) -> super::Result<(Mapping, Version)> {
+    let version = version_of(path)?;
+    let content = std::fs::read_to_string(path)?;
+    let mut mapping = parse_mapping(&content, format)?;
+    if !columns.is_empty() {
+        for labels in mapping.values_mut() {
+            labels.retain(|(name, _)| columns.contains(name));
+        }
+    }
+    Ok((mapping, version))
+}
+
+fn version(metadata: &std::fs::Metadata) -> Version {
+    metadata
+        .modified()
+        .ok()
+        .map(|mtime| (mtime, metadata.len()))
+}
+
+fn parse_mapping(content: &str, format: MappingFormat) -> super::Result<Mapping> {
+    match format {
+        MappingFormat::Csv => parse_csv(content),
+        MappingFormat::Json => parse_json(content),
+    }
+}
+
+/// `key,label1,label2` header, then a row per key. Empty cells are left out.
+fn parse_csv(content: &str) -> super::Result<Mapping> {
+    let mut lines = content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+    let Some((_, header)) = lines.next() else {
+        return Ok(Mapping::new());
+    };
+    let names = header
+        .split(',')
+        .skip(1)
+        .map(|name| Value::from(name.trim()))
+        .collect::<Vec<_>>();
+
+    let mut mapping = Mapping::new();
+    for (idx, line) in lines {
+        let mut cells = line.split(',').map(str::trim);
+        let key = cells.next().unwrap_or_default();
+        let cells = cells.collect::<Vec<_>>();
+        if cells.len() != names.len() {
+            return Err(super::Error::InvalidMapping(format!(
+                "line {}: expected {} columns, got {}",
+                idx + 1,
+                names.len() + 1,
+                cells.len() + 1
+            )));
+        }
+
+        let labels = names
+            .iter()
+            .zip(cells)
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(name, value)| (name.clone(), Value::from(value)))
+            .collect();
+        mapping.insert(key.to_string(), labels);
+    }
+
+    Ok(mapping)
+}
+
+/// `{"key": {"label": "value"}}`, numbers and booleans are turned into strings.
+fn parse_json(content: &str) -> super::Result<Mapping> {
+    let entries: HashMap<String, HashMap<String, serde_json::Value>> =
+        serde_json::from_str(content).map_err(|e| super::Error::InvalidMapping(e.to_string()))?;
+
+    let mut mapping = Mapping::new();
+    for (key, labels) in entries {
+        let labels = labels
+            .into_iter()
+            .map(|(name, value)| {
+                let value = match value {
+                    serde_json::Value::String(s) => s,
+                    serde_json::Value::Number(n) => n.to_string(),
+                    serde_json::Value::Bool(b) => b.to_string(),
+                    other => {
+                        return Err(super::Error::InvalidMapping(format!(
+                            "{}.{}: expected a string, got {}",
+                            key, name, other
+                        )))
+                    }
+                };
+                Ok((Value::from(name), Value::from(value)))
+            })
+            .collect::<super::Result<Vec<_>>>()?;
+        mapping.insert(key, labels);
+    }
+
+    Ok(mapping)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(mapping: &Mapping, key: &str) -> Vec<(String, String)> {
+        let mut labels = mapping[key]
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Vec<_>>();
+        labels.sort();
+        labels
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let mapping = parse_csv("host, rack, datacenter\nweb01, r1, eu\n\nweb02,,us\n").unwrap();
+        assert_eq!(mapping.len(), 2);
+        assert_eq!(
+            labels(&mapping, "web01"),
+            vec![
+                ("datacenter".to_string(), "eu".to_string()),
+                ("rack".to_string(), "r1".to_string())
+            ]
+        );
+        assert_eq!(
+            labels(&mapping, "web02"),
+            vec![("datacenter".to_string(), "us".to_string())]
+        );
+
+        assert!(parse_csv("").unwrap().is_empty());
+        assert!(matches!(
+            parse_csv("host,rack\nweb01,r1,extra"),
+            Err(super::super::Error::InvalidMapping(..))
+        ));
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let mapping =
+            parse_json(r#"{"web01": {"rack": "r1", "slot": 3, "spare": false}}"#).unwrap();
+        assert_eq!(
+            labels(&mapping, "web01"),
+            vec![
+                ("rack".to_string(), "r1".to_string()),
+                ("slot".to_string(), "3".to_string()),
+                ("spare".to_string(), "false".to_string())
+            ]
+        );
+
+        assert!(parse_json(r#"{"web01": {"rack": ["r1"]}}"#).is_err());
+        assert!(parse_json("[]").is_err());
+    }
+
+    fn lookup(path: &Path, columns: &str) -> LabelLookup {
+        let cfg: LabelLookupConfig = toml::from_str(&format!(
+            "key_field = \"host\"\nmapping_file = {:?}\nmapping_format = \"csv\"\n\
+             refresh_interval = \"1ms\"\ncolumns = {}",
+            path, columns
+        ))
+        .unwrap();
+        LabelLookup::load(cfg).unwrap()
+    }
+
+    async fn refresh(lookup: &mut LabelLookup) {
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        lookup.refresh();
+        lookup.wait_pending().await;
+    }
+
+    #[tokio::test]
+    async fn test_refresh() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hosts.csv");
+        std::fs::write(&path, "host,rack,owner\nweb01,r1,db\n").unwrap();
+
+        let mut lookup = lookup(&path, "[\"rack\"]");
+        let loaded = lookup.mapping.clone();
+        assert_eq!(
+            labels(&loaded, "web01"),
+            vec![("rack".to_string(), "r1".to_string())]
+        );
+
+        // Unchanged, not read again
+        refresh(&mut lookup).await;
+        assert!(Arc::ptr_eq(&loaded, &lookup.mapping));
+
+        // Logged once, the previous mapping is kept
+        std::fs::write(&path, "host,rack\nweb01,r1,extra\n").unwrap();
+        refresh(&mut lookup).await;
+        refresh(&mut lookup).await;
+        assert!(lookup.last_error.as_ref().unwrap().contains("line 2"));
+        assert!(Arc::ptr_eq(&loaded, &lookup.mapping));
+
+        std::fs::write(&path, "host,rack\nweb01,r2\n").unwrap();
+        refresh(&mut lookup).await;
+        assert!(lookup.last_error.is_none());
+        assert_eq!(
+            labels(&lookup.mapping, "web01"),
+            vec![("rack".to_string(), "r2".to_string())]
+        );
+    }
+}
@@ -1,58 +1,93 @@
 pub mod annotate;
+mod distribution;
+mod lookup;
+mod units;
 
 pub use annotate::TimeseriesAnnotatePipe;
+use distribution::Distributions;
+use units::UnitNormalizer;
 
 pub use super::{Error, Result};
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use async_trait::async_trait;
-use log::{debug, warn};
 use once_cell::sync::Lazy;
 use tokio_util::sync::CancellationToken;
 
 use crate::{
-    config::pipe::timeseries::{MetricType, TimeseriesPipeConfig},
+    actor_debug, actor_warn,
+    config::{
+        global,
+        pipe::{
+            timeseries::{MetricType, TimeseriesPipeConfig, UnexpectedFields, ValueCast},
+            Priority,
+        },
+    },
     core::{
         actor::Actor,
-        manager::{ChannelGraph, TaggedReceiver, TaggedSender},
+        dead_letter::DeadLetter,
+        manager::{ChannelGraph, SendError, TaggedReceiver, TaggedSender},
+        metrics,
         tag::{HasTag, TagId},
         types::{Attribute, Record, Symbol, Value},
     },
-    utils::recv::recv_batch,
+    utils::{
+        budget::{drain_carry, PollBudget},
+        recv::recv_batch_grouped,
+        throttle::Throttle,
+    },
 };
 
-use super::Pipe;
+use super::{LabelPolicy, Pipe, RecordSizeObserver};
 
 #[derive(Debug)]
 struct InnerState {
     tag: TagId,
     label_syms: Vec<Symbol>,
-    value_syms: Option<HashMap<Symbol, MetricType>>,
+    value_syms: Option<HashMap<Symbol, (MetricType, ValueCast)>>,
     timestamp_sym: Option<Symbol>,
     extra_labels: HashMap<Symbol, String>,
-    outbound: TaggedSender,
+    unexpected_fields: UnexpectedFields,
+    unexpected_throttle: spin::Mutex<Throttle>,
+    label_policy: Option<LabelPolicy>,
+    distributions: spin::Mutex<Distributions>,
+    units: UnitNormalizer,
 }
 
 impl InnerState {
     fn new(
         tag: TagId,
         label_syms: Vec<Symbol>,
-        value_syms: Option<HashMap<Symbol, MetricType>>,
+        value_syms: Option<HashMap<Symbol, (MetricType, ValueCast)>>,
         timestamp_sym: Option<Symbol>,
         extra_labels: HashMap<Symbol, String>,
-        outbound: TaggedSender,
+        unexpected_fields: UnexpectedFields,
+        label_policy: Option<LabelPolicy>,
     ) -> Self {
         InnerState {
-            tag,
+            tag: tag.clone(),
             label_syms,
             value_syms,
             timestamp_sym,
             extra_labels,
-            outbound,
+            unexpected_fields,
+            unexpected_throttle: spin::Mutex::new(Throttle::new(UNEXPECTED_FIELDS_WARN_INTERVAL)),
+            label_policy,
+            distributions: spin::Mutex::new(Distributions::new(tag)),
+            units: UnitNormalizer::default(),
         }
     }
 
-    fn transform(&self, record: Record) -> super::Result<Vec<Record>> {
+    fn with_units(mut self, units: UnitNormalizer) -> Self {
+        self.units = units;
+        self
+    }
+
+    fn transform(&self, mut record: Record) -> super::Result<Vec<Record>> {
         let inbound = record
             .get_attribute(&Attribute::Inbound)
             .cloned()
@@ -73,34 +108,47 @@ impl InnerState {
             }
         };
 
-        let attrs = record.attributes().clone();
-        let tracing_ctx = record.ctx().clone();
+        // What the new records share: the timestamp, the attributes and the tracing context
+        let mut shared = record.project(&[]);
+        shared.set(TIMESTAMP_FIELD.clone(), timestamp);
+        shared.set_attribute(Attribute::Type, RECORD_TYPE_TIMESERIES_VALUE.clone());
+        shared.set_attribute(Attribute::Inbound, inbound);
 
-        let (labels, values) = record
-            .take()
-            .into_iter()
-            .partition::<Vec<_>, _>(|(sym, _)| self.label_syms.contains(sym));
-        let labels: Value = labels
-            .into_iter()
-            .map(|(sym, value)| {
+        // The label fields apart, the others are looked through for the values
+        let mut labels = Record::empty();
+        for sym in &self.label_syms {
+            if let Some(value) = record.remove(sym) {
                 let key = ensure_valid_label(sym.as_ref())?;
-                Ok((Symbol::from(key), value))
-            })
-            .collect::<super::Result<_>>()?;
+                labels.set(Symbol::from(key), value);
+            }
+        }
 
-        let (values, _) =
-            values
-                .into_iter()
-                .partition::<Vec<_>, _>(|(sym, _)| match self.value_syms {
-                    Some(ref syms) => syms.contains_key(sym),
-                    None => {
-                        let in_labels = self.label_syms.contains(sym);
-                        let in_timestamp =
-                            self.timestamp_sym.as_ref().map_or(false, |ts| ts == sym);
-                        let in_name = sym.as_str() == NAME_FIELD.as_ref();
-                        !in_labels && !in_timestamp && !in_name
-                    }
-                });
+        let mut values = Vec::new();
+        let mut unexpected = Vec::new();
+        for (sym, value) in record.take() {
+            let in_timestamp = self.timestamp_sym.as_ref() == Some(&sym);
+            let in_name = sym.as_str() == NAME_FIELD.as_ref();
+            if in_timestamp || in_name {
+                continue;
+            }
+
+            let is_value = match self.value_syms {
+                Some(ref syms) => syms.contains_key(&sym),
+                // Only the fields which can be turned into a sample are inferred as values.
+                None => value.is_number() || value.is_bool(),
+            };
+
+            if is_value {
+                values.push((sym, value));
+            } else {
+                unexpected.push((sym, value));
+            }
+        }
+
+        if !unexpected.is_empty() {
+            self.handle_unexpected_fields(unexpected, &mut labels)?;
+        }
+        let labels: Value = labels.take().into_iter().collect();
 
         if values.is_empty() {
             return Err(super::Error::FieldNotFound(VALUE_FIELD_STR));
@@ -108,12 +156,10 @@ impl InnerState {
 
         let mut new_records = Vec::new();
         for (name, value) in values {
-            let mut new_record = Record::new_with_attrs(attrs.clone(), tracing_ctx.clone());
-
-            let metric_type = match self.value_syms {
+            let (metric_type, cast) = match self.value_syms {
                 Some(ref syms) => {
-                    if let Some(typ) = syms.get(&name) {
-                        typ.clone()
+                    if let Some((typ, cast)) = syms.get(&name) {
+                        (typ.clone(), *cast)
                     } else {
                         return Err(super::Error::InvalidRecord(format!(
                             "Value {} not found in value syms",
@@ -121,45 +167,134 @@ impl InnerState {
                         )));
                     }
                 }
-                None => MetricType::default(),
+                None => (MetricType::default(), ValueCast::default()),
             };
+            let value = match cast {
+                ValueCast::Float => value.cast_float()?,
+                ValueCast::Int => value.cast_int()?,
+            };
+            let value = self.units.normalize(&self.tag, &name, value);
             let name = ensure_valid_name(name.as_ref())?;
 
-            new_record.set(NAME_FIELD.clone(), name.into());
-            new_record.set(METRIC_TYPE_FIELD.clone(), Value::String(metric_type.into()));
-            new_record.set(TIMESTAMP_FIELD.clone(), timestamp.clone());
-
-            let value = value.cast_float()?;
-            let value_guard = value.float()?;
-            let unit = value_guard.unit().cloned();
-
-            new_record.set(VALUE_FIELD.clone(), value);
+            let unit = match value {
+                Value::Int(ref number) => number.unit.clone(),
+                _ => value.float()?.unit().cloned(),
+            };
 
             let mut labels = labels.clone();
             let mut labels_guard = labels.map_mut()?;
-            match unit {
-                Some(unit) => labels_guard.set(UNIT_FIELD.clone().into(), unit.into()),
-                None => {}
-            };
+            if let Some(unit) = unit {
+                labels_guard.set(UNIT_FIELD.clone().into(), unit.into());
+            }
 
             for (key, value) in &self.extra_labels {
                 labels_guard.set(key.into(), value.as_str().into());
             }
-            new_record.set(LABELS_FIELD.clone(), labels);
 
-            new_record.set_attribute(Attribute::Type, RECORD_TYPE_TIMESERIES_VALUE.clone());
-            new_record.set_attribute(Attribute::Inbound, inbound.clone());
+            // The single choke point: every label, whichever way it came in, is checked here
+            if let Some(ref policy) = self.label_policy {
+                policy.apply(&mut labels)?;
+            }
+
+            let new_record = |name: String, value: Value, labels: Value| {
+                let mut new_record = shared.project(std::slice::from_ref(&TIMESTAMP_FIELD));
+                new_record.set(NAME_FIELD.clone(), name.into());
+                new_record.set(
+                    METRIC_TYPE_FIELD.clone(),
+                    Value::String(metric_type.clone().into()),
+                );
+                new_record.set(VALUE_FIELD.clone(), value);
+                new_record.set(LABELS_FIELD.clone(), labels);
+                new_record
+            };
 
-            new_records.push(new_record);
+            match metric_type {
+                MetricType::Counter | MetricType::Gauge => {
+                    new_records.push(new_record(name, value, labels));
+                }
+                // A single observation updates several series: the buckets or quantiles,
+                // `_sum` and `_count`
+                MetricType::Histogram { .. } | MetricType::Summary { .. } => {
+                    let observed = value.float()?.value();
+                    let samples = self.distributions.lock().observe(
+                        &name,
+                        &labels,
+                        &metric_type,
+                        observed,
+                    )?;
+                    for (suffix, extra_label, value) in samples {
+                        let mut labels = labels.clone();
+                        if let Some(extra_label) = extra_label {
+                            labels
+                                .map_mut()?
+                                .set(extra_label.name().into(), extra_label.value().into());
+                        }
+                        new_records.push(new_record(
+                            format!("{}{}", name, suffix),
+                            value.into(),
+                            labels,
+                        ));
+                    }
+                }
+            }
         }
 
         if new_records.is_empty() {
-            warn!("{}: no values found in record", self.tag);
+            actor_warn!(self.tag, "no values found in record");
             return Err(super::Error::FieldNotFound(VALUE_FIELD_STR));
         }
 
         Ok(new_records)
     }
+
+    fn handle_unexpected_fields(
+        &self,
+        unexpected: Vec<(Symbol, Value)>,
+        labels: &mut Record,
+    ) -> super::Result<()> {
+        match self.unexpected_fields {
+            UnexpectedFields::Ignore => {}
+            UnexpectedFields::Warn => {
+                if let Some(suppressed) = self.unexpected_throttle.lock().check_at(Instant::now()) {
+                    let names = unexpected
+                        .iter()
+                        .map(|(sym, _)| sym.as_str())
+                        .collect::<Vec<_>>();
+                    actor_warn!(
+                        self.tag,
+                        "dropping unexpected fields {:?} ({} similar warnings suppressed)",
+                        names,
+                        suppressed
+                    );
+                }
+            }
+            UnexpectedFields::Error => {
+                let names = unexpected
+                    .iter()
+                    .map(|(sym, _)| sym.as_str())
+                    .collect::<Vec<_>>();
+                return Err(super::Error::InvalidRecord(format!(
+                    "Unexpected fields {:?}",
+                    names
+                )));
+            }
+            UnexpectedFields::Label => {
+                let mut extra = Record::empty();
+                for (sym, value) in unexpected {
+                    let key = ensure_valid_label(sym.as_ref())?;
+                    let value = match value.cast_string() {
+                        Ok(value) => value,
+                        Err(_) => Value::from(value.to_string()),
+                    };
+                    extra.set(Symbol::from(key), value);
+                }
+                // A label field wins over an unexpected one sanitized into the same key
+                labels.merge_from(&extra, false);
+            }
+        }
+
+        Ok(())
+    }
 }
 
 pub struct TimeseriesPipe {
@@ -169,9 +304,19 @@ pub struct TimeseriesPipe {
 
     inbounds: Vec<TaggedReceiver>,
     outbound: TaggedSender,
+    dead_letter: DeadLetter,
+
+    size_observer: RecordSizeObserver,
+    priority: Option<Priority>,
+
+    // Received but not yet transformed with the inbound they come from, left over by a poll
+    // which ran out of budget
+    carry: VecDeque<(TagId, Record)>,
+    max_poll_duration: Duration,
 
     interval: Duration,
     buffer_size: usize,
+    max_batch_latency: Duration,
 }
 
 pub static RECORD_TYPE_TIMESERIES: Lazy<Symbol> = Lazy::new(|| Symbol::intern("TimeseriesRecord"));
@@ -179,6 +324,8 @@ pub static RECORD_TYPE_TIMESERIES: Lazy<Symbol> = Lazy::new(|| Symbol::intern("T
 pub static RECORD_TYPE_TIMESERIES_VALUE: Lazy<Value> =
     Lazy::new(|| Value::from(RECORD_TYPE_TIMESERIES.as_ref()));
 
+const UNEXPECTED_FIELDS_WARN_INTERVAL: Duration = Duration::from_secs(10);
+
 pub const NAME_FIELD_STR: &str = "name";
 pub const TIMESTAMP_FIELD_STR: &str = "timestamp";
 pub const METRIC_TYPE_FIELD_STR: &str = "metric_type";
@@ -198,18 +345,21 @@ impl TimeseriesPipe {
         cfg: TimeseriesPipeConfig,
         channels: &mut ChannelGraph,
     ) -> super::Result<Self> {
+        let unexpected_fields = cfg.unexpected_fields();
         let tag = cfg.tag.into();
+        let label_policy = LabelPolicy::resolve(&tag, cfg.label_policy)?;
         let inbounds = cfg
             .inbounds
             .iter()
             .map(|inbound| channels.recv_from(inbound, &tag))
             .collect::<Vec<_>>();
         let outbound = channels.sender(&tag);
+        let dead_letter = channels.dead_letter(&tag);
 
         let value_syms = if let Some(values) = cfg.values {
             let syms = values
                 .into_iter()
-                .map(|field| (field.name, field.r#type))
+                .map(|field| (field.name, (field.r#type, field.cast)))
                 .collect::<HashMap<_, _>>();
 
             Some(syms)
@@ -218,11 +368,7 @@ impl TimeseriesPipe {
             None
         };
 
-        let label_syms = cfg
-            .labels
-            .into_iter()
-            .map(|label| label.clone())
-            .collect::<Vec<_>>();
+        let label_syms = cfg.labels.into_iter().collect::<Vec<_>>();
 
         let inner = InnerState::new(
             tag.clone(),
@@ -230,43 +376,76 @@ impl TimeseriesPipe {
             value_syms,
             cfg.timestamp,
             cfg.extra_labels,
-            outbound.clone(),
-        );
+            unexpected_fields,
+            label_policy,
+        )
+        .with_units(UnitNormalizer::new(&cfg.normalize_units));
         let inner = Arc::new(inner);
+        let size_observer = RecordSizeObserver::new(tag.clone(), cfg.record_size);
 
         Ok(TimeseriesPipe {
             tag,
             inner,
             inbounds,
             outbound,
-            interval: cfg.recv_timeout,
+            dead_letter,
+            size_observer,
+            priority: cfg.priority,
+            carry: VecDeque::new(),
+            max_poll_duration: global::max_poll_duration().pipe.into(),
+            interval: cfg.recv_timeout.into(),
             buffer_size: cfg.recv_buffer_size,
+            max_batch_latency: cfg.max_batch_latency.into(),
         })
     }
 
-    fn transform_records(&mut self, records: Vec<Record>) -> super::Result<()> {
+    async fn transform_records(&mut self, budget: &PollBudget) -> super::Result<()> {
         let inner = &self.inner;
+        let size_observer = &mut self.size_observer;
+        let dead_letter = &mut self.dead_letter;
+        let mut out = Vec::new();
 
-        let transformed_records: Vec<_> = records
-            .into_iter()
-            .map(|r| inner.transform(r))
-            .filter_map(|r| match r {
-                Ok(records) => Some(records),
+        drain_carry(&mut self.carry, budget, |(inbound, mut record)| {
+            size_observer.observe(&mut record);
+            let original = dead_letter.is_enabled().then(|| record.clone());
+            let records = match inner.transform(record) {
+                Ok(records) => records,
                 Err(e) => {
-                    warn!("{}: error transforming record: {:?}", inner.tag, e);
-                    None
+                    actor_warn!(
+                        inner.tag,
+                        "error transforming record from {}: {:?}",
+                        inbound,
+                        e
+                    );
+                    metrics::count_transform_error(&inner.tag);
+                    if let Some(original) = original {
+                        dead_letter.send(original, &e);
+                    }
+                    return;
                 }
-            })
-            .flatten()
-            .collect();
+            };
 
-        // mark pipeline sending time
-        transformed_records.into_iter().for_each(|record| {
-            if let Err(e) = self.outbound.send(record) {
-                warn!("{}: error sending record: {}", inner.tag, e);
-            }
+            out.extend(records);
         });
 
+        // Sent once transformed, the channel may have to wait for room
+        for mut record in out {
+            if let Some(priority) = self.priority {
+                record.set_priority(priority);
+            }
+            if let Err(e @ SendError::Closed(_)) = self.outbound.send(record).await {
+                actor_warn!(self.tag, "error sending record: {}", e);
+            }
+        }
+
+        if !self.carry.is_empty() {
+            actor_debug!(
+                self.tag,
+                "poll budget exhausted, {} records carried over",
+                self.carry.len()
+            );
+        }
+
         Ok(())
     }
 }
@@ -281,30 +460,47 @@ impl HasTag for TimeseriesPipe {
 impl Actor for TimeseriesPipe {
     type Error = super::Error;
     async fn poll(&mut self, ctx: CancellationToken) -> super::Result<()> {
-        let tag = self.tag.clone();
-
-        let records = match recv_batch(
-            &tag,
-            &mut self.inbounds,
-            Some(self.interval),
-            self.buffer_size,
-            ctx,
-        )
-        .await
-        {
-            Ok(records) => records,
-            Err(crate::utils::recv::Error::Timeout) => {
-                return Ok(());
-            }
-            Err(e) => return Err(e.into()),
-        };
+        // Finish the records left over by the last poll before receiving new ones
+        if self.carry.is_empty() {
+            let tag = self.tag.clone();
+            let batch = match recv_batch_grouped(
+                &tag,
+                &mut self.inbounds,
+                Some(self.interval),
+                self.buffer_size,
+                self.max_batch_latency,
+                ctx.clone(),
+            )
+            .await
+            {
+                Ok(batch) => batch,
+                Err(crate::utils::recv::Error::Timeout) => {
+                    return Ok(());
+                }
+                Err(e) => return Err(e.into()),
+            };
 
-        debug!("{}: received {} records", self.tag, records.len());
+            for (inbound, records) in batch {
+                actor_debug!(
+                    self.tag,
+                    "received {} records from {}",
+                    records.len(),
+                    inbound
+                );
+                self.carry
+                    .extend(records.into_iter().map(|record| (inbound.clone(), record)));
+            }
+        }
 
-        self.transform_records(records)?;
+        let budget = PollBudget::new(self.max_poll_duration, ctx);
+        self.transform_records(&budget).await?;
 
         Ok(())
     }
+
+    fn has_pending(&self) -> bool {
+        !self.carry.is_empty()
+    }
 }
 
 impl Pipe for TimeseriesPipe {}
@@ -381,3 +577,453 @@ pub fn ensure_valid_name(name: &str) -> super::Result<String> {
 
     Ok(name)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::pipe::{label_policy::LabelPolicyConfig, RecordSizeConfig},
+        core::{
+            manager::ActorChannel,
+            tag::{InboundTagId, OutboundTagId, PipeTagId},
+            types::{parse_value, ValueType},
+        },
+    };
+
+    fn inner(explicit_values: bool, mode: UnexpectedFields) -> InnerState {
+        let value_syms = explicit_values
+            .then(|| HashMap::from([(Symbol::from("cpu"), (MetricType::Gauge, ValueCast::Float))]));
+
+        InnerState::new(
+            PipeTagId::new("timeseries").into(),
+            vec![Symbol::from("host")],
+            value_syms,
+            None,
+            HashMap::new(),
+            mode,
+            None,
+        )
+    }
+
+    fn record() -> Record {
+        let mut record = Record::empty();
+        record.set(Symbol::from("host"), Value::from("a"));
+        record.set(Symbol::from("cpu"), Value::from(1.0));
+        record.set(Symbol::from("request_id"), Value::from("abc"));
+        record
+    }
+
+    fn labels_of(record: &Record) -> HashMap<Value, Value> {
+        record[&LABELS_FIELD].map().unwrap().as_hashmap().clone()
+    }
+
+    #[test]
+    fn test_unexpected_fields_ignore() {
+        for explicit in [true, false] {
+            let records = inner(explicit, UnexpectedFields::Ignore)
+                .transform(record())
+                .unwrap();
+            assert_eq!(records.len(), 1);
+            assert_eq!(records[0][&NAME_FIELD], Value::from("cpu"));
+
+            let labels = labels_of(&records[0]);
+            assert_eq!(labels.len(), 1);
+            assert_eq!(labels.get(&Value::from("host")), Some(&Value::from("a")));
+        }
+    }
+
+    #[test]
+    fn test_unexpected_fields_warn() {
+        for explicit in [true, false] {
+            let inner = inner(explicit, UnexpectedFields::Warn);
+            let records = inner.transform(record()).unwrap();
+            assert_eq!(records.len(), 1);
+            assert_eq!(labels_of(&records[0]).len(), 1);
+
+            // the warning has been logged, the next one within the interval is suppressed
+            assert_eq!(
+                inner.unexpected_throttle.lock().check_at(Instant::now()),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn test_unexpected_fields_error() {
+        for explicit in [true, false] {
+            let result = inner(explicit, UnexpectedFields::Error).transform(record());
+            assert!(matches!(result, Err(super::super::Error::InvalidRecord(_))));
+        }
+
+        // records without leftovers are still accepted
+        let mut clean = Record::empty();
+        clean.set(Symbol::from("host"), Value::from("a"));
+        clean.set(Symbol::from("cpu"), Value::from(1.0));
+        for explicit in [true, false] {
+            let records = inner(explicit, UnexpectedFields::Error)
+                .transform(clean.clone())
+                .unwrap();
+            assert_eq!(records.len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_unexpected_fields_label() {
+        for explicit in [true, false] {
+            let records = inner(explicit, UnexpectedFields::Label)
+                .transform(record())
+                .unwrap();
+            assert_eq!(records.len(), 1);
+
+            let labels = labels_of(&records[0]);
+            assert_eq!(labels.len(), 2);
+            assert_eq!(
+                labels.get(&Value::from("request_id")),
+                Some(&Value::from("abc"))
+            );
+        }
+
+        // in the explicit shape, numeric leftovers are labels too
+        let mut record = record();
+        record.set(Symbol::from("port"), Value::from(8080i64));
+        let records = inner(true, UnexpectedFields::Label)
+            .transform(record)
+            .unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            labels_of(&records[0]).get(&Value::from("port")),
+            Some(&Value::from("8080"))
+        );
+    }
+
+    #[test]
+    fn test_unexpected_fields_label_collision() {
+        // a leftover sanitized into a label key does not overwrite the label
+        let mut record = record();
+        record.set(Symbol::from(" host"), Value::from("b"));
+        let records = inner(true, UnexpectedFields::Label)
+            .transform(record)
+            .unwrap();
+        assert_eq!(
+            labels_of(&records[0]).get(&Value::from("host")),
+            Some(&Value::from("a"))
+        );
+    }
+
+    #[test]
+    fn test_unexpected_fields_default() {
+        let explicit: TimeseriesPipeConfig = toml::from_str(
+            r#"
+            inbounds = ["inbound:data"]
+            labels = ["host"]
+            values = ["cpu"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(explicit.unexpected_fields(), UnexpectedFields::Ignore);
+
+        let inferred: TimeseriesPipeConfig = toml::from_str(
+            r#"
+            inbounds = ["inbound:data"]
+            labels = ["host"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(inferred.unexpected_fields(), UnexpectedFields::Warn);
+
+        let overridden: TimeseriesPipeConfig = toml::from_str(
+            r#"
+            inbounds = ["inbound:data"]
+            labels = ["host"]
+            unexpected_fields = "label"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(overridden.unexpected_fields(), UnexpectedFields::Label);
+    }
+
+    fn inner_with_policy(extra_labels: HashMap<Symbol, String>, deny: &[&str]) -> InnerState {
+        let tag: TagId = PipeTagId::new("timeseries").into();
+        let cfg = LabelPolicyConfig {
+            deny_keys: deny.iter().map(|s| s.to_string()).collect(),
+            allow_keys: None,
+        };
+
+        InnerState::new(
+            tag.clone(),
+            vec![Symbol::from("host"), Symbol::from("api_token")],
+            None,
+            None,
+            extra_labels,
+            UnexpectedFields::Label,
+            LabelPolicy::resolve(&tag, Some(cfg)).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_label_policy_no_smuggling() {
+        let extra_labels = HashMap::from([
+            (Symbol::from("user_email"), "a@example.com".to_string()),
+            (Symbol::from("dc"), "eu".to_string()),
+        ]);
+        let inner = inner_with_policy(extra_labels, &["user_email", "*_token"]);
+
+        let mut record = record();
+        // a configured label, and an unexpected field folded into the labels
+        record.set(Symbol::from("api_token"), Value::from("secret"));
+        record.set(Symbol::from("session_token"), Value::from("secret"));
+
+        let records = inner.transform(record).unwrap();
+        let labels = labels_of(&records[0]);
+        let mut keys = labels.keys().map(|k| k.to_string()).collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, vec!["dc", "host", "request_id"]);
+
+        let policy = inner.label_policy.as_ref().unwrap();
+        assert_eq!(policy.stripped(), 3);
+    }
+
+    #[test]
+    fn test_label_policy_unit() {
+        let inner = inner_with_policy(HashMap::new(), &["unit"]);
+
+        let mut record = Record::empty();
+        record.set(Symbol::from("host"), Value::from("a"));
+        record.set(
+            Symbol::from("cpu"),
+            parse_value("1.0 %", ValueType::Float).unwrap(),
+        );
+
+        let records = inner.transform(record).unwrap();
+        let labels = labels_of(&records[0]);
+        assert_eq!(labels.len(), 1);
+        assert!(!labels.contains_key(&Value::from(UNIT_FIELD_STR)));
+        assert_eq!(inner.label_policy.as_ref().unwrap().stripped(), 1);
+    }
+
+    #[test]
+    fn test_normalize_units() {
+        let inner = inner(false, UnexpectedFields::Ignore).with_units(UnitNormalizer::new(
+            &HashMap::from([(Symbol::from("latency"), "s".to_string())]),
+        ));
+
+        let mut record = Record::empty();
+        record.set(Symbol::from("host"), Value::from("a"));
+        record.set(
+            Symbol::from("latency"),
+            parse_value("1500 milliseconds", ValueType::Float).unwrap(),
+        );
+        record.set(
+            Symbol::from("cpu"),
+            parse_value("42 %", ValueType::Float).unwrap(),
+        );
+
+        let records = inner.transform(record).unwrap();
+        let sample = |name: &str| {
+            records
+                .iter()
+                .find(|r| r[&NAME_FIELD] == Value::from(name))
+                .unwrap()
+        };
+
+        let latency = sample("latency");
+        assert_eq!(latency[&VALUE_FIELD].float().unwrap().value(), 1.5);
+        assert_eq!(
+            labels_of(latency)[&Value::from(UNIT_FIELD_STR)],
+            Value::from("s")
+        );
+        let cpu = sample("cpu");
+        assert_eq!(cpu[&VALUE_FIELD].float().unwrap().value(), 42.0);
+        assert_eq!(
+            labels_of(cpu)[&Value::from(UNIT_FIELD_STR)],
+            Value::from("percent")
+        );
+    }
+
+    #[test]
+    fn test_int_values() {
+        let value_syms = HashMap::from([
+            (Symbol::from("seq"), (MetricType::Counter, ValueCast::Int)),
+            (Symbol::from("cpu"), (MetricType::Gauge, ValueCast::Float)),
+        ]);
+        let inner = InnerState::new(
+            PipeTagId::new("timeseries").into(),
+            vec![Symbol::from("host")],
+            Some(value_syms),
+            None,
+            HashMap::new(),
+            UnexpectedFields::Ignore,
+            None,
+        );
+
+        // Past 2^53, where a float would round it
+        let seq = (1i64 << 53) + 1;
+        let mut record = Record::empty();
+        record.set(Symbol::from("host"), Value::from("a"));
+        record.set(Symbol::from("seq"), Value::from(seq));
+        record.set(Symbol::from("cpu"), Value::from(3i64));
+
+        let records = inner.transform(record).unwrap();
+        let sample = |name: &str| {
+            records
+                .iter()
+                .find(|r| r[&NAME_FIELD] == Value::from(name))
+                .unwrap()[&VALUE_FIELD]
+                .clone()
+        };
+        assert_eq!(sample("seq"), Value::from(seq));
+        assert_eq!(sample("cpu"), Value::from(3.0));
+
+        let mut record = Record::empty();
+        record.set(Symbol::from("host"), Value::from("a"));
+        record.set(Symbol::from("seq"), Value::from(1e300));
+        assert!(inner.transform(record).is_err());
+    }
+
+    fn pipe(max_poll_duration: Duration, records: usize) -> (TimeseriesPipe, TaggedReceiver) {
+        let tag: TagId = PipeTagId::new("timeseries").into();
+        let mut channel = ActorChannel::new(tag.clone(), 1024);
+        let receiver = channel.receiver(&OutboundTagId::new("stdio").into());
+
+        let pipe = TimeseriesPipe {
+            tag: tag.clone(),
+            inner: Arc::new(inner(true, UnexpectedFields::Ignore)),
+            inbounds: Vec::new(),
+            outbound: channel.sender(),
+            dead_letter: DeadLetter::new(tag.clone(), None),
+            size_observer: RecordSizeObserver::new(tag, RecordSizeConfig::default()),
+            priority: None,
+            // Stands for a huge batch received by the last poll
+            carry: (0..records)
+                .map(|_| (InboundTagId::new("a").into(), record()))
+                .collect(),
+            max_poll_duration,
+            interval: Duration::from_millis(5),
+            buffer_size: 1024,
+            max_batch_latency: Duration::from_millis(5),
+        };
+        (pipe, receiver)
+    }
+
+    #[tokio::test]
+    async fn test_dead_letter() {
+        let (mut pipe, mut receiver) = pipe(Duration::from_secs(1), 1);
+        let mut channel = ActorChannel::new(TagId::new("internal", "dead_letter"), 1);
+        pipe.dead_letter = DeadLetter::new(pipe.tag.clone(), Some(channel.sender()));
+        let mut dead_letters = channel.receiver(&OutboundTagId::new("file").into());
+
+        let mut bad = record();
+        bad.set(Symbol::from("cpu"), Value::from("not a number"));
+        pipe.carry.push_back((InboundTagId::new("a").into(), bad));
+        pipe.poll(CancellationToken::new()).await.unwrap();
+
+        assert!(receiver.try_recv().is_ok());
+        assert!(receiver.try_recv().is_err());
+
+        let dead = dead_letters.try_recv().unwrap();
+        assert_eq!(dead[&Symbol::from("cpu")], Value::from("not a number"));
+        assert_eq!(
+            dead.get_attribute(&Attribute::FailedBy),
+            Some(&Value::from("pipe:timeseries"))
+        );
+        assert!(dead.get_attribute(&Attribute::Error).is_some());
+        assert!(dead_letters.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_huge_batch_across_polls() {
+        let (mut pipe, mut receiver) = pipe(Duration::from_millis(5), 50_000);
+
+        let mut polls = 0;
+        while !pipe.carry.is_empty() {
+            pipe.poll(CancellationToken::new()).await.unwrap();
+            polls += 1;
+        }
+        assert!(polls > 1, "the batch was transformed in a single poll");
+
+        let mut received = 0;
+        while receiver.try_recv().is_ok() {
+            received += 1;
+        }
+        assert_eq!(received, 50_000);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_cancel_mid_batch() {
+        let (mut pipe, _receiver) = pipe(Duration::from_secs(60), 100_000);
+        let ctx = CancellationToken::new();
+
+        let cancel = ctx.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            cancel.cancel();
+        });
+
+        let start = Instant::now();
+        pipe.poll(ctx).await.unwrap();
+        let elapsed = start.elapsed();
+
+        assert!(!pipe.carry.is_empty(), "the whole batch was transformed");
+        assert!(elapsed < Duration::from_secs(1), "poll took {:?}", elapsed);
+    }
+
+    #[test]
+    fn test_histogram_series() {
+        let cfg: TimeseriesPipeConfig = toml::from_str(
+            "inbounds = [\"inbound:a\"]\nlabels = [\"host\"]\nvalues = [\"histogram(0.1,1,10):latency_ms\"]",
+        )
+        .unwrap();
+        let value_syms = cfg
+            .values
+            .unwrap()
+            .into_iter()
+            .map(|field| (field.name, (field.r#type, field.cast)))
+            .collect();
+        let inner = InnerState::new(
+            PipeTagId::new("timeseries").into(),
+            cfg.labels,
+            Some(value_syms),
+            None,
+            HashMap::new(),
+            UnexpectedFields::Ignore,
+            None,
+        );
+
+        let mut records = Vec::new();
+        for latency in [0.5, 5.0, 0.05] {
+            let mut record = Record::empty();
+            record.set(Symbol::from("host"), Value::from("a"));
+            record.set(Symbol::from("latency_ms"), Value::from(latency));
+            records = inner.transform(record).unwrap();
+        }
+
+        let tss = crate::core::types::conv::prometheus::transform_timeseries(records).unwrap();
+        let mut series = tss
+            .iter()
+            .map(|ts| {
+                let label = |name: &str| {
+                    ts.labels
+                        .iter()
+                        .find(|l| l.name == name)
+                        .map(|l| l.value.clone())
+                };
+                (label("__name__").unwrap(), label("le"), ts.samples[0].value)
+            })
+            .collect::<Vec<_>>();
+        series.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let bucket =
+            |le: &str, count: f64| ("latency_ms_bucket".to_string(), Some(le.to_string()), count);
+        assert_eq!(
+            series,
+            vec![
+                bucket("+Inf", 3.0),
+                bucket("0.1", 1.0),
+                bucket("1", 2.0),
+                bucket("10", 3.0),
+                ("latency_ms_count".to_string(), None, 3.0),
+                ("latency_ms_sum".to_string(), None, 5.55),
+            ]
+        );
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::actor_warn;
+use crate::core::{
+    tag::TagId,
+    types::{Symbol, Unit, Value},
+};
+
+// Past it the units are no longer remembered, nor warned about
+const MAX_WARNED_UNITS: usize = 1024;
+
+/// Rewrites the units of the values into their canonical spelling, converting the values of
+/// the fields given a target unit.
+#[derive(Debug, Default)]
+pub(super) struct UnitNormalizer {
+    targets: HashMap<Symbol, Unit>,
+    warned: spin::Mutex<HashSet<String>>,
+}
+
+impl UnitNormalizer {
+    /// The units are checked by the config, unknown ones are skipped.
+    pub fn new(targets: &HashMap<Symbol, String>) -> Self {
+        UnitNormalizer {
+            targets: targets
+                .iter()
+                .filter_map(|(field, unit)| Some((field.clone(), Unit::parse(unit)?)))
+                .collect(),
+            warned: spin::Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Normalize the unit of the number `value` of `field`, other values are left untouched.
+    ///
+    /// An int is only converted when the result is an int as well, e.g. `2 s` into `2000 ms`
+    /// but not `1500 ms` into seconds, which keeps its unit.
+    pub fn normalize(&self, tag: &TagId, field: &Symbol, value: Value) -> Value {
+        match value {
+            Value::Float(mut number) => {
+                let Some(ref spelling) = number.unit else {
+                    return Value::Float(number);
+                };
+                let Some(unit) = self.parse(tag, spelling) else {
+                    return Value::Float(number);
+                };
+
+                let unit = match self.convert(tag, field, unit, number.value) {
+                    Some((converted, target)) => {
+                        number.value = converted;
+                        target
+                    }
+                    None => unit,
+                };
+
+                number.unit = Some(unit.name().to_string());
+                Value::Float(number)
+            }
+            Value::Int(mut number) => {
+                let Some(ref spelling) = number.unit else {
+                    return Value::Int(number);
+                };
+                let Some(unit) = self.parse(tag, spelling) else {
+                    return Value::Int(number);
+                };
+
+                let exact = number.fits_f64();
+                let unit = match self.convert(tag, field, unit, number.value as f64) {
+                    // 2^63 is exact as a float, i64::MAX is not
+                    Some((converted, target))
+                        if exact
+                            && converted.fract() == 0.0
+                            && converted >= i64::MIN as f64
+                            && converted < i64::MAX as f64 =>
+                    {
+                        number.value = converted as i64;
+                        target
+                    }
+                    Some((_, target)) => {
+                        self.warn_once(unit.name(), || {
+                            actor_warn!(
+                                tag,
+                                "{} not converted: {} is not an int in {}",
+                                field,
+                                number,
+                                target.name()
+                            )
+                        });
+                        unit
+                    }
+                    None => unit,
+                };
+
+                number.unit = Some(unit.name().to_string());
+                Value::Int(number)
+            }
+            value => value,
+        }
+    }
+
+    fn parse(&self, tag: &TagId, spelling: &str) -> Option<Unit> {
+        let unit = Unit::parse(spelling);
+        if unit.is_none() {
+            self.warn_once(spelling, || {
+                actor_warn!(tag, "unknown unit {:?}, left as is", spelling)
+            });
+        }
+        unit
+    }
+
+    /// The value converted into the target unit of the field, if it has one.
+    fn convert(&self, tag: &TagId, field: &Symbol, unit: Unit, value: f64) -> Option<(f64, Unit)> {
+        let target = self.targets.get(field)?;
+        match unit.convert(value, *target) {
+            Ok(converted) => Some((converted, *target)),
+            Err(e) => {
+                self.warn_once(unit.name(), || {
+                    actor_warn!(tag, "{} not converted: {}", field, e)
+                });
+                None
+            }
+        }
+    }
+
+    fn warn_once(&self, unit: &str, warn: impl FnOnce()) {
+        let mut warned = self.warned.lock();
+        if warned.len() < MAX_WARNED_UNITS && warned.insert(unit.to_string()) {
+            warn();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{
+        tag::PipeTagId,
+        types::{parse_value, ValueType},
+    };
+
+    fn normalizer() -> UnitNormalizer {
+        UnitNormalizer::new(&HashMap::from([
+            (Symbol::new("latency"), "s".to_string()),
+            (Symbol::new("memory"), "bytes".to_string()),
+        ]))
+    }
+
+    fn normalize(normalizer: &UnitNormalizer, field: &str, value: &str) -> (f64, Option<String>) {
+        let tag: TagId = PipeTagId::new("timeseries").into();
+        let value = parse_value(value, ValueType::Float).unwrap();
+        let value = normalizer.normalize(&tag, &Symbol::new(field), value);
+        let value = value.float().unwrap();
+        (value.value(), value.unit().cloned())
+    }
+
+    #[test]
+    fn test_normalize() {
+        let normalizer = normalizer();
+        let unit = |s: &str| Some(s.to_string());
+
+        assert_eq!(
+            normalize(&normalizer, "latency", "1500 ms"),
+            (1.5, unit("s"))
+        );
+        assert_eq!(
+            normalize(&normalizer, "latency", "250 milliseconds"),
+            (0.25, unit("s"))
+        );
+        assert_eq!(
+            normalize(&normalizer, "memory", "2 MiB"),
+            (2097152.0, unit("bytes"))
+        );
+
+        // Only spelled canonically without a target
+        assert_eq!(
+            normalize(&normalizer, "duration", "1500 milliseconds"),
+            (1500.0, unit("ms"))
+        );
+        assert_eq!(
+            normalize(&normalizer, "cpu", "42 %"),
+            (42.0, unit("percent"))
+        );
+        assert_eq!(normalize(&normalizer, "cpu", "42"), (42.0, None));
+
+        // Unknown or mismatched units pass through, warned about once
+        assert_eq!(
+            normalize(&normalizer, "latency", "3 furlongs"),
+            (3.0, unit("furlongs"))
+        );
+        assert_eq!(
+            normalize(&normalizer, "latency", "4 furlongs"),
+            (4.0, unit("furlongs"))
+        );
+        assert_eq!(normalize(&normalizer, "latency", "1 KB"), (1.0, unit("KB")));
+        assert_eq!(normalizer.warned.lock().len(), 2);
+    }
+
+    #[test]
+    fn test_normalize_int() {
+        let normalizer = normalizer();
+        let tag: TagId = PipeTagId::new("timeseries").into();
+        let normalize = |field: &str, value: &str| {
+            let value = parse_value(value, ValueType::Int).unwrap();
+            let value = normalizer.normalize(&tag, &Symbol::new(field), value);
+            let value = value.int().unwrap();
+            (value.value(), value.unit().cloned())
+        };
+        let unit = |s: &str| Some(s.to_string());
+
+        assert_eq!(normalize("memory", "2 MiB"), (2097152, unit("bytes")));
+        assert_eq!(normalize("duration", "3 milliseconds"), (3, unit("ms")));
+        // Not an int in seconds, nor exact as a float
+        assert_eq!(normalize("latency", "1500 ms"), (1500, unit("ms")));
+        assert_eq!(
+            normalize("memory", "9007199254740993 KiB"),
+            (9007199254740993, unit("KiB"))
+        );
+    }
+}
@@ -0,0 +1,350 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    actor_debug, actor_warn,
+    config::pipe::{
+        transform::{TransformOp, TransformPipeConfig},
+        Priority,
+    },
+    core::{
+        actor::Actor,
+        dead_letter::DeadLetter,
+        manager::{ChannelGraph, SendError, TaggedReceiver, TaggedSender},
+        pipe::Pipe,
+        tag::{HasTag, TagId},
+        types::{Record, Symbol, Value},
+    },
+    utils::{recv::recv_batch, stats::GLOBAL_STATS},
+};
+
+/// [`TransformOp`] with the value of `set` converted once.
+enum Operation {
+    Rename {
+        from: Symbol,
+        to: Symbol,
+        strict: bool,
+    },
+    Drop(Symbol),
+    Copy {
+        from: Symbol,
+        to: Symbol,
+    },
+    Set(Symbol, Value),
+    Coalesce {
+        fields: Vec<Symbol>,
+        into: Symbol,
+    },
+}
+
+impl TryFrom<TransformOp> for Operation {
+    type Error = super::Error;
+
+    fn try_from(op: TransformOp) -> super::Result<Self> {
+        Ok(match op {
+            TransformOp::Rename { from, to, strict } => Operation::Rename { from, to, strict },
+            TransformOp::Drop { field } => Operation::Drop(field),
+            TransformOp::Copy { from, to } => Operation::Copy { from, to },
+            TransformOp::Set { field, value } => {
+                let value = Value::try_from(&value)
+                    .map_err(|e| super::Error::InvalidAction(format!("set {}: {}", field, e)))?;
+                Operation::Set(field, value)
+            }
+            TransformOp::Coalesce { fields, into } => Operation::Coalesce { fields, into },
+        })
+    }
+}
+
+/// Applies the operations to the fields of each record in order and forwards it. The records
+/// missing the field of a strict `rename` are sent to the dead letter channel.
+pub struct TransformPipe {
+    tag: TagId,
+    operations: Vec<Operation>,
+    priority: Option<Priority>,
+
+    inbounds: Vec<TaggedReceiver>,
+    outbound: TaggedSender,
+    dead_letter: DeadLetter,
+
+    interval: Duration,
+    buffer_size: usize,
+    max_batch_latency: Duration,
+}
+
+impl TransformPipe {
+    pub fn try_create_from(
+        cfg: TransformPipeConfig,
+        channels: &mut ChannelGraph,
+    ) -> super::Result<Self> {
+        let tag: TagId = (&cfg.tag).into();
+        let inbounds = cfg
+            .inbounds
+            .iter()
+            .map(|inbound| channels.recv_from(inbound, &tag))
+            .collect::<Vec<_>>();
+        let outbound = channels.sender(&tag);
+        let dead_letter = channels.dead_letter(&tag);
+
+        Self::new(cfg, inbounds, outbound, dead_letter)
+    }
+
+    fn new(
+        cfg: TransformPipeConfig,
+        inbounds: Vec<TaggedReceiver>,
+        outbound: TaggedSender,
+        dead_letter: DeadLetter,
+    ) -> super::Result<Self> {
+        let operations = cfg
+            .operations
+            .into_iter()
+            .map(Operation::try_from)
+            .collect::<super::Result<Vec<_>>>()?;
+
+        Ok(TransformPipe {
+            tag: cfg.tag.into(),
+            operations,
+            priority: cfg.priority,
+            inbounds,
+            outbound,
+            dead_letter,
+            interval: cfg.recv_timeout.into(),
+            buffer_size: cfg.recv_buffer_size,
+            max_batch_latency: cfg.max_batch_latency.into(),
+        })
+    }
+
+    /// Only the fields are changed, the attributes of the record are kept as they are.
+    fn apply(&self, record: &mut Record) -> super::Result<()> {
+        for op in &self.operations {
+            match op {
+                Operation::Rename { from, to, strict } => match record.remove(from) {
+                    Some(value) => record.set(to.clone(), value),
+                    None if *strict => {
+                        return Err(super::Error::InvalidRecord(format!(
+                            "missing field {} to rename",
+                            from
+                        )));
+                    }
+                    None => {}
+                },
+                Operation::Drop(field) => {
+                    record.remove(field);
+                }
+                Operation::Copy { from, to } => {
+                    if let Some(value) = record.get(from).cloned() {
+                        record.set(to.clone(), value);
+                    }
+                }
+                Operation::Set(field, value) => record.set(field.clone(), value.clone()),
+                Operation::Coalesce { fields, into } => {
+                    let value = fields
+                        .iter()
+                        .filter_map(|field| record.get(field))
+                        .find(|value| !matches!(value, Value::Null))
+                        .cloned();
+                    if let Some(value) = value {
+                        record.set(into.clone(), value);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl HasTag for TransformPipe {
+    fn tag(&self) -> &TagId {
+        &self.tag
+    }
+}
+
+#[async_trait]
+impl Actor for TransformPipe {
+    type Error = super::Error;
+
+    async fn poll(&mut self, ctx: CancellationToken) -> super::Result<()> {
+        let tag = self.tag.clone();
+        let records = match recv_batch(
+            &tag,
+            &mut self.inbounds,
+            Some(self.interval),
+            self.buffer_size,
+            self.max_batch_latency,
+            ctx,
+        )
+        .await
+        {
+            Ok(records) => records,
+            Err(crate::utils::recv::Error::Timeout) => {
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut rejected = 0;
+        for mut record in records {
+            match self.apply(&mut record) {
+                Ok(()) => {
+                    if let Some(priority) = self.priority {
+                        record.set_priority(priority);
+                    }
+                    if let Err(e @ SendError::Closed(_)) = self.outbound.send(record).await {
+                        actor_warn!(self.tag, "error sending record: {}", e);
+                    }
+                }
+                Err(e) => {
+                    rejected += 1;
+                    actor_debug!(self.tag, "rejected a record: {}", e);
+                    self.dead_letter.send(record, &e);
+                }
+            }
+        }
+
+        if rejected > 0 {
+            GLOBAL_STATS.incr(&format!("{} rejected records", self.tag), rejected);
+        }
+
+        Ok(())
+    }
+}
+
+impl Pipe for TransformPipe {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{
+        manager::ActorChannel,
+        tag::{InboundTagId, PipeTagId, INTERNAL_TAG_SCOPE},
+        types::Attribute,
+    };
+
+    fn config(operations: &str) -> TransformPipeConfig {
+        toml::from_str(&format!(
+            "inbounds = [\"inbound:a\"]\noperations = [{}]",
+            operations
+        ))
+        .unwrap()
+    }
+
+    fn pipe(cfg: TransformPipeConfig) -> TransformPipe {
+        let tag: TagId = (&cfg.tag).into();
+        let mut input = ActorChannel::new(InboundTagId::new("a").into(), 16);
+        let mut output = ActorChannel::new(tag.clone(), 16);
+        TransformPipe::new(
+            cfg,
+            vec![input.receiver(&tag)],
+            output.sender(),
+            DeadLetter::new(tag, None),
+        )
+        .unwrap()
+    }
+
+    fn record(fields: &[(&str, Value)]) -> Record {
+        let mut record = Record::empty();
+        for (name, value) in fields {
+            record.set(Symbol::new(name), value.clone());
+        }
+        record
+    }
+
+    fn get<'a>(record: &'a Record, name: &str) -> Option<&'a Value> {
+        record.get(&Symbol::new(name))
+    }
+
+    #[test]
+    fn test_operation_order() {
+        let pipe = pipe(config(
+            r#"{ op = "rename", from = "hostName", to = "host" },
+               { op = "copy", from = "host", to = "instance" },
+               { op = "drop", field = "host" },
+               { op = "set", field = "env", value = "prod" },
+               { op = "coalesce", fields = ["region", "zone", "env"], into = "location" },
+               { op = "set", field = "env", value = 1 }"#,
+        ));
+
+        let mut r = record(&[
+            ("hostName", Value::from("web-1")),
+            ("zone", Value::Null),
+            ("value", Value::from(1.5)),
+        ]);
+        pipe.apply(&mut r).unwrap();
+
+        assert_eq!(get(&r, "hostName"), None);
+        assert_eq!(get(&r, "host"), None);
+        assert_eq!(get(&r, "instance"), Some(&Value::from("web-1")));
+        // coalesce sees the first value of env, set again afterwards
+        assert_eq!(get(&r, "location"), Some(&Value::from("prod")));
+        assert_eq!(get(&r, "env"), Some(&Value::from(1i64)));
+        assert_eq!(get(&r, "zone"), Some(&Value::Null));
+        assert_eq!(get(&r, "value"), Some(&Value::from(1.5)));
+
+        // Missing fields are no-ops
+        let mut r = record(&[("value", Value::from(1.5))]);
+        pipe.apply(&mut r).unwrap();
+        assert_eq!(get(&r, "instance"), None);
+        assert_eq!(get(&r, "location"), Some(&Value::from("prod")));
+    }
+
+    #[test]
+    fn test_attributes_untouched() {
+        let pipe = pipe(config(
+            r#"{ op = "drop", field = "__type__" },
+               { op = "rename", from = "name", to = "__name__" }"#,
+        ));
+
+        let mut r = record(&[("name", Value::from("cpu"))]);
+        r.set_type(Value::from("timeseries"));
+        r.set_attribute(Attribute::Error, Value::from("oops"));
+        pipe.apply(&mut r).unwrap();
+
+        assert_eq!(r.get_type(), Some(&Value::from("timeseries")));
+        assert_eq!(
+            r.get_attribute(&Attribute::Error),
+            Some(&Value::from("oops"))
+        );
+        assert_eq!(get(&r, "__name__"), Some(&Value::from("cpu")));
+        assert_eq!(r.attributes().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_strict_rename() {
+        let cfg = config(r#"{ op = "rename", from = "hostName", to = "host", strict = true }"#);
+        let tag: TagId = (&cfg.tag).into();
+
+        let mut input = ActorChannel::new(InboundTagId::new("a").into(), 16);
+        let mut output = ActorChannel::new(tag.clone(), 16);
+        let mut dead_letters = ActorChannel::new(TagId::new(INTERNAL_TAG_SCOPE, "dead_letter"), 16);
+        let mut received = output.receiver(&PipeTagId::new("timeseries").into());
+        let mut rejected = dead_letters.receiver(&PipeTagId::new("dump").into());
+        let mut pipe = TransformPipe::new(
+            cfg,
+            vec![input.receiver(&tag)],
+            output.sender(),
+            DeadLetter::new(tag, Some(dead_letters.sender())),
+        )
+        .unwrap();
+
+        let mut sender = input.sender();
+        sender
+            .send(record(&[("hostName", Value::from("web-1"))]))
+            .await
+            .unwrap();
+        sender
+            .send(record(&[("host", Value::from("web-2"))]))
+            .await
+            .unwrap();
+        pipe.poll(CancellationToken::new()).await.unwrap();
+
+        let renamed = received.recv().await.unwrap();
+        assert_eq!(get(&renamed, "host"), Some(&Value::from("web-1")));
+        assert!(received.try_recv().is_err());
+
+        let invalid = rejected.recv().await.unwrap();
+        assert_eq!(get(&invalid, "host"), Some(&Value::from("web-2")));
+        assert!(invalid.get_attribute(&Attribute::Error).is_some());
+    }
+}
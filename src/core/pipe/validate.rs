@@ -0,0 +1,460 @@
+use std::{collections::HashMap, time::Duration};
+
+use async_trait::async_trait;
+use chrono_tz::Tz;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    actor_debug, actor_warn,
+    config::pipe::validate::{ExtraFields, MismatchPolicy, SchemaField, ValidatePipeConfig},
+    core::{
+        actor::Actor,
+        dead_letter::DeadLetter,
+        manager::{ChannelGraph, SendError, TaggedReceiver, TaggedSender},
+        metrics,
+        pipe::Pipe,
+        tag::{HasTag, TagId},
+        types::{parse_primitive_in, Attribute, Primitive, Record, Symbol, Value},
+    },
+    utils::{recv::recv_batch, stats::GLOBAL_STATS},
+};
+
+/// A change making a record match the schema.
+#[derive(Debug, PartialEq)]
+enum Fix {
+    Set(Symbol, Value),
+    Remove(Symbol),
+}
+
+/// Forwards the records matching the schema, fixed as `on_mismatch` says. The others are
+/// sent to the dead letter channel, or dropped as errors.
+pub struct ValidatePipe {
+    tag: TagId,
+    fields: Vec<SchemaField>,
+    on_mismatch: MismatchPolicy,
+    // The declared fields, the only ones kept with `extra_fields = "strip"`
+    strip_to: Option<Vec<Symbol>>,
+
+    inbounds: Vec<TaggedReceiver>,
+    outbound: TaggedSender,
+    dead_letter: DeadLetter,
+
+    interval: Duration,
+    buffer_size: usize,
+    max_batch_latency: Duration,
+}
+
+impl ValidatePipe {
+    pub fn try_create_from(
+        cfg: ValidatePipeConfig,
+        channels: &mut ChannelGraph,
+    ) -> super::Result<Self> {
+        let tag: TagId = (&cfg.tag).into();
+        let inbounds = cfg
+            .inbounds
+            .iter()
+            .map(|inbound| channels.recv_from(inbound, &tag))
+            .collect::<Vec<_>>();
+        let outbound = channels.sender(&tag);
+        let dead_letter = channels.dead_letter(&tag);
+
+        Ok(Self::new(cfg, inbounds, outbound, dead_letter))
+    }
+
+    fn new(
+        cfg: ValidatePipeConfig,
+        inbounds: Vec<TaggedReceiver>,
+        outbound: TaggedSender,
+        dead_letter: DeadLetter,
+    ) -> Self {
+        let strip_to = (cfg.extra_fields == ExtraFields::Strip)
+            .then(|| cfg.fields.iter().map(|field| field.name.clone()).collect());
+        let on_mismatch = cfg.on_mismatch();
+        ValidatePipe {
+            tag: cfg.tag.into(),
+            on_mismatch,
+            fields: cfg.fields,
+            strip_to,
+            inbounds,
+            outbound,
+            dead_letter,
+            interval: cfg.recv_timeout.into(),
+            buffer_size: cfg.recv_buffer_size,
+            max_batch_latency: cfg.max_batch_latency.into(),
+        }
+    }
+
+    /// The fixes to apply to a valid record, or why it is not. The fields missing or of
+    /// another type are pushed to `mismatched`, until the first making the record invalid.
+    fn check(&self, record: &Record, mismatched: &mut Vec<Symbol>) -> super::Result<Vec<Fix>> {
+        let mut fixes = Vec::new();
+        for field in &self.fields {
+            let value = match record.get(&field.name) {
+                None | Some(Value::Null) if field.required => {
+                    mismatched.push(field.name.clone());
+                    return Err(super::Error::InvalidRecord(format!(
+                        "missing required field {}",
+                        field.name
+                    )));
+                }
+                None | Some(Value::Null) => continue,
+                Some(value) => value,
+            };
+
+            if value.type_name() == field.r#type.as_str() {
+                continue;
+            }
+
+            mismatched.push(field.name.clone());
+            match self.on_mismatch {
+                MismatchPolicy::Coerce => {
+                    fixes.push(Fix::Set(field.name.clone(), coerce(value, &field.r#type)?))
+                }
+                MismatchPolicy::Drop if !field.required => {
+                    fixes.push(Fix::Remove(field.name.clone()))
+                }
+                _ => {
+                    return Err(super::Error::InvalidRecord(format!(
+                        "field {} is {}, expected {}",
+                        field.name,
+                        value.type_name(),
+                        field.r#type
+                    )));
+                }
+            }
+        }
+
+        Ok(fixes)
+    }
+
+    /// Count the mismatched fields of a batch in `<tag> mismatched <field> from <inbound>`,
+    /// to tell which producer sends them.
+    fn count_mismatches(&self, mismatches: HashMap<(Symbol, String), u64>) {
+        for ((field, inbound), count) in mismatches {
+            GLOBAL_STATS.incr(
+                &format!("{} mismatched {} from {}", self.tag, field, inbound),
+                count,
+            );
+        }
+    }
+}
+
+/// Strings are parsed as values of the type, the other values are cast.
+fn coerce(value: &Value, r#type: &Primitive) -> crate::core::types::Result<Value> {
+    if let Value::String(s) = value {
+        return parse_primitive_in(s.as_str(), r#type, Tz::UTC);
+    }
+
+    match r#type {
+        Primitive::String => value.cast_string(),
+        Primitive::Int => value.cast_int(),
+        Primitive::Float => value.cast_float(),
+        Primitive::Bool => value.cast_bool(),
+        Primitive::DateTime => value.cast_datetime(),
+        _ => Err(crate::core::types::Error::UnexpectedType(
+            r#type.as_str(),
+            value.type_name(),
+        )),
+    }
+}
+
+impl HasTag for ValidatePipe {
+    fn tag(&self) -> &TagId {
+        &self.tag
+    }
+}
+
+#[async_trait]
+impl Actor for ValidatePipe {
+    type Error = super::Error;
+
+    async fn poll(&mut self, ctx: CancellationToken) -> super::Result<()> {
+        let tag = self.tag.clone();
+        let records = match recv_batch(
+            &tag,
+            &mut self.inbounds,
+            Some(self.interval),
+            self.buffer_size,
+            self.max_batch_latency,
+            ctx,
+        )
+        .await
+        {
+            Ok(records) => records,
+            Err(crate::utils::recv::Error::Timeout) => {
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let total = records.len();
+        let mut rejected = 0;
+        let mut mismatched = Vec::new();
+        // Mismatched fields by the inbound they came from
+        let mut mismatches = HashMap::<(Symbol, String), u64>::new();
+        for mut record in records {
+            let result = self.check(&record, &mut mismatched);
+            if !mismatched.is_empty() {
+                let inbound = record
+                    .get_attribute(&Attribute::Inbound)
+                    .map_or_else(|| "unknown".to_string(), Value::to_string);
+                for field in mismatched.drain(..) {
+                    *mismatches.entry((field, inbound.clone())).or_default() += 1;
+                }
+            }
+
+            match result {
+                Ok(fixes) => {
+                    for fix in fixes {
+                        match fix {
+                            Fix::Set(name, value) => record.set(name, value),
+                            Fix::Remove(name) => {
+                                record.remove(&name);
+                            }
+                        }
+                    }
+                    if let Some(ref declared) = self.strip_to {
+                        record.retain_fields(declared);
+                    }
+                    if let Err(e @ SendError::Closed(_)) = self.outbound.send(record).await {
+                        actor_warn!(self.tag, "error sending record: {}", e);
+                    }
+                }
+                Err(e) if self.on_mismatch == MismatchPolicy::Error => {
+                    rejected += 1;
+                    actor_warn!(self.tag, "rejected a record: {}", e);
+                    metrics::count_transform_error(&self.tag);
+                }
+                Err(e) => {
+                    rejected += 1;
+                    actor_debug!(self.tag, "rejected a record: {}", e);
+                    self.dead_letter.send(record, &e);
+                }
+            }
+        }
+
+        self.count_mismatches(mismatches);
+        if rejected > 0 {
+            actor_debug!(self.tag, "rejected {} of {} records", rejected, total);
+            GLOBAL_STATS.incr(&format!("{} rejected records", self.tag), rejected);
+        }
+
+        Ok(())
+    }
+}
+
+impl Pipe for ValidatePipe {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{
+        manager::ActorChannel,
+        tag::{InboundTagId, PipeTagId, INTERNAL_TAG_SCOPE},
+        types::Attribute,
+    };
+
+    fn record(value: Value) -> Record {
+        let mut record = Record::empty();
+        record.set(Symbol::new("name"), Value::from("cpu"));
+        record.set(Symbol::new("value"), value);
+        record
+    }
+
+    fn config(coerce: bool) -> ValidatePipeConfig {
+        config_with(&format!("coerce = {}", coerce))
+    }
+
+    fn config_with(body: &str) -> ValidatePipeConfig {
+        toml::from_str(&format!(
+            r#"
+            inbounds = ["inbound:a"]
+            {}
+            fields = [
+                {{ name = "value", type = "float", required = true }},
+                {{ name = "name", type = "string", required = true }},
+                {{ name = "count", type = "int" }},
+            ]
+            "#,
+            body
+        ))
+        .unwrap()
+    }
+
+    fn pipe(cfg: ValidatePipeConfig) -> ValidatePipe {
+        let tag: TagId = (&cfg.tag).into();
+        let mut input = ActorChannel::new(InboundTagId::new("a").into(), 16);
+        let mut output = ActorChannel::new(tag.clone(), 16);
+        ValidatePipe::new(
+            cfg,
+            vec![input.receiver(&tag)],
+            output.sender(),
+            DeadLetter::new(tag, None),
+        )
+    }
+
+    impl ValidatePipe {
+        fn check_all(&self, record: &Record) -> super::super::Result<Vec<Fix>> {
+            self.check(record, &mut Vec::new())
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let strict = pipe(config(false));
+        assert!(strict
+            .check_all(&record(Value::from(1.5)))
+            .unwrap()
+            .is_empty());
+
+        let mut with_count = record(Value::from(1.5));
+        with_count.set(Symbol::new("count"), Value::Null);
+        assert!(strict.check_all(&with_count).unwrap().is_empty());
+        with_count.set(Symbol::new("count"), Value::from(3i64));
+        assert!(strict.check_all(&with_count).unwrap().is_empty());
+
+        for invalid in [
+            record(Value::from("1.5")),
+            record(Value::from(1i64)),
+            record(Value::Null),
+            Record::empty(),
+        ] {
+            assert!(
+                matches!(
+                    strict.check_all(&invalid),
+                    Err(super::super::Error::InvalidRecord(..))
+                ),
+                "{}",
+                invalid
+            );
+        }
+
+        let coercing = pipe(config(true));
+        assert_eq!(
+            coercing.check_all(&record(Value::from(" 1.5 "))).unwrap(),
+            vec![Fix::Set(Symbol::new("value"), Value::from(1.5))]
+        );
+        assert_eq!(
+            coercing.check_all(&record(Value::from(2i64))).unwrap(),
+            vec![Fix::Set(Symbol::new("value"), Value::from(2.0))]
+        );
+        assert_eq!(
+            coercing.check_all(&record(Value::from(true))).unwrap(),
+            vec![Fix::Set(Symbol::new("value"), Value::from(1.0))]
+        );
+
+        let mut with_count = record(Value::from(1.5));
+        with_count.set(Symbol::new("count"), Value::from("12"));
+        assert_eq!(
+            coercing.check_all(&with_count).unwrap(),
+            vec![Fix::Set(Symbol::new("count"), Value::from(12i64))]
+        );
+
+        assert!(matches!(
+            coercing.check_all(&record(Value::from("n/a"))),
+            Err(super::super::Error::Type(..))
+        ));
+        assert!(matches!(
+            coercing.check_all(&record(Value::from(vec![Value::from(1.0)]))),
+            Err(super::super::Error::Type(..))
+        ));
+        assert!(matches!(
+            coercing.check_all(&record(Value::Null)),
+            Err(super::super::Error::InvalidRecord(..))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_validate_pipe() {
+        let cfg = config(true);
+        let tag: TagId = (&cfg.tag).into();
+
+        let mut input = ActorChannel::new(InboundTagId::new("a").into(), 16);
+        let mut output = ActorChannel::new(tag.clone(), 16);
+        let mut dead_letters = ActorChannel::new(TagId::new(INTERNAL_TAG_SCOPE, "dead_letter"), 16);
+        let mut received = output.receiver(&PipeTagId::new("timeseries").into());
+        let mut rejected = dead_letters.receiver(&PipeTagId::new("dump").into());
+        let mut pipe = ValidatePipe::new(
+            cfg,
+            vec![input.receiver(&tag)],
+            output.sender(),
+            DeadLetter::new(tag, Some(dead_letters.sender())),
+        );
+
+        let mut sender = input.sender();
+        sender.send(record(Value::from("42"))).await.unwrap();
+        sender.send(record(Value::from("n/a"))).await.unwrap();
+        pipe.poll(CancellationToken::new()).await.unwrap();
+
+        let valid = received.recv().await.unwrap();
+        assert_eq!(valid.get(&Symbol::new("value")), Some(&Value::from(42.0)));
+        assert!(received.try_recv().is_err());
+
+        let invalid = rejected.recv().await.unwrap();
+        assert_eq!(
+            invalid.get(&Symbol::new("value")),
+            Some(&Value::from("n/a"))
+        );
+        assert!(invalid.get_attribute(&Attribute::Error).is_some());
+    }
+
+    #[test]
+    fn test_on_mismatch() {
+        let mut invalid = record(Value::from(1.5));
+        invalid.set(Symbol::new("count"), Value::from("n/a"));
+
+        let dropping = pipe(config_with(r#"on_mismatch = "drop""#));
+        let mut mismatched = Vec::new();
+        assert_eq!(
+            dropping.check(&invalid, &mut mismatched).unwrap(),
+            vec![Fix::Remove(Symbol::new("count"))]
+        );
+        assert_eq!(mismatched, vec![Symbol::new("count")]);
+
+        // A required field is never dropped
+        mismatched.clear();
+        assert!(dropping
+            .check(&record(Value::from("1.5")), &mut mismatched)
+            .is_err());
+        assert_eq!(mismatched, vec![Symbol::new("value")]);
+
+        let erroring = pipe(config_with(r#"on_mismatch = "error""#));
+        assert!(matches!(
+            erroring.check_all(&invalid),
+            Err(super::super::Error::InvalidRecord(..))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_drop_and_strip() {
+        let cfg = config_with(
+            r#"
+            on_mismatch = "drop"
+            extra_fields = "strip"
+            "#,
+        );
+        let tag: TagId = (&cfg.tag).into();
+
+        let mut input = ActorChannel::new(InboundTagId::new("a").into(), 16);
+        let mut output = ActorChannel::new(tag.clone(), 16);
+        let mut received = output.receiver(&PipeTagId::new("timeseries").into());
+        let mut pipe = ValidatePipe::new(
+            cfg,
+            vec![input.receiver(&tag)],
+            output.sender(),
+            DeadLetter::new(tag, None),
+        );
+
+        let mut sent = record(Value::from(1.5));
+        sent.set(Symbol::new("count"), Value::from("n/a"));
+        sent.set(Symbol::new("host"), Value::from("a"));
+        input.sender().send(sent).await.unwrap();
+        pipe.poll(CancellationToken::new()).await.unwrap();
+
+        let valid = received.recv().await.unwrap();
+        assert_eq!(valid.get(&Symbol::new("value")), Some(&Value::from(1.5)));
+        assert_eq!(valid.get(&Symbol::new("name")), Some(&Value::from("cpu")));
+        assert_eq!(valid.get(&Symbol::new("count")), None);
+        assert_eq!(valid.get(&Symbol::new("host")), None);
+    }
+}
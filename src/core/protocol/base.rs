@@ -6,3 +6,13 @@ use crate::core::types::Record;
 pub trait ProtocolParser: Send {
     async fn read_next(&mut self) -> super::Result<Record>;
 }
+
+/// UTF-8 byte order mark, as written at the start of a file by some Windows tools.
+const UTF8_BOM: char = '\u{feff}';
+
+/// Strip the byte order mark off the first line of a stream.
+pub(super) fn strip_bom(line: &mut String) {
+    if line.starts_with(UTF8_BOM) {
+        line.drain(..UTF8_BOM.len_utf8());
+    }
+}
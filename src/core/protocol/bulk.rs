@@ -0,0 +1,367 @@
+/*
+Bulk read mode for large regular files.
+
+The file is memory-mapped and parsed window by window: each window is split at record
+boundaries into chunks which are parsed in parallel, the records are then emitted in
+file order. Records are split and parsed exactly as in the streaming parser, a line break
+inside quotes does not end a record.
+*/
+
+use std::{collections::VecDeque, fs::File, ops::Range, sync::Arc};
+
+use async_trait::async_trait;
+use memmap2::Mmap;
+use rayon::prelude::*;
+
+use crate::{config::protocol::csv::CSVProtocolConfig, core::protocol, core::types::Record};
+
+use super::csv_nom::{CSVLineParser, RecordScanner};
+
+const WINDOW_SIZE: usize = 32 * 1024 * 1024;
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+
+pub struct BulkCSVParser {
+    mmap: Arc<Mmap>,
+    offset: usize,
+
+    line_parser: Arc<CSVLineParser>,
+    header_checked: bool,
+
+    pending: VecDeque<protocol::Result<Record>>,
+}
+
+impl BulkCSVParser {
+    pub fn try_create_from(file: &File, cfg: CSVProtocolConfig) -> protocol::Result<Self> {
+        // Safety: 文件在读取期间不应被截断或修改, 这也是批量模式只用于导出文件的原因
+        let mmap = unsafe { Mmap::map(file)? };
+
+        Ok(Self {
+            mmap: Arc::new(mmap),
+            offset: 0,
+            line_parser: Arc::new(CSVLineParser::new(cfg)),
+            header_checked: false,
+            pending: VecDeque::new(),
+        })
+    }
+
+    fn check_header(&mut self) -> protocol::Result<()> {
+        self.header_checked = true;
+
+        if self.mmap.starts_with(UTF8_BOM) {
+            self.offset = UTF8_BOM.len();
+        }
+
+        if !self.line_parser.has_header() {
+            return Ok(());
+        }
+
+        let (line, next) = next_line(
+            &self.mmap,
+            self.offset..self.mmap.len(),
+            self.line_parser.scanner(),
+        )
+        .ok_or(protocol::Error::Eof)?;
+        self.offset = next;
+
+        let line = String::from_utf8_lossy(&self.mmap[line]);
+        Arc::make_mut(&mut self.line_parser).parse_header(&line)
+    }
+
+    /// Parse the next window of the file into `pending`.
+    async fn fill(&mut self) -> protocol::Result<()> {
+        let start = self.offset;
+        let mmap = self.mmap.clone();
+        let line_parser = self.line_parser.clone();
+        let (end, chunks) = tokio::task::spawn_blocking(move || {
+            // 记录边界取决于此前的引号, 只能顺序查找
+            let chunks = split_chunks(&mmap, start..start + WINDOW_SIZE, line_parser.scanner());
+            let end = chunks.last().map_or(start, |chunk| chunk.end);
+            let records = chunks
+                .into_par_iter()
+                .map(|chunk| parse_chunk(&mmap, chunk, &line_parser))
+                .collect::<Vec<_>>();
+            (end, records)
+        })
+        .await
+        .map_err(|e| protocol::Error::Io(std::io::Error::other(e)))?;
+
+        self.offset = end;
+        self.pending.extend(chunks.into_iter().flatten());
+        Ok(())
+    }
+}
+
+/// Split the records from `window.start` into chunks of about `CHUNK_SIZE`, up to the end of
+/// the record containing `window.end`. `window.start` must be the start of a record.
+fn split_chunks(data: &[u8], window: Range<usize>, scanner: RecordScanner) -> Vec<Range<usize>> {
+    let mut chunks = Vec::new();
+    let mut start = window.start;
+    let mut pos = window.start;
+    while start < window.end.min(data.len()) {
+        let target = (start + CHUNK_SIZE).min(window.end);
+        while pos < target {
+            match next_line(data, pos..data.len(), scanner) {
+                Some((_, next)) => pos = next,
+                None => break,
+            }
+        }
+        chunks.push(start..pos);
+        start = pos;
+    }
+    chunks
+}
+
+/// Same record splitting as the streaming parser: a record ends at `\n`, `\r\n` or a lone `\r`
+/// outside of quotes. Returns the record and the start of the next one.
+fn next_line(
+    data: &[u8],
+    range: Range<usize>,
+    mut scanner: RecordScanner,
+) -> Option<(Range<usize>, usize)> {
+    if range.is_empty() {
+        return None;
+    }
+
+    let slice = &data[range.clone()];
+    // The data of the record ends with the range
+    match scanner.find_end(slice).or_else(|| scanner.end_of_data()) {
+        Some(pos) => {
+            let end = range.start + pos;
+            let terminator = if slice[pos] == b'\r' && slice.get(pos + 1) == Some(&b'\n') {
+                2
+            } else {
+                1
+            };
+            Some((range.start..end, end + terminator))
+        }
+        None => Some((range.clone(), range.end)),
+    }
+}
+
+fn parse_chunk(
+    data: &[u8],
+    chunk: Range<usize>,
+    line_parser: &CSVLineParser,
+) -> Vec<protocol::Result<Record>> {
+    let mut records = Vec::new();
+    let mut start = chunk.start;
+
+    while let Some((line, next)) = next_line(data, start..chunk.end, line_parser.scanner()) {
+        start = next;
+
+        let line = String::from_utf8_lossy(&data[line]);
+        let record = line_parser.parse_line(&line);
+        let stop = record.is_err();
+        records.push(record);
+
+        // 与流式读取一样, 读取在第一个错误 (或空行) 处结束, 其后的行无需解析
+        if stop {
+            break;
+        }
+    }
+
+    records
+}
+
+#[async_trait]
+impl protocol::ProtocolParser for BulkCSVParser {
+    async fn read_next(&mut self) -> protocol::Result<Record> {
+        if !self.header_checked {
+            self.check_header()?;
+        }
+
+        loop {
+            if let Some(record) = self.pending.pop_front() {
+                return record;
+            }
+
+            if self.offset >= self.mmap.len() {
+                return Err(protocol::Error::Eof);
+            }
+
+            self.fill().await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, time::Instant};
+
+    use crate::{
+        config::protocol::csv::{CSVField, MatchBy},
+        core::{
+            protocol::{csv_nom::CSVProtocolParser, ProtocolParser},
+            tag::{TagId, PROTOCOL_TAG_SCOPE},
+            types::{Primitive, Symbol},
+        },
+    };
+
+    use super::*;
+
+    fn config(has_header: bool, match_by: MatchBy) -> CSVProtocolConfig {
+        let field = |index, name, r#type, optional| CSVField {
+            index,
+            name: Symbol::new(name),
+            r#type,
+            optional,
+        };
+
+        CSVProtocolConfig {
+            tag: TagId::new(PROTOCOL_TAG_SCOPE, "csv").into(),
+            delimiter: ',',
+            has_header,
+            num_fields: 4,
+            match_by,
+            header_case_insensitive: false,
+            strict_quotes: false,
+            timezone: chrono_tz::Tz::UTC,
+            fields: vec![
+                field(0, "name", Primitive::String, false),
+                field(1, "value", Primitive::Float, false),
+                field(2, "count", Primitive::Int, false),
+                field(3, "label", Primitive::String, true),
+            ],
+        }
+    }
+
+    fn write_file(content: &[u8]) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(content).unwrap();
+        file.flush().unwrap();
+        file
+    }
+
+    fn generate(lines: usize) -> String {
+        let mut content = String::from("name,value,count,label\n");
+        for i in 0..lines {
+            let label = if i % 3 == 0 { "" } else { "\"a, b\"" };
+            content.push_str(&format!("metric_{},{}.5,{},{}\n", i % 97, i, i, label));
+        }
+        content
+    }
+
+    // Read until the first error, as the inbound does; the error is kept as a string.
+    async fn read_all(mut parser: Box<dyn ProtocolParser>) -> (Vec<String>, String) {
+        let mut records = Vec::new();
+        loop {
+            match parser.read_next().await {
+                Ok(record) => records.push(record.to_string()),
+                Err(e) => return (records, e.to_string()),
+            }
+        }
+    }
+
+    async fn both_modes(content: &[u8], cfg: CSVProtocolConfig) -> [(Vec<String>, String); 2] {
+        let file = write_file(content);
+
+        let streaming = CSVProtocolParser::try_create_from(
+            tokio::fs::File::open(file.path()).await.unwrap(),
+            cfg.clone(),
+        )
+        .unwrap();
+        let bulk = BulkCSVParser::try_create_from(&File::open(file.path()).unwrap(), cfg).unwrap();
+
+        [
+            read_all(Box::new(streaming)).await,
+            read_all(Box::new(bulk)).await,
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_identical_output() {
+        let content = generate(20_000);
+        let [streaming, bulk] = both_modes(content.as_bytes(), config(true, MatchBy::Index)).await;
+
+        assert_eq!(streaming.0.len(), 20_000);
+        assert_eq!(streaming, bulk);
+    }
+
+    #[tokio::test]
+    async fn test_identical_output_edge_cases() {
+        let cases: &[&[u8]] = &[
+            b"",
+            b"name,value,count,label\n",
+            b"\xef\xbb\xbfname,value,count,label\r\na,1.0,1,x\r\nb,2.0,2,\r\n",
+            b"name,value,count,label\na,1.0,1\rb,2.0,2,y",
+            b"name,value,count,label\na,1.0,1,x\n\nb,2.0,2,y\n",
+            b"name,value,count,label\na,1.0,1,x\nb,oops,2,y\nc,3.0,3,z\n",
+            b"name,value,count,label\na,1.0,1,\xff\xfe\n",
+            b"name,value,count,label\n\"a,\r\nb\",1.0,1,\"x\"\"\ny\"\n\"\",2.0,2\n",
+            b"name,value,count,label\n\"a,1.0,1,x\nb,2.0,2,y\n",
+        ];
+
+        for content in cases {
+            let [streaming, bulk] = both_modes(content, config(true, MatchBy::Index)).await;
+            assert_eq!(streaming, bulk, "{:?}", String::from_utf8_lossy(content));
+        }
+
+        let content = b"label,count,value,name\nx,1,1.0,a\n,2,2.0,b\n";
+        let [streaming, bulk] = both_modes(content, config(true, MatchBy::Header)).await;
+        assert_eq!(streaming.0.len(), 2);
+        assert_eq!(streaming, bulk);
+
+        let content = b"a,1.0,1,x\nb,2.0,2,y\n";
+        let [streaming, bulk] = both_modes(content, config(false, MatchBy::Index)).await;
+        assert_eq!(streaming.0.len(), 2);
+        assert_eq!(streaming, bulk);
+    }
+
+    #[test]
+    fn test_chunks_split_at_record_boundaries() {
+        let data = b"aaaa\nb,\"x\ny\r\nz\"\r\ncccccc\nd";
+        let scanner = RecordScanner::new(',');
+
+        assert_eq!(
+            split_chunks(data, 0..data.len(), scanner),
+            vec![0..data.len()]
+        );
+        assert_eq!(split_chunks(data, 0..1, scanner), vec![0..5]);
+        assert_eq!(split_chunks(data, 5..6, scanner), vec![5..17]);
+        assert_eq!(split_chunks(data, 17..30, scanner), vec![17..data.len()]);
+        assert_eq!(split_chunks(data, 24..100, scanner), vec![24..data.len()]);
+        assert!(split_chunks(data, 25..100, scanner).is_empty());
+    }
+
+    async fn count_all(mut parser: Box<dyn ProtocolParser>) -> usize {
+        let mut n = 0;
+        while parser.read_next().await.is_ok() {
+            n += 1;
+        }
+        n
+    }
+
+    // cargo test --release bench_bulk_vs_streaming -- --ignored --nocapture
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore]
+    async fn bench_bulk_vs_streaming() {
+        let file = write_file(generate(1_000_000).as_bytes());
+        let cfg = config(true, MatchBy::Index);
+
+        let start = Instant::now();
+        let parser = CSVProtocolParser::try_create_from(
+            tokio::fs::File::open(file.path()).await.unwrap(),
+            cfg.clone(),
+        )
+        .unwrap();
+        let streaming = count_all(Box::new(parser)).await;
+        let streaming_elapsed = start.elapsed();
+
+        let start = Instant::now();
+        let parser =
+            BulkCSVParser::try_create_from(&File::open(file.path()).unwrap(), cfg).unwrap();
+        let bulk = count_all(Box::new(parser)).await;
+        let bulk_elapsed = start.elapsed();
+
+        println!(
+            "1M lines: streaming {:?}, bulk {:?} ({:.1}x)",
+            streaming_elapsed,
+            bulk_elapsed,
+            streaming_elapsed.as_secs_f64() / bulk_elapsed.as_secs_f64()
+        );
+        assert_eq!(streaming, 1_000_000);
+        assert_eq!(streaming, bulk);
+    }
+}
@@ -79,7 +79,7 @@ where
                 match self.reader.read_u8().await {
                     Ok(0) => {
                         // EOF reached
-                        return Err(super::Error::EOF);
+                        return Err(super::Error::Eof);
                     }
                     Ok(c) => {
                         if c == b'\n' {
@@ -90,7 +90,7 @@ where
                     Err(e) => match e.kind() {
                         std::io::ErrorKind::UnexpectedEof => {
                             // EOF reached
-                            return Err(super::Error::EOF);
+                            return Err(super::Error::Eof);
                         }
                         std::io::ErrorKind::WouldBlock => {
                             // Would block, continue reading
@@ -218,7 +218,7 @@ where
                     Ok(0) => {
                         // Handle EOF condition
                         if self.input_buf.is_empty() {
-                            return Err(super::Error::EOF);
+                            return Err(super::Error::Eof);
                         }
                         // If we have some data left, continue processing it
                     }
@@ -242,7 +242,7 @@ where
                         match self.reader.read_buf(&mut self.input_buf).await {
                             Ok(0) => {
                                 // No more data to read
-                                return Err(super::Error::EOF);
+                                return Err(super::Error::Eof);
                             }
                             Ok(_) => {
                                 // Got more data, continue processing
@@ -270,7 +270,7 @@ where
                     self.input_buf.advance(input_pos);
                     return Ok(record);
                 }
-                ReadRecordResult::End => return Err(super::Error::EOF),
+                ReadRecordResult::End => return Err(super::Error::Eof),
             }
         }
     }
@@ -637,7 +637,7 @@ mod tests {
         let result = parser.read_next().await;
         assert!(result.is_err());
 
-        assert!(matches!(result.unwrap_err(), Error::EOF));
+        assert!(matches!(result.unwrap_err(), Error::Eof));
     }
 
     #[tokio::test]
@@ -648,7 +648,7 @@ mod tests {
 
         let result = parser.read_next().await;
         assert!(result.is_err());
-        assert!(matches!(result.unwrap_err(), Error::EOF));
+        assert!(matches!(result.unwrap_err(), Error::Eof));
     }
 
     #[tokio::test]
@@ -6,43 +6,57 @@ use nom::{
     branch::alt,
     bytes::complete::{tag, take_while},
     character::complete::char,
-    combinator::{eof, map, opt},
+    combinator::{eof, opt},
+    error::ErrorKind,
     multi::separated_list0,
-    sequence::{delimited, terminated},
+    sequence::terminated,
     IResult, Parser,
 };
 use tokio::io::AsyncReadExt;
 
 use crate::{
-    config::protocol::csv::CSVProtocolConfig,
+    config::protocol::csv::{CSVProtocolConfig, MatchBy},
     core::protocol,
-    core::types::{parse_value, Primitive, Record, Symbol, SymbolMap},
+    core::types::{parse_primitive_in, Primitive, Record, Symbol, SymbolMap},
     utils::tracing::TracingContext,
 };
 
+use super::base::strip_bom;
+
 const BUFFER_SIZE: usize = 16 * 1024;
 
+// A quoted field still open past this many bytes of its record is taken as an unclosed quote
+const MAX_QUOTED_RECORD_SIZE: usize = 1024 * 1024;
+
 pub struct CSVProtocolParser<R> {
     reader: R,
-    config: CSVProtocolConfig,
 
     has_header: bool,
     header_skipped: bool,
 
+    line_parser: CSVLineParser,
+    scanner: RecordScanner,
+    // 上一条记录以缓冲区末尾的 \r 结束, 其后的 \n 属于同一个结束符
+    skip_lf: bool,
+
+    input_buf: BytesMut,
+    bom_checked: bool,
+}
+
+/// 单行 CSV 的解析, 与数据来源无关, 由流式与批量 (bulk) 两种读取方式共用
+#[derive(Clone)]
+pub(super) struct CSVLineParser {
+    config: CSVProtocolConfig,
+
     fields: HashMap<usize, (Symbol, Primitive, bool)>,
 
     num_required_fields: usize,
     num_optional_fields: usize,
     num_fields: usize,
-
-    input_buf: BytesMut,
 }
 
-impl<R> CSVProtocolParser<R>
-where
-    R: tokio::io::AsyncRead + Unpin + Send + 'static,
-{
-    pub fn try_create_from(reader: R, cfg: CSVProtocolConfig) -> protocol::Result<Self> {
+impl CSVLineParser {
+    pub(super) fn new(cfg: CSVProtocolConfig) -> Self {
         let fields = cfg
             .fields
             .iter()
@@ -59,108 +73,95 @@ where
             .filter(|(_, (_, _, optional))| *optional)
             .count();
 
-        Ok(Self {
-            reader,
-            config: cfg.clone(),
-            has_header: cfg.has_header,
-            header_skipped: !cfg.has_header,
+        Self {
+            config: cfg,
             num_required_fields,
             num_optional_fields,
             num_fields: num_optional_fields + num_required_fields,
             fields,
-            input_buf: BytesMut::with_capacity(BUFFER_SIZE),
-        })
+        }
     }
 
-    async fn skip_header(&mut self) -> protocol::Result<()> {
-        if self.has_header && !self.header_skipped {
-            loop {
-                match self.reader.read_u8().await {
-                    Ok(0) => {
-                        // EOF reached
-                        return Err(protocol::Error::EOF);
-                    }
-                    Ok(c) => {
-                        if c == b'\n' {
-                            self.header_skipped = true;
-                            break;
-                        }
-                    }
-                    Err(e) => match e.kind() {
-                        std::io::ErrorKind::UnexpectedEof => {
-                            // EOF reached
-                            return Err(protocol::Error::EOF);
-                        }
-                        std::io::ErrorKind::WouldBlock => {
-                            // Would block, continue reading
-                            continue;
-                        }
-                        _ => {
-                            // Other IO error
-                            return Err(protocol::Error::Io(e));
-                        }
-                    },
-                }
-            }
-        }
-        Ok(())
+    pub(super) fn has_header(&self) -> bool {
+        self.config.has_header
     }
 
-    async fn read_line(&mut self) -> protocol::Result<Option<String>> {
-        let mut line_buf = Vec::new();
+    /// 用于查找记录边界, 每条记录从新的 scanner 开始
+    pub(super) fn scanner(&self) -> RecordScanner {
+        RecordScanner::new(self.config.delimiter)
+    }
 
-        loop {
-            // 如果缓冲区不为空，尝试在现有数据中查找行结束符
-            if !self.input_buf.is_empty() {
-                if let Some(pos) = self.find_line_end() {
-                    let line_end_len = if pos < self.input_buf.len() - 1
-                        && self.input_buf[pos] == b'\r'
-                        && self.input_buf[pos + 1] == b'\n'
-                    {
-                        2
-                    } else {
-                        1
-                    };
+    /// 处理表头行
+    pub(super) fn parse_header(&mut self, line: &str) -> protocol::Result<()> {
+        if self.config.match_by == MatchBy::Header {
+            // 表头与数据行使用相同的解析器, 以支持带引号的列名
+            let (_, headers) = parse_csv_line(
+                line,
+                self.config.delimiter,
+                self.config.strict_quotes,
+            )
+            .map_err(|e| {
+                protocol::Error::MismatchedFormat(format!("Failed to parse CSV header: {:?}", e))
+            })?;
+            self.match_header(&headers)?;
+        }
 
-                    // 添加当前行到line_buf
-                    line_buf.extend_from_slice(&self.input_buf[..pos]);
-                    self.input_buf.advance(pos + line_end_len);
+        Ok(())
+    }
 
-                    return Ok(Some(String::from_utf8_lossy(&line_buf).into_owned()));
-                } else {
-                    // 没有找到结束符，将所有数据添加到line_buf
-                    line_buf.extend_from_slice(&self.input_buf);
-                    self.input_buf.clear();
-                }
+    /// 根据表头重新确定各字段所在的列
+    fn match_header(&mut self, headers: &[String]) -> protocol::Result<()> {
+        let case_insensitive = self.config.header_case_insensitive;
+        let normalize = |s: &str| {
+            let s = s.trim();
+            if case_insensitive {
+                s.to_lowercase()
+            } else {
+                s.to_string()
             }
-
-            // 尝试读取更多数据
-            match self.reader.read_buf(&mut self.input_buf).await {
-                Ok(0) => {
-                    // EOF reached
-                    if line_buf.is_empty() {
-                        return Ok(None);
-                    } else {
-                        // 返回剩余数据作为最后一行
-                        return Ok(Some(String::from_utf8_lossy(&line_buf).into_owned()));
-                    }
+        };
+        let headers = headers.iter().map(|h| normalize(h)).collect::<Vec<_>>();
+
+        let mut fields = HashMap::new();
+        for field in &self.config.fields {
+            let name = normalize(field.name.as_str());
+            match headers.iter().position(|h| *h == name) {
+                Some(index) => {
+                    fields.insert(
+                        index,
+                        (field.name.clone(), field.r#type.clone(), field.optional),
+                    );
                 }
-                Ok(_) => {
-                    // 成功读取更多数据，继续循环处理
+                None if field.optional => {}
+                None => {
+                    return Err(protocol::Error::MismatchedFormat(format!(
+                        "Field {} not found in CSV header",
+                        field.name
+                    )));
                 }
-                Err(e) => return Err(protocol::Error::Io(e)),
             }
         }
+
+        self.num_optional_fields = fields.values().filter(|(_, _, o)| *o).count();
+        self.num_fields = self.num_required_fields + self.num_optional_fields;
+        self.fields = fields;
+
+        Ok(())
     }
 
-    fn find_line_end(&self) -> Option<usize> {
-        for i in 0..self.input_buf.len() {
-            // 检测换行符 \n 或 \r
-            if self.input_buf[i] == b'\n' || self.input_buf[i] == b'\r' {
-                return Some(i);
-            }
+    /// 解析一行数据, 空行视为数据结束
+    pub(super) fn parse_line(&self, line: &str) -> protocol::Result<Record> {
+        if line.trim().is_empty() {
+            return Err(protocol::Error::Eof);
+        }
+
+        match parse_csv_line(line, self.config.delimiter, self.config.strict_quotes) {
+            Ok((_, record)) => self.parse_record(record),
+            Err(e) => Err(protocol::Error::MismatchedFormat(format!(
+                "Failed to parse CSV line: {:?}",
+                e
+            ))),
         }
-        None
     }
 
     fn parse_record(&self, record: Vec<String>) -> protocol::Result<Record> {
@@ -182,9 +183,9 @@ where
             )));
         }
 
-        // 检查是否有过多的字段
+        // 检查是否有过多的字段, 按表头匹配时忽略多余的列
         let max_defined_index = self.fields.keys().max().copied().unwrap_or(0);
-        if record.len() > max_defined_index + 1 {
+        if self.config.match_by == MatchBy::Index && record.len() > max_defined_index + 1 {
             return Err(protocol::Error::MismatchedFormat(format!(
                 "Too many fields in CSV record. Expected at most {} fields, got {}",
                 max_defined_index + 1,
@@ -224,7 +225,8 @@ where
                     }
                 }
 
-                let parsed_value = parse_value(field_str, data_type.into()).map_err(|_| {
+                let parsed_value = parse_primitive_in(field_str, data_type, self.config.timezone)
+                    .map_err(|_| {
                     protocol::Error::MismatchedFormat(format!(
                         "Failed to parse field {}: {}, expected {}",
                         name, field_str, data_type
@@ -240,19 +242,242 @@ where
     }
 }
 
-fn parse_csv_line(input: &str, delimiter: char) -> IResult<&str, Vec<String>> {
-    // 定义字段解析器
-    let field_content = |c| c != delimiter && c != '\n' && c != '\r';
-    let quoted_field = map(
-        delimited(char('"'), take_while(|c| c != '"'), char('"')),
-        |s: &str| s.to_string(),
-    );
-    let unquoted_field = map(take_while(field_content), |s: &str| s.trim().to_string());
+impl<R> CSVProtocolParser<R>
+where
+    R: tokio::io::AsyncRead + Unpin + Send + 'static,
+{
+    pub fn try_create_from(reader: R, cfg: CSVProtocolConfig) -> protocol::Result<Self> {
+        Ok(Self {
+            reader,
+            has_header: cfg.has_header,
+            header_skipped: !cfg.has_header,
+            scanner: RecordScanner::new(cfg.delimiter),
+            skip_lf: false,
+            line_parser: CSVLineParser::new(cfg),
+            input_buf: BytesMut::with_capacity(BUFFER_SIZE),
+            bom_checked: false,
+        })
+    }
+
+    async fn skip_header(&mut self) -> protocol::Result<()> {
+        if !self.has_header || self.header_skipped {
+            return Ok(());
+        }
 
-    let field = alt((quoted_field, unquoted_field));
+        let line = self.read_line().await?.ok_or(protocol::Error::Eof)?;
+        self.header_skipped = true;
 
+        self.line_parser.parse_header(&line)
+    }
+
+    /// 读取一条记录, 引号内的换行不结束记录, 因此一条记录可能跨越多行. 记录在找到结束符前
+    /// 一直留在缓冲区中, scanner 只扫描新读取的数据
+    async fn read_line(&mut self) -> protocol::Result<Option<String>> {
+        loop {
+            // 如果缓冲区不为空，尝试在现有数据中查找行结束符
+            if !self.input_buf.is_empty() {
+                if std::mem::take(&mut self.skip_lf) && self.input_buf[0] == b'\n' {
+                    self.input_buf.advance(1);
+                    continue;
+                }
+
+                if let Some(pos) = self.scanner.find_end(&self.input_buf) {
+                    return Ok(Some(self.take_line(pos)));
+                }
+            }
+
+            // 尝试读取更多数据
+            match self.reader.read_buf(&mut self.input_buf).await {
+                Ok(0) => {
+                    // EOF reached
+                    if self.input_buf.is_empty() {
+                        return Ok(None);
+                    }
+                    // 未闭合的引号在其后的第一个换行处结束记录, 否则剩余数据作为最后一行
+                    let line = match self.scanner.end_of_data() {
+                        Some(pos) => self.take_line(pos),
+                        None => {
+                            let line = self.input_buf.split();
+                            self.finish_line(&line)
+                        }
+                    };
+                    return Ok(Some(line));
+                }
+                Ok(_) => {
+                    // 成功读取更多数据，继续循环处理
+                }
+                Err(e) => return Err(protocol::Error::Io(e)),
+            }
+        }
+    }
+
+    fn finish_line(&mut self, line: &[u8]) -> String {
+        let mut line = String::from_utf8_lossy(line).into_owned();
+        if !self.bom_checked {
+            self.bom_checked = true;
+            strip_bom(&mut line);
+        }
+        line
+    }
+
+    /// 取出以 `pos` 处的换行结束的记录, 连同其结束符
+    fn take_line(&mut self, pos: usize) -> String {
+        let line_end_len = if pos < self.input_buf.len() - 1
+            && self.input_buf[pos] == b'\r'
+            && self.input_buf[pos + 1] == b'\n'
+        {
+            2
+        } else {
+            1
+        };
+        self.skip_lf = pos == self.input_buf.len() - 1 && self.input_buf[pos] == b'\r';
+
+        let line = self.input_buf.split_to(pos);
+        self.input_buf.advance(line_end_len);
+        self.finish_line(&line)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum QuoteState {
+    FieldStart,
+    Unquoted,
+    Quoted,
+    /// A quote inside a quoted field, either closing it or the first of a doubled quote
+    QuoteInQuoted,
+}
+
+/// Finds where a record ends: at `\n` or `\r` outside of quotes. As in the parser, a quote
+/// opens a quoted field only at the start of the field. The state is kept between calls, so
+/// the data of a record can be fed in pieces.
+///
+/// A quote which never closes is an ordinary character, as in the lenient parser: the record
+/// then ends at the first line break after it. This is known at the end of the data, or once
+/// the quoted field holds more than `MAX_QUOTED_RECORD_SIZE` bytes.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct RecordScanner {
+    // 非 ASCII 的分隔符按其 UTF-8 编码的最后一个字节匹配
+    delimiter: u8,
+    state: QuoteState,
+    // Bytes of the record scanned by the previous calls
+    scanned: usize,
+    // The first line break inside quotes, where the record ends if the quote never closes
+    quoted_break: Option<usize>,
+}
+
+impl RecordScanner {
+    pub(super) fn new(delimiter: char) -> Self {
+        let mut buf = [0; 4];
+        let delimiter = delimiter.encode_utf8(&mut buf).as_bytes();
+
+        Self {
+            delimiter: delimiter[delimiter.len() - 1],
+            state: QuoteState::FieldStart,
+            scanned: 0,
+            quoted_break: None,
+        }
+    }
+
+    /// The position of the line break ending the record starting at `record[0]`. Each call is
+    /// given the whole record received so far, only the bytes added since the previous call
+    /// are scanned. The scanner is ready for the next record once the end is found.
+    pub(super) fn find_end(&mut self, record: &[u8]) -> Option<usize> {
+        for (i, &b) in record.iter().enumerate().skip(self.scanned) {
+            self.state = match (self.state, b) {
+                (QuoteState::Quoted, b'"') => QuoteState::QuoteInQuoted,
+                (QuoteState::Quoted, b'\n' | b'\r') => {
+                    self.quoted_break.get_or_insert(i);
+                    QuoteState::Quoted
+                }
+                (QuoteState::Quoted, _) => QuoteState::Quoted,
+                (QuoteState::FieldStart | QuoteState::QuoteInQuoted, b'"') => QuoteState::Quoted,
+                (_, b'\n' | b'\r') => return Some(self.finish(i)),
+                (_, b) if b == self.delimiter => QuoteState::FieldStart,
+                _ => QuoteState::Unquoted,
+            };
+
+            if self.state == QuoteState::Quoted && i >= MAX_QUOTED_RECORD_SIZE {
+                if let Some(end) = self.quoted_break {
+                    return Some(self.finish(end));
+                }
+            }
+        }
+
+        self.scanned = record.len();
+        None
+    }
+
+    /// Where the record ends when no more data follows: at the first line break after a quote
+    /// which never closed, if any. The scanner is ready for the next record.
+    pub(super) fn end_of_data(&mut self) -> Option<usize> {
+        let end = self.quoted_break;
+        self.finish(0);
+        end
+    }
+
+    fn finish(&mut self, end: usize) -> usize {
+        self.state = QuoteState::FieldStart;
+        self.scanned = 0;
+        self.quoted_break = None;
+        end
+    }
+}
+
+/// 带引号的字段, 字段内的引号写作两个引号 (`""`)
+fn quoted_field(input: &str) -> IResult<&str, String> {
+    let (mut input, _) = char('"')(input)?;
+    let mut field = String::new();
+
+    loop {
+        let (rest, part) = take_while(|c| c != '"')(input)?;
+        field.push_str(part);
+
+        let (rest, _) = char('"')(rest)?;
+        match rest.strip_prefix('"') {
+            Some(rest) => {
+                field.push('"');
+                input = rest;
+            }
+            None => return Ok((rest, field)),
+        }
+    }
+}
+
+/// 一个字段, 引号只在字段开头有效. 宽松模式下, 结束引号后的内容追加到字段中, 未闭合的引号
+/// 与未加引号字段中的引号按普通字符处理; 严格模式 (`strict_quotes`) 下这些情况都是错误
+fn field(input: &str, delimiter: char, strict: bool) -> IResult<&str, String> {
+    let content = |c| c != delimiter && c != '\n' && c != '\r';
+
+    if input.starts_with('"') {
+        match quoted_field(input) {
+            Ok((rest, mut field)) => {
+                let (rest, trailing) = take_while(content)(rest)?;
+                if strict && !trailing.trim().is_empty() {
+                    return Err(malformed(rest, ErrorKind::Verify));
+                }
+                field.push_str(trailing.trim_end());
+                return Ok((rest, field));
+            }
+            Err(_) if strict => return Err(malformed(input, ErrorKind::Char)),
+            Err(_) => {}
+        }
+    }
+
+    let (rest, field) = take_while(content)(input)?;
+    if strict && field.contains('"') {
+        return Err(malformed(input, ErrorKind::Verify));
+    }
+    Ok((rest, field.trim().to_string()))
+}
+
+/// A failure rather than an error, so that the field list does not stop quietly before it
+fn malformed(input: &str, kind: ErrorKind) -> nom::Err<nom::error::Error<&str>> {
+    nom::Err::Failure(nom::error::Error::new(input, kind))
+}
+
+fn parse_csv_line(input: &str, delimiter: char, strict: bool) -> IResult<&str, Vec<String>> {
     // 处理字段列表
-    let fields = separated_list0(char(delimiter), field);
+    let fields = separated_list0(char(delimiter), |input| field(input, delimiter, strict));
 
     // 处理行尾
     let line_end = alt((tag("\r\n"), tag("\n"), eof));
@@ -271,30 +496,10 @@ where
     async fn read_next(&mut self) -> protocol::Result<Record> {
         self.skip_header().await?;
 
-        loop {
-            match self.read_line().await? {
-                Some(line) => {
-                    if line.trim().is_empty() {
-                        return Err(protocol::Error::EOF);
-                    }
-                    match parse_csv_line(&line, self.config.delimiter) {
-                        Ok((_, record)) => {
-                            let parsed_record = self.parse_record(record)?;
-                            return Ok(parsed_record);
-                        }
-                        Err(e) => {
-                            return Err(protocol::Error::MismatchedFormat(format!(
-                                "Failed to parse CSV line: {:?}",
-                                e
-                            )));
-                        }
-                    }
-                }
-                None => {
-                    // EOF reached
-                    return Err(protocol::Error::EOF);
-                }
-            }
+        match self.read_line().await? {
+            Some(line) => self.line_parser.parse_line(&line),
+            // EOF reached
+            None => Err(protocol::Error::Eof),
         }
     }
 }
@@ -303,7 +508,7 @@ where
 mod tests {
     use std::io::Cursor;
 
-    use crate::config::protocol::csv::{CSVField, CSVProtocolConfig};
+    use crate::config::protocol::csv::{CSVField, CSVProtocolConfig, MatchBy};
     use crate::config::Verify;
     use crate::core::protocol::{Error, ProtocolParser};
     use crate::core::tag::{TagId, PROTOCOL_TAG_SCOPE};
@@ -318,6 +523,10 @@ mod tests {
             delimiter: ',',
             has_header: true,
             num_fields: 3,
+            match_by: MatchBy::Index,
+            header_case_insensitive: false,
+            strict_quotes: false,
+            timezone: chrono_tz::Tz::UTC,
             fields: vec![
                 CSVField {
                     index: 0,
@@ -367,15 +576,12 @@ mod tests {
                 .value(),
             30
         );
-        assert_eq!(
-            record
-                .get(&Symbol::new("active"))
-                .unwrap()
-                .bool()
-                .unwrap()
-                .value(),
-            true
-        );
+        assert!(record
+            .get(&Symbol::new("active"))
+            .unwrap()
+            .bool()
+            .unwrap()
+            .value());
 
         // Second record
         let record = parser.read_next().await.unwrap();
@@ -393,20 +599,17 @@ mod tests {
                 .value(),
             25
         );
-        assert_eq!(
-            record
-                .get(&Symbol::new("active"))
-                .unwrap()
-                .bool()
-                .unwrap()
-                .value(),
-            false
-        );
+        assert!(!record
+            .get(&Symbol::new("active"))
+            .unwrap()
+            .bool()
+            .unwrap()
+            .value());
 
         // No more records
         let result = parser.read_next().await;
         assert!(result.is_err());
-        assert!(matches!(result.unwrap_err(), Error::EOF));
+        assert!(matches!(result.unwrap_err(), Error::Eof));
     }
 
     #[tokio::test]
@@ -454,7 +657,7 @@ mod tests {
         // No more records
         let result = parser.read_next().await;
         assert!(result.is_err());
-        assert!(matches!(result.unwrap_err(), Error::EOF));
+        assert!(matches!(result.unwrap_err(), Error::Eof));
     }
 
     #[tokio::test]
@@ -541,26 +744,27 @@ mod tests {
             record.get(&Symbol::new("name")).unwrap(),
             &Value::String(intern("Alice"))
         );
-        assert_eq!(
-            record
-                .get(&Symbol::new("active"))
-                .unwrap()
-                .bool()
-                .unwrap()
-                .value(),
-            true
-        );
+        assert!(record
+            .get(&Symbol::new("active"))
+            .unwrap()
+            .bool()
+            .unwrap()
+            .value());
     }
 
     #[tokio::test]
     async fn test_different_data_types() {
-        let data = "string,int,float,bool,date\ntext,42,3.14,true,2023-01-01\n";
+        let data = "string,int,float,bool,date\ntext,42,2.75,true,2023-01-01\n";
 
         let mut cfg = CSVProtocolConfig {
             tag: TagId::new(PROTOCOL_TAG_SCOPE, "csv").into(),
             delimiter: ',',
             has_header: true,
             num_fields: 5,
+            match_by: MatchBy::Index,
+            header_case_insensitive: false,
+            strict_quotes: false,
+            timezone: chrono_tz::Tz::UTC,
             fields: vec![
                 CSVField {
                     index: 0,
@@ -622,19 +826,16 @@ mod tests {
                 .float()
                 .unwrap()
                 .value()
-                - 3.14)
+                - 2.75)
                 .abs()
                 < 0.001
         );
-        assert_eq!(
-            record
-                .get(&Symbol::new("bool"))
-                .unwrap()
-                .bool()
-                .unwrap()
-                .value(),
-            true
-        );
+        assert!(record
+            .get(&Symbol::new("bool"))
+            .unwrap()
+            .bool()
+            .unwrap()
+            .value());
     }
 
     #[tokio::test]
@@ -664,7 +865,7 @@ mod tests {
 
         let result = parser.read_next().await;
         assert!(result.is_err());
-        assert!(matches!(result.unwrap_err(), Error::EOF));
+        assert!(matches!(result.unwrap_err(), Error::Eof));
     }
 
     #[tokio::test]
@@ -675,7 +876,7 @@ mod tests {
 
         let result = parser.read_next().await;
         assert!(result.is_err());
-        assert!(matches!(result.unwrap_err(), Error::EOF));
+        assert!(matches!(result.unwrap_err(), Error::Eof));
     }
 
     #[tokio::test]
@@ -688,6 +889,10 @@ mod tests {
             delimiter: ',',
             has_header: true,
             num_fields: 3,
+            match_by: MatchBy::Index,
+            header_case_insensitive: false,
+            strict_quotes: false,
+            timezone: chrono_tz::Tz::UTC,
             fields: vec![
                 CSVField {
                     index: 0,
@@ -740,4 +945,372 @@ mod tests {
             &Value::String(intern("ninety"))
         );
     }
+
+    #[tokio::test]
+    async fn test_bytes_fields() {
+        let data = "3q2+7w==,DEADBEEF\n,0x00ff\nnot base64,00\n";
+
+        let field = |index, name: &str, r#type| CSVField {
+            index,
+            name: Symbol::new(name),
+            r#type,
+            optional: true,
+        };
+        let mut cfg = CSVProtocolConfig {
+            tag: TagId::new(PROTOCOL_TAG_SCOPE, "csv").into(),
+            delimiter: ',',
+            has_header: false,
+            num_fields: 2,
+            match_by: MatchBy::Index,
+            header_case_insensitive: false,
+            strict_quotes: false,
+            timezone: chrono_tz::Tz::UTC,
+            fields: vec![
+                field(0, "base64", Primitive::Bytes),
+                field(1, "hex", Primitive::HexBytes),
+            ],
+        };
+        cfg.verify().expect("Invalid config");
+        let mut parser = CSVProtocolParser::try_create_from(Cursor::new(data), cfg).unwrap();
+
+        let record = parser.read_next().await.unwrap();
+        let payload = Value::Bytes(vec![0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(record.get(&Symbol::new("base64")), Some(&payload));
+        assert_eq!(record.get(&Symbol::new("hex")), Some(&payload));
+
+        let record = parser.read_next().await.unwrap();
+        assert_eq!(record.get(&Symbol::new("base64")), None);
+        assert_eq!(
+            record.get(&Symbol::new("hex")),
+            Some(&Value::Bytes(vec![0x00, 0xff]))
+        );
+
+        assert!(parser.read_next().await.is_err());
+    }
+
+    fn header_config(match_by: MatchBy, case_insensitive: bool) -> CSVProtocolConfig {
+        let field = |name: &str, r#type| CSVField {
+            index: 0,
+            name: Symbol::new(name),
+            r#type,
+            optional: false,
+        };
+        let mut cfg = CSVProtocolConfig {
+            tag: TagId::new(PROTOCOL_TAG_SCOPE, "csv").into(),
+            delimiter: ',',
+            has_header: true,
+            num_fields: 0,
+            match_by,
+            header_case_insensitive: case_insensitive,
+            strict_quotes: false,
+            timezone: chrono_tz::Tz::UTC,
+            fields: vec![
+                field("Host Name", Primitive::String),
+                field("CPU %, total", Primitive::Float),
+            ],
+        };
+        cfg.verify().expect("Invalid config");
+        cfg
+    }
+
+    #[tokio::test]
+    async fn test_bom_without_header() {
+        let data = "\u{feff}Alice,30,true\n";
+        let mut cfg = create_test_config();
+        cfg.has_header = false;
+        let mut parser = CSVProtocolParser::try_create_from(Cursor::new(data), cfg).unwrap();
+
+        let record = parser.read_next().await.unwrap();
+        assert_eq!(
+            record.get(&Symbol::new("name")).unwrap(),
+            &Value::String(intern("Alice"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bom_with_quoted_header() {
+        let data = "\u{feff}\"Host Name\",\"CPU %, total\"\r\nnode-a,12.5\r\n";
+        let cfg = header_config(MatchBy::Index, false);
+        let mut parser = CSVProtocolParser::try_create_from(Cursor::new(data), cfg).unwrap();
+
+        let record = parser.read_next().await.unwrap();
+        assert_eq!(
+            record.get(&Symbol::new("Host Name")).unwrap(),
+            &Value::String(intern("node-a"))
+        );
+        assert_eq!(
+            record
+                .get(&Symbol::new("CPU %, total"))
+                .unwrap()
+                .float()
+                .unwrap()
+                .value(),
+            12.5
+        );
+    }
+
+    #[tokio::test]
+    async fn test_match_by_header() {
+        // 列顺序与配置不同, 且带有 BOM 与未配置的列
+        let data = "\u{feff}\"CPU %, total\",\"Comment\",\" Host Name \"\n42,\"a, b\",node-b\n";
+        let cfg = header_config(MatchBy::Header, false);
+        let mut parser = CSVProtocolParser::try_create_from(Cursor::new(data), cfg).unwrap();
+
+        let record = parser.read_next().await.unwrap();
+        assert_eq!(record.len(), 2);
+        assert_eq!(
+            record.get(&Symbol::new("Host Name")).unwrap(),
+            &Value::String(intern("node-b"))
+        );
+        assert_eq!(
+            record
+                .get(&Symbol::new("CPU %, total"))
+                .unwrap()
+                .float()
+                .unwrap()
+                .value(),
+            42.0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_match_by_header_case_insensitive() {
+        let data = "\"host name\",\"cpu %, TOTAL\"\nnode-c,1.0\n";
+
+        let cfg = header_config(MatchBy::Header, false);
+        let mut parser = CSVProtocolParser::try_create_from(Cursor::new(data), cfg).unwrap();
+        assert!(matches!(
+            parser.read_next().await,
+            Err(Error::MismatchedFormat(_))
+        ));
+
+        let cfg = header_config(MatchBy::Header, true);
+        let mut parser = CSVProtocolParser::try_create_from(Cursor::new(data), cfg).unwrap();
+        let record = parser.read_next().await.unwrap();
+        assert_eq!(
+            record.get(&Symbol::new("Host Name")).unwrap(),
+            &Value::String(intern("node-c"))
+        );
+    }
+
+    #[test]
+    fn test_match_by_header_requires_header() {
+        let mut cfg = header_config(MatchBy::Header, false);
+        cfg.has_header = false;
+        assert!(cfg.verify().is_err());
+    }
+
+    #[test]
+    fn test_escaped_quotes() {
+        for strict in [false, true] {
+            let (_, fields) =
+                parse_csv_line("\"say \"\"hi\"\"\",\"\"\"\",plain", ',', strict).unwrap();
+            assert_eq!(fields, vec!["say \"hi\"", "\"", "plain"]);
+
+            let (_, fields) =
+                parse_csv_line("\"hello \"\"world\"\", ok\",\"\",x", ',', strict).unwrap();
+            assert_eq!(fields, vec!["hello \"world\", ok", "", "x"]);
+        }
+    }
+
+    #[test]
+    fn test_malformed_quotes() {
+        let cases = [
+            // 结束引号后的内容
+            ("\"a\"b,1", vec!["ab", "1"]),
+            ("\"a\" b ,1", vec!["a b", "1"]),
+            // 未闭合的引号
+            ("\"a,1", vec!["\"a", "1"]),
+            // 未加引号字段中的引号
+            ("a\"b\",1", vec!["a\"b\"", "1"]),
+            (" \"a\",1", vec!["\"a\"", "1"]),
+        ];
+
+        for (line, expected) in cases {
+            let (_, fields) = parse_csv_line(line, ',', false).unwrap();
+            assert_eq!(fields, expected, "{}", line);
+
+            assert!(
+                matches!(parse_csv_line(line, ',', true), Err(nom::Err::Failure(..))),
+                "{}",
+                line
+            );
+        }
+
+        // 结束引号后的空白不算错误
+        let (_, fields) = parse_csv_line("\"a\"  ,1", ',', true).unwrap();
+        assert_eq!(fields, vec!["a", "1"]);
+    }
+
+    #[test]
+    fn test_record_scanner() {
+        let data = b"a,\"x\ny\"\nb,c\"d\ne\r\n\"\"\"\n\"\n";
+        let mut scanner = RecordScanner::new(',');
+        assert_eq!(scanner.find_end(data), Some(7));
+        assert_eq!(scanner.find_end(&data[8..]), Some(5));
+        assert_eq!(scanner.find_end(&data[14..]), Some(1));
+        assert_eq!(scanner.find_end(&data[16..]), Some(0));
+        assert_eq!(scanner.find_end(&data[17..]), Some(5));
+
+        // 分多次输入, 每次给出目前收到的整条记录
+        let mut scanner = RecordScanner::new(',');
+        assert_eq!(scanner.find_end(b"a,\"x"), None);
+        assert_eq!(scanner.find_end(b"a,\"x\r\n\""), None);
+        assert_eq!(scanner.find_end(b"a,\"x\r\n\"\"y\"\r\n"), Some(10));
+
+        // 未闭合的引号在数据结束时按普通字符处理
+        let mut scanner = RecordScanner::new(',');
+        assert_eq!(scanner.find_end(b"\"a,1\nb,2\n"), None);
+        assert_eq!(scanner.end_of_data(), Some(4));
+        assert_eq!(scanner.find_end(b"b,2\n"), Some(3));
+        assert_eq!(scanner.end_of_data(), None);
+
+        // 或者在带引号的字段过长时
+        let mut record = b"\"a\n".to_vec();
+        record.resize(MAX_QUOTED_RECORD_SIZE + 1, b'x');
+        let mut scanner = RecordScanner::new(',');
+        assert_eq!(scanner.find_end(&record), Some(2));
+
+        let mut scanner = RecordScanner::new('；');
+        assert_eq!(scanner.find_end("a；\"\n\"\n".as_bytes()), Some(7));
+    }
+
+    #[tokio::test]
+    async fn test_unclosed_quote_ends_at_line_break() {
+        let field = |index, name: &str, r#type| CSVField {
+            index,
+            name: Symbol::new(name),
+            r#type,
+            optional: false,
+        };
+        let mut cfg = CSVProtocolConfig {
+            tag: TagId::new(PROTOCOL_TAG_SCOPE, "csv").into(),
+            delimiter: ',',
+            has_header: false,
+            num_fields: 4,
+            match_by: MatchBy::Index,
+            header_case_insensitive: false,
+            strict_quotes: false,
+            timezone: chrono_tz::Tz::UTC,
+            fields: vec![
+                field(0, "name", Primitive::String),
+                field(1, "value", Primitive::Float),
+                field(2, "count", Primitive::Int),
+                field(3, "host", Primitive::String),
+            ],
+        };
+        cfg.verify().expect("Invalid config");
+
+        let data = "\"a,1.0,1,x\nb,2.0,2,y\n";
+        let mut parser = CSVProtocolParser::try_create_from(Cursor::new(data), cfg).unwrap();
+
+        let first = parser.read_next().await.unwrap();
+        assert_eq!(first.get(&Symbol::new("name")), Some(&Value::from("\"a")));
+        assert_eq!(first.get(&Symbol::new("host")), Some(&Value::from("x")));
+        let second = parser.read_next().await.unwrap();
+        assert_eq!(second.get(&Symbol::new("name")), Some(&Value::from("b")));
+        assert_eq!(second.get(&Symbol::new("host")), Some(&Value::from("y")));
+        assert!(matches!(
+            parser.read_next().await,
+            Err(protocol::Error::Eof)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_quoted_delimiter() {
+        let data = "name,age,active\n\"Doe, John\",30,true\n\"say \"\"hi\"\", ok\",25,\"false\"\n";
+        let mut parser =
+            CSVProtocolParser::try_create_from(Cursor::new(data), create_test_config()).unwrap();
+
+        let record = parser.read_next().await.unwrap();
+        assert_eq!(
+            record.get(&Symbol::new("name")).unwrap(),
+            &Value::String(intern("Doe, John"))
+        );
+        assert_eq!(
+            record
+                .get(&Symbol::new("age"))
+                .unwrap()
+                .int()
+                .unwrap()
+                .value(),
+            30
+        );
+
+        let record = parser.read_next().await.unwrap();
+        assert_eq!(
+            record.get(&Symbol::new("name")).unwrap(),
+            &Value::String(intern("say \"hi\", ok"))
+        );
+        assert!(!record
+            .get(&Symbol::new("active"))
+            .unwrap()
+            .bool()
+            .unwrap()
+            .value());
+
+        assert!(matches!(parser.read_next().await, Err(Error::Eof)));
+    }
+
+    #[tokio::test]
+    async fn test_quoted_newlines() {
+        let data = "name,age,active\r\n\"multi\r\nline\",30,true\r\n\"a\nb\rc\",25,false\n";
+
+        // 在引号内的任意位置分开读取
+        for split in 0..data.len() {
+            let reader =
+                AsyncReadExt::chain(Cursor::new(&data[..split]), Cursor::new(&data[split..]));
+            let mut parser =
+                CSVProtocolParser::try_create_from(reader, create_test_config()).unwrap();
+
+            let record = parser.read_next().await.unwrap();
+            assert_eq!(
+                record.get(&Symbol::new("name")).unwrap(),
+                &Value::String(intern("multi\r\nline")),
+                "split at {}",
+                split
+            );
+            let record = parser.read_next().await.unwrap();
+            assert_eq!(
+                record.get(&Symbol::new("name")).unwrap(),
+                &Value::String(intern("a\nb\rc")),
+                "split at {}",
+                split
+            );
+            assert!(matches!(parser.read_next().await, Err(Error::Eof)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_strict_quotes() {
+        let data = "name,age,active\nAlice,30,true\n\"Bob\"by,25,false\n";
+
+        let mut parser =
+            CSVProtocolParser::try_create_from(Cursor::new(data), create_test_config()).unwrap();
+        parser.read_next().await.unwrap();
+        let record = parser.read_next().await.unwrap();
+        assert_eq!(
+            record.get(&Symbol::new("name")).unwrap(),
+            &Value::String(intern("Bobby"))
+        );
+
+        let mut cfg = create_test_config();
+        cfg.strict_quotes = true;
+        let mut parser = CSVProtocolParser::try_create_from(Cursor::new(data), cfg).unwrap();
+        parser.read_next().await.unwrap();
+        assert!(matches!(
+            parser.read_next().await,
+            Err(Error::MismatchedFormat(..))
+        ));
+
+        // 未闭合的引号持续到输入结束
+        let mut cfg = create_test_config();
+        cfg.strict_quotes = true;
+        let data = "name,age,active\n\"Alice,30,true\nBob,25,false\n";
+        let mut parser = CSVProtocolParser::try_create_from(Cursor::new(data), cfg).unwrap();
+        assert!(matches!(
+            parser.read_next().await,
+            Err(Error::MismatchedFormat(..))
+        ));
+    }
 }
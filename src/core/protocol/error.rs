@@ -6,7 +6,7 @@ pub enum Error {
     #[error("Io error: {0}")]
     Io(#[from] std::io::Error),
     #[error("EOF")]
-    EOF,
+    Eof,
     #[error("Mismatched format: {0}")]
     MismatchedFormat(String),
 }
@@ -15,6 +15,11 @@ pub type Result<T> = miette::Result<T, Error>;
 
 impl Error {
     pub fn is_eof(&self) -> bool {
-        matches!(self, Error::EOF)
+        matches!(self, Error::Eof)
+    }
+
+    /// Whether only the current record is affected, the stream can go on.
+    pub fn is_recoverable(&self) -> bool {
+        matches!(self, Error::MismatchedFormat(_))
     }
 }
@@ -1,29 +1,40 @@
 use async_trait::async_trait;
 use bytes::{Buf, BytesMut};
 use chrono::TimeZone;
+use log::debug;
 use nom::{
-    bytes::complete::take_while1,
+    bytes::complete::{take_while, take_while1},
     character::complete::{char, digit1, space1},
     multi::many0,
     sequence::{preceded, separated_pair},
     IResult, Parser,
 };
-use std::collections::HashMap;
+use std::collections::{hash_map::Entry, HashMap};
 use tokio::io::AsyncReadExt;
 
 use crate::{
     config::protocol::graphite::GraphiteProtocolConfig,
     core::{
-        pipe::{NAME_FIELD, TIMESTAMP_FIELD, VALUE_FIELD},
+        pipe::TIMESTAMP_FIELD,
         protocol,
-        types::{parse_value, Record, Symbol, SymbolMap, Value, ValueType},
+        types::{parse_primitive_in, Primitive, Record, Symbol, SymbolMap, Value},
     },
     utils::tracing::TracingContext,
 };
 
-/// 解析指标名称 (任何非空格字符)
-fn parse_metric_name(input: &str) -> IResult<&str, &str> {
-    take_while1(|c: char| !c.is_whitespace())(input)
+use super::base::strip_bom;
+
+type Tags = Vec<(String, String)>;
+
+/// 解析指标名称 (任何非空格字符), 以及 Graphite 1.1 的标签 `name;k1=v1;k2=v2`
+fn parse_metric_name(input: &str) -> IResult<&str, (&str, Tags)> {
+    let name = take_while1(|c: char| !c.is_whitespace() && c != ';');
+    let tag_key = take_while1(|c: char| !c.is_whitespace() && c != ';' && c != '=');
+    let tag_value = take_while(|c: char| !c.is_whitespace() && c != ';');
+    let tag = separated_pair(tag_key, char('='), tag_value)
+        .map(|(k, v): (&str, &str)| (k.to_string(), v.to_string()));
+
+    (name, many0(preceded(char(';'), tag))).parse(input)
 }
 
 use nom::number::complete::double;
@@ -90,12 +101,36 @@ pub fn parse_graphite_to_record<'a>(
     input: &'a str,
     config: &GraphiteProtocolConfig,
 ) -> IResult<&'a str, Record> {
-    let (input, metric_name) = parse_metric_name(input)?;
+    let (input, (metric_name, tags)) = parse_metric_name(input)?;
     let (input, _) = space1(input)?;
     let (input, value) = parse_metric_value(input)?;
     let (input, _) = space1(input)?;
     let (input, timestamp) = parse_timestamp(input)?;
-    let (input, raw_attributes) = parse_attributes(input)?;
+    let (input, attributes) = parse_attributes(input)?;
+
+    // 空格分隔的属性优先于名称中的标签; Graphite 不允许空的标签值, 这里直接忽略
+    let mut raw_attributes = tags
+        .into_iter()
+        .filter(|(_, v)| !v.is_empty())
+        .collect::<HashMap<_, _>>();
+    for (key, value) in attributes {
+        match raw_attributes.entry(key) {
+            Entry::Occupied(mut tag) => {
+                debug!(
+                    "Graphite tag {}={} of {} overridden by the attribute {}={}",
+                    tag.key(),
+                    tag.get(),
+                    metric_name,
+                    tag.key(),
+                    value
+                );
+                tag.insert(value);
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(value);
+            }
+        }
+    }
 
     // 创建一个新的 Record
     let mut record = Record::new_with_values(SymbolMap::new(), TracingContext::new_root());
@@ -111,12 +146,13 @@ pub fn parse_graphite_to_record<'a>(
         let key_symbol = Symbol::new(&key);
 
         // 获取配置中指定的属性类型，如果没有则默认为字符串
-        let attribute_type = get_attribute_type(config, &key).unwrap_or(ValueType::String);
+        let attribute_type = get_attribute_type(config, &key).unwrap_or(&Primitive::String);
 
         // 解析值为指定类型
-        let parsed_value = parse_value(&value_str, attribute_type).map_err(|_| {
-            nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::MapRes))
-        })?;
+        let parsed_value = parse_primitive_in(&value_str, attribute_type, config.timezone)
+            .map_err(|_| {
+                nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::MapRes))
+            })?;
 
         record.set(key_symbol, parsed_value);
     }
@@ -125,13 +161,8 @@ pub fn parse_graphite_to_record<'a>(
 }
 
 /// 从配置中获取属性类型
-fn get_attribute_type(config: &GraphiteProtocolConfig, key: &str) -> Option<ValueType> {
-    if let Some(attributes) = &config.attributes {
-        if let Some(primitive) = attributes.get(key) {
-            return Some(ValueType::from(primitive));
-        }
-    }
-    None
+fn get_attribute_type<'a>(config: &'a GraphiteProtocolConfig, key: &str) -> Option<&'a Primitive> {
+    config.attributes.as_ref()?.get(key)
 }
 
 const BUFFER_SIZE: usize = 16 * 1024;
@@ -140,6 +171,7 @@ pub struct GraphiteProtocolParser<R> {
     reader: R,
     config: GraphiteProtocolConfig,
     input_buf: BytesMut,
+    bom_checked: bool,
 }
 
 impl<R> GraphiteProtocolParser<R>
@@ -151,6 +183,7 @@ where
             reader,
             config: cfg,
             input_buf: BytesMut::with_capacity(BUFFER_SIZE),
+            bom_checked: false,
         })
     }
 
@@ -174,7 +207,7 @@ where
                     line_buf.extend_from_slice(&self.input_buf[..pos]);
                     self.input_buf.advance(pos + line_end_len);
 
-                    return Ok(Some(String::from_utf8_lossy(&line_buf).into_owned()));
+                    return Ok(Some(self.finish_line(&line_buf)));
                 } else {
                     // 没有找到结束符，将所有数据添加到line_buf
                     line_buf.extend_from_slice(&self.input_buf);
@@ -190,7 +223,7 @@ where
                         return Ok(None);
                     } else {
                         // 返回剩余数据作为最后一行
-                        return Ok(Some(String::from_utf8_lossy(&line_buf).into_owned()));
+                        return Ok(Some(self.finish_line(&line_buf)));
                     }
                 }
                 Ok(_) => {
@@ -201,14 +234,20 @@ where
         }
     }
 
-    fn find_line_end(&self) -> Option<usize> {
-        for i in 0..self.input_buf.len() {
-            // 检测换行符 \n 或 \r
-            if self.input_buf[i] == b'\n' || self.input_buf[i] == b'\r' {
-                return Some(i);
-            }
+    fn finish_line(&mut self, line: &[u8]) -> String {
+        let mut line = String::from_utf8_lossy(line).into_owned();
+        if !self.bom_checked {
+            self.bom_checked = true;
+            strip_bom(&mut line);
         }
-        None
+        line
+    }
+
+    fn find_line_end(&self) -> Option<usize> {
+        // 检测换行符 \n 或 \r
+        self.input_buf
+            .iter()
+            .position(|&b| b == b'\n' || b == b'\r')
     }
 }
 
@@ -239,7 +278,7 @@ where
                 }
                 None => {
                     // EOF reached
-                    return Err(protocol::Error::EOF);
+                    return Err(protocol::Error::Eof);
                 }
             }
         }
@@ -256,6 +295,7 @@ mod tests {
         GraphiteProtocolConfig {
             tag: ProtocolTagId::new("test"),
             attributes: None,
+            timezone: chrono_tz::Tz::UTC,
         }
     }
 
@@ -270,6 +310,7 @@ mod tests {
         GraphiteProtocolConfig {
             tag: ProtocolTagId::new("test"),
             attributes: Some(attributes),
+            timezone: chrono_tz::Tz::UTC,
         }
     }
 
@@ -284,15 +325,8 @@ mod tests {
         let (remaining, record) = result.unwrap();
         assert_eq!(remaining, "");
 
-        // 验证记录中的字段
-        let metric_name = record.get(&NAME_FIELD).unwrap();
-        if let Value::String(name) = metric_name {
-            assert_eq!(name.as_str(), "system.cpu.usage");
-        } else {
-            panic!("Metric name is not a string");
-        }
-
-        let value = record.get(&VALUE_FIELD).unwrap();
+        // 指标名称作为字段名, 指标值作为字段值
+        let value = record.get(&Symbol::new("system.cpu.usage")).unwrap();
         if let Value::Float(num) = value {
             assert_eq!(num.value, 42.5);
         } else {
@@ -360,7 +394,7 @@ mod tests {
         // 验证布尔型属性
         let bool_val = record.get(&Symbol::new("bool_val")).unwrap();
         if let Value::Bool(val) = bool_val {
-            assert_eq!(*val, true);
+            assert!(*val);
         } else {
             panic!("bool_val is not a boolean");
         }
@@ -434,7 +468,7 @@ mod tests {
         assert!(result.is_ok());
         let (_, record) = result.unwrap();
 
-        let value = record.get(&VALUE_FIELD).unwrap();
+        let value = record.get(&Symbol::new("system.temp")).unwrap();
         if let Value::Float(num) = value {
             assert_eq!(num.value, -10.5);
         } else {
@@ -450,11 +484,13 @@ mod tests {
         assert!(result.is_ok());
         let (_, record) = result.unwrap();
 
-        let metric_name = record.get(&NAME_FIELD).unwrap();
-        if let Value::String(name) = metric_name {
-            assert_eq!(name.as_str(), "system.cpu-usage.percentage");
+        let value = record
+            .get(&Symbol::new("system.cpu-usage.percentage"))
+            .unwrap();
+        if let Value::Float(num) = value {
+            assert_eq!(num.value, 99.9);
         } else {
-            panic!("Metric name is not a string");
+            panic!("Value is not a float");
         }
     }
 
@@ -488,6 +524,88 @@ mod tests {
         assert!(matches!(rack, Value::String(_)));
     }
 
+    #[test]
+    fn test_parse_tagged_metric() {
+        let input = "cpu.usage;host=web01;dc=eu 0.42 1620000000";
+        let config = create_config_with_attributes();
+        let (remaining, record) = parse_graphite_to_record(input, &config).unwrap();
+        assert_eq!(remaining, "");
+
+        assert_eq!(
+            record.get(&Symbol::new("cpu.usage")),
+            Some(&Value::from(0.42))
+        );
+        assert_eq!(
+            record.get(&Symbol::new("host")),
+            Some(&Value::from("web01"))
+        );
+        assert_eq!(record.get(&Symbol::new("dc")), Some(&Value::from("eu")));
+        // 标签不会留在名称中
+        assert!(record.keys().all(|k| !k.as_str().contains(';')));
+    }
+
+    #[test]
+    fn test_parse_tagged_metric_with_attributes() {
+        let input = "cpu.usage;host=web01;int_val=3 0.42 1620000000 host=web02 region=eu";
+        let config = create_config_with_attributes();
+        let (_, record) = parse_graphite_to_record(input, &config).unwrap();
+
+        // 空格分隔的属性优先
+        assert_eq!(
+            record.get(&Symbol::new("host")),
+            Some(&Value::from("web02"))
+        );
+        assert_eq!(record.get(&Symbol::new("region")), Some(&Value::from("eu")));
+        // 标签同样按配置的类型解析
+        assert_eq!(
+            record.get(&Symbol::new("int_val")),
+            Some(&Value::from(3i64))
+        );
+
+        let input = "cpu.usage;int_val=abc 0.42 1620000000";
+        assert!(parse_graphite_to_record(input, &config).is_err());
+    }
+
+    #[test]
+    fn test_parse_tagged_metric_edge_cases() {
+        let config = create_test_config();
+
+        // 空的标签值被忽略
+        let (_, record) =
+            parse_graphite_to_record("cpu.usage;host=;dc=eu 1 1620000000", &config).unwrap();
+        assert_eq!(record.get(&Symbol::new("host")), None);
+        assert_eq!(record.get(&Symbol::new("dc")), Some(&Value::from("eu")));
+
+        // 标签的键和值都可以包含 `.`
+        let (_, record) = parse_graphite_to_record(
+            "cpu.usage;k8s.pod=api.v1.x;dc=eu.west 1 1620000000",
+            &config,
+        )
+        .unwrap();
+        assert_eq!(
+            record.get(&Symbol::new("k8s.pod")),
+            Some(&Value::from("api.v1.x"))
+        );
+        assert_eq!(
+            record.get(&Symbol::new("dc")),
+            Some(&Value::from("eu.west"))
+        );
+
+        // 第一个 `;` 之前的 `=` 属于名称
+        let (_, record) =
+            parse_graphite_to_record("cpu.mode=idle;host=a 1 1620000000", &config).unwrap();
+        assert_eq!(
+            record.get(&Symbol::new("cpu.mode=idle")),
+            Some(&Value::from(1.0))
+        );
+        assert_eq!(record.get(&Symbol::new("host")), Some(&Value::from("a")));
+        assert_eq!(record.get(&Symbol::new("cpu.mode")), None);
+
+        // 缺少名称或 `=` 的标签
+        assert!(parse_graphite_to_record(";host=a 1 1620000000", &config).is_err());
+        assert!(parse_graphite_to_record("cpu;host 1 1620000000", &config).is_err());
+    }
+
     #[test]
     fn test_parse_scientific_notation() {
         let input = "system.memory 1.2e6 1620000000";
@@ -496,7 +614,7 @@ mod tests {
         assert!(result.is_ok());
         let (_, record) = result.unwrap();
 
-        let value = record.get(&VALUE_FIELD).unwrap();
+        let value = record.get(&Symbol::new("system.memory")).unwrap();
         if let Value::Float(num) = value {
             assert_eq!(num.value, 1.2e6);
         } else {
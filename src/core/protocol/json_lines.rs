@@ -0,0 +1,230 @@
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+use chrono_tz::Tz;
+use serde_json::Value as JsonValue;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::{
+    config::protocol::json::JsonProtocolConfig,
+    core::{
+        protocol,
+        types::{parse_value_in, Record, Symbol, Value, ValueType},
+    },
+};
+
+use super::base::strip_bom;
+
+/// 逐行读取 JSON 对象, 一行一条记录
+pub struct JsonLinesProtocolParser<R> {
+    reader: BufReader<R>,
+    fields: Option<HashSet<String>>,
+    timestamp_field: Option<Symbol>,
+    timezone: Tz,
+    line: String,
+    bom_checked: bool,
+}
+
+impl<R> JsonLinesProtocolParser<R>
+where
+    R: tokio::io::AsyncRead + Unpin + Send + 'static,
+{
+    pub fn try_create_from(reader: R, cfg: JsonProtocolConfig) -> protocol::Result<Self> {
+        let fields = cfg.fields.map(|fields| {
+            fields
+                .iter()
+                .chain(cfg.timestamp_field.as_ref())
+                .map(|field| field.as_str().to_string())
+                .collect()
+        });
+
+        Ok(Self {
+            reader: BufReader::new(reader),
+            fields,
+            timestamp_field: cfg.timestamp_field,
+            timezone: cfg.timezone,
+            line: String::new(),
+            bom_checked: false,
+        })
+    }
+
+    fn parse_line(&self, line: &str) -> protocol::Result<Record> {
+        let mut json = serde_json::from_str::<JsonValue>(line).map_err(|e| {
+            protocol::Error::MismatchedFormat(format!("Failed to parse JSON line: {}", e))
+        })?;
+
+        if let (Some(fields), JsonValue::Object(map)) = (&self.fields, &mut json) {
+            map.retain(|key, _| fields.contains(key));
+        }
+
+        let mut record = Record::from_json(&json).map_err(|e| {
+            protocol::Error::MismatchedFormat(format!("Invalid JSON record: {}", e))
+        })?;
+
+        if let Some(field) = &self.timestamp_field {
+            let timestamp = match record.get(field) {
+                Some(Value::String(s)) => {
+                    parse_value_in(s.as_str(), ValueType::DateTime, self.timezone).ok()
+                }
+                Some(Value::Int(n)) => {
+                    parse_value_in(&n.value.to_string(), ValueType::DateTime, self.timezone).ok()
+                }
+                Some(_) => None,
+                None => {
+                    return Err(protocol::Error::MismatchedFormat(format!(
+                        "Missing timestamp field {}",
+                        field
+                    )))
+                }
+            };
+
+            match timestamp {
+                Some(timestamp @ Value::DateTime(_)) => record.set(field.clone(), timestamp),
+                _ => {
+                    return Err(protocol::Error::MismatchedFormat(format!(
+                        "Invalid timestamp in field {}: {}",
+                        field,
+                        record.get(field).unwrap()
+                    )))
+                }
+            }
+        }
+
+        Ok(record)
+    }
+}
+
+#[async_trait]
+impl<R> protocol::ProtocolParser for JsonLinesProtocolParser<R>
+where
+    R: tokio::io::AsyncRead + Unpin + Send + 'static,
+{
+    async fn read_next(&mut self) -> protocol::Result<Record> {
+        loop {
+            self.line.clear();
+            if self.reader.read_line(&mut self.line).await? == 0 {
+                return Err(protocol::Error::Eof);
+            }
+
+            if !self.bom_checked {
+                self.bom_checked = true;
+                strip_bom(&mut self.line);
+            }
+
+            let line = self.line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            return self.parse_line(line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::core::{protocol::ProtocolParser, tag::ProtocolTagId};
+
+    fn parser(
+        data: &'static str,
+        fields: Option<Vec<&str>>,
+        timestamp_field: Option<&str>,
+    ) -> JsonLinesProtocolParser<Cursor<&'static [u8]>> {
+        let cfg = JsonProtocolConfig {
+            tag: ProtocolTagId::new("json"),
+            fields: fields.map(|fields| fields.into_iter().map(Symbol::new).collect()),
+            timestamp_field: timestamp_field.map(Symbol::new),
+            timezone: chrono_tz::Tz::UTC,
+        };
+        JsonLinesProtocolParser::try_create_from(Cursor::new(data.as_bytes()), cfg).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_json_lines() {
+        let mut parser = parser(
+            "\u{feff}{\"cpu\": 0.4, \"host\": \"a\"}\r\n\n{\"cpu\": 1, \"labels\": {\"zone\": \"b\"}}",
+            None,
+            None,
+        );
+
+        let record = parser.read_next().await.unwrap();
+        assert_eq!(record.get(&Symbol::new("cpu")), Some(&Value::from(0.4)));
+        assert_eq!(record.get(&Symbol::new("host")), Some(&Value::from("a")));
+
+        let record = parser.read_next().await.unwrap();
+        assert_eq!(record.get(&Symbol::new("cpu")), Some(&Value::from(1i64)));
+        assert!(record.get(&Symbol::new("labels")).unwrap().is_map());
+
+        assert!(parser.read_next().await.unwrap_err().is_eof());
+    }
+
+    #[tokio::test]
+    async fn test_malformed_lines() {
+        let mut parser = parser("{\"cpu\": \n[1, 2]\n{\"cpu\": 0.5}\n", None, None);
+
+        for _ in 0..2 {
+            let err = parser.read_next().await.unwrap_err();
+            assert!(
+                matches!(err, protocol::Error::MismatchedFormat(_)),
+                "{}",
+                err
+            );
+        }
+        // The stream goes on after a malformed line
+        let record = parser.read_next().await.unwrap();
+        assert_eq!(record.get(&Symbol::new("cpu")), Some(&Value::from(0.5)));
+    }
+
+    #[tokio::test]
+    async fn test_fields_and_timestamp() {
+        let mut parser = parser(
+            concat!(
+                "{\"cpu\": 0.4, \"host\": \"a\", \"ts\": \"2025-04-03T08:09:03Z\"}\n",
+                "{\"cpu\": 0.5, \"ts\": 1743667743000}\n",
+                "{\"cpu\": 0.6}\n",
+                "{\"cpu\": 0.7, \"ts\": \"yesterday\"}\n",
+            ),
+            Some(vec!["cpu"]),
+            Some("ts"),
+        );
+        let expected = Value::DateTime(DateTime::from_timestamp(1743667743, 0).unwrap());
+
+        let record = parser.read_next().await.unwrap();
+        assert!(record.get(&Symbol::new("host")).is_none());
+        assert_eq!(record.get(&Symbol::new("ts")), Some(&expected));
+
+        let record = parser.read_next().await.unwrap();
+        assert_eq!(record.get(&Symbol::new("ts")), Some(&expected));
+
+        for _ in 0..2 {
+            let err = parser.read_next().await.unwrap_err();
+            assert!(
+                matches!(err, protocol::Error::MismatchedFormat(_)),
+                "{}",
+                err
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_timestamp_timezone() {
+        let cfg: JsonProtocolConfig =
+            toml::from_str("timestamp_field = \"ts\"\ntimezone = \"Asia/Shanghai\"").unwrap();
+        let data = "{\"ts\": \"2025-04-03 16:09:03\"}\n{\"ts\": \"2025-04-03T16:09:03+08:00\"}\n";
+        let mut parser =
+            JsonLinesProtocolParser::try_create_from(Cursor::new(data.as_bytes()), cfg).unwrap();
+        let expected = Value::DateTime(DateTime::from_timestamp(1743667743, 0).unwrap());
+
+        for _ in 0..2 {
+            let record = parser.read_next().await.unwrap();
+            assert_eq!(record.get(&Symbol::new("ts")), Some(&expected));
+        }
+
+        assert!(toml::from_str::<JsonProtocolConfig>("timezone = \"Mars/Olympus\"").is_err());
+    }
+}
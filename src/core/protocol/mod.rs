@@ -1,8 +1,10 @@
 mod base;
+mod bulk;
 // mod csv;
 mod csv_nom;
 mod error;
 mod graphite_nom;
+mod json_lines;
 
 pub use base::ProtocolParser;
 pub use error::{Error, Result};
@@ -14,11 +16,28 @@ where
     R: tokio::io::AsyncRead + Unpin + Send + 'static,
 {
     match cfg {
-        ProtocolConfig::CSV(cfg) => Ok(Box::new(csv_nom::CSVProtocolParser::try_create_from(
+        ProtocolConfig::Csv(cfg) => Ok(Box::new(csv_nom::CSVProtocolParser::try_create_from(
             reader, cfg,
         )?)),
         ProtocolConfig::Graphite(cfg) => Ok(Box::new(
             graphite_nom::GraphiteProtocolParser::try_create_from(reader, cfg)?,
         )),
+        ProtocolConfig::Json(cfg) => Ok(Box::new(
+            json_lines::JsonLinesProtocolParser::try_create_from(reader, cfg)?,
+        )),
+    }
+}
+
+/// Create a parser reading the whole file at once, see [`bulk`]. Returns `None` when the
+/// protocol has no bulk mode.
+pub fn try_create_bulk_from(
+    file: &std::fs::File,
+    cfg: ProtocolConfig,
+) -> Result<Option<Box<dyn ProtocolParser>>> {
+    match cfg {
+        ProtocolConfig::Csv(cfg) => Ok(Some(Box::new(bulk::BulkCSVParser::try_create_from(
+            file, cfg,
+        )?))),
+        ProtocolConfig::Graphite(_) | ProtocolConfig::Json(_) => Ok(None),
     }
 }
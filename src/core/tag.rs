@@ -32,6 +32,8 @@ pub const INBOUND_TAG_SCOPE: &str = "inbound";
 pub const OUTBOUND_TAG_SCOPE: &str = "outbound";
 pub const PROTOCOL_TAG_SCOPE: &str = "protocol";
 pub const PIPE_TAG_SCOPE: &str = "pipe";
+// Built-in sources, e.g. `internal:metrics`
+pub const INTERNAL_TAG_SCOPE: &str = "internal";
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct InboundTagId(TagId);
@@ -65,6 +67,11 @@ macro_rules! impl_serde_for_scoped_tag_id {
                 D: serde::Deserializer<'de>,
             {
                 let name = String::deserialize(deserializer)?;
+                // Accept the scoped form written by Serialize, e.g. in the effective config
+                let name = match name.strip_prefix($scope).and_then(|n| n.strip_prefix(':')) {
+                    Some(unscoped) => unscoped.to_string(),
+                    None => name,
+                };
                 let name = name.leak();
                 Ok(Self(TagId {
                     scope: $scope,
@@ -139,7 +146,8 @@ impl<'de> Deserialize<'de> for TagId {
     {
         let str = String::deserialize(deserializer)?;
         let str = str.leak();
-        let parts: Vec<&str> = str.split(':').collect();
+        // The name may hold more colons, e.g. the routes of a pipe `pipe:split:infra`
+        let parts: Vec<&str> = str.splitn(2, ':').collect();
         if parts.len() != 2 {
             return Err(serde::de::Error::custom("Invalid ScopedTagId format"));
         }
@@ -156,6 +164,7 @@ impl<'de> Deserialize<'de> for TagId {
             OUTBOUND_TAG_SCOPE => OUTBOUND_TAG_SCOPE,
             PROTOCOL_TAG_SCOPE => PROTOCOL_TAG_SCOPE,
             PIPE_TAG_SCOPE => PIPE_TAG_SCOPE,
+            INTERNAL_TAG_SCOPE => INTERNAL_TAG_SCOPE,
             _ => return Err(serde::de::Error::custom("Invalid ScopedTagId scope")),
         };
 
@@ -173,7 +182,7 @@ impl TagId {
     }
 
     pub fn name(&self) -> &str {
-        self.name.as_ref()
+        self.name
     }
 
     pub fn is_inbound(&self) -> bool {
@@ -191,6 +200,20 @@ impl TagId {
     pub fn is_pipe(&self) -> bool {
         self.scope == PIPE_TAG_SCOPE
     }
+
+    pub fn is_internal(&self) -> bool {
+        self.scope == INTERNAL_TAG_SCOPE
+    }
+
+    /// The tag of a named output of this one, e.g. `pipe:split:infra` for the route `infra`
+    /// of `pipe:split`.
+    pub fn route(&self, route: &str) -> TagId {
+        let name = format!("{}:{}", self.name, route).leak();
+        TagId {
+            scope: self.scope,
+            name,
+        }
+    }
 }
 
 pub fn find_duplicate_tags<T>(tags: &[T]) -> Option<Vec<&TagId>>
@@ -0,0 +1,299 @@
+use super::{value::Number, Error, Result, Value};
+
+const NUMBER_TYPE: &str = "Int or Float";
+
+/// Arithmetic on numbers. Int with Int stays Int and fails on overflow, anything with a Float
+/// gives a Float.
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl Op {
+    fn symbol(self) -> &'static str {
+        match self {
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Div => "/",
+        }
+    }
+
+    fn int(self, a: i64, b: i64) -> Option<i64> {
+        match self {
+            Op::Add => a.checked_add(b),
+            Op::Sub => a.checked_sub(b),
+            Op::Mul => a.checked_mul(b),
+            Op::Div => a.checked_div(b),
+        }
+    }
+
+    fn float(self, a: f64, b: f64) -> f64 {
+        match self {
+            Op::Add => a + b,
+            Op::Sub => a - b,
+            Op::Mul => a * b,
+            Op::Div => a / b,
+        }
+    }
+
+    /// Sums need the same unit on both sides. A product or a quotient keeps the unit of the
+    /// side which has one, and dividing by the same unit leaves none.
+    fn unit(self, a: &Option<String>, b: &Option<String>) -> Result<Option<String>> {
+        match (self, a, b) {
+            (Op::Add | Op::Sub, a, b) if a == b => Ok(a.clone()),
+            (Op::Mul | Op::Div, Some(unit), None) | (Op::Mul | Op::Div, None, Some(unit)) => {
+                Ok(Some(unit.clone()))
+            }
+            (Op::Mul | Op::Div, None, None) => Ok(None),
+            (Op::Div, Some(a), Some(b)) if a == b => Ok(None),
+            _ => Err(Error::UnitMismatch(a.clone(), b.clone())),
+        }
+    }
+
+    fn apply(self, lhs: &Value, rhs: &Value) -> Result<Value> {
+        let (a_unit, b_unit) = match (lhs, rhs) {
+            (Value::Int(a), Value::Int(b)) => (&a.unit, &b.unit),
+            (Value::Int(a), Value::Float(b)) => (&a.unit, &b.unit),
+            (Value::Float(a), Value::Int(b)) => (&a.unit, &b.unit),
+            (Value::Float(a), Value::Float(b)) => (&a.unit, &b.unit),
+            (Value::Int(_) | Value::Float(_), other) | (other, _) => {
+                return Err(Error::UnexpectedType(NUMBER_TYPE, other.type_name()))
+            }
+        };
+        let unit = self.unit(a_unit, b_unit)?;
+
+        let is_zero = match rhs {
+            Value::Int(b) => b.value == 0,
+            Value::Float(b) => b.value == 0.0,
+            _ => false,
+        };
+        if matches!(self, Op::Div) && is_zero {
+            return Err(Error::DivisionByZero(lhs.to_string()));
+        }
+
+        let overflow = || Error::Overflow(format!("{} {} {}", lhs, self.symbol(), rhs));
+        match (lhs, rhs) {
+            (Value::Int(a), Value::Int(b)) => {
+                let value = self.int(a.value, b.value).ok_or_else(overflow)?;
+                Ok(Value::Int(Number { value, unit }))
+            }
+            _ => {
+                let a = as_f64(lhs);
+                let b = as_f64(rhs);
+                let value = self.float(a, b);
+                if value.is_infinite() && a.is_finite() && b.is_finite() {
+                    return Err(overflow());
+                }
+                Ok(Value::Float(Number { value, unit }))
+            }
+        }
+    }
+}
+
+fn as_f64(value: &Value) -> f64 {
+    match value {
+        Value::Int(n) => Number::<f64>::from(n.clone()).value,
+        Value::Float(n) => n.value,
+        _ => unreachable!("checked to be a number"),
+    }
+}
+
+impl Value {
+    pub fn try_add(&self, other: &Value) -> Result<Value> {
+        Op::Add.apply(self, other)
+    }
+
+    pub fn try_sub(&self, other: &Value) -> Result<Value> {
+        Op::Sub.apply(self, other)
+    }
+
+    pub fn try_mul(&self, other: &Value) -> Result<Value> {
+        Op::Mul.apply(self, other)
+    }
+
+    /// Int by Int is an integer division, rounded toward zero.
+    pub fn try_div(&self, other: &Value) -> Result<Value> {
+        Op::Div.apply(self, other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(value: i64, unit: Option<&str>) -> Value {
+        Value::Int(Number {
+            value,
+            unit: unit.map(str::to_string),
+        })
+    }
+
+    fn float(value: f64, unit: Option<&str>) -> Value {
+        Value::Float(Number {
+            value,
+            unit: unit.map(str::to_string),
+        })
+    }
+
+    type CheckedOp = fn(i64, i64) -> Option<i64>;
+
+    /// Edge cases, and pseudo random values spread over the whole range.
+    fn samples() -> Vec<i64> {
+        let mut samples = vec![
+            0,
+            1,
+            -1,
+            2,
+            i64::MAX,
+            i64::MIN,
+            i64::MAX - 1,
+            i64::MIN + 1,
+            1 << 53,
+            (1 << 53) + 1,
+            1 << 32,
+        ];
+        let mut x = 0x2545_f491_4f6c_dd1du64;
+        for _ in 0..64 {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            samples.push(x as i64 >> (x % 63));
+        }
+        samples
+    }
+
+    #[test]
+    fn test_int_matches_checked_ops() {
+        let ops: [(Op, CheckedOp); 4] = [
+            (Op::Add, i64::checked_add),
+            (Op::Sub, i64::checked_sub),
+            (Op::Mul, i64::checked_mul),
+            (Op::Div, i64::checked_div),
+        ];
+        let samples = samples();
+        for &a in &samples {
+            for &b in &samples {
+                for (op, checked) in ops {
+                    let result = op.apply(&int(a, None), &int(b, None));
+                    match checked(a, b) {
+                        Some(expected) => assert_eq!(
+                            result.unwrap(),
+                            int(expected, None),
+                            "{} {} {}",
+                            a,
+                            op.symbol(),
+                            b
+                        ),
+                        None if matches!(op, Op::Div) && b == 0 => {
+                            assert!(matches!(result, Err(Error::DivisionByZero(..))))
+                        }
+                        None => assert!(
+                            matches!(result, Err(Error::Overflow(..))),
+                            "{} {} {}",
+                            a,
+                            op.symbol(),
+                            b
+                        ),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_properties() {
+        let samples = samples();
+        for &a in &samples {
+            for &b in &samples {
+                let (a, b) = (int(a, Some("B")), int(b, Some("B")));
+                assert_eq!(a.try_add(&b).ok(), b.try_add(&a).ok());
+                assert_eq!(a.try_mul(&int(1, None)).ok(), int(1, None).try_mul(&a).ok());
+
+                // Whatever does not overflow can be undone
+                if let Ok(sum) = a.try_add(&b) {
+                    assert_eq!(sum.try_sub(&b).unwrap(), a);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_promotion() {
+        assert_eq!(int(2, None).try_add(&int(3, None)).unwrap(), int(5, None));
+        assert_eq!(
+            int(2, None).try_add(&float(0.5, None)).unwrap(),
+            float(2.5, None)
+        );
+        assert_eq!(
+            float(0.5, None).try_mul(&int(4, None)).unwrap(),
+            float(2.0, None)
+        );
+        assert_eq!(int(7, None).try_div(&int(2, None)).unwrap(), int(3, None));
+        assert_eq!(
+            int(7, None).try_div(&float(2.0, None)).unwrap(),
+            float(3.5, None)
+        );
+
+        // Beyond 2^53 only Int keeps every digit
+        let big = int((1 << 53) + 1, None);
+        assert_eq!(
+            big.try_add(&int(1, None)).unwrap(),
+            int((1 << 53) + 2, None)
+        );
+
+        assert!(matches!(
+            float(f64::MAX, None).try_mul(&int(2, None)),
+            Err(Error::Overflow(..))
+        ));
+        assert!(matches!(
+            float(1.0, None).try_div(&float(0.0, None)),
+            Err(Error::DivisionByZero(..))
+        ));
+        assert!(matches!(
+            int(1, None).try_add(&Value::from("1")),
+            Err(Error::UnexpectedType(..))
+        ));
+        assert!(matches!(
+            Value::Null.try_sub(&int(1, None)),
+            Err(Error::UnexpectedType(..))
+        ));
+    }
+
+    #[test]
+    fn test_units() {
+        let ms = |v| int(v, Some("ms"));
+        let s = |v| int(v, Some("s"));
+
+        assert_eq!(ms(1).try_add(&ms(2)).unwrap(), ms(3));
+        assert_eq!(ms(3).try_sub(&ms(2)).unwrap(), ms(1));
+        assert_eq!(ms(3).try_mul(&int(2, None)).unwrap(), ms(6));
+        assert_eq!(int(2, None).try_mul(&ms(3)).unwrap(), ms(6));
+        assert_eq!(ms(6).try_div(&int(2, None)).unwrap(), ms(3));
+        assert_eq!(ms(6).try_div(&ms(2)).unwrap(), int(3, None));
+
+        for result in [
+            ms(1).try_add(&s(1)),
+            ms(1).try_add(&int(1, None)),
+            int(1, None).try_sub(&ms(1)),
+            ms(1).try_mul(&ms(1)),
+            ms(1).try_div(&s(1)),
+            float(1.0, Some("ms")).try_add(&float(1.0, Some("s"))),
+        ] {
+            assert!(
+                matches!(result, Err(Error::UnitMismatch(..))),
+                "{:?}",
+                result
+            );
+        }
+
+        // The units are checked before the values
+        assert!(matches!(
+            ms(i64::MAX).try_add(&s(1)),
+            Err(Error::UnitMismatch(..))
+        ));
+    }
+}
@@ -1,13 +1,17 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use serde_json::{Map, Value as JsonValue};
 use std::collections::HashMap;
 use thiserror::Error;
 
 use crate::core::types::{
-    intern, resolve,
+    intern,
     value::{Number, STRING_TYPE},
     Record, Value,
 };
 
+/// Bytes are written as `{"__bytes__": "<base64>"}`, and read back from such an object.
+pub const BYTES_HINT: &str = "__bytes__";
+
 #[derive(Debug, Error)]
 pub enum ConversionError {
     #[error("Invalid type, expected a {0} but got {1}")]
@@ -41,7 +45,7 @@ impl TryFrom<&Value> for JsonValue {
                         .unwrap_or(JsonValue::Null)
                 }
             }
-            Value::String(s) => JsonValue::String(s.to_string()),
+            Value::String(s) => JsonValue::String(s.as_str().to_owned()),
             Value::Array(arr) => JsonValue::Array(
                 arr.iter()
                     .map(JsonValue::try_from)
@@ -61,6 +65,11 @@ impl TryFrom<&Value> for JsonValue {
                 JsonValue::Object(json_map)
             }
             Value::DateTime(dt) => JsonValue::String(dt.to_string()),
+            Value::Bytes(bytes) => {
+                let mut hinted = Map::new();
+                hinted.insert(BYTES_HINT.into(), JsonValue::String(BASE64.encode(bytes)));
+                JsonValue::Object(hinted)
+            }
         };
 
         Ok(json_value)
@@ -105,6 +114,10 @@ impl TryFrom<&JsonValue> for Value {
                     .collect::<Result<Vec<_>, _>>()?,
             )),
             JsonValue::Object(map) => {
+                if let Some(bytes) = hinted_bytes(map) {
+                    return Ok(Value::Bytes(bytes));
+                }
+
                 let mut values = HashMap::new();
                 for (k, v) in map {
                     values.insert(Value::from(intern(k)), Value::try_from(v)?);
@@ -115,6 +128,14 @@ impl TryFrom<&JsonValue> for Value {
     }
 }
 
+/// The payload of a `{"__bytes__": "<base64>"}` object, other objects are maps.
+fn hinted_bytes(map: &Map<String, JsonValue>) -> Option<Vec<u8>> {
+    match (map.len(), map.get(BYTES_HINT)) {
+        (1, Some(JsonValue::String(encoded))) => BASE64.decode(encoded).ok(),
+        _ => None,
+    }
+}
+
 // 直接复用引用实现
 impl TryFrom<JsonValue> for Value {
     type Error = ConversionError;
@@ -131,7 +152,7 @@ impl Record {
         // 合并处理常规字段和属性字段
         // 转换常规值
         for (key, value) in self.iter() {
-            map.insert(resolve(key).into(), JsonValue::try_from(value)?);
+            map.insert(key.as_str().to_owned(), JsonValue::try_from(value)?);
         }
 
         // 转换属性
@@ -197,8 +218,8 @@ mod tests {
             json!(42)
         );
         assert_eq!(
-            JsonValue::try_from(&Value::Float(Number::new(3.14))).unwrap(),
-            json!(3.14)
+            JsonValue::try_from(&Value::Float(Number::new(2.75))).unwrap(),
+            json!(2.75)
         );
 
         // Test string
@@ -241,8 +262,8 @@ mod tests {
             Value::Int(Number::new(42))
         );
         assert_eq!(
-            Value::try_from(&json!(3.14)).unwrap(),
-            Value::Float(Number::new(3.14))
+            Value::try_from(&json!(2.75)).unwrap(),
+            Value::Float(Number::new(2.75))
         );
 
         // Test string
@@ -355,8 +376,8 @@ mod tests {
         let arr = vec![Value::Int(Number::new(1)), Value::Map(nested_map.clone())];
 
         let mut record = Record::empty();
-        record.set(intern("array").into(), Value::Array(arr));
-        record.set(intern("map").into(), Value::Map(nested_map));
+        record.set(intern("array"), Value::Array(arr));
+        record.set(intern("map"), Value::Map(nested_map));
 
         let json = record.to_json();
         let expected = json!({
@@ -525,7 +546,7 @@ mod tests {
 
     #[test]
     fn test_datetime_conversion() {
-        use chrono::{DateTime, Utc};
+        use chrono::Utc;
 
         // Create a datetime value
         let now = Utc::now();
@@ -560,4 +581,18 @@ mod tests {
             _ => panic!("Expected Value::Int"),
         }
     }
+
+    #[test]
+    fn test_bytes_conversion() {
+        let bytes = Value::Bytes(vec![0xde, 0xad, 0xbe, 0xef]);
+        let json = JsonValue::try_from(&bytes).unwrap();
+        assert_eq!(json, json!({"__bytes__": "3q2+7w=="}));
+        assert_eq!(Value::try_from(&json).unwrap(), bytes);
+
+        // Only a lone base64 string is taken for bytes
+        let map = Value::try_from(&json!({"__bytes__": "3q2+7w==", "other": 1})).unwrap();
+        assert!(map.is_map());
+        let map = Value::try_from(&json!({"__bytes__": "not base64!"})).unwrap();
+        assert!(map.is_map());
+    }
 }
@@ -1,3 +1,4 @@
 pub mod json;
+pub mod otlp;
 pub mod parquet;
 pub mod prometheus;
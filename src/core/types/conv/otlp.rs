@@ -0,0 +1,646 @@
+use std::{collections::HashMap, io::Write};
+
+use flate2::{write::GzEncoder, Compression};
+use miette::Diagnostic;
+use thiserror::Error;
+
+use crate::core::{
+    pipe::{LABELS_FIELD, METRIC_TYPE_FIELD, NAME_FIELD, TIMESTAMP_FIELD, VALUE_FIELD},
+    types::{Record, Value},
+};
+
+use super::prometheus::{
+    LABELS_FIELD_STR, METRIC_TYPE_FIELD_STR, NAME_FIELD_STR, TIMESTAMP_FIELD_STR, VALUE_FIELD_STR,
+};
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum Error {
+    #[error("Field not found: {0}")]
+    FieldNotFound(&'static str),
+    #[error("Timestamp out of range: {0}")]
+    TimestampOutOfRange(String),
+    #[error("Invalid type: {0}")]
+    Type(#[from] crate::core::types::Error),
+    #[error("Failed to compress: {0}")]
+    Gzip(#[from] std::io::Error),
+    #[error("Message of {0} bytes too large for gRPC")]
+    MessageTooLarge(usize),
+}
+
+/// Name of the service and of the instrumentation scope the metrics are reported by
+pub const SCOPE_NAME: &str = "void";
+
+/// .proto:
+/// ```protobuf
+/// message ExportMetricsServiceRequest {
+///   repeated ResourceMetrics resource_metrics = 1;
+/// }
+/// ```
+#[derive(prost::Message, Clone, PartialEq)]
+pub struct ExportMetricsServiceRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub resource_metrics: Vec<ResourceMetrics>,
+}
+
+/// .proto:
+/// ```protobuf
+/// message ResourceMetrics {
+///   Resource resource = 1;
+///   repeated ScopeMetrics scope_metrics = 2;
+///   string schema_url = 3;
+/// }
+/// ```
+#[derive(prost::Message, Clone, PartialEq)]
+pub struct ResourceMetrics {
+    #[prost(message, optional, tag = "1")]
+    pub resource: Option<Resource>,
+    #[prost(message, repeated, tag = "2")]
+    pub scope_metrics: Vec<ScopeMetrics>,
+}
+
+/// .proto:
+/// ```protobuf
+/// message Resource {
+///   repeated KeyValue attributes = 1;
+///   uint32 dropped_attributes_count = 2;
+/// }
+/// ```
+#[derive(prost::Message, Clone, PartialEq)]
+pub struct Resource {
+    #[prost(message, repeated, tag = "1")]
+    pub attributes: Vec<KeyValue>,
+}
+
+/// .proto:
+/// ```protobuf
+/// message ScopeMetrics {
+///   InstrumentationScope scope = 1;
+///   repeated Metric metrics = 2;
+///   string schema_url = 3;
+/// }
+/// ```
+#[derive(prost::Message, Clone, PartialEq)]
+pub struct ScopeMetrics {
+    #[prost(message, optional, tag = "1")]
+    pub scope: Option<InstrumentationScope>,
+    #[prost(message, repeated, tag = "2")]
+    pub metrics: Vec<Metric>,
+}
+
+/// .proto:
+/// ```protobuf
+/// message InstrumentationScope {
+///   string name = 1;
+///   string version = 2;
+///   repeated KeyValue attributes = 3;
+///   uint32 dropped_attributes_count = 4;
+/// }
+/// ```
+#[derive(prost::Message, Clone, PartialEq)]
+pub struct InstrumentationScope {
+    #[prost(string, tag = "1")]
+    pub name: String,
+}
+
+/// Only the gauges and the sums are sent, the other kinds of data are left out.
+///
+/// .proto:
+/// ```protobuf
+/// message Metric {
+///   string name = 1;
+///   string description = 2;
+///   string unit = 3;
+///   oneof data {
+///     Gauge gauge = 5;
+///     Sum sum = 7;
+///     Histogram histogram = 9;
+///     ExponentialHistogram exponential_histogram = 10;
+///     Summary summary = 11;
+///   }
+///   repeated KeyValue metadata = 12;
+/// }
+/// ```
+#[derive(prost::Message, Clone, PartialEq)]
+pub struct Metric {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "3")]
+    pub unit: String,
+    #[prost(oneof = "MetricData", tags = "5, 7")]
+    pub data: Option<MetricData>,
+}
+
+#[derive(prost::Oneof, Clone, PartialEq)]
+pub enum MetricData {
+    #[prost(message, tag = "5")]
+    Gauge(Gauge),
+    #[prost(message, tag = "7")]
+    Sum(Sum),
+}
+
+/// .proto:
+/// ```protobuf
+/// message Gauge {
+///   repeated NumberDataPoint data_points = 1;
+/// }
+/// ```
+#[derive(prost::Message, Clone, PartialEq)]
+pub struct Gauge {
+    #[prost(message, repeated, tag = "1")]
+    pub data_points: Vec<NumberDataPoint>,
+}
+
+/// .proto:
+/// ```protobuf
+/// message Sum {
+///   repeated NumberDataPoint data_points = 1;
+///   AggregationTemporality aggregation_temporality = 2;
+///   bool is_monotonic = 3;
+/// }
+/// ```
+#[derive(prost::Message, Clone, PartialEq)]
+pub struct Sum {
+    #[prost(message, repeated, tag = "1")]
+    pub data_points: Vec<NumberDataPoint>,
+    #[prost(enumeration = "AggregationTemporality", tag = "2")]
+    pub aggregation_temporality: i32,
+    #[prost(bool, tag = "3")]
+    pub is_monotonic: bool,
+}
+
+#[derive(prost::Enumeration, Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(i32)]
+pub enum AggregationTemporality {
+    Unspecified = 0,
+    Delta = 1,
+    Cumulative = 2,
+}
+
+/// .proto:
+/// ```protobuf
+/// message NumberDataPoint {
+///   repeated KeyValue attributes = 7;
+///   fixed64 start_time_unix_nano = 2;
+///   fixed64 time_unix_nano = 3;
+///   oneof value {
+///     double as_double = 4;
+///     sfixed64 as_int = 6;
+///   }
+///   repeated Exemplar exemplars = 5;
+///   uint32 flags = 8;
+/// }
+/// ```
+#[derive(prost::Message, Clone, PartialEq)]
+pub struct NumberDataPoint {
+    #[prost(fixed64, tag = "3")]
+    pub time_unix_nano: u64,
+    #[prost(oneof = "NumberValue", tags = "4, 6")]
+    pub value: Option<NumberValue>,
+    #[prost(message, repeated, tag = "7")]
+    pub attributes: Vec<KeyValue>,
+}
+
+#[derive(prost::Oneof, Clone, PartialEq)]
+pub enum NumberValue {
+    #[prost(double, tag = "4")]
+    AsDouble(f64),
+    #[prost(sfixed64, tag = "6")]
+    AsInt(i64),
+}
+
+/// .proto:
+/// ```protobuf
+/// message KeyValue {
+///   string key = 1;
+///   AnyValue value = 2;
+/// }
+/// ```
+#[derive(prost::Message, Clone, PartialEq)]
+pub struct KeyValue {
+    #[prost(string, tag = "1")]
+    pub key: String,
+    #[prost(message, optional, tag = "2")]
+    pub value: Option<AnyValue>,
+}
+
+/// Only the scalar values are used, the other kinds of values are sent as strings.
+///
+/// .proto:
+/// ```protobuf
+/// message AnyValue {
+///   oneof value {
+///     string string_value = 1;
+///     bool bool_value = 2;
+///     int64 int_value = 3;
+///     double double_value = 4;
+///     ArrayValue array_value = 5;
+///     KeyValueList kvlist_value = 6;
+///     bytes bytes_value = 7;
+///   }
+/// }
+/// ```
+#[derive(prost::Message, Clone, PartialEq)]
+pub struct AnyValue {
+    #[prost(oneof = "AnyValueKind", tags = "1, 2, 3, 4")]
+    pub value: Option<AnyValueKind>,
+}
+
+#[derive(prost::Oneof, Clone, PartialEq)]
+pub enum AnyValueKind {
+    #[prost(string, tag = "1")]
+    String(String),
+    #[prost(bool, tag = "2")]
+    Bool(bool),
+    #[prost(int64, tag = "3")]
+    Int(i64),
+    #[prost(double, tag = "4")]
+    Double(f64),
+}
+
+impl From<Value> for AnyValue {
+    fn from(value: Value) -> Self {
+        let value = match value {
+            Value::String(s) => AnyValueKind::String(s.into_string()),
+            Value::Bool(b) => AnyValueKind::Bool(b),
+            Value::Int(n) => AnyValueKind::Int(n.value),
+            Value::Float(n) => AnyValueKind::Double(n.value),
+            value => AnyValueKind::String(value.to_string()),
+        };
+        AnyValue { value: Some(value) }
+    }
+}
+
+impl KeyValue {
+    fn new(key: impl Into<String>, value: impl Into<AnyValue>) -> Self {
+        KeyValue {
+            key: key.into(),
+            value: Some(value.into()),
+        }
+    }
+}
+
+/// A data point and what tells apart the metric it belongs to.
+#[derive(Debug, Clone, PartialEq)]
+struct Point {
+    name: String,
+    unit: String,
+    monotonic: bool,
+    point: NumberDataPoint,
+}
+
+impl TryFrom<Record> for Point {
+    type Error = Error;
+
+    fn try_from(record: Record) -> Result<Self, Self::Error> {
+        // Counters are cumulative sums, the other types are sent as gauges, including the
+        // series of the histograms and summaries.
+        let monotonic = record
+            .get(&METRIC_TYPE_FIELD)
+            .ok_or(Error::FieldNotFound(METRIC_TYPE_FIELD_STR))?
+            .string()?
+            .as_str()
+            == "counter";
+
+        let timestamp = record
+            .get(&TIMESTAMP_FIELD)
+            .ok_or(Error::FieldNotFound(TIMESTAMP_FIELD_STR))?
+            .datetime()?;
+        let time_unix_nano = timestamp
+            .as_datetime()
+            .timestamp_nanos_opt()
+            .and_then(|nanos| u64::try_from(nanos).ok())
+            .ok_or_else(|| Error::TimestampOutOfRange(timestamp.as_datetime().to_rfc3339()))?;
+
+        let (value, unit) = match record
+            .get(&VALUE_FIELD)
+            .ok_or(Error::FieldNotFound(VALUE_FIELD_STR))?
+        {
+            Value::Int(n) => (NumberValue::AsInt(n.value), n.unit.clone()),
+            value => {
+                let n = value.float()?;
+                (NumberValue::AsDouble(n.value()), n.unit().cloned())
+            }
+        };
+
+        let mut fields = record.take();
+
+        let name = fields
+            .remove(&NAME_FIELD)
+            .ok_or(Error::FieldNotFound(NAME_FIELD_STR))?
+            .into_string()?;
+
+        let labels = fields
+            .remove(&LABELS_FIELD)
+            .ok_or(Error::FieldNotFound(LABELS_FIELD_STR))?
+            .into_map()?;
+        let mut attributes = labels
+            .into_iter()
+            .map(|(key, value)| Ok(KeyValue::new(key.into_string()?, value)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        attributes.sort_by(|a, b| a.key.cmp(&b.key));
+
+        Ok(Point {
+            name,
+            unit: unit.unwrap_or_default(),
+            monotonic,
+            point: NumberDataPoint {
+                time_unix_nano,
+                value: Some(value),
+                attributes,
+            },
+        })
+    }
+}
+
+/// Convert timeseries records into one export request, the data points of a metric being
+/// grouped in the order they come.
+pub fn transform_metrics(records: Vec<Record>) -> Result<ExportMetricsServiceRequest, Error> {
+    let mut metrics: Vec<Metric> = Vec::new();
+    let mut index = HashMap::new();
+
+    for record in records {
+        let Point {
+            name,
+            unit,
+            monotonic,
+            point,
+        } = Point::try_from(record)?;
+
+        let i = *index
+            .entry((name.clone(), unit.clone(), monotonic))
+            .or_insert_with(|| {
+                let data = match monotonic {
+                    true => MetricData::Sum(Sum {
+                        data_points: Vec::new(),
+                        aggregation_temporality: AggregationTemporality::Cumulative as i32,
+                        is_monotonic: true,
+                    }),
+                    false => MetricData::Gauge(Gauge {
+                        data_points: Vec::new(),
+                    }),
+                };
+                metrics.push(Metric {
+                    name,
+                    unit,
+                    data: Some(data),
+                });
+                metrics.len() - 1
+            });
+
+        match metrics[i].data {
+            Some(MetricData::Sum(ref mut sum)) => sum.data_points.push(point),
+            Some(MetricData::Gauge(ref mut gauge)) => gauge.data_points.push(point),
+            None => unreachable!("built with its data"),
+        }
+    }
+
+    Ok(ExportMetricsServiceRequest {
+        resource_metrics: vec![ResourceMetrics {
+            resource: Some(Resource {
+                attributes: vec![KeyValue::new(
+                    "service.name",
+                    Value::from(SCOPE_NAME.to_string()),
+                )],
+            }),
+            scope_metrics: vec![ScopeMetrics {
+                scope: Some(InstrumentationScope {
+                    name: SCOPE_NAME.to_string(),
+                }),
+                metrics,
+            }],
+        }],
+    })
+}
+
+impl ExportMetricsServiceRequest {
+    pub fn num_data_points(&self) -> usize {
+        self.resource_metrics
+            .iter()
+            .flat_map(|r| &r.scope_metrics)
+            .flat_map(|s| &s.metrics)
+            .map(|m| match m.data {
+                Some(MetricData::Gauge(ref gauge)) => gauge.data_points.len(),
+                Some(MetricData::Sum(ref sum)) => sum.data_points.len(),
+                None => 0,
+            })
+            .sum()
+    }
+
+    pub fn encode_proto3(&self) -> Vec<u8> {
+        prost::Message::encode_to_vec(self)
+    }
+
+    pub fn encode_gzip(&self) -> Result<Vec<u8>, Error> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&self.encode_proto3())?;
+        Ok(encoder.finish()?)
+    }
+
+    /// The message of the gRPC `Export` call, framed as in the gRPC protocol over HTTP/2: a
+    /// flag telling whether it is compressed, its length in big endian, then the message.
+    pub fn encode_grpc(&self, gzip: bool) -> Result<Vec<u8>, Error> {
+        let message = match gzip {
+            true => self.encode_gzip()?,
+            false => self.encode_proto3(),
+        };
+        let len =
+            u32::try_from(message.len()).map_err(|_| Error::MessageTooLarge(message.len()))?;
+
+        let mut frame = Vec::with_capacity(5 + message.len());
+        frame.push(gzip as u8);
+        frame.extend_from_slice(&len.to_be_bytes());
+        frame.extend_from_slice(&message);
+        Ok(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+    use crate::core::pipe::RECORD_TYPE_TIMESERIES_VALUE;
+    use crate::core::types::parse_value;
+    use crate::core::types::ValueType;
+
+    fn record(name: &str, metric_type: &str, value: Value, labels: &[(&str, Value)]) -> Record {
+        let mut record = Record::new_root();
+        record.set(NAME_FIELD.clone(), Value::from(name));
+        record.set(METRIC_TYPE_FIELD.clone(), Value::from(metric_type));
+        record.set(VALUE_FIELD.clone(), value);
+        record.set(
+            TIMESTAMP_FIELD.clone(),
+            Value::from(chrono::DateTime::from_timestamp_millis(1_700_000_000_123).unwrap()),
+        );
+        record.set(
+            LABELS_FIELD.clone(),
+            Value::from(
+                labels
+                    .iter()
+                    .map(|(k, v)| (Value::from(*k), v.clone()))
+                    .collect::<HashMap<_, _>>(),
+            ),
+        );
+        record.set_type(RECORD_TYPE_TIMESERIES_VALUE.clone());
+        record
+    }
+
+    fn hex(s: &str) -> Vec<u8> {
+        let s = s.split_whitespace().collect::<String>();
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_golden_bytes() {
+        let request = transform_metrics(vec![
+            record(
+                "up",
+                "gauge",
+                Value::from(1i64),
+                &[("host", Value::from("a"))],
+            ),
+            record(
+                "requests",
+                "counter",
+                Value::from(2.5),
+                &[("code", Value::from(200i64)), ("ok", Value::from(true))],
+            ),
+        ])
+        .unwrap();
+
+        // Encoded by hand from the OTLP .proto definitions
+        let expected = hex("0a 8a 01
+               0a 18 0a 16 0a 0c 73 65 72 76 69 63 65 2e 6e 61 6d 65 12 06 0a 04 76 6f 69 64
+               12 6e
+                 0a 06 0a 04 76 6f 69 64
+                 12 27 0a 02 75 70
+                   2a 21 0a 1f
+                     19 c0 d4 7e 3d fe 9c 97 17
+                     31 01 00 00 00 00 00 00 00
+                     3a 0b 0a 04 68 6f 73 74 12 03 0a 01 61
+                 12 3b 0a 08 72 65 71 75 65 73 74 73
+                   3a 2f 0a 29
+                     19 c0 d4 7e 3d fe 9c 97 17
+                     21 00 00 00 00 00 00 04 40
+                     3a 0b 0a 04 63 6f 64 65 12 03 18 c8 01
+                     3a 08 0a 02 6f 6b 12 02 10 01
+                   10 02 18 01");
+        assert_eq!(request.encode_proto3(), expected);
+        assert_eq!(request.num_data_points(), 2);
+    }
+
+    #[test]
+    fn test_grouping() {
+        let ms = |v| parse_value(v, ValueType::Float).unwrap();
+        let request = transform_metrics(vec![
+            record(
+                "latency",
+                "gauge",
+                ms("1 ms"),
+                &[("host", Value::from("a"))],
+            ),
+            record("errors", "counter", Value::from(1i64), &[]),
+            record(
+                "latency",
+                "gauge",
+                ms("2 ms"),
+                &[("host", Value::from("b"))],
+            ),
+            record("errors", "counter", Value::from(3i64), &[]),
+            record("latency", "histogram", Value::from(1.0), &[]),
+        ])
+        .unwrap();
+
+        let metrics = &request.resource_metrics[0].scope_metrics[0].metrics;
+        assert_eq!(
+            metrics
+                .iter()
+                .map(|m| (m.name.as_str(), m.unit.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("latency", "ms"), ("errors", ""), ("latency", "")]
+        );
+
+        let Some(MetricData::Gauge(ref gauge)) = metrics[0].data else {
+            panic!("expected a gauge: {:?}", metrics[0]);
+        };
+        assert_eq!(gauge.data_points.len(), 2);
+        assert_eq!(gauge.data_points[1].value, Some(NumberValue::AsDouble(2.0)));
+        assert_eq!(
+            gauge.data_points[1].attributes,
+            vec![KeyValue::new("host", Value::from("b"))]
+        );
+
+        let Some(MetricData::Sum(ref sum)) = metrics[1].data else {
+            panic!("expected a sum: {:?}", metrics[1]);
+        };
+        assert!(sum.is_monotonic);
+        assert_eq!(
+            sum.aggregation_temporality,
+            AggregationTemporality::Cumulative as i32
+        );
+        assert_eq!(sum.data_points[1].value, Some(NumberValue::AsInt(3)));
+        assert_eq!(request.num_data_points(), 5);
+    }
+
+    #[test]
+    fn test_invalid_records() {
+        let mut missing = Record::new_root();
+        missing.set(NAME_FIELD.clone(), Value::from("up"));
+        assert!(matches!(
+            transform_metrics(vec![missing]),
+            Err(Error::FieldNotFound(METRIC_TYPE_FIELD_STR))
+        ));
+
+        let not_a_number = record("up", "gauge", Value::from("1"), &[]);
+        assert!(matches!(
+            transform_metrics(vec![not_a_number]),
+            Err(Error::Type(..))
+        ));
+
+        let mut too_late = record("up", "gauge", Value::from(1i64), &[]);
+        too_late.set(
+            TIMESTAMP_FIELD.clone(),
+            Value::from(chrono::DateTime::from_timestamp(10_000_000_000, 0).unwrap()),
+        );
+        assert!(matches!(
+            transform_metrics(vec![too_late]),
+            Err(Error::TimestampOutOfRange(..))
+        ));
+    }
+
+    #[test]
+    fn test_gzip() {
+        let request =
+            transform_metrics(vec![record("up", "gauge", Value::from(1i64), &[])]).unwrap();
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(request.encode_gzip().unwrap().as_slice())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, request.encode_proto3());
+    }
+
+    #[test]
+    fn test_grpc_frame() {
+        let request =
+            transform_metrics(vec![record("up", "gauge", Value::from(1i64), &[])]).unwrap();
+        let message = request.encode_proto3();
+
+        let frame = request.encode_grpc(false).unwrap();
+        assert_eq!(frame[0], 0);
+        assert_eq!(frame[1..5], (message.len() as u32).to_be_bytes());
+        assert_eq!(frame[5..], message);
+
+        let frame = request.encode_grpc(true).unwrap();
+        assert_eq!(frame[0], 1);
+        let len = u32::from_be_bytes(frame[1..5].try_into().unwrap()) as usize;
+        assert_eq!(len, frame.len() - 5);
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(&frame[5..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, message);
+    }
+}
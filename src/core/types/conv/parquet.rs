@@ -1,18 +1,26 @@
+#[cfg(test)]
+use arrow::array::Array;
 use arrow::array::{
-    Array, ArrayRef, BooleanArray, Float64Array, Int64Array, ListArray, MapArray, StringArray,
-    StructArray,
+    ArrayRef, BinaryArray, BooleanArray, Float64Array, Int64Array, ListArray, MapArray,
+    StringArray, StructArray,
 };
 use arrow::buffer::OffsetBuffer;
 use arrow::datatypes::{DataType as ArrowDataType, Field, Fields, Schema, SchemaRef};
 use arrow::record_batch::RecordBatch;
 use miette::Diagnostic;
+#[cfg(test)]
 use parquet::file::reader::FileReader;
-use std::collections::{BTreeMap, HashMap};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+#[cfg(test)]
+use std::collections::HashMap;
 use std::sync::Arc;
 use thiserror::Error;
 
+#[cfg(test)]
 use crate::core::types::value::Number;
-use crate::core::types::{intern, Record, Value};
+use crate::core::types::{intern, Primitive, Record, Value};
+#[cfg(test)]
 use crate::utils::tracing::TracingContext;
 
 #[derive(Debug, Error, Diagnostic)]
@@ -45,17 +53,14 @@ pub fn value_to_data_type(value: &Value) -> Result<ArrowDataType, Error> {
         Value::Float(_) => Ok(ArrowDataType::Float64),
         Value::Bool(_) => Ok(ArrowDataType::Boolean),
         Value::DateTime(_) => Ok(ArrowDataType::Int64),
+        Value::Bytes(_) => Ok(ArrowDataType::Binary),
         Value::Map(fields) => {
             // 使用迭代器直接构建字段集合
             let arrow_fields = fields
                 .iter()
                 .map(|(k, v)| {
-                    let key_name = match k {
-                        Value::String(s) => s.to_string(),
-                        _ => k.to_string(),
-                    };
-
-                    value_to_data_type(v).map(|field_type| Field::new(&key_name, field_type, true))
+                    value_to_data_type(v)
+                        .map(|field_type| Field::new(key_name(k), field_type, true))
                 })
                 .collect::<Result<Vec<Field>, Error>>()?;
 
@@ -132,18 +137,24 @@ pub fn value_to_data_type(value: &Value) -> Result<ArrowDataType, Error> {
     }
 }
 
-/// 将单个Value转换为Arrow数组元素
-fn value_to_array_element(value: &Value, data_type: &ArrowDataType) -> Option<Value> {
-    match (value, data_type) {
-        (Value::Null, _) => None,
-        (_, ArrowDataType::Utf8) => Some(Value::String(value.to_string().into())),
-        (Value::Int(n), ArrowDataType::Int64) => Some(Value::Int(n.clone())),
-        (Value::Float(f), ArrowDataType::Float64) => Some(Value::Float(f.clone())),
-        (Value::Bool(b), ArrowDataType::Boolean) => Some(Value::Bool(*b)),
-        (Value::DateTime(dt), ArrowDataType::Int64) => {
-            Some(Value::Int(Number::new(dt.timestamp_millis())))
-        }
-        (_, _) => Some(value.clone()),
+/// 配置的字段类型对应的Arrow数据类型, 时间与推断时一样以毫秒存储
+pub fn primitive_to_data_type(primitive: &Primitive) -> ArrowDataType {
+    match primitive {
+        Primitive::Null | Primitive::String => ArrowDataType::Utf8,
+        Primitive::Int | Primitive::DateTime => ArrowDataType::Int64,
+        Primitive::Float => ArrowDataType::Float64,
+        Primitive::Bool => ArrowDataType::Boolean,
+        Primitive::Bytes | Primitive::HexBytes => ArrowDataType::Binary,
+    }
+}
+
+/// 能同时容纳两种类型的列类型: 整数放宽为浮点数, 其余冲突放宽为字符串
+pub fn widen_data_type(a: &ArrowDataType, b: &ArrowDataType) -> ArrowDataType {
+    match (a, b) {
+        _ if a == b => a.clone(),
+        (ArrowDataType::Int64, ArrowDataType::Float64)
+        | (ArrowDataType::Float64, ArrowDataType::Int64) => ArrowDataType::Float64,
+        _ => ArrowDataType::Utf8,
     }
 }
 
@@ -151,16 +162,35 @@ fn value_to_array_element(value: &Value, data_type: &ArrowDataType) -> Option<Va
 // 基本数据类型转换函数
 //
 
-/// 将Values转换为StringArray
+/// Map 的键作为字段名, 字符串键直接借用
+fn key_name(k: &Value) -> Cow<'_, str> {
+    match k {
+        Value::String(s) => Cow::Borrowed(s.as_str()),
+        _ => Cow::Owned(k.to_string()),
+    }
+}
+
+/// 将Values转换为StringArray, 二进制数据以base64写入
 fn values_to_string_array(values: &[Option<Value>]) -> StringArray {
     StringArray::from_iter(values.iter().map(|v| {
         v.as_ref().map(|val| match val {
             Value::Null => "null".to_string(),
+            Value::Bytes(_) => val.cast_string().map(|s| s.to_string()).unwrap_or_default(),
             _ => val.to_string(),
         })
     }))
 }
 
+/// 将Values转换为BinaryArray
+fn values_to_binary_array(values: &[Option<Value>]) -> BinaryArray {
+    BinaryArray::from_iter(values.iter().map(|v| {
+        v.as_ref().and_then(|val| match val {
+            Value::Bytes(bytes) => Some(bytes.as_slice()),
+            _ => None,
+        })
+    }))
+}
+
 /// 将Values转换为Int64Array
 fn values_to_int_array(values: &[Option<Value>]) -> Int64Array {
     Int64Array::from_iter(values.iter().map(|v| {
@@ -209,7 +239,7 @@ fn values_to_list_array(values: &[Option<Value>], field: &Field) -> Result<ListA
                     v.as_ref().and_then(|val| {
                         if let Value::Array(items) = val {
                             if !items.is_empty() {
-                                return Some(value_to_data_type(&items[0]).ok()?);
+                                return value_to_data_type(&items[0]).ok();
                             }
                         }
                         None
@@ -250,7 +280,7 @@ fn values_to_list_array(values: &[Option<Value>], field: &Field) -> Result<ListA
 }
 
 /// 将Value映射转换为MapArray
-fn values_to_map_array(values: &[Option<Value>], field: &Field) -> Result<MapArray, Error> {
+fn values_to_map_array(values: &[Option<Value>]) -> Result<MapArray, Error> {
     // 准备键值对存储
     let mut offsets = Vec::with_capacity(values.len() + 1);
     let mut key_values = Vec::new();
@@ -334,12 +364,8 @@ fn values_to_struct_array(values: &[Option<Value>], field: &Field) -> Result<Str
                         if let Value::Map(map) = val {
                             let mut fields = Vec::new();
                             for (k, v) in map {
-                                let key_name = match k {
-                                    Value::String(s) => s.to_string(),
-                                    _ => k.to_string(),
-                                };
                                 if let Ok(field_type) = value_to_data_type(v) {
-                                    fields.push(Field::new(&key_name, field_type, true));
+                                    fields.push(Field::new(key_name(k), field_type, true));
                                 }
                             }
                             return Some(Fields::from(fields));
@@ -363,12 +389,7 @@ fn values_to_struct_array(values: &[Option<Value>], field: &Field) -> Result<Str
     for (i, value) in values.iter().enumerate() {
         if let Some(Value::Map(map)) = value {
             for (k, v) in map {
-                let key_str = match k {
-                    Value::String(s) => s.to_string(),
-                    _ => k.to_string(),
-                };
-
-                if let Some(values) = field_values.get_mut(&key_str) {
+                if let Some(values) = field_values.get_mut(key_name(k).as_ref()) {
                     values[i] = Some(v.clone());
                 }
             }
@@ -397,14 +418,12 @@ fn values_to_array(values: &[Option<Value>], data_type: &ArrowDataType) -> Resul
         ArrowDataType::Int64 => Ok(Arc::new(values_to_int_array(values))),
         ArrowDataType::Float64 => Ok(Arc::new(values_to_float_array(values))),
         ArrowDataType::Boolean => Ok(Arc::new(values_to_bool_array(values))),
+        ArrowDataType::Binary => Ok(Arc::new(values_to_binary_array(values))),
         ArrowDataType::List(element_field) => {
             let list_field = Field::new("item", ArrowDataType::List(element_field.clone()), true);
             Ok(Arc::new(values_to_list_array(values, &list_field)?))
         }
-        ArrowDataType::Map(_, _) => {
-            let field = Field::new("item", data_type.clone(), true);
-            Ok(Arc::new(values_to_map_array(values, &field)?))
-        }
+        ArrowDataType::Map(_, _) => Ok(Arc::new(values_to_map_array(values)?)),
         ArrowDataType::Struct(_) => {
             let field = Field::new("item", data_type.clone(), true);
             Ok(Arc::new(values_to_struct_array(values, &field)?))
@@ -413,6 +432,7 @@ fn values_to_array(values: &[Option<Value>], data_type: &ArrowDataType) -> Resul
     }
 }
 
+#[cfg(test)]
 /// 从Arrow数组提取指定索引的值
 fn extract_value_from_array(array: &ArrayRef, index: usize) -> Result<Option<Value>, Error> {
     if array.is_null(index) {
@@ -437,6 +457,10 @@ fn extract_value_from_array(array: &ArrayRef, index: usize) -> Result<Option<Val
             let array = array.as_any().downcast_ref::<BooleanArray>().unwrap();
             Ok(Some(Value::Bool(array.value(index))))
         }
+        ArrowDataType::Binary => {
+            let array = array.as_any().downcast_ref::<BinaryArray>().unwrap();
+            Ok(Some(Value::Bytes(array.value(index).to_vec())))
+        }
         ArrowDataType::List(_) => {
             let array = array.as_any().downcast_ref::<ListArray>().unwrap();
             let mut values = Vec::new();
@@ -456,6 +480,7 @@ fn extract_value_from_array(array: &ArrayRef, index: usize) -> Result<Option<Val
     }
 }
 
+#[cfg(test)]
 /// 从MapArray提取Value
 fn extract_map_value(array: &ArrayRef, index: usize) -> Result<Option<Value>, Error> {
     let array = array
@@ -483,6 +508,7 @@ fn extract_map_value(array: &ArrayRef, index: usize) -> Result<Option<Value>, Er
     Ok(Some(Value::Map(map)))
 }
 
+#[cfg(test)]
 /// 从各种类型的数组中提取Map键
 fn extract_map_key(array: &ArrayRef, index: usize) -> Result<Option<Value>, Error> {
     if array.is_null(index) {
@@ -503,6 +529,7 @@ fn extract_map_key(array: &ArrayRef, index: usize) -> Result<Option<Value>, Erro
     }
 }
 
+#[cfg(test)]
 /// 从StructArray提取Value
 fn extract_struct_value(array: &ArrayRef, index: usize) -> Result<Option<Value>, Error> {
     let array = array
@@ -580,6 +607,7 @@ pub fn records_to_record_batch(
     RecordBatch::try_new(schema, arrays).map_err(From::from)
 }
 
+#[cfg(test)]
 /// 将Arrow RecordBatch转换为Records
 pub fn record_batch_to_records(batch: &RecordBatch) -> Result<Vec<Record>, Error> {
     let schema = batch.schema();
@@ -608,6 +636,7 @@ pub fn record_batch_to_records(batch: &RecordBatch) -> Result<Vec<Record>, Error
 // 文件操作
 //
 
+#[cfg(test)]
 /// 直接将Records写入Parquet文件
 pub fn write_records_to_parquet(
     records: &[Record],
@@ -631,18 +660,20 @@ pub fn write_records_to_parquet(
 }
 
 /// 用于写入Records到Parquet文件的writer
-pub struct ParquetWriter {
-    writer: parquet::arrow::ArrowWriter<std::fs::File>,
+pub struct ParquetWriter<W: std::io::Write + Send = std::fs::File> {
+    writer: parquet::arrow::ArrowWriter<W>,
     schema: SchemaRef,
     path: String,
 }
 
 impl ParquetWriter {
+    #[cfg(test)]
     /// 创建一个带有默认属性的ParquetWriter
     pub fn new(path: &str, schema: SchemaRef) -> Result<Self, Error> {
         Self::with_properties(path, schema, None)
     }
 
+    #[cfg(test)]
     /// 创建一个带有自定义属性的ParquetWriter
     pub fn with_properties(
         path: &str,
@@ -650,27 +681,34 @@ impl ParquetWriter {
         props: Option<parquet::file::properties::WriterProperties>,
     ) -> Result<Self, Error> {
         let file = std::fs::File::create(path)?;
-        let props =
-            props.unwrap_or_else(|| parquet::file::properties::WriterProperties::builder().build());
-        let writer = parquet::arrow::ArrowWriter::try_new(file, schema.clone(), Some(props))?;
-
-        Ok(Self {
-            writer,
-            schema,
-            path: path.to_string(),
-        })
+        ParquetWriter::from_writer(file, path, schema, props)
     }
 
+    #[cfg(test)]
     /// 从样本记录创建ParquetWriter
     pub fn from_record(path: &str, record: &Record) -> Result<Self, Error> {
         let schema = record_to_schema(record)?;
         Self::new(path, schema)
     }
+}
 
-    /// 写入单条记录到parquet文件
-    pub fn write_record(&mut self, record: &Record) -> Result<(), Error> {
-        let batch = records_to_record_batch(&[record.clone()], self.schema.clone())?;
-        self.writer.write(&batch).map_err(From::from)
+impl<W: std::io::Write + Send> ParquetWriter<W> {
+    /// 基于任意的Write创建ParquetWriter, path仅用于展示
+    pub fn from_writer(
+        sink: W,
+        path: &str,
+        schema: SchemaRef,
+        props: Option<parquet::file::properties::WriterProperties>,
+    ) -> Result<Self, Error> {
+        let props =
+            props.unwrap_or_else(|| parquet::file::properties::WriterProperties::builder().build());
+        let writer = parquet::arrow::ArrowWriter::try_new(sink, schema.clone(), Some(props))?;
+
+        Ok(Self {
+            writer,
+            schema,
+            path: path.to_string(),
+        })
     }
 
     /// 写入多条记录到parquet文件
@@ -682,6 +720,7 @@ impl ParquetWriter {
         self.writer.write(&batch).map_err(From::from)
     }
 
+    #[cfg(test)]
     /// 直接写入RecordBatch到parquet文件
     pub fn write_batch(&mut self, batch: &RecordBatch) -> Result<(), Error> {
         self.writer.write(batch).map_err(From::from)
@@ -693,23 +732,20 @@ impl ParquetWriter {
         Ok(())
     }
 
-    /// 返回writer使用的schema
-    pub fn schema(&self) -> &SchemaRef {
-        &self.schema
-    }
-
     /// 返回写入的文件路径
     pub fn path(&self) -> &str {
         &self.path
     }
 }
 
+#[cfg(test)]
 /// 用于从Parquet文件读取Records的reader
 pub struct ParquetReader {
     path: String,
     batch_size: usize,
 }
 
+#[cfg(test)]
 impl ParquetReader {
     /// 创建一个新的ParquetReader
     pub fn new(path: &str, batch_size: usize) -> Self {
@@ -788,6 +824,20 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_widen_data_type() {
+        use ArrowDataType::*;
+        assert_eq!(widen_data_type(&Int64, &Int64), Int64);
+        assert_eq!(widen_data_type(&Int64, &Float64), Float64);
+        assert_eq!(widen_data_type(&Float64, &Int64), Float64);
+        assert_eq!(widen_data_type(&Boolean, &Int64), Utf8);
+        assert_eq!(widen_data_type(&Utf8, &Float64), Utf8);
+        assert_eq!(
+            primitive_to_data_type(&Primitive::DateTime),
+            value_to_data_type(&Value::DateTime(chrono::Utc::now())).unwrap()
+        );
+    }
+
     #[test]
     fn test_record_to_schema() {
         let context = TracingContext::new_root();
@@ -854,6 +904,41 @@ mod tests {
         assert_eq!(r2.get(&intern("int_field")).unwrap().to_string(), "20");
     }
 
+    #[test]
+    fn test_bytes_conversion() {
+        let mut record = Record::new(TracingContext::new_root());
+        record.set(intern("payload"), Value::Bytes(vec![0, 159, 146, 150]));
+        let records = vec![record, Record::new(TracingContext::new_root())];
+
+        let schema = record_to_schema(&records[0]).unwrap();
+        assert_eq!(schema.field(0).data_type(), &ArrowDataType::Binary);
+        assert_eq!(
+            primitive_to_data_type(&Primitive::HexBytes),
+            ArrowDataType::Binary
+        );
+
+        let batch = records_to_record_batch(&records, schema).unwrap();
+        let converted = record_batch_to_records(&batch).unwrap();
+        assert_eq!(
+            converted[0].get(&intern("payload")),
+            Some(&Value::Bytes(vec![0, 159, 146, 150]))
+        );
+        assert_eq!(converted[1].get(&intern("payload")), None);
+
+        // Widened to a string column, bytes are written in base64
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "payload",
+            ArrowDataType::Utf8,
+            true,
+        )]));
+        let batch = records_to_record_batch(&records, schema).unwrap();
+        let converted = record_batch_to_records(&batch).unwrap();
+        assert_eq!(
+            converted[0].get(&intern("payload")),
+            Some(&Value::from("AJ+Slg=="))
+        );
+    }
+
     #[test]
     fn test_write_and_read_parquet() {
         let dir = tempdir().unwrap();
@@ -1,20 +1,16 @@
 use miette::Diagnostic;
 use reqwest::Client;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::{
-    config::outbound::auth::AuthConfig,
-    core::{
-        pipe::{LABELS_FIELD, METRIC_TYPE_FIELD, NAME_FIELD, TIMESTAMP_FIELD, VALUE_FIELD},
-        types::Record,
-    },
+use crate::core::{
+    pipe::{LABELS_FIELD, METRIC_TYPE_FIELD, NAME_FIELD, TIMESTAMP_FIELD, VALUE_FIELD},
+    types::{Record, Value},
 };
 use std::collections::HashMap;
 
 #[derive(Debug, Error, Diagnostic)]
 pub enum Error {
-    #[error("Invalid record: {0}")]
-    InvalidRecord(String),
     #[error("Empty record")]
     EmptyRecord,
     #[error("Field not found: {0}")]
@@ -23,15 +19,15 @@ pub enum Error {
     Type(#[from] crate::core::types::Error),
     #[error("Failed to compress: {0}")]
     Snap(#[from] snap::Error),
+    #[error("Failed to compress: {0}")]
+    Zstd(std::io::Error),
 }
 
 pub const NAME_FIELD_STR: &str = "name";
 pub const TIMESTAMP_FIELD_STR: &str = "timestamp";
-pub const TYPE_FIELD_STR: &str = "type";
 pub const METRIC_TYPE_FIELD_STR: &str = "metric_type";
 pub const LABELS_FIELD_STR: &str = "labels";
 pub const VALUE_FIELD_STR: &str = "value";
-pub const UNIT_FIELD_STR: &str = "unit";
 
 /// A label.
 ///
@@ -67,11 +63,32 @@ pub struct Sample {
     pub timestamp: i64,
 }
 
+/// The label telling apart the series of a histogram (`le`) or a summary (`quantile`).
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ExtraLabel {
     LessThan(f64),
     Quantile(f64),
 }
 
+impl ExtraLabel {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExtraLabel::LessThan(_) => "le",
+            ExtraLabel::Quantile(_) => "quantile",
+        }
+    }
+
+    /// Formatted the way the Prometheus clients do, e.g. `0.5`, `10` or `+Inf`.
+    pub fn value(&self) -> String {
+        let (ExtraLabel::LessThan(v) | ExtraLabel::Quantile(v)) = *self;
+        if v == f64::INFINITY {
+            "+Inf".to_string()
+        } else {
+            v.to_string()
+        }
+    }
+}
+
 /// A time series.
 ///
 /// .proto:
@@ -100,12 +117,77 @@ impl TimeSeries {
     }
 
     pub fn sort_samples(&mut self) {
-        self.samples.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+        self.samples.sort_by_key(|s| s.timestamp);
     }
+}
 
-    pub fn sorted(mut self) -> Self {
-        self.sort_labels_and_samples();
-        self
+/// The caps on a single remote write request, see [`WriteRequest::encode_split`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestLimits {
+    /// Compressed size of the body
+    pub max_bytes: usize,
+    pub max_samples: usize,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: usize::MAX,
+            max_samples: usize::MAX,
+        }
+    }
+}
+
+/// A part of a [`WriteRequest::encode_split`], with its compressed body.
+#[derive(Debug)]
+pub struct EncodedRequest {
+    pub request: WriteRequest,
+    pub body: Vec<u8>,
+    /// Down to a single sample, still over the size limit
+    pub oversized: bool,
+}
+
+/// How the body of a remote write request is compressed. Configured by its name, the level
+/// of `zstd` comes from `compression_level`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Compression {
+    /// Snappy block format, the only one required by the remote write spec
+    #[default]
+    Snappy,
+    /// Zstandard with the level, smaller than snappy for series with many labels, if the
+    /// endpoint takes it
+    Zstd(i32),
+}
+
+impl Compression {
+    /// The `Content-Encoding` of the body.
+    pub fn content_encoding(&self) -> &'static str {
+        match self {
+            Compression::Snappy => "snappy",
+            Compression::Zstd(_) => "zstd",
+        }
+    }
+}
+
+impl TryFrom<String> for Compression {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        match name.as_str() {
+            "snappy" => Ok(Compression::Snappy),
+            "zstd" => Ok(Compression::Zstd(zstd::DEFAULT_COMPRESSION_LEVEL)),
+            _ => Err(format!(
+                "unknown compression {:?}, expected snappy or zstd",
+                name
+            )),
+        }
+    }
+}
+
+impl From<Compression> for String {
+    fn from(compression: Compression) -> Self {
+        compression.content_encoding().to_string()
     }
 }
 
@@ -118,7 +200,7 @@ impl TimeSeries {
 ///   // Cortex uses this field to determine the source of the write request.
 ///   // We reserve it to avoid any compatibility issues.
 ///   reserved  2;
-
+///
 ///   // Prometheus uses this field to send metadata, but this is
 ///   // omitted from v1 of the spec as it is experimental.
 ///   reserved  3;
@@ -145,40 +227,131 @@ impl WriteRequest {
     /// Encode this write request as a protobuf message.
     ///
     /// NOTE: The API requires snappy compression, not a raw protobuf message.
+    #[cfg(test)]
     pub fn encode_proto3(self) -> Vec<u8> {
         prost::Message::encode_to_vec(&self.sorted())
     }
 
-    pub fn encode_compressed(self) -> Result<Vec<u8>, snap::Error> {
-        snap::raw::Encoder::new().compress_vec(&self.encode_proto3())
+    /// Encode this write request compressed with snappy, in the block format.
+    pub fn encode_snappy(&self) -> Result<Vec<u8>, Error> {
+        Ok(snap::raw::Encoder::new().compress_vec(&prost::Message::encode_to_vec(self))?)
+    }
+
+    /// Encode this write request compressed with zstd, at `level`.
+    pub fn encode_zstd(&self, level: i32) -> Result<Vec<u8>, Error> {
+        zstd::bulk::compress(&prost::Message::encode_to_vec(self), level).map_err(Error::Zstd)
+    }
+
+    /// Encode this write request, the body of a remote write request.
+    pub fn encode_compressed(&self, compression: Compression) -> Result<Vec<u8>, Error> {
+        match compression {
+            Compression::Snappy => self.encode_snappy(),
+            Compression::Zstd(level) => self.encode_zstd(level),
+        }
+    }
+
+    pub fn num_samples(&self) -> usize {
+        self.timeseries.iter().map(|ts| ts.samples.len()).sum()
     }
 
-    pub fn build_request(
+    /// Split into requests whose compressed body stays under `limits.max_bytes`, less a 10%
+    /// margin, and which hold at most `limits.max_samples` samples, each with its body. The
+    /// series are split first, then the samples of a series too large on its own. The earlier
+    /// samples of a split series go into the earlier request, and every request is sorted.
+    ///
+    /// A request of a single sample larger than that is kept as is, marked as oversized.
+    pub fn encode_split(
         self,
+        limits: RequestLimits,
+        compression: Compression,
+    ) -> Result<Vec<EncodedRequest>, Error> {
+        let limit = limits.max_bytes - limits.max_bytes / 10;
+        // Popped from the back, the first half is pushed last
+        let mut pending = vec![self.sorted()];
+        let mut parts = Vec::new();
+
+        while let Some(request) = pending.pop() {
+            // Too many samples, split without encoding
+            let request = match request.num_samples() > limits.max_samples {
+                true => match request.halve() {
+                    Ok((first, second)) => {
+                        pending.push(second);
+                        pending.push(first);
+                        continue;
+                    }
+                    Err(request) => request,
+                },
+                false => request,
+            };
+
+            let body = request.encode_compressed(compression)?;
+            if body.len() <= limit {
+                parts.push(EncodedRequest {
+                    request,
+                    body,
+                    oversized: false,
+                });
+                continue;
+            }
+
+            match request.halve() {
+                Ok((first, second)) => {
+                    pending.push(second);
+                    pending.push(first);
+                }
+                Err(request) => parts.push(EncodedRequest {
+                    request,
+                    body,
+                    oversized: true,
+                }),
+            }
+        }
+
+        Ok(parts)
+    }
+
+    /// The request given back when down to a single sample.
+    fn halve(mut self) -> Result<(Self, Self), Self> {
+        if self.timeseries.len() > 1 {
+            let second = self.timeseries.split_off(self.timeseries.len() / 2);
+            return Ok((self, second.into()));
+        }
+
+        let Some(ts) = self
+            .timeseries
+            .first_mut()
+            .filter(|ts| ts.samples.len() > 1)
+        else {
+            return Err(self);
+        };
+        let second = TimeSeries {
+            labels: ts.labels.clone(),
+            samples: ts.samples.split_off(ts.samples.len() / 2),
+        };
+        Ok((self, vec![second].into()))
+    }
+
+    /// A remote write request of a body from [`WriteRequest::encode_split`], without the
+    /// credentials.
+    pub fn request(
+        body: Vec<u8>,
+        compression: Compression,
         client: &Client,
-        auth: &AuthConfig,
         endpoint: &str,
         useragent: &str,
-    ) -> Result<reqwest::RequestBuilder, Error> {
+    ) -> reqwest::RequestBuilder {
         let url = format!("{}/api/v1/write", endpoint);
         let builder = client
             .post(&url)
             .header(reqwest::header::CONTENT_TYPE, "application/x-protobuf")
-            .header(reqwest::header::CONTENT_ENCODING, "snappy")
+            .header(
+                reqwest::header::CONTENT_ENCODING,
+                compression.content_encoding(),
+            )
             .header("X-Prometheus-Remote-Write-Version", "0.1.0")
             .header(reqwest::header::USER_AGENT, useragent);
 
-        let builder = match auth {
-            AuthConfig::None => builder,
-            AuthConfig::Basic { username, password } => {
-                builder.basic_auth(username, Some(password.as_str()))
-            }
-            AuthConfig::Bearer { token } => builder.bearer_auth(token),
-        };
-
-        let request = builder.body(self.encode_compressed()?);
-
-        Ok(request)
+        builder.body(body)
     }
 }
 
@@ -186,58 +359,74 @@ impl TryFrom<Record> for TimeSeries {
     type Error = Error;
 
     fn try_from(record: Record) -> Result<Self, Self::Error> {
-        let name = record
-            .get(&NAME_FIELD)
-            .ok_or_else(|| Error::FieldNotFound(NAME_FIELD_STR))?;
-
-        let name = Label {
-            name: "__name__".to_string(),
-            value: name.to_string(),
-        };
-
-        let metric_type = record
+        let _metric_type = record
             .get(&METRIC_TYPE_FIELD)
-            .ok_or_else(|| Error::FieldNotFound(METRIC_TYPE_FIELD_STR))?
-            .string()?
-            .as_str();
-
-        let mut labels = record
-            .get(&LABELS_FIELD)
-            .ok_or_else(|| Error::FieldNotFound(LABELS_FIELD_STR))?
-            .map()?
-            .iter()
-            .map(|(key, value)| {
-                let label = Label {
-                    name: key.string()?.to_string(),
-                    value: value.to_string(),
-                };
-                Ok::<_, Error>(label)
-            })
-            .collect::<Result<Vec<_>, _>>()?;
-        labels.push(name);
+            .ok_or(Error::FieldNotFound(METRIC_TYPE_FIELD_STR))?
+            .string()?;
 
         let timestamp = record
             .get(&TIMESTAMP_FIELD)
-            .ok_or_else(|| Error::FieldNotFound(TIMESTAMP_FIELD_STR))?
+            .ok_or(Error::FieldNotFound(TIMESTAMP_FIELD_STR))?
             .datetime()?
             .timestamp_millis();
 
-        let value = record
+        // The samples of remote write are floats, ints are converted exactly up to 2^53
+        let value = match record
             .get(&VALUE_FIELD)
-            .ok_or_else(|| Error::FieldNotFound(VALUE_FIELD_STR))?
-            .float()?
-            .value();
+            .ok_or(Error::FieldNotFound(VALUE_FIELD_STR))?
+        {
+            value @ Value::Int(_) => value.cast_float()?.float()?.value(),
+            value => value.float()?.value(),
+        };
+
+        // The record is consumed, so that its strings are moved into the labels instead of
+        // being copied. Only the interned ones have to be copied.
+        let mut fields = record.take();
+
+        let name = fields
+            .remove(&NAME_FIELD)
+            .ok_or(Error::FieldNotFound(NAME_FIELD_STR))?;
+        let name = Label {
+            // Unavoidable allocation, the protobuf message owns its strings
+            name: "__name__".to_string(),
+            value: label_value(name),
+        };
+
+        let labels = fields
+            .remove(&LABELS_FIELD)
+            .ok_or(Error::FieldNotFound(LABELS_FIELD_STR))?
+            .into_map()?;
+
+        let mut ts_labels = Vec::with_capacity(labels.len() + 1);
+        for (key, value) in labels {
+            ts_labels.push(Label {
+                name: key.into_string()?,
+                value: label_value(value),
+            });
+        }
+        ts_labels.push(name);
 
         let sample = Sample { value, timestamp };
 
         let samples = vec![sample];
-        let timeseries = TimeSeries { labels, samples };
+        let timeseries = TimeSeries {
+            labels: ts_labels,
+            samples,
+        };
 
         Ok(timeseries)
     }
 }
 
-fn combine_timeseries(tss: Vec<TimeSeries>) -> Result<Vec<TimeSeries>, Error> {
+fn label_value(value: Value) -> String {
+    match value {
+        Value::String(s) => s.into_string(),
+        // Formatted into a fresh string, the only allocation for these
+        value => value.to_string(),
+    }
+}
+
+pub fn combine_timeseries(tss: Vec<TimeSeries>) -> Result<Vec<TimeSeries>, Error> {
     if tss.is_empty() {
         return Err(Error::EmptyRecord);
     }
@@ -288,6 +477,7 @@ mod tests {
     use super::*;
     use crate::core::pipe::RECORD_TYPE_TIMESERIES_VALUE;
     use crate::core::types::Value;
+    use crate::utils::alloc::count_allocations;
 
     fn create_test_record() -> Record {
         let mut record = Record::new_root();
@@ -329,20 +519,26 @@ mod tests {
         // Verify samples
         assert_eq!(ts.samples.len(), 1);
         assert_eq!(ts.samples[0].value, 42.0);
+
+        let mut record = create_test_record();
+        record.set(VALUE_FIELD.clone(), Value::from(1i64 << 53));
+        let ts = TimeSeries::try_from(record).unwrap();
+        assert_eq!(ts.samples[0].value, 9007199254740992.0);
     }
 
     #[test]
     fn test_combine_timeseries() {
         // Create two time series with identical labels
-        let mut labels1 = Vec::new();
-        labels1.push(Label {
-            name: "__name__".to_string(),
-            value: "test_metric".to_string(),
-        });
-        labels1.push(Label {
-            name: "env".to_string(),
-            value: "test".to_string(),
-        });
+        let labels1 = vec![
+            Label {
+                name: "__name__".to_string(),
+                value: "test_metric".to_string(),
+            },
+            Label {
+                name: "env".to_string(),
+                value: "test".to_string(),
+            },
+        ];
 
         let labels2 = labels1.clone();
 
@@ -435,9 +631,238 @@ mod tests {
         assert!(!encoded.is_empty());
     }
 
+    fn series(name: &str, timestamps: impl Iterator<Item = i64>) -> TimeSeries {
+        TimeSeries {
+            labels: vec![Label {
+                name: "__name__".to_string(),
+                value: name.to_string(),
+            }],
+            samples: timestamps
+                .map(|timestamp| Sample {
+                    value: (timestamp as f64 * 1.37).sin(),
+                    timestamp,
+                })
+                .collect(),
+        }
+    }
+
+    fn bytes(max_bytes: usize) -> RequestLimits {
+        RequestLimits {
+            max_bytes,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_encode_split() {
+        let tss = (0..50)
+            .map(|i| series(&format!("metric_{}", i), (0..100).rev()))
+            .collect::<Vec<_>>();
+        let parts = WriteRequest::from(tss)
+            .encode_split(bytes(4096), Compression::Snappy)
+            .unwrap();
+        assert!(parts.len() > 1);
+
+        let mut samples = 0;
+        for EncodedRequest {
+            request,
+            body,
+            oversized,
+        } in &parts
+        {
+            assert!(!oversized);
+            assert!(body.len() <= 4096 - 409, "{}", body.len());
+            let decoded = snap::raw::Decoder::new().decompress_vec(body).unwrap();
+            let decoded: WriteRequest = prost::Message::decode(decoded.as_slice()).unwrap();
+            assert_eq!(&decoded, request);
+            for ts in &request.timeseries {
+                assert!(ts
+                    .samples
+                    .windows(2)
+                    .all(|w| w[0].timestamp < w[1].timestamp));
+                samples += ts.samples.len();
+            }
+        }
+        assert_eq!(samples, 50 * 100);
+
+        // A series too large on its own is split by time
+        let parts = WriteRequest::from(vec![series("big", (0..2000).rev())])
+            .encode_split(bytes(4096), Compression::Snappy)
+            .unwrap();
+        assert!(parts.len() > 1);
+        let timestamps = parts
+            .iter()
+            .flat_map(|part| &part.request.timeseries[0].samples)
+            .map(|sample| sample.timestamp)
+            .collect::<Vec<_>>();
+        assert_eq!(timestamps, (0..2000).collect::<Vec<_>>());
+
+        // Nothing left to split
+        let parts = WriteRequest::from(vec![series("one", 0..1)])
+            .encode_split(bytes(10), Compression::Snappy)
+            .unwrap();
+        assert_eq!(parts.len(), 1);
+        assert!(parts[0].oversized);
+
+        // Only the sample too large on its own is marked
+        let mut large = series("large", 0..1);
+        large.labels.push(Label {
+            name: "payload".to_string(),
+            // Hardly compressible
+            value: (0..100u64)
+                .map(|i| format!("{:x}", i.wrapping_mul(0x9E37_79B9_7F4A_7C15)))
+                .collect(),
+        });
+        let parts = WriteRequest::from(vec![large, series("small", 0..1)])
+            .encode_split(bytes(256), Compression::Snappy)
+            .unwrap();
+        assert_eq!(parts.len(), 2);
+        assert!(parts[0].oversized);
+        assert!(!parts[1].oversized);
+
+        let parts = WriteRequest::from(vec![series("all", 0..10)])
+            .encode_split(RequestLimits::default(), Compression::Snappy)
+            .unwrap();
+        assert_eq!(parts.len(), 1);
+    }
+
+    #[test]
+    fn test_encode_compressed() {
+        let request = WriteRequest::from(
+            (0..20)
+                .map(|i| series(&format!("metric_{}", i), 0..50))
+                .collect::<Vec<_>>(),
+        )
+        .sorted();
+        let decode =
+            |raw: Vec<u8>| -> WriteRequest { prost::Message::decode(raw.as_slice()).unwrap() };
+
+        let body = request.encode_compressed(Compression::Snappy).unwrap();
+        let raw = snap::raw::Decoder::new().decompress_vec(&body).unwrap();
+        assert_eq!(decode(raw), request);
+
+        let body = request.encode_compressed(Compression::Zstd(3)).unwrap();
+        let raw = zstd::stream::decode_all(body.as_slice()).unwrap();
+        assert_eq!(decode(raw), request);
+
+        // Split by the size of the zstd body
+        let parts = request
+            .clone()
+            .encode_split(bytes(512), Compression::Zstd(19))
+            .unwrap();
+        assert!(parts.len() > 1);
+        for part in &parts {
+            assert!(part.body.len() <= 512 - 51, "{}", part.body.len());
+            let raw = zstd::stream::decode_all(part.body.as_slice()).unwrap();
+            assert_eq!(decode(raw), part.request);
+        }
+
+        let built = WriteRequest::request(
+            Vec::new(),
+            Compression::Zstd(3),
+            &Client::new(),
+            "http://localhost:9090",
+            "void",
+        )
+        .build()
+        .unwrap();
+        assert_eq!(
+            built
+                .headers()
+                .get(reqwest::header::CONTENT_ENCODING)
+                .unwrap(),
+            "zstd"
+        );
+    }
+
+    #[test]
+    fn test_encode_split_samples() {
+        // A batch as the outbound converts it, 10 samples of 20 series
+        let start = chrono::Utc::now();
+        let records = (0..200)
+            .map(|i| {
+                let mut record = create_test_record();
+                let mut labels = HashMap::new();
+                labels.insert(Value::from("host"), Value::from(format!("host-{}", i % 20)));
+                record.set(LABELS_FIELD.clone(), Value::from(labels));
+                record.set(
+                    TIMESTAMP_FIELD.clone(),
+                    Value::from(start + chrono::Duration::seconds(i / 20)),
+                );
+                record
+            })
+            .collect::<Vec<_>>();
+        let tss = combine_timeseries(transform_timeseries(records).unwrap()).unwrap();
+        assert_eq!(tss.len(), 20);
+
+        let limits = RequestLimits {
+            max_samples: 30,
+            ..Default::default()
+        };
+        let parts = WriteRequest::from(tss.clone())
+            .encode_split(limits, Compression::Snappy)
+            .unwrap();
+        assert!(parts.iter().all(|part| part.request.num_samples() <= 30));
+        assert_eq!(
+            parts
+                .iter()
+                .map(|part| part.request.num_samples())
+                .sum::<usize>(),
+            200
+        );
+
+        // Both caps apply, each request is under the tighter one
+        let limits = RequestLimits {
+            max_bytes: 256,
+            max_samples: 100,
+        };
+        let parts = WriteRequest::from(tss)
+            .encode_split(limits, Compression::Snappy)
+            .unwrap();
+        assert!(parts.len() > 2, "{}", parts.len());
+        for part in &parts {
+            assert!(part.request.num_samples() <= 100);
+            assert!(part.body.len() <= 256 - 25, "{}", part.body.len());
+        }
+    }
+
     #[test]
     fn test_empty_timeseries() {
         let result = combine_timeseries(Vec::new());
         assert!(matches!(result, Err(Error::EmptyRecord)));
     }
+
+    #[test]
+    fn test_try_from_allocations() {
+        // Unique label values are not interned, as usual for the high cardinality ones
+        let records = (0..100)
+            .map(|i| {
+                let mut record = create_test_record();
+                let mut labels = HashMap::new();
+                labels.insert(Value::from("env"), Value::from("test"));
+                labels.insert(Value::from("host"), Value::from(format!("host-{}", i)));
+                labels.insert(Value::from("pid"), Value::from(i as i64));
+                record.set(LABELS_FIELD.clone(), Value::from(labels));
+                record
+            })
+            .collect::<Vec<_>>();
+
+        let (tss, allocations) = count_allocations(|| {
+            records
+                .into_iter()
+                .map(TimeSeries::try_from)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        });
+        assert_eq!(tss.len(), 100);
+        assert!(tss[7].labels.contains(&Label {
+            name: "host".to_string(),
+            value: "host-7".to_string(),
+        }));
+
+        // Per record: the labels vector, the samples vector, `__name__`, the pid label value,
+        // and the interned strings (the name and the label keys).
+        let per_record = allocations / 100;
+        assert!(per_record <= 9, "{} allocations per record", per_record);
+    }
 }
@@ -12,14 +12,19 @@ pub enum Primitive {
     Bool,
     #[serde(rename = "datetime")]
     DateTime,
+    /// Binary payloads, written in base64
+    Bytes,
+    /// Binary payloads, written in hex
+    HexBytes,
 }
 
-pub const NULL_TYPE: &'static str = "Null";
-pub const STRING_TYPE: &'static str = "String";
-pub const INT_TYPE: &'static str = "Int";
-pub const FLOAT_TYPE: &'static str = "Float";
-pub const BOOL_TYPE: &'static str = "Bool";
-pub const DATETIME_TYPE: &'static str = "Datetime";
+pub const NULL_TYPE: &str = "Null";
+pub const STRING_TYPE: &str = "String";
+pub const INT_TYPE: &str = "Int";
+pub const FLOAT_TYPE: &str = "Float";
+pub const BOOL_TYPE: &str = "Bool";
+pub const DATETIME_TYPE: &str = "Datetime";
+pub const BYTES_TYPE: &str = "Bytes";
 
 impl Primitive {
     pub fn as_str(&self) -> &'static str {
@@ -30,6 +35,7 @@ impl Primitive {
             Primitive::Float => FLOAT_TYPE,
             Primitive::Bool => BOOL_TYPE,
             Primitive::DateTime => DATETIME_TYPE,
+            Primitive::Bytes | Primitive::HexBytes => BYTES_TYPE,
         }
     }
 }
@@ -43,6 +49,8 @@ impl Display for Primitive {
             Primitive::Float => write!(f, "float"),
             Primitive::Bool => write!(f, "bool"),
             Primitive::DateTime => write!(f, "datetime"),
+            Primitive::Bytes => write!(f, "bytes"),
+            Primitive::HexBytes => write!(f, "hex_bytes"),
         }
     }
 }
@@ -11,10 +11,14 @@ pub enum Error {
     InvalidArrayFormat(String),
     #[error("Invalid bool format: {0}, expected: true, false, yes, no, on, off, active, inactive, not active")]
     InvalidBoolFormat(String),
+    #[error("Invalid bytes format: {0}, expected: {1}")]
+    InvalidBytesFormat(String, &'static str),
     #[error("Unknown datetime format {0}")]
     UnknownDatetimeFormat(String),
     #[error("Non-unique timestamp zone mapping: {0}")]
     NonUniqueTimestampZoneMapping(i64),
+    #[error("Local time {0} does not exist in timezone {1}")]
+    NonexistentLocalTime(String, chrono_tz::Tz),
     #[error("Invalid value type: {0}")]
     InvalidValueType(String),
     #[error("Unexpected value type, expect {0}, got {1}")]
@@ -27,6 +31,16 @@ pub enum Error {
     IndexOutOfBounds(usize, usize),
     #[error("Invalid slice range: start {0}, end {1}, len {2}")]
     InvalidSliceRange(usize, usize, usize),
+    #[error("Unit mismatch: {} and {}", unit_name(.0), unit_name(.1))]
+    UnitMismatch(Option<String>, Option<String>),
+    #[error("Overflow: {0}")]
+    Overflow(String),
+    #[error("Division by zero: {0} / 0")]
+    DivisionByZero(String),
+}
+
+fn unit_name(unit: &Option<String>) -> &str {
+    unit.as_deref().unwrap_or("no unit")
 }
 
 pub type Result<T> = miette::Result<T, Error>;
@@ -1,12 +1,17 @@
+mod arith;
 pub mod conv;
 mod data_type;
 mod error;
 mod record;
 mod string;
+mod unit;
 mod value;
 
 pub use data_type::Primitive;
 pub use error::{Error, Result};
 pub use record::{Attribute, Record, SymbolMap};
-pub use string::{intern, num_interned_strings, resolve, Symbol};
-pub use value::{parse_value, Value, ValueType};
+pub use string::{
+    intern, interner_stats, interner_top_counted, num_interned_strings, InternerStats, Symbol,
+};
+pub use unit::Unit;
+pub use value::{parse_primitive_in, parse_value, parse_value_in, Value, ValueType};
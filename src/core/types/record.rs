@@ -1,9 +1,9 @@
 use std::{collections::BTreeMap, fmt::Display, sync::Arc};
 
 use crate::{
-    config::global::use_time_tracing,
-    core::{tag::TagId, types::resolve},
-    utils::tracing::{Direction, TracingContext},
+    config::{global::use_time_tracing, pipe::Priority},
+    core::tag::TagId,
+    utils::tracing::{Direction, TracingContext, GLOBAL_TRACING},
 };
 
 use super::{Symbol, Value};
@@ -13,6 +13,12 @@ pub enum Attribute {
     Id,
     Inbound,
     Type,
+    // Set on the records sent to the dead letter channel
+    Error,
+    FailedBy,
+    FailedAt,
+    // How much the record matters to the outbounds shedding load, see `Priority`
+    Priority,
 }
 
 impl Display for Attribute {
@@ -21,6 +27,26 @@ impl Display for Attribute {
             Attribute::Inbound => write!(f, "__inbound__"),
             Attribute::Type => write!(f, "__type__"),
             Attribute::Id => write!(f, "__id__"),
+            Attribute::Error => write!(f, "__error__"),
+            Attribute::FailedBy => write!(f, "__failed_by__"),
+            Attribute::FailedAt => write!(f, "__failed_at__"),
+            Attribute::Priority => write!(f, "__priority__"),
+        }
+    }
+}
+
+impl Attribute {
+    /// The attribute written as `name`, e.g. `__type__`.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "__inbound__" => Some(Attribute::Inbound),
+            "__type__" => Some(Attribute::Type),
+            "__id__" => Some(Attribute::Id),
+            "__error__" => Some(Attribute::Error),
+            "__failed_by__" => Some(Attribute::FailedBy),
+            "__failed_at__" => Some(Attribute::FailedAt),
+            "__priority__" => Some(Attribute::Priority),
+            _ => None,
         }
     }
 }
@@ -45,6 +71,7 @@ impl Record {
         }
     }
 
+    #[cfg(test)]
     pub fn new_root() -> Self {
         Self {
             values: SymbolMap::new(),
@@ -53,6 +80,7 @@ impl Record {
         }
     }
 
+    #[cfg(test)]
     pub fn new(ctx: Arc<TracingContext>) -> Self {
         let ctx = TracingContext::inherit(ctx);
         Self {
@@ -92,6 +120,40 @@ impl Record {
         self.values.get_mut(key)
     }
 
+    pub fn remove(&mut self, key: &Symbol) -> Option<Value> {
+        self.values.remove(key)
+    }
+
+    /// A record derived from this one with only the named fields, the attributes and the
+    /// tracing context carry over.
+    pub fn project(&self, fields: &[Symbol]) -> Record {
+        let values = fields
+            .iter()
+            .filter_map(|field| {
+                let (key, value) = self.values.get_key_value(field)?;
+                Some((copy_key(key), value.clone()))
+            })
+            .collect();
+        let mut record = Record::new_with_attrs(self.attributes.clone(), self.tracing_ctx.clone());
+        record.values = values;
+        record
+    }
+
+    /// Copy the fields of `other` into this record, replacing those it already holds only
+    /// with `overwrite`. The attributes of this record are left as they are.
+    pub fn merge_from(&mut self, other: &Record, overwrite: bool) {
+        for (key, value) in &other.values {
+            if overwrite || !self.values.contains_key(key) {
+                self.values.insert(copy_key(key), value.clone());
+            }
+        }
+    }
+
+    /// Remove every field but the named ones.
+    pub fn retain_fields(&mut self, fields: &[Symbol]) {
+        self.values.retain(|key, _| fields.contains(key));
+    }
+
     pub fn set_attribute_overwrite(&mut self, key: Attribute, value: Value, overwrite: bool) {
         if overwrite {
             self.attributes.insert(key, value);
@@ -108,10 +170,6 @@ impl Record {
         self.attributes.get(key)
     }
 
-    pub fn get_attribute_mut(&mut self, key: &Attribute) -> Option<&mut Value> {
-        self.attributes.get_mut(key)
-    }
-
     pub fn set_type(&mut self, value: Value) {
         self.set_attribute(Attribute::Type, value);
     }
@@ -120,6 +178,18 @@ impl Record {
         self.get_attribute(&Attribute::Type)
     }
 
+    pub fn set_priority(&mut self, priority: Priority) {
+        self.set_attribute_overwrite(Attribute::Priority, Value::from(priority.as_str()), true);
+    }
+
+    /// `normal` unless a pipe set another one.
+    pub fn priority(&self) -> Priority {
+        match self.get_attribute(&Attribute::Priority) {
+            Some(Value::String(s)) => Priority::parse(s.as_str()).unwrap_or_default(),
+            _ => Priority::Normal,
+        }
+    }
+
     pub fn take(self) -> SymbolMap {
         self.values
     }
@@ -132,17 +202,62 @@ impl Record {
         &self.tracing_ctx
     }
 
+    /// Rough estimation of the payload size of this record, see [`Value::estimated_bytes`].
+    pub fn estimated_bytes(&self) -> usize {
+        let values = self
+            .values
+            .iter()
+            .map(|(k, v)| k.as_str().len() + v.estimated_bytes())
+            .sum::<usize>();
+        let attributes = self
+            .attributes
+            .values()
+            .map(|v| v.estimated_bytes())
+            .sum::<usize>();
+
+        values + attributes
+    }
+
+    /// Truncate every map or array value holding more than `max_entries` entries,
+    /// see [`Value::truncate_entries`]. Returns the number of top-level fields affected.
+    pub fn truncate_entries(&mut self, max_entries: usize) -> usize {
+        self.values
+            .values_mut()
+            .filter_map(|v| v.truncate_entries(max_entries).then_some(()))
+            .count()
+    }
+
     pub fn mark_timestamp(&self, tag: &TagId, direction: Direction) {
         if use_time_tracing() {
             self.tracing_ctx.add_timepoint(tag, direction);
         }
     }
 
-    pub fn mark_record_release(&self) {
+    /// Stamp the time the inbound parsed the record, where its first stage starts, see
+    /// [`Record::mark_record_release`].
+    pub fn mark_received(&self, inbound: &TagId) {
         if use_time_tracing() {
-            self.tracing_ctx.record();
+            self.tracing_ctx.mark_received();
+            self.tracing_ctx.add_timepoint(inbound, Direction::Incoming);
         }
     }
+
+    /// Called by an outbound once the record is written, records the time it spent in each
+    /// stage and since it was received.
+    pub fn mark_record_release(&self, outbound: &TagId) {
+        if use_time_tracing() {
+            self.tracing_ctx.release(outbound, &GLOBAL_TRACING);
+        }
+    }
+}
+
+/// The key copied as it is: a string not interned yet is not counted once more, as cloning
+/// the symbol would.
+fn copy_key(key: &Symbol) -> Symbol {
+    match key {
+        Symbol::Interned(spur) => Symbol::Interned(*spur),
+        Symbol::String(s) => Symbol::String(s.clone()),
+    }
 }
 
 // impl From<HashMap<Symbol, Value>> for Record {
@@ -179,13 +294,11 @@ impl std::ops::Deref for Record {
 
 impl Display for Record {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut keys = self.values.keys().cloned().collect::<Vec<_>>();
-        keys.sort_by(|a, b| resolve(a).cmp(&resolve(b)));
-
-        let fields = keys.into_iter().map(|key| {
-            let value = self.values.get(&key).unwrap();
-            format!("\"{}\": {}", resolve(&key), value)
-        });
+        // The values are kept sorted by key
+        let fields = self
+            .values
+            .iter()
+            .map(|(key, value)| format!("\"{}\": {}", key.as_str(), value));
 
         let attrs = self.attributes.keys().cloned().collect::<Vec<_>>();
         let attrs = attrs.into_iter().map(|key| {
@@ -307,9 +420,94 @@ mod tests {
         assert!(displayed.contains("\"__type__\": Person"));
     }
 
+    #[test]
+    fn test_estimated_bytes_and_truncate() {
+        let mut record = Record::empty();
+        let values = (0..8).map(|i| Value::from(i as i64)).collect::<Vec<_>>();
+        record.set(Symbol::from("arr"), Value::Array(values));
+        record.set(Symbol::from("name"), Value::from("abc"));
+
+        // "arr" + 8 ints, "name" + "abc"
+        assert_eq!(record.estimated_bytes(), 3 + 8 * 8 + 4 + 3);
+
+        assert_eq!(record.truncate_entries(4), 1);
+        assert_eq!(record[&Symbol::from("arr")].array().unwrap().len(), 5);
+        assert_eq!(record.truncate_entries(4), 0);
+    }
+
+    #[test]
+    fn test_priority() {
+        let mut record = Record::empty();
+        assert_eq!(record.priority(), Priority::Normal);
+
+        record.set_priority(Priority::Low);
+        record.set_priority(Priority::High);
+        assert_eq!(record.priority(), Priority::High);
+        assert_eq!(
+            record.get_attribute(&Attribute::Priority),
+            Some(&Value::from("high"))
+        );
+
+        record.set_attribute_overwrite(Attribute::Priority, Value::from("urgent"), true);
+        assert_eq!(record.priority(), Priority::Normal);
+    }
+
+    fn fields(record: &Record) -> Vec<String> {
+        record.keys().map(|key| key.to_string()).collect()
+    }
+
+    #[test]
+    fn test_project() {
+        let mut record = Record::empty();
+        record.set(Symbol::from("host"), Value::from("a"));
+        record.set(Symbol::from("value"), Value::from(1i64));
+        record.set(Symbol::from("extra"), Value::from(true));
+        record.set_type(Value::from("metric"));
+        record.set_priority(Priority::High);
+
+        let projected = record.project(&[Symbol::from("value"), Symbol::from("missing")]);
+        assert_eq!(fields(&projected), vec!["value"]);
+        assert_eq!(projected.attributes(), record.attributes());
+        assert_eq!(projected.priority(), Priority::High);
+        // Derived from the record, the time points of the record stay reachable
+        record.ctx().mark_received();
+        assert!(projected.ctx().received().is_some());
+        assert_eq!(projected.ctx().received(), record.ctx().received());
+
+        record.retain_fields(&[Symbol::from("host"), Symbol::from("extra")]);
+        assert_eq!(fields(&record), vec!["extra", "host"]);
+        assert_eq!(record.get_type(), Some(&Value::from("metric")));
+    }
+
+    #[test]
+    fn test_merge_from() {
+        let mut record = Record::empty();
+        record.set(Symbol::from("host"), Value::from("a"));
+        record.set_type(Value::from("metric"));
+
+        let mut other = Record::empty();
+        other.set(Symbol::from("host"), Value::from("b"));
+        other.set(Symbol::from("region"), Value::from("eu"));
+        other.set_type(Value::from("other"));
+
+        record.merge_from(&other, false);
+        assert_eq!(record[&Symbol::from("host")], Value::from("a"));
+        assert_eq!(record[&Symbol::from("region")], Value::from("eu"));
+
+        record.merge_from(&other, true);
+        assert_eq!(record[&Symbol::from("host")], Value::from("b"));
+        // Only the fields are merged
+        assert_eq!(record.get_type(), Some(&Value::from("metric")));
+        assert_eq!(record.attributes().len(), 1);
+    }
+
     #[test]
     fn test_attribute_display() {
         assert_eq!(Attribute::Type.to_string(), "__type__");
         assert_eq!(Attribute::Inbound.to_string(), "__inbound__");
+        for attribute in [Attribute::Type, Attribute::FailedBy, Attribute::Priority] {
+            assert_eq!(Attribute::parse(&attribute.to_string()), Some(attribute));
+        }
+        assert_eq!(Attribute::parse("type"), None);
     }
 }
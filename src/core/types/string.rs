@@ -4,28 +4,94 @@ use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize, Serializer};
 use std::{
     borrow::Cow,
-    sync::atomic::{AtomicUsize, Ordering},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
 };
 
 pub const INTERN_THRESHOLD: usize = 8;
 
+// The counter is pruned once it holds this many strings
+const COUNTER_CAPACITY: usize = 1 << 16;
+
+/// Counts the strings not interned yet. An interned string leaves the counter, so it only
+/// grows with the strings seen less than [`INTERN_THRESHOLD`] times.
 struct Counter {
     map: DashMap<String, AtomicUsize>,
+    capacity: usize,
+    pruning: AtomicBool,
 }
 
 impl Counter {
-    fn new() -> Self {
+    fn with_capacity(capacity: usize) -> Self {
         Self {
             map: DashMap::new(),
+            capacity,
+            pruning: AtomicBool::new(false),
         }
     }
 
     fn increment<T: AsRef<str>>(&self, s: T) -> usize {
-        let s = s.as_ref().to_string();
-        let entry = self.map.entry(s).or_insert_with(|| AtomicUsize::new(0));
-        entry.fetch_add(1, Ordering::SeqCst) + 1
+        let s = s.as_ref();
+        // Only allocate the key the first time the string is seen
+        if let Some(count) = self.map.get(s) {
+            return count.fetch_add(1, Ordering::SeqCst) + 1;
+        }
+
+        let count = self
+            .map
+            .entry(s.to_string())
+            .or_insert_with(|| AtomicUsize::new(0))
+            .fetch_add(1, Ordering::SeqCst)
+            + 1;
+        // The entry must be released first, pruning locks every shard
+        if self.map.len() > self.capacity {
+            self.prune();
+        }
+        count
+    }
+
+    /// Halve the counts, forgetting the strings seen once since the last pruning. A string
+    /// seen often keeps most of its count, e.g. a label value of every other record.
+    fn prune(&self) {
+        if self.pruning.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        self.map.retain(|_, count| {
+            let halved = *count.get_mut() / 2;
+            *count.get_mut() = halved;
+            halved > 0
+        });
+        self.pruning.store(false, Ordering::SeqCst);
+    }
+
+    fn remove(&self, s: &str) {
+        self.map.remove(s);
+    }
+
+    fn len(&self) -> usize {
+        self.map.len()
     }
 
+    /// The `n` strings counted the most, most first. A full scan, fine for a periodic report.
+    fn top(&self, n: usize) -> Vec<(String, usize)> {
+        let mut counts = self
+            .map
+            .iter()
+            .map(|count| (count.key().clone(), count.load(Ordering::SeqCst)))
+            .collect::<Vec<_>>();
+        counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then_with(|| a.cmp(b)));
+        counts.truncate(n);
+        counts
+    }
+
+    fn bytes(&self) -> usize {
+        let entry = std::mem::size_of::<String>() + std::mem::size_of::<AtomicUsize>();
+        self.map
+            .iter()
+            .map(|count| entry + count.key().capacity())
+            .sum()
+    }
+
+    #[cfg(test)]
     fn get_count<T: AsRef<str>>(&self, s: T) -> usize {
         self.map
             .get(s.as_ref())
@@ -34,7 +100,20 @@ impl Counter {
     }
 }
 
-pub struct Interner(ThreadedRodeo<Spur>);
+pub struct Interner {
+    rodeo: ThreadedRodeo<Spur>,
+    counter: Counter,
+}
+
+/// Memory held by the interner, see [`interner_stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InternerStats {
+    // Distinct strings seen, not interned yet
+    pub counter_entries: usize,
+    pub interned_strings: usize,
+    // Approximate size of both, in bytes
+    pub approx_bytes: usize,
+}
 
 #[derive(Debug)]
 pub enum Symbol {
@@ -50,23 +129,42 @@ impl Symbol {
         INTERNER.get_or_intern(s)
     }
 
+    /// Same as [`Symbol::new`] without counting the string, for the values known to be of
+    /// high cardinality (e.g. a number held as a string), which would only fill the counter.
+    /// A string already interned is still given its key. Cloning the symbol counts it.
+    pub fn new_no_count<T>(s: T) -> Self
+    where
+        T: AsRef<str>,
+    {
+        INTERNER.get_or_string(s.as_ref())
+    }
+
     pub fn as_str(&self) -> &str {
         match self {
-            Symbol::Interned(spur) => INTERNER.0.resolve(spur),
+            Symbol::Interned(spur) => INTERNER.rodeo.resolve(spur),
             Symbol::String(s) => s.as_str(),
         }
     }
+
+    /// Turn the symbol into an owned string, the string variant is moved out without copying.
+    pub fn into_string(self) -> String {
+        match self {
+            // Unavoidable copy, the interner owns the bytes
+            Symbol::Interned(spur) => INTERNER.rodeo.resolve(&spur).to_owned(),
+            Symbol::String(s) => s,
+        }
+    }
 }
 
 impl PartialOrd for Symbol {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.as_str().partial_cmp(other.as_str())
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for Symbol {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap()
+        self.as_str().cmp(other.as_str())
     }
 }
 
@@ -101,12 +199,16 @@ impl std::hash::Hash for Symbol {
     }
 }
 
-static INTERNER_COUNTER: Lazy<Counter> = Lazy::new(|| Counter::new());
-pub static INTERNER: Lazy<Interner> = Lazy::new(|| Interner::new());
+pub static INTERNER: Lazy<Interner> = Lazy::new(|| Interner::with_capacity(COUNTER_CAPACITY));
 
 impl Interner {
-    fn new() -> Self {
-        Self(ThreadedRodeo::new())
+    /// The symbols of an interner other than [`INTERNER`] are only good for comparing with
+    /// each other, they cannot be resolved.
+    fn with_capacity(counter_capacity: usize) -> Self {
+        Self {
+            rodeo: ThreadedRodeo::new(),
+            counter: Counter::with_capacity(counter_capacity),
+        }
     }
 
     pub fn get_or_intern<T>(&self, s: T) -> Symbol
@@ -114,33 +216,72 @@ impl Interner {
         T: AsRef<str>,
     {
         let s_ref = s.as_ref();
-        let count = INTERNER_COUNTER.increment(s_ref);
+        match self.get_or_count(s_ref) {
+            Some(spur) => Symbol::Interned(spur),
+            None => Symbol::String(s_ref.to_string()),
+        }
+    }
+
+    /// The key of `s` if it is already interned, a plain string otherwise.
+    fn get_or_string(&self, s: &str) -> Symbol {
+        match self.rodeo.get(s) {
+            Some(spur) => Symbol::Interned(spur),
+            None => Symbol::String(s.to_string()),
+        }
+    }
+
+    /// The key of `s` once it has been seen [`INTERN_THRESHOLD`] times.
+    fn get_or_count(&self, s: &str) -> Option<Spur> {
+        if let Some(spur) = self.rodeo.get(s) {
+            return Some(spur);
+        }
 
-        if count >= INTERN_THRESHOLD {
-            let symbol = self.0.get_or_intern(s_ref);
-            Symbol::Interned(symbol)
+        if self.counter.increment(s) >= INTERN_THRESHOLD {
+            Some(self.intern(s))
         } else {
-            Symbol::String(s_ref.to_string())
+            None
         }
     }
 
+    fn intern(&self, s: &str) -> Spur {
+        if let Some(spur) = self.rodeo.get(s) {
+            return spur;
+        }
+
+        let spur = self.rodeo.get_or_intern(s);
+        self.counter.remove(s);
+        spur
+    }
+
     pub fn resolve(&self, symbol: &Symbol) -> Cow<'_, str> {
         match symbol {
-            Symbol::Interned(spur) => Cow::Borrowed(self.0.resolve(spur)),
+            Symbol::Interned(spur) => Cow::Borrowed(self.rodeo.resolve(spur)),
             Symbol::String(s) => Cow::Owned(s.clone()),
         }
     }
 
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.rodeo.len()
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+    pub fn stats(&self) -> InternerStats {
+        InternerStats {
+            counter_entries: self.counter.len(),
+            interned_strings: self.rodeo.len(),
+            approx_bytes: self.counter.bytes() + self.rodeo.current_memory_usage(),
+        }
+    }
+
+    /// The `n` strings seen the most among those not interned yet, with their counts. Those
+    /// about to be interned, or churning through the counter without ever getting there.
+    pub fn top_counted(&self, n: usize) -> Vec<(String, usize)> {
+        self.counter.top(n)
     }
 
-    fn inner(&self) -> &ThreadedRodeo<Spur> {
-        &self.0
+    /// Forget the counts, so that a test does not depend on the strings seen by the others.
+    #[cfg(test)]
+    pub fn reset(&self) {
+        self.counter.map.clear();
     }
 }
 
@@ -149,10 +290,7 @@ impl Symbol {
     where
         T: AsRef<str>,
     {
-        let s = str.as_ref();
-        let _ = INTERNER_COUNTER.increment(s);
-        let supr = INTERNER.inner().get_or_intern(s);
-        Symbol::Interned(supr)
+        Symbol::Interned(INTERNER.intern(str.as_ref()))
     }
 
     pub fn is_empty(&self) -> bool {
@@ -168,9 +306,7 @@ impl Symbol {
 
     pub fn force_intern(&mut self) {
         if let Symbol::String(s) = self {
-            let _ = INTERNER_COUNTER.increment(&s);
-            let spur = INTERNER.inner().get_or_intern(s);
-            *self = Symbol::Interned(spur);
+            *self = Symbol::Interned(INTERNER.intern(s));
         }
     }
 }
@@ -179,15 +315,7 @@ impl Clone for Symbol {
     fn clone(&self) -> Self {
         match self {
             Symbol::Interned(spur) => Symbol::Interned(*spur),
-            Symbol::String(s) => {
-                let count = INTERNER_COUNTER.increment(&s);
-                if count >= INTERN_THRESHOLD {
-                    let spur = INTERNER.inner().get_or_intern(s);
-                    Symbol::Interned(spur)
-                } else {
-                    Symbol::String(s.clone())
-                }
-            }
+            Symbol::String(s) => INTERNER.get_or_intern(s),
         }
     }
 }
@@ -225,7 +353,7 @@ impl From<String> for Symbol {
 impl AsRef<str> for Symbol {
     fn as_ref(&self) -> &str {
         match self {
-            Symbol::Interned(spur) => INTERNER.0.resolve(spur),
+            Symbol::Interned(spur) => INTERNER.rodeo.resolve(spur),
             Symbol::String(s) => s.as_str(),
         }
     }
@@ -238,7 +366,7 @@ impl Serialize for Symbol {
     {
         match self {
             Symbol::Interned(spur) => {
-                let str = INTERNER.0.resolve(spur);
+                let str = INTERNER.rodeo.resolve(spur);
                 serializer.serialize_str(str)
             }
             Symbol::String(s) => serializer.serialize_str(s),
@@ -267,8 +395,18 @@ where
     INTERNER.get_or_intern(s)
 }
 
+/// Number of strings held by the interner, interned or still counted.
 pub fn num_interned_strings() -> usize {
-    INTERNER.len()
+    INTERNER.len() + INTERNER.counter.len()
+}
+
+pub fn interner_stats() -> InternerStats {
+    INTERNER.stats()
+}
+
+/// See [`Interner::top_counted`].
+pub fn interner_top_counted(n: usize) -> Vec<(String, usize)> {
+    INTERNER.top_counted(n)
 }
 
 #[cfg(test)]
@@ -301,17 +439,130 @@ mod tests {
 
     #[test]
     fn test_interning_threshold() {
+        let interner = Interner::with_capacity(COUNTER_CAPACITY);
         let test_string = "threshold_test";
 
         let mut symbols = Vec::new();
         for _ in 0..INTERN_THRESHOLD - 1 {
-            symbols.push(Symbol::new(test_string));
+            symbols.push(interner.get_or_intern(test_string));
         }
 
         assert!(!symbols.last().unwrap().is_interned());
+        assert_eq!(
+            interner.counter.get_count(test_string),
+            INTERN_THRESHOLD - 1
+        );
 
-        let s_threshold = Symbol::new(test_string);
+        let s_threshold = interner.get_or_intern(test_string);
         assert!(s_threshold.is_interned());
+        // Interned strings are no longer counted
+        assert_eq!(interner.counter.get_count(test_string), 0);
+        assert!(interner.get_or_intern(test_string).is_interned());
+        assert_eq!(interner.stats().counter_entries, 0);
+    }
+
+    #[test]
+    fn test_reset() {
+        let interner = Interner::with_capacity(COUNTER_CAPACITY);
+        for _ in 0..INTERN_THRESHOLD - 1 {
+            interner.get_or_intern("reset_test");
+        }
+        interner.reset();
+        assert!(!interner.get_or_intern("reset_test").is_interned());
+        assert_eq!(interner.counter.get_count("reset_test"), 1);
+    }
+
+    #[test]
+    fn test_counter_pruning() {
+        let interner = Interner::with_capacity(4);
+        for _ in 0..4 {
+            interner.get_or_intern("frequent");
+        }
+        interner.get_or_intern("once_a");
+        interner.get_or_intern("once_b");
+        interner.get_or_intern("once_c");
+        assert_eq!(interner.stats().counter_entries, 4);
+
+        // Going past the capacity forgets the strings seen once
+        interner.get_or_intern("once_d");
+        assert_eq!(interner.stats().counter_entries, 1);
+        assert_eq!(interner.counter.get_count("frequent"), 2);
+
+        for _ in 0..INTERN_THRESHOLD - 2 {
+            interner.get_or_intern("frequent");
+        }
+        assert!(interner.get_or_intern("frequent").is_interned());
+    }
+
+    #[test]
+    fn test_interner_stats() {
+        let interner = Interner::with_capacity(COUNTER_CAPACITY);
+        assert_eq!(interner.stats().counter_entries, 0);
+        assert_eq!(interner.stats().interned_strings, 0);
+
+        for i in 0..10 {
+            let s = format!("stats_{}", i);
+            if i < 3 {
+                for _ in 0..INTERN_THRESHOLD {
+                    interner.get_or_intern(&s);
+                }
+            } else {
+                interner.get_or_intern(&s);
+            }
+        }
+
+        let stats = interner.stats();
+        assert_eq!(stats.counter_entries, 7);
+        assert_eq!(stats.interned_strings, 3);
+        assert!(stats.approx_bytes >= 7 * "stats_0".len(), "{:?}", stats);
+    }
+
+    #[test]
+    fn test_top_counted() {
+        let interner = std::sync::Arc::new(Interner::with_capacity(COUNTER_CAPACITY));
+        // Thread i counts `top_i` i + 1 times, every thread counts `shared` once
+        let threads = (0..4)
+            .map(|i| {
+                let interner = interner.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..=i {
+                        interner.get_or_intern(format!("top_{}", i));
+                    }
+                    interner.get_or_intern("top_shared");
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(
+            interner.top_counted(3),
+            vec![
+                ("top_3".to_string(), 4),
+                ("top_shared".to_string(), 4),
+                ("top_2".to_string(), 3)
+            ]
+        );
+        assert_eq!(interner.top_counted(10).len(), 5);
+
+        // Interned, it leaves the counter
+        for _ in 0..INTERN_THRESHOLD {
+            interner.get_or_intern("top_shared");
+        }
+        assert_eq!(interner.top_counted(1), vec![("top_3".to_string(), 4)]);
+    }
+
+    #[test]
+    fn test_new_no_count() {
+        let s = Symbol::new_no_count("no_count_test");
+        assert!(!s.is_interned());
+        assert_eq!(s.as_str(), "no_count_test");
+        assert_eq!(INTERNER.counter.get_count("no_count_test"), 0);
+
+        let interned = Symbol::intern("no_count_interned");
+        assert_eq!(Symbol::new_no_count("no_count_interned"), interned);
+        assert!(Symbol::new_no_count("no_count_interned").is_interned());
     }
 
     #[test]
@@ -398,4 +649,28 @@ mod tests {
         assert!(s3 == s1);
         assert!(s4 == s2);
     }
+
+    #[test]
+    fn test_into_string() {
+        let owned = Symbol::String("into_string_test".to_string());
+        let ((), allocations) = crate::utils::alloc::count_allocations(|| {
+            assert_eq!(owned.into_string(), "into_string_test");
+        });
+        assert_eq!(allocations, 0);
+
+        assert_eq!(
+            Symbol::intern("into_string_test").into_string(),
+            "into_string_test"
+        );
+    }
+
+    #[test]
+    fn test_clone_does_not_copy_counter_key() {
+        let s = Symbol::new("clone_allocation_test");
+        let _first = s.clone();
+
+        // only the string itself is copied, the counter key already exists
+        let (_second, allocations) = crate::utils::alloc::count_allocations(|| s.clone());
+        assert_eq!(allocations, 1);
+    }
 }
@@ -0,0 +1,180 @@
+use super::{Error, Result};
+
+/// What a unit measures, only the units of a same dimension convert into each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dimension {
+    Time,
+    Bytes,
+    Percent,
+}
+
+/// A known unit, see [`Unit::parse`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Unit {
+    name: &'static str,
+    dimension: Dimension,
+    // Multiple of the smallest unit of the dimension, integral so that the ratios between
+    // the units are exact
+    scale: f64,
+}
+
+// The canonical name first, then the other spellings
+const UNITS: &[(&str, Dimension, f64, &[&str])] = &[
+    (
+        "ns",
+        Dimension::Time,
+        1.0,
+        &["nanosecond", "nanoseconds", "nanos"],
+    ),
+    (
+        "us",
+        Dimension::Time,
+        1e3,
+        &["µs", "μs", "microsecond", "microseconds", "micros"],
+    ),
+    (
+        "ms",
+        Dimension::Time,
+        1e6,
+        &["millisecond", "milliseconds", "millis", "msec"],
+    ),
+    (
+        "s",
+        Dimension::Time,
+        1e9,
+        &["sec", "secs", "second", "seconds"],
+    ),
+    ("min", Dimension::Time, 60e9, &["minute", "minutes"]),
+    ("h", Dimension::Time, 3600e9, &["hour", "hours"]),
+    ("bytes", Dimension::Bytes, 1.0, &["B", "byte"]),
+    (
+        "KB",
+        Dimension::Bytes,
+        1e3,
+        &["kB", "kilobyte", "kilobytes"],
+    ),
+    ("KiB", Dimension::Bytes, 1024.0, &["kibibyte", "kibibytes"]),
+    ("MB", Dimension::Bytes, 1e6, &["megabyte", "megabytes"]),
+    (
+        "MiB",
+        Dimension::Bytes,
+        1048576.0,
+        &["mebibyte", "mebibytes"],
+    ),
+    ("GB", Dimension::Bytes, 1e9, &["gigabyte", "gigabytes"]),
+    (
+        "GiB",
+        Dimension::Bytes,
+        1073741824.0,
+        &["gibibyte", "gibibytes"],
+    ),
+    ("TB", Dimension::Bytes, 1e12, &["terabyte", "terabytes"]),
+    (
+        "TiB",
+        Dimension::Bytes,
+        1099511627776.0,
+        &["tebibyte", "tebibytes"],
+    ),
+    ("percent", Dimension::Percent, 1.0, &["%", "pct"]),
+    ("ratio", Dimension::Percent, 100.0, &[]),
+];
+
+impl Unit {
+    /// Look a unit up by any of its spellings, e.g. `ms`, `milliseconds` or `Millis`. The
+    /// short symbols are case sensitive, `MB` is not `mb`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let found = UNITS
+            .iter()
+            .find(|(name, _, _, aliases)| *name == s || aliases.contains(&s))
+            .or_else(|| {
+                // Only the words, the symbols are too short to be told apart without the case
+                UNITS.iter().find(|(_, _, _, aliases)| {
+                    aliases
+                        .iter()
+                        .any(|alias| alias.len() > 3 && alias.eq_ignore_ascii_case(s))
+                })
+            });
+
+        found.map(|&(name, dimension, scale, _)| Unit {
+            name,
+            dimension,
+            scale,
+        })
+    }
+
+    /// The canonical name, the one written in the `unit` label.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Convert `value` from this unit into `to`. Fails across dimensions.
+    pub fn convert(&self, value: f64, to: Unit) -> Result<f64> {
+        if self.dimension != to.dimension {
+            return Err(Error::UnitMismatch(
+                Some(self.name.to_string()),
+                Some(to.name.to_string()),
+            ));
+        }
+
+        // A single multiplication or division by an exact ratio when there is one, so
+        // that 1500ms is exactly 1.5s
+        let value = if self.scale % to.scale == 0.0 {
+            value * (self.scale / to.scale)
+        } else if to.scale % self.scale == 0.0 {
+            value / (to.scale / self.scale)
+        } else {
+            value * self.scale / to.scale
+        };
+
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(value: f64, from: &str, to: &str) -> f64 {
+        Unit::parse(from)
+            .unwrap()
+            .convert(value, Unit::parse(to).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Unit::parse("ms").unwrap().name(), "ms");
+        assert_eq!(Unit::parse("milliseconds").unwrap().name(), "ms");
+        assert_eq!(Unit::parse("Milliseconds").unwrap().name(), "ms");
+        assert_eq!(Unit::parse(" µs ").unwrap().name(), "us");
+        assert_eq!(Unit::parse("B").unwrap().name(), "bytes");
+        assert_eq!(Unit::parse("%").unwrap().name(), "percent");
+        assert_eq!(Unit::parse("KiB").unwrap().dimension, Dimension::Bytes);
+
+        assert!(Unit::parse("mb").is_none());
+        assert!(Unit::parse("furlong").is_none());
+    }
+
+    #[test]
+    fn test_convert() {
+        assert_eq!(convert(1500.0, "ms", "s"), 1.5);
+        assert_eq!(convert(1.5, "s", "ms"), 1500.0);
+        assert_eq!(convert(123.0, "us", "ms"), 0.123);
+        assert_eq!(convert(0.1, "s", "ms"), 100.0);
+        assert_eq!(convert(7.0, "ns", "s"), 7e-9);
+        assert_eq!(convert(2.0, "h", "min"), 120.0);
+        assert_eq!(convert(90.0, "seconds", "min"), 1.5);
+
+        assert_eq!(convert(1.0, "GiB", "bytes"), 1073741824.0);
+        assert_eq!(convert(2048.0, "KiB", "MiB"), 2.0);
+        assert_eq!(convert(1.0, "KiB", "KB"), 1.024);
+        assert_eq!(convert(1500.0, "MB", "GB"), 1.5);
+
+        assert_eq!(convert(50.0, "%", "ratio"), 0.5);
+        assert_eq!(convert(0.25, "ratio", "percent"), 25.0);
+
+        let ms = Unit::parse("ms").unwrap();
+        assert!(ms.convert(1.0, Unit::parse("bytes").unwrap()).is_err());
+    }
+}
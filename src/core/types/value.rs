@@ -1,16 +1,26 @@
 use std::{collections::HashMap, fmt::Display, str::FromStr};
 
-use chrono::{Offset, TimeZone};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::TimeZone;
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
 use std::hash::Hash;
 
 pub use super::data_type::{
-    BOOL_TYPE, DATETIME_TYPE, FLOAT_TYPE, INT_TYPE, NULL_TYPE, STRING_TYPE,
+    BOOL_TYPE, BYTES_TYPE, DATETIME_TYPE, FLOAT_TYPE, INT_TYPE, NULL_TYPE, STRING_TYPE,
 };
 use super::Primitive;
 
-pub const MAP_TYPE: &'static str = "Map";
-pub const ARRAY_TYPE: &'static str = "Array";
+pub const MAP_TYPE: &str = "Map";
+pub const ARRAY_TYPE: &str = "Array";
+
+pub const TRUNCATED_MARKER_STR: &str = "__truncated__";
+
+/// Bytes shown by `Display` before the rest of a payload is elided.
+const BYTES_PREVIEW_LEN: usize = 16;
+
+pub static TRUNCATED_MARKER: once_cell::sync::Lazy<Value> =
+    once_cell::sync::Lazy::new(|| Value::from(TRUNCATED_MARKER_STR));
 
 // Add Guard type definition
 pub struct StringGuard<'a>(&'a super::string::Symbol);
@@ -18,6 +28,8 @@ pub struct IntGuard<'a>(&'a Number<i64>);
 pub struct FloatGuard<'a>(&'a Number<f64>);
 pub struct BoolGuard(bool);
 pub struct DateTimeGuard<'a>(&'a chrono::DateTime<chrono::Utc>);
+pub struct BytesGuard<'a>(&'a Vec<u8>);
+pub struct BytesGuardMut<'a>(&'a mut Vec<u8>);
 pub struct MapGuard<'a>(&'a HashMap<Value, Value>);
 pub struct MapGuardMut<'a>(&'a mut HashMap<Value, Value>);
 pub struct ArrayGuard<'a>(&'a Vec<Value>);
@@ -32,9 +44,11 @@ impl<'a> StringGuard<'a> {
     pub fn as_symbol(&self) -> &super::string::Symbol {
         self.0
     }
+}
 
-    pub fn to_string(&self) -> String {
-        self.0.to_string()
+impl Display for StringGuard<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.0.as_str())
     }
 }
 
@@ -97,10 +111,6 @@ impl<'a> DateTimeGuard<'a> {
         self.0.to_rfc2822()
     }
 
-    pub fn to_string(&self) -> String {
-        self.0.to_string()
-    }
-
     pub fn to_local(&self) -> chrono::DateTime<chrono::Local> {
         self.0.with_timezone(&chrono::Local)
     }
@@ -114,6 +124,52 @@ impl<'a> DateTimeGuard<'a> {
     }
 }
 
+impl Display for DateTimeGuard<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<'a> BytesGuard<'a> {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+
+    pub fn to_base64(&self) -> String {
+        BASE64.encode(self.0)
+    }
+
+    pub fn to_hex(&self) -> String {
+        encode_hex(self.0)
+    }
+}
+
+impl<'a> BytesGuardMut<'a> {
+    pub fn extend_from_slice(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.0.truncate(len);
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    pub fn as_mut_vec(&mut self) -> &mut Vec<u8> {
+        self.0
+    }
+}
+
 impl<'a> MapGuard<'a> {
     pub fn len(&self) -> usize {
         self.0.len()
@@ -279,6 +335,8 @@ pub enum Value {
     Float(Number<f64>),
     Bool(bool),
     DateTime(chrono::DateTime<chrono::Utc>),
+    // Kept out of the interner, unlike strings
+    Bytes(Vec<u8>),
     Map(HashMap<Value, Value>),
     Array(Vec<Value>),
 }
@@ -291,6 +349,7 @@ pub enum ValueType {
     Float,
     Bool,
     DateTime,
+    Bytes,
     Map,
     Array,
 }
@@ -304,6 +363,7 @@ impl ValueType {
             ValueType::Float => FLOAT_TYPE,
             ValueType::Bool => BOOL_TYPE,
             ValueType::DateTime => DATETIME_TYPE,
+            ValueType::Bytes => BYTES_TYPE,
             ValueType::Map => MAP_TYPE,
             ValueType::Array => ARRAY_TYPE,
         }
@@ -317,6 +377,7 @@ impl ValueType {
                 | ValueType::Int
                 | ValueType::Float
                 | ValueType::Bool
+                | ValueType::Bytes
         )
     }
 
@@ -336,6 +397,7 @@ impl TryInto<Primitive> for ValueType {
             ValueType::Float => Ok(Primitive::Float),
             ValueType::Bool => Ok(Primitive::Bool),
             ValueType::DateTime => Ok(Primitive::DateTime),
+            ValueType::Bytes => Ok(Primitive::Bytes),
             ValueType::Map => Err(super::Error::InvalidValueType(MAP_TYPE.to_string())),
             ValueType::Array => Err(super::Error::InvalidValueType(ARRAY_TYPE.to_string())),
         }
@@ -351,6 +413,7 @@ impl From<&Primitive> for ValueType {
             Primitive::Float => ValueType::Float,
             Primitive::Bool => ValueType::Bool,
             Primitive::DateTime => ValueType::DateTime,
+            Primitive::Bytes | Primitive::HexBytes => ValueType::Bytes,
         }
     }
 }
@@ -387,15 +450,15 @@ where
     }
 }
 
-impl<T> ToString for Number<T>
+impl<T> Display for Number<T>
 where
     T: Num + Display,
 {
-    fn to_string(&self) -> String {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if let Some(unit) = &self.unit {
-            format!("{} {}", self.value, unit)
+            write!(f, "{} {}", self.value, unit)
         } else {
-            self.value.to_string()
+            write!(f, "{}", self.value)
         }
     }
 }
@@ -423,10 +486,12 @@ impl Eq for Number<f64> {}
 
 impl PartialOrd for Number<i64> {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.value.partial_cmp(&other.value)
+        Some(self.cmp(other))
     }
 }
 
+// NaN is unordered, unlike with `cmp`
+#[allow(clippy::non_canonical_partial_ord_impl)]
 impl PartialOrd for Number<f64> {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         self.value.partial_cmp(&other.value)
@@ -447,8 +512,31 @@ impl Ord for Number<f64> {
     }
 }
 
+/// Integers beyond 2^53 are rounded by f64. Warned about once, later losses are logged at
+/// debug level.
+static PRECISION_LOSS_WARNED: std::sync::atomic::AtomicBool =
+    std::sync::atomic::AtomicBool::new(false);
+
+impl Number<i64> {
+    /// Whether the value converts to f64 exactly.
+    pub fn fits_f64(&self) -> bool {
+        (self.value as f64) as i128 == self.value as i128
+    }
+}
+
 impl From<Number<i64>> for Number<f64> {
     fn from(number: Number<i64>) -> Self {
+        if !number.fits_f64() {
+            if PRECISION_LOSS_WARNED.swap(true, std::sync::atomic::Ordering::Relaxed) {
+                log::debug!("Precision lost converting {} to float", number);
+            } else {
+                log::warn!(
+                    "Precision lost converting {} to float, use integers for large counters",
+                    number
+                );
+            }
+        }
+
         Number {
             value: number.value as f64,
             unit: number.unit,
@@ -614,6 +702,18 @@ impl From<&super::string::Symbol> for Value {
     }
 }
 
+impl From<Vec<u8>> for Value {
+    fn from(bytes: Vec<u8>) -> Self {
+        Value::Bytes(bytes)
+    }
+}
+
+impl From<&[u8]> for Value {
+    fn from(bytes: &[u8]) -> Self {
+        Value::Bytes(bytes.to_vec())
+    }
+}
+
 impl From<HashMap<Value, Value>> for Value {
     fn from(map: HashMap<Value, Value>) -> Self {
         Value::Map(map)
@@ -674,6 +774,7 @@ impl PartialOrd for Value {
             (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
             (Value::Bool(a), Value::Bool(b)) => a.partial_cmp(b),
             (Value::DateTime(a), Value::DateTime(b)) => a.partial_cmp(b),
+            (Value::Bytes(a), Value::Bytes(b)) => a.partial_cmp(b),
             _ => None,
         }
     }
@@ -708,6 +809,10 @@ impl Value {
         matches!(self, Value::DateTime(_))
     }
 
+    pub fn is_bytes(&self) -> bool {
+        matches!(self, Value::Bytes(_))
+    }
+
     pub fn is_map(&self) -> bool {
         matches!(self, Value::Map(_))
     }
@@ -732,6 +837,7 @@ impl Value {
             Value::Float(_) => ValueType::Float,
             Value::Bool(_) => ValueType::Bool,
             Value::DateTime(_) => ValueType::DateTime,
+            Value::Bytes(_) => ValueType::Bytes,
             Value::Map(_) => ValueType::Map,
             Value::Array(_) => ValueType::Array,
         }
@@ -744,11 +850,14 @@ impl Value {
             Value::Float(_) => FLOAT_TYPE,
             Value::Bool(_) => BOOL_TYPE,
             Value::DateTime(_) => DATETIME_TYPE,
+            Value::Bytes(_) => BYTES_TYPE,
             Value::Map(_) => MAP_TYPE,
             Value::Array(_) => ARRAY_TYPE,
         }
     }
 
+    /// Bytes are encoded in base64. Nothing casts to bytes, strings are only decoded by
+    /// [`parse_value`].
     pub fn cast_string(&self) -> super::Result<Self> {
         match self {
             Value::String(_) => Ok(self.clone()),
@@ -756,6 +865,7 @@ impl Value {
             Value::Float(number) => Ok(Value::String(number.to_string().into())),
             Value::Bool(boolean) => Ok(Value::String(boolean.to_string().into())),
             Value::DateTime(datetime) => Ok(Value::String(datetime.to_rfc3339().into())),
+            Value::Bytes(bytes) => Ok(Value::String(BASE64.encode(bytes).into())),
             _ => Err(super::Error::CanNotCast(
                 self.type_name(),
                 STRING_TYPE,
@@ -764,9 +874,11 @@ impl Value {
         }
     }
 
+    /// Strings are parsed as numbers, e.g. `1.5 ms`.
     pub fn cast_float(&self) -> super::Result<Self> {
         match self {
             Value::Float(_) => Ok(self.clone()),
+            Value::String(string) => parse_number_value::<f64>(string.as_str()),
             Value::Int(number) => Ok(Value::Float(number.clone().into())),
             Value::Bool(boolean) => Ok(Value::Float(Number {
                 value: if *boolean { 1.0 } else { 0.0 },
@@ -780,7 +892,78 @@ impl Value {
         }
     }
 
-    pub fn string(&self) -> super::Result<StringGuard> {
+    /// Float values are truncated toward zero, and must be within the range of i64. Strings
+    /// are parsed as numbers, e.g. `1500 ms`, floats being truncated the same way.
+    pub fn cast_int(&self) -> super::Result<Self> {
+        match self {
+            Value::Int(_) => Ok(self.clone()),
+            // 2^63 is exact as a float, i64::MAX is not
+            Value::Float(number)
+                if number.value.is_finite()
+                    && number.value >= i64::MIN as f64
+                    && number.value < i64::MAX as f64 =>
+            {
+                Ok(Value::Int(Number {
+                    value: number.value.trunc() as i64,
+                    unit: number.unit.clone(),
+                }))
+            }
+            Value::Float(number) if number.value.is_finite() => Err(super::Error::Overflow(
+                format!("{} as {}", number, INT_TYPE),
+            )),
+            Value::Bool(boolean) => Ok(Value::Int(Number::new(*boolean as i64))),
+            Value::String(string) => match parse_number_value::<i64>(string.as_str()) {
+                Ok(value) => Ok(value),
+                Err(e) => parse_number_value::<f64>(string.as_str())
+                    .map_err(|_| e)?
+                    .cast_int(),
+            },
+            _ => Err(super::Error::CanNotCast(
+                self.type_name(),
+                INT_TYPE,
+                self.clone(),
+            )),
+        }
+    }
+
+    /// Numbers are only true or false as `1` or `0`, strings are parsed like the other
+    /// booleans, e.g. `yes` or `off`.
+    pub fn cast_bool(&self) -> super::Result<Self> {
+        match self {
+            Value::Bool(_) => Ok(self.clone()),
+            Value::Int(number) if number.value == 0 || number.value == 1 => {
+                Ok(Value::Bool(number.value == 1))
+            }
+            Value::Float(number) if number.value == 0.0 || number.value == 1.0 => {
+                Ok(Value::Bool(number.value == 1.0))
+            }
+            Value::String(string) => parse_bool_value(string.as_str()),
+            _ => Err(super::Error::CanNotCast(
+                self.type_name(),
+                BOOL_TYPE,
+                self.clone(),
+            )),
+        }
+    }
+
+    /// Strings are parsed as datetimes, in UTC without an offset. Integers are timestamps in
+    /// seconds, milliseconds or nanoseconds, told apart by their number of digits.
+    pub fn cast_datetime(&self) -> super::Result<Self> {
+        match self {
+            Value::DateTime(_) => Ok(self.clone()),
+            Value::String(string) => parse_datetime_value(string.as_str(), Tz::UTC),
+            Value::Int(number) if number.unit.is_none() => {
+                parse_datetime_value(&number.value.to_string(), Tz::UTC)
+            }
+            _ => Err(super::Error::CanNotCast(
+                self.type_name(),
+                DATETIME_TYPE,
+                self.clone(),
+            )),
+        }
+    }
+
+    pub fn string(&self) -> super::Result<StringGuard<'_>> {
         if let Value::String(string) = self {
             Ok(StringGuard(string))
         } else {
@@ -788,7 +971,7 @@ impl Value {
         }
     }
 
-    pub fn int(&self) -> super::Result<IntGuard> {
+    pub fn int(&self) -> super::Result<IntGuard<'_>> {
         if let Value::Int(number) = self {
             Ok(IntGuard(number))
         } else {
@@ -796,7 +979,7 @@ impl Value {
         }
     }
 
-    pub fn float(&self) -> super::Result<FloatGuard> {
+    pub fn float(&self) -> super::Result<FloatGuard<'_>> {
         if let Value::Float(number) = self {
             Ok(FloatGuard(number))
         } else {
@@ -812,7 +995,7 @@ impl Value {
         }
     }
 
-    pub fn datetime(&self) -> super::Result<DateTimeGuard> {
+    pub fn datetime(&self) -> super::Result<DateTimeGuard<'_>> {
         if let Value::DateTime(datetime) = self {
             Ok(DateTimeGuard(datetime))
         } else {
@@ -823,7 +1006,38 @@ impl Value {
         }
     }
 
-    pub fn map(&self) -> super::Result<MapGuard> {
+    pub fn bytes(&self) -> super::Result<BytesGuard<'_>> {
+        if let Value::Bytes(bytes) = self {
+            Ok(BytesGuard(bytes))
+        } else {
+            Err(super::Error::UnexpectedType(BYTES_TYPE, self.type_name()))
+        }
+    }
+
+    pub fn bytes_mut(&mut self) -> super::Result<BytesGuardMut<'_>> {
+        if let Value::Bytes(bytes) = self {
+            Ok(BytesGuardMut(bytes))
+        } else {
+            Err(super::Error::UnexpectedType(BYTES_TYPE, self.type_name()))
+        }
+    }
+
+    /// Take the string out of the value, it is only copied if interned.
+    pub fn into_string(self) -> super::Result<String> {
+        match self {
+            Value::String(string) => Ok(string.into_string()),
+            other => Err(super::Error::UnexpectedType(STRING_TYPE, other.type_name())),
+        }
+    }
+
+    pub fn into_map(self) -> super::Result<HashMap<Value, Value>> {
+        match self {
+            Value::Map(map) => Ok(map),
+            other => Err(super::Error::UnexpectedType(MAP_TYPE, other.type_name())),
+        }
+    }
+
+    pub fn map(&self) -> super::Result<MapGuard<'_>> {
         if let Value::Map(map) = self {
             Ok(MapGuard(map))
         } else {
@@ -831,7 +1045,7 @@ impl Value {
         }
     }
 
-    pub fn map_mut(&mut self) -> super::Result<MapGuardMut> {
+    pub fn map_mut(&mut self) -> super::Result<MapGuardMut<'_>> {
         if let Value::Map(map) = self {
             Ok(MapGuardMut(map))
         } else {
@@ -839,7 +1053,7 @@ impl Value {
         }
     }
 
-    pub fn array(&self) -> super::Result<ArrayGuard> {
+    pub fn array(&self) -> super::Result<ArrayGuard<'_>> {
         if let Value::Array(array) = self {
             Ok(ArrayGuard(array))
         } else {
@@ -847,13 +1061,113 @@ impl Value {
         }
     }
 
-    pub fn array_mut(&mut self) -> super::Result<ArrayGuardMut> {
+    pub fn array_mut(&mut self) -> super::Result<ArrayGuardMut<'_>> {
         if let Value::Array(array) = self {
             Ok(ArrayGuardMut(array))
         } else {
             Err(super::Error::UnexpectedType(ARRAY_TYPE, self.type_name()))
         }
     }
+
+    /// Hash which only depends on the content of the value: unlike [`Hash`], the iteration
+    /// order of maps does not matter, so equal values hash the same across runs.
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = std::hash::DefaultHasher::new();
+        match self {
+            Value::Map(map) => {
+                // Combine the entries with a commutative operation
+                let entries = map
+                    .iter()
+                    .map(|(k, v)| k.content_hash() ^ v.content_hash().rotate_left(32))
+                    .fold(0u64, |acc, h| acc.wrapping_add(h));
+                (map.len(), entries).hash(&mut hasher);
+            }
+            Value::Array(array) => {
+                array.len().hash(&mut hasher);
+                for v in array {
+                    v.content_hash().hash(&mut hasher);
+                }
+            }
+            _ => self.hash(&mut hasher),
+        }
+        std::hash::Hasher::finish(&hasher)
+    }
+
+    /// Rough estimation of the payload size of this value, in bytes.
+    ///
+    /// This is not the in-memory size, but close to what the value would take once serialized,
+    /// which is what matters when a misbehaving producer starts sending huge records.
+    pub fn estimated_bytes(&self) -> usize {
+        match self {
+            Value::Null | Value::Bool(_) => 1,
+            Value::String(s) => s.as_str().len(),
+            Value::Int(n) => 8 + n.unit.as_ref().map_or(0, |u| u.len()),
+            Value::Float(n) => 8 + n.unit.as_ref().map_or(0, |u| u.len()),
+            Value::DateTime(_) => 12,
+            Value::Bytes(bytes) => bytes.len(),
+            Value::Map(map) => map
+                .iter()
+                .map(|(k, v)| k.estimated_bytes() + v.estimated_bytes())
+                .sum(),
+            Value::Array(array) => array.iter().map(|v| v.estimated_bytes()).sum(),
+        }
+    }
+
+    /// Truncate maps and arrays holding more than `max_entries` entries, recursively.
+    ///
+    /// Truncated maps keep their first `max_entries` keys (in key order) and get an extra
+    /// `__truncated__: true` entry; truncated arrays keep their first `max_entries` elements
+    /// and get a `{__truncated__: true}` map appended. Returns whether anything was truncated.
+    pub fn truncate_entries(&mut self, max_entries: usize) -> bool {
+        match self {
+            Value::Map(map) => {
+                // A map truncated before already carries the marker, which is not counted.
+                let len = map.len() - map.contains_key(&TRUNCATED_MARKER) as usize;
+                let mut truncated = false;
+                if len > max_entries {
+                    map.remove(&TRUNCATED_MARKER);
+                    let mut keys = map.keys().cloned().collect::<Vec<_>>();
+                    keys.sort_by_cached_key(|k| k.to_string());
+                    for key in keys.into_iter().skip(max_entries) {
+                        map.remove(&key);
+                    }
+                    map.insert(TRUNCATED_MARKER.clone(), Value::Bool(true));
+                    truncated = true;
+                }
+
+                for value in map.values_mut() {
+                    truncated |= value.truncate_entries(max_entries);
+                }
+
+                truncated
+            }
+            Value::Array(array) => {
+                // Same for an array truncated before, which already ends with the marker.
+                let len = array.len() - array.last().is_some_and(is_truncated_marker) as usize;
+                let mut truncated = false;
+                for value in array.iter_mut().take(len.min(max_entries)) {
+                    truncated |= value.truncate_entries(max_entries);
+                }
+
+                if len > max_entries {
+                    array.truncate(max_entries);
+                    let marker = HashMap::from([(TRUNCATED_MARKER.clone(), Value::Bool(true))]);
+                    array.push(Value::Map(marker));
+                    truncated = true;
+                }
+
+                truncated
+            }
+            _ => false,
+        }
+    }
+}
+
+fn is_truncated_marker(value: &Value) -> bool {
+    match value {
+        Value::Map(map) => map.len() == 1 && map.contains_key(&TRUNCATED_MARKER),
+        _ => false,
+    }
 }
 
 impl Hash for Value {
@@ -887,6 +1201,10 @@ impl Hash for Value {
                 datetime.timestamp().hash(state);
                 datetime.timestamp_subsec_nanos().hash(state);
             }
+            Value::Bytes(bytes) => {
+                BYTES_TYPE.hash(state);
+                bytes.hash(state);
+            }
             Value::Map(map) => {
                 MAP_TYPE.hash(state);
                 for (key, value) in map {
@@ -909,10 +1227,18 @@ impl Display for Value {
         match self {
             Value::Null => write!(f, "null"),
             Value::String(string) => write!(f, "{}", string),
-            Value::Int(number) => write!(f, "{}", number.to_string()),
-            Value::Float(number) => write!(f, "{}", number.to_string()),
+            Value::Int(number) => write!(f, "{}", number),
+            Value::Float(number) => write!(f, "{}", number),
             Value::Bool(boolean) => write!(f, "{}", boolean),
             Value::DateTime(datetime) => write!(f, "{}", datetime),
+            // A hex preview, payloads can be large
+            Value::Bytes(bytes) if bytes.len() > BYTES_PREVIEW_LEN => write!(
+                f,
+                "0x{}... ({} bytes)",
+                encode_hex(&bytes[..BYTES_PREVIEW_LEN]),
+                bytes.len()
+            ),
+            Value::Bytes(bytes) => write!(f, "0x{}", encode_hex(bytes)),
             Value::Map(map) => {
                 let map_str = map
                     .iter()
@@ -960,6 +1286,47 @@ where
     }
 }
 
+fn encode_hex(bytes: &[u8]) -> String {
+    use std::fmt::Write;
+
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    hex
+}
+
+/// Either case, an optional `0x` prefix.
+fn parse_hex_bytes_value(value: &str) -> super::Result<Value> {
+    let invalid = || super::Error::InvalidBytesFormat(value.to_string(), "hex");
+    let digits = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .unwrap_or(value);
+    if !digits.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+
+    let bytes = digits
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(invalid)
+        })
+        .collect::<super::Result<Vec<_>>>()?;
+    Ok(Value::Bytes(bytes))
+}
+
+fn parse_base64_bytes_value(value: &str) -> super::Result<Value> {
+    BASE64
+        .decode(value)
+        .map(Value::Bytes)
+        .map_err(|_| super::Error::InvalidBytesFormat(value.to_string(), "base64"))
+}
+
 fn parse_bool_value(value: &str) -> super::Result<Value> {
     const TRUE_VALUES: [&str; 7] = ["true", "t", "yes", "y", "on", "active", "1"];
     const FALSE_VALUES: [&str; 8] = [
@@ -983,7 +1350,8 @@ fn parse_bool_value(value: &str) -> super::Result<Value> {
     Err(super::Error::InvalidBoolFormat(value))
 }
 
-fn parse_datetime_value(value: &str) -> super::Result<Value> {
+/// Datetimes without an offset are taken as local times in `tz`.
+fn parse_datetime_value(value: &str, tz: Tz) -> super::Result<Value> {
     let value_len = value.len();
     // Check if the value is a timestamp in seconds, milliseconds, or nanoseconds
     if let Ok(timestamp) = value.parse::<i64>() {
@@ -1034,8 +1402,11 @@ fn parse_datetime_value(value: &str) -> super::Result<Value> {
 
     for format in FORMATS.iter() {
         if let Ok(datetime) = chrono::NaiveDateTime::parse_from_str(value, format) {
-            let utc = datetime.and_utc() - chrono::Local::now().offset().fix();
-            return Ok(utc.into());
+            // 夏令时回拨时重复的本地时间取较早的一个
+            return match tz.from_local_datetime(&datetime).earliest() {
+                Some(datetime) => Ok(datetime.with_timezone(&chrono::Utc).into()),
+                None => Err(super::Error::NonexistentLocalTime(value.to_string(), tz)),
+            };
         }
     }
 
@@ -1098,26 +1469,41 @@ fn parse_primitive_value(value: &str) -> super::Result<Value> {
     Ok(Value::String(super::string::intern(value)))
 }
 
+/// Same as [`parse_value_in`], with naive datetimes taken as UTC.
 pub fn parse_value(value: &str, typ: ValueType) -> super::Result<Value> {
+    parse_value_in(value, typ, Tz::UTC)
+}
+
+/// Same as [`parse_value_in`], for a configured type: bytes may be written in hex.
+pub fn parse_primitive_in(value: &str, typ: &Primitive, tz: Tz) -> super::Result<Value> {
+    match typ {
+        Primitive::HexBytes if !value.trim().is_empty() => parse_hex_bytes_value(value.trim()),
+        _ => parse_value_in(value, typ.into(), tz),
+    }
+}
+
+/// Parse a value of the given type, naive datetimes being local times in `tz`.
+pub fn parse_value_in(value: &str, typ: ValueType, tz: Tz) -> super::Result<Value> {
     let value = value.trim();
     if value.is_empty() {
         return Ok(Value::Null);
     }
 
     match typ {
-        ValueType::Null => return Ok(Value::Null),
-        ValueType::String => return Ok(Value::String(super::string::intern(value))),
-        ValueType::Int => return parse_number_value::<i64>(value),
-        ValueType::Float => return parse_number_value::<f64>(value),
-        ValueType::Bool => return parse_bool_value(value),
-        ValueType::DateTime => return parse_datetime_value(value),
+        ValueType::Null => Ok(Value::Null),
+        ValueType::String => Ok(Value::String(super::string::intern(value))),
+        ValueType::Int => parse_number_value::<i64>(value),
+        ValueType::Float => parse_number_value::<f64>(value),
+        ValueType::Bool => parse_bool_value(value),
+        ValueType::DateTime => parse_datetime_value(value, tz),
+        ValueType::Bytes => parse_base64_bytes_value(value),
         ValueType::Map => {
             if value.starts_with('{') && value.ends_with('}') {
                 let inner = &value[1..value.len() - 1];
                 return parse_map_value(inner);
             }
 
-            return Err(super::Error::InvalidMapFormat(value.to_string()));
+            Err(super::Error::InvalidMapFormat(value.to_string()))
         }
         ValueType::Array => {
             if value.starts_with('[') && value.ends_with(']') {
@@ -1125,15 +1511,14 @@ pub fn parse_value(value: &str, typ: ValueType) -> super::Result<Value> {
                 return parse_array_value(inner);
             }
 
-            return Err(super::Error::InvalidArrayFormat(value.to_string()));
+            Err(super::Error::InvalidArrayFormat(value.to_string()))
         }
     }
-
-    parse_primitive_value(value)
 }
 
 #[cfg(test)]
 mod tests {
+    use super::super::Error;
     use super::*;
     use std::collections::HashMap;
 
@@ -1174,24 +1559,24 @@ mod tests {
         let mut map = HashMap::new();
         map.insert(string("key1"), string("value1"));
         map.insert(string("key2"), int(42));
-        map.insert(string("key3"), float(3.14));
+        map.insert(string("key3"), float(2.75));
         map.into()
     }
 
     fn array() -> Value {
-        vec![string("item1"), int(42), float(3.14)].into()
+        vec![string("item1"), int(42), float(2.75)].into()
     }
 
     #[test]
     fn test_value_creation() {
-        assert_eq!(null().is_null(), true);
-        assert_eq!(string("test").is_string(), true);
-        assert_eq!(int(42).is_int(), true);
-        assert_eq!(float(3.14).is_float(), true);
-        assert_eq!(bool_val(true).is_bool(), true);
-        assert_eq!(datetime(1609459200).is_datetime(), true);
-        assert_eq!(map().is_map(), true);
-        assert_eq!(array().is_array(), true);
+        assert!(null().is_null());
+        assert!(string("test").is_string());
+        assert!(int(42).is_int());
+        assert!(float(2.75).is_float());
+        assert!(bool_val(true).is_bool());
+        assert!(datetime(1609459200).is_datetime());
+        assert!(map().is_map());
+        assert!(array().is_array());
 
         let int_unit = int_with_unit(100, "kg");
         assert!(int_unit.is_int());
@@ -1213,7 +1598,7 @@ mod tests {
         assert_eq!(null().type_name(), NULL_TYPE);
         assert_eq!(string("test").type_name(), STRING_TYPE);
         assert_eq!(int(42).type_name(), INT_TYPE);
-        assert_eq!(float(3.14).type_name(), FLOAT_TYPE);
+        assert_eq!(float(2.75).type_name(), FLOAT_TYPE);
         assert_eq!(bool_val(true).type_name(), BOOL_TYPE);
         assert_eq!(datetime(1609459200).type_name(), DATETIME_TYPE);
         assert_eq!(map().type_name(), MAP_TYPE);
@@ -1225,7 +1610,7 @@ mod tests {
         assert_eq!(format!("{}", null()), "null");
         assert_eq!(format!("{}", string("test")), "test");
         assert_eq!(format!("{}", int(42)), "42");
-        assert_eq!(format!("{}", float(3.14)), "3.14");
+        assert_eq!(format!("{}", float(2.75)), "2.75");
         assert_eq!(format!("{}", bool_val(true)), "true");
         assert_eq!(format!("{}", int_with_unit(100, "kg")), "100 kg");
         assert_eq!(format!("{}", float_with_unit(72.5, "cm")), "72.5 cm");
@@ -1235,20 +1620,20 @@ mod tests {
         assert!(map_str.ends_with("}"));
         assert!(map_str.contains("key1: value1"));
         assert!(map_str.contains("key2: 42"));
-        assert!(map_str.contains("key3: 3.14"));
+        assert!(map_str.contains("key3: 2.75"));
 
         let array_str = format!("{}", array());
         assert!(array_str.starts_with("["));
         assert!(array_str.ends_with("]"));
         assert!(array_str.contains("item1"));
         assert!(array_str.contains("42"));
-        assert!(array_str.contains("3.14"));
+        assert!(array_str.contains("2.75"));
     }
 
     #[test]
     fn test_cast_methods() {
         assert_eq!(int(42).cast_string().unwrap(), string("42"));
-        assert_eq!(float(3.14).cast_string().unwrap(), string("3.14"));
+        assert_eq!(float(2.75).cast_string().unwrap(), string("2.75"));
         assert_eq!(bool_val(true).cast_string().unwrap(), string("true"));
 
         assert_eq!(int(42).cast_float().unwrap().float().unwrap().value(), 42.0);
@@ -1261,6 +1646,109 @@ mod tests {
                 .value(),
             1.0
         );
+
+        assert_eq!(float(-3.9).cast_int().unwrap(), int(-3));
+        assert_eq!(
+            int(i64::MAX).cast_int().unwrap().int().unwrap().value(),
+            i64::MAX
+        );
+        assert_eq!(bool_val(true).cast_int().unwrap(), int(1));
+        assert_eq!(float(i64::MIN as f64).cast_int().unwrap(), int(i64::MIN));
+        assert!(float(i64::MAX as f64).cast_int().is_err());
+        assert!(float(f64::NAN).cast_int().is_err());
+        assert!(float(f64::INFINITY).cast_int().is_err());
+
+        // Above 2^53 not every integer is a float
+        assert!(Number::new(1i64 << 53).fits_f64());
+        assert!(!Number::new((1i64 << 53) + 1).fits_f64());
+        assert!(!Number::new(i64::MAX).fits_f64());
+        assert!(Number::new(i64::MIN).fits_f64());
+    }
+
+    #[test]
+    fn test_cast_int() {
+        assert!(matches!(float(1e19).cast_int(), Err(Error::Overflow(_))));
+        assert!(matches!(float(-1e19).cast_int(), Err(Error::Overflow(_))));
+        assert!(matches!(
+            float(f64::NAN).cast_int(),
+            Err(Error::CanNotCast(..))
+        ));
+
+        // The unit is kept, whichever way the number comes in
+        assert_eq!(
+            float_with_unit(1.9, "ms").cast_int().unwrap(),
+            int_with_unit(1, "ms")
+        );
+        assert_eq!(
+            string("1500 ms").cast_int().unwrap(),
+            int_with_unit(1500, "ms")
+        );
+        assert_eq!(string("2.5 s").cast_int().unwrap(), int_with_unit(2, "s"));
+        assert_eq!(
+            int_with_unit(3, "bytes").cast_float().unwrap(),
+            float_with_unit(3.0, "bytes")
+        );
+        assert_eq!(string("-7").cast_int().unwrap(), int(-7));
+        assert_eq!(
+            string("9223372036854775807").cast_int().unwrap(),
+            int(i64::MAX)
+        );
+        assert!(string("1e19").cast_int().is_err());
+        assert!(matches!(
+            string("abc").cast_int(),
+            Err(Error::InvalidNumberFormat(_))
+        ));
+        assert!(datetime(0).cast_int().is_err());
+
+        // Round trips
+        for n in [0, -1, 42, 1 << 53] {
+            assert_eq!(int(n).cast_float().unwrap().cast_int().unwrap(), int(n));
+            assert_eq!(int(n).cast_string().unwrap().cast_int().unwrap(), int(n));
+        }
+        assert_eq!(
+            int(i64::MAX).cast_string().unwrap().cast_int().unwrap(),
+            int(i64::MAX)
+        );
+        assert_eq!(
+            float_with_unit(2.5, "s")
+                .cast_string()
+                .unwrap()
+                .cast_float()
+                .unwrap(),
+            float_with_unit(2.5, "s")
+        );
+    }
+
+    #[test]
+    fn test_cast_bool_and_datetime() {
+        assert_eq!(int(1).cast_bool().unwrap(), bool_val(true));
+        assert_eq!(float(0.0).cast_bool().unwrap(), bool_val(false));
+        assert_eq!(string("Yes").cast_bool().unwrap(), bool_val(true));
+        assert_eq!(string("not active").cast_bool().unwrap(), bool_val(false));
+        assert!(int(2).cast_bool().is_err());
+        assert!(matches!(
+            string("maybe").cast_bool(),
+            Err(Error::InvalidBoolFormat(_))
+        ));
+        for b in [true, false] {
+            let cast = bool_val(b).cast_int().unwrap().cast_bool().unwrap();
+            assert_eq!(cast, bool_val(b));
+            let cast = bool_val(b).cast_string().unwrap().cast_bool().unwrap();
+            assert_eq!(cast, bool_val(b));
+        }
+
+        let dt = datetime(1743696543);
+        assert_eq!(int(1743696543).cast_datetime().unwrap(), dt);
+        assert_eq!(int(1743696543000).cast_datetime().unwrap(), dt);
+        assert_eq!(string("2025-04-03 16:09:03").cast_datetime().unwrap(), dt);
+        assert_eq!(dt.cast_string().unwrap().cast_datetime().unwrap(), dt);
+        assert!(int(42).cast_datetime().is_err());
+        assert!(int_with_unit(1743696543, "s").cast_datetime().is_err());
+        assert!(float(1743696543.0).cast_datetime().is_err());
+        assert!(matches!(
+            string("yesterday").cast_datetime(),
+            Err(Error::UnknownDatetimeFormat(_))
+        ));
     }
 
     #[test]
@@ -1403,7 +1891,7 @@ mod tests {
         let items: Vec<&Value> = arr_guard.iter().collect();
         assert_eq!(items.len(), 3);
         assert_eq!(items[0], &int(42));
-        assert_eq!(items[1], &float(3.14));
+        assert_eq!(items[1], &float(2.75));
         assert_eq!(items[2], &bool_val(false));
 
         arr.array_mut().unwrap().clear();
@@ -1487,7 +1975,7 @@ mod tests {
 
         // Test array contains
         assert!(arr.array().unwrap().contains(&int(42)));
-        assert_eq!(arr.array().unwrap().contains(&string("nonexistent")), false);
+        assert!(!arr.array().unwrap().contains(&string("nonexistent")));
 
         // Test array index_of
         assert_eq!(arr.array().unwrap().index_of(&int(42)), Some(2));
@@ -1498,7 +1986,7 @@ mod tests {
         assert!(joined.contains("replaced"));
         assert!(joined.contains("true"));
         assert!(joined.contains("42"));
-        assert!(joined.contains("3.14"));
+        assert!(joined.contains("2.75"));
 
         // Test array slice (manually implement slicing logic)
         let sliced = {
@@ -1516,4 +2004,209 @@ mod tests {
             assert_eq!(sliced_arr[1], int(42));
         }
     }
+
+    #[test]
+    fn test_estimated_bytes() {
+        assert_eq!(null().estimated_bytes(), 1);
+        assert_eq!(string("hello").estimated_bytes(), 5);
+        assert_eq!(int(1).estimated_bytes(), 8);
+        assert_eq!(float_with_unit(1.0, "ms").estimated_bytes(), 10);
+
+        let arr = Value::Array(vec![string("ab"), int(1)]);
+        assert_eq!(arr.estimated_bytes(), 10);
+
+        let map: Value = vec![(string("key"), string("value"))].into();
+        assert_eq!(map.estimated_bytes(), 8);
+    }
+
+    #[test]
+    fn test_truncate_map_entries() {
+        let mut map: Value = (0..10)
+            .map(|i| (string(&format!("k{}", i)), int(i)))
+            .collect::<Vec<_>>()
+            .into();
+
+        assert!(map.truncate_entries(3));
+
+        let guard = map.map().unwrap();
+        // 3 original keys + the marker
+        assert_eq!(guard.len(), 4);
+        assert_eq!(guard.get(&string("k0")), Some(&int(0)));
+        assert_eq!(guard.get(&string("k1")), Some(&int(1)));
+        assert_eq!(guard.get(&string("k2")), Some(&int(2)));
+        assert!(!guard.contains_key(&string("k3")));
+        assert_eq!(guard.get(&TRUNCATED_MARKER), Some(&bool_val(true)));
+    }
+
+    #[test]
+    fn test_truncate_array_entries() {
+        let mut arr = Value::Array((0..5).map(int).collect());
+
+        assert!(arr.truncate_entries(2));
+
+        let guard = arr.array().unwrap();
+        assert_eq!(guard.len(), 3);
+        assert_eq!(guard.get(0), Some(&int(0)));
+        assert_eq!(guard.get(1), Some(&int(1)));
+        let marker = guard.get(2).unwrap().map().unwrap();
+        assert_eq!(marker.get(&TRUNCATED_MARKER), Some(&bool_val(true)));
+    }
+
+    #[test]
+    fn test_truncate_entries_nested_and_untouched() {
+        let inner = Value::Array((0..4).map(int).collect());
+        let mut map: Value = vec![(string("inner"), inner)].into();
+
+        assert!(map.truncate_entries(2));
+        let guard = map.map().unwrap();
+        // outer map is within the limit, so it does not get a marker
+        assert!(!guard.contains_key(&TRUNCATED_MARKER));
+        assert_eq!(
+            guard.get(&string("inner")).unwrap().array().unwrap().len(),
+            3
+        );
+
+        let mut small = Value::Array(vec![int(1)]);
+        assert!(!small.truncate_entries(2));
+        assert_eq!(small, Value::Array(vec![int(1)]));
+    }
+
+    #[test]
+    fn test_content_hash_ignores_map_order() {
+        let a = Value::from(vec![
+            (Value::from("k1"), Value::from(1i64)),
+            (Value::from("k2"), Value::from(2i64)),
+        ]);
+        let b = Value::from(vec![
+            (Value::from("k2"), Value::from(2i64)),
+            (Value::from("k1"), Value::from(1i64)),
+        ]);
+        assert_eq!(a.content_hash(), b.content_hash());
+    }
+
+    fn utc(value: &str, tz: Tz) -> String {
+        match parse_value_in(value, ValueType::DateTime, tz).unwrap() {
+            Value::DateTime(datetime) => {
+                datetime.to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+            }
+            other => panic!("unexpected value: {}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_naive_datetime_in_timezone() {
+        let tz = chrono_tz::Asia::Shanghai;
+        for (input, expected) in [
+            ("2025/04/03 16:09:03.452", "2025-04-03T08:09:03.452Z"),
+            ("2025/04/03 16:09:03", "2025-04-03T08:09:03.000Z"),
+            ("2025-04-03 16:09:03.452", "2025-04-03T08:09:03.452Z"),
+            ("2025-04-03 16:09:03", "2025-04-03T08:09:03.000Z"),
+            ("2025-04-03T16:09:03.452", "2025-04-03T08:09:03.452Z"),
+            ("2025-04-03T16:09:03", "2025-04-03T08:09:03.000Z"),
+            ("03/04/2025 16:09:03.452", "2025-04-03T08:09:03.452Z"),
+            ("04/13/2025 16:09:03.452", "2025-04-13T08:09:03.452Z"),
+            ("03-04-2025 16:09:03.452", "2025-04-03T08:09:03.452Z"),
+            ("04-13-2025 16:09:03.452", "2025-04-13T08:09:03.452Z"),
+        ] {
+            assert_eq!(utc(input, tz), expected, "{}", input);
+        }
+
+        // UTC by default
+        assert_eq!(
+            utc("2025-04-03 16:09:03", Tz::UTC),
+            "2025-04-03T16:09:03.000Z"
+        );
+        match parse_value("2025-04-03 16:09:03", ValueType::DateTime).unwrap() {
+            Value::DateTime(datetime) => {
+                assert_eq!(datetime.to_rfc3339(), "2025-04-03T16:09:03+00:00")
+            }
+            other => panic!("unexpected value: {}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_datetime_with_offset_ignores_timezone() {
+        let tz = chrono_tz::Asia::Shanghai;
+        for (input, expected) in [
+            ("1743696543", "2025-04-03T16:09:03.000Z"),
+            ("1743696543452", "2025-04-03T16:09:03.452Z"),
+            ("1743696543452000000", "2025-04-03T16:09:03.452Z"),
+            ("2025-04-03T16:09:03.452+02:00", "2025-04-03T14:09:03.452Z"),
+            ("2025-04-03T16:09:03Z", "2025-04-03T16:09:03.000Z"),
+            (
+                "Thu, 03 Apr 2025 16:09:03 +0200",
+                "2025-04-03T14:09:03.000Z",
+            ),
+            ("2025/04/03 16:09:03.452+02:00", "2025-04-03T14:09:03.452Z"),
+            ("2025-04-03 16:09:03.452+02:00", "2025-04-03T14:09:03.452Z"),
+            ("03/04/2025 16:09:03.452+02:00", "2025-04-03T14:09:03.452Z"),
+        ] {
+            assert_eq!(utc(input, tz), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_datetime_across_dst() {
+        let tz = chrono_tz::America::New_York;
+        // 01:30 happens twice when the clocks go back, the first one is in EDT
+        assert_eq!(utc("2024-11-03 01:30:00", tz), "2024-11-03T05:30:00.000Z");
+        assert_eq!(utc("2024-11-03 03:30:00", tz), "2024-11-03T08:30:00.000Z");
+        assert_eq!(utc("2024-07-01 12:00:00", tz), "2024-07-01T16:00:00.000Z");
+
+        // 02:30 never happens when the clocks go forward
+        assert!(matches!(
+            parse_value_in("2024-03-10 02:30:00", ValueType::DateTime, tz),
+            Err(Error::NonexistentLocalTime(..))
+        ));
+    }
+
+    #[test]
+    fn test_bytes() {
+        let mut value = Value::from(vec![0xde, 0xad, 0xbe, 0xef]);
+        assert!(value.is_bytes());
+        assert!(value.is_primitive());
+        assert_eq!(value.type_name(), BYTES_TYPE);
+        assert_eq!(value.bytes().unwrap().to_hex(), "deadbeef");
+        assert_eq!(value.estimated_bytes(), 4);
+        assert_eq!(format!("{}", value), "0xdeadbeef");
+        assert_eq!(
+            format!("{}", Value::from(vec![0u8; 20])),
+            format!("0x{}... (20 bytes)", "00".repeat(16))
+        );
+
+        // Base64 as a string, nothing casts back
+        assert_eq!(value.cast_string().unwrap(), string("3q2+7w=="));
+        assert!(value.cast_int().is_err());
+        assert!(string("3q2+7w==").cast_string().unwrap().bytes().is_err());
+
+        value.bytes_mut().unwrap().extend_from_slice(&[0x01]);
+        assert_eq!(
+            value.bytes().unwrap().as_slice(),
+            &[0xde, 0xad, 0xbe, 0xef, 0x01]
+        );
+        assert!(int(1).bytes_mut().is_err());
+        assert_ne!(
+            value.content_hash(),
+            Value::from(vec![0xde, 0xad, 0xbe, 0xef]).content_hash()
+        );
+        assert_ne!(value.content_hash(), string("3q2+7wE=").content_hash());
+
+        let payload = Value::from(vec![0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(parse_value("3q2+7w==", ValueType::Bytes).unwrap(), payload);
+        assert!(parse_value("not base64!", ValueType::Bytes).is_err());
+        for hex in ["deadbeef", "0xDEADBEEF", " DeadBeef "] {
+            assert_eq!(
+                parse_primitive_in(hex, &Primitive::HexBytes, Tz::UTC).unwrap(),
+                payload,
+                "{}",
+                hex
+            );
+        }
+        assert!(parse_primitive_in("abc", &Primitive::HexBytes, Tz::UTC).is_err());
+        assert!(parse_primitive_in("zz", &Primitive::HexBytes, Tz::UTC).is_err());
+        assert_eq!(
+            parse_primitive_in("", &Primitive::HexBytes, Tz::UTC).unwrap(),
+            Value::Null
+        );
+    }
 }
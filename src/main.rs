@@ -8,21 +8,53 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use config::Config;
 use fern::colors::{Color, ColoredLevelConfig};
 use log::{info, warn};
 use miette::IntoDiagnostic;
 
-use jemallocator::Jemalloc;
+// jemalloc does not build on windows, the system allocator is used there
+#[cfg(unix)]
+use jemallocator::Jemalloc as Allocator;
+#[cfg(not(unix))]
+use std::alloc::System as Allocator;
 
+#[cfg(not(test))]
 #[global_allocator]
-static GLOBAL: Jemalloc = Jemalloc;
+static GLOBAL: Allocator = Allocator;
+
+#[cfg(test)]
+#[global_allocator]
+static GLOBAL: utils::alloc::CountingAllocator<Allocator> =
+    utils::alloc::CountingAllocator(Allocator);
 
 /// Void 应用程序
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    // 不带子命令时等同于 `run`
+    #[command(flatten)]
+    run: RunArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// 启动应用程序（默认）
+    Run(RunArgs),
+
+    /// 检查配置文件并试创建所有组件，打印组件列表与拓扑后退出，不监听任何地址
+    Validate(ConfigArgs),
+
+    /// 打印配置文件对应的拓扑后退出，不监听任何地址
+    Graph(GraphArgs),
+}
+
+#[derive(clap::Args)]
+struct RunArgs {
     /// 配置文件路径
     #[arg(short, long, default_value = "config.toml")]
     config: PathBuf,
@@ -30,9 +62,42 @@ struct Args {
     /// 日志文件输出路径
     #[arg(short, long, default_value = "output.log")]
     log_file: PathBuf,
+
+    /// 打印生效的配置（密钥已脱敏）后退出
+    #[arg(long)]
+    print_config: bool,
+
+    /// 配置文件变化时自动重新加载，等同于收到 SIGHUP
+    #[arg(long)]
+    watch_config: bool,
+}
+
+#[derive(clap::Args)]
+struct ConfigArgs {
+    /// 配置文件路径
+    #[arg(short, long, default_value = "config.toml")]
+    config: PathBuf,
+}
+
+#[derive(clap::Args)]
+struct GraphArgs {
+    /// 配置文件路径
+    #[arg(short, long, default_value = "config.toml")]
+    config: PathBuf,
+
+    /// 输出格式
+    #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+    format: GraphFormat,
 }
 
-fn setup_logger(log_file_path: &Path) -> std::result::Result<(), fern::InitError> {
+#[derive(Clone, Copy, ValueEnum)]
+enum GraphFormat {
+    Dot,
+    Json,
+}
+
+/// 不写日志文件时只输出到 stderr，stdout 留给命令的结果
+fn setup_logger(log_file_path: Option<&Path>) -> std::result::Result<(), fern::InitError> {
     let colors = ColoredLevelConfig::new()
         .debug(Color::Cyan)
         .error(Color::Red)
@@ -52,6 +117,13 @@ fn setup_logger(log_file_path: &Path) -> std::result::Result<(), fern::InitError
             }
             let target = target;
 
+            // 日志中不输出已解析的密钥
+            let message = if config::secret::has_secrets() {
+                config::secret::redact(&message.to_string())
+            } else {
+                message.to_string()
+            };
+
             if use_color {
                 out.finish(format_args!(
                     "[{} {} {}] {}",
@@ -72,44 +144,64 @@ fn setup_logger(log_file_path: &Path) -> std::result::Result<(), fern::InitError
         }
     };
 
-    let log_level = std::env::var("RUST_LOG")
-        .unwrap_or_else(|_| "info".to_string())
-        .parse()
-        .expect("Invalid log level");
+    // 日志级别由 `global.log` 决定, 加载配置前使用 `RUST_LOG`
+    let Some(log_file_path) = log_file_path else {
+        fern::Dispatch::new()
+            .format(make_formatter(true))
+            .filter(utils::logging::enabled)
+            .chain(std::io::stderr())
+            .apply()?;
+        log::set_max_level(utils::logging::max_level());
+        return Ok(());
+    };
 
     let file_dispatch = fern::Dispatch::new()
         .format(make_formatter(false))
-        .level(log_level)
         .chain(fern::log_file(log_file_path)?);
 
     let stdout_dispatch = fern::Dispatch::new()
         .format(make_formatter(true))
-        .level(log_level)
         .chain(std::io::stdout());
 
     fern::Dispatch::new()
+        .filter(utils::logging::enabled)
         .chain(stdout_dispatch)
         .chain(file_dispatch)
         .apply()?;
+    log::set_max_level(utils::logging::max_level());
 
     Ok(())
 }
 
 #[tokio::main]
 pub async fn main() -> miette::Result<()> {
-    console_subscriber::init();
-
     let args = Args::parse();
 
-    setup_logger(args.log_file.as_path()).into_diagnostic()?;
+    match args.command.unwrap_or(Command::Run(args.run)) {
+        Command::Run(args) => run(args).await,
+        Command::Validate(args) => validate(args),
+        Command::Graph(args) => graph(args),
+    }
+}
+
+async fn run(args: RunArgs) -> miette::Result<()> {
+    console_subscriber::init();
+
+    setup_logger(Some(args.log_file.as_path())).into_diagnostic()?;
 
     info!("Starting the application");
     info!("Using config file: {}", args.config.display());
     info!("Writing logs to: {}", args.log_file.display());
 
     let config = Config::load_from_file(&args.config)?;
+    utils::logging::apply(&config.global.log);
     info!("Loaded config from {}", args.config.display());
 
+    if args.print_config {
+        println!("{}", config.effective_config()?);
+        return Ok(());
+    }
+
     let ctx = tokio_util::sync::CancellationToken::new();
     let child_token = ctx.child_token();
 
@@ -119,9 +211,45 @@ pub async fn main() -> miette::Result<()> {
     })
     .into_diagnostic()?;
 
-    let mgr = manager::try_create_from_config(config)?;
+    let reloads =
+        manager::spawn_reload_task(args.config.clone(), args.watch_config).into_diagnostic()?;
+    let mgr = manager::try_create_from_config(config)?.with_reloads(reloads);
     mgr.run(child_token).await?;
 
     info!("Application has exited");
     Ok(())
 }
+
+/// 配置有误时以 miette 的诊断信息失败退出，适合在 CI 中使用
+///
+/// 所有组件都会被创建一遍，但不监听地址、不创建文件，警告输出到 stderr
+fn validate(args: ConfigArgs) -> miette::Result<()> {
+    setup_logger(None).into_diagnostic()?;
+
+    let config = Config::load_from_file(&args.config)?;
+    let mgr = manager::dry_run_from_config(config)?;
+
+    for (kind, tag) in mgr.components() {
+        println!("{:<8} {}", kind, tag);
+    }
+    println!();
+    println!("{}", mgr.snapshot().dot);
+    println!("{}: ok", args.config.display());
+    Ok(())
+}
+
+fn graph(args: GraphArgs) -> miette::Result<()> {
+    setup_logger(None).into_diagnostic()?;
+
+    let config = Config::load_from_file(&args.config)?;
+    let snapshot = manager::channel_graph_from_config(&config)?.snapshot();
+
+    match args.format {
+        GraphFormat::Dot => println!("{}", snapshot.dot),
+        GraphFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&snapshot.view()).into_diagnostic()?
+        ),
+    }
+    Ok(())
+}
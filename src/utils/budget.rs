@@ -0,0 +1,92 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use tokio_util::sync::CancellationToken;
+
+/// Soft deadline of a single poll.
+///
+/// Long-running work checks it at natural boundaries (between records, between chunks) and
+/// leaves the rest for the next poll. Cancellation is checked at the same boundaries, so that
+/// a huge batch never delays the shutdown by more than the budget.
+#[derive(Debug, Clone)]
+pub struct PollBudget {
+    deadline: Instant,
+    ctx: CancellationToken,
+}
+
+impl PollBudget {
+    pub fn new(max_duration: Duration, ctx: CancellationToken) -> Self {
+        Self {
+            deadline: Instant::now() + max_duration,
+            ctx,
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.ctx.is_cancelled()
+    }
+
+    pub fn exhausted(&self) -> bool {
+        self.is_cancelled() || Instant::now() >= self.deadline
+    }
+}
+
+/// Process the carried over items until the budget is exhausted, returns how many were
+/// processed. At least one item is processed, so that a tiny budget still makes progress.
+pub fn drain_carry<T>(
+    carry: &mut VecDeque<T>,
+    budget: &PollBudget,
+    mut process: impl FnMut(T),
+) -> usize {
+    let mut n = 0;
+    while let Some(item) = carry.pop_front() {
+        process(item);
+        n += 1;
+
+        if budget.exhausted() {
+            break;
+        }
+    }
+    n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drain_within_budget() {
+        let mut carry = (0..1000).collect::<VecDeque<_>>();
+        let mut seen = Vec::new();
+
+        let mut polls = 0;
+        while !carry.is_empty() {
+            polls += 1;
+            let budget = PollBudget::new(Duration::from_millis(1), CancellationToken::new());
+            drain_carry(&mut carry, &budget, |i| {
+                std::thread::sleep(Duration::from_micros(100));
+                seen.push(i);
+            });
+        }
+
+        assert!(polls > 1, "drained in a single poll");
+        assert_eq!(seen, (0..1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_cancelled_budget() {
+        let ctx = CancellationToken::new();
+        let budget = PollBudget::new(Duration::from_secs(60), ctx.clone());
+        assert!(!budget.exhausted());
+
+        ctx.cancel();
+        assert!(budget.exhausted());
+
+        // still makes progress
+        let mut carry = VecDeque::from([1, 2, 3]);
+        assert_eq!(drain_carry(&mut carry, &budget, |_| {}), 1);
+        assert_eq!(carry.len(), 2);
+    }
+}
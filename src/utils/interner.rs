@@ -0,0 +1,88 @@
+use log::{info, warn};
+
+use crate::{
+    config::global::{interner_growth_warn_threshold, interner_report_interval},
+    core::types::{interner_stats, interner_top_counted, InternerStats},
+};
+
+// Strings counted the most, logged with each report
+const REPORT_TOP: usize = 10;
+
+/// Remembers the previous report, to tell how much the interner grew since.
+#[derive(Debug)]
+struct InternerAudit {
+    growth_warn_threshold: usize,
+    last: Option<InternerStats>,
+}
+
+impl InternerAudit {
+    fn new(growth_warn_threshold: usize) -> Self {
+        Self {
+            growth_warn_threshold,
+            last: None,
+        }
+    }
+
+    /// The strings interned since the previous report, Some when over the threshold.
+    fn check(&mut self, stats: InternerStats) -> Option<usize> {
+        let last = self.last.replace(stats)?;
+        let growth = stats.interned_strings.saturating_sub(last.interned_strings);
+        (growth > self.growth_warn_threshold).then_some(growth)
+    }
+}
+
+/// Logs the statistics of the interner every `global.interner_report_interval`, warning when
+/// it grew by more than `global.interner_growth_warn_threshold` strings since the previous
+/// report: what is interned is never freed, a label of high cardinality makes it grow forever.
+pub fn spawn_interner_report_task() {
+    let Some(interval) = interner_report_interval() else {
+        return;
+    };
+
+    let mut audit = InternerAudit::new(interner_growth_warn_threshold());
+    tokio::task::Builder::new()
+        .name("interner report")
+        .spawn(async move {
+            loop {
+                tokio::time::sleep(interval.get()).await;
+                let stats = interner_stats();
+                let top = interner_top_counted(REPORT_TOP)
+                    .into_iter()
+                    .map(|(s, count)| format!("{:?} ({})", s, count))
+                    .collect::<Vec<_>>();
+                info!(
+                    "Interner: {} interned strings, {} counted, about {} bytes, counted the most: [{}]",
+                    stats.interned_strings,
+                    stats.counter_entries,
+                    stats.approx_bytes,
+                    top.join(", ")
+                );
+                if let Some(growth) = audit.check(stats) {
+                    warn!(
+                        "Interner grew by {} strings in {}, which are never freed: a label of high cardinality?",
+                        growth, interval
+                    );
+                }
+            }
+        })
+        .expect("Failed to spawn interner report task");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_growth() {
+        let stats = |interned_strings| InternerStats {
+            counter_entries: 0,
+            interned_strings,
+            approx_bytes: 0,
+        };
+        let mut audit = InternerAudit::new(100);
+        assert_eq!(audit.check(stats(5000)), None);
+        assert_eq!(audit.check(stats(5100)), None);
+        assert_eq!(audit.check(stats(5250)), Some(150));
+        assert_eq!(audit.check(stats(5250)), None);
+    }
+}
@@ -0,0 +1,120 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::core::tag::TagId;
+
+/// Tracks whether the watched actors are still making progress.
+///
+/// An actor beats once per completed poll. Only actors whose polls are bounded (by a receive
+/// timeout or a poll budget) should be watched: an inbound legitimately waits for its producers
+/// as long as they are silent.
+#[derive(Debug)]
+pub struct Liveness {
+    start: Instant,
+    actors: Mutex<Vec<(TagId, Arc<AtomicU64>)>>,
+}
+
+/// Handle of a watched actor, see [`Liveness::register`].
+#[derive(Debug, Clone)]
+pub struct Heartbeat {
+    start: Instant,
+    last_beat: Arc<AtomicU64>,
+    beats: Arc<AtomicU64>,
+    // Whether the actor held records back at its last beat
+    pending: Arc<AtomicBool>,
+}
+
+impl Heartbeat {
+    pub fn beat(&self) {
+        let elapsed = self.start.elapsed().as_millis() as u64;
+        self.last_beat.store(elapsed, Ordering::Relaxed);
+        self.beats.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of beats so far, i.e. of completed polls.
+    pub fn beats(&self) -> u64 {
+        self.beats.load(Ordering::Relaxed)
+    }
+
+    pub fn set_pending(&self, pending: bool) {
+        self.pending.store(pending, Ordering::Relaxed);
+    }
+
+    /// Whether the actor still held records after its last poll, see
+    /// [`Actor::has_pending`](crate::core::actor::Actor::has_pending).
+    pub fn is_pending(&self) -> bool {
+        self.pending.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for Liveness {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            actors: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl Liveness {
+    pub fn register(&self, tag: TagId) -> Heartbeat {
+        let heartbeat = Heartbeat {
+            start: self.start,
+            last_beat: Arc::new(AtomicU64::new(0)),
+            beats: Arc::new(AtomicU64::new(0)),
+            pending: Arc::new(AtomicBool::new(false)),
+        };
+        heartbeat.beat();
+
+        let mut actors = self.actors.lock().unwrap();
+        actors.push((tag, heartbeat.last_beat.clone()));
+        heartbeat
+    }
+
+    /// Stop watching an actor, once it is removed.
+    pub fn unregister(&self, tag: &TagId) {
+        let mut actors = self.actors.lock().unwrap();
+        actors.retain(|(t, _)| t != tag);
+    }
+
+    /// The watched actors which have not beaten for longer than `max_silence`.
+    pub fn stalled(&self, max_silence: Duration) -> Vec<TagId> {
+        let now = self.start.elapsed().as_millis() as u64;
+        let max_silence = max_silence.as_millis() as u64;
+
+        let actors = self.actors.lock().unwrap();
+        actors
+            .iter()
+            .filter(|(_, last_beat)| {
+                now.saturating_sub(last_beat.load(Ordering::Relaxed)) > max_silence
+            })
+            .map(|(tag, _)| tag.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tag::{OutboundTagId, PipeTagId};
+
+    #[test]
+    fn test_stalled() {
+        let liveness = Liveness::default();
+        let pipe = liveness.register(PipeTagId::new("timeseries").into());
+        let _outbound = liveness.register(OutboundTagId::new("parquet").into());
+        assert!(liveness.stalled(Duration::from_millis(20)).is_empty());
+
+        std::thread::sleep(Duration::from_millis(50));
+        pipe.beat();
+        assert_eq!(
+            liveness.stalled(Duration::from_millis(20)),
+            vec![TagId::from(OutboundTagId::new("parquet"))]
+        );
+    }
+}
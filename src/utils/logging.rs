@@ -0,0 +1,239 @@
+//! Log levels per target and per actor from `global.log`, replaced on reload.
+//!
+//! Actors log through [`actor_log!`](crate::actor_log) and friends, whose target is the module
+//! followed by the tag of the actor, e.g. `void::core::pipe::route[pipe:split]`.
+
+use std::collections::HashMap;
+
+use log::LevelFilter;
+use once_cell::sync::Lazy;
+
+use crate::{
+    config::global::logging::{LogConfig, TAG_TARGET_PREFIX},
+    core::tag::TagId,
+};
+
+static FILTER: Lazy<spin::RwLock<LogFilter>> =
+    Lazy::new(|| spin::RwLock::new(LogFilter::new(&LogConfig::default())));
+
+struct LogFilter {
+    default: LevelFilter,
+    // Module prefixes, the longest first
+    targets: Vec<(String, LevelFilter)>,
+    // By full tag or by name
+    tags: HashMap<String, LevelFilter>,
+}
+
+impl LogFilter {
+    fn new(cfg: &LogConfig) -> Self {
+        let mut targets = Vec::new();
+        let mut tags = HashMap::new();
+        for (target, &level) in &cfg.targets {
+            match target.strip_prefix(TAG_TARGET_PREFIX) {
+                Some(tag) => {
+                    tags.insert(tag.to_string(), level.into());
+                }
+                // The logs show the crate as `app`
+                None => match target.strip_prefix("app") {
+                    Some(rest) if rest.is_empty() || rest.starts_with("::") => {
+                        targets.push((format!("void{}", rest), level.into()))
+                    }
+                    _ => targets.push((target.clone(), level.into())),
+                },
+            }
+        }
+        targets.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+
+        LogFilter {
+            default: cfg.level.map_or_else(env_level, Into::into),
+            targets,
+            tags,
+        }
+    }
+
+    fn level(&self, target: &str) -> LevelFilter {
+        let (module, tag) = match target.strip_suffix(']').and_then(|t| t.split_once('[')) {
+            Some((module, tag)) => (module, Some(tag)),
+            None => (target, None),
+        };
+
+        if let Some(tag) = tag {
+            let name = tag.split_once(':').map_or(tag, |(_, name)| name);
+            if let Some(&level) = self.tags.get(tag).or_else(|| self.tags.get(name)) {
+                return level;
+            }
+        }
+
+        self.targets
+            .iter()
+            .find(|(prefix, _)| {
+                module
+                    .strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .map_or(self.default, |&(_, level)| level)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.targets
+            .iter()
+            .map(|&(_, level)| level)
+            .chain(self.tags.values().copied())
+            .fold(self.default, Ord::max)
+    }
+}
+
+/// `RUST_LOG`, `info` without it.
+fn env_level() -> LevelFilter {
+    std::env::var("RUST_LOG")
+        .unwrap_or_else(|_| "info".to_string())
+        .parse()
+        .expect("Invalid log level")
+}
+
+/// Whether a record passes the levels applied last, used as the filter of the logger.
+pub fn enabled(metadata: &log::Metadata) -> bool {
+    metadata.level() <= FILTER.read().level(metadata.target())
+}
+
+/// Replace the levels, e.g. after loading or reloading the config.
+pub fn apply(cfg: &LogConfig) {
+    let filter = LogFilter::new(cfg);
+    log::set_max_level(filter.max_level());
+    *FILTER.write() = filter;
+}
+
+/// The most verbose of the levels applied last.
+pub fn max_level() -> LevelFilter {
+    FILTER.read().max_level()
+}
+
+pub fn actor_target(module: &str, tag: &TagId) -> String {
+    format!("{}[{}]", module, tag)
+}
+
+/// Log with the tag of an actor in the target, so that `"tag:<name>"` in `global.log.targets`
+/// applies to it. The tag shows up in the target, the message does not repeat it.
+#[macro_export]
+macro_rules! actor_log {
+    ($tag:expr, $level:expr, $($arg:tt)+) => {{
+        let level = $level;
+        if level <= ::log::max_level() {
+            let target = $crate::utils::logging::actor_target(module_path!(), &$tag);
+            ::log::log!(target: &target, level, $($arg)+);
+        }
+    }};
+}
+
+#[macro_export]
+macro_rules! actor_error {
+    ($tag:expr, $($arg:tt)+) => {
+        $crate::actor_log!($tag, ::log::Level::Error, $($arg)+)
+    };
+}
+
+#[macro_export]
+macro_rules! actor_warn {
+    ($tag:expr, $($arg:tt)+) => {
+        $crate::actor_log!($tag, ::log::Level::Warn, $($arg)+)
+    };
+}
+
+#[macro_export]
+macro_rules! actor_info {
+    ($tag:expr, $($arg:tt)+) => {
+        $crate::actor_log!($tag, ::log::Level::Info, $($arg)+)
+    };
+}
+
+#[macro_export]
+macro_rules! actor_debug {
+    ($tag:expr, $($arg:tt)+) => {
+        $crate::actor_log!($tag, ::log::Level::Debug, $($arg)+)
+    };
+}
+
+#[macro_export]
+macro_rules! actor_trace {
+    ($tag:expr, $($arg:tt)+) => {
+        $crate::actor_log!($tag, ::log::Level::Trace, $($arg)+)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tag::{InboundTagId, OutboundTagId, PipeTagId};
+
+    #[test]
+    fn test_log_filter() {
+        let cfg: LogConfig = toml::from_str(
+            r#"
+            level = "warn"
+
+            [targets]
+            "app::core::pipe" = "info"
+            "app::core::pipe::timeseries" = "debug"
+            "hyper" = "off"
+            "tag:prometheus_out" = "trace"
+            "tag:pipe:route" = "error"
+            "#,
+        )
+        .unwrap();
+        let filter = LogFilter::new(&cfg);
+
+        assert_eq!(filter.level("void::core::manager"), LevelFilter::Warn);
+        assert_eq!(filter.level("void::core::pipe::route"), LevelFilter::Info);
+        assert_eq!(
+            filter.level("void::core::pipe::timeseries::units"),
+            LevelFilter::Debug
+        );
+        // Only whole path segments
+        assert_eq!(filter.level("void::core::pipeline"), LevelFilter::Warn);
+        assert_eq!(filter.level("hyper::proto::h1"), LevelFilter::Off);
+
+        // The actor overrides its module
+        let prometheus = OutboundTagId::new("prometheus_out").into();
+        let target = actor_target("void::core::outbound::prometheus", &prometheus);
+        assert_eq!(filter.level(&target), LevelFilter::Trace);
+        let route = PipeTagId::new("route").into();
+        assert_eq!(
+            filter.level(&actor_target("void::core::pipe::route", &route)),
+            LevelFilter::Error
+        );
+        let other = PipeTagId::new("other").into();
+        assert_eq!(
+            filter.level(&actor_target("void::core::pipe::timeseries", &other)),
+            LevelFilter::Debug
+        );
+
+        assert_eq!(filter.max_level(), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn test_inbound_tag_override() {
+        let cfg: LogConfig = toml::from_str(
+            r#"
+            level = "info"
+
+            [targets]
+            "tag:inbound:noisy" = "error"
+            "#,
+        )
+        .unwrap();
+        let filter = LogFilter::new(&cfg);
+        let enabled = |tag: TagId, level: log::Level| {
+            let target = actor_target("void::core::inbound::tcp", &tag);
+            level <= filter.level(&target)
+        };
+
+        // e.g. the rejected connections of a flooded listener
+        let noisy: TagId = InboundTagId::new("noisy").into();
+        assert!(!enabled(noisy.clone(), log::Level::Warn));
+        assert!(enabled(noisy, log::Level::Error));
+
+        let quiet: TagId = InboundTagId::new("quiet").into();
+        assert!(enabled(quiet.clone(), log::Level::Warn));
+        assert!(!enabled(quiet, log::Level::Debug));
+    }
+}
@@ -1,7 +1,19 @@
-mod duration;
+#[cfg(test)]
+pub mod alloc;
+pub mod budget;
+mod interner;
+pub mod liveness;
+pub mod logging;
+pub mod rate_limit;
 pub mod recv;
+pub mod retry;
+pub mod stats;
+#[cfg(unix)]
+pub mod systemd;
+pub mod throttle;
 mod timeit;
 pub mod tracing;
 
-pub use duration::parse_duration;
+pub use interner::spawn_interner_report_task;
+pub use stats::spawn_stats_task;
 pub use tracing::spawn_tracing_task;
@@ -0,0 +1,113 @@
+use std::{sync::Mutex, time::Duration};
+
+use tokio::time::Instant;
+
+/// A token bucket shared by the tasks sending requests, refilled at `rate` tokens per second
+/// up to one second's worth.
+///
+/// A token is reserved even when the bucket is empty, so that the waiting tasks go through in
+/// the order they asked.
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    // Negative once reserved ahead
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64) -> Self {
+        let burst = rate.max(1.0);
+        Self {
+            rate,
+            burst,
+            bucket: Mutex::new(Bucket {
+                tokens: burst,
+                last: Instant::now(),
+            }),
+        }
+    }
+
+    /// How long to wait before the reserved token is available.
+    fn reserve(&self) -> Duration {
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.last).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.last = now;
+
+        bucket.tokens -= 1.0;
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            // A rate too small for the wait to fit in a Duration never frees a token
+            Duration::try_from_secs_f64(-bucket.tokens / self.rate).unwrap_or(Duration::MAX)
+        }
+    }
+
+    pub async fn acquire(&self) {
+        let wait = self.reserve();
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserve() {
+        let limiter = RateLimiter::new(10.0);
+        // The burst goes through at once
+        for _ in 0..10 {
+            assert!(limiter.reserve().is_zero());
+        }
+        let wait = limiter.reserve();
+        assert!(wait > Duration::from_millis(90) && wait <= Duration::from_millis(100));
+        let wait = limiter.reserve();
+        assert!(wait > Duration::from_millis(190) && wait <= Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn test_acquire() {
+        let limiter = std::sync::Arc::new(RateLimiter::new(50.0));
+        let start = Instant::now();
+        let tasks = (0..60)
+            .map(|_| {
+                let limiter = limiter.clone();
+                tokio::spawn(async move { limiter.acquire().await })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        // 50 at once, the other 10 at 50 per second
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(190), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(400), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn test_slow_rate() {
+        let limiter = RateLimiter::new(0.5);
+        assert!(limiter.reserve().is_zero());
+        let wait = limiter.reserve();
+        assert!(wait > Duration::from_millis(1900) && wait <= Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_tiny_rate() {
+        let limiter = RateLimiter::new(f64::MIN_POSITIVE);
+        assert!(limiter.reserve().is_zero());
+        assert_eq!(limiter.reserve(), Duration::MAX);
+    }
+}
@@ -1,11 +1,11 @@
-use std::time::Duration;
+use std::{collections::HashMap, time::Duration};
 
-use crate::core::{manager::TaggedReceiver, tag::TagId, types::Record};
-use futures::StreamExt;
+use crate::actor_warn;
+use crate::core::{manager::TaggedReceiver, metrics, tag::TagId, types::Record};
 use log::{debug, warn};
 use miette::Diagnostic;
 use thiserror::Error;
-use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
 use tokio_util::sync::CancellationToken;
 
 #[derive(Debug, Error, Diagnostic)]
@@ -50,7 +50,7 @@ pub async fn recv(
                     return Err(Error::ChannelClosed(tag))
                 },
                 (tag, Err(RecvError::Lagged(n))) => {
-                    warn!("{}: inbound lagged {}", tag, n);
+                    actor_warn!(tag, "inbound lagged {}", n);
                     time_left = timeout.saturating_sub(now.elapsed());
                     continue 'body;
                 }
@@ -65,16 +65,34 @@ pub async fn recv(
     }
 }
 
+/// Receive up to `max_batch_size` records. Waits up to `timeout` for the first record, then
+/// returns whatever was received once the first one has waited `max_batch_latency`, so that a
+/// large batch size adds no latency in quiet periods. `Error::Timeout` means that nothing
+/// arrived.
 pub async fn recv_batch(
     who: &TagId,
     inbounds: &mut [TaggedReceiver],
     timeout: Option<Duration>,
-    num_records: usize,
+    max_batch_size: usize,
+    max_batch_latency: Duration,
     ctx: CancellationToken,
 ) -> Result<Vec<Record>, Error> {
-    let now = std::time::Instant::now();
+    let records = collect_batch(inbounds, timeout, max_batch_size, max_batch_latency, ctx).await?;
+    metrics::actor_metrics(who).observe_batch_size(records.len());
+    Ok(records)
+}
+
+async fn collect_batch(
+    inbounds: &mut [TaggedReceiver],
+    timeout: Option<Duration>,
+    max_batch_size: usize,
+    max_batch_latency: Duration,
+    ctx: CancellationToken,
+) -> Result<Vec<Record>, Error> {
+    let now = tokio::time::Instant::now();
     let timeout = timeout.unwrap_or(Duration::from_secs(999));
-    let mut time_left = timeout;
+    // Until the first record arrives, then the deadline of the batch
+    let mut deadline = now + timeout;
 
     let mut records = inbounds
         .iter_mut()
@@ -82,11 +100,11 @@ pub async fn recv_batch(
             let mut buffer = Vec::new();
             while let Ok(record) = inbound.try_recv() {
                 buffer.push(record);
-                if buffer.len() >= num_records {
+                if buffer.len() >= max_batch_size {
                     break;
                 }
 
-                if now.elapsed() >= timeout {
+                if now.elapsed() >= max_batch_latency {
                     break;
                 }
             }
@@ -94,9 +112,12 @@ pub async fn recv_batch(
         })
         .collect::<Vec<_>>();
 
-    if records.len() >= num_records {
+    if records.len() >= max_batch_size || now.elapsed() >= max_batch_latency {
         return Ok(records);
     }
+    if !records.is_empty() {
+        deadline = now + max_batch_latency;
+    }
 
     loop {
         let futs = inbounds.iter_mut().map(|inbound| {
@@ -108,11 +129,12 @@ pub async fn recv_batch(
         let last_active_index = tokio::select! {
             (record, i, _) = futures::future::select_all(futs) => match record {
                 (_, Ok(record)) => {
-                  time_left = timeout.saturating_sub(now.elapsed());
-
+                  if records.is_empty() {
+                      deadline = tokio::time::Instant::now() + max_batch_latency;
+                  }
                   records.push(record);
 
-                  if records.len() >= num_records {
+                  if records.len() >= max_batch_size {
                       return Ok(records);
                   }
 
@@ -122,13 +144,12 @@ pub async fn recv_batch(
                     return Err(Error::ChannelClosed(tag))
                 },
                 (tag, Err(RecvError::Lagged(n))) => {
-                    warn!("{}: inbound lagged {}", tag, n);
-                    time_left = timeout.saturating_sub(now.elapsed());
+                    actor_warn!(tag, "inbound lagged {}", n);
 
                     i
                 }
             },
-            _ = tokio::time::sleep(time_left) => match records.len() {
+            _ = tokio::time::sleep_until(deadline) => match records.len() {
                 0 => return Err(Error::Timeout),
                 _ => return Ok(records),
             },
@@ -139,9 +160,407 @@ pub async fn recv_batch(
 
         while let Ok(record) = inbounds[last_active_index].try_recv() {
             records.push(record);
-            if records.len() >= num_records || now.elapsed() >= timeout {
+            if records.len() >= max_batch_size || tokio::time::Instant::now() >= deadline {
                 return Ok(records);
             }
         }
     }
 }
+
+/// The occupancy of the fullest of the inbounds, see [`TaggedReceiver::occupancy`].
+pub fn occupancy(inbounds: &[TaggedReceiver]) -> f64 {
+    inbounds
+        .iter()
+        .map(TaggedReceiver::occupancy)
+        .fold(0.0, f64::max)
+}
+
+/// Same as [`recv_batch`], with the records grouped by the inbound they come from.
+///
+/// The inbounds are read in turn, one record at a time, so that a busy inbound cannot fill
+/// the batch while the records of the others wait: each inbound with records available gets
+/// at least its share of `max_batch_size`.
+pub async fn recv_batch_grouped(
+    who: &TagId,
+    inbounds: &mut [TaggedReceiver],
+    timeout: Option<Duration>,
+    max_batch_size: usize,
+    max_batch_latency: Duration,
+    ctx: CancellationToken,
+) -> Result<HashMap<TagId, Vec<Record>>, Error> {
+    let records = collect_batch_grouped(
+        who,
+        inbounds,
+        timeout,
+        max_batch_size,
+        max_batch_latency,
+        ctx,
+    )
+    .await?;
+    metrics::actor_metrics(who).observe_batch_size(records.values().map(Vec::len).sum());
+    Ok(records)
+}
+
+async fn collect_batch_grouped(
+    who: &TagId,
+    inbounds: &mut [TaggedReceiver],
+    timeout: Option<Duration>,
+    max_batch_size: usize,
+    max_batch_latency: Duration,
+    ctx: CancellationToken,
+) -> Result<HashMap<TagId, Vec<Record>>, Error> {
+    let timeout = timeout.unwrap_or(Duration::from_secs(999));
+    // Until the first record arrives, then the deadline of the batch
+    let mut deadline = tokio::time::Instant::now() + timeout;
+    let mut batch = GroupedBatch::default();
+    let tags = inbounds
+        .iter()
+        .map(|inbound| inbound.tag().clone())
+        .collect::<Vec<_>>();
+
+    loop {
+        let was_empty = batch.len == 0;
+        batch.drain_fairly(inbounds, max_batch_size);
+        if batch.len >= max_batch_size {
+            return Ok(batch.records);
+        }
+        if was_empty && batch.len > 0 {
+            deadline = tokio::time::Instant::now() + max_batch_latency;
+        }
+        if batch.len > 0 && tokio::time::Instant::now() >= deadline {
+            return Ok(batch.records);
+        }
+
+        let futs = inbounds
+            .iter_mut()
+            .map(|inbound| Box::pin(async move { inbound.recv().await }));
+
+        tokio::select! {
+            (record, i, _) = futures::future::select_all(futs) => match record {
+                Ok(record) => {
+                    if batch.len == 0 {
+                        deadline = tokio::time::Instant::now() + max_batch_latency;
+                    }
+                    batch.push(&tags[i], record);
+                }
+                Err(RecvError::Closed) => {
+                    return Err(Error::ChannelClosed(tags[i].clone()))
+                }
+                Err(RecvError::Lagged(n)) => {
+                    warn!("{}: inbound lagged {}", tags[i], n);
+                }
+            },
+            _ = tokio::time::sleep_until(deadline) => match batch.len {
+                0 => return Err(Error::Timeout),
+                _ => return Ok(batch.records),
+            },
+            _ = ctx.cancelled() => {
+                return Err(Error::Canceled);
+            }
+        }
+
+        if batch.len >= max_batch_size {
+            debug!("{} received a full batch of {} records", who, batch.len);
+            return Ok(batch.records);
+        }
+    }
+}
+
+#[derive(Default)]
+struct GroupedBatch {
+    records: HashMap<TagId, Vec<Record>>,
+    len: usize,
+}
+
+impl GroupedBatch {
+    fn push(&mut self, inbound: &TagId, record: Record) {
+        match self.records.get_mut(inbound) {
+            Some(records) => records.push(record),
+            None => {
+                self.records.insert(inbound.clone(), vec![record]);
+            }
+        }
+        self.len += 1;
+    }
+
+    /// Take the records already received, one per inbound in turn.
+    fn drain_fairly(&mut self, inbounds: &mut [TaggedReceiver], max_batch_size: usize) {
+        let mut drained = vec![false; inbounds.len()];
+        while self.len < max_batch_size && drained.iter().any(|drained| !drained) {
+            for (inbound, drained) in inbounds.iter_mut().zip(drained.iter_mut()) {
+                if *drained || self.len >= max_batch_size {
+                    continue;
+                }
+
+                match inbound.try_recv() {
+                    Ok(record) => self.push(inbound.tag(), record),
+                    Err(TryRecvError::Lagged(n)) => {
+                        warn!("{}: inbound lagged {}", inbound.tag(), n)
+                    }
+                    Err(TryRecvError::Empty | TryRecvError::Closed) => *drained = true,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{
+        manager::ActorChannel,
+        tag::{InboundTagId, PipeTagId},
+    };
+
+    #[tokio::test]
+    async fn test_recv_batch_max_latency() {
+        let who: TagId = PipeTagId::new("batch").into();
+        let mut channel = ActorChannel::new(InboundTagId::new("a").into(), 16);
+        let mut inbounds = vec![channel.receiver(&who)];
+        let mut sender = channel.sender();
+
+        let timeout = Some(Duration::from_secs(5));
+        let latency = Duration::from_millis(20);
+
+        sender.send(Record::empty()).await.unwrap();
+        let start = std::time::Instant::now();
+        let records = recv_batch(
+            &who,
+            &mut inbounds,
+            timeout,
+            1024,
+            latency,
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(records.len(), 1);
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // 第一条记录在等待中到达，同样只等待 max_batch_latency
+        let delayed = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            sender.send(Record::empty()).await.unwrap();
+            sender
+        });
+        let start = std::time::Instant::now();
+        let records = recv_batch(
+            &who,
+            &mut inbounds,
+            timeout,
+            1024,
+            latency,
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(records.len(), 1);
+        assert!(start.elapsed() < Duration::from_secs(1));
+        let _sender = delayed.await.unwrap();
+
+        let result = recv_batch(
+            &who,
+            &mut inbounds,
+            Some(Duration::from_millis(50)),
+            1024,
+            latency,
+            CancellationToken::new(),
+        )
+        .await;
+        assert!(matches!(result, Err(Error::Timeout)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_recv_batch_linger() {
+        let who: TagId = PipeTagId::new("batch").into();
+        let mut channel = ActorChannel::new(InboundTagId::new("a").into(), 16);
+        let mut inbounds = vec![channel.receiver(&who)];
+        let mut sender = channel.sender();
+
+        let timeout = Some(Duration::from_secs(5));
+        let linger = Duration::from_millis(20);
+        let start = tokio::time::Instant::now();
+
+        // A trickle of records, far from filling the buffer
+        let producer = tokio::spawn(async move {
+            for at in [1000, 1010, 3000] {
+                tokio::time::sleep_until(start + Duration::from_millis(at)).await;
+                sender.send(Record::empty()).await.unwrap();
+            }
+            sender
+        });
+
+        // The window starts with the first record, the second one arrives within it
+        let records = recv_batch(
+            &who,
+            &mut inbounds,
+            timeout,
+            512,
+            linger,
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(start.elapsed(), Duration::from_millis(1020));
+
+        let records = recv_batch(
+            &who,
+            &mut inbounds,
+            timeout,
+            512,
+            linger,
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(start.elapsed(), Duration::from_millis(3020));
+        let mut sender = producer.await.unwrap();
+
+        // A full buffer does not wait for the linger
+        for _ in 0..4 {
+            sender.send(Record::empty()).await.unwrap();
+        }
+        let start = tokio::time::Instant::now();
+        let records = recv_batch(
+            &who,
+            &mut inbounds,
+            timeout,
+            4,
+            linger,
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        // Nothing during the whole interval
+        let result = recv_batch(
+            &who,
+            &mut inbounds,
+            timeout,
+            512,
+            linger,
+            CancellationToken::new(),
+        )
+        .await;
+        assert!(matches!(result, Err(Error::Timeout)));
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+    }
+
+    fn tagged(i: usize) -> Record {
+        let mut record = Record::empty();
+        record.set(crate::core::types::Symbol::new("i"), (i as i64).into());
+        record
+    }
+
+    #[tokio::test]
+    async fn test_recv_batch_grouped_fairness() {
+        let who: TagId = PipeTagId::new("batch").into();
+        let fast_tag: TagId = InboundTagId::new("fast").into();
+        let slow_tag: TagId = InboundTagId::new("slow").into();
+        let mut fast = ActorChannel::new(fast_tag.clone(), 1024);
+        let mut slow = ActorChannel::new(slow_tag.clone(), 1024);
+        let mut inbounds = vec![fast.receiver(&who), slow.receiver(&who)];
+        let (mut fast, mut slow) = (fast.sender(), slow.sender());
+
+        // The fast inbound produces 10 times as many records, and is read first
+        let mut fast_backlog = 0;
+        for round in 0..5 {
+            for i in 0..50 {
+                fast.send(tagged(i)).await.unwrap();
+            }
+            for i in 0..5 {
+                slow.send(tagged(i)).await.unwrap();
+            }
+            fast_backlog += 50;
+
+            let batch = recv_batch_grouped(
+                &who,
+                &mut inbounds,
+                Some(Duration::from_secs(1)),
+                20,
+                Duration::from_millis(20),
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+
+            assert_eq!(batch[&slow_tag].len(), 5, "round {}", round);
+            assert_eq!(batch[&fast_tag].len(), 15, "round {}", round);
+            fast_backlog -= 15;
+        }
+
+        // The backlog of the fast inbound is still delivered
+        let mut received = Vec::new();
+        while received.len() < fast_backlog {
+            let mut batch = recv_batch_grouped(
+                &who,
+                &mut inbounds,
+                Some(Duration::from_secs(1)),
+                64,
+                Duration::from_millis(20),
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+            assert!(!batch.contains_key(&slow_tag));
+            received.extend(batch.remove(&fast_tag).unwrap());
+        }
+        assert_eq!(received.len(), fast_backlog);
+    }
+
+    #[tokio::test]
+    async fn test_recv_batch_grouped_waits() {
+        let who: TagId = PipeTagId::new("batch").into();
+        let tag: TagId = InboundTagId::new("a").into();
+        let mut channel = ActorChannel::new(tag.clone(), 16);
+        let mut inbounds = vec![channel.receiver(&who)];
+        let mut sender = channel.sender();
+
+        let delayed = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            sender.send(tagged(0)).await.unwrap();
+            sender.send(tagged(1)).await.unwrap();
+            sender
+        });
+        let batch = recv_batch_grouped(
+            &who,
+            &mut inbounds,
+            Some(Duration::from_secs(5)),
+            1024,
+            Duration::from_millis(20),
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(batch[&tag].len(), 2);
+        let _sender = delayed.await.unwrap();
+
+        let result = recv_batch_grouped(
+            &who,
+            &mut inbounds,
+            Some(Duration::from_millis(30)),
+            1024,
+            Duration::from_millis(20),
+            CancellationToken::new(),
+        )
+        .await;
+        assert!(matches!(result, Err(Error::Timeout)));
+
+        let ctx = CancellationToken::new();
+        ctx.cancel();
+        let result = recv_batch_grouped(
+            &who,
+            &mut inbounds,
+            None,
+            1024,
+            Duration::from_millis(20),
+            ctx,
+        )
+        .await;
+        assert!(matches!(result, Err(Error::Canceled)));
+    }
+}
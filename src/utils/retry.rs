@@ -0,0 +1,485 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use crate::config::retry::RetryConfig;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Jitter {
+    /// Use the computed delay as is
+    None,
+    /// Pick a delay uniformly in `[0, delay]`
+    #[default]
+    Full,
+    /// Pick a delay uniformly in `[delay / 2, delay]`
+    Equal,
+}
+
+pub type Classifier<E> = Arc<dyn Fn(&E) -> bool + Send + Sync>;
+
+/// Minimum delay before the next attempt asked for by an error, e.g. a `Retry-After` header.
+pub type DelayHint<E> = Arc<dyn Fn(&E) -> Option<Duration> + Send + Sync>;
+
+pub struct RetryPolicy<E> {
+    pub max_attempts: usize,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: f64,
+    pub jitter: Jitter,
+    retryable: Classifier<E>,
+    delay_hint: DelayHint<E>,
+}
+
+impl<E> Clone for RetryPolicy<E> {
+    fn clone(&self) -> Self {
+        Self {
+            max_attempts: self.max_attempts,
+            initial_delay: self.initial_delay,
+            max_delay: self.max_delay,
+            multiplier: self.multiplier,
+            jitter: self.jitter,
+            retryable: self.retryable.clone(),
+            delay_hint: self.delay_hint.clone(),
+        }
+    }
+}
+
+impl<E> std::fmt::Debug for RetryPolicy<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("initial_delay", &self.initial_delay)
+            .field("max_delay", &self.max_delay)
+            .field("multiplier", &self.multiplier)
+            .field("jitter", &self.jitter)
+            .finish()
+    }
+}
+
+impl<E> RetryPolicy<E> {
+    /// Every error is considered retryable until a classifier is set.
+    pub fn new(max_attempts: usize, initial_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            initial_delay,
+            max_delay,
+            multiplier: 2.0,
+            jitter: Jitter::default(),
+            retryable: Arc::new(|_| true),
+            delay_hint: Arc::new(|_| None),
+        }
+    }
+
+    pub fn from_config(cfg: &RetryConfig) -> Self {
+        Self::new(
+            cfg.max_attempts,
+            cfg.initial_delay.into(),
+            cfg.max_delay.into(),
+        )
+        .with_multiplier(cfg.multiplier)
+        .with_jitter(cfg.jitter)
+    }
+
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    pub fn with_jitter(mut self, jitter: Jitter) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn with_classifier<F>(mut self, retryable: F) -> Self
+    where
+        F: Fn(&E) -> bool + Send + Sync + 'static,
+    {
+        self.retryable = Arc::new(retryable);
+        self
+    }
+
+    /// The delay before a retry is at least the one hinted by the error.
+    pub fn with_delay_hint<F>(mut self, delay_hint: F) -> Self
+    where
+        F: Fn(&E) -> Option<Duration> + Send + Sync + 'static,
+    {
+        self.delay_hint = Arc::new(delay_hint);
+        self
+    }
+
+    pub fn is_retryable(&self, error: &E) -> bool {
+        (self.retryable)(error)
+    }
+
+    pub fn backoff(&self) -> Backoff {
+        self.backoff_with_rng(StdRng::from_os_rng())
+    }
+
+    pub fn backoff_with_rng<R: Rng>(&self, rng: R) -> Backoff<R> {
+        Backoff::new(
+            self.initial_delay,
+            self.max_delay,
+            self.multiplier,
+            self.jitter,
+            rng,
+        )
+    }
+}
+
+/// Endless sequence of delays, for the components which manage their own retry loop.
+// Only the unix fifo resets it so far
+#[cfg_attr(not(unix), allow(dead_code))]
+#[derive(Debug, Clone)]
+pub struct Backoff<R = StdRng> {
+    initial_delay: Duration,
+    max_delay: Duration,
+    multiplier: f64,
+    jitter: Jitter,
+    current: Duration,
+    rng: R,
+}
+
+#[cfg_attr(not(unix), allow(dead_code))]
+impl Backoff {
+    pub fn from_config(cfg: &RetryConfig) -> Self {
+        Self::new(
+            cfg.initial_delay.into(),
+            cfg.max_delay.into(),
+            cfg.multiplier,
+            cfg.jitter,
+            StdRng::from_os_rng(),
+        )
+    }
+}
+
+impl<R: Rng> Backoff<R> {
+    pub fn new(
+        initial_delay: Duration,
+        max_delay: Duration,
+        multiplier: f64,
+        jitter: Jitter,
+        rng: R,
+    ) -> Self {
+        Self {
+            initial_delay,
+            max_delay,
+            multiplier,
+            jitter,
+            current: initial_delay.min(max_delay),
+            rng,
+        }
+    }
+
+    /// Start over from the initial delay, e.g. once a connection has been healthy for a while.
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub fn reset(&mut self) {
+        self.current = self.initial_delay.min(self.max_delay);
+    }
+
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub fn max_delay(&self) -> Duration {
+        self.max_delay
+    }
+}
+
+impl<R: Rng> Iterator for Backoff<R> {
+    type Item = Duration;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let delay = self.current;
+        // A huge max_delay times the multiplier may not fit in a Duration
+        self.current = Duration::try_from_secs_f64(self.current.as_secs_f64() * self.multiplier)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+
+        let nanos = delay.as_nanos() as u64;
+        let delay = match self.jitter {
+            Jitter::None => delay,
+            Jitter::Full => Duration::from_nanos(self.rng.random_range(0..=nanos)),
+            Jitter::Equal => Duration::from_nanos(nanos / 2 + self.rng.random_range(0..=nanos / 2)),
+        };
+
+        Some(delay)
+    }
+}
+
+#[derive(Debug)]
+pub enum RetryOutcome<T, E> {
+    Succeeded {
+        value: T,
+        attempts: usize,
+    },
+    /// Either the error was not retryable, or the attempts were exhausted.
+    GaveUp {
+        error: E,
+        attempts: usize,
+    },
+    Cancelled {
+        attempts: usize,
+        last_error: Option<E>,
+    },
+}
+
+/// Run `op` until it succeeds, fails with a non-retryable error, runs out of attempts,
+/// or `ctx` gets cancelled. `op` is given the 1-based attempt number.
+pub async fn retry<T, E, F, Fut>(
+    policy: &RetryPolicy<E>,
+    ctx: CancellationToken,
+    op: F,
+) -> RetryOutcome<T, E>
+where
+    F: FnMut(usize) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    retry_with_backoff(policy, policy.backoff(), ctx, op).await
+}
+
+pub async fn retry_with_backoff<T, E, F, Fut, R>(
+    policy: &RetryPolicy<E>,
+    mut backoff: Backoff<R>,
+    ctx: CancellationToken,
+    mut op: F,
+) -> RetryOutcome<T, E>
+where
+    F: FnMut(usize) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    R: Rng,
+{
+    let mut attempts = 0;
+    let mut last_error = None;
+
+    loop {
+        if ctx.is_cancelled() {
+            return RetryOutcome::Cancelled {
+                attempts,
+                last_error,
+            };
+        }
+
+        attempts += 1;
+        let error = match op(attempts).await {
+            Ok(value) => return RetryOutcome::Succeeded { value, attempts },
+            Err(error) => error,
+        };
+
+        if attempts >= policy.max_attempts || !policy.is_retryable(&error) {
+            return RetryOutcome::GaveUp { error, attempts };
+        }
+        let delay = backoff.next().unwrap_or(policy.max_delay);
+        let delay = match (policy.delay_hint)(&error) {
+            Some(hint) => delay.max(hint),
+            None => delay,
+        };
+        last_error = Some(error);
+
+        tokio::select! {
+            _ = ctx.cancelled() => {
+                return RetryOutcome::Cancelled {
+                    attempts,
+                    last_error,
+                };
+            }
+            _ = tokio::time::sleep(delay) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Status(u16);
+
+    fn policy(max_attempts: usize) -> RetryPolicy<Status> {
+        RetryPolicy::new(
+            max_attempts,
+            Duration::from_millis(1),
+            Duration::from_millis(4),
+        )
+        .with_jitter(Jitter::None)
+        .with_classifier(|s: &Status| s.0 >= 500)
+    }
+
+    fn seeded() -> StdRng {
+        StdRng::seed_from_u64(42)
+    }
+
+    #[test]
+    fn test_backoff_sequence_without_jitter() {
+        let backoff = Backoff::new(
+            Duration::from_millis(100),
+            Duration::from_millis(1000),
+            2.0,
+            Jitter::None,
+            seeded(),
+        );
+        let delays = backoff.take(6).map(|d| d.as_millis()).collect::<Vec<_>>();
+        assert_eq!(delays, vec![100, 200, 400, 800, 1000, 1000]);
+    }
+
+    #[test]
+    fn test_backoff_sequence_with_seeded_jitter() {
+        let make = |jitter| {
+            Backoff::new(
+                Duration::from_millis(100),
+                Duration::from_millis(1000),
+                2.0,
+                jitter,
+                seeded(),
+            )
+        };
+
+        let full = make(Jitter::Full).take(6).collect::<Vec<_>>();
+        assert_eq!(full, make(Jitter::Full).take(6).collect::<Vec<_>>());
+        for (delay, cap) in full.iter().zip([100, 200, 400, 800, 1000, 1000]) {
+            assert!(*delay <= Duration::from_millis(cap));
+        }
+
+        let equal = make(Jitter::Equal).take(6).collect::<Vec<_>>();
+        assert_eq!(equal, make(Jitter::Equal).take(6).collect::<Vec<_>>());
+        for (delay, cap) in equal.iter().zip([100, 200, 400, 800, 1000, 1000]) {
+            assert!(*delay >= Duration::from_millis(cap / 2));
+            assert!(*delay <= Duration::from_millis(cap));
+        }
+    }
+
+    #[test]
+    fn test_backoff_reset() {
+        let mut backoff = Backoff::new(
+            Duration::from_millis(10),
+            Duration::from_millis(100),
+            3.0,
+            Jitter::None,
+            seeded(),
+        );
+        backoff.next();
+        backoff.next();
+        backoff.reset();
+        assert_eq!(backoff.next(), Some(Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn test_backoff_overflowing_multiplier() {
+        let backoff = Backoff::new(
+            Duration::from_secs(u64::MAX / 2),
+            Duration::MAX,
+            4.0,
+            Jitter::None,
+            seeded(),
+        );
+        let delays = backoff.take(3).collect::<Vec<_>>();
+        assert_eq!(delays[1], Duration::MAX);
+        assert_eq!(delays[2], Duration::MAX);
+    }
+
+    #[tokio::test]
+    async fn test_retry_server_error_then_success() {
+        let policy = policy(5);
+        let outcome = retry_with_backoff(
+            &policy,
+            policy.backoff_with_rng(seeded()),
+            CancellationToken::new(),
+            |attempt| async move {
+                if attempt < 3 {
+                    Err(Status(503))
+                } else {
+                    Ok(attempt)
+                }
+            },
+        )
+        .await;
+
+        assert!(matches!(
+            outcome,
+            RetryOutcome::Succeeded {
+                value: 3,
+                attempts: 3
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_retry_client_error_not_retried() {
+        let policy = policy(5);
+        let calls = AtomicUsize::new(0);
+        let outcome: RetryOutcome<(), _> = retry(&policy, CancellationToken::new(), |_| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            async { Err(Status(400)) }
+        })
+        .await;
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(matches!(
+            outcome,
+            RetryOutcome::GaveUp {
+                error: Status(400),
+                attempts: 1
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_retry_gives_up_after_max_attempts() {
+        let policy = policy(3);
+        let outcome: RetryOutcome<(), _> = retry(&policy, CancellationToken::new(), |_| async {
+            Err(Status(500))
+        })
+        .await;
+
+        assert!(matches!(
+            outcome,
+            RetryOutcome::GaveUp {
+                error: Status(500),
+                attempts: 3
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_retry_cancelled_between_attempts() {
+        let policy = RetryPolicy::new(10, Duration::from_secs(60), Duration::from_secs(60))
+            .with_jitter(Jitter::None);
+        let ctx = CancellationToken::new();
+
+        let cancel = ctx.clone();
+        let outcome: RetryOutcome<(), _> = retry(&policy, ctx, move |_| {
+            cancel.cancel();
+            async { Err(Status(503)) }
+        })
+        .await;
+
+        // the 60s delay must not be waited for
+        match outcome {
+            RetryOutcome::Cancelled {
+                attempts,
+                last_error,
+            } => {
+                assert_eq!(attempts, 1);
+                assert_eq!(last_error, Some(Status(503)));
+            }
+            other => panic!("unexpected outcome: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_honors_delay_hint() {
+        // 429 asks to wait 50ms, way longer than the backoff
+        let policy =
+            policy(3).with_delay_hint(|s: &Status| (s.0 == 429).then(|| Duration::from_millis(50)));
+        let policy = policy.with_classifier(|s: &Status| s.0 == 429 || s.0 >= 500);
+
+        let start = std::time::Instant::now();
+        let outcome: RetryOutcome<(), _> = retry(&policy, CancellationToken::new(), |_| async {
+            Err(Status(429))
+        })
+        .await;
+        assert!(matches!(outcome, RetryOutcome::GaveUp { attempts: 3, .. }));
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
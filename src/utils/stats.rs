@@ -0,0 +1,209 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use dashmap::DashMap;
+
+use crate::{config::global::use_stats, core::types::interner_stats};
+
+/// Log2-bucketed histogram, bucket `i` counts the observations `v` with `2^(i-1) <= v < 2^i`.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    buckets: [u64; 65],
+    count: u64,
+    sum: u64,
+    max: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: [0; 65],
+            count: 0,
+            sum: 0,
+            max: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn observe(&mut self, value: u64) {
+        let bucket = (u64::BITS - value.leading_zeros()) as usize;
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(value);
+        self.max = self.max.max(value);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn mean(&self) -> u64 {
+        self.sum.checked_div(self.count).unwrap_or(0)
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// Upper bound of the bucket holding the given quantile.
+    pub fn quantile(&self, q: f64) -> u64 {
+        let target = (self.count as f64 * q).ceil() as u64;
+        let mut seen = 0;
+        for (i, n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= target && *n > 0 {
+                let upper = u64::MAX.checked_shr(u64::BITS - i as u32).unwrap_or(0);
+                return upper.min(self.max);
+            }
+        }
+
+        self.max
+    }
+}
+
+/// Interval of the stats summary.
+pub const STATS_INTERVAL: Duration = Duration::from_secs(10);
+
+// Counted even with the stats disabled, e.g. for the systemd status line
+static INGESTED_RECORDS: AtomicU64 = AtomicU64::new(0);
+
+/// Count a record accepted by an inbound.
+pub fn count_ingested() {
+    INGESTED_RECORDS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Total number of records accepted by the inbounds so far.
+#[cfg(unix)]
+pub fn ingested_records() -> u64 {
+    INGESTED_RECORDS.load(Ordering::Relaxed)
+}
+
+#[derive(Debug)]
+pub struct GlobalStats {
+    window_interval: Duration,
+    counters: DashMap<String, u64>,
+    histograms: DashMap<String, Histogram>,
+}
+
+impl GlobalStats {
+    pub fn new() -> Self {
+        Self {
+            window_interval: STATS_INTERVAL,
+            counters: DashMap::new(),
+            histograms: DashMap::new(),
+        }
+    }
+
+    pub fn incr(&self, key: &str, n: u64) {
+        if !use_stats() {
+            return;
+        }
+
+        match self.counters.get_mut(key) {
+            Some(mut entry) => *entry += n,
+            None => *self.counters.entry(key.to_string()).or_default() += n,
+        }
+    }
+
+    pub fn observe(&self, key: &str, value: u64) {
+        if !use_stats() {
+            return;
+        }
+
+        match self.histograms.get_mut(key) {
+            Some(mut entry) => entry.observe(value),
+            None => self
+                .histograms
+                .entry(key.to_string())
+                .or_default()
+                .observe(value),
+        }
+    }
+
+    fn summary(&self) {
+        let mut counters = self
+            .counters
+            .iter()
+            .map(|e| (e.key().clone(), *e.value()))
+            .collect::<Vec<_>>();
+        counters.sort();
+
+        let mut histograms = self
+            .histograms
+            .iter()
+            .map(|e| (e.key().clone(), e.value().clone()))
+            .collect::<Vec<_>>();
+        histograms.sort_by(|a, b| a.0.cmp(&b.0));
+
+        if counters.is_empty() && histograms.is_empty() {
+            return;
+        }
+
+        eprintln!("Stats Summary:");
+        eprintln!("=========================");
+        if !counters.is_empty() {
+            eprintln!("| Key | Count |");
+            eprintln!("-------------------------------------------------");
+            for (key, count) in counters.iter() {
+                eprintln!("{:60} | {:10}", key, count);
+            }
+            eprintln!("-------------------------------------------------");
+        }
+        if !histograms.is_empty() {
+            eprintln!("| Key | Count | Mean | Max | P50 | P90 | P99 |");
+            eprintln!("-------------------------------------------------");
+            for (key, h) in histograms.iter() {
+                eprintln!(
+                    "{:60} | {:8} | {:10} | {:10} | {:10} | {:10} | {:10}",
+                    key,
+                    h.count(),
+                    h.mean(),
+                    h.max(),
+                    h.quantile(0.5),
+                    h.quantile(0.9),
+                    h.quantile(0.99)
+                );
+            }
+            eprintln!("-------------------------------------------------");
+        }
+    }
+
+    fn clear(&self) {
+        self.counters.clear();
+        self.histograms.clear();
+    }
+}
+
+pub static GLOBAL_STATS: once_cell::sync::Lazy<Arc<GlobalStats>> =
+    once_cell::sync::Lazy::new(|| Arc::new(GlobalStats::new()));
+
+pub fn spawn_stats_task() {
+    if !use_stats() {
+        return;
+    }
+
+    let global_stats = GLOBAL_STATS.clone();
+    tokio::task::Builder::new()
+        .name("stats")
+        .spawn(async move {
+            loop {
+                tokio::time::sleep(global_stats.window_interval).await;
+                let interner = interner_stats();
+                global_stats.incr(
+                    "interner: interned strings",
+                    interner.interned_strings as u64,
+                );
+                global_stats.incr("interner: counted strings", interner.counter_entries as u64);
+                global_stats.incr("interner: approx bytes", interner.approx_bytes as u64);
+                global_stats.summary();
+                global_stats.clear();
+            }
+        })
+        .expect("Failed to spawn stats task");
+}
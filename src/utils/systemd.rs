@@ -0,0 +1,224 @@
+/*
+systemd 集成 (`Type=notify`), 仅在 systemd 设置了 NOTIFY_SOCKET 时启用:
+- 所有 actor 启动后发送 READY=1
+- 每个统计周期发送一行 STATUS=
+- 设置了 WatchdogSec 时发送 WATCHDOG=1, 但只在被监视的 actor 仍在推进时发送,
+  卡死的管道因此会被 systemd 重启
+- 开始退出时发送 STOPPING=1
+*/
+
+use std::{
+    ffi::OsStr,
+    os::unix::net::{SocketAddr, UnixDatagram},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+use tokio::time::Interval;
+use tokio_util::sync::CancellationToken;
+
+use super::{
+    liveness::Liveness,
+    stats::{ingested_records, STATS_INTERVAL},
+};
+
+/// Writes to the notify socket of the service manager, see `sd_notify(3)`.
+#[derive(Debug)]
+pub struct Notifier {
+    socket: UnixDatagram,
+    addr: SocketAddr,
+
+    watchdog: Option<Duration>,
+    status_interval: Duration,
+}
+
+impl Notifier {
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var_os("NOTIFY_SOCKET")?;
+        match Self::connect(&path, watchdog_timeout(), STATS_INTERVAL) {
+            Ok(notifier) => {
+                info!(
+                    "systemd: notifying {:?}, watchdog: {:?}",
+                    path, notifier.watchdog
+                );
+                Some(notifier)
+            }
+            Err(e) => {
+                warn!("systemd: invalid NOTIFY_SOCKET {:?}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    pub fn connect(
+        path: &OsStr,
+        watchdog: Option<Duration>,
+        status_interval: Duration,
+    ) -> std::io::Result<Self> {
+        let addr = socket_addr(path)?;
+        let socket = UnixDatagram::unbound()?;
+        // The notify socket is a datagram socket, a full queue must never block the runtime
+        socket.set_nonblocking(true)?;
+
+        Ok(Self {
+            socket,
+            addr,
+            watchdog,
+            status_interval,
+        })
+    }
+
+    pub fn notify(&self, state: &str) {
+        if let Err(e) = self.socket.send_to_addr(state.as_bytes(), &self.addr) {
+            warn!("systemd: failed to send {:?}: {}", state, e);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn socket_addr(path: &OsStr) -> std::io::Result<SocketAddr> {
+    use std::os::{linux::net::SocketAddrExt, unix::ffi::OsStrExt};
+
+    // "@" 开头的是抽象命名空间的地址
+    match path.as_bytes().strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name),
+        None => SocketAddr::from_pathname(path),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn socket_addr(path: &OsStr) -> std::io::Result<SocketAddr> {
+    SocketAddr::from_pathname(path)
+}
+
+/// `WatchdogSec` of the service, if the watchdog is enabled for this process.
+fn watchdog_timeout() -> Option<Duration> {
+    let usec = std::env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok()? != std::process::id() {
+            return None;
+        }
+    }
+
+    Some(Duration::from_micros(usec)).filter(|timeout| !timeout.is_zero())
+}
+
+async fn tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+struct StatusLine {
+    at: Instant,
+    records: u64,
+}
+
+impl StatusLine {
+    fn next(&mut self, liveness: &Liveness, max_silence: Duration) -> String {
+        let (now, records) = (Instant::now(), ingested_records());
+        let elapsed = now.duration_since(self.at).as_secs_f64();
+        let rate = (records - self.records) as f64 / elapsed.max(f64::EPSILON);
+        (self.at, self.records) = (now, records);
+
+        let stalled = liveness.stalled(max_silence);
+        if stalled.is_empty() {
+            return format!("STATUS={:.0} records/s, all actors healthy", rate);
+        }
+
+        let stalled = stalled
+            .iter()
+            .map(|tag| tag.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("STATUS={:.0} records/s, stalled: {}", rate, stalled)
+    }
+}
+
+/// Send READY=1 and spawn the task sending the status, the watchdog pings and STOPPING=1.
+pub fn spawn_systemd_task(
+    notifier: Arc<Notifier>,
+    liveness: Arc<Liveness>,
+    ctx: CancellationToken,
+) {
+    notifier.notify("READY=1");
+
+    // An actor silent for a whole watchdog period is considered stuck
+    let max_silence = notifier.watchdog.unwrap_or(STATS_INTERVAL);
+
+    tokio::task::Builder::new()
+        .name("systemd")
+        .spawn(async move {
+            let mut status = tokio::time::interval(notifier.status_interval);
+            status.tick().await;
+            let mut watchdog = notifier
+                .watchdog
+                .map(|timeout| tokio::time::interval(timeout / 2));
+            let mut line = StatusLine {
+                at: Instant::now(),
+                records: ingested_records(),
+            };
+
+            loop {
+                tokio::select! {
+                    _ = ctx.cancelled() => {
+                        notifier.notify("STOPPING=1");
+                        return;
+                    }
+                    _ = status.tick() => {
+                        notifier.notify(&line.next(&liveness, max_silence));
+                    }
+                    _ = tick(&mut watchdog) => {
+                        let stalled = liveness.stalled(max_silence);
+                        if stalled.is_empty() {
+                            notifier.notify("WATCHDOG=1");
+                        } else {
+                            warn!("systemd: actors {:?} are stuck, watchdog not pinged", stalled);
+                        }
+                    }
+                }
+            }
+        })
+        .expect("Failed to spawn systemd task");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notify() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify.sock");
+        let server = UnixDatagram::bind(&path).unwrap();
+
+        let notifier = Notifier::connect(path.as_os_str(), None, STATS_INTERVAL).unwrap();
+        notifier.notify("READY=1");
+
+        let mut buf = [0; 64];
+        let n = server.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_abstract_socket() {
+        use std::os::linux::net::SocketAddrExt;
+
+        let name = format!("void-notify-test-{}", std::process::id());
+        let server =
+            UnixDatagram::bind_addr(&SocketAddr::from_abstract_name(&name).unwrap()).unwrap();
+
+        let path = format!("@{}", name);
+        let notifier = Notifier::connect(OsStr::new(&path), None, STATS_INTERVAL).unwrap();
+        notifier.notify("STOPPING=1");
+
+        let mut buf = [0; 64];
+        let n = server.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"STOPPING=1");
+    }
+}
@@ -0,0 +1,35 @@
+use std::time::{Duration, Instant};
+
+/// Lets an event through at most once per interval, e.g. to rate-limit a warning
+/// that would otherwise be logged for every record.
+#[derive(Debug, Clone)]
+pub struct Throttle {
+    interval: Duration,
+    last: Option<Instant>,
+    suppressed: usize,
+}
+
+impl Throttle {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last: None,
+            suppressed: 0,
+        }
+    }
+
+    /// Returns the number of events suppressed since the last one let through,
+    /// or `None` if the event happening at `now` should be suppressed as well.
+    pub fn check_at(&mut self, now: Instant) -> Option<usize> {
+        match self.last {
+            Some(last) if now.saturating_duration_since(last) < self.interval => {
+                self.suppressed += 1;
+                None
+            }
+            _ => {
+                self.last = Some(now);
+                Some(std::mem::take(&mut self.suppressed))
+            }
+        }
+    }
+}
@@ -1,12 +1,22 @@
-use std::{fmt::Display, sync::Arc};
+use std::{
+    fmt::Display,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+    time::{Duration, Instant},
+};
 
 use dashmap::DashMap;
+use log::info;
 
-use crate::{config::global::use_time_tracing, core::tag::TagId};
+use crate::{
+    config::global::{time_tracing_interval, use_time_tracing},
+    core::tag::TagId,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Direction {
-    None,
     Incoming,
     Outgoing,
 }
@@ -14,7 +24,6 @@ pub enum Direction {
 impl Display for Direction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Direction::None => write!(f, "none"),
             Direction::Incoming => write!(f, "incoming"),
             Direction::Outgoing => write!(f, "outgoing"),
         }
@@ -29,23 +38,13 @@ pub struct Timepoint {
     pub direction: Direction,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct TimeRangeKey {
-    pub from: (TagId, Direction),
-    pub to: (TagId, Direction),
-}
-
-#[derive(Debug, Clone)]
-pub struct TimeRange {
-    pub key: String,
-    pub elapsed: std::time::Duration,
-}
-
 #[derive(Debug)]
 
 pub struct TracingContext {
     timepoints: spin::Mutex<Vec<Timepoint>>,
     parent: Option<Arc<TracingContext>>,
+    // When the inbound parsed the record, shared by the records derived from it
+    received: OnceLock<Instant>,
 }
 
 impl TracingContext {
@@ -53,6 +52,7 @@ impl TracingContext {
         let ctx = TracingContext {
             timepoints: spin::Mutex::new(Vec::new()),
             parent: None,
+            received: OnceLock::new(),
         };
 
         Arc::new(ctx)
@@ -61,6 +61,7 @@ impl TracingContext {
         let ctx = TracingContext {
             timepoints: spin::Mutex::new(Vec::new()),
             parent: Some(parent),
+            received: OnceLock::new(),
         };
 
         Arc::new(ctx)
@@ -77,141 +78,335 @@ impl TracingContext {
         timepoints.push(timepoint);
     }
 
-    pub fn record(&self) {
-        if !use_time_tracing() {
-            return;
+    pub fn mark_received(&self) {
+        let _ = self.received.set(Instant::now());
+    }
+
+    /// When the record, or the one it was derived from, was received.
+    pub fn received(&self) -> Option<Instant> {
+        let mut ctx = Some(self);
+        while let Some(c) = ctx {
+            if let Some(received) = c.received.get() {
+                return Some(*received);
+            }
+            ctx = c.parent.as_deref();
         }
+        None
+    }
 
-        let mut parent = self.parent.clone();
+    /// The timepoints of the record and of those it was derived from, oldest first.
+    fn timepoints(&self) -> Vec<Timepoint> {
         let mut timepoints = self.timepoints.lock().clone();
-
+        let mut parent = self.parent.clone();
         while let Some(p) = parent {
-            {
-                let parent_timepoints = p.timepoints.lock();
-                parent_timepoints
-                    .iter()
-                    .for_each(|e| timepoints.push(e.clone()));
-            }
-
+            timepoints.extend(p.timepoints.lock().iter().cloned());
             parent = p.parent.clone();
         }
 
-        timepoints.sort_by(|a, b| a.time.cmp(&b.time));
-        if timepoints.len() < 2 {
+        timepoints.sort_by_key(|timepoint| timepoint.time);
+        timepoints
+    }
+
+    /// Called once `outbound` has written the record. Records the time spent in each stage,
+    /// from the record coming in to it going out, and from the first stage, usually the
+    /// inbound, to the outbound.
+    pub fn release(&self, outbound: &TagId, tracing: &GlobalTracing) {
+        let now = Instant::now();
+        let mut timepoints = self.timepoints();
+        let Some(first) = timepoints.first() else {
             return;
+        };
+
+        let start = self.received().unwrap_or(first.time);
+        tracing.add_edge_latency(&first.stage, outbound, now.saturating_duration_since(start));
+
+        // The outbound goes out once the record is written
+        timepoints.push(Timepoint {
+            stage: outbound.clone(),
+            time: now,
+            direction: Direction::Outgoing,
+        });
+        for (i, entry) in timepoints.iter().enumerate() {
+            if entry.direction != Direction::Incoming {
+                continue;
+            }
+            let exit = timepoints[i + 1..].iter().find(|timepoint| {
+                timepoint.stage == entry.stage && timepoint.direction == Direction::Outgoing
+            });
+            if let Some(exit) = exit {
+                tracing.add_stage_latency(&entry.stage, exit.time.duration_since(entry.time));
+            }
         }
+    }
+}
+
+// Bucket `i` counts the latencies below 2^i microseconds, the last one everything above
+const LATENCY_BUCKETS: usize = 28;
+
+/// Latencies in power of two buckets, so that recording does not allocate.
+#[derive(Debug, Default)]
+pub struct LatencyHistogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS],
+}
 
-        let start_timestamp = timepoints
-            .first()
-            .map(|e| e.time)
-            .unwrap_or(std::time::Instant::now());
+impl LatencyHistogram {
+    pub fn observe(&self, latency: Duration) {
+        let micros = latency.as_micros() as u64;
+        let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+        self.buckets[bucket.min(LATENCY_BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+    }
 
-        for (i, item) in timepoints.iter().enumerate() {
-            let elapsed = item.time.duration_since(start_timestamp);
-            let key = format!("[{}] {}({})", i, item.stage, item.direction);
-            let range = TimeRange { key, elapsed };
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().map(|b| b.load(Ordering::Relaxed)).sum()
+    }
 
-            GLOBAL_TRACING.add_time_range(range);
+    /// Upper bound of the bucket holding the `q` quantile, `None` without observations.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        let counts = self
+            .buckets
+            .iter()
+            .map(|b| b.load(Ordering::Relaxed))
+            .collect::<Vec<_>>();
+        let total = counts.iter().sum::<u64>();
+        if total == 0 {
+            return None;
         }
+
+        let rank = ((q * total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, count) in counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some(Duration::from_micros(1 << i));
+            }
+        }
+        Some(Duration::from_micros(1 << (LATENCY_BUCKETS - 1)))
+    }
+
+    fn clear(&self) {
+        self.buckets
+            .iter()
+            .for_each(|b| b.store(0, Ordering::Relaxed));
     }
 }
 
-#[derive(Debug)]
-pub struct GlobalTracing {
-    window_interval: std::time::Duration,
-    buffer: DashMap<String, Vec<std::time::Duration>>,
+/// The quantiles of the latencies of a stage or an edge over the last interval.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencySummary {
+    pub name: String,
+    pub count: u64,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
 }
 
-impl GlobalTracing {
-    pub fn new() -> Self {
-        Self {
-            window_interval: std::time::Duration::from_secs(10),
-            buffer: DashMap::new(),
+impl LatencySummary {
+    fn of(name: String, histogram: &LatencyHistogram) -> Option<Self> {
+        let count = histogram.count();
+        if count == 0 {
+            return None;
         }
+
+        let [p50, p95, p99] = [0.5, 0.95, 0.99].map(|q| histogram.quantile(q).unwrap_or_default());
+        Some(Self {
+            name,
+            count,
+            p50,
+            p95,
+            p99,
+        })
     }
+}
 
-    pub fn add_time_range(&self, range: TimeRange) {
-        if !use_time_tracing() {
-            return;
-        }
+impl Display for LatencySummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} records, p50 <= {:?}, p95 <= {:?}, p99 <= {:?}",
+            self.name, self.count, self.p50, self.p95, self.p99
+        )
+    }
+}
+
+/// Latencies collected from the records released by the outbounds, with
+/// `global.time_tracing`.
+#[derive(Debug, Default)]
+pub struct GlobalTracing {
+    // From a record coming into a stage to it going out, by stage
+    stages: DashMap<TagId, Arc<LatencyHistogram>>,
+    // From the first stage of a record to the outbound releasing it
+    edges: DashMap<(TagId, TagId), Arc<LatencyHistogram>>,
+}
 
-        let mut entry = self.buffer.entry(range.key).or_default();
-        entry.push(range.elapsed);
+fn histogram<K>(histograms: &DashMap<K, Arc<LatencyHistogram>>, key: &K) -> Arc<LatencyHistogram>
+where
+    K: Eq + std::hash::Hash + Clone,
+{
+    match histograms.get(key) {
+        Some(histogram) => histogram.clone(),
+        None => histograms.entry(key.clone()).or_default().clone(),
     }
+}
 
-    fn summary(&self) {
-        if !use_time_tracing() {
-            return;
-        }
+impl GlobalTracing {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-        // mean, stddev, min, max, p25, p50, p75, p90 by key
-        let mut summary = vec![];
-
-        for mut entry in self.buffer.iter_mut() {
-            let key = entry.key().clone();
-            let elapsed = entry.value_mut();
-            elapsed.sort();
-
-            let count = elapsed.len() as i64;
-            let mean = elapsed.iter().copied().sum::<std::time::Duration>() / elapsed.len() as u32;
-            let min = *elapsed.iter().min().unwrap_or(&std::time::Duration::ZERO);
-            let max = *elapsed.iter().max().unwrap_or(&std::time::Duration::ZERO);
-            let p25 = elapsed[elapsed.len() / 4];
-            let p50 = elapsed[elapsed.len() / 2];
-            let p75 = elapsed[3 * elapsed.len() / 4];
-            let p90 = elapsed[9 * elapsed.len() / 10];
-
-            let mean = mean.as_millis() as i64;
-            let min = min.as_millis() as i64;
-            let max = max.as_millis() as i64;
-            let p25 = p25.as_millis() as i64;
-            let p50 = p50.as_millis() as i64;
-            let p75 = p75.as_millis() as i64;
-            let p90 = p90.as_millis() as i64;
-
-            summary.push((key, count, mean, min, max, p25, p50, p75, p90));
-        }
+    pub fn add_stage_latency(&self, stage: &TagId, latency: Duration) {
+        histogram(&self.stages, stage).observe(latency);
+    }
 
-        summary.sort_by(|a, b| a.0.cmp(&b.0));
-        eprintln!("Time Tracing Summary:");
-        eprintln!("=========================");
-        eprintln!("| Key | Count | Mean (ms) | Min (ms) | Max (ms) | P25 (ms) | P50 (ms) | P75 (ms) | P90 (ms) |");
-        eprintln!("-------------------------------------------------");
-        for (key, count, mean, min, max, p25, p50, p75, p90) in summary.iter() {
-            eprintln!(
-                "{:40} | {:6} | {:+6} | {:+6} | {:+6} | {:+6} | {:+6} | {:+6} | {:+6}",
-                key, count, mean, min, max, p25, p50, p75, p90
-            );
-        }
-        eprintln!("-------------------------------------------------");
+    pub fn add_edge_latency(&self, from: &TagId, to: &TagId, latency: Duration) {
+        histogram(&self.edges, &(from.clone(), to.clone())).observe(latency);
     }
 
-    fn clear(&self) {
-        if !use_time_tracing() {
-            return;
+    /// The latencies of the stages then of the edges seen since the last report, by name.
+    pub fn summaries(&self) -> (Vec<LatencySummary>, Vec<LatencySummary>) {
+        let mut stages = self
+            .stages
+            .iter()
+            .filter_map(|e| LatencySummary::of(e.key().to_string(), e.value()))
+            .collect::<Vec<_>>();
+        let mut edges = self
+            .edges
+            .iter()
+            .filter_map(|e| {
+                let (from, to) = e.key();
+                LatencySummary::of(format!("{} -> {}", from, to), e.value())
+            })
+            .collect::<Vec<_>>();
+
+        stages.sort_by(|a, b| a.name.cmp(&b.name));
+        edges.sort_by(|a, b| a.name.cmp(&b.name));
+        (stages, edges)
+    }
+
+    /// Log the latencies seen since the last report, and start over.
+    fn report(&self) {
+        let (stages, edges) = self.summaries();
+        for summary in stages {
+            info!("stage latency of {}", summary);
+        }
+        for summary in edges {
+            info!("pipeline latency of {}", summary);
         }
 
-        self.buffer.clear();
+        self.stages.iter().for_each(|e| e.value().clear());
+        self.edges.iter().for_each(|e| e.value().clear());
     }
 }
 
 pub static GLOBAL_TRACING: once_cell::sync::Lazy<Arc<GlobalTracing>> =
     once_cell::sync::Lazy::new(|| Arc::new(GlobalTracing::new()));
 
+/// Logs the latencies of the stages and of the edges every `global.time_tracing_interval`.
 pub fn spawn_tracing_task() {
     if !use_time_tracing() {
         return;
     }
 
     let global_tracing = GLOBAL_TRACING.clone();
+    let interval = time_tracing_interval().get();
     tokio::task::Builder::new()
         .name("tracing")
         .spawn(async move {
             loop {
-                tokio::time::sleep(global_tracing.window_interval).await;
-                global_tracing.summary();
-                global_tracing.clear();
+                tokio::time::sleep(interval).await;
+                global_tracing.report();
             }
         })
         .expect("Failed to spawn tracing task");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tag::{InboundTagId, OutboundTagId, PipeTagId};
+
+    #[test]
+    fn test_latency_histogram() {
+        let histogram = LatencyHistogram::default();
+        assert_eq!(histogram.quantile(0.5), None);
+
+        for _ in 0..90 {
+            histogram.observe(Duration::from_micros(100));
+        }
+        for _ in 0..10 {
+            histogram.observe(Duration::from_millis(50));
+        }
+        histogram.observe(Duration::from_secs(3600));
+
+        assert_eq!(histogram.count(), 101);
+        assert_eq!(histogram.quantile(0.5), Some(Duration::from_micros(128)));
+        assert_eq!(histogram.quantile(0.95), Some(Duration::from_micros(65536)));
+        assert_eq!(
+            histogram.quantile(1.0),
+            Some(Duration::from_micros(1 << (LATENCY_BUCKETS - 1)))
+        );
+
+        histogram.clear();
+        assert_eq!(histogram.count(), 0);
+    }
+
+    #[test]
+    fn test_stage_latencies() {
+        let tracing = GlobalTracing::new();
+        let inbound: TagId = InboundTagId::new("tcp").into();
+        let filter: TagId = PipeTagId::new("filter").into();
+        let timeseries: TagId = PipeTagId::new("timeseries").into();
+        let outbound: TagId = OutboundTagId::new("prometheus").into();
+        let spend = || std::thread::sleep(Duration::from_millis(2));
+
+        // Passed on by the filter, turned into a new record by the timeseries pipe
+        let root = TracingContext::new_root();
+        root.mark_received();
+        root.add_timepoint(&inbound, Direction::Incoming);
+        spend();
+        root.add_timepoint(&inbound, Direction::Outgoing);
+        root.add_timepoint(&filter, Direction::Incoming);
+        spend();
+        root.add_timepoint(&filter, Direction::Outgoing);
+        root.add_timepoint(&timeseries, Direction::Incoming);
+        spend();
+        let derived = TracingContext::inherit(root.clone());
+        derived.add_timepoint(&timeseries, Direction::Outgoing);
+        derived.add_timepoint(&outbound, Direction::Incoming);
+        spend();
+        derived.release(&outbound, &tracing);
+
+        let (stages, edges) = tracing.summaries();
+        let names = stages.iter().map(|s| s.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "inbound:tcp",
+                "outbound:prometheus",
+                "pipe:filter",
+                "pipe:timeseries"
+            ]
+        );
+        for stage in &stages {
+            assert_eq!(stage.count, 1);
+            assert!(stage.p50 >= Duration::from_millis(2), "{}", stage);
+        }
+
+        assert_eq!(edges.len(), 1);
+        assert_eq!(edges[0].name, "inbound:tcp -> outbound:prometheus");
+        assert!(edges[0].p99 >= Duration::from_millis(8), "{}", edges[0]);
+
+        tracing.report();
+        assert_eq!(tracing.summaries(), (vec![], vec![]));
+    }
+
+    #[test]
+    fn test_received_is_inherited() {
+        let root = TracingContext::new_root();
+        let child = TracingContext::inherit(root.clone());
+        assert_eq!(child.received(), None);
+
+        root.mark_received();
+        assert_eq!(child.received(), root.received());
+        assert!(child.received().is_some());
+    }
+}